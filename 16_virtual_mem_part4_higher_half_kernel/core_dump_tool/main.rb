@@ -0,0 +1,117 @@
+#!/usr/bin/env ruby
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+# Reassemble the core dumps in a captured console log. See src/exception/core_dump.rs for the
+# format.
+#
+# Usage: ruby core_dump_tool/main.rb <console log>
+
+require 'zlib'
+
+REGISTER_NAMES = (0..29).map { |i| "x#{i}" } +
+                 %w[lr elr_el1 spsr_el1 sp_el0 sp esr_el1 far_el1]
+
+MAP_ATTRIBUTES = { 0 => 'Dev', 1 => 'RW', 2 => 'XN' }.freeze
+
+class ChecksumError < StandardError; end
+
+# A section of a dump.
+class Section
+    attr_reader :name, :addr, :bytes
+
+    def initialize(name, addr, len, crc)
+        @name = name
+        @addr = addr
+        @len = len
+        @crc = crc
+        @bytes = String.new(encoding: Encoding::BINARY)
+    end
+
+    def <<(hex_line)
+        @bytes << [hex_line].pack('H*')
+    end
+
+    def check
+        raise ChecksumError, "#{@name}: got #{@bytes.size} of #{@len} bytes" if @bytes.size != @len
+        raise ChecksumError, "#{@name}: CRC mismatch" if Zlib.crc32(@bytes) != @crc
+    end
+
+    def words
+        @bytes.unpack('Q<*')
+    end
+end
+
+def print_registers(section)
+    section.words.zip(REGISTER_NAMES).each do |value, name|
+        puts format('  %-8<name>s 0x%016<value>x', name: name || '?', value: value)
+    end
+end
+
+def print_stack(section)
+    section.bytes.unpack('Q<*').each_slice(2).with_index do |words, i|
+        line = words.map { |x| format('%016x', x) }.join(' ')
+        puts format('  0x%016<addr>x: %<line>s', addr: section.addr + (i * 16), line: line)
+    end
+end
+
+def print_mappings(section)
+    section.words.each_slice(4) do |virt, phys, size, attr|
+        flags = MAP_ATTRIBUTES.select { |bit, _| attr[bit] == 1 }.values.join(' ')
+        puts format('  0x%016<virt>x --> 0x%016<phys>x  %8<size>d KiB  %<flags>s',
+                    virt: virt, phys: phys, size: size / 1024, flags: flags)
+    end
+end
+
+def print_irqs(section)
+    *per_core, depth = section.words
+    per_core.each_with_index { |x, core| puts "  IRQs taken on core #{core}: #{x}" }
+    puts "  IRQ nesting depth: #{depth}"
+end
+
+def print_section(section)
+    puts "#{section.name}:"
+    case section.name
+    when 'REGS' then print_registers(section)
+    when 'STACK' then print_stack(section)
+    when 'MAPS' then print_mappings(section)
+    when 'IRQS' then print_irqs(section)
+    else puts "  #{section.bytes.size} bytes"
+    end
+end
+
+sections = nil
+current = nil
+
+File.foreach(ARGV[0]) do |line|
+    line = line.strip
+
+    case line
+    when /^=== CORE DUMP BEGIN v1 core=(\d+) ===$/
+        puts
+        puts "Core dump of core #{Regexp.last_match(1)}"
+        sections = []
+    when '=== CORE DUMP END ==='
+        next if sections.nil?
+
+        sections.each do |x|
+            x.check
+            print_section(x)
+        end
+        sections = nil
+        current = nil
+    when /^@(\w+) addr=0x(\h+) len=(\d+) crc=(\h{8})$/
+        next if sections.nil?
+
+        current = Section.new(Regexp.last_match(1), Regexp.last_match(2).to_i(16),
+                              Regexp.last_match(3).to_i, Regexp.last_match(4).to_i(16))
+        sections << current
+    when /^\h+$/
+        current << line unless current.nil?
+    end
+end
+
+abort 'Incomplete core dump' unless sections.nil?
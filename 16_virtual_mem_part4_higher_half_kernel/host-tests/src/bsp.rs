@@ -0,0 +1,22 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Stand-in for the BSP.
+
+pub mod memory {
+    use crate::memory::{Address, Physical};
+
+    #[path = "../../../../src/bsp/raspberrypi/memory/layout.rs"]
+    pub mod layout;
+
+    /// Same as the Raspberry Pi 3's.
+    pub const fn phys_mmio_window() -> (Address<Physical>, Address<Physical>) {
+        (Address::new(0x3F00_0000), Address::new(0x4001_0000))
+    }
+
+    pub mod mmu {
+        /// Same as the Raspberry Pi's.
+        pub type KernelGranule = crate::memory::mmu::TranslationGranule<{ 64 * 1024 }>;
+    }
+}
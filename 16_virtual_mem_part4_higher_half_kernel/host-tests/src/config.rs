@@ -0,0 +1,10 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Mirror of `crate::config`.
+//!
+//! Only the memory configuration is needed. It is the kernel's own, included by path.
+
+#[path = "../../src/config/memory.rs"]
+pub mod memory;
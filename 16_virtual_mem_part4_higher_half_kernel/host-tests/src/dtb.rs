@@ -0,0 +1,8 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Mirror of `crate::dtb`.
+
+#[path = "../../src/dtb/fdt.rs"]
+mod fdt;
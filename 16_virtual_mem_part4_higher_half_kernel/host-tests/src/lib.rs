@@ -0,0 +1,45 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Host-side unit tests for the kernel's pure logic.
+//!
+//! The kernel only builds for the target, but some of its modules are plain computations. These are
+//! included here by path, in a module tree that mirrors the kernel's, so that their `crate::` paths
+//! resolve. The few items that they need from the rest of the kernel are replaced by stand-ins.
+//!
+//! The tests themselves live next to the code they test, in modules gated with
+//! `#[cfg(all(test, not(target_os = "none")))]`. The kernel's own unit tests are gated with
+//! `#[cfg(all(test, target_os = "none"))]` in these files, because they need the kernel's test
+//! framework.
+//!
+//! Run with `make test_host`.
+
+#![allow(dead_code)]
+#![feature(const_fn)]
+#![feature(const_fn_fn_ptr_basics)]
+#![feature(const_panic)]
+
+mod bsp;
+#[path = "../../src/common.rs"]
+mod common;
+mod config;
+mod dtb;
+mod memory;
+mod synchronization;
+
+/// Stand-in for the kernel's `info!`.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ({
+        println!($($arg)*);
+    })
+}
+
+/// Stand-in for the kernel's `warn!`.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ({
+        println!($($arg)*);
+    })
+}
@@ -0,0 +1,11 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Mirror of `crate::memory`.
+
+#[path = "../../src/memory/address.rs"]
+mod address;
+pub mod mmu;
+
+pub use address::*;
@@ -0,0 +1,39 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Mirror of `crate::memory::mmu`.
+
+#[path = "../../../src/memory/mmu/mapping_record.rs"]
+mod mapping_record;
+mod translation_table;
+#[path = "../../../src/memory/mmu/types.rs"]
+mod types;
+
+use crate::memory::{Address, Physical, Virtual};
+
+pub use types::*;
+
+/// Stand-in for the architectural MMU code.
+pub mod arch_mmu {
+    /// Same as in `_arch/aarch64/memory/mmu.rs`.
+    pub type Granule64KiB = super::TranslationGranule<{ 64 * 1024 }>;
+
+    /// Same as in `_arch/aarch64/memory/mmu.rs`.
+    pub mod mair {
+        pub const DEVICE: u64 = 0;
+        pub const NORMAL: u64 = 1;
+    }
+}
+
+/// Same as the kernel's.
+#[derive(Debug)]
+pub enum TranslationError {
+    MMUDisabled,
+    Aborted,
+}
+
+/// There is no MMU to ask on the host.
+pub fn try_virt_to_phys(_virt: Address<Virtual>) -> Result<Address<Physical>, TranslationError> {
+    Err(TranslationError::MMUDisabled)
+}
@@ -0,0 +1,9 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Stand-in for `crate::memory::mmu::translation_table::arch_translation_table`, which is the
+//! parent of the descriptor module in the kernel.
+
+#[path = "../../../../src/_arch/aarch64/memory/mmu/translation_table/descriptor.rs"]
+mod descriptor;
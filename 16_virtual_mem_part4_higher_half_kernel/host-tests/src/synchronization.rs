@@ -0,0 +1,45 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Stand-in for `crate::synchronization`.
+
+use core::cell::UnsafeCell;
+
+pub mod interface {
+    /// Same as the kernel's.
+    pub trait ReadWriteEx {
+        type Data;
+
+        fn write<R>(&self, f: impl FnOnce(&mut Self::Data) -> R) -> R;
+
+        fn read<R>(&self, f: impl FnOnce(&Self::Data) -> R) -> R;
+    }
+}
+
+/// Like the kernel's, a lock that does not lock. The tests must not share the global instances.
+pub struct InitStateLock<T> {
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for InitStateLock<T> {}
+
+impl<T> InitStateLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T> interface::ReadWriteEx for InitStateLock<T> {
+    type Data = T;
+
+    fn write<R>(&self, f: impl FnOnce(&mut Self::Data) -> R) -> R {
+        f(unsafe { &mut *self.data.get() })
+    }
+
+    fn read<R>(&self, f: impl FnOnce(&Self::Data) -> R) -> R {
+        f(unsafe { &*self.data.get() })
+    }
+}
@@ -11,7 +11,8 @@
 //!
 //! crate::cpu::arch_cpu
 
-use cortex_a::asm;
+use crate::{config, memory};
+use cortex_a::{asm, regs::*};
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
@@ -19,11 +20,172 @@ use cortex_a::asm;
 
 pub use asm::nop;
 
+/// Cache the executing core's id in `TPIDR_EL1`, which is otherwise unused by the kernel.
+///
+/// The id is taken from the affinity level 0 field of `MPIDR_EL1`.
+///
+/// # Safety
+///
+/// - Changes the HW state of the executing core.
+/// - Must be called on each core before the first call to `core_id()`.
+#[inline(always)]
+pub unsafe fn cache_core_id() {
+    const AFF0_MASK: u64 = 0xff;
+
+    let id = MPIDR_EL1.get() & AFF0_MASK;
+
+    asm!(
+        "msr TPIDR_EL1, {}",
+        in(reg) id,
+        options(nomem, nostack, preserves_flags)
+    );
+}
+
+/// Return the executing core's id.
+///
+/// Fast path that reads the value cached by `cache_core_id()` instead of decoding `MPIDR_EL1`.
+#[inline(always)]
+pub fn core_id() -> usize {
+    let id: u64;
+
+    unsafe {
+        asm!(
+            "mrs {}, TPIDR_EL1",
+            out(reg) id,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+
+    id as usize
+}
+
+/// Enable the generic timer's event stream on the executing core.
+///
+/// The event stream periodically generates an event, which bounds the time that a core can sleep
+/// in `wait_for_event()` even if nobody ever calls `send_event()`. The rate is configured by
+/// `config::time::EVENT_STREAM_COUNTER_BIT`.
+///
+/// # Safety
+///
+/// - Changes the HW state of the executing core.
+#[inline(always)]
+pub unsafe fn enable_event_stream() {
+    const EVNTEN: u64 = 1 << 2;
+    const EVNTI_SHIFT: u64 = 4;
+    const EVNTI: u64 = config::time::EVENT_STREAM_COUNTER_BIT;
+    assert!(EVNTI <= 0xf, "Event stream counter bit out of range");
+
+    let mut cntkctl: u64;
+
+    asm!(
+        "mrs {}, CNTKCTL_EL1",
+        out(reg) cntkctl,
+        options(nomem, nostack, preserves_flags)
+    );
+
+    cntkctl &= !(0xf << EVNTI_SHIFT);
+    cntkctl |= EVNTEN | (EVNTI << EVNTI_SHIFT);
+
+    asm!(
+        "msr CNTKCTL_EL1, {}",
+        in(reg) cntkctl,
+        options(nomem, nostack, preserves_flags)
+    );
+}
+
+/// Put the core into a low-power state until an event is received.
+///
+/// Returns on events sent by other cores, on events of the timer's event stream, and on pending
+/// interrupts, even if they are masked. Callers must therefore always re-check their wake-up
+/// condition.
+#[inline(always)]
+pub fn wait_for_event() {
+    asm::wfe()
+}
+
+/// Put the core into a low-power state until an interrupt is pending.
+///
+/// Wakes up on pending interrupts, even if they are masked, but not on events. Masking IRQs before
+/// checking for work and waiting therefore cannot lose a wake-up. The IRQ is taken once it is
+/// unmasked again.
+#[inline(always)]
+pub fn wait_for_interrupt() {
+    asm::wfi()
+}
+
+/// Send an event to all cores.
+#[inline(always)]
+pub fn send_event() {
+    unsafe { asm!("sev", options(nomem, nostack, preserves_flags)) };
+}
+
+/// Spin for `n` iterations of a loop that takes one cycle per iteration on the Raspberry's cores.
+#[inline(never)]
+pub fn spin_for_iterations(n: u64) {
+    if n == 0 {
+        return;
+    }
+
+    unsafe {
+        asm!(
+            "1:",
+            "subs {0}, {0}, #1",
+            "b.ne 1b",
+            inout(reg) n => _,
+            options(nomem, nostack)
+        )
+    };
+}
+
+/// The current value of the stack pointer.
+#[inline(always)]
+pub fn stack_pointer() -> usize {
+    let sp: usize;
+    unsafe { asm!("mov {}, sp", out(reg) sp, options(nomem, nostack, preserves_flags)) };
+
+    sp
+}
+
+/// Fill `buf` with the return addresses of the calling function and its callers, innermost first,
+/// and return how many were found.
+///
+/// Follows the chain of frame records that x29 points to, so it only sees the functions that were
+/// built with frame pointers. The walk ends at a null or misaligned frame pointer, at one that
+/// does not lead further up the stack, and at the top of the current stack.
+#[inline(always)]
+pub fn return_addresses(buf: &mut [usize]) -> usize {
+    let mut fp: usize;
+    unsafe { asm!("mov {}, x29", out(reg) fp, options(nomem, nostack, preserves_flags)) };
+
+    let top = match memory::stack::current_stack_top() {
+        Some(x) => x,
+        None => return 0,
+    };
+
+    let mut n = 0;
+    while (n < buf.len()) && (fp != 0) && (fp % 8 == 0) && (fp + 16 <= top) {
+        // A frame record holds the caller's frame pointer, followed by the return address.
+        let (next, lr) = unsafe { (*(fp as *const usize), *((fp + 8) as *const usize)) };
+        buf[n] = lr;
+        n += 1;
+
+        if next <= fp {
+            break;
+        }
+        fp = next;
+    }
+
+    n
+}
+
 /// Pause execution on the core.
+///
+/// Waits for interrupts instead of events, so that the timer's event stream does not wake the core
+/// all the time.
 #[inline(always)]
 pub fn wait_forever() -> ! {
     loop {
-        asm::wfe()
+        asm::wfi()
     }
 }
 
@@ -36,14 +198,8 @@ use qemu_exit::QEMUExit;
 #[cfg(feature = "test_build")]
 const QEMU_EXIT_HANDLE: qemu_exit::AArch64 = qemu_exit::AArch64::new();
 
-/// Make the host QEMU binary execute `exit(1)`.
-#[cfg(feature = "test_build")]
-pub fn qemu_exit_failure() -> ! {
-    QEMU_EXIT_HANDLE.exit_failure()
-}
-
-/// Make the host QEMU binary execute `exit(0)`.
+/// Make the host QEMU binary execute `exit(code)`.
 #[cfg(feature = "test_build")]
-pub fn qemu_exit_success() -> ! {
-    QEMU_EXIT_HANDLE.exit_success()
+pub fn qemu_exit(code: u32) -> ! {
+    QEMU_EXIT_HANDLE.exit(code)
 }
@@ -0,0 +1,110 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural memory and instruction barriers.
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::cpu::barrier::arch_barrier
+
+use cortex_a::barrier;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Data synchronization barrier, full system.
+///
+/// Completes all earlier memory accesses and maintenance operations, including those that are
+/// observed by other bus masters, before any later instruction executes.
+#[inline(always)]
+pub fn dsb_sy() {
+    unsafe { barrier::dsb(barrier::SY) }
+}
+
+/// Data synchronization barrier, inner shareable domain.
+///
+/// Like `dsb_sy()`, but only waits for the other cores. Completes broadcast TLB maintenance.
+#[inline(always)]
+pub fn dsb_ish() {
+    unsafe { barrier::dsb(barrier::ISH) }
+}
+
+/// Data synchronization barrier for stores, inner shareable domain.
+///
+/// Completes earlier stores only. Loads may still pass it.
+#[inline(always)]
+pub fn dsb_ishst() {
+    unsafe { barrier::dsb(barrier::ISHST) }
+}
+
+/// Data synchronization barrier, non-shareable domain.
+///
+/// Completes maintenance operations that only affect the executing core.
+#[inline(always)]
+pub fn dsb_nsh() {
+    unsafe { barrier::dsb(barrier::NSH) }
+}
+
+/// Data memory barrier, inner shareable domain.
+///
+/// Orders earlier memory accesses before later ones, as observed by the other cores, without
+/// waiting for them to complete.
+#[inline(always)]
+pub fn dmb_ish() {
+    unsafe { barrier::dmb(barrier::ISH) }
+}
+
+/// Instruction synchronization barrier.
+///
+/// Flushes the pipeline, so that later instructions are fetched and executed in the context
+/// established by everything before it.
+#[inline(always)]
+pub fn isb() {
+    unsafe { barrier::isb(barrier::SY) }
+}
+
+/// Make a system register write take effect for the instructions that follow.
+///
+/// Writes to system registers, for example SCTLR_EL1 or VBAR_EL1, are only guaranteed to be visible
+/// to later instructions after a context synchronization event.
+#[inline(always)]
+pub fn complete_system_register_write() {
+    isb();
+}
+
+/// Make translation table writes visible to the table walkers of all cores.
+///
+/// Must be called after changing descriptors and before invalidating the TLB entries that cover
+/// them. Otherwise, a walk that happens in between can refill the TLB with the stale descriptor.
+#[inline(always)]
+pub fn complete_table_update() {
+    dsb_ishst();
+}
+
+/// Complete broadcast TLB invalidations, and make the executing core use the new translations.
+///
+/// The DSB waits until all cores have dropped the entries. The ISB discards instructions that were
+/// fetched using the old translations.
+#[inline(always)]
+pub fn complete_tlb_invalidation() {
+    dsb_ish();
+    isb();
+}
+
+/// Complete TLB or instruction cache invalidations that only target the executing core.
+#[inline(always)]
+pub fn complete_local_invalidation() {
+    dsb_nsh();
+    isb();
+}
+
+/// Complete data cache maintenance by address, so that other bus masters observe the result.
+#[inline(always)]
+pub fn complete_cache_maintenance() {
+    dsb_sy();
+}
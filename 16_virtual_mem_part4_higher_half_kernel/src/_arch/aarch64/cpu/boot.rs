@@ -13,7 +13,7 @@
 
 use crate::{cpu, memory, memory::Address};
 use core::intrinsics::unlikely;
-use cortex_a::{asm, regs::*};
+use cortex_a::regs::*;
 
 // Assembly counterpart to this file.
 global_asm!(include_str!("boot.s"));
@@ -31,6 +31,7 @@ global_asm!(include_str!("boot.s"));
 #[inline(always)]
 unsafe fn prepare_el2_to_el1_transition(
     virt_boot_core_stack_end_exclusive_addr: u64,
+    virt_exception_stack_end_exclusive_addr: u64,
     virt_runtime_init_addr: u64,
 ) {
     // Enable timer counter registers for EL1.
@@ -44,22 +45,26 @@ unsafe fn prepare_el2_to_el1_transition(
 
     // Set up a simulated exception return.
     //
-    // First, fake a saved program status where all interrupts were masked and SP_EL1 was used as a
-    // stack pointer.
+    // First, fake a saved program status where all interrupts were masked and SP_EL0 was used as a
+    // stack pointer. The kernel runs on SP_EL0, so that exceptions, which are always taken on
+    // SP_EL1, switch to the dedicated exception stack.
     SPSR_EL2.write(
         SPSR_EL2::D::Masked
             + SPSR_EL2::A::Masked
             + SPSR_EL2::I::Masked
             + SPSR_EL2::F::Masked
-            + SPSR_EL2::M::EL1h,
+            + SPSR_EL2::M::EL1t,
     );
 
     // Second, let the link register point to runtime_init().
     ELR_EL2.set(virt_runtime_init_addr);
 
-    // Set up SP_EL1 (stack pointer), which will be used by EL1 once we "return" to it. Since there
+    // Set up SP_EL0 (stack pointer), which will be used by EL1 once we "return" to it. Since there
     // are no plans to ever return to EL2, just re-use the same stack.
-    SP_EL1.set(virt_boot_core_stack_end_exclusive_addr);
+    SP_EL0.set(virt_boot_core_stack_end_exclusive_addr);
+
+    // Set up SP_EL1, which is used while handling exceptions.
+    SP_EL1.set(virt_exception_stack_end_exclusive_addr);
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -68,7 +73,10 @@ unsafe fn prepare_el2_to_el1_transition(
 
 /// The Rust entry of the `kernel` binary.
 ///
-/// The function is called from the assembly `_start` function.
+/// The function is called from the assembly `_start` function on the boot core, and from
+/// `_start_secondary` on secondary cores. In the latter case, `virt_runtime_init_addr` points to
+/// `secondary_runtime_init()` instead, and `phys_dtb_addr` is zero. The stack addresses are those of
+/// the executing core.
 ///
 /// # Safety
 ///
@@ -79,19 +87,38 @@ pub unsafe extern "C" fn _start_rust(
     phys_kernel_tables_base_addr: u64,
     virt_boot_core_stack_end_exclusive_addr: u64,
     virt_runtime_init_addr: u64,
+    phys_dtb_addr: u64,
+    virt_exception_stack_end_exclusive_addr: u64,
 ) -> ! {
     prepare_el2_to_el1_transition(
         virt_boot_core_stack_end_exclusive_addr,
+        virt_exception_stack_end_exclusive_addr,
         virt_runtime_init_addr,
     );
 
-    // Turn on the MMU for EL1.
+    // Enable the fast path of `cpu::core_id()` on this core.
+    cpu::cache_core_id();
+
+    // Make sure that `cpu::wait_for_event()` cannot sleep forever.
+    cpu::enable_event_stream();
+
+    // Start the cycle counter, and hand the performance monitors to EL1.
+    cpu::pmu::init();
+
+    // Turn on the MMU for EL1. The boot core configures the translation regime, the secondary
+    // cores apply the boot core's configuration.
     let addr = Address::new(phys_kernel_tables_base_addr as usize);
-    if unlikely(memory::mmu::enable_mmu_and_caching(addr).is_err()) {
+    let result = if cpu::is_boot_core() {
+        memory::mmu::enable_mmu_and_caching(addr)
+    } else {
+        memory::mmu::saved_translation_control().and_then(|x| memory::mmu::activate(&x, addr))
+    };
+    if unlikely(result.is_err()) {
         cpu::wait_forever();
     }
 
     // Use `eret` to "return" to EL1. Since virtual memory will already be enabled, this results in
-    // execution of runtime_init() in EL1 from its _virtual address_.
-    asm::eret()
+    // execution of runtime_init() in EL1 from its _virtual address_. The device tree address is
+    // handed over as its argument.
+    asm!("eret", in("x0") phys_dtb_addr, options(noreturn))
 }
@@ -30,6 +30,17 @@
 	movk	\register, #:abs_g0_nc:\symbol
 .endm
 
+// Load the _absolute_ (virtual) end address of the exception stack of the core whose id is in
+// `\core_id`. Each slot starts with a guard page, so the stack ends where the next slot starts:
+//
+// __exception_stacks_start + ((core_id + 1) * __exception_stack_slot_size)
+.macro ADR_EXCEPTION_STACK_END register, core_id, scratch
+	ADR_ABS	\register, __exception_stacks_start
+	ADR_ABS	\scratch, __exception_stack_slot_size
+	madd	\register, \core_id, \scratch, \register
+	add	\register, \register, \scratch
+.endm
+
 .equ _EL2, 0x8
 .equ _core_id_mask, 0b11
 
@@ -42,6 +53,9 @@
 // fn _start()
 //------------------------------------------------------------------------------
 _start:
+	// Preserve the address of the device tree that the firmware passed.
+	mov	x3, x0
+
 	// Only proceed if the core executes in EL2. Park it otherwise.
 	mrs	x0, CurrentEL
 	cmp	x0, _EL2
@@ -56,6 +70,9 @@ _start:
 
 	// If execution reaches here, it is the boot core. Now, prepare the jump to Rust code.
 
+	// Load the _absolute_ address of the end of this core's exception stack.
+	ADR_EXCEPTION_STACK_END x4, x1, x5
+
 	// Load the base address of the kernel's translation tables.
 	ldr	x0, PHYS_KERNEL_TABLES_BASE_ADDR // provided by bsp/__board_name__/memory/mmu.rs
 
@@ -72,10 +89,13 @@ _start:
 	// Setting the stack pointer to this value ensures that anything that still runs in EL2,
 	// until the kernel returns to EL1 with the MMU enabled, works as well. After the return to
 	// EL1, the virtual address of the stack retrieved above will be used.
-	ADR_REL	x4, __boot_core_stack_end_exclusive
-	mov	sp, x4
+	ADR_REL	x5, __boot_core_stack_end_exclusive
+	mov	sp, x5
+
+	// Terminate the chain of frame records, see `cpu::return_addresses()`.
+	mov	x29, xzr
 
-	// Jump to Rust code. x0, x1 and x2 hold the function arguments provided to _start_rust().
+	// Jump to Rust code. x0 to x4 hold the function arguments provided to _start_rust().
 	b	_start_rust
 
 	// Infinitely wait for events (aka "park the core").
@@ -85,3 +105,54 @@ _start:
 .size	_start, . - _start
 .type	_start, function
 .global	_start
+
+//------------------------------------------------------------------------------
+// fn _start_secondary()
+//------------------------------------------------------------------------------
+_start_secondary:
+	// Only proceed if the core executes in EL2. Park it otherwise.
+	mrs	x0, CurrentEL
+	cmp	x0, _EL2
+	b.ne	1f
+
+	// Secondary cores are released from the firmware's spin-table by the boot core. The stack
+	// slot of each secondary core is selected using its core id.
+	mrs	x3, MPIDR_EL1
+	and	x3, x3, _core_id_mask
+	ADR_ABS	x4, __secondary_core_stack_slot_size
+
+	// Load the base address of the kernel's translation tables.
+	ldr	x0, PHYS_KERNEL_TABLES_BASE_ADDR // provided by bsp/__board_name__/memory/mmu.rs
+
+	// Calculate the _absolute_ (virtual) stack end address of this core's slot:
+	//
+	// __secondary_core_stacks_start + (core_id * __secondary_core_stack_slot_size)
+	ADR_ABS	x1, __secondary_core_stacks_start
+	madd	x1, x3, x4, x1
+	ADR_ABS	x2, secondary_runtime_init
+
+	// Same calculation as above, but PC-relative, which returns the "physical" address that is
+	// needed until the MMU is switched on.
+	ADR_REL	x5, __secondary_core_stacks_start
+	madd	x5, x3, x4, x5
+	mov	sp, x5
+
+	// Load the _absolute_ address of the end of this core's exception stack.
+	ADR_EXCEPTION_STACK_END x4, x3, x5
+
+	// Secondary cores don't get a device tree.
+	mov	x3, xzr
+
+	// Terminate the chain of frame records, see `cpu::return_addresses()`.
+	mov	x29, xzr
+
+	// Jump to Rust code. x0 to x4 hold the function arguments provided to _start_rust().
+	b	_start_rust
+
+	// Infinitely wait for events (aka "park the core").
+1:	wfe
+	b	1b
+
+.size	_start_secondary, . - _start_secondary
+.type	_start_secondary, function
+.global	_start_secondary
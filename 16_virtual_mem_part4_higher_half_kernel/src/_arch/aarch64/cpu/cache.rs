@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural cache control.
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::cpu::cache::arch_cache
+
+use crate::cpu::{barrier, cache::CacheStatus};
+use cortex_a::regs::*;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Invalidate all instruction caches to the point of unification.
+///
+/// # Safety
+///
+/// - Changes the HW state of the executing core.
+#[inline(always)]
+pub unsafe fn invalidate_all_icache() {
+    asm!("ic iallu", options(nostack, preserves_flags));
+    barrier::complete_local_invalidation();
+}
+
+/// Enable the instruction cache.
+///
+/// # Safety
+///
+/// - Changes the HW state of the executing core.
+#[inline(always)]
+pub unsafe fn enable_icache() {
+    SCTLR_EL1.modify(SCTLR_EL1::I::Cacheable);
+    barrier::complete_system_register_write();
+}
+
+/// Disable the instruction cache.
+///
+/// The cache is invalidated afterwards, so that no stale instructions are hit once it is enabled
+/// again.
+///
+/// # Safety
+///
+/// - Changes the HW state of the executing core.
+#[inline(always)]
+pub unsafe fn disable_icache() {
+    SCTLR_EL1.modify(SCTLR_EL1::I::NonCacheable);
+    barrier::complete_system_register_write();
+
+    invalidate_all_icache();
+}
+
+/// Enable the data cache.
+///
+/// # Safety
+///
+/// - Changes the HW state of the executing core.
+#[inline(always)]
+pub unsafe fn enable_dcache() {
+    SCTLR_EL1.modify(SCTLR_EL1::C::Cacheable);
+    barrier::complete_system_register_write();
+}
+
+/// Disable the data cache.
+///
+/// Once the cache is disabled, all data caches are cleaned and invalidated by set/way, so that
+/// dirty lines reach memory and no stale lines are hit once the cache is enabled again. Both steps
+/// are done in a single assembly block, so that the compiler cannot place stack accesses in
+/// between.
+///
+/// # Safety
+///
+/// - Changes the HW state of the executing core.
+/// - Only the executing core is affected. Data shared with other cores may go out of sync.
+/// - Atomic read-modify-write operations need cacheable memory on the Raspberry's cores. Callers
+///   must not use them while the data cache is disabled.
+#[inline(always)]
+pub unsafe fn disable_dcache() {
+    asm!(
+        // Clear SCTLR_EL1.C.
+        "mrs {tmp}, SCTLR_EL1",
+        "bic {tmp}, {tmp}, #(1 << 2)",
+        "msr SCTLR_EL1, {tmp}",
+        "isb",
+        // Level of coherence from CLIDR_EL1.
+        "mrs {clidr}, CLIDR_EL1",
+        "ubfx {loc}, {clidr}, #24, #3",
+        "cbz {loc}, 5f",
+        "mov {level}, #0",
+        // For each cache level up to the level of coherence.
+        "1:",
+        "add {tmp}, {level}, {level}, lsl #1",
+        "lsr {tmp}, {clidr}, {tmp}",
+        "and {tmp}, {tmp}, #7",
+        // Skip levels without a data cache.
+        "cmp {tmp}, #2",
+        "b.lt 4f",
+        // Select the level's data cache and read its geometry.
+        "lsl {tmp}, {level}, #1",
+        "msr CSSELR_EL1, {tmp}",
+        "isb",
+        "mrs {ccsidr}, CCSIDR_EL1",
+        // Line size shift.
+        "and {line}, {ccsidr}, #7",
+        "add {line}, {line}, #4",
+        // Maximum way number and the shift of the way field.
+        "ubfx {way}, {ccsidr}, #3, #10",
+        "clz {wshift:w}, {way:w}",
+        // For each way.
+        "2:",
+        "ubfx {set}, {ccsidr}, #13, #15",
+        // For each set.
+        "3:",
+        "lsl {tmp}, {way}, {wshift}",
+        "orr {tmp}, {tmp}, {level}, lsl #1",
+        "lsl {op}, {set}, {line}",
+        "orr {tmp}, {tmp}, {op}",
+        "dc cisw, {tmp}",
+        "subs {set}, {set}, #1",
+        "b.ge 3b",
+        "subs {way}, {way}, #1",
+        "b.ge 2b",
+        "4:",
+        "add {level}, {level}, #1",
+        "cmp {level}, {loc}",
+        "b.lt 1b",
+        "5:",
+        // Restore the default cache selection.
+        "msr CSSELR_EL1, xzr",
+        "dsb sy",
+        "isb",
+        tmp = out(reg) _,
+        clidr = out(reg) _,
+        loc = out(reg) _,
+        level = out(reg) _,
+        ccsidr = out(reg) _,
+        line = out(reg) _,
+        way = out(reg) _,
+        wshift = out(reg) _,
+        set = out(reg) _,
+        op = out(reg) _,
+        options(nostack)
+    );
+}
+
+/// Clean and invalidate the data cache lines covering `size` bytes at `virt_addr` to the point of
+/// coherency.
+///
+/// Used for memory that is shared with bus masters which do not snoop the cores' caches. Dirty
+/// lines reach memory, and the next read fetches what the other bus master wrote.
+///
+/// # Safety
+///
+/// - The range must be mapped.
+#[inline(always)]
+pub unsafe fn clean_invalidate_dcache_range(virt_addr: usize, size: usize) {
+    let ctr: u64;
+    asm!("mrs {}, CTR_EL0", out(reg) ctr, options(nomem, nostack, preserves_flags));
+
+    // DminLine is the log2 of the number of words in the smallest data cache line.
+    let line_size: usize = 4 << ((ctr >> 16) & 0xF);
+
+    let mut addr = virt_addr & !(line_size - 1);
+    while addr < virt_addr + size {
+        asm!("dc civac, {}", in(reg) addr, options(nostack, preserves_flags));
+        addr += line_size;
+    }
+
+    barrier::complete_cache_maintenance();
+}
+
+/// Return the current state of the MMU and the caches.
+pub fn status() -> CacheStatus {
+    CacheStatus {
+        mmu: SCTLR_EL1.matches_all(SCTLR_EL1::M::Enable),
+        dcache: SCTLR_EL1.matches_all(SCTLR_EL1::C::Cacheable),
+        icache: SCTLR_EL1.matches_all(SCTLR_EL1::I::Cacheable),
+    }
+}
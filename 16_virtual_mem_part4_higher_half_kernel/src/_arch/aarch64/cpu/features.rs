@@ -0,0 +1,229 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural CPU feature detection.
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::cpu::features::arch_features
+
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Read a system register by name.
+macro_rules! read_sysreg {
+    ($name:literal) => {{
+        let value: u64;
+
+        // Safety: Reading ID registers has no side effects.
+        unsafe {
+            asm!(
+                concat!("mrs {}, ", $name),
+                out(reg) value,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+
+        value
+    }};
+}
+
+/// Extract the 4 bit ID register field starting at `shift`.
+#[inline(always)]
+fn field(reg: u64, shift: u32) -> u64 {
+    (reg >> shift) & 0xf
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// CPU features that can be queried at runtime.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Feature {
+    FloatingPoint,
+    AdvancedSIMD,
+    AES,
+    PMULL,
+    SHA1,
+    SHA256,
+    CRC32,
+    LSE,
+    RNDR,
+    Granule4KiB,
+    Granule16KiB,
+    Granule64KiB,
+}
+
+/// The processor implementation, decoded from `MIDR_EL1`.
+#[derive(Copy, Clone)]
+pub struct Implementation {
+    implementer: u8,
+    variant: u8,
+    part_num: u16,
+    revision: u8,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Feature {
+    /// All features, in reporting order.
+    pub const ALL: [Feature; 12] = [
+        Feature::FloatingPoint,
+        Feature::AdvancedSIMD,
+        Feature::AES,
+        Feature::PMULL,
+        Feature::SHA1,
+        Feature::SHA256,
+        Feature::CRC32,
+        Feature::LSE,
+        Feature::RNDR,
+        Feature::Granule4KiB,
+        Feature::Granule16KiB,
+        Feature::Granule64KiB,
+    ];
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Feature::FloatingPoint => "Floating point",
+            Feature::AdvancedSIMD => "Advanced SIMD",
+            Feature::AES => "AES",
+            Feature::PMULL => "PMULL",
+            Feature::SHA1 => "SHA1",
+            Feature::SHA256 => "SHA256",
+            Feature::CRC32 => "CRC32",
+            Feature::LSE => "Atomics (LSE)",
+            Feature::RNDR => "RNDR",
+            Feature::Granule4KiB => "4 KiB granule",
+            Feature::Granule16KiB => "16 KiB granule",
+            Feature::Granule64KiB => "64 KiB granule",
+        };
+
+        f.pad(name)
+    }
+}
+
+impl Implementation {
+    /// Decode a raw `MIDR_EL1` value.
+    pub const fn from_midr(midr: u64) -> Self {
+        Self {
+            implementer: (midr >> 24) as u8,
+            variant: ((midr >> 20) & 0xf) as u8,
+            part_num: ((midr >> 4) & 0xfff) as u16,
+            revision: (midr & 0xf) as u8,
+        }
+    }
+
+    /// The implementer's name, if known.
+    pub fn implementer_name(&self) -> Option<&'static str> {
+        match self.implementer {
+            0x41 => Some("ARM"),
+            _ => None,
+        }
+    }
+
+    /// The part's name, if known.
+    pub fn part_name(&self) -> Option<&'static str> {
+        match (self.implementer, self.part_num) {
+            (0x41, 0xd03) => Some("Cortex-A53"),
+            (0x41, 0xd08) => Some("Cortex-A72"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Implementation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.implementer_name() {
+            Some(x) => write!(f, "{} ", x)?,
+            None => write!(f, "Implementer {:#04x} ", self.implementer)?,
+        }
+
+        match self.part_name() {
+            Some(x) => write!(f, "{} ", x)?,
+            None => write!(f, "Part {:#05x} ", self.part_num)?,
+        }
+
+        write!(f, "r{}p{}", self.variant, self.revision)
+    }
+}
+
+/// Return the executing core's implementation.
+pub fn implementation() -> Implementation {
+    Implementation::from_midr(read_sysreg!("MIDR_EL1"))
+}
+
+/// Return the supported physical address range in bits.
+pub fn pa_range_bits() -> Option<u32> {
+    let bits = match field(read_sysreg!("ID_AA64MMFR0_EL1"), 0) {
+        0b0000 => 32,
+        0b0001 => 36,
+        0b0010 => 40,
+        0b0011 => 42,
+        0b0100 => 44,
+        0b0101 => 48,
+        0b0110 => 52,
+        _ => return None,
+    };
+
+    Some(bits)
+}
+
+/// Return true if the executing core supports the given feature.
+///
+/// The MMU code checks the translation granule with it. `LSE` and `RNDR` are only reported for now:
+/// The kernel has no spinlock type whose atomics could be switched at runtime, and no RNG driver.
+pub fn has_feature(feature: Feature) -> bool {
+    match feature {
+        // A value of 0b1111 means not implemented.
+        Feature::FloatingPoint => field(read_sysreg!("ID_AA64PFR0_EL1"), 16) != 0b1111,
+        Feature::AdvancedSIMD => field(read_sysreg!("ID_AA64PFR0_EL1"), 20) != 0b1111,
+
+        Feature::AES => field(read_sysreg!("ID_AA64ISAR0_EL1"), 4) >= 0b0001,
+        Feature::PMULL => field(read_sysreg!("ID_AA64ISAR0_EL1"), 4) >= 0b0010,
+        Feature::SHA1 => field(read_sysreg!("ID_AA64ISAR0_EL1"), 8) >= 0b0001,
+        Feature::SHA256 => field(read_sysreg!("ID_AA64ISAR0_EL1"), 12) >= 0b0001,
+        Feature::CRC32 => field(read_sysreg!("ID_AA64ISAR0_EL1"), 16) >= 0b0001,
+        Feature::LSE => field(read_sysreg!("ID_AA64ISAR0_EL1"), 20) >= 0b0010,
+        Feature::RNDR => field(read_sysreg!("ID_AA64ISAR0_EL1"), 60) >= 0b0001,
+
+        // TGran4 and TGran64 encode 0b0000 as supported, TGran16 encodes 0b0000 as unsupported.
+        Feature::Granule4KiB => field(read_sysreg!("ID_AA64MMFR0_EL1"), 28) != 0b1111,
+        Feature::Granule16KiB => field(read_sysreg!("ID_AA64MMFR0_EL1"), 20) != 0b0000,
+        Feature::Granule64KiB => field(read_sysreg!("ID_AA64MMFR0_EL1"), 24) != 0b1111,
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check decoding of known and unknown `MIDR_EL1` values.
+    #[kernel_test]
+    fn midr_decoding_works() {
+        let a72 = Implementation::from_midr(0x410f_d083);
+        assert_eq!(a72.part_name(), Some("Cortex-A72"));
+        assert_eq!((a72.variant, a72.revision), (0, 3));
+
+        let unknown = Implementation::from_midr(0x5100_c000);
+        assert_eq!(unknown.implementer_name(), None);
+        assert_eq!(unknown.part_name(), None);
+    }
+}
@@ -0,0 +1,345 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural performance monitors.
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::cpu::pmu::arch_pmu
+
+use crate::{bsp, config, cpu, cpu::barrier, driver, exception};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Read a system register by name.
+macro_rules! read_sysreg {
+    ($name:literal) => {{
+        let value: u64;
+
+        // Safety: Reading the PMU registers has no side effects.
+        unsafe {
+            asm!(
+                concat!("mrs {}, ", $name),
+                out(reg) value,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+
+        value
+    }};
+}
+
+/// Write a system register by name.
+macro_rules! write_sysreg {
+    ($name:literal, $value:expr) => {{
+        let value: u64 = $value;
+
+        asm!(
+            concat!("msr ", $name, ", {}"),
+            in(reg) value,
+            options(nomem, nostack, preserves_flags)
+        );
+    }};
+}
+
+/// PMCR_EL0 bits.
+mod pmcr {
+    pub const E: u64 = 1 << 0;
+    pub const P: u64 = 1 << 1;
+    pub const C: u64 = 1 << 2;
+    pub const LC: u64 = 1 << 6;
+    pub const N_SHIFT: u64 = 11;
+    pub const N_MASK: u64 = 0x1f;
+}
+
+/// MDCR_EL2 bits.
+mod mdcr {
+    pub const HPMN_MASK: u64 = 0x1f;
+    pub const TPMCR: u64 = 1 << 5;
+    pub const TPM: u64 = 1 << 6;
+    pub const HPME: u64 = 1 << 7;
+}
+
+/// PMUSERENR_EL0 bits.
+mod pmuserenr {
+    pub const EN: u64 = 1 << 0;
+    pub const CR: u64 = 1 << 2;
+    pub const ER: u64 = 1 << 3;
+}
+
+/// The bit of the cycle counter in PMCNTENSET_EL0 and friends.
+const CYCLE_COUNTER_BIT: u64 = 1 << 31;
+
+/// The event counters are 32 bit wide.
+const COUNTER_BITS: u32 = 32;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_OVERFLOWS: [AtomicU32; MAX_COUNTERS] = [
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+];
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The number of event counters that are supported at most. Both the Cortex-A53 and the Cortex-A72
+/// have six.
+pub const MAX_COUNTERS: usize = 6;
+
+/// Events that an event counter can count.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// Architecturally executed instructions. INST_RETIRED.
+    InstructionsRetired,
+
+    /// Accesses that missed in the L1 data cache. L1D_CACHE_REFILL.
+    L1DRefill,
+
+    /// Accesses that missed in the L1 data TLB. L1D_TLB_REFILL.
+    TlbRefill,
+}
+
+/// The PMU's overflow interrupt.
+///
+/// Counts the overflows of the 32 bit event counters, so that `read_counter()` returns 64 bit
+/// values even for long measurements. The cycle counter is 64 bit wide and does not overflow in
+/// practice.
+pub struct PerformanceMonitor {
+    irq_number: bsp::device_driver::IRQNumber,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// The number of overflows of each event counter, per core.
+static OVERFLOWS: [[AtomicU32; MAX_COUNTERS]; config::cpu::NUM_CORES] =
+    [NO_OVERFLOWS; config::cpu::NUM_CORES];
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Event {
+    /// The architectural event number.
+    const fn number(&self) -> u64 {
+        match self {
+            Event::InstructionsRetired => 0x08,
+            Event::L1DRefill => 0x03,
+            Event::TlbRefill => 0x05,
+        }
+    }
+}
+
+/// The number of event counters that the hardware implements.
+fn implemented_counters() -> usize {
+    ((read_sysreg!("PMCR_EL0") >> pmcr::N_SHIFT) & pmcr::N_MASK) as usize
+}
+
+/// Select the event counter that PMXEVTYPER_EL0 and PMXEVCNTR_EL0 refer to.
+///
+/// # Safety
+///
+/// - Changes the HW state of the executing core. The caller must mask IRQs, so that the selection
+///   is not changed under its feet.
+unsafe fn select_counter(idx: usize) {
+    write_sysreg!("PMSELR_EL0", idx as u64);
+    barrier::isb();
+}
+
+/// Read the raw 32 bit value of an event counter.
+fn read_counter_low(idx: usize) -> u64 {
+    exception::asynchronous::exec_with_irq_masked(|| unsafe {
+        select_counter(idx);
+
+        read_sysreg!("PMXEVCNTR_EL0")
+    })
+}
+
+/// Return true if an overflow of the event counter is pending.
+fn is_overflow_pending(idx: usize) -> bool {
+    read_sysreg!("PMOVSCLR_EL0") & (1 << idx) != 0
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Make the PMU available to EL1, start the cycle counter, and allow or deny EL0 access as
+/// configured by `config::cpu::PMU_EL0_ACCESS`.
+///
+/// # Safety
+///
+/// - Changes the HW state of the executing core.
+/// - Must be called in EL2, on each core.
+pub unsafe fn init() {
+    let num_counters = implemented_counters() as u64;
+
+    // Do not trap PMU accesses of EL1 and EL0 to EL2, and give all counters to them.
+    let mut mdcr_el2 = read_sysreg!("MDCR_EL2");
+    mdcr_el2 &= !(mdcr::HPMN_MASK | mdcr::TPMCR | mdcr::TPM | mdcr::HPME);
+    mdcr_el2 |= num_counters;
+    write_sysreg!("MDCR_EL2", mdcr_el2);
+
+    let el0_access = if config::cpu::PMU_EL0_ACCESS {
+        pmuserenr::EN | pmuserenr::CR | pmuserenr::ER
+    } else {
+        0
+    };
+    write_sysreg!("PMUSERENR_EL0", el0_access);
+
+    // Count cycles in EL1 and EL0, with the full 64 bit.
+    write_sysreg!("PMCCFILTR_EL0", 0);
+    write_sysreg!("PMCR_EL0", pmcr::E | pmcr::P | pmcr::C | pmcr::LC);
+    write_sysreg!("PMCNTENSET_EL0", CYCLE_COUNTER_BIT);
+    barrier::complete_system_register_write();
+}
+
+/// The number of event counters that can be configured.
+pub fn num_counters() -> usize {
+    implemented_counters().min(MAX_COUNTERS)
+}
+
+/// Read the executing core's cycle counter.
+#[inline(always)]
+pub fn read_cycles() -> u64 {
+    // Prevent that the counter is read ahead of time due to out-of-order execution.
+    barrier::isb();
+
+    read_sysreg!("PMCCNTR_EL0")
+}
+
+/// Let event counter `idx` of the executing core count `event`, starting at zero.
+///
+/// The counter counts in EL1 and EL0. Its overflow interrupt is enabled, so that the overflows are
+/// accounted for once `PerformanceMonitor`'s IRQ handler is registered.
+pub fn configure_counter(idx: usize, event: Event) -> Result<(), &'static str> {
+    if idx >= num_counters() {
+        return Err("Event counter index out of range");
+    }
+
+    let bit = 1 << idx;
+    exception::asynchronous::exec_with_irq_masked(|| unsafe {
+        write_sysreg!("PMCNTENCLR_EL0", bit);
+        write_sysreg!("PMOVSCLR_EL0", bit);
+
+        select_counter(idx);
+        write_sysreg!("PMXEVTYPER_EL0", event.number());
+        write_sysreg!("PMXEVCNTR_EL0", 0);
+
+        OVERFLOWS[cpu::core_id()][idx].store(0, Ordering::Relaxed);
+
+        write_sysreg!("PMINTENSET_EL1", bit);
+        write_sysreg!("PMCNTENSET_EL0", bit);
+        barrier::complete_system_register_write();
+    });
+
+    Ok(())
+}
+
+/// Read event counter `idx` of the executing core, including its overflows.
+pub fn read_counter(idx: usize) -> Result<u64, &'static str> {
+    if idx >= num_counters() {
+        return Err("Event counter index out of range");
+    }
+
+    let value = exception::asynchronous::exec_with_irq_masked(|| {
+        let overflows = u64::from(OVERFLOWS[cpu::core_id()][idx].load(Ordering::Relaxed));
+
+        // An overflow that is pending, but not handled yet, must be counted as well. If it happens
+        // while reading, the counter is read again, so that both values are from after it.
+        let pending = is_overflow_pending(idx);
+        let mut low = read_counter_low(idx);
+        let pending_after = is_overflow_pending(idx);
+        if pending != pending_after {
+            low = read_counter_low(idx);
+        }
+
+        let overflows = overflows + u64::from(pending_after);
+        (overflows << COUNTER_BITS) | low
+    });
+
+    Ok(value)
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Event::InstructionsRetired => "instructions",
+            Event::L1DRefill => "L1D refills",
+            Event::TlbRefill => "TLB refills",
+        })
+    }
+}
+
+impl PerformanceMonitor {
+    /// Create an instance with the IRQ of the PMU's overflow interrupt.
+    pub const fn new(irq_number: bsp::device_driver::IRQNumber) -> Self {
+        Self { irq_number }
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl driver::interface::DeviceDriver for PerformanceMonitor {
+    fn compatible(&self) -> &'static str {
+        "ARMv8 Performance Monitors"
+    }
+
+    fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
+        use bsp::exception::asynchronous::irq_manager;
+        use exception::asynchronous::{interface::IRQManager, IRQDescriptor};
+
+        let descriptor = IRQDescriptor {
+            name: "ARMv8 PMU Overflow",
+            handler: self,
+        };
+
+        irq_manager().register_handler(self.irq_number, descriptor)?;
+        irq_manager().enable(self.irq_number);
+
+        Ok(())
+    }
+
+    fn irq_numbers(&self) -> &[bsp::device_driver::IRQNumber] {
+        core::slice::from_ref(&self.irq_number)
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for PerformanceMonitor {
+    fn handle(&self, _context: &exception::ExceptionContext) -> Result<(), &'static str> {
+        // The IRQ is level-sensitive. Clearing the overflow flags deasserts it.
+        let pending = read_sysreg!("PMOVSCLR_EL0") & ((1 << MAX_COUNTERS) - 1);
+        if pending == 0 {
+            return Err("No event counter overflowed");
+        }
+
+        unsafe { write_sysreg!("PMOVSCLR_EL0", pending) };
+
+        let overflows = &OVERFLOWS[cpu::core_id()];
+        for (idx, x) in overflows.iter().enumerate() {
+            if pending & (1 << idx) != 0 {
+                x.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        Ok(())
+    }
+}
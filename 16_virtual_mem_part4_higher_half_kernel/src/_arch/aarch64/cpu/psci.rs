@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural Power State Coordination Interface.
+//!
+//! # Resources
+//!
+//! - <https://developer.arm.com/documentation/den0022/latest>
+//! - <https://developer.arm.com/documentation/den0028/latest>
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::cpu::psci::arch_psci
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The instruction used to call into the PSCI firmware.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Conduit {
+    /// Secure Monitor Call, handled by firmware in EL3.
+    Smc,
+
+    /// Hypervisor Call, handled by firmware or a hypervisor in EL2.
+    Hvc,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Issue a PSCI call following the SMC Calling Convention.
+///
+/// # Safety
+///
+/// - The caller must ensure that firmware servicing the chosen conduit exists. Otherwise, the
+///   exception is taken to an undefined vector and the core is lost.
+pub unsafe fn call(conduit: Conduit, function_id: u32, arg0: u64, arg1: u64, arg2: u64) -> i64 {
+    let mut ret = function_id as u64;
+
+    // SMCCC v1.0 allows the firmware to overwrite x4 to x17, so they are declared as clobbered.
+    match conduit {
+        Conduit::Smc => asm!(
+            "smc #0",
+            inout("x0") ret,
+            inout("x1") arg0 => _,
+            inout("x2") arg1 => _,
+            inout("x3") arg2 => _,
+            out("x4") _,
+            out("x5") _,
+            out("x6") _,
+            out("x7") _,
+            out("x8") _,
+            out("x9") _,
+            out("x10") _,
+            out("x11") _,
+            out("x12") _,
+            out("x13") _,
+            out("x14") _,
+            out("x15") _,
+            out("x16") _,
+            out("x17") _,
+            options(nostack)
+        ),
+        Conduit::Hvc => asm!(
+            "hvc #0",
+            inout("x0") ret,
+            inout("x1") arg0 => _,
+            inout("x2") arg1 => _,
+            inout("x3") arg2 => _,
+            out("x4") _,
+            out("x5") _,
+            out("x6") _,
+            out("x7") _,
+            out("x8") _,
+            out("x9") _,
+            out("x10") _,
+            out("x11") _,
+            out("x12") _,
+            out("x13") _,
+            out("x14") _,
+            out("x15") _,
+            out("x16") _,
+            out("x17") _,
+            options(nostack)
+        ),
+    }
+
+    ret as i64
+}
@@ -11,19 +11,52 @@
 //!
 //! crate::cpu::smp::arch_smp
 
-use cortex_a::regs::*;
+use crate::{
+    bsp, config,
+    cpu::barrier,
+    memory,
+    memory::{Address, Physical},
+};
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
-/// Return the executing core's id.
-#[inline(always)]
-pub fn core_id<T>() -> T
-where
-    T: From<u8>,
-{
-    const CORE_MASK: u64 = 0b11;
+/// Return the physical address of the secondary cores' entry point, `_start_secondary`.
+pub fn phys_secondary_entry_addr() -> Result<Address<Physical>, &'static str> {
+    extern "C" {
+        fn _start_secondary();
+    }
 
-    T::from((MPIDR_EL1.get() & CORE_MASK) as u8)
+    let virt_entry_addr = Address::new(_start_secondary as usize);
+
+    memory::mmu::try_virt_to_phys(virt_entry_addr).map_err(|_| "Secondary core entry is not mapped")
+}
+
+/// Release the secondary cores from the firmware's spin-table.
+///
+/// The physical address of `_start_secondary` is written to the spin-table entry of each secondary
+/// core. Afterwards, the cores, which are waiting in `wfe`, are woken up with `sev`.
+pub fn release_secondary_cores_spin_table() -> Result<(), &'static str> {
+    let phys_entry_addr = phys_secondary_entry_addr()?;
+
+    let mut num_released = 0;
+    for core_id in 0..config::cpu::NUM_CORES {
+        if let Some(release_addr) = bsp::cpu::spin_table_release_addr(core_id) {
+            unsafe { core::ptr::write_volatile(release_addr, phys_entry_addr.into_usize() as u64) };
+            num_released += 1;
+        }
+    }
+
+    if num_released == 0 {
+        return Err("Spin-table not available");
+    }
+
+    // Make sure the entry addresses are observable by the secondary cores before waking them up.
+    barrier::dsb_sy();
+    unsafe {
+        asm!("sev", options(nomem, nostack, preserves_flags));
+    }
+
+    Ok(())
 }
@@ -13,11 +13,15 @@
 
 use crate::{
     bsp::{self},
-    exception,
-    memory::Address,
+    cmdline, config, console,
+    cpu::{self, barrier},
+    exception::{self, core_dump, FaultClass, SyncExceptionInfo},
+    memory::{self, mmu, Address},
+    panic_record,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
 };
-use core::{cell::UnsafeCell, fmt};
-use cortex_a::{barrier, regs::*};
+use core::{cell::UnsafeCell, fmt, mem};
+use cortex_a::regs::*;
 use register::InMemoryRegister;
 
 // Assembly counterpart to this file.
@@ -31,9 +35,63 @@ global_asm!(include_str!("exception.s"));
 #[repr(transparent)]
 struct SpsrEL1(InMemoryRegister<u64, SPSR_EL1::Register>);
 
+/// Wrapper struct for printing the registers of a context that differ from a snapshot.
+struct SnapshotDiff<'a> {
+    context: &'a ExceptionContext,
+    snapshot: Option<Snapshot>,
+}
+
+/// Wrapper struct for pretty printing ESR_EL1.
+struct EsrEL1;
+
+/// Exception classes of ESR_EL1 that are decoded further.
+mod ec {
+    pub const INSTR_ABORT_LOWER_EL: u64 = 0x20;
+    pub const INSTR_ABORT_CURRENT_EL: u64 = 0x21;
+    pub const DATA_ABORT_LOWER_EL: u64 = 0x24;
+    pub const DATA_ABORT_CURRENT_EL: u64 = 0x25;
+    pub const SVC64: u64 = 0x15;
+    pub const BRK64: u64 = 0x3c;
+}
+
+/// The size of an A64 instruction. All of them have the same.
+const INSTRUCTION_SIZE: u64 = 4;
+
+/// Immediate of the breakpoint that `null_exception()` executes. The handler returns right away.
+const NULL_EXCEPTION_BRK_IMM: u64 = 0x7e6;
+
+/// Immediate of the breakpoint that `take_context_snapshot()` executes.
+const SNAPSHOT_BRK_IMM: u64 = 0x7e7;
+
+/// Immediate of the breakpoint that `core_dump()` executes.
+const CORE_DUMP_BRK_IMM: u64 = 0x7e8;
+
+/// Names of the registers after the general purpose ones, in the order of `Snapshot::get()`.
+const SPECIAL_REGISTER_NAMES: [&str; 4] = ["lr", "elr_el1", "spsr_el1", "sp_el0"];
+
+/// Number of registers in a core dump. The snapshot's registers come first, followed by the
+/// interrupted stack pointer, ESR_EL1 and FAR_EL1.
+const NUM_CORE_DUMP_REGISTERS: usize = Snapshot::NUM_REGISTERS + 3;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A copy of the register values of an exception context, for comparison with a later one.
+///
+/// See `ExceptionContext::snapshot()` and `ExceptionContext::print_diff()`.
+#[derive(Copy, Clone)]
+pub struct Snapshot {
+    gpr: [u64; 30],
+    lr: u64,
+    elr_el1: u64,
+    spsr_el1: u64,
+    sp_el0: u64,
+}
+
 /// The exception context as it is stored on the stack on exception entry.
 #[repr(C)]
-struct ExceptionContext {
+pub struct ExceptionContext {
     /// General Purpose Registers.
     gpr: [u64; 30],
 
@@ -45,10 +103,17 @@ struct ExceptionContext {
 
     /// Saved program status.
     spsr_el1: SpsrEL1,
+
+    /// The stack pointer of EL0.
+    sp_el0: u64,
 }
 
-/// Wrapper struct for pretty printing ESR_EL1.
-struct EsrEL1;
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// The snapshot saved by `take_context_snapshot()`.
+static SNAPSHOT: IRQSafeNullLock<Option<Snapshot>> = IRQSafeNullLock::new(None);
 
 //--------------------------------------------------------------------------------------------------
 // Private Code
@@ -58,6 +123,10 @@ struct EsrEL1;
 fn inspect_data_abort(f: &mut fmt::Formatter) -> fmt::Result {
     let fault_addr = Address::new(FAR_EL1.get() as usize);
 
+    if let Some(section) = bsp::memory::mmu::virt_addr_properties(fault_addr) {
+        writeln!(f, "\n\n      >> Fault address is in: {} <<", section.name)?;
+    }
+
     if bsp::memory::mmu::virt_boot_core_stack_guard_page_desc().contains(fault_addr) {
         writeln!(
             f,
@@ -65,56 +134,294 @@ fn inspect_data_abort(f: &mut fmt::Formatter) -> fmt::Result {
         )?;
     }
 
+    for core_id in 1..config::cpu::NUM_CORES {
+        if bsp::memory::mmu::virt_secondary_core_stack_guard_page_desc(core_id).contains(fault_addr)
+        {
+            writeln!(
+                f,
+                "\n\n      >> Attempted to access the guard page of core {}'s stack <<",
+                core_id
+            )?;
+        }
+    }
+
+    for core_id in 0..config::cpu::NUM_CORES {
+        if bsp::memory::mmu::virt_exception_stack_guard_page_desc(core_id).contains(fault_addr) {
+            writeln!(
+                f,
+                "\n\n      >> Attempted to access the guard page of core {}'s exception stack <<",
+                core_id
+            )?;
+        }
+    }
+
     Ok(())
 }
 
+/// Decode the fault status code of an instruction or data abort.
+fn decode_fault_status(iss: u64) -> FaultClass {
+    match iss & 0b11_1111 {
+        0b00_0100..=0b00_0111 => FaultClass::Translation,
+        0b00_1001..=0b00_1011 => FaultClass::AccessFlag,
+        0b00_1101..=0b00_1111 => FaultClass::Permission,
+        0b10_0001 => FaultClass::Alignment,
+        _ => FaultClass::Other,
+    }
+}
+
+/// The immediate of the breakpoint instruction that raised the exception that is currently being
+/// handled, if any.
+fn brk_immediate() -> Option<u64> {
+    let esr_el1 = ESR_EL1.extract();
+
+    if esr_el1.read(ESR_EL1::EC) != ec::BRK64 {
+        return None;
+    }
+
+    Some(esr_el1.read(ESR_EL1::ISS) & 0xFFFF)
+}
+
+impl ExceptionContext {
+    /// The stack that was in use when the exception was taken.
+    fn interrupted_stack(&self) -> &'static str {
+        match self.spsr_el1.0.read(SPSR_EL1::M) {
+            0b0100 => "Kernel stack (SP_EL0)",
+            0b0101 => "Exception stack (SP_EL1), nested exception",
+            _ => "Unknown",
+        }
+    }
+
+    /// The stack pointer at the time the exception was taken.
+    fn interrupted_sp(&self) -> u64 {
+        match self.spsr_el1.0.read(SPSR_EL1::M) {
+            // A nested exception pushed its context right below the interrupted one's stack.
+            0b0101 => self as *const _ as u64 + mem::size_of::<Self>() as u64,
+            _ => self.sp_el0,
+        }
+    }
+}
+
+impl Snapshot {
+    /// Number of registers in a snapshot.
+    const NUM_REGISTERS: usize = 30 + SPECIAL_REGISTER_NAMES.len();
+
+    /// The value of the register with index `i`. The general purpose registers come first.
+    fn get(&self, i: usize) -> u64 {
+        match i {
+            0..=29 => self.gpr[i],
+            30 => self.lr,
+            31 => self.elr_el1,
+            32 => self.spsr_el1,
+            _ => self.sp_el0,
+        }
+    }
+}
+
+/// Decode the synchronous exception that is currently being handled.
+fn sync_exception_info(e: &ExceptionContext) -> SyncExceptionInfo {
+    let esr_el1 = ESR_EL1.extract();
+    let iss = esr_el1.read(ESR_EL1::ISS);
+
+    let (class, is_abort) = match esr_el1.read(ESR_EL1::EC) {
+        ec::INSTR_ABORT_LOWER_EL
+        | ec::INSTR_ABORT_CURRENT_EL
+        | ec::DATA_ABORT_LOWER_EL
+        | ec::DATA_ABORT_CURRENT_EL => (decode_fault_status(iss), true),
+        ec::BRK64 => (FaultClass::Breakpoint, false),
+        ec::SVC64 => (FaultClass::SupervisorCall, false),
+        _ => (FaultClass::Other, false),
+    };
+
+    SyncExceptionInfo {
+        class,
+        fault_addr: if is_abort {
+            Some(Address::new(FAR_EL1.get() as usize))
+        } else {
+            None
+        },
+        pc: Address::new(e.elr_el1 as usize),
+        syndrome: esr_el1.get(),
+    }
+}
+
+/// Emit a core dump of the context through the emergency console.
+fn dump_context(e: &ExceptionContext) {
+    let snapshot = e.snapshot();
+    let sp = e.interrupted_sp();
+
+    let mut registers = [0; NUM_CORE_DUMP_REGISTERS];
+    for (i, x) in registers
+        .iter_mut()
+        .take(Snapshot::NUM_REGISTERS)
+        .enumerate()
+    {
+        *x = snapshot.get(i);
+    }
+    registers[Snapshot::NUM_REGISTERS] = sp;
+    registers[Snapshot::NUM_REGISTERS + 1] = ESR_EL1.get();
+    registers[Snapshot::NUM_REGISTERS + 2] = FAR_EL1.get();
+
+    core_dump::write(&registers, sp as usize);
+}
+
+/// Stop the executing core for good after a fatal exception that the panic handler cannot report.
+fn park_after_fatal_exception() -> ! {
+    #[cfg(feature = "test_build")]
+    {
+        crate::shutdown::kernel_shutdown(crate::shutdown::ShutdownReason::Panic)
+    }
+
+    #[cfg(not(feature = "test_build"))]
+    {
+        cpu::wait_forever()
+    }
+}
+
 /// Prints verbose information about the exception and then panics.
+///
+/// If the exception hit while the translation tables were being changed, the console's mapping may
+/// be broken, and the panic handler would likely fault again. The information is printed through
+/// the emergency console instead, which does not depend on the mappings, and the core is parked.
 fn default_exception_handler(e: &ExceptionContext) {
+    if mmu::kernel_tables_in_flux() {
+        use fmt::Write;
+
+        let mut out = unsafe { bsp::console::panic_emergency_console_out() };
+        let _ = write!(
+            out,
+            "\n\nCPU Exception while the translation tables were in flux!\n\
+             FAR_EL1: {:#018x}\n\
+             {}\n\
+             {}\n\
+             Parking core {}.\n",
+            FAR_EL1.get(),
+            EsrEL1 {},
+            e,
+            cpu::core_id()
+        );
+
+        park_after_fatal_exception()
+    }
+
+    // Before the console is registered, it takes a lot of machinery to get the panic message out.
+    // Leave a trace that needs none of it first.
+    if console::mux::console_mux().num_sinks() == 0 {
+        console::emergency_write("\n\nCPU Exception before the console came up!\n");
+    }
+
+    panic_record::set_faulting_pc(Address::new(e.elr_el1 as usize));
+
+    // Opt-in, since the dump is long and would bury the panic message on a plain console.
+    if cmdline::flag("coredump") {
+        dump_context(e);
+    }
+
+    let snapshot = SNAPSHOT.lock(|x| *x);
     panic!(
         "\n\nCPU Exception!\n\
          FAR_EL1: {:#018x}\n\
          {}\n\
-         {}",
+         {}{}{}",
         FAR_EL1.get(),
         EsrEL1 {},
-        e
+        e,
+        if snapshot.is_some() { "\n\n" } else { "" },
+        SnapshotDiff {
+            context: e,
+            snapshot,
+        }
     );
 }
 
+/// Handle a synchronous exception that was taken from EL1.
+fn current_synchronous(e: &mut ExceptionContext) {
+    match brk_immediate() {
+        Some(NULL_EXCEPTION_BRK_IMM) => {
+            if e.skip_faulting_instruction().is_ok() {
+                return;
+            }
+        }
+        Some(CORE_DUMP_BRK_IMM) => {
+            dump_context(e);
+
+            if e.skip_faulting_instruction().is_ok() {
+                return;
+            }
+        }
+        Some(SNAPSHOT_BRK_IMM) => {
+            let snapshot = e.snapshot();
+            SNAPSHOT.lock(|x| *x = Some(snapshot));
+
+            if e.skip_faulting_instruction().is_ok() {
+                return;
+            }
+        }
+        _ => (),
+    }
+
+    // Give fault injection a chance to observe the exception and carry on.
+    let info = sync_exception_info(e);
+    if exception::call_sync_exception_hook(&info) {
+        // A supervisor call returns to the next instruction already.
+        if info.class == FaultClass::SupervisorCall || e.skip_faulting_instruction().is_ok() {
+            return;
+        }
+    }
+
+    default_exception_handler(e);
+}
+
+/// Handle an IRQ that was taken from EL1.
+///
+/// # Safety
+///
+/// - Must only be called from the IRQ exception vector.
+unsafe fn current_irq(e: &ExceptionContext) {
+    use exception::asynchronous::interface::IRQManager;
+
+    let token = &exception::asynchronous::IRQContext::new();
+    exception::asynchronous::account_irq(token);
+
+    exception::asynchronous::irq_context_enter(token);
+    bsp::exception::asynchronous::irq_manager().handle_pending_irqs(token, e);
+    exception::asynchronous::irq_context_exit(token);
+}
+
 //------------------------------------------------------------------------------
 // Current, EL0
+//
+// The kernel runs on SP_EL0, so these are the exceptions that interrupt regular kernel code.
 //------------------------------------------------------------------------------
 
 #[no_mangle]
-unsafe extern "C" fn current_el0_synchronous(_e: &mut ExceptionContext) {
-    panic!("Should not be here. Use of SP_EL0 in EL1 is not supported.")
+unsafe extern "C" fn current_el0_synchronous(e: &mut ExceptionContext) {
+    current_synchronous(e);
 }
 
 #[no_mangle]
-unsafe extern "C" fn current_el0_irq(_e: &mut ExceptionContext) {
-    panic!("Should not be here. Use of SP_EL0 in EL1 is not supported.")
+unsafe extern "C" fn current_el0_irq(e: &mut ExceptionContext) {
+    current_irq(e);
 }
 
 #[no_mangle]
-unsafe extern "C" fn current_el0_serror(_e: &mut ExceptionContext) {
-    panic!("Should not be here. Use of SP_EL0 in EL1 is not supported.")
+unsafe extern "C" fn current_el0_serror(e: &mut ExceptionContext) {
+    default_exception_handler(e);
 }
 
 //------------------------------------------------------------------------------
 // Current, ELx
+//
+// Exceptions that are taken while an exception handler runs on the exception stack.
 //------------------------------------------------------------------------------
 
 #[no_mangle]
 unsafe extern "C" fn current_elx_synchronous(e: &mut ExceptionContext) {
-    default_exception_handler(e);
+    current_synchronous(e);
 }
 
 #[no_mangle]
-unsafe extern "C" fn current_elx_irq(_e: &mut ExceptionContext) {
-    use exception::asynchronous::interface::IRQManager;
-
-    let token = &exception::asynchronous::IRQContext::new();
-    bsp::exception::asynchronous::irq_manager().handle_pending_irqs(token);
+unsafe extern "C" fn current_elx_irq(e: &mut ExceptionContext) {
+    current_irq(e);
 }
 
 #[no_mangle]
@@ -217,9 +524,18 @@ impl fmt::Display for SpsrEL1 {
         writeln!(f, "            IRQ    (I): {}", to_mask_str(self.0.is_set(SPSR_EL1::I)))?;
         writeln!(f, "            FIQ    (F): {}", to_mask_str(self.0.is_set(SPSR_EL1::F)))?;
 
-        write!(f, "      Illegal Execution State (IL): {}",
+        writeln!(f, "      Illegal Execution State (IL): {}",
             to_flag_str(self.0.is_set(SPSR_EL1::IL))
-        )
+        )?;
+
+        // The exception level and the stack pointer that were in use.
+        let mode = match self.0.read(SPSR_EL1::M) {
+            0b0000 => "EL0t - EL0 with SP_EL0",
+            0b0100 => "EL1t - EL1 with SP_EL0",
+            0b0101 => "EL1h - EL1 with SP_EL1",
+            _ => "Unknown",
+        };
+        write!(f, "      Mode (M): {}", mode)
     }
 }
 
@@ -227,6 +543,8 @@ impl fmt::Display for SpsrEL1 {
 impl fmt::Display for ExceptionContext {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "ELR_EL1: {:#018x}", self.elr_el1)?;
+        writeln!(f, "SP_EL0:  {:#018x}", self.sp_el0)?;
+        writeln!(f, "Stack:   {}", self.interrupted_stack())?;
         writeln!(f, "{}", self.spsr_el1)?;
         writeln!(f)?;
         writeln!(f, "General purpose register:")?;
@@ -244,10 +562,173 @@ impl fmt::Display for ExceptionContext {
     }
 }
 
+/// Human readable print of the registers that changed since the snapshot. Prints nothing if no
+/// snapshot was taken.
+impl fmt::Display for SnapshotDiff<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let snapshot = match &self.snapshot {
+            None => return Ok(()),
+            Some(x) => x,
+        };
+        let current = self.context.snapshot();
+
+        write!(
+            f,
+            "Changed since the snapshot at {:#018x}:",
+            snapshot.elr_el1
+        )?;
+
+        let mut num_changed = 0;
+        for i in 0..Snapshot::NUM_REGISTERS {
+            let (old, new) = (snapshot.get(i), current.get(i));
+            if old == new {
+                continue;
+            }
+
+            match i {
+                0..=29 => write!(f, "\n      * x{: <7}", i)?,
+                _ => write!(f, "\n      * {: <8}", SPECIAL_REGISTER_NAMES[i - 30])?,
+            }
+            write!(f, ": {:#018x} (was {:#018x})", new, old)?;
+            num_changed += 1;
+        }
+
+        if num_changed == 0 {
+            write!(f, "\n      None")?;
+        }
+
+        Ok(())
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
-use crate::exception::PrivilegeLevel;
+use crate::exception::{ConditionFlags, PrivilegeLevel};
+
+impl ExceptionContext {
+    /// Copy the register values, for comparison with the context of a later exception.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            gpr: self.gpr,
+            lr: self.lr,
+            elr_el1: self.elr_el1,
+            spsr_el1: self.spsr_el1.0.get(),
+            sp_el0: self.sp_el0,
+        }
+    }
+
+    /// Print the registers whose values differ from `snapshot`.
+    pub fn print_diff(&self, snapshot: &Snapshot) {
+        crate::println!(
+            "{}",
+            SnapshotDiff {
+                context: self,
+                snapshot: Some(*snapshot),
+            }
+        );
+    }
+
+    /// The privilege level of the code that was interrupted.
+    pub fn interrupted_privilege_level(&self) -> PrivilegeLevel {
+        match self.spsr_el1.0.read(SPSR_EL1::M) {
+            0b0000 => PrivilegeLevel::User,
+            0b0100 | 0b0101 => PrivilegeLevel::Kernel,
+            0b1000 | 0b1001 => PrivilegeLevel::Hypervisor,
+            _ => PrivilegeLevel::Unknown,
+        }
+    }
+
+    /// The address of the instruction that execution resumes at when the exception returns.
+    pub fn interrupted_pc(&self) -> Address<memory::Virtual> {
+        Address::new(self.elr_el1 as usize)
+    }
+
+    /// Resume execution at `addr` when the exception returns.
+    ///
+    /// The address must be instruction-aligned and lie in a recorded, executable kernel mapping.
+    pub fn set_return_to(&mut self, addr: Address<memory::Virtual>) -> Result<(), &'static str> {
+        if addr.into_usize() as u64 % INSTRUCTION_SIZE != 0 {
+            return Err("Return address is not instruction-aligned");
+        }
+
+        match mmu::kernel_find_mapping(addr) {
+            None => return Err("Return address is not mapped"),
+            Some(x) if x.attribute_fields.execute_never => {
+                return Err("Return address is not executable")
+            }
+            Some(_) => (),
+        }
+
+        self.elr_el1 = addr.into_usize() as u64;
+
+        Ok(())
+    }
+
+    /// Resume execution after the instruction that caused the exception.
+    ///
+    /// The faulting instruction's page was executable, so the mappings are only consulted when the
+    /// next instruction lies on another page.
+    pub fn skip_faulting_instruction(&mut self) -> Result<(), &'static str> {
+        let next = self
+            .elr_el1
+            .checked_add(INSTRUCTION_SIZE)
+            .ok_or("Faulting instruction is the last one of the address space")?;
+
+        let page_mask = !(bsp::memory::mmu::KernelGranule::SIZE as u64 - 1);
+        if next & page_mask != self.elr_el1 & page_mask {
+            return self.set_return_to(Address::new(next as usize));
+        }
+
+        self.elr_el1 = next;
+
+        Ok(())
+    }
+
+    /// The value of general purpose register `n`, x0 to x30.
+    pub fn gpr(&self, n: usize) -> Option<u64> {
+        match n {
+            0..=29 => Some(self.gpr[n]),
+            30 => Some(self.lr),
+            _ => None,
+        }
+    }
+
+    /// Set general purpose register `n`, x0 to x30, to `value` for when the exception returns.
+    pub fn set_gpr(&mut self, n: usize, value: u64) -> Result<(), &'static str> {
+        match n {
+            0..=29 => self.gpr[n] = value,
+            30 => self.lr = value,
+            _ => return Err("Register index out of range"),
+        }
+
+        Ok(())
+    }
+
+    /// The condition flags of the interrupted context.
+    pub fn spsr_flags(&self) -> ConditionFlags {
+        let spsr = &self.spsr_el1.0;
+
+        ConditionFlags {
+            negative: spsr.is_set(SPSR_EL1::N),
+            zero: spsr.is_set(SPSR_EL1::Z),
+            carry: spsr.is_set(SPSR_EL1::C),
+            overflow: spsr.is_set(SPSR_EL1::V),
+        }
+    }
+
+    /// Set the condition flags for when the exception returns.
+    pub fn set_spsr_flags(&mut self, flags: ConditionFlags) {
+        let to_field = |x| if x { 1 } else { 0 };
+
+        self.spsr_el1.0.modify(
+            SPSR_EL1::N.val(to_field(flags.negative))
+                + SPSR_EL1::Z.val(to_field(flags.zero))
+                + SPSR_EL1::C.val(to_field(flags.carry))
+                + SPSR_EL1::V.val(to_field(flags.overflow)),
+        );
+    }
+}
 
 /// The processing element's current privilege level.
 pub fn current_privilege_level() -> (PrivilegeLevel, &'static str) {
@@ -260,11 +741,43 @@ pub fn current_privilege_level() -> (PrivilegeLevel, &'static str) {
     }
 }
 
+/// Take a synchronous exception that does nothing but return.
+///
+/// Used to measure the cost of entering and leaving the exception handler.
+#[inline(always)]
+pub fn null_exception() {
+    unsafe { asm!("brk #0x7e6", options(nostack)) };
+}
+
+/// Save the current register values as a known-good snapshot.
+///
+/// The registers are captured by a breakpoint, so that the snapshot holds them exactly as the
+/// exception entry code saves them for a later exception. A later fatal exception additionally
+/// prints the registers that differ from the snapshot.
+#[inline(always)]
+pub fn take_context_snapshot() {
+    unsafe { asm!("brk #0x7e7", options(nostack)) };
+}
+
+/// The snapshot that was saved last by `take_context_snapshot()`.
+pub fn last_context_snapshot() -> Option<Snapshot> {
+    SNAPSHOT.lock(|x| *x)
+}
+
+/// Emit a core dump of the executing core's current context through the emergency console.
+///
+/// See `exception::core_dump` for the format.
+#[inline(always)]
+pub fn core_dump() {
+    unsafe { asm!("brk #0x7e8", options(nostack)) };
+}
+
 /// Init exception handling by setting the exception vector base address register.
 ///
 /// # Safety
 ///
 /// - Changes the HW state of the executing core.
+/// - Must not be called from an exception handler.
 /// - The vector table and the symbol `__exception_vector_table_start` from the linker script must
 ///   adhere to the alignment and size constraints demanded by the ARMv8-A Architecture Reference
 ///   Manual.
@@ -277,5 +790,103 @@ pub unsafe fn handling_init() {
     VBAR_EL1.set(__exception_vector_start.get() as u64);
 
     // Force VBAR update to complete before next instruction.
-    barrier::isb(barrier::SY);
+    barrier::complete_system_register_write();
+
+    // No exception is being handled, so the exception stack is unused and can be filled for the
+    // high-water mark.
+    memory::stack::fill(memory::stack::Stack::Exception(cpu::core_id()));
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check that a snapshot is saved, and that execution continues after the breakpoint.
+    #[kernel_test]
+    fn context_snapshot_is_taken() {
+        SNAPSHOT.lock(|x| *x = None);
+
+        take_context_snapshot();
+        let snapshot = last_context_snapshot();
+        SNAPSHOT.lock(|x| *x = None);
+
+        assert!(snapshot.map_or(false, |x| x.elr_el1 != 0));
+    }
+
+    /// Check that only the changed registers are listed.
+    #[kernel_test]
+    fn snapshot_diff_lists_changed_registers() {
+        let mut e: ExceptionContext = unsafe { mem::zeroed() };
+        let snapshot = e.snapshot();
+        let diff = |e: &ExceptionContext| {
+            alloc::format!(
+                "{}",
+                SnapshotDiff {
+                    context: e,
+                    snapshot: Some(snapshot),
+                }
+            )
+        };
+
+        assert!(diff(&e).ends_with("None"));
+
+        e.set_gpr(3, 0x42).unwrap();
+        e.sp_el0 = 0x1000;
+        let changed = diff(&e);
+        assert!(changed.contains("* x3 "));
+        assert!(changed.contains("* sp_el0 "));
+        assert!(!changed.contains("* x4 "));
+    }
+
+    /// Check that execution resumes right after a handled breakpoint, and not at or behind it.
+    #[kernel_test]
+    fn faulting_instruction_is_skipped() {
+        fn hook(info: &exception::SyncExceptionInfo) -> bool {
+            info.class == exception::FaultClass::Breakpoint
+        }
+
+        let x: u64;
+
+        exception::set_sync_exception_hook(Some(hook));
+        unsafe {
+            asm!(
+                "mov {0}, #1",
+                "brk #0x7f0",
+                "add {0}, {0}, #1",
+                "add {0}, {0}, #1",
+                out(reg) x,
+                options(nomem, nostack)
+            )
+        };
+        exception::set_sync_exception_hook(None);
+
+        assert_eq!(x, 3);
+    }
+
+    /// Check the bounds of the register accessors, and that the flags round-trip.
+    #[kernel_test]
+    fn context_accessors_work() {
+        let mut e: ExceptionContext = unsafe { mem::zeroed() };
+
+        assert!(e.set_gpr(30, 0x1234).is_ok());
+        assert_eq!(e.gpr(30), Some(0x1234));
+        assert!(e.set_gpr(31, 0).is_err());
+        assert_eq!(e.gpr(31), None);
+
+        let flags = ConditionFlags {
+            negative: true,
+            zero: false,
+            carry: true,
+            overflow: false,
+        };
+        e.set_spsr_flags(flags);
+        assert_eq!(e.spsr_flags(), flags);
+
+        assert!(e.set_return_to(Address::new(0x2)).is_err());
+    }
 }
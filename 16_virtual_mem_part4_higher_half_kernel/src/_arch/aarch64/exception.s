@@ -29,12 +29,14 @@
 	stp	x26, x27, [sp, #16 * 13]
 	stp	x28, x29, [sp, #16 * 14]
 
-	// Add the exception link register (ELR_EL1) and the saved program status (SPSR_EL1).
+	// Add the exception link register (ELR_EL1), the saved program status (SPSR_EL1) and the
+	// stack pointer of EL0 (SP_EL0).
 	mrs	x1,  ELR_EL1
 	mrs	x2,  SPSR_EL1
+	mrs	x3,  SP_EL0
 
 	stp	lr,  x1,  [sp, #16 * 15]
-	str	x2,       [sp, #16 * 16]
+	stp	x2,  x3,  [sp, #16 * 16]
 
 	// x0 is the first argument for the function called through `\handler`.
 	mov	x0,  sp
@@ -67,7 +69,8 @@
 // Export a symbol for the Rust code to use.
 __exception_vector_start:
 
-// Current exception level with SP_EL0.
+// Current exception level with SP_EL0, which is what the kernel runs on. The handlers run on
+// SP_EL1, the core's exception stack, like for all exceptions taken to EL1.
 //
 // .org sets the offset relative to section start.
 //
@@ -0,0 +1,58 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural fault injection.
+//!
+//! Each fault is caused by exactly one instruction, so that the exception handler can resume after
+//! it.
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::exception::fault_injection::arch_fault_injection
+
+use crate::memory::{Address, Virtual};
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Load a word from `addr`.
+///
+/// # Safety
+///
+/// - Only for causing faults. Reading from a valid address might have side effects.
+pub unsafe fn load(addr: Address<Virtual>) {
+    asm!(
+        "ldr {tmp:w}, [{addr}]",
+        addr = in(reg) addr.into_usize(),
+        tmp = out(reg) _,
+        options(nostack)
+    );
+}
+
+/// Store a zero word to `addr`.
+///
+/// # Safety
+///
+/// - Only for causing faults. Writing to a valid address corrupts it.
+pub unsafe fn store(addr: Address<Virtual>) {
+    asm!(
+        "str wzr, [{addr}]",
+        addr = in(reg) addr.into_usize(),
+        options(nostack)
+    );
+}
+
+/// Execute a breakpoint instruction.
+pub fn brk() {
+    unsafe { asm!("brk #0x7e5", options(nomem, nostack)) };
+}
+
+/// Execute a supervisor call.
+pub fn svc() {
+    unsafe { asm!("svc #0", options(nomem, nostack)) };
+}
@@ -0,0 +1,59 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural chainloader code.
+//!
+//! The kernel runs in the higher half, so switching off the MMU in place would make the next
+//! instruction fetch go to a nonexistent physical address. Therefore, the 512 MiB block that
+//! contains the trampoline is identity mapped through TTBR0 first, see
+//! `mmu::enable_identity_mapping()`. The kernel jumps to the trampoline's physical address, and the
+//! trampoline then switches off the MMU and jumps to the image.
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::loader::arch_loader
+
+use crate::{
+    exception,
+    memory::{mmu, Address, Physical},
+};
+
+// Assembly counterpart to this file.
+global_asm!(include_str!("loader.s"));
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+extern "C" {
+    fn __loader_trampoline();
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Switch off the MMU and the caches, and jump to `entry`.
+///
+/// # Safety
+///
+/// - See `LoadedImage::boot()`.
+pub unsafe fn jump_to_image(entry: Address<Physical>) -> ! {
+    exception::asynchronous::local_irq_mask();
+
+    let trampoline = mmu::try_virt_to_phys(Address::new(__loader_trampoline as usize))
+        .expect("Loader trampoline not mapped");
+
+    mmu::enable_identity_mapping(trampoline).expect("Loader trampoline not identity mappable");
+
+    asm!(
+        "br {trampoline}",
+        trampoline = in(reg) trampoline.into_usize(),
+        in("x0") entry.into_usize(),
+        options(noreturn)
+    )
+}
@@ -0,0 +1,33 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+.section .text
+
+//------------------------------------------------------------------------------
+// fn __loader_trampoline(entry: usize) -> !
+//
+// Switch off the MMU and the caches, and jump to `entry`. Must be executed from an identity
+// mapped address.
+//------------------------------------------------------------------------------
+__loader_trampoline:
+	mrs	x1, SCTLR_EL1
+	bic	x1, x1, #(1 << 0)	// M
+	bic	x1, x1, #(1 << 2)	// C
+	bic	x1, x1, #(1 << 12)	// I
+	msr	SCTLR_EL1, x1
+	isb
+
+	// Make sure that no instructions cached by the kernel are hit.
+	ic	iallu
+	dsb	nsh
+	isb
+
+	br	x0
+
+.size	__loader_trampoline, . - __loader_trampoline
+.type	__loader_trampoline, function
+.global	__loader_trampoline
@@ -6,6 +6,11 @@
 //!
 //! Only 64 KiB granule is supported.
 //!
+//! The kernel runs in the higher half, so switching off the MMU in place would make the next
+//! instruction fetch go to a nonexistent physical address. Code that must run with the MMU switched
+//! off is therefore entered through its physical address, after the 512 MiB block that contains it
+//! was identity mapped through TTBR0.
+//!
 //! # Orientation
 //!
 //! Since arch modules are imported into generic modules using the path attribute, the path of this
@@ -14,11 +19,19 @@
 //! crate::memory::mmu::arch_mmu
 
 use crate::{
-    bsp, memory,
-    memory::{mmu::TranslationGranule, Address, Physical, Virtual},
+    bsp,
+    cpu::{barrier, cache, features, features::Feature},
+    memory,
+    memory::{
+        mmu::{MMUEnableError, PhysTransmitter, TranslationGranule},
+        Address, Physical, Virtual,
+    },
 };
 use core::intrinsics::unlikely;
-use cortex_a::{barrier, regs::*};
+use cortex_a::regs::*;
+
+// Assembly counterpart to this file.
+global_asm!(include_str!("mmu.s"));
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
@@ -27,6 +40,31 @@ use cortex_a::{barrier, regs::*};
 /// Memory Management Unit type.
 struct MemoryManagementUnit;
 
+extern "C" {
+    fn __mmu_off_write(data_reg: usize, flags_reg: usize, busy_mask: u32, bytes: usize, len: usize);
+}
+
+/// Size of the input address space of the identity mapping, as a shift.
+const IDENTITY_AS_SHIFT: u64 = 32;
+
+/// Each level 2 entry maps 512 MiB when using the 64 KiB granule.
+const L2_BLOCK_SHIFT: usize = 29;
+
+/// Stage 1 level 2 block descriptor bits.
+mod block {
+    pub const VALID: u64 = 0b01;
+    pub const ATTR_INDX_NORMAL: u64 = super::mair::NORMAL << 2;
+    pub const AP_RO_EL1: u64 = 0b10 << 6;
+    pub const SH_INNER: u64 = 0b11 << 8;
+    pub const AF: u64 = 1 << 10;
+}
+
+/// A level 2 table covering `1 << IDENTITY_AS_SHIFT` bytes.
+#[repr(C, align(64))]
+struct IdentityTable {
+    entries: [u64; 1 << (IDENTITY_AS_SHIFT as usize - L2_BLOCK_SHIFT)],
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -34,6 +72,17 @@ struct MemoryManagementUnit;
 pub type Granule512MiB = TranslationGranule<{ 512 * 1024 * 1024 }>;
 pub type Granule64KiB = TranslationGranule<{ 64 * 1024 }>;
 
+/// The register values that set up stage 1 of the EL1 translation regime.
+///
+/// Computed once by `configure_translation_control()`. Every core programs the same values with
+/// `activate()`.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TranslationControl {
+    pub mair: u64,
+    pub tcr: u64,
+}
+
 /// Constants for indexing the MAIR_EL1.
 #[allow(dead_code)]
 pub mod mair {
@@ -47,6 +96,18 @@ pub mod mair {
 
 static MMU: MemoryManagementUnit = MemoryManagementUnit;
 
+/// The boot core's configuration, for the secondary cores.
+///
+/// Written by the boot core before `bss` is initialized, so it must be placed in `.data`. The write
+/// happens with the data cache off, so the secondary cores, which read it before they switch on
+/// their caches, see it in memory.
+#[link_section = ".data"]
+static mut SAVED_TRANSLATION_CONTROL: TranslationControl = TranslationControl { mair: 0, tcr: 0 };
+
+static mut IDENTITY_TABLE: IdentityTable = IdentityTable {
+    entries: [0; 1 << (IDENTITY_AS_SHIFT as usize - L2_BLOCK_SHIFT)],
+};
+
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
@@ -63,54 +124,214 @@ impl<const AS_SIZE: usize> memory::mmu::AddressSpace<AS_SIZE> {
     }
 }
 
-impl MemoryManagementUnit {
-    /// Setup function for the MAIR_EL1 register.
-    #[inline(always)]
-    fn set_up_mair(&self) {
-        // Define the memory types being mapped.
-        MAIR_EL1.write(
-            // Attribute 1 - Cacheable normal DRAM.
-            MAIR_EL1::Attr1_Normal_Outer::WriteBack_NonTransient_ReadWriteAlloc +
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Return a reference to the MMU instance.
+pub fn mmu() -> &'static impl memory::mmu::interface::MMU {
+    &MMU
+}
+
+/// Compute the MAIR_EL1 and TCR_EL1 values for the kernel's translation regime.
+///
+/// Fails if the HW does not support the translation granule. Does not touch the MMU registers.
+pub fn configure_translation_control() -> Result<TranslationControl, MMUEnableError> {
+    if unlikely(!features::has_feature(Feature::Granule64KiB)) {
+        return Err(MMUEnableError::Other(
+            "Translation granule not supported in HW",
+        ));
+    }
+
+    let t1sz = (64 - bsp::memory::mmu::KernelVirtAddrSpace::SIZE_SHIFT) as u64;
+
+    // Define the memory types being mapped.
+    let mair =
+        // Attribute 1 - Cacheable normal DRAM.
+        MAIR_EL1::Attr1_Normal_Outer::WriteBack_NonTransient_ReadWriteAlloc +
         MAIR_EL1::Attr1_Normal_Inner::WriteBack_NonTransient_ReadWriteAlloc +
 
         // Attribute 0 - Device.
-        MAIR_EL1::Attr0_Device::nonGathering_nonReordering_EarlyWriteAck,
-        );
+        MAIR_EL1::Attr0_Device::nonGathering_nonReordering_EarlyWriteAck;
+
+    let tcr = TCR_EL1::TBI1::Used
+        + TCR_EL1::IPS::Bits_40
+        + TCR_EL1::TG1::KiB_64
+        + TCR_EL1::SH1::Inner
+        + TCR_EL1::ORGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+        + TCR_EL1::IRGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+        + TCR_EL1::EPD1::EnableTTBR1Walks
+        + TCR_EL1::A1::TTBR1
+        + TCR_EL1::T1SZ.val(t1sz)
+        + TCR_EL1::EPD0::DisableTTBR0Walks;
+
+    Ok(TranslationControl {
+        mair: mair.value,
+        tcr: tcr.value,
+    })
+}
+
+/// Program `config` and the tables at `phys_tables_base_addr`, then switch on the MMU and caching
+/// of the executing core.
+///
+/// # Safety
+///
+/// - Changes the HW state of the executing core.
+/// - `config` must come from `configure_translation_control()`.
+pub unsafe fn activate(
+    config: &TranslationControl,
+    phys_tables_base_addr: Address<Physical>,
+) -> Result<(), MMUEnableError> {
+    if unlikely(MMU.is_enabled()) {
+        return Err(MMUEnableError::AlreadyEnabled);
     }
 
-    /// Configure various settings of stage 1 of the EL1 translation regime.
-    #[inline(always)]
-    fn configure_translation_control(&self) {
-        let t1sz = (64 - bsp::memory::mmu::KernelVirtAddrSpace::SIZE_SHIFT) as u64;
+    // Prepare the memory attribute indirection register.
+    MAIR_EL1.set(config.mair);
 
-        TCR_EL1.write(
-            TCR_EL1::TBI1::Used
-                + TCR_EL1::IPS::Bits_40
-                + TCR_EL1::TG1::KiB_64
-                + TCR_EL1::SH1::Inner
-                + TCR_EL1::ORGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
-                + TCR_EL1::IRGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
-                + TCR_EL1::EPD1::EnableTTBR1Walks
-                + TCR_EL1::A1::TTBR1
-                + TCR_EL1::T1SZ.val(t1sz)
-                + TCR_EL1::EPD0::DisableTTBR0Walks,
-        );
+    // Set the "Translation Table Base Register".
+    TTBR1_EL1.set_baddr(phys_tables_base_addr.into_usize() as u64);
+
+    TCR_EL1.set(config.tcr);
+
+    // Switch the MMU on.
+    //
+    // First, force all previous changes to be seen before the MMU is enabled.
+    barrier::complete_system_register_write();
+
+    // Make sure that no instructions cached by earlier boot stages are hit.
+    cache::invalidate_all_icache();
+
+    // Enable the MMU.
+    SCTLR_EL1.modify(SCTLR_EL1::M::Enable);
+
+    // Force MMU init to complete before next instruction.
+    barrier::complete_system_register_write();
+
+    // Turn on data and instruction caching.
+    cache::enable_dcache();
+    cache::enable_icache();
+
+    Ok(())
+}
+
+/// The configuration that the boot core computed in `enable_mmu_and_caching()`.
+///
+/// # Safety
+///
+/// - Must only be called after the boot core enabled its MMU.
+pub unsafe fn saved_translation_control() -> Result<TranslationControl, MMUEnableError> {
+    let config = core::ptr::read_volatile(&SAVED_TRANSLATION_CONTROL);
+    if config.tcr == 0 {
+        return Err(MMUEnableError::Other(
+            "Translation control was never configured",
+        ));
+    }
+
+    Ok(config)
+}
+
+/// Identity map the 512 MiB block that contains `phys_addr` through TTBR0.
+///
+/// The data cache is cleaned and disabled first, because the table walks for the identity mapping
+/// bypass it. Code in the block can then be entered through its physical address and switch off the
+/// MMU.
+///
+/// # Safety
+///
+/// - Changes the HW state of the executing core.
+/// - IRQs must be masked.
+/// - See `cache::disable_dcache()`.
+pub unsafe fn enable_identity_mapping(phys_addr: Address<Physical>) -> Result<(), &'static str> {
+    let phys_addr = phys_addr.into_usize();
+    if (phys_addr >> IDENTITY_AS_SHIFT) != 0 {
+        return Err("Address outside of the identity mapping");
     }
+
+    let table = memory::mmu::try_virt_to_phys(Address::new(&IDENTITY_TABLE as *const _ as usize))
+        .map_err(|_| "Identity table not mapped")?
+        .into_usize();
+
+    let index = phys_addr >> L2_BLOCK_SHIFT;
+    IDENTITY_TABLE.entries[index] = ((index << L2_BLOCK_SHIFT) as u64)
+        | block::AF
+        | block::SH_INNER
+        | block::AP_RO_EL1
+        | block::ATTR_INDX_NORMAL
+        | block::VALID;
+
+    // Clean everything to memory, including the identity table. From here on, data accesses and
+    // table walks bypass the caches.
+    cache::disable_dcache();
+
+    TTBR0_EL1.set_baddr(table as u64);
+    TCR_EL1.modify(
+        TCR_EL1::TG0::KiB_64
+            + TCR_EL1::SH0::Inner
+            + TCR_EL1::ORGN0::NonCacheable
+            + TCR_EL1::IRGN0::NonCacheable
+            + TCR_EL1::T0SZ.val(64 - IDENTITY_AS_SHIFT)
+            + TCR_EL1::EPD0::EnableTTBR0Walks,
+    );
+    barrier::complete_system_register_write();
+
+    asm!("tlbi vmalle1", options(nostack, preserves_flags));
+    barrier::complete_local_invalidation();
+
+    Ok(())
 }
 
-//--------------------------------------------------------------------------------------------------
-// Public Code
-//--------------------------------------------------------------------------------------------------
+/// Undo `enable_identity_mapping()`, including switching the data cache back on.
+///
+/// # Safety
+///
+/// - Changes the HW state of the executing core.
+/// - Nothing may execute from the identity mapping anymore.
+pub unsafe fn disable_identity_mapping() {
+    TCR_EL1.modify(TCR_EL1::EPD0::DisableTTBR0Walks);
+    barrier::complete_system_register_write();
 
-/// Return a reference to the MMU instance.
-pub fn mmu() -> &'static impl memory::mmu::interface::MMU {
-    &MMU
+    asm!("tlbi vmalle1", options(nostack, preserves_flags));
+    barrier::complete_local_invalidation();
+
+    cache::enable_dcache();
+}
+
+/// Write `len` bytes at the physical address `bytes` to `tx`, with the MMU switched off.
+///
+/// # Safety
+///
+/// - IRQs must be masked. Any exception taken while the MMU is off is fatal.
+/// - See `enable_identity_mapping()`.
+pub unsafe fn write_with_mmu_disabled(
+    tx: &PhysTransmitter,
+    bytes: Address<Physical>,
+    len: usize,
+) -> Result<(), &'static str> {
+    let trampoline = memory::mmu::try_virt_to_phys(Address::new(__mmu_off_write as usize))
+        .map_err(|_| "MMU-off writer not mapped")?;
+
+    enable_identity_mapping(trampoline)?;
+
+    let write_phys: unsafe extern "C" fn(usize, usize, u32, usize, usize) =
+        core::mem::transmute(trampoline.into_usize());
+    write_phys(
+        tx.data_reg.into_usize(),
+        tx.flags_reg.into_usize(),
+        tx.busy_mask,
+        bytes.into_usize(),
+        len,
+    );
+
+    disable_identity_mapping();
+
+    Ok(())
 }
 
 //------------------------------------------------------------------------------
 // OS Interface Code
 //------------------------------------------------------------------------------
-use memory::mmu::{MMUEnableError, TranslationError};
+use memory::mmu::{interface::MMU, TranslationError};
 
 impl memory::mmu::interface::MMU for MemoryManagementUnit {
     unsafe fn enable_mmu_and_caching(
@@ -121,33 +342,10 @@ impl memory::mmu::interface::MMU for MemoryManagementUnit {
             return Err(MMUEnableError::AlreadyEnabled);
         }
 
-        // Fail early if translation granule is not supported.
-        if unlikely(!ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran64::Supported)) {
-            return Err(MMUEnableError::Other(
-                "Translation granule not supported in HW",
-            ));
-        }
-
-        // Prepare the memory attribute indirection register.
-        self.set_up_mair();
-
-        // Set the "Translation Table Base Register".
-        TTBR1_EL1.set_baddr(phys_tables_base_addr.into_usize() as u64);
-
-        self.configure_translation_control();
-
-        // Switch the MMU on.
-        //
-        // First, force all previous changes to be seen before the MMU is enabled.
-        barrier::isb(barrier::SY);
-
-        // Enable the MMU and turn on data and instruction caching.
-        SCTLR_EL1.modify(SCTLR_EL1::M::Enable + SCTLR_EL1::C::Cacheable + SCTLR_EL1::I::Cacheable);
+        let config = configure_translation_control()?;
+        core::ptr::write_volatile(&mut SAVED_TRANSLATION_CONTROL, config);
 
-        // Force MMU init to complete before next instruction.
-        barrier::isb(barrier::SY);
-
-        Ok(())
+        activate(&config, phys_tables_base_addr)
     }
 
     #[inline(always)]
@@ -181,4 +379,57 @@ impl memory::mmu::interface::MMU for MemoryManagementUnit {
 
         Ok(Address::new(phys_addr as usize))
     }
+
+    fn is_virt_writable(&self, virt: Address<Virtual>) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+
+        let addr = virt.into_usize() as u64;
+        unsafe {
+            asm!(
+            "AT S1E1W, {0}",
+            in(reg) addr,
+            options(readonly, nostack, preserves_flags)
+            );
+        }
+
+        !PAR_EL1.matches_all(PAR_EL1::F::TranslationAborted)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use register::InMemoryRegister;
+    use test_macros::kernel_test;
+
+    /// The computed configuration selects the kernel's translation regime.
+    #[kernel_test]
+    fn translation_control_is_as_expected() {
+        let config = configure_translation_control().unwrap();
+        let tcr = InMemoryRegister::<u64, TCR_EL1::Register>::new(config.tcr);
+
+        assert!(tcr.matches_all(
+            TCR_EL1::TBI1::Used
+                + TCR_EL1::IPS::Bits_40
+                + TCR_EL1::TG1::KiB_64
+                + TCR_EL1::SH1::Inner
+                + TCR_EL1::EPD1::EnableTTBR1Walks
+                + TCR_EL1::A1::TTBR1
+                + TCR_EL1::EPD0::DisableTTBR0Walks
+        ));
+        assert_eq!(
+            1 << (64 - tcr.read(TCR_EL1::T1SZ)),
+            bsp::memory::mmu::KernelVirtAddrSpace::SIZE as u64
+        );
+
+        // The running core was set up with the same values.
+        assert_eq!(MAIR_EL1.get(), config.mair);
+        assert_eq!(unsafe { saved_translation_control() }, Ok(config));
+    }
 }
@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//--------------------------------------------------------------------------------------------------
+// Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Bounded wait for space in the transmitter, so that a stuck device cannot hang the caller.
+.equ _max_spins_per_byte, 0x20000
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+.section .text
+
+//------------------------------------------------------------------------------
+// fn __mmu_off_write(data_reg: usize, flags_reg: usize, busy_mask: u32, bytes: usize, len: usize)
+//
+// Switch off the MMU, write `len` bytes from `bytes` to the 32 bit `data_reg`, waiting while
+// `flags_reg & busy_mask` is non-zero, and switch the MMU back on. All addresses are physical. Must
+// be executed from an identity mapped address, and must not touch the stack.
+//------------------------------------------------------------------------------
+__mmu_off_write:
+	mrs	x9, SCTLR_EL1
+	bic	x10, x9, #(1 << 0)	// M
+	msr	SCTLR_EL1, x10
+	isb
+
+1:	cbz	x4, 4f
+	ldrb	w11, [x3], #1
+
+	mov	x12, _max_spins_per_byte
+2:	ldr	w13, [x1]
+	tst	w13, w2
+	b.eq	3f
+	subs	x12, x12, #1
+	b.ne	2b
+
+3:	str	w11, [x0]
+	sub	x4, x4, #1
+	b	1b
+
+	// Drain the writes before the MMU comes back.
+4:	dsb	sy
+	msr	SCTLR_EL1, x9
+	isb
+
+	ret
+
+.size	__mmu_off_write, . - __mmu_off_write
+.type	__mmu_off_write, function
+.global	__mmu_off_write
@@ -13,112 +13,28 @@
 //!
 //! crate::memory::mmu::translation_table::arch_translation_table
 
+#[path = "translation_table/descriptor.rs"]
+mod descriptor;
+
 use crate::{
-    bsp, memory,
+    bsp, config,
+    cpu::barrier,
+    memory,
     memory::{
         mmu::{
             arch_mmu::{Granule512MiB, Granule64KiB},
-            AccessPermissions, AttributeFields, MemAttributes, Page, PageSliceDescriptor,
+            AccessPermissions, AttributeFields, Page, PageSliceDescriptor,
         },
         Address, Physical, Virtual,
     },
 };
-use core::convert::{self, TryInto};
-use register::{register_bitfields, InMemoryRegister};
+use core::convert::TryInto;
+use descriptor::{PageDescriptor, TableDescriptor};
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
 //--------------------------------------------------------------------------------------------------
 
-// A table descriptor, as per ARMv8-A Architecture Reference Manual Figure D5-15.
-register_bitfields! {u64,
-    STAGE1_TABLE_DESCRIPTOR [
-        /// Physical address of the next descriptor.
-        NEXT_LEVEL_TABLE_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
-
-        TYPE  OFFSET(1) NUMBITS(1) [
-            Block = 0,
-            Table = 1
-        ],
-
-        VALID OFFSET(0) NUMBITS(1) [
-            False = 0,
-            True = 1
-        ]
-    ]
-}
-
-// A level 3 page descriptor, as per ARMv8-A Architecture Reference Manual Figure D5-17.
-register_bitfields! {u64,
-    STAGE1_PAGE_DESCRIPTOR [
-        /// Unprivileged execute-never.
-        UXN      OFFSET(54) NUMBITS(1) [
-            False = 0,
-            True = 1
-        ],
-
-        /// Privileged execute-never.
-        PXN      OFFSET(53) NUMBITS(1) [
-            False = 0,
-            True = 1
-        ],
-
-        /// Physical address of the next table descriptor (lvl2) or the page descriptor (lvl3).
-        OUTPUT_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
-
-        /// Access flag.
-        AF       OFFSET(10) NUMBITS(1) [
-            False = 0,
-            True = 1
-        ],
-
-        /// Shareability field.
-        SH       OFFSET(8) NUMBITS(2) [
-            OuterShareable = 0b10,
-            InnerShareable = 0b11
-        ],
-
-        /// Access Permissions.
-        AP       OFFSET(6) NUMBITS(2) [
-            RW_EL1 = 0b00,
-            RW_EL1_EL0 = 0b01,
-            RO_EL1 = 0b10,
-            RO_EL1_EL0 = 0b11
-        ],
-
-        /// Memory attributes index into the MAIR_EL1 register.
-        AttrIndx OFFSET(2) NUMBITS(3) [],
-
-        TYPE     OFFSET(1) NUMBITS(1) [
-            Reserved_Invalid = 0,
-            Page = 1
-        ],
-
-        VALID    OFFSET(0) NUMBITS(1) [
-            False = 0,
-            True = 1
-        ]
-    ]
-}
-
-/// A table descriptor for 64 KiB aperture.
-///
-/// The output points to the next table.
-#[derive(Copy, Clone)]
-#[repr(C)]
-struct TableDescriptor {
-    value: u64,
-}
-
-/// A page descriptor with 64 KiB aperture.
-///
-/// The output points to physical memory.
-#[derive(Copy, Clone)]
-#[repr(C)]
-struct PageDescriptor {
-    value: u64,
-}
-
 trait StartAddr {
     fn virt_start_addr(&self) -> Address<Virtual>;
 }
@@ -138,11 +54,20 @@ pub struct FixedSizeTranslationTable<const NUM_TABLES: usize, const START_FROM_T
     /// Table descriptors, covering 512 MiB windows.
     lvl2: [TableDescriptor; NUM_TABLES],
 
-    /// Index of the next free MMIO page.
-    cur_l3_mmio_index: usize,
+    /// Number of pages handed out from the start of the MMIO region.
+    ///
+    /// Counted from zero, so that a table created by `new_for_runtime()` is all-zero and can be
+    /// placed in `.bss`.
+    mmio_pages_used: usize,
 
     /// Have the tables been initialized?
     initialized: bool,
+
+    /// Start of the table's own pages, if they are mapped read-only. See `write_protect()`.
+    protected_start: Address<Virtual>,
+
+    /// The number of the table's own pages that are mapped read-only. Zero if none are.
+    num_protected_pages: usize,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -155,98 +80,16 @@ impl<T, const N: usize> StartAddr for [T; N] {
     }
 }
 
-impl TableDescriptor {
-    /// Create an instance.
-    ///
-    /// Descriptor is invalid by default.
-    pub const fn new_zeroed() -> Self {
-        Self { value: 0 }
-    }
-
-    /// Create an instance pointing to the supplied address.
-    pub fn from_next_lvl_table_addr(phys_next_lvl_table_addr: Address<Physical>) -> Self {
-        let val = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(0);
-
-        let shifted = phys_next_lvl_table_addr.into_usize() >> Granule64KiB::SHIFT;
-        val.write(
-            STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR_64KiB.val(shifted as u64)
-                + STAGE1_TABLE_DESCRIPTOR::TYPE::Table
-                + STAGE1_TABLE_DESCRIPTOR::VALID::True,
-        );
-
-        TableDescriptor { value: val.get() }
-    }
-}
-
-/// Convert the kernel's generic memory attributes to HW-specific attributes of the MMU.
-impl convert::From<AttributeFields>
-    for register::FieldValue<u64, STAGE1_PAGE_DESCRIPTOR::Register>
-{
-    fn from(attribute_fields: AttributeFields) -> Self {
-        // Memory attributes.
-        let mut desc = match attribute_fields.mem_attributes {
-            MemAttributes::CacheableDRAM => {
-                STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable
-                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(memory::mmu::arch_mmu::mair::NORMAL)
-            }
-            MemAttributes::Device => {
-                STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
-                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(memory::mmu::arch_mmu::mair::DEVICE)
-            }
-        };
-
-        // Access Permissions.
-        desc += match attribute_fields.acc_perms {
-            AccessPermissions::ReadOnly => STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1,
-            AccessPermissions::ReadWrite => STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1,
-        };
-
-        // The execute-never attribute is mapped to PXN in AArch64.
-        desc += if attribute_fields.execute_never {
-            STAGE1_PAGE_DESCRIPTOR::PXN::True
-        } else {
-            STAGE1_PAGE_DESCRIPTOR::PXN::False
-        };
-
-        // Always set unprivileged exectue-never as long as userspace is not implemented yet.
-        desc += STAGE1_PAGE_DESCRIPTOR::UXN::True;
-
-        desc
-    }
-}
-
-impl PageDescriptor {
-    /// Create an instance.
-    ///
-    /// Descriptor is invalid by default.
-    pub const fn new_zeroed() -> Self {
-        Self { value: 0 }
-    }
-
-    /// Create an instance.
-    pub fn from_output_addr(
-        phys_output_addr: *const Page<Physical>,
-        attribute_fields: &AttributeFields,
-    ) -> Self {
-        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(0);
-
-        let shifted = phys_output_addr as u64 >> Granule64KiB::SHIFT;
-        val.write(
-            STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR_64KiB.val(shifted)
-                + STAGE1_PAGE_DESCRIPTOR::AF::True
-                + STAGE1_PAGE_DESCRIPTOR::TYPE::Page
-                + STAGE1_PAGE_DESCRIPTOR::VALID::True
-                + (*attribute_fields).into(),
-        );
-
-        Self { value: val.get() }
-    }
+/// Invalidate the TLB entries of a page on all cores.
+///
+/// # Safety
+///
+/// - The caller must complete the invalidation with `barrier::complete_tlb_invalidation()`.
+#[inline(always)]
+unsafe fn invalidate_tlb_entries(virt_page: Address<Virtual>) {
+    let va = (virt_page.into_usize() as u64) >> 12;
 
-    /// Returns the valid bit.
-    fn is_valid(&self) -> bool {
-        InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value)
-            .is_set(STAGE1_PAGE_DESCRIPTOR::VALID)
-    }
+    asm!("tlbi vaae1is, {}", in(reg) va, options(nostack, preserves_flags));
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -268,9 +111,12 @@ where
 impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
     FixedSizeTranslationTable<NUM_TABLES, START_FROM_TOP>
 {
-    // Reserve the last 256 MiB of the address space for MMIO mappings.
+    // Reserve the end of the address space for MMIO mappings. The region must fit into the last
+    // lvl2 entry.
     const L2_MMIO_START_INDEX: usize = NUM_TABLES - 1;
-    const L3_MMIO_START_INDEX: usize = 8192 / 2;
+    const L3_MMIO_START_INDEX: usize =
+        8192 - (config::memory::MMIO_REGION_SIZE >> Granule64KiB::SHIFT);
+    const L3_MMIO_END_INDEX: usize = 8191;
 
     const START_FROM_TOP_OFFSET: Address<Virtual> =
         Address::new((usize::MAX - (Granule512MiB::SIZE * NUM_TABLES)) + 1);
@@ -286,8 +132,10 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
         Self {
             lvl3: [[PageDescriptor::new_zeroed(); 8192]; NUM_TABLES],
             lvl2: [TableDescriptor::new_zeroed(); NUM_TABLES],
-            cur_l3_mmio_index: Self::L3_MMIO_START_INDEX,
+            mmio_pages_used: 0,
             initialized: for_precompute,
+            protected_start: Address::new(0),
+            num_protected_pages: 0,
         }
     }
 
@@ -296,7 +144,7 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
     }
 
     #[cfg(test)]
-    pub fn new_for_runtime() -> Self {
+    pub const fn new_for_runtime() -> Self {
         Self::_new(false)
     }
 
@@ -340,7 +188,9 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
         let mut addr = addr as usize;
 
         if START_FROM_TOP {
-            addr -= Self::START_FROM_TOP_OFFSET.into_usize()
+            addr = addr
+                .checked_sub(Self::START_FROM_TOP_OFFSET.into_usize())
+                .ok_or("Virtual page is out of bounds of translation table")?;
         }
 
         let lvl2_index = addr >> Granule512MiB::SHIFT;
@@ -363,6 +213,135 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
 
         Ok(&mut self.lvl3[lvl2_index][lvl3_index])
     }
+
+    /// The address of the descriptor that translates `virt_addr`.
+    fn page_descriptor_addr(&self, virt_addr: Address<Virtual>) -> Option<Address<Virtual>> {
+        let (lvl2_index, lvl3_index) = self
+            .lvl2_lvl3_index_from(virt_addr.into_usize() as *const Page<Virtual>)
+            .ok()?;
+
+        Some(Address::new(
+            &self.lvl3[lvl2_index][lvl3_index] as *const _ as usize,
+        ))
+    }
+
+    /// Returns true if `page` is one of the table's own pages that are mapped read-only.
+    ///
+    /// The pages that hold the descriptors of the table's own pages are never among them. They must
+    /// stay writable, since unprotecting any page starts with a write to them.
+    fn is_protected_page(&self, page: Address<Virtual>) -> bool {
+        if self.num_protected_pages == 0 {
+            return false;
+        }
+
+        let protected =
+            PageSliceDescriptor::from_addr(self.protected_start, self.num_protected_pages);
+        if !protected.contains(page) {
+            return false;
+        }
+
+        (0..protected.num_pages()).all(|i| {
+            let own_page = protected.start_addr() + i * Granule64KiB::SIZE;
+
+            self.page_descriptor_addr(own_page)
+                .map_or(true, |x| x.align_down(Granule64KiB::SIZE) != page)
+        })
+    }
+
+    /// Change the access permissions of those of the `num_pages` pages starting at `first_page`
+    /// that are protected, and invalidate their TLB entries.
+    ///
+    /// Only the permissions change, which does not require a break-before-make sequence.
+    unsafe fn set_protected_pages_access(
+        &mut self,
+        first_page: Address<Virtual>,
+        num_pages: usize,
+        acc_perms: AccessPermissions,
+    ) {
+        let page = |i| first_page + i * Granule64KiB::SIZE;
+
+        for i in 0..num_pages {
+            if !self.is_protected_page(page(i)) {
+                continue;
+            }
+
+            if let Ok(desc) =
+                self.page_descriptor_from(page(i).into_usize() as *const Page<Virtual>)
+            {
+                *desc = desc.with_access_permissions(acc_perms);
+            }
+        }
+
+        // Make the descriptor updates visible to the table walker before invalidating the TLB.
+        barrier::complete_table_update();
+
+        for i in 0..num_pages {
+            if self.is_protected_page(page(i)) {
+                invalidate_tlb_entries(page(i));
+            }
+        }
+
+        barrier::complete_tlb_invalidation();
+    }
+
+    /// Call `f`, which writes to the bytes from `start` to `end_inclusive` of the table, with the
+    /// table's pages that hold them mapped writable.
+    unsafe fn with_range_writable<T>(
+        &mut self,
+        start: Address<Virtual>,
+        end_inclusive: Address<Virtual>,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        if self.num_protected_pages == 0 {
+            return f(self);
+        }
+
+        let first_page = start.align_down(Granule64KiB::SIZE);
+        let num_pages = (end_inclusive
+            .align_down(Granule64KiB::SIZE)
+            .offset_from(first_page)
+            >> Granule64KiB::SHIFT)
+            + 1;
+
+        self.set_protected_pages_access(first_page, num_pages, AccessPermissions::ReadWrite);
+        let ret = f(self);
+        self.set_protected_pages_access(first_page, num_pages, AccessPermissions::ReadOnly);
+
+        ret
+    }
+
+    /// Call `f`, which writes the descriptors of `virt_pages`, with the table's pages that hold them
+    /// mapped writable.
+    unsafe fn with_descriptors_writable<T>(
+        &mut self,
+        virt_pages: &PageSliceDescriptor<Virtual>,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        let first = self.page_descriptor_addr(virt_pages.start_addr());
+        let last = self.page_descriptor_addr(virt_pages.end_addr_inclusive());
+
+        match (first, last) {
+            (Some(first), Some(last)) => self.with_range_writable(
+                first,
+                last + (core::mem::size_of::<PageDescriptor>() - 1),
+                f,
+            ),
+            // Out of bounds of the table, which `f` reports on its own.
+            _ => f(self),
+        }
+    }
+
+    /// Set the number of pages handed out from the MMIO region.
+    fn set_mmio_pages_used(&mut self, mmio_pages_used: usize) {
+        let addr = Address::new(&self.mmio_pages_used as *const _ as usize);
+
+        // Safety: Only the field's page is made writable, and only while it is written.
+        unsafe {
+            self.with_range_writable(addr, addr + (core::mem::size_of::<usize>() - 1), |t| {
+                t.mmio_pages_used = mmio_pages_used
+            })
+        }
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -389,7 +368,7 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
             *lvl2_entry = desc;
         }
 
-        self.cur_l3_mmio_index = Self::L3_MMIO_START_INDEX;
+        self.mmio_pages_used = 0;
         self.initialized = true;
 
         Ok(())
@@ -419,19 +398,90 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
             return Err("Tried to map outside of physical address space");
         }
 
-        let iter = p.iter().zip(v.iter());
-        for (phys_page, virt_page) in iter {
-            let page_descriptor = self.page_descriptor_from(virt_page.as_ptr())?;
-            if page_descriptor.is_valid() {
-                return Err("Virtual page is already mapped");
+        self.with_descriptors_writable(virt_pages, |t| {
+            let iter = p.iter().zip(v.iter());
+            for (phys_page, virt_page) in iter {
+                let page_descriptor = t.page_descriptor_from(virt_page.as_ptr())?;
+                if page_descriptor.is_valid() {
+                    return Err("Virtual page is already mapped");
+                }
+
+                *page_descriptor = PageDescriptor::from_output_addr(phys_page.as_ptr(), &attr);
+            }
+
+            Ok(())
+        })
+    }
+
+    fn verify_pages_at(
+        &self,
+        virt_pages: &PageSliceDescriptor<Virtual>,
+        phys_pages: &PageSliceDescriptor<Physical>,
+        attr: &AttributeFields,
+    ) -> Result<(), &'static str> {
+        assert!(self.initialized, "Translation tables not initialized");
+
+        let p = phys_pages.as_slice();
+        let v = virt_pages.as_slice();
+
+        if v.len() != p.len() {
+            return Err("Tried to verify page slices with unequal sizes");
+        }
+
+        for (phys_page, virt_page) in p.iter().zip(v.iter()) {
+            let (lvl2_index, lvl3_index) = self.lvl2_lvl3_index_from(virt_page.as_ptr())?;
+
+            let lvl3_addr = self.lvl3[lvl2_index]
+                .virt_start_addr()
+                .try_into()
+                .map_err(|_| "Translation error")?;
+            let expected = TableDescriptor::from_next_lvl_table_addr(lvl3_addr);
+            if self.lvl2[lvl2_index].into_u64() != expected.into_u64() {
+                return Err("Table descriptor does not point to its lvl3 table");
             }
 
-            *page_descriptor = PageDescriptor::from_output_addr(phys_page.as_ptr(), &attr);
+            let expected = PageDescriptor::from_output_addr(phys_page.as_ptr(), attr);
+            if self.lvl3[lvl2_index][lvl3_index].into_u64() != expected.into_u64() {
+                return Err("Page descriptor differs from the expected encoding");
+            }
         }
 
         Ok(())
     }
 
+    unsafe fn unmap_pages_at(
+        &mut self,
+        virt_pages: &PageSliceDescriptor<Virtual>,
+    ) -> Result<(), &'static str> {
+        assert!(self.initialized, "Translation tables not initialized");
+
+        let v = virt_pages.as_slice();
+
+        self.with_descriptors_writable(virt_pages, |t| {
+            for virt_page in v.iter() {
+                let page_descriptor = t.page_descriptor_from(virt_page.as_ptr())?;
+                if !page_descriptor.is_valid() {
+                    return Err("Virtual page is not mapped");
+                }
+
+                *page_descriptor = PageDescriptor::new_zeroed();
+            }
+
+            Ok(())
+        })?;
+
+        // Make the descriptor updates visible to the table walker before invalidating the TLB.
+        barrier::complete_table_update();
+
+        for virt_page in v.iter() {
+            invalidate_tlb_entries(Address::new(virt_page.as_ptr() as usize));
+        }
+
+        barrier::complete_tlb_invalidation();
+
+        Ok(())
+    }
+
     fn next_mmio_virt_page_slice(
         &mut self,
         num_pages: usize,
@@ -442,15 +492,16 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
             return Err("num_pages == 0");
         }
 
-        if (self.cur_l3_mmio_index + num_pages) > 8191 {
+        let l3_mmio_index = Self::L3_MMIO_START_INDEX + self.mmio_pages_used;
+        if (l3_mmio_index + num_pages) > Self::L3_MMIO_END_INDEX {
             return Err("Not enough MMIO space left");
         }
 
         let mut addr = Address::new(
             (Self::L2_MMIO_START_INDEX << Granule512MiB::SHIFT)
-                | (self.cur_l3_mmio_index << Granule64KiB::SHIFT),
+                | (l3_mmio_index << Granule64KiB::SHIFT),
         );
-        self.cur_l3_mmio_index += num_pages;
+        self.set_mmio_pages_used(self.mmio_pages_used + num_pages);
 
         if START_FROM_TOP {
             addr += Self::START_FROM_TOP_OFFSET;
@@ -459,6 +510,68 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
         Ok(PageSliceDescriptor::from_addr(addr, num_pages))
     }
 
+    fn free_mmio_virt_page_slice(&mut self, virt_pages: &PageSliceDescriptor<Virtual>) {
+        assert!(self.initialized, "Translation tables not initialized");
+
+        if !self.is_virt_page_slice_mmio(virt_pages) {
+            return;
+        }
+
+        let offset = virt_pages.start_addr().into_usize() - self.mmio_start_addr().into_usize();
+        let first_page = offset >> Granule64KiB::SHIFT;
+
+        // Only the topmost slice can be given back.
+        if (first_page + virt_pages.num_pages()) == self.mmio_pages_used {
+            self.set_mmio_pages_used(first_page);
+        }
+    }
+
+    fn mmio_stats(&self) -> (usize, usize) {
+        (
+            self.mmio_pages_used,
+            Self::L3_MMIO_END_INDEX - Self::L3_MMIO_START_INDEX,
+        )
+    }
+
+    unsafe fn write_protect(
+        &mut self,
+        own_pages: &PageSliceDescriptor<Virtual>,
+    ) -> Result<(), &'static str> {
+        assert!(self.initialized, "Translation tables not initialized");
+
+        if self.num_protected_pages != 0 {
+            return Err("Translation tables are already write protected");
+        }
+
+        let self_start = Address::new(self as *const _ as usize);
+        if !own_pages.contains(self_start)
+            || (own_pages.end_addr().into_usize()
+                < self_start.into_usize() + core::mem::size_of::<Self>())
+        {
+            return Err("Pages do not cover the translation tables");
+        }
+
+        if self.page_descriptor_addr(own_pages.start_addr()).is_none()
+            || self
+                .page_descriptor_addr(own_pages.end_addr_inclusive())
+                .is_none()
+        {
+            return Err("Translation tables are not mapped by themselves");
+        }
+
+        // The fields are on one of the pages that become read-only, so they are set first.
+        self.protected_start = own_pages.start_addr();
+        self.num_protected_pages = own_pages.num_pages();
+
+        self.set_protected_pages_access(
+            own_pages.start_addr(),
+            own_pages.num_pages(),
+            AccessPermissions::ReadOnly,
+        );
+
+        Ok(())
+    }
+
     fn is_virt_page_slice_mmio(&self, virt_pages: &PageSliceDescriptor<Virtual>) -> bool {
         let start_addr = virt_pages.start_addr();
         let end_addr_inclusive = virt_pages.end_addr_inclusive();
@@ -483,8 +596,26 @@ pub type MinSizeTranslationTable = FixedSizeTranslationTable<1, false>;
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        memory::mmu::{
+            arch_mmu::mair, translation_table::interface::TranslationTable, AccessPermissions,
+            MemAttributes,
+        },
+        synchronization::interface::{Mutex, ReadWriteEx},
+    };
     use test_macros::kernel_test;
 
+    const DESC_VALID_AND_TYPE: u64 = 0b11;
+    const DESC_ADDR_MASK: u64 = 0x0000_FFFF_FFFF_0000;
+    const DESC_AF: u64 = 1 << 10;
+    const DESC_PXN: u64 = 1 << 53;
+    const DESC_UXN: u64 = 1 << 54;
+
+    /// Read a descriptor from the table's memory, bypassing the descriptor types.
+    fn raw<T>(desc: &T) -> u64 {
+        unsafe { core::ptr::read_volatile(desc as *const T as *const u64) }
+    }
+
     /// Check if the size of `struct TableDescriptor` is as expected.
     #[kernel_test]
     fn size_of_tabledescriptor_equals_64_bit() {
@@ -502,4 +633,115 @@ mod tests {
             core::mem::size_of::<u64>()
         );
     }
+
+    /// The lvl2 entries of the test table point to its lvl3 tables.
+    #[kernel_test]
+    fn test_table_init_populates_lvl2() {
+        bsp::memory::mmu::test_translation_tables().lock(|tables| {
+            assert!(tables.init().is_ok());
+
+            for (lvl2_nr, lvl2_entry) in tables.lvl2.iter().enumerate() {
+                let lvl3_phys: Address<Physical> =
+                    tables.lvl3[lvl2_nr].virt_start_addr().try_into().unwrap();
+                let desc = raw(lvl2_entry);
+
+                assert_eq!(desc & DESC_VALID_AND_TYPE, DESC_VALID_AND_TYPE);
+                assert_eq!(desc & DESC_ADDR_MASK, lvl3_phys.into_usize() as u64);
+            }
+        });
+    }
+
+    /// Mapping writes the expected bits, and the error cases leave the table untouched.
+    #[kernel_test]
+    fn test_table_map_pages_at_works() {
+        bsp::memory::mmu::test_translation_tables().lock(|tables| {
+            assert!(tables.init().is_ok());
+
+            // A few pages into the lvl2 window that holds the MMIO region.
+            let window_start = tables.mmio_start_addr().align_down(Granule512MiB::SIZE);
+            let virt = PageSliceDescriptor::from_addr(window_start + Granule64KiB::SIZE * 4, 2);
+            let phys = PageSliceDescriptor::<Physical>::from_addr(Address::new(0x30_0000), 2);
+            let attr = AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+            };
+
+            unsafe {
+                assert!(tables.map_pages_at(&virt, &phys, &attr).is_ok());
+
+                for (virt_page, phys_page) in virt.as_slice().iter().zip(phys.as_slice().iter()) {
+                    let desc = raw(tables.page_descriptor_from(virt_page.as_ptr()).unwrap());
+
+                    assert_eq!(desc & DESC_VALID_AND_TYPE, DESC_VALID_AND_TYPE);
+                    assert_eq!(desc & DESC_ADDR_MASK, phys_page.as_ptr() as u64);
+                    assert_ne!(desc & DESC_AF, 0);
+                    assert_ne!(desc & DESC_PXN, 0);
+                    assert_ne!(desc & DESC_UXN, 0);
+                    assert_eq!((desc >> 2) & 0b111, mair::NORMAL); // AttrIndx
+                    assert_eq!((desc >> 6) & 0b11, 0b00); // AP: RW_EL1
+                    assert_eq!((desc >> 8) & 0b11, 0b11); // SH: Inner Shareable
+                }
+
+                // Overlapping the mapping.
+                let overlap = PageSliceDescriptor::from_addr(
+                    virt.start_addr() + bsp::memory::mmu::KernelGranule::SIZE,
+                    2,
+                );
+                assert_eq!(
+                    tables.map_pages_at(&overlap, &phys, &attr),
+                    Err("Virtual page is already mapped")
+                );
+
+                // Unequal sizes.
+                let small = PageSliceDescriptor::from_addr(virt.start_addr(), 1);
+                assert!(tables.map_pages_at(&small, &phys, &attr).is_err());
+
+                // Below the range that the table covers.
+                let below = PageSliceDescriptor::from_addr(Address::new(0x1_0000), 2);
+                assert!(tables.map_pages_at(&below, &phys, &attr).is_err());
+
+                assert!(tables.verify_pages_at(&virt, &phys, &attr).is_ok());
+                let ro_attr = AttributeFields {
+                    acc_perms: AccessPermissions::ReadOnly,
+                    ..attr
+                };
+                assert!(tables.verify_pages_at(&virt, &phys, &ro_attr).is_err());
+
+                assert!(tables.unmap_pages_at(&virt).is_ok());
+                for virt_page in virt.as_slice().iter() {
+                    assert_eq!(
+                        raw(tables.page_descriptor_from(virt_page.as_ptr()).unwrap()),
+                        0
+                    );
+                }
+            }
+        });
+    }
+
+    /// The MMIO allocator of the test table runs out without affecting the kernel's.
+    #[kernel_test]
+    fn test_table_mmio_exhaustion() {
+        let kernel_stats = bsp::memory::mmu::kernel_translation_tables().read(|t| t.mmio_stats());
+
+        bsp::memory::mmu::test_translation_tables().lock(|tables| {
+            assert!(tables.init().is_ok());
+
+            let (used, total) = tables.mmio_stats();
+            assert_eq!(used, 0);
+
+            let all = tables.next_mmio_virt_page_slice(total).unwrap();
+            assert!(tables.is_virt_page_slice_mmio(&all));
+            assert!(tables.next_mmio_virt_page_slice(1).is_err());
+            assert_eq!(tables.mmio_stats(), (total, total));
+
+            tables.free_mmio_virt_page_slice(&all);
+            assert_eq!(tables.mmio_stats(), (0, total));
+        });
+
+        assert_eq!(
+            bsp::memory::mmu::kernel_translation_tables().read(|t| t.mmio_stats()),
+            kernel_stats
+        );
+    }
 }
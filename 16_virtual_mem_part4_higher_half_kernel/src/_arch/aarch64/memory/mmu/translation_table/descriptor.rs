@@ -0,0 +1,363 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural translation table descriptors.
+//!
+//! Pure bit encoding, without any access to the hardware, so that it can be tested on the host as
+//! well. The translation table tool includes this file, too, for precomputing the kernel's tables.
+//! The kernel checks the precomputed tables against it at boot, so the two can not disagree.
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::memory::mmu::translation_table::arch_translation_table::descriptor
+
+use crate::memory::{
+    mmu::{
+        arch_mmu::{mair, Granule64KiB},
+        AccessPermissions, AttributeFields, MemAttributes, Page,
+    },
+    Address, Physical,
+};
+use register::{register_bitfields, InMemoryRegister};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// A table descriptor, as per ARMv8-A Architecture Reference Manual Figure D5-15.
+register_bitfields! {u64,
+    STAGE1_TABLE_DESCRIPTOR [
+        /// Physical address of the next descriptor.
+        NEXT_LEVEL_TABLE_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
+
+        TYPE  OFFSET(1) NUMBITS(1) [
+            Block = 0,
+            Table = 1
+        ],
+
+        VALID OFFSET(0) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ]
+    ]
+}
+
+// A level 3 page descriptor, as per ARMv8-A Architecture Reference Manual Figure D5-17.
+register_bitfields! {u64,
+    STAGE1_PAGE_DESCRIPTOR [
+        /// Unprivileged execute-never.
+        UXN      OFFSET(54) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
+        /// Privileged execute-never.
+        PXN      OFFSET(53) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
+        /// Physical address of the next table descriptor (lvl2) or the page descriptor (lvl3).
+        OUTPUT_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
+
+        /// Access flag.
+        AF       OFFSET(10) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
+        /// Shareability field.
+        SH       OFFSET(8) NUMBITS(2) [
+            OuterShareable = 0b10,
+            InnerShareable = 0b11
+        ],
+
+        /// Access Permissions.
+        AP       OFFSET(6) NUMBITS(2) [
+            RW_EL1 = 0b00,
+            RW_EL1_EL0 = 0b01,
+            RO_EL1 = 0b10,
+            RO_EL1_EL0 = 0b11
+        ],
+
+        /// Memory attributes index into the MAIR_EL1 register.
+        AttrIndx OFFSET(2) NUMBITS(3) [],
+
+        TYPE     OFFSET(1) NUMBITS(1) [
+            Reserved_Invalid = 0,
+            Page = 1
+        ],
+
+        VALID    OFFSET(0) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ]
+    ]
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A table descriptor for 64 KiB aperture.
+///
+/// The output points to the next table.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct TableDescriptor {
+    value: u64,
+}
+
+/// A page descriptor with 64 KiB aperture.
+///
+/// The output points to physical memory.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct PageDescriptor {
+    value: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl TableDescriptor {
+    /// Create an instance.
+    ///
+    /// Descriptor is invalid by default.
+    pub const fn new_zeroed() -> Self {
+        Self { value: 0 }
+    }
+
+    /// Create an instance pointing to the supplied address.
+    pub fn from_next_lvl_table_addr(phys_next_lvl_table_addr: Address<Physical>) -> Self {
+        let val = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(0);
+
+        let shifted = phys_next_lvl_table_addr.into_usize() >> Granule64KiB::SHIFT;
+        val.write(
+            STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR_64KiB.val(shifted as u64)
+                + STAGE1_TABLE_DESCRIPTOR::TYPE::Table
+                + STAGE1_TABLE_DESCRIPTOR::VALID::True,
+        );
+
+        TableDescriptor { value: val.get() }
+    }
+
+    /// The raw value, as the table walker reads it.
+    pub const fn into_u64(self) -> u64 {
+        self.value
+    }
+}
+
+/// Convert the kernel's generic memory attributes to HW-specific attributes of the MMU.
+impl From<AttributeFields> for register::FieldValue<u64, STAGE1_PAGE_DESCRIPTOR::Register> {
+    fn from(attribute_fields: AttributeFields) -> Self {
+        // Memory attributes.
+        let mut desc = match attribute_fields.mem_attributes {
+            MemAttributes::CacheableDRAM => {
+                STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::NORMAL)
+            }
+            MemAttributes::Device => {
+                STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::DEVICE)
+            }
+        };
+
+        // Access Permissions.
+        desc += match attribute_fields.acc_perms {
+            AccessPermissions::ReadOnly => STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1,
+            AccessPermissions::ReadWrite => STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1,
+        };
+
+        // The execute-never attribute is mapped to PXN in AArch64.
+        desc += if attribute_fields.execute_never {
+            STAGE1_PAGE_DESCRIPTOR::PXN::True
+        } else {
+            STAGE1_PAGE_DESCRIPTOR::PXN::False
+        };
+
+        // Always set unprivileged exectue-never as long as userspace is not implemented yet.
+        desc += STAGE1_PAGE_DESCRIPTOR::UXN::True;
+
+        desc
+    }
+}
+
+impl PageDescriptor {
+    /// Create an instance.
+    ///
+    /// Descriptor is invalid by default.
+    pub const fn new_zeroed() -> Self {
+        Self { value: 0 }
+    }
+
+    /// Create an instance.
+    pub fn from_output_addr(
+        phys_output_addr: *const Page<Physical>,
+        attribute_fields: &AttributeFields,
+    ) -> Self {
+        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(0);
+
+        let shifted = phys_output_addr as u64 >> Granule64KiB::SHIFT;
+        val.write(
+            STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR_64KiB.val(shifted)
+                + STAGE1_PAGE_DESCRIPTOR::AF::True
+                + STAGE1_PAGE_DESCRIPTOR::TYPE::Page
+                + STAGE1_PAGE_DESCRIPTOR::VALID::True
+                + (*attribute_fields).into(),
+        );
+
+        Self { value: val.get() }
+    }
+
+    /// The raw value, as the table walker reads it.
+    pub const fn into_u64(self) -> u64 {
+        self.value
+    }
+
+    /// Returns the valid bit.
+    pub fn is_valid(&self) -> bool {
+        InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value)
+            .is_set(STAGE1_PAGE_DESCRIPTOR::VALID)
+    }
+
+    /// Return a copy with different access permissions.
+    pub fn with_access_permissions(&self, acc_perms: AccessPermissions) -> Self {
+        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value);
+
+        val.modify(match acc_perms {
+            AccessPermissions::ReadOnly => STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1,
+            AccessPermissions::ReadWrite => STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1,
+        });
+
+        Self { value: val.get() }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(all(test, not(target_os = "none")))]
+mod host_tests {
+    use super::*;
+
+    type PageReg = InMemoryRegister<u64, STAGE1_PAGE_DESCRIPTOR::Register>;
+
+    const ALL_ATTRIBUTES: [AttributeFields; 8] = {
+        use AccessPermissions::*;
+        use MemAttributes::*;
+
+        const fn attr(m: MemAttributes, a: AccessPermissions, xn: bool) -> AttributeFields {
+            AttributeFields {
+                mem_attributes: m,
+                acc_perms: a,
+                execute_never: xn,
+            }
+        }
+
+        [
+            attr(CacheableDRAM, ReadOnly, false),
+            attr(CacheableDRAM, ReadOnly, true),
+            attr(CacheableDRAM, ReadWrite, false),
+            attr(CacheableDRAM, ReadWrite, true),
+            attr(Device, ReadOnly, false),
+            attr(Device, ReadOnly, true),
+            attr(Device, ReadWrite, false),
+            attr(Device, ReadWrite, true),
+        ]
+    };
+
+    /// Check the encoding of a table descriptor.
+    #[test]
+    fn table_descriptor_encoding() {
+        let addr = Address::<Physical>::new(0x1234_0000);
+        let desc = TableDescriptor::from_next_lvl_table_addr(addr);
+        let reg = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(desc.value);
+
+        assert!(reg.is_set(STAGE1_TABLE_DESCRIPTOR::VALID));
+        assert!(reg.matches_all(STAGE1_TABLE_DESCRIPTOR::TYPE::Table));
+        assert_eq!(
+            reg.read(STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR_64KiB) << Granule64KiB::SHIFT,
+            0x1234_0000
+        );
+    }
+
+    /// Check that zeroed descriptors are invalid.
+    #[test]
+    fn zeroed_descriptors_are_invalid() {
+        assert_eq!(TableDescriptor::new_zeroed().value, 0);
+        assert!(!PageDescriptor::new_zeroed().is_valid());
+    }
+
+    /// Encode all attribute combinations and decode them again.
+    #[test]
+    fn page_descriptor_round_trip() {
+        let addr = 0x3F20_0000 as *const Page<Physical>;
+
+        for attr in ALL_ATTRIBUTES.iter() {
+            let desc = PageDescriptor::from_output_addr(addr, attr);
+            let reg = PageReg::new(desc.value);
+
+            assert!(desc.is_valid());
+            assert!(reg.matches_all(STAGE1_PAGE_DESCRIPTOR::TYPE::Page));
+            assert!(reg.is_set(STAGE1_PAGE_DESCRIPTOR::AF));
+            assert!(reg.is_set(STAGE1_PAGE_DESCRIPTOR::UXN));
+            assert_eq!(
+                reg.read(STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR_64KiB) << Granule64KiB::SHIFT,
+                0x3F20_0000
+            );
+
+            let mem_attributes = match reg.read(STAGE1_PAGE_DESCRIPTOR::AttrIndx) {
+                mair::NORMAL => MemAttributes::CacheableDRAM,
+                mair::DEVICE => MemAttributes::Device,
+                x => panic!("Unexpected AttrIndx {}", x),
+            };
+            assert!(mem_attributes == attr.mem_attributes);
+
+            let expected_sh = match attr.mem_attributes {
+                MemAttributes::CacheableDRAM => STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable,
+                MemAttributes::Device => STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable,
+            };
+            assert!(reg.matches_all(expected_sh));
+
+            let read_only = reg.matches_all(STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1);
+            assert_eq!(
+                read_only,
+                matches!(attr.acc_perms, AccessPermissions::ReadOnly)
+            );
+
+            assert_eq!(reg.is_set(STAGE1_PAGE_DESCRIPTOR::PXN), attr.execute_never);
+        }
+    }
+
+    /// Changing the access permissions leaves all other bits alone.
+    #[test]
+    fn access_permissions_are_replaced() {
+        let addr = 0x3F20_0000 as *const Page<Physical>;
+
+        for attr in ALL_ATTRIBUTES.iter() {
+            let desc = PageDescriptor::from_output_addr(addr, attr);
+
+            for acc_perms in [AccessPermissions::ReadOnly, AccessPermissions::ReadWrite].iter() {
+                let expected = PageDescriptor::from_output_addr(
+                    addr,
+                    &AttributeFields {
+                        acc_perms: *acc_perms,
+                        ..*attr
+                    },
+                );
+
+                assert_eq!(
+                    desc.with_access_permissions(*acc_perms).value,
+                    expected.value
+                );
+            }
+        }
+    }
+}
@@ -11,35 +11,154 @@
 //!
 //! crate::time::arch_time
 
-use crate::{time, warn};
-use core::time::Duration;
-use cortex_a::{barrier, regs::*};
+use crate::{
+    bsp, config,
+    cpu::barrier,
+    driver, exception, info,
+    synchronization::{interface::Mutex, interface::ReadWriteEx, IRQSafeNullLock, InitStateLock},
+    time,
+    units::Hertz,
+};
+use core::{fmt, time::Duration};
+use cortex_a::regs::*;
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
 //--------------------------------------------------------------------------------------------------
 
-const NS_PER_S: u64 = 1_000_000_000;
+/// CNTP_CTL_EL0 and CNTV_CTL_EL0 bits.
+mod cnt_ctl {
+    pub const ENABLE: u64 = 1 << 0;
+}
+
+/// How often `probe_counter()` reads the virtual counter at most while waiting for it to advance.
+const PROBE_MAX_READS: usize = 1_000_000;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
 
 /// ARMv8 Generic Timer.
-struct GenericTimer;
+///
+/// The kernel's default time source. Reads the counter that `select_counter()` chose.
+pub struct GenericTimer;
+
+/// The generic timer's counters, each with a timer of its own.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Counter {
+    /// CNTPCT_EL0 and the EL1 physical timer.
+    Physical,
+
+    /// CNTVCT_EL0 and the virtual timer.
+    Virtual,
+}
+
+/// A one-shot timeout on top of the timer that belongs to the active counter.
+///
+/// The timers are banked per core, so a timeout fires on the core that armed it.
+pub struct TimeoutTimer {
+    /// The IRQs of the physical and the virtual timer, in this order.
+    irq_numbers: [bsp::device_driver::IRQNumber; 2],
+    on_timeout: IRQSafeNullLock<Option<fn()>>,
+}
 
 //--------------------------------------------------------------------------------------------------
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
-static TIME_MANAGER: GenericTimer = GenericTimer;
+static GENERIC_TIMER: GenericTimer = GenericTimer;
+
+static ACTIVE_COUNTER: InitStateLock<Counter> = InitStateLock::new(Counter::Physical);
 
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
 
-impl GenericTimer {
-    #[inline(always)]
-    fn read_cntpct(&self) -> u64 {
-        // Prevent that the counter is read ahead of time due to out-of-order execution.
-        unsafe { barrier::isb(barrier::SY) };
-        CNTPCT_EL0.get()
+#[inline(always)]
+fn read_cntpct() -> u64 {
+    // Prevent that the counter is read ahead of time due to out-of-order execution.
+    barrier::isb();
+    CNTPCT_EL0.get()
+}
+
+#[inline(always)]
+fn read_cntvct() -> u64 {
+    barrier::isb();
+    CNTVCT_EL0.get()
+}
+
+/// Decide which counter to use, and why.
+///
+/// The kernel enables EL1's access to the physical counter and clears the virtual offset itself
+/// before it drops from EL2, but EL1 can not read back CNTHCTL_EL2 and CNTVOFF_EL2. So the effect of
+/// the two is checked instead. A hypervisor that traps the physical counter tends to return a
+/// constant, and a virtual offset shows as a physical count outside of two virtual ones. Either
+/// way, the virtual counter is the one that is meant to be used.
+fn probe_counter() -> (Counter, &'static str) {
+    let v1 = read_cntvct();
+    let p1 = read_cntpct();
+    let v2 = read_cntvct();
+
+    // Wait for the virtual counter to move on, then check that the physical one did as well.
+    let mut reads = 0;
+    while (read_cntvct() == v2) && (reads < PROBE_MAX_READS) {
+        reads += 1;
+    }
+    let p2 = read_cntpct();
+
+    if reads == PROBE_MAX_READS {
+        return (Counter::Physical, "the virtual counter does not advance");
+    }
+    if p2 == p1 {
+        return (Counter::Virtual, "the physical counter does not advance");
+    }
+    if (p1 < v1) || (p1 > v2) {
+        return (Counter::Virtual, "the virtual counter is offset");
+    }
+
+    (Counter::Physical, "the counters agree")
+}
+
+/// Convert a duration into timer ticks. Returns `None` if it does not fit into the 32 bits of a
+/// TVAL register.
+fn duration_to_tval(duration: Duration) -> Option<u64> {
+    let tval = Hertz(CNTFRQ_EL0.get()).cycles_in(duration)?;
+
+    if tval > u32::max_value().into() {
+        return None;
+    }
+
+    Some(tval)
+}
+
+/// Write the CTL register of the active counter's timer.
+fn set_ctl(val: u64) {
+    match active_counter() {
+        Counter::Physical => unsafe {
+            asm!("msr cntp_ctl_el0, {}", "isb", in(reg) val, options(nostack))
+        },
+        Counter::Virtual => unsafe {
+            asm!("msr cntv_ctl_el0, {}", "isb", in(reg) val, options(nostack))
+        },
+    }
+}
+
+/// Write the TVAL register of the active counter's timer.
+fn set_tval(val: u64) {
+    match active_counter() {
+        Counter::Physical => unsafe {
+            asm!("msr cntp_tval_el0, {}", in(reg) val, options(nostack))
+        },
+        Counter::Virtual => unsafe { asm!("msr cntv_tval_el0, {}", in(reg) val, options(nostack)) },
+    }
+}
+
+impl TimeoutTimer {
+    fn irq_number(&self) -> &bsp::device_driver::IRQNumber {
+        match active_counter() {
+            Counter::Physical => &self.irq_numbers[0],
+            Counter::Virtual => &self.irq_numbers[1],
+        }
     }
 }
 
@@ -47,72 +166,145 @@ impl GenericTimer {
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
-/// Return a reference to the time manager.
-pub fn time_manager() -> &'static impl time::interface::TimeManager {
-    &TIME_MANAGER
+/// Return a reference to the architectural time source.
+pub fn arch_time_source() -> &'static (dyn time::interface::TimeSource + Sync) {
+    &GENERIC_TIMER
+}
+
+/// Probe the generic timer's counters and select the one that time is derived from.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init, before the timeout timer registers its IRQ handler.
+pub unsafe fn select_counter() {
+    let (counter, reason) = probe_counter();
+
+    ACTIVE_COUNTER.write(|x| *x = counter);
+    info!(
+        "Generic timer: Using the {} counter, since {}",
+        counter, reason
+    );
+}
+
+/// The counter that the generic timer reads.
+pub fn active_counter() -> Counter {
+    ACTIVE_COUNTER.read(|x| *x)
+}
+
+/// The time between two events of the timer's event stream, see `cpu::enable_event_stream()`.
+///
+/// The stream fires on the counter bit's transitions from zero to one only, so once in two of the
+/// bit's periods.
+pub fn event_stream_period() -> Duration {
+    let ticks = 1 << (config::time::EVENT_STREAM_COUNTER_BIT + 1);
+
+    Hertz(CNTFRQ_EL0.get())
+        .duration_of(ticks)
+        .unwrap_or_default()
+}
+
+impl fmt::Display for Counter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Counter::Physical => "physical",
+            Counter::Virtual => "virtual",
+        })
+    }
+}
+
+impl TimeoutTimer {
+    /// Create an instance with the IRQs of the physical and the virtual timer.
+    pub const fn new(
+        phys_irq_number: bsp::device_driver::IRQNumber,
+        virt_irq_number: bsp::device_driver::IRQNumber,
+    ) -> Self {
+        Self {
+            irq_numbers: [phys_irq_number, virt_irq_number],
+            on_timeout: IRQSafeNullLock::new(None),
+        }
+    }
+
+    /// Call `on_timeout` in IRQ context after `timeout` has passed, unless disarmed before.
+    ///
+    /// Replaces an earlier timeout that is still armed.
+    pub fn arm(&self, timeout: Duration, on_timeout: fn()) -> Result<(), &'static str> {
+        let tval = duration_to_tval(timeout).ok_or("Timeout too long")?;
+
+        self.disarm();
+        self.on_timeout.lock(|x| *x = Some(on_timeout));
+
+        set_tval(tval);
+        set_ctl(cnt_ctl::ENABLE);
+
+        Ok(())
+    }
+
+    /// Cancel the armed timeout, if any.
+    pub fn disarm(&self) {
+        set_ctl(0);
+        self.on_timeout.lock(|x| *x = None);
+    }
 }
 
 //------------------------------------------------------------------------------
 // OS Interface Code
 //------------------------------------------------------------------------------
 
-impl time::interface::TimeManager for GenericTimer {
-    fn resolution(&self) -> Duration {
-        Duration::from_nanos(NS_PER_S / (CNTFRQ_EL0.get() as u64))
+impl time::interface::TimeSource for GenericTimer {
+    fn name(&self) -> &'static str {
+        match active_counter() {
+            Counter::Physical => "ARMv8 Generic Timer (physical)",
+            Counter::Virtual => "ARMv8 Generic Timer (virtual)",
+        }
     }
 
-    fn uptime(&self) -> Duration {
-        let current_count: u64 = self.read_cntpct() * NS_PER_S;
-        let frq: u64 = CNTFRQ_EL0.get() as u64;
-
-        Duration::from_nanos(current_count / frq)
+    fn frequency(&self) -> Hertz {
+        Hertz(CNTFRQ_EL0.get())
     }
 
-    fn spin_for(&self, duration: Duration) {
-        // Instantly return on zero.
-        if duration.as_nanos() == 0 {
-            return;
+    fn counter(&self) -> u64 {
+        match active_counter() {
+            Counter::Physical => read_cntpct(),
+            Counter::Virtual => read_cntvct(),
         }
+    }
+}
 
-        // Calculate the register compare value.
-        let frq = CNTFRQ_EL0.get();
-        let x = match frq.checked_mul(duration.as_nanos() as u64) {
-            None => {
-                warn!("Spin duration too long, skipping");
-                return;
-            }
-            Some(val) => val,
-        };
-        let tval = x / NS_PER_S;
-
-        // Check if it is within supported bounds.
-        let warn: Option<&str> = if tval == 0 {
-            Some("smaller")
-        // The upper 32 bits of CNTP_TVAL_EL0 are reserved.
-        } else if tval > u32::max_value().into() {
-            Some("bigger")
-        } else {
-            None
+impl driver::interface::DeviceDriver for TimeoutTimer {
+    fn compatible(&self) -> &'static str {
+        "ARMv8 Timeout Timer"
+    }
+
+    fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
+        use bsp::exception::asynchronous::irq_manager;
+        use exception::asynchronous::{interface::IRQManager, IRQDescriptor};
+
+        let descriptor = IRQDescriptor {
+            name: "ARMv8 Timeout Timer",
+            handler: self,
         };
 
-        if let Some(w) = warn {
-            warn!(
-                "Spin duration {} than architecturally supported, skipping",
-                w
-            );
-            return;
-        }
+        irq_manager().register_handler(*self.irq_number(), descriptor)?;
+        irq_manager().enable(*self.irq_number());
 
-        // Set the compare value register.
-        CNTP_TVAL_EL0.set(tval);
+        Ok(())
+    }
 
-        // Kick off the counting.                       // Disable timer interrupt.
-        CNTP_CTL_EL0.modify(CNTP_CTL_EL0::ENABLE::SET + CNTP_CTL_EL0::IMASK::SET);
+    fn irq_numbers(&self) -> &[bsp::device_driver::IRQNumber] {
+        core::slice::from_ref(self.irq_number())
+    }
+}
 
-        // ISTATUS will be '1' when cval ticks have passed. Busy-check it.
-        while !CNTP_CTL_EL0.matches_all(CNTP_CTL_EL0::ISTATUS::SET) {}
+impl exception::asynchronous::interface::IRQHandler for TimeoutTimer {
+    fn handle(&self, _context: &exception::ExceptionContext) -> Result<(), &'static str> {
+        // The timer IRQ is level-sensitive. Disabling the timer deasserts it.
+        set_ctl(0);
+
+        // The callback is gone if the timeout was disarmed while the IRQ was already on its way.
+        if let Some(on_timeout) = self.on_timeout.lock(|x| x.take()) {
+            on_timeout();
+        }
 
-        // Disable counting again.
-        CNTP_CTL_EL0.modify(CNTP_CTL_EL0::ENABLE::CLEAR);
+        Ok(())
     }
 }
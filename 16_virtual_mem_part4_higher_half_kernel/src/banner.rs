@@ -0,0 +1,325 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! The boot banner.
+//!
+//! The banner is made of named sections. The generic ones are built in, and subsystems register
+//! their own during kernel init. Sections are rendered in order, built-in ones first, into a
+//! `fmt::Write`, so that tests can check a single section without printing the whole banner.
+//!
+//! ```text
+//! Kernel:          mingo version 0.1.0
+//! Board:           Raspberry Pi 4 Model B, revision 1.4 (from the firmware)
+//! Clocks:          ARM 1500 MHz (max 1500 MHz, measured 1499.870 MHz)
+//!                  Core 500 MHz
+//! ```
+
+use crate::{
+    bsp, cpu, driver, exception, info,
+    memory::mmu,
+    state,
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+    time,
+    time::interface::TimeManager,
+    units::ByteSize,
+};
+use core::fmt::{self, Write};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Maximum number of sections that can be registered in addition to the built-in ones.
+const MAX_REGISTERED_SECTIONS: usize = 8;
+
+/// Width of the column with the section names, including the colon.
+const NAME_WIDTH: usize = 16;
+
+/// Maximum length of a printed line. Longer lines are wrapped.
+const MAX_LINE_LEN: usize = 128;
+
+const BUILTIN_SECTIONS: [Section; 5] = [
+    ("Kernel", render_kernel),
+    ("Cores", render_cores),
+    ("MMU", render_mmu),
+    ("Time", render_time),
+    ("Drivers", render_drivers),
+];
+
+/// Prints the output of a section line by line, with the section's name in front of the first one.
+struct SectionPrinter {
+    name: &'static str,
+    is_first_line: bool,
+    line: [u8; MAX_LINE_LEN],
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Renders a section. A trailing newline is optional.
+pub type SectionFn = fn(&mut dyn fmt::Write) -> fmt::Result;
+
+/// A section, made of its name and its renderer.
+pub type Section = (&'static str, SectionFn);
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static REGISTERED_SECTIONS: InitStateLock<[Option<Section>; MAX_REGISTERED_SECTIONS]> =
+    InitStateLock::new([None; MAX_REGISTERED_SECTIONS]);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn render_kernel(out: &mut dyn fmt::Write) -> fmt::Result {
+    let (_, privilege_level) = exception::current_privilege_level();
+
+    writeln!(out, "{}", crate::version())?;
+    write!(out, "Running at {}", privilege_level)
+}
+
+fn render_cores(out: &mut dyn fmt::Write) -> fmt::Result {
+    write!(
+        out,
+        "{}/{} online",
+        state::state_manager().num_cores_checked_in(),
+        crate::config::cpu::NUM_CORES
+    )
+}
+
+fn render_mmu(out: &mut dyn fmt::Write) -> fmt::Result {
+    use bsp::memory::mmu::{KernelGranule, KernelVirtAddrSpace};
+
+    let mut num_mappings = 0;
+    mmu::kernel_for_each_mapping(|_| num_mappings += 1);
+
+    writeln!(out, "{}", cpu::cache::status())?;
+    writeln!(
+        out,
+        "{} granule, {} kernel address space",
+        ByteSize(KernelGranule::SIZE),
+        ByteSize(KernelVirtAddrSpace::SIZE)
+    )?;
+    write!(out, "{} kernel mappings", num_mappings)
+}
+
+fn render_time(out: &mut dyn fmt::Write) -> fmt::Result {
+    write!(
+        out,
+        "{} at {}, resolution {} ns",
+        time::time_source_name(),
+        time::time_source_frequency(),
+        time::time_manager().resolution().as_nanos()
+    )
+}
+
+fn render_drivers(out: &mut dyn fmt::Write) -> fmt::Result {
+    use driver::DriverStatus;
+
+    let manager = driver::driver_manager();
+
+    write!(
+        out,
+        "{} ok, {} failed, {} not initialized",
+        manager.num_drivers_with_status(DriverStatus::Ok),
+        manager.num_drivers_with_status(DriverStatus::Failed),
+        manager.num_drivers_with_status(DriverStatus::Uninitialized)
+    )
+}
+
+/// The section named `name`, if any.
+fn find(name: &str) -> Option<Section> {
+    BUILTIN_SECTIONS
+        .iter()
+        .copied()
+        .find(|(x, _)| *x == name)
+        .or_else(|| {
+            REGISTERED_SECTIONS
+                .read(|sections| sections.iter().flatten().copied().find(|(x, _)| *x == name))
+        })
+}
+
+/// Print a section, formatted as part of the banner.
+fn print_one((name, render): Section) {
+    let mut printer = SectionPrinter::new(name);
+
+    if render(&mut printer).is_err() {
+        let _ = write!(printer, " [incomplete]");
+    }
+    printer.finish();
+}
+
+impl SectionPrinter {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            is_first_line: true,
+            line: [0; MAX_LINE_LEN],
+            len: 0,
+        }
+    }
+
+    fn print_line(&mut self) {
+        // A line that was wrapped in the middle of a character is printed up to the character.
+        let line = match core::str::from_utf8(&self.line[..self.len]) {
+            Ok(x) => x,
+            Err(e) => unsafe { core::str::from_utf8_unchecked(&self.line[..e.valid_up_to()]) },
+        };
+
+        if self.is_first_line {
+            info!(
+                "{}:{:width$}{}",
+                self.name,
+                "",
+                line,
+                width = NAME_WIDTH.saturating_sub(self.name.len() + 1)
+            );
+        } else {
+            info!("{:width$}{}", "", line, width = NAME_WIDTH);
+        }
+
+        self.is_first_line = false;
+        self.len = 0;
+    }
+
+    /// Print what is left over from the last line, or the name alone if the section was empty.
+    fn finish(&mut self) {
+        if (self.len > 0) || self.is_first_line {
+            self.print_line();
+        }
+    }
+}
+
+impl fmt::Write for SectionPrinter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for b in s.bytes() {
+            if b == b'\n' {
+                self.print_line();
+                continue;
+            }
+
+            if self.len == MAX_LINE_LEN {
+                self.print_line();
+            }
+
+            self.line[self.len] = b;
+            self.len += 1;
+        }
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Register an additional section.
+///
+/// Must be called during kernel init. Sections are rendered in the order of registration.
+pub fn register_section(section: Section) -> Result<(), &'static str> {
+    REGISTERED_SECTIONS.write(|sections| {
+        if BUILTIN_SECTIONS.iter().any(|(x, _)| *x == section.0)
+            || sections.iter().flatten().any(|(x, _)| *x == section.0)
+        {
+            return Err("Banner section already registered");
+        }
+
+        match sections.iter_mut().find(|x| x.is_none()) {
+            None => Err("Banner section table full"),
+            Some(x) => {
+                *x = Some(section);
+                Ok(())
+            }
+        }
+    })
+}
+
+/// Render the section named `name` into `out`.
+pub fn render_section(name: &str, out: &mut dyn fmt::Write) -> Result<(), &'static str> {
+    let (_, render) = find(name).ok_or("Unknown banner section")?;
+
+    render(out).map_err(|_| "Error rendering the banner section")
+}
+
+/// Print the section named `name` on its own.
+pub fn print_section(name: &str) -> Result<(), &'static str> {
+    print_one(find(name).ok_or("Unknown banner section")?);
+
+    Ok(())
+}
+
+/// Print all sections.
+pub fn print() {
+    let registered = REGISTERED_SECTIONS.read(|x| *x);
+
+    for section in BUILTIN_SECTIONS
+        .iter()
+        .copied()
+        .chain(registered.iter().flatten().copied())
+    {
+        print_one(section)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Collects the output in a fixed buffer.
+    struct Buffer {
+        data: [u8; 128],
+        len: usize,
+    }
+
+    impl fmt::Write for Buffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            if end > self.data.len() {
+                return Err(fmt::Error);
+            }
+
+            self.data[self.len..end].copy_from_slice(s.as_bytes());
+            self.len = end;
+
+            Ok(())
+        }
+    }
+
+    fn render_test(out: &mut dyn fmt::Write) -> fmt::Result {
+        write!(out, "answer {}", 42)
+    }
+
+    /// Check that sections can be rendered on their own, and that names are unique.
+    #[kernel_test]
+    fn sections_render_individually() {
+        let mut buf = Buffer {
+            data: [0; 128],
+            len: 0,
+        };
+
+        render_section("Kernel", &mut buf).unwrap();
+        let out = core::str::from_utf8(&buf.data[..buf.len]).unwrap();
+        assert!(out.starts_with(crate::version()));
+
+        register_section(("Test", render_test)).unwrap();
+        assert!(register_section(("Test", render_test)).is_err());
+        assert!(register_section(("Kernel", render_test)).is_err());
+
+        buf.len = 0;
+        render_section("Test", &mut buf).unwrap();
+        assert_eq!(&buf.data[..buf.len], b"answer 42");
+
+        assert!(render_section("Nonexistent", &mut buf).is_err());
+    }
+}
@@ -0,0 +1,316 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! In-kernel micro-benchmarks.
+//!
+//! Makes the effect of caches, the MMU and the drivers measurable. Each benchmark prints exactly
+//! one line in a fixed format, so that runs can be compared by scripts:
+//!
+//! ```text
+//! bench <name> <value> <unit>
+//! bench <name> error <reason>
+//! ```
+//!
+//! `<value>` is an integer, and `<unit>` is either `B/s` or `ops/s`. Running all benchmarks ends
+//! with one line per core that reports how busy it was during the run:
+//!
+//! ```text
+//! bench busy_core<id> <value> %
+//! ```
+
+use crate::{
+    bsp, console, cpu, exception,
+    memory::{
+        mmu,
+        mmu::{AccessPermissions, AttributeFields, MemAttributes, PageSliceDescriptor},
+        Address, Virtual,
+    },
+    monitor, println,
+    synchronization::{interface::Mutex, interface::ReadWriteEx, IRQSafeNullLock, InitStateLock},
+    time,
+    time::interface::TimeManager,
+};
+use core::{fmt, time::Duration};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Size of the memcpy window in pages. Copying between its halves misses in all cache levels.
+const WINDOW_NUM_PAGES: usize = 32;
+
+const MEMCPY_ROUNDS: u64 = 16;
+const UART_NUM_BYTES: usize = 4096;
+const LOCK_ITERATIONS: u64 = 100_000;
+const EXCEPTION_ITERATIONS: u64 = 10_000;
+const TRANSLATION_ITERATIONS: u64 = 10_000;
+
+const NS_PER_S: u128 = 1_000_000_000;
+
+type Benchmark = fn() -> Result<BenchResult, &'static str>;
+
+const BENCHMARKS: [(&str, Benchmark); 5] = [
+    ("memcpy", memcpy_bandwidth),
+    ("uart_tx", uart_throughput),
+    ("lock", lock_latency),
+    ("exception", exception_round_trip),
+    ("translation", translation_overhead),
+];
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The unit of a benchmark result.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Unit {
+    /// Bytes per second.
+    BytesPerSec,
+
+    /// Operations per second.
+    OpsPerSec,
+}
+
+/// The outcome of one benchmark.
+#[derive(Copy, Clone, Debug)]
+pub struct BenchResult {
+    /// The benchmark's name.
+    pub name: &'static str,
+
+    /// The measured rate.
+    pub value: u64,
+
+    /// The rate's unit.
+    pub unit: Unit,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static WINDOW: InitStateLock<Option<PageSliceDescriptor<Virtual>>> = InitStateLock::new(None);
+
+static LOCK: IRQSafeNullLock<u64> = IRQSafeNullLock::new(0);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Time `f`.
+fn measure(f: impl FnOnce()) -> Duration {
+    let start = time::time_manager().uptime();
+    f();
+
+    time::time_manager().uptime() - start
+}
+
+/// Convert `count` things in `elapsed` into a rate per second.
+fn rate(count: u64, elapsed: Duration) -> u64 {
+    let ns = elapsed.as_nanos().max(1);
+
+    ((count as u128) * NS_PER_S / ns) as u64
+}
+
+impl BenchResult {
+    fn new(name: &'static str, count: u64, elapsed: Duration, unit: Unit) -> Self {
+        Self {
+            name,
+            value: rate(count, elapsed),
+            unit,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Unit::BytesPerSec => "B/s",
+            Unit::OpsPerSec => "ops/s",
+        })
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bench {} {} {}", self.name, self.value, self.unit)
+    }
+}
+
+/// Map the window for the memcpy benchmark.
+///
+/// The window is taken from the end of the unused DRAM, leaving out the last page, which is used by
+/// fault injection.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init.
+/// - The window's DRAM is assumed to be unused otherwise.
+pub unsafe fn init() -> Result<(), &'static str> {
+    let attr = AttributeFields {
+        mem_attributes: MemAttributes::CacheableDRAM,
+        acc_perms: AccessPermissions::ReadWrite,
+        execute_never: true,
+    };
+
+    let phys_unused = bsp::memory::mmu::phys_unused_dram_page_desc();
+    if phys_unused.num_pages() < WINDOW_NUM_PAGES + 1 {
+        return Err("Not enough unused DRAM for the benchmark window");
+    }
+
+    let granule = bsp::memory::mmu::KernelGranule::SIZE;
+    let phys_pages = PageSliceDescriptor::from_addr(
+        phys_unused.end_addr() - (WINDOW_NUM_PAGES + 1) * granule,
+        WINDOW_NUM_PAGES,
+    );
+    let virt_pages = mmu::kernel_alloc_window(WINDOW_NUM_PAGES)?;
+    mmu::kernel_map_window(&virt_pages, &phys_pages, &attr)?;
+    mmu::kernel_add_mapping_record("Benchmark window", &virt_pages, &phys_pages, &attr);
+
+    WINDOW.write(|x| *x = Some(virt_pages));
+
+    Ok(())
+}
+
+/// Copy between the two halves of the window. Needs `init()`.
+pub fn memcpy_bandwidth() -> Result<BenchResult, &'static str> {
+    let window = WINDOW
+        .read(|x| *x)
+        .ok_or("Benchmark window not initialized")?;
+
+    let half = window.size() / 2;
+    let src = window.start_addr().into_usize() as *const u8;
+    let dst = (window.start_addr().into_usize() + half) as *mut u8;
+
+    let elapsed = measure(|| {
+        for _ in 0..MEMCPY_ROUNDS {
+            unsafe { core::ptr::copy_nonoverlapping(src, dst, half) };
+        }
+    });
+
+    Ok(BenchResult::new(
+        "memcpy",
+        MEMCPY_ROUNDS * half as u64,
+        elapsed,
+        Unit::BytesPerSec,
+    ))
+}
+
+/// Send a block of dots to the console and wait until they left the UART.
+pub fn uart_throughput() -> Result<BenchResult, &'static str> {
+    use console::interface::Write;
+
+    let mut line = [b'.'; 64];
+    line[63] = b'\n';
+
+    let console = bsp::console::console();
+    console.flush();
+
+    let elapsed = measure(|| {
+        for _ in 0..(UART_NUM_BYTES / line.len()) {
+            console.write_array(&line);
+        }
+        console.flush();
+    });
+
+    Ok(BenchResult::new(
+        "uart_tx",
+        UART_NUM_BYTES as u64,
+        elapsed,
+        Unit::BytesPerSec,
+    ))
+}
+
+/// Acquire and release an IRQ-safe lock.
+pub fn lock_latency() -> Result<BenchResult, &'static str> {
+    let elapsed = measure(|| {
+        for _ in 0..LOCK_ITERATIONS {
+            LOCK.lock(|x| *x += 1);
+        }
+    });
+
+    Ok(BenchResult::new(
+        "lock",
+        LOCK_ITERATIONS,
+        elapsed,
+        Unit::OpsPerSec,
+    ))
+}
+
+/// Enter and leave the synchronous exception handler.
+pub fn exception_round_trip() -> Result<BenchResult, &'static str> {
+    let elapsed = measure(|| {
+        for _ in 0..EXCEPTION_ITERATIONS {
+            exception::null_exception();
+        }
+    });
+
+    Ok(BenchResult::new(
+        "exception",
+        EXCEPTION_ITERATIONS,
+        elapsed,
+        Unit::OpsPerSec,
+    ))
+}
+
+/// Translate a kernel code address with the MMU.
+pub fn translation_overhead() -> Result<BenchResult, &'static str> {
+    let addr = Address::<Virtual>::new(translation_overhead as usize);
+    let mut result = Ok(());
+
+    let elapsed = measure(|| {
+        for _ in 0..TRANSLATION_ITERATIONS {
+            if mmu::try_virt_to_phys(addr).is_err() {
+                result = Err("Kernel code is not mapped");
+            }
+        }
+    });
+    result?;
+
+    Ok(BenchResult::new(
+        "translation",
+        TRANSLATION_ITERATIONS,
+        elapsed,
+        Unit::OpsPerSec,
+    ))
+}
+
+/// The names of all benchmarks.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    BENCHMARKS.iter().map(|(name, _)| *name)
+}
+
+/// Run the benchmark called `name`, and print its result.
+pub fn run(name: &str) -> Result<(), &'static str> {
+    let (name, benchmark) = BENCHMARKS
+        .iter()
+        .find(|(x, _)| *x == name)
+        .ok_or("Unknown benchmark")?;
+
+    match benchmark() {
+        Ok(x) => println!("{}", x),
+        Err(x) => println!("bench {} error {}", name, x),
+    }
+
+    Ok(())
+}
+
+/// Run and print all benchmarks, and the utilization of each core during the run.
+pub fn run_all() {
+    let earlier = cpu::idle::snapshot();
+
+    for name in names() {
+        monitor::safe_point();
+
+        // Names come from the table, so the lookup can not fail.
+        let _ = run(name);
+    }
+
+    for (core_id, x) in cpu::idle::utilization(&earlier).iter().enumerate() {
+        println!("bench busy_core{} {} %", core_id, x.busy_percent);
+    }
+}
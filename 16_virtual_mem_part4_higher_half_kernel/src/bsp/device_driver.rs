@@ -14,3 +14,4 @@ mod common;
 pub use arm::*;
 #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
 pub use bcm::*;
+pub use common::{ArmIRQ, IRQNumber, LocalIRQ, PeripheralIRQ};
@@ -79,21 +79,54 @@
 mod gicc;
 mod gicd;
 
-use crate::{bsp, cpu, driver, exception, memory, synchronization, synchronization::InitStateLock};
-use core::sync::atomic::{AtomicBool, Ordering};
+use crate::{
+    bsp::device_driver::IRQNumber, cpu, driver, exception, memory, synchronization,
+    synchronization::InitStateLock,
+};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
 //--------------------------------------------------------------------------------------------------
 
-type HandlerTable = [Option<exception::asynchronous::IRQDescriptor>; GICv2::NUM_IRQS];
+type HandlerTable = [exception::asynchronous::IRQHandlerSlot; GICv2::NUM_IRQS];
+
+/// The GIC's interrupt ID.
+type InterruptID = exception::asynchronous::IRQNumber<{ GICv2::MAX_IRQ_NUMBER }>;
+
+/// PPI IDs of the core timers, indexed by their local IRQ number: CNTPS, CNTPNS, CNTHP and CNTV.
+const CORE_TIMER_PPI_IDS: [usize; 4] = [29, 30, 26, 27];
+
+/// SPI ID of the boot core's PMU interrupt, as wired on the BCM2711. The PMU interrupts of the other
+/// cores follow it, but are not supported, since all SPIs are routed to the boot core.
+const BOOT_CORE_PMU_SPI_ID: usize = 48;
+
+/// The local IRQ number of the PMU interrupt.
+const PMU_LOCAL_IRQ_NUMBER: usize = 9;
+
+/// ID of the first ARM-side peripheral's SPI, as wired on the BCM2711.
+const ARM_SPI_BASE_ID: usize = 64;
+
+/// ID of the first VideoCore peripheral's SPI, as wired on the BCM2711.
+const PERIPHERAL_SPI_BASE_ID: usize = 96;
+
+/// JEP106 code of ARM, the implementer of the BCM2711's GIC-400.
+const IMPLEMENTER_ARM: u32 = 0x43B;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: exception::asynchronous::IRQHandlerSlot =
+    exception::asynchronous::IRQHandlerSlot::new();
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
-/// Used for the associated type of trait [`exception::asynchronous::interface::IRQManager`].
-pub type IRQNumber = exception::asynchronous::IRQNumber<{ GICv2::MAX_IRQ_NUMBER }>;
+/// The configuration of the GIC, as saved by `GICv2::save_state()`.
+#[derive(Copy, Clone)]
+pub struct GICv2State {
+    gicd: gicd::GICDState,
+    gicc: gicc::GICCState,
+}
 
 /// Representation of the GIC.
 pub struct GICv2 {
@@ -109,10 +142,29 @@ pub struct GICv2 {
     /// Have the MMIO regions been remapped yet?
     is_mmio_remapped: AtomicBool,
 
+    /// The Distributor's virtual start address after remapping.
+    virt_gicd_start_addr: AtomicUsize,
+
     /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
     handler_table: InitStateLock<HandlerTable>,
 }
 
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Translate to the GIC's interrupt ID. `None` if the interrupt is not wired to the GIC.
+fn interrupt_id(irq: IRQNumber) -> Option<InterruptID> {
+    let id = match irq {
+        IRQNumber::Local(x) if x.get() == PMU_LOCAL_IRQ_NUMBER => BOOT_CORE_PMU_SPI_ID,
+        IRQNumber::Local(x) => *CORE_TIMER_PPI_IDS.get(x.get())?,
+        IRQNumber::Arm(x) => ARM_SPI_BASE_ID + x.get(),
+        IRQNumber::Peripheral(x) => PERIPHERAL_SPI_BASE_ID + x.get(),
+    };
+
+    Some(InterruptID::new(id))
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -136,9 +188,56 @@ impl GICv2 {
             gicd: gicd::GICD::new(gicd_mmio_descriptor.start_addr().into_usize()),
             gicc: gicc::GICC::new(gicc_mmio_descriptor.start_addr().into_usize()),
             is_mmio_remapped: AtomicBool::new(false),
-            handler_table: InitStateLock::new([None; Self::NUM_IRQS]),
+            virt_gicd_start_addr: AtomicUsize::new(0),
+            handler_table: InitStateLock::new([NO_HANDLER; Self::NUM_IRQS]),
+        }
+    }
+
+    /// Return the GIC to its reset state, so that nothing that was configured before reaches the
+    /// executing core: The CPU interface and the Distributor are disabled, and all interrupts are
+    /// disabled, not pending and not active, with default priorities and no targets.
+    ///
+    /// Banked interrupts are only reset for the executing core.
+    pub fn reset_to_defaults(&self) {
+        self.gicc.disable();
+        self.gicd.reset_to_defaults();
+    }
+
+    /// Save the configuration of the Distributor and the executing core's CPU interface.
+    #[cfg_attr(not(feature = "test_build"), allow(dead_code))]
+    pub fn save_state(&self) -> GICv2State {
+        GICv2State {
+            gicd: self.gicd.save_state(),
+            gicc: self.gicc.save_state(),
+        }
+    }
+
+    /// Restore a configuration that was saved with `save_state()` on the same core.
+    #[cfg_attr(not(feature = "test_build"), allow(dead_code))]
+    pub fn restore_state(&self, state: &GICv2State) {
+        self.gicd.restore_state(&state.gicd);
+        self.gicc.restore_state(&state.gicc);
+    }
+
+    /// The GIC's interrupt ID of an IRQ, if it is wired to the GIC.
+    #[cfg(feature = "test_build")]
+    pub fn native_irq_number(irq: IRQNumber) -> Option<usize> {
+        interrupt_id(irq).map(|x| x.get())
+    }
+
+    /// Make an interrupt pending, as if its source had signaled it.
+    #[cfg(feature = "test_build")]
+    pub fn set_pending(&self, irq_number: IRQNumber) {
+        if let Some(id) = interrupt_id(irq_number) {
+            self.gicd.set_pending(id);
         }
     }
+
+    /// Return true if an interrupt is pending.
+    #[cfg(feature = "test_build")]
+    pub fn is_pending(&self, irq_number: IRQNumber) -> bool {
+        interrupt_id(irq_number).map_or(false, |id| self.gicd.is_pending(id))
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -151,6 +250,10 @@ impl driver::interface::DeviceDriver for GICv2 {
         "GICv2 (ARM Generic Interrupt Controller v2)"
     }
 
+    fn is_essential(&self) -> bool {
+        true
+    }
+
     unsafe fn init(&self) -> Result<(), &'static str> {
         let remapped = self.is_mmio_remapped.load(Ordering::Relaxed);
         if !remapped {
@@ -159,6 +262,8 @@ impl driver::interface::DeviceDriver for GICv2 {
             // GICD
             virt_addr = memory::mmu::kernel_map_mmio("GICD", &self.gicd_mmio_descriptor)?;
             self.gicd.set_mmio(virt_addr.into_usize());
+            self.virt_gicd_start_addr
+                .store(virt_addr.into_usize(), Ordering::Relaxed);
 
             // GICC
             virt_addr = memory::mmu::kernel_map_mmio("GICC", &self.gicc_mmio_descriptor)?;
@@ -168,7 +273,9 @@ impl driver::interface::DeviceDriver for GICv2 {
             self.is_mmio_remapped.store(true, Ordering::Relaxed);
         }
 
-        if bsp::cpu::BOOT_CORE_ID == cpu::smp::core_id() {
+        if cpu::is_boot_core() {
+            // A chainloading kernel might have left its configuration behind.
+            self.reset_to_defaults();
             self.gicd.boot_core_init();
         }
 
@@ -177,6 +284,21 @@ impl driver::interface::DeviceDriver for GICv2 {
 
         Ok(())
     }
+
+    fn verify_mmio(&self) -> Result<(), &'static str> {
+        driver::check_mmio_id("GICD", self.gicd.implementer(), IMPLEMENTER_ARM)
+    }
+
+    /// Returns the Distributor's virtual start address. The CPU Interface is mapped separately.
+    fn virt_mmio_start_addr(&self) -> Option<usize> {
+        let addr = self.virt_gicd_start_addr.load(Ordering::Relaxed);
+
+        if addr == 0 {
+            return None;
+        }
+
+        Some(addr)
+    }
 }
 
 impl exception::asynchronous::interface::IRQManager for GICv2 {
@@ -187,26 +309,45 @@ impl exception::asynchronous::interface::IRQManager for GICv2 {
         irq_number: Self::IRQNumberType,
         descriptor: exception::asynchronous::IRQDescriptor,
     ) -> Result<(), &'static str> {
-        self.handler_table.write(|table| {
-            let irq_number = irq_number.get();
+        let id = interrupt_id(irq_number).ok_or("IRQ is not wired to the GIC")?;
 
-            if table[irq_number].is_some() {
-                return Err("IRQ handler already registered");
-            }
+        self.handler_table
+            .write(|table| table[id.get()].register(descriptor))
+    }
+
+    fn enable(&self, irq_number: Self::IRQNumberType) {
+        if let Some(id) = interrupt_id(irq_number) {
+            self.gicd.enable(id);
+        }
+    }
 
-            table[irq_number] = Some(descriptor);
+    fn disable(&self, irq_number: Self::IRQNumberType) {
+        if let Some(id) = interrupt_id(irq_number) {
+            self.gicd.disable(id);
+        }
+    }
 
-            Ok(())
-        })
+    fn set_target_cores(
+        &self,
+        irq_number: Self::IRQNumberType,
+        core_mask: usize,
+    ) -> Result<(), &'static str> {
+        exception::asynchronous::check_target_cores(core_mask)?;
+        let id = interrupt_id(irq_number).ok_or("IRQ is not wired to the GIC")?;
+
+        // On the BCM2711, the CPU interface numbers are the core ids.
+        self.gicd.set_targets(id, core_mask as u8)
     }
 
-    fn enable(&self, irq_number: Self::IRQNumberType) {
-        self.gicd.enable(irq_number);
+    fn local_core_init(&self) {
+        self.gicc.priority_accept_all();
+        self.gicc.enable();
     }
 
     fn handle_pending_irqs<'irq_context>(
         &'irq_context self,
         ic: &exception::asynchronous::IRQContext<'irq_context>,
+        context: &exception::ExceptionContext,
     ) {
         // Extract the highest priority pending IRQ number from the Interrupt Acknowledge Register
         // (IAR).
@@ -217,17 +358,15 @@ impl exception::asynchronous::interface::IRQManager for GICv2 {
             return;
         }
 
-        // Call the IRQ handler. Panic if there is none.
-        self.handler_table.read(|table| {
-            match table[irq_number] {
-                None => panic!("No handler registered for IRQ {}", irq_number),
-                Some(descriptor) => {
-                    // Call the IRQ handler. Panics on failure.
-                    descriptor.handler.handle().expect("Error handling IRQ");
-                }
-            }
+        // Call the IRQ handler, if there is one.
+        let outcome = self.handler_table.read(|table| {
+            exception::asynchronous::handle_irq(ic, &table[irq_number], "", irq_number, context)
         });
 
+        if outcome == exception::asynchronous::IRQOutcome::Mask {
+            self.gicd.disable(InterruptID::new(irq_number));
+        }
+
         // Signal completion of handling.
         self.gicc.mark_comleted(irq_number as u32, ic);
     }
@@ -235,14 +374,53 @@ impl exception::asynchronous::interface::IRQManager for GICv2 {
     fn print_handler(&self) {
         use crate::info;
 
-        info!("      Peripheral handler:");
-
         self.handler_table.read(|table| {
-            for (i, opt) in table.iter().skip(32).enumerate() {
-                if let Some(handler) = opt {
+            info!("      Private handler:");
+            for (i, slot) in table.iter().take(32).enumerate() {
+                if let Some(handler) = slot.descriptor() {
+                    info!("            {: >3}. {}", i, handler.name);
+                }
+            }
+
+            info!("      Peripheral handler:");
+            for (i, slot) in table.iter().skip(32).enumerate() {
+                if let Some(handler) = slot.descriptor() {
                     info!("            {: >3}. {}", i + 32, handler.name);
                 }
             }
         });
     }
+
+    fn handler_health(&self, f: &mut dyn FnMut(&exception::asynchronous::IRQHandlerHealthReport)) {
+        self.handler_table
+            .read(|table| table.iter().filter_map(|x| x.health()).for_each(|x| f(&x)));
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsp::device_driver::{ArmIRQ, LocalIRQ, PeripheralIRQ};
+    use test_macros::kernel_test;
+
+    /// Check the translation into the BCM2711's interrupt IDs.
+    #[kernel_test]
+    fn interrupt_id_translation_works() {
+        let id = |irq| interrupt_id(irq).map(|x| x.get());
+
+        // CNTV.
+        assert_eq!(id(IRQNumber::Local(LocalIRQ::new(3))), Some(27));
+        // Mailbox.
+        assert_eq!(id(IRQNumber::Arm(ArmIRQ::new(1))), Some(65));
+        // PL011 UART.
+        assert_eq!(id(IRQNumber::Peripheral(PeripheralIRQ::new(57))), Some(153));
+        // PMU of the boot core.
+        assert_eq!(id(IRQNumber::Local(LocalIRQ::new(9))), Some(48));
+        // The local GPU interrupt does not exist on the GIC.
+        assert_eq!(id(IRQNumber::Local(LocalIRQ::new(8))), None);
+    }
 }
@@ -56,6 +56,13 @@ type Registers = MMIODerefWrapper<RegisterBlock>;
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// The configuration of the executing core's CPU interface, as saved by `GICC::save_state()`.
+#[derive(Copy, Clone)]
+pub struct GICCState {
+    ctlr: u32,
+    pmr: u32,
+}
+
 /// Representation of the GIC CPU interface.
 pub struct GICC {
     registers: InitStateLock<Registers>,
@@ -112,6 +119,36 @@ impl GICC {
         });
     }
 
+    /// Disable the interface - stop accepting IRQs.
+    ///
+    /// # Safety
+    ///
+    /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
+    ///   of `&mut self`.
+    pub fn disable(&self) {
+        self.registers.read(|regs| {
+            regs.CTLR.write(CTLR::Enable::CLEAR);
+        });
+    }
+
+    /// Save the configuration of the executing core's interface.
+    #[cfg_attr(not(feature = "test_build"), allow(dead_code))]
+    pub fn save_state(&self) -> GICCState {
+        self.registers.read(|regs| GICCState {
+            ctlr: regs.CTLR.get(),
+            pmr: regs.PMR.get(),
+        })
+    }
+
+    /// Restore a configuration that was saved with `save_state()` on the same core.
+    #[cfg_attr(not(feature = "test_build"), allow(dead_code))]
+    pub fn restore_state(&self, state: &GICCState) {
+        self.registers.read(|regs| {
+            regs.PMR.set(state.pmr);
+            regs.CTLR.set(state.ctlr);
+        });
+    }
+
     /// Extract the number of the highest-priority pending IRQ.
     ///
     /// Can only be called from IRQ context, which is ensured by taking an `IRQContext` token.
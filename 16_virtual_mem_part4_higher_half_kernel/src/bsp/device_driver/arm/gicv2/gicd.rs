@@ -31,6 +31,14 @@ register_bitfields! {
         ITLinesNumber OFFSET(0)  NUMBITS(5) []
     ],
 
+    /// Distributor Implementer Identification Register
+    IIDR [
+        ProductID   OFFSET(24) NUMBITS(8)  [],
+        Variant     OFFSET(16) NUMBITS(4)  [],
+        Revision    OFFSET(12) NUMBITS(4)  [],
+        Implementer OFFSET(0)  NUMBITS(12) []
+    ],
+
     /// Interrupt Processor Targets Registers
     ITARGETSR [
         Offset3 OFFSET(24) NUMBITS(8) [],
@@ -45,9 +53,20 @@ register_structs! {
     SharedRegisterBlock {
         (0x000 => CTLR: ReadWrite<u32, CTLR::Register>),
         (0x004 => TYPER: ReadOnly<u32, TYPER::Register>),
-        (0x008 => _reserved1),
+        (0x008 => IIDR: ReadOnly<u32, IIDR::Register>),
+        (0x00C => _reserved1),
         (0x104 => ISENABLER: [ReadWrite<u32>; 31]),
-        (0x108 => _reserved2),
+        (0x180 => _reserved2),
+        (0x184 => ICENABLER: [ReadWrite<u32>; 31]),
+        (0x200 => _reserved3),
+        (0x204 => ISPENDR: [ReadWrite<u32>; 31]),
+        (0x280 => _reserved4),
+        (0x284 => ICPENDR: [ReadWrite<u32>; 31]),
+        (0x300 => _reserved5),
+        (0x384 => ICACTIVER: [ReadWrite<u32>; 31]),
+        (0x400 => _reserved6),
+        (0x420 => IPRIORITYR: [ReadWrite<u32>; 248]),
+        (0x800 => _reserved7),
         (0x820 => ITARGETSR: [ReadWrite<u32, ITARGETSR::Register>; 248]),
         (0x824 => @END),
     }
@@ -59,6 +78,16 @@ register_structs! {
         (0x000 => _reserved1),
         (0x100 => ISENABLER: ReadWrite<u32>),
         (0x104 => _reserved2),
+        (0x180 => ICENABLER: ReadWrite<u32>),
+        (0x184 => _reserved3),
+        (0x200 => ISPENDR: ReadWrite<u32>),
+        (0x204 => _reserved4),
+        (0x280 => ICPENDR: ReadWrite<u32>),
+        (0x284 => _reserved5),
+        (0x380 => ICACTIVER: ReadWrite<u32>),
+        (0x384 => _reserved6),
+        (0x400 => IPRIORITYR: [ReadWrite<u32>; 8]),
+        (0x420 => _reserved7),
         (0x800 => ITARGETSR: [ReadOnly<u32, ITARGETSR::Register>; 8]),
         (0x804 => @END),
     }
@@ -70,10 +99,31 @@ type SharedRegisters = MMIODerefWrapper<SharedRegisterBlock>;
 /// Abstraction for the banked parts of the associated MMIO registers.
 type BankedRegisters = MMIODerefWrapper<BankedRegisterBlock>;
 
+/// The number of SPI enable registers that are saved. Enough for the IRQ numbers that the driver
+/// supports, see `GICv2::MAX_IRQ_NUMBER`.
+const NUM_SAVED_ENABLE_REGS: usize = 9;
+
+/// The number of SPI priority and target registers that are saved. Four IRQs per register.
+const NUM_SAVED_BYTE_REGS: usize = NUM_SAVED_ENABLE_REGS * 8;
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// The configuration of the Distributor, as saved by `GICD::save_state()`.
+///
+/// Pending and active states are not part of it. They are transient, and whoever saves the state is
+/// expected to have quiesced the IRQs before.
+#[derive(Copy, Clone)]
+pub struct GICDState {
+    ctlr: u32,
+    banked_enable: u32,
+    banked_priority: [u32; 8],
+    spi_enable: [u32; NUM_SAVED_ENABLE_REGS],
+    spi_priority: [u32; NUM_SAVED_BYTE_REGS],
+    spi_target: [u32; NUM_SAVED_BYTE_REGS],
+}
+
 /// Representation of the GIC Distributor.
 pub struct GICD {
     /// Access to shared registers is guarded with a lock.
@@ -112,6 +162,31 @@ impl SharedRegisters {
         // Rust automatically inserts slice range sanity check, i.e. max >= min.
         &self.ITARGETSR[0..spi_itargetsr_max_index]
     }
+
+    /// Return the number of implemented SPI registers that have one bit per IRQ.
+    #[inline(always)]
+    fn num_spi_bit_regs(&mut self) -> usize {
+        (self.num_irqs() >> 5) - 1
+    }
+
+    /// Return the number of implemented SPI registers that have one byte per IRQ.
+    #[inline(always)]
+    fn num_spi_byte_regs(&mut self) -> usize {
+        (self.num_irqs() - 32) >> 2
+    }
+}
+
+impl GICDState {
+    const fn new() -> Self {
+        Self {
+            ctlr: 0,
+            banked_enable: 0,
+            banked_priority: [0; 8],
+            spi_enable: [0; NUM_SAVED_ENABLE_REGS],
+            spi_priority: [0; NUM_SAVED_BYTE_REGS],
+            spi_target: [0; NUM_SAVED_BYTE_REGS],
+        }
+    }
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -151,6 +226,12 @@ impl GICD {
             .read(|regs| regs.ITARGETSR[0].read(ITARGETSR::Offset0))
     }
 
+    /// Return the JEP106 code of the distributor's implementer.
+    pub fn implementer(&self) -> u32 {
+        self.shared_registers
+            .lock(|regs| regs.IIDR.read(IIDR::Implementer))
+    }
+
     /// Route all SPIs to the boot core and enable the distributor.
     pub fn boot_core_init(&self) {
         assert!(
@@ -175,8 +256,134 @@ impl GICD {
         });
     }
 
+    /// Return the Distributor to its reset state: Disabled, all interrupts disabled, not pending, not
+    /// active, with the highest priority and no target.
+    ///
+    /// The banked registers are reset for the executing core only.
+    pub fn reset_to_defaults(&self) {
+        self.shared_registers.lock(|regs| {
+            regs.CTLR.write(CTLR::Enable::CLEAR);
+
+            for i in 0..regs.num_spi_bit_regs() {
+                regs.ICENABLER[i].set(u32::MAX);
+                regs.ICPENDR[i].set(u32::MAX);
+                regs.ICACTIVER[i].set(u32::MAX);
+            }
+
+            for i in 0..regs.num_spi_byte_regs() {
+                regs.IPRIORITYR[i].set(0);
+                regs.ITARGETSR[i].set(0);
+            }
+        });
+
+        self.banked_registers.read(|regs| {
+            regs.ICENABLER.set(u32::MAX);
+            regs.ICPENDR.set(u32::MAX);
+            regs.ICACTIVER.set(u32::MAX);
+
+            for i in regs.IPRIORITYR.iter() {
+                i.set(0);
+            }
+        });
+    }
+
+    /// Save the configuration of the Distributor and of the executing core's banked interrupts.
+    #[cfg_attr(not(feature = "test_build"), allow(dead_code))]
+    pub fn save_state(&self) -> GICDState {
+        let mut state = GICDState::new();
+
+        self.shared_registers.lock(|regs| {
+            state.ctlr = regs.CTLR.get();
+
+            let num_bit_regs = regs.num_spi_bit_regs().min(NUM_SAVED_ENABLE_REGS);
+            for (i, x) in state.spi_enable[..num_bit_regs].iter_mut().enumerate() {
+                *x = regs.ISENABLER[i].get();
+            }
+
+            let num_byte_regs = regs.num_spi_byte_regs().min(NUM_SAVED_BYTE_REGS);
+            for i in 0..num_byte_regs {
+                state.spi_priority[i] = regs.IPRIORITYR[i].get();
+                state.spi_target[i] = regs.ITARGETSR[i].get();
+            }
+        });
+
+        self.banked_registers.read(|regs| {
+            state.banked_enable = regs.ISENABLER.get();
+
+            for (x, reg) in state.banked_priority.iter_mut().zip(regs.IPRIORITYR.iter()) {
+                *x = reg.get();
+            }
+        });
+
+        state
+    }
+
+    /// Restore a configuration that was saved with `save_state()`.
+    ///
+    /// The Distributor is enabled last, so that no interrupt is forwarded with a half-restored
+    /// configuration.
+    #[cfg_attr(not(feature = "test_build"), allow(dead_code))]
+    pub fn restore_state(&self, state: &GICDState) {
+        self.banked_registers.read(|regs| {
+            for (x, reg) in state.banked_priority.iter().zip(regs.IPRIORITYR.iter()) {
+                reg.set(*x);
+            }
+
+            regs.ICENABLER.set(!state.banked_enable);
+            regs.ISENABLER.set(state.banked_enable);
+        });
+
+        self.shared_registers.lock(|regs| {
+            regs.CTLR.write(CTLR::Enable::CLEAR);
+
+            let num_byte_regs = regs.num_spi_byte_regs().min(NUM_SAVED_BYTE_REGS);
+            for i in 0..num_byte_regs {
+                regs.IPRIORITYR[i].set(state.spi_priority[i]);
+                regs.ITARGETSR[i].set(state.spi_target[i]);
+            }
+
+            let num_bit_regs = regs.num_spi_bit_regs().min(NUM_SAVED_ENABLE_REGS);
+            for (i, x) in state.spi_enable[..num_bit_regs].iter().enumerate() {
+                regs.ICENABLER[i].set(!*x);
+                regs.ISENABLER[i].set(*x);
+            }
+
+            regs.CTLR.set(state.ctlr);
+        });
+    }
+
+    /// Make an interrupt pending, as if its source had signaled it.
+    #[cfg(feature = "test_build")]
+    pub fn set_pending(&self, irq_num: super::InterruptID) {
+        let irq_num = irq_num.get();
+        let bit: u32 = 1u32 << (irq_num % 32);
+
+        match irq_num {
+            0..=31 => self.banked_registers.read(|regs| regs.ISPENDR.set(bit)),
+            _ => self
+                .shared_registers
+                .lock(|regs| regs.ISPENDR[(irq_num >> 5) - 1].set(bit)),
+        }
+    }
+
+    /// Return true if an interrupt is pending.
+    #[cfg(feature = "test_build")]
+    pub fn is_pending(&self, irq_num: super::InterruptID) -> bool {
+        let irq_num = irq_num.get();
+        let bit: u32 = 1u32 << (irq_num % 32);
+
+        let reg = match irq_num {
+            0..=31 => self.banked_registers.read(|regs| regs.ISPENDR.get()),
+            _ => self
+                .shared_registers
+                .lock(|regs| regs.ISPENDR[(irq_num >> 5) - 1].get()),
+        };
+
+        reg & bit != 0
+    }
+
     /// Enable an interrupt.
-    pub fn enable(&self, irq_num: super::IRQNumber) {
+    pub fn enable(&self, irq_num: super::InterruptID) {
         let irq_num = irq_num.get();
 
         // Each bit in the u32 enable register corresponds to one IRQ number. Shift right by 5
@@ -202,4 +409,53 @@ impl GICD {
             }
         }
     }
+
+    /// Route a shared interrupt to the CPU interfaces in `target_mask`.
+    pub fn set_targets(
+        &self,
+        irq_num: super::InterruptID,
+        target_mask: u8,
+    ) -> Result<(), &'static str> {
+        let irq_num = irq_num.get();
+        if irq_num < 32 {
+            return Err("Private interrupts can not be routed");
+        }
+
+        // Each ITARGETSR holds the targets of four interrupts, one byte each. The shared array
+        // starts with interrupt 32.
+        let reg_index = (irq_num - 32) >> 2;
+        let shift = (irq_num % 4) * 8;
+
+        self.shared_registers.lock(|regs| {
+            if reg_index >= regs.num_spi_byte_regs() {
+                return Err("Interrupt not implemented by the GIC");
+            }
+
+            let reg = &regs.ITARGETSR[reg_index];
+            let value = (reg.get() & !(0xFF << shift)) | ((target_mask as u32) << shift);
+            reg.set(value);
+
+            Ok(())
+        })
+    }
+
+    /// Disable an interrupt.
+    pub fn disable(&self, irq_num: super::InterruptID) {
+        let irq_num = irq_num.get();
+
+        // Same layout as ISENABLER. Writing a 1 clears the enable bit, zeros have no effect.
+        let disable_reg_index = irq_num >> 5;
+        let disable_bit: u32 = 1u32 << (irq_num % 32);
+
+        match irq_num {
+            // Private.
+            0..=31 => self
+                .banked_registers
+                .read(|regs| regs.ICENABLER.set(disable_bit)),
+            // Shared.
+            _ => self
+                .shared_registers
+                .lock(|regs| regs.ICENABLER[disable_reg_index - 1].set(disable_bit)),
+        }
+    }
 }
@@ -4,12 +4,22 @@
 
 //! BCM driver top level.
 
+mod bcm2xxx_clock_manager;
+mod bcm2xxx_framebuffer;
 mod bcm2xxx_gpio;
 #[cfg(feature = "bsp_rpi3")]
 mod bcm2xxx_interrupt_controller;
+mod bcm2xxx_mailbox;
 mod bcm2xxx_pl011_uart;
+mod bcm2xxx_pm_watchdog;
+mod bcm2xxx_system_timer;
 
+pub use bcm2xxx_clock_manager::*;
+pub use bcm2xxx_framebuffer::*;
 pub use bcm2xxx_gpio::*;
 #[cfg(feature = "bsp_rpi3")]
 pub use bcm2xxx_interrupt_controller::*;
+pub use bcm2xxx_mailbox::*;
 pub use bcm2xxx_pl011_uart::*;
+pub use bcm2xxx_pm_watchdog::*;
+pub use bcm2xxx_system_timer::*;
@@ -0,0 +1,451 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Clock Manager Driver.
+//!
+//! The clock manager derives the peripheral clocks, for example the PWM's, from the oscillator or
+//! one of the PLLs. Each clock has a control and a divisor register. Every write must carry a
+//! password, and neither the source nor the divisor may change while the clock generator is busy.
+//!
+//! - BCM2837 / BCM2711 Peripherals, chapter "General Purpose GPIO Clocks".
+
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper, driver, memory, poll_register, synchronization,
+    synchronization::IRQSafeNullLock, units::Hertz,
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use register::{mmio::*, register_bitfields, register_structs};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Clock manager registers.
+//
+// Only the clocks that are documented in the peripherals manual are described. The PCM and PWM
+// clocks are laid out like the general purpose clocks.
+register_bitfields! {
+    u32,
+
+    /// Clock Control
+    CTL [
+        /// Password that must accompany every write.
+        PASSWD OFFSET(24) NUMBITS(8) [
+            Password = 0x5a
+        ],
+
+        /// MASH filter stages. Zero selects integer division.
+        MASH OFFSET(9) NUMBITS(2) [
+            Integer = 0,
+            OneStage = 1
+        ],
+
+        /// The clock generator is running.
+        BUSY OFFSET(7) NUMBITS(1) [],
+
+        /// Enable the clock generator.
+        ENAB OFFSET(4) NUMBITS(1) [],
+
+        /// Clock source.
+        SRC OFFSET(0) NUMBITS(4) []
+    ],
+
+    /// Clock Divisor
+    DIV [
+        /// Password that must accompany every write.
+        PASSWD OFFSET(24) NUMBITS(8) [
+            Password = 0x5a
+        ],
+
+        /// Integer part of the divisor.
+        DIVI OFFSET(12) NUMBITS(12) [],
+
+        /// Fractional part of the divisor, in 1/4096.
+        DIVF OFFSET(0) NUMBITS(12) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => _reserved1),
+        (0x70 => GP0CTL: ReadWrite<u32, CTL::Register>),
+        (0x74 => GP0DIV: ReadWrite<u32, DIV::Register>),
+        (0x78 => GP1CTL: ReadWrite<u32, CTL::Register>),
+        (0x7C => GP1DIV: ReadWrite<u32, DIV::Register>),
+        (0x80 => GP2CTL: ReadWrite<u32, CTL::Register>),
+        (0x84 => GP2DIV: ReadWrite<u32, DIV::Register>),
+        (0x88 => _reserved2),
+        (0x98 => PCMCTL: ReadWrite<u32, CTL::Register>),
+        (0x9C => PCMDIV: ReadWrite<u32, DIV::Register>),
+        (0xA0 => PWMCTL: ReadWrite<u32, CTL::Register>),
+        (0xA4 => PWMDIV: ReadWrite<u32, DIV::Register>),
+        (0xA8 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// How long a clock generator is given to stop or start.
+const TIMEOUT: Duration = Duration::from_millis(10);
+
+/// The largest integer or fractional divisor part.
+const DIV_MAX: u32 = 0xFFF;
+
+/// Rate of the crystal oscillator.
+#[cfg(feature = "bsp_rpi3")]
+const OSCILLATOR_FREQ: Hertz = Hertz::from_khz(19_200);
+
+/// Rate of the crystal oscillator.
+#[cfg(feature = "bsp_rpi4")]
+const OSCILLATOR_FREQ: Hertz = Hertz::from_mhz(54);
+
+/// Rate of PLLD's peripheral output, as set up by the firmware.
+#[cfg(feature = "bsp_rpi3")]
+const PLLD_FREQ: Hertz = Hertz::from_mhz(500);
+
+/// Rate of PLLD's peripheral output, as set up by the firmware.
+#[cfg(feature = "bsp_rpi4")]
+const PLLD_FREQ: Hertz = Hertz::from_mhz(750);
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The clocks of the clock manager.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CmClock {
+    /// General purpose clock 0.
+    Gp0,
+
+    /// General purpose clock 1.
+    Gp1,
+
+    /// General purpose clock 2.
+    Gp2,
+
+    /// The PCM / I2S clock.
+    Pcm,
+
+    /// The PWM clock.
+    Pwm,
+}
+
+/// Sources a clock can be derived from.
+///
+/// PLLC is not offered. The firmware scales it with the VideoCore's clock, which would drag the
+/// derived clock along.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CmSource {
+    /// The crystal oscillator.
+    Oscillator,
+
+    /// PLLA's peripheral output.
+    PllA,
+
+    /// PLLD's peripheral output.
+    PllD,
+
+    /// The HDMI auxiliary clock.
+    HdmiAux,
+}
+
+pub struct ClockManagerInner {
+    registers: Registers,
+}
+
+/// Representation of the clock manager.
+pub struct ClockManager {
+    mmio_descriptor: memory::mmu::MMIODescriptor,
+    virt_mmio_start_addr: AtomicUsize,
+    inner: IRQSafeNullLock<ClockManagerInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl CmSource {
+    /// The value of the CTL register's SRC field.
+    fn to_src(self) -> u32 {
+        match self {
+            CmSource::Oscillator => 1,
+            CmSource::PllA => 4,
+            CmSource::PllD => 6,
+            CmSource::HdmiAux => 7,
+        }
+    }
+
+    /// The rate of the source, if it is fixed.
+    fn frequency(self) -> Option<Hertz> {
+        match self {
+            CmSource::Oscillator => Some(OSCILLATOR_FREQ),
+            CmSource::PllD => Some(PLLD_FREQ),
+            CmSource::PllA | CmSource::HdmiAux => None,
+        }
+    }
+
+    /// Decode the CTL register's SRC field. None for the sources that the driver does not offer.
+    fn from_src(src: u32) -> Option<Self> {
+        match src {
+            1 => Some(CmSource::Oscillator),
+            4 => Some(CmSource::PllA),
+            6 => Some(CmSource::PllD),
+            7 => Some(CmSource::HdmiAux),
+            _ => None,
+        }
+    }
+}
+
+/// Check a divisor before it is programmed.
+///
+/// A fractional divisor needs the MASH filter, which needs an integer part of at least two.
+fn check_divisor(div_int: u32, div_frac: u32) -> Result<(), &'static str> {
+    if div_int > DIV_MAX || div_frac > DIV_MAX {
+        return Err("Clock manager: Divisor out of range");
+    }
+
+    let min = if div_frac == 0 { 1 } else { 2 };
+    if div_int < min {
+        return Err("Clock manager: Divisor too small");
+    }
+
+    Ok(())
+}
+
+/// The rate that a source is divided down to. MASH_INTEGER ignores the fractional part.
+fn divided(source: Hertz, div_int: u32, div_frac: u32, mash: bool) -> Hertz {
+    if !mash {
+        return Hertz(source.0 / u64::from(div_int.max(1)));
+    }
+
+    let div = (u64::from(div_int) << 12) + u64::from(div_frac);
+
+    Hertz((source.0 << 12) / div.max(1))
+}
+
+impl ClockManagerInner {
+    fn registers_of(
+        &self,
+        clock: CmClock,
+    ) -> (
+        &ReadWrite<u32, CTL::Register>,
+        &ReadWrite<u32, DIV::Register>,
+    ) {
+        let r = &self.registers;
+
+        match clock {
+            CmClock::Gp0 => (&r.GP0CTL, &r.GP0DIV),
+            CmClock::Gp1 => (&r.GP1CTL, &r.GP1DIV),
+            CmClock::Gp2 => (&r.GP2CTL, &r.GP2DIV),
+            CmClock::Pcm => (&r.PCMCTL, &r.PCMDIV),
+            CmClock::Pwm => (&r.PWMCTL, &r.PWMDIV),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for CmClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            CmClock::Gp0 => "GP0",
+            CmClock::Gp1 => "GP1",
+            CmClock::Gp2 => "GP2",
+            CmClock::Pcm => "PCM",
+            CmClock::Pwm => "PWM",
+        })
+    }
+}
+
+impl ClockManagerInner {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+        }
+    }
+
+    /// Init code.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    pub unsafe fn init(&mut self, new_mmio_start_addr: Option<usize>) -> Result<(), &'static str> {
+        if let Some(addr) = new_mmio_start_addr {
+            self.registers = Registers::new(addr);
+        }
+
+        Ok(())
+    }
+
+    /// Derive `clock` from `source`, divided by `div_int + div_frac / 4096`.
+    ///
+    /// The clock is stopped, reprogrammed, restarted and read back. The arguments are checked
+    /// before the hardware is touched.
+    pub fn configure(
+        &mut self,
+        clock: CmClock,
+        source: CmSource,
+        div_int: u32,
+        div_frac: u32,
+    ) -> Result<(), &'static str> {
+        check_divisor(div_int, div_frac)?;
+
+        let mash = if div_frac == 0 {
+            CTL::MASH::Integer
+        } else {
+            CTL::MASH::OneStage
+        };
+        let (ctl, div) = self.registers_of(clock);
+
+        // Stop the clock generator, keeping its source, and wait until it has come to a halt.
+        ctl.write(CTL::PASSWD::Password + CTL::SRC.val(ctl.read(CTL::SRC)));
+        poll_register!(TIMEOUT, ctl, CTL::BUSY::CLEAR)
+            .map_err(|_| "Clock manager: Timeout stopping the clock")?;
+
+        div.write(DIV::PASSWD::Password + DIV::DIVI.val(div_int) + DIV::DIVF.val(div_frac));
+        ctl.write(CTL::PASSWD::Password + mash + CTL::SRC.val(source.to_src()));
+        ctl.write(CTL::PASSWD::Password + mash + CTL::SRC.val(source.to_src()) + CTL::ENAB::SET);
+
+        poll_register!(TIMEOUT, ctl, CTL::BUSY::SET)
+            .map_err(|_| "Clock manager: Timeout starting the clock")?;
+
+        if (div.read(DIV::DIVI) != div_int)
+            || (div.read(DIV::DIVF) != div_frac)
+            || (ctl.read(CTL::SRC) != source.to_src())
+        {
+            return Err("Clock manager: Read back does not match the configuration");
+        }
+
+        Ok(())
+    }
+
+    /// The rate of `clock`, if it runs and its source's rate is known.
+    pub fn frequency(&self, clock: CmClock) -> Option<Hertz> {
+        let (ctl, div) = self.registers_of(clock);
+
+        if !ctl.is_set(CTL::ENAB) {
+            return None;
+        }
+
+        let source = CmSource::from_src(ctl.read(CTL::SRC))?.frequency()?;
+
+        Some(divided(
+            source,
+            div.read(DIV::DIVI),
+            div.read(DIV::DIVF),
+            ctl.read(CTL::MASH) != 0,
+        ))
+    }
+}
+
+impl ClockManager {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct MMIO descriptors.
+    pub const unsafe fn new(mmio_descriptor: memory::mmu::MMIODescriptor) -> Self {
+        Self {
+            mmio_descriptor,
+            virt_mmio_start_addr: AtomicUsize::new(0),
+            inner: IRQSafeNullLock::new(ClockManagerInner::new(
+                mmio_descriptor.start_addr().into_usize(),
+            )),
+        }
+    }
+
+    /// Concurrency safe version of `ClockManagerInner.configure()`
+    pub fn configure(
+        &self,
+        clock: CmClock,
+        source: CmSource,
+        div_int: u32,
+        div_frac: u32,
+    ) -> Result<(), &'static str> {
+        if self.virt_mmio_start_addr().is_none() {
+            return Err("Clock manager: Not initialized");
+        }
+
+        self.inner
+            .lock(|inner| inner.configure(clock, source, div_int, div_frac))
+    }
+
+    /// Concurrency safe version of `ClockManagerInner.frequency()`
+    pub fn frequency(&self, clock: CmClock) -> Option<Hertz> {
+        self.virt_mmio_start_addr()?;
+
+        self.inner.lock(|inner| inner.frequency(clock))
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use driver::interface::DeviceDriver;
+use synchronization::interface::Mutex;
+
+impl driver::interface::DeviceDriver for ClockManager {
+    fn compatible(&self) -> &'static str {
+        "BCM Clock Manager"
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let virt_addr = memory::mmu::kernel_map_mmio(self.compatible(), &self.mmio_descriptor)?;
+
+        self.inner
+            .lock(|inner| inner.init(Some(virt_addr.into_usize())))?;
+
+        self.virt_mmio_start_addr
+            .store(virt_addr.into_usize(), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn virt_mmio_start_addr(&self) -> Option<usize> {
+        let addr = self.virt_mmio_start_addr.load(Ordering::Relaxed);
+
+        if addr == 0 {
+            return None;
+        }
+
+        Some(addr)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check that bad divisors are rejected, and that the derived rate honors the MASH filter.
+    #[kernel_test]
+    fn divisors_are_checked() {
+        assert!(check_divisor(0, 0).is_err());
+        assert!(check_divisor(1, 0).is_ok());
+        assert!(check_divisor(1, 1).is_err());
+        assert!(check_divisor(2, 2048).is_ok());
+        assert!(check_divisor(DIV_MAX + 1, 0).is_err());
+        assert!(check_divisor(2, DIV_MAX + 1).is_err());
+
+        assert_eq!(divided(Hertz(19_200_000), 2, 2048, false), Hertz(9_600_000));
+        assert_eq!(divided(Hertz(19_200_000), 2, 2048, true), Hertz(7_680_000));
+    }
+}
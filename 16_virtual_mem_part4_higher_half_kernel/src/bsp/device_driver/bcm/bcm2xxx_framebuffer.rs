@@ -0,0 +1,438 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! VideoCore Framebuffer Driver.
+//!
+//! The firmware allocates the framebuffer in the VideoCore's part of the DRAM when asked through
+//! the mailbox, and scans it out to the display. The framebuffer itself has no registers.
+//!
+//! In double-buffered mode, the buffer is requested with twice the display's height as its virtual
+//! height. The display shows the half at the virtual offset, and drawing goes to the other half.
+//! `present()` flips the halves by moving the virtual offset, which the firmware applies at the
+//! next vertical sync. If the SMI raises its vsync IRQ, `present()` waits for it, so that drawing
+//! does not start while the new back buffer is still on the display.
+
+use crate::{
+    bsp,
+    bsp::device_driver::{common::MMIODerefWrapper, tag, Mailbox},
+    cpu, driver, exception, memory,
+    memory::{Address, Physical, Virtual},
+    synchronization,
+    synchronization::IRQSafeNullLock,
+};
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use register::{mmio::*, register_structs};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Secondary memory interface registers. Only the vsync IRQ is used.
+//
+// There is no official documentation. The layout is taken from the Linux bcm2708_fb driver.
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => CS: ReadWrite<u32>),
+        (0x04 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// The interrupt bits of `CS`, which the vsync IRQ handler clears.
+const CS_INTERRUPTS: u32 = (1 << 9) | (1 << 10) | (1 << 11);
+
+/// Bits per pixel.
+const DEPTH: u32 = 32;
+
+/// The alignment of the buffer that the firmware is asked for.
+const BUFFER_ALIGN: u32 = 16;
+
+/// The bits of a VideoCore bus address that carry the ARM's physical address.
+const BUS_ADDR_MASK: u32 = 0x3FFF_FFFF;
+
+/// How long `present()` waits for the vsync IRQ. A bit more than one frame at 50 Hz.
+const VSYNC_TIMEOUT: Duration = Duration::from_millis(25);
+
+struct FramebufferInner {
+    registers: Registers,
+    info: Option<FramebufferInfo>,
+    virt_start_addr: usize,
+
+    /// The index of the buffer that is drawn to.
+    back: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The display mode that is asked for.
+#[derive(Copy, Clone, Debug)]
+pub struct FramebufferMode {
+    /// Width in pixels.
+    pub width: u32,
+
+    /// Height in pixels.
+    pub height: u32,
+
+    /// Whether to draw off-screen and flip with `present()`.
+    pub double_buffered: bool,
+}
+
+/// The framebuffer that the firmware allocated.
+#[derive(Copy, Clone, Debug)]
+pub struct FramebufferInfo {
+    /// Width in pixels.
+    pub width: u32,
+
+    /// Height in pixels.
+    pub height: u32,
+
+    /// The distance between the starts of two lines in bytes.
+    pub pitch: u32,
+
+    /// One, or two if double-buffered.
+    pub num_buffers: u32,
+}
+
+/// A buffer to draw to, handed out by `Framebuffer::draw()`.
+pub struct FramebufferSurface {
+    start: *mut u32,
+    width: usize,
+    height: usize,
+    stride: usize,
+    index: usize,
+}
+
+/// Representation of the framebuffer.
+pub struct Framebuffer {
+    mailbox: &'static Mailbox,
+    mode: FramebufferMode,
+    smi_mmio_descriptor: memory::mmu::MMIODescriptor,
+    vsync_irq_number: bsp::device_driver::IRQNumber,
+    virt_mmio_start_addr: AtomicUsize,
+    num_vsyncs: AtomicUsize,
+    inner: IRQSafeNullLock<FramebufferInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl FramebufferInner {
+    const unsafe fn new(smi_mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(smi_mmio_start_addr),
+            info: None,
+            virt_start_addr: 0,
+            back: 0,
+        }
+    }
+
+    fn surface(&self) -> Result<FramebufferSurface, &'static str> {
+        let info = self.info.ok_or("Framebuffer is not initialized")?;
+        let buffer_size = (info.pitch * info.height) as usize;
+
+        Ok(FramebufferSurface {
+            start: (self.virt_start_addr + self.back * buffer_size) as *mut u32,
+            width: info.width as usize,
+            height: info.height as usize,
+            stride: (info.pitch / 4) as usize,
+            index: self.back,
+        })
+    }
+
+    /// Show the back buffer, and draw to the other one from now on. Returns false if
+    /// single-buffered.
+    fn flip(&mut self, mailbox: &Mailbox) -> Result<bool, &'static str> {
+        let info = self.info.ok_or("Framebuffer is not initialized")?;
+        if info.num_buffers == 1 {
+            return Ok(false);
+        }
+
+        let offset = self.back as u32 * info.height;
+        mailbox.property(tag::SET_VIRTUAL_OFFSET, [0, offset])?;
+        self.back ^= 1;
+
+        Ok(true)
+    }
+}
+
+impl FramebufferSurface {
+    /// The address of the pixel at `x`, `y`.
+    fn pixel_addr(&self, x: usize, y: usize) -> Address<Virtual> {
+        Address::new(self.start.wrapping_add(y * self.stride + x) as usize)
+    }
+}
+
+impl Framebuffer {
+    /// Ask the firmware for the buffer. Returns the info and the physical start address and size.
+    fn allocate(&self) -> Result<(FramebufferInfo, Address<Physical>, usize), &'static str> {
+        let mode = &self.mode;
+        let num_buffers = if mode.double_buffered { 2 } else { 1 };
+
+        let [width, height] = self
+            .mailbox
+            .property(tag::SET_PHYSICAL_SIZE, [mode.width, mode.height])?;
+        if (width != mode.width) || (height != mode.height) {
+            return Err("Framebuffer: Firmware did not accept the resolution");
+        }
+
+        let [_, virt_height] = self
+            .mailbox
+            .property(tag::SET_VIRTUAL_SIZE, [width, height * num_buffers])?;
+        if virt_height != height * num_buffers {
+            return Err("Framebuffer: Firmware did not accept the virtual height");
+        }
+
+        let [depth] = self.mailbox.property(tag::SET_DEPTH, [DEPTH])?;
+        if depth != DEPTH {
+            return Err("Framebuffer: Firmware did not accept the depth");
+        }
+
+        self.mailbox.property(tag::SET_VIRTUAL_OFFSET, [0, 0])?;
+
+        let [bus_addr, size] = self
+            .mailbox
+            .property(tag::ALLOCATE_BUFFER, [BUFFER_ALIGN, 0])?;
+        let [pitch] = self.mailbox.property(tag::GET_PITCH, [0])?;
+
+        if (bus_addr == 0) || (pitch < width * (DEPTH / 8)) {
+            return Err("Framebuffer: Firmware did not allocate the buffer");
+        }
+        if (size as usize) < (pitch * height * num_buffers) as usize {
+            return Err("Framebuffer: Buffer is too small");
+        }
+
+        let info = FramebufferInfo {
+            width,
+            height,
+            pitch,
+            num_buffers,
+        };
+
+        Ok((
+            info,
+            Address::new((bus_addr & BUS_ADDR_MASK) as usize),
+            size as usize,
+        ))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl FramebufferSurface {
+    /// Width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The index of the buffer, which tells the two buffers of double-buffered mode apart.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Fill a rectangle. Pixels outside of the surface are skipped.
+    pub fn fill_rect(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        color: u32,
+    ) -> Result<(), &'static str> {
+        if x >= self.width {
+            return Ok(());
+        }
+        let num_cols = width.min(self.width - x);
+
+        for row in y..(y + height).min(self.height) {
+            unsafe { memory::ops::set_device_u32(self.pixel_addr(x, row), color, num_cols)? };
+        }
+
+        Ok(())
+    }
+
+    /// Draw an 8x8 glyph. Bit `n` of a row is the `n`-th pixel from the left.
+    pub fn draw_glyph(
+        &mut self,
+        x: usize,
+        y: usize,
+        glyph: &[u8; 8],
+        fg: u32,
+        bg: u32,
+    ) -> Result<(), &'static str> {
+        if (x + 8 > self.width) || (y + 8 > self.height) {
+            return Ok(());
+        }
+
+        for (i, bits) in glyph.iter().enumerate() {
+            let mut line = [0; 8 * 4];
+
+            for (j, pixel) in line.chunks_exact_mut(4).enumerate() {
+                let color = if bits & (1 << j) != 0 { fg } else { bg };
+                pixel.copy_from_slice(&color.to_ne_bytes());
+            }
+
+            unsafe { memory::ops::copy_to_device(self.pixel_addr(x, y + i), &line)? };
+        }
+
+        Ok(())
+    }
+}
+
+impl Framebuffer {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO descriptor for the SMI.
+    /// - The user must ensure to provide the correct IRQ number of the SMI.
+    pub const unsafe fn new(
+        mailbox: &'static Mailbox,
+        mode: FramebufferMode,
+        smi_mmio_descriptor: memory::mmu::MMIODescriptor,
+        vsync_irq_number: bsp::device_driver::IRQNumber,
+    ) -> Self {
+        Self {
+            mailbox,
+            mode,
+            smi_mmio_descriptor,
+            vsync_irq_number,
+            virt_mmio_start_addr: AtomicUsize::new(0),
+            num_vsyncs: AtomicUsize::new(0),
+            inner: IRQSafeNullLock::new(FramebufferInner::new(
+                smi_mmio_descriptor.start_addr().into_usize(),
+            )),
+        }
+    }
+
+    /// The framebuffer, once the firmware allocated it.
+    pub fn info(&self) -> Option<FramebufferInfo> {
+        self.inner.lock(|inner| inner.info)
+    }
+
+    /// Call `f` with the buffer that is drawn to.
+    ///
+    /// In double-buffered mode, it is not on the display until `present()`.
+    pub fn draw<R>(&self, f: impl FnOnce(&mut FramebufferSurface) -> R) -> Result<R, &'static str> {
+        self.inner
+            .lock(|inner| inner.surface().map(|mut x| f(&mut x)))
+    }
+
+    /// Show what was drawn.
+    ///
+    /// Flips the buffers in double-buffered mode, and does nothing otherwise. Unless called from
+    /// IRQ context, waits for the next vsync if the vsync IRQ was seen before, for at most
+    /// `VSYNC_TIMEOUT`.
+    pub fn present(&self) -> Result<(), &'static str> {
+        use exception::asynchronous::in_irq_context;
+
+        let num_vsyncs = self.num_vsyncs.load(Ordering::Acquire);
+
+        if !self.inner.lock(|inner| inner.flip(self.mailbox))? {
+            return Ok(());
+        }
+
+        // The wait happens with the lock released, since the lock masks the vsync IRQ.
+        if (num_vsyncs != 0) && !in_irq_context() {
+            cpu::spin_until(
+                || self.num_vsyncs.load(Ordering::Acquire) != num_vsyncs,
+                Some(VSYNC_TIMEOUT),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The number of vsync IRQs so far. Stays zero if the SMI does not raise them.
+    pub fn num_vsyncs(&self) -> usize {
+        self.num_vsyncs.load(Ordering::Relaxed)
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl driver::interface::DeviceDriver for Framebuffer {
+    fn compatible(&self) -> &'static str {
+        "BCM VideoCore Framebuffer"
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let (info, phys_start_addr, size) = self.allocate()?;
+
+        let buffer = memory::mmu::MMIODescriptor::new(phys_start_addr, size);
+        let virt_start_addr = memory::mmu::kernel_map_mmio(self.compatible(), &buffer)?;
+        let virt_addr = memory::mmu::kernel_map_mmio(self.compatible(), &self.smi_mmio_descriptor)?;
+
+        self.inner.lock(|inner| {
+            inner.registers = Registers::new(virt_addr.into_usize());
+            inner.info = Some(info);
+            inner.virt_start_addr = virt_start_addr.into_usize();
+            inner.back = if info.num_buffers == 2 { 1 } else { 0 };
+        });
+
+        self.virt_mmio_start_addr
+            .store(virt_addr.into_usize(), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
+        use bsp::exception::asynchronous::irq_manager;
+        use exception::asynchronous::{interface::IRQManager, IRQDescriptor};
+
+        let descriptor = IRQDescriptor {
+            name: "BCM VideoCore Framebuffer",
+            handler: self,
+        };
+
+        irq_manager().register_handler(self.vsync_irq_number, descriptor)?;
+        irq_manager().enable(self.vsync_irq_number);
+
+        Ok(())
+    }
+
+    fn virt_mmio_start_addr(&self) -> Option<usize> {
+        let addr = self.virt_mmio_start_addr.load(Ordering::Relaxed);
+
+        if addr == 0 {
+            return None;
+        }
+
+        Some(addr)
+    }
+
+    fn irq_numbers(&self) -> &[bsp::device_driver::IRQNumber] {
+        core::slice::from_ref(&self.vsync_irq_number)
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for Framebuffer {
+    fn handle(&self, _context: &exception::ExceptionContext) -> Result<(), &'static str> {
+        self.inner.lock(|inner| {
+            let cs = inner.registers.CS.get();
+            inner.registers.CS.set(cs & !CS_INTERRUPTS);
+        });
+        self.num_vsyncs.fetch_add(1, Ordering::Release);
+
+        Ok(())
+    }
+}
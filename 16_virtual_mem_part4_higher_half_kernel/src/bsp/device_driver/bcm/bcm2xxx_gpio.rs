@@ -5,10 +5,13 @@
 //! GPIO Driver.
 
 use crate::{
-    bsp::device_driver::common::MMIODerefWrapper, driver, memory, synchronization,
+    bsp::device_driver::common::MMIODerefWrapper, driver, info, memory, synchronization,
     synchronization::IRQSafeNullLock,
 };
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use register::{mmio::*, register_bitfields, register_structs};
 
 //--------------------------------------------------------------------------------------------------
@@ -91,26 +94,110 @@ register_bitfields! {
 register_structs! {
     #[allow(non_snake_case)]
     RegisterBlock {
-        (0x00 => _reserved1),
+        (0x00 => GPFSEL0: ReadWrite<u32>),
         (0x04 => GPFSEL1: ReadWrite<u32, GPFSEL1::Register>),
-        (0x08 => _reserved2),
+        (0x08 => GPFSEL2: ReadWrite<u32>),
+        (0x0C => GPFSEL3: ReadWrite<u32>),
+        (0x10 => GPFSEL4: ReadWrite<u32>),
+        (0x14 => GPFSEL5: ReadWrite<u32>),
+        (0x18 => _reserved1),
+        (0x1C => GPSET0: WriteOnly<u32>),
+        (0x20 => GPSET1: WriteOnly<u32>),
+        (0x24 => _reserved2),
+        (0x28 => GPCLR0: WriteOnly<u32>),
+        (0x2C => GPCLR1: WriteOnly<u32>),
+        (0x30 => _reserved3),
+        (0x34 => GPLEV0: ReadOnly<u32>),
+        (0x38 => GPLEV1: ReadOnly<u32>),
+        (0x3C => _reserved4),
         (0x94 => GPPUD: ReadWrite<u32, GPPUD::Register>),
         (0x98 => GPPUDCLK0: ReadWrite<u32, GPPUDCLK0::Register>),
-        (0x9C => _reserved3),
+        (0x9C => _reserved5),
         (0xE4 => GPIO_PUP_PDN_CNTRL_REG0: ReadWrite<u32, GPIO_PUP_PDN_CNTRL_REG0::Register>),
-        (0xE8 => @END),
+        (0xE8 => GPIO_PUP_PDN_CNTRL_REG1: ReadOnly<u32>),
+        (0xEC => GPIO_PUP_PDN_CNTRL_REG2: ReadOnly<u32>),
+        (0xF0 => GPIO_PUP_PDN_CNTRL_REG3: ReadOnly<u32>),
+        (0xF4 => @END),
     }
 }
 
 /// Abstraction for the associated MMIO registers.
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
+/// Number of pins.
+#[cfg(feature = "bsp_rpi3")]
+const NUM_PINS: u32 = 54;
+
+/// Number of pins.
+#[cfg(feature = "bsp_rpi4")]
+const NUM_PINS: u32 = 58;
+
+/// Function select value of an output pin.
+const FSEL_OUTPUT: u32 = 0b001;
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// Number of pins, as an array length.
+pub const GPIO_NUM_PINS: usize = NUM_PINS as usize;
+
+/// The function that a pin is muxed to.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GPIOFunction {
+    Input,
+    Output,
+    Alt0,
+    Alt1,
+    Alt2,
+    Alt3,
+    Alt4,
+    Alt5,
+}
+
+/// The pull resistor of a pin.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GPIOPull {
+    /// No pull resistor.
+    None,
+
+    /// Pull-up.
+    Up,
+
+    /// Pull-down.
+    Down,
+
+    /// The BCM2837 cannot read back the pull configuration. Its driver only knows the pulls that it
+    /// set itself.
+    Unknown,
+}
+
+/// The state of a pin.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct GPIOPinState {
+    /// The function the pin is muxed to.
+    pub function: GPIOFunction,
+
+    /// The level at the pin.
+    pub high: bool,
+
+    /// The pull resistor.
+    pub pull: GPIOPull,
+}
+
+/// The state of all pins at one point in time.
+#[derive(Copy, Clone)]
+pub struct GPIOSnapshot {
+    pins: [GPIOPinState; GPIO_NUM_PINS],
+}
+
 pub struct GPIOInner {
     registers: Registers,
+
+    /// The pulls set by the driver. The BCM2837's pull configuration is write-only.
+    #[cfg(feature = "bsp_rpi3")]
+    pull_shadow: [GPIOPull; GPIO_NUM_PINS],
 }
 
 // Export the inner struct so that BSPs can use it for the panic handler.
@@ -136,6 +223,9 @@ impl GPIOInner {
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
         Self {
             registers: Registers::new(mmio_start_addr),
+
+            #[cfg(feature = "bsp_rpi3")]
+            pull_shadow: [GPIOPull::Unknown; GPIO_NUM_PINS],
         }
     }
 
@@ -171,6 +261,9 @@ impl GPIOInner {
 
         self.registers.GPPUD.write(GPPUD::PUD::Off);
         self.registers.GPPUDCLK0.set(0);
+
+        self.pull_shadow[14] = GPIOPull::None;
+        self.pull_shadow[15] = GPIOPull::None;
     }
 
     /// Disable pull-up/down on pins 14 and 15.
@@ -182,6 +275,61 @@ impl GPIOInner {
         );
     }
 
+    /// The pull resistor of `pin`, as recorded by the driver.
+    #[cfg(feature = "bsp_rpi3")]
+    fn pull(&self, pin: usize) -> GPIOPull {
+        self.pull_shadow[pin]
+    }
+
+    /// The pull resistor of `pin`, as read from the hardware.
+    #[cfg(feature = "bsp_rpi4")]
+    fn pull(&self, pin: usize) -> GPIOPull {
+        let r = &self.registers;
+        let val = match pin / 16 {
+            0 => r.GPIO_PUP_PDN_CNTRL_REG0.get(),
+            1 => r.GPIO_PUP_PDN_CNTRL_REG1.get(),
+            2 => r.GPIO_PUP_PDN_CNTRL_REG2.get(),
+            _ => r.GPIO_PUP_PDN_CNTRL_REG3.get(),
+        };
+
+        match (val >> ((pin % 16) * 2)) & 0b11 {
+            0b00 => GPIOPull::None,
+            0b01 => GPIOPull::Up,
+            0b10 => GPIOPull::Down,
+            _ => GPIOPull::Unknown,
+        }
+    }
+
+    /// Read the state of all pins.
+    pub fn snapshot(&self) -> GPIOSnapshot {
+        let r = &self.registers;
+        let fsel = [
+            r.GPFSEL0.get(),
+            r.GPFSEL1.get(),
+            r.GPFSEL2.get(),
+            r.GPFSEL3.get(),
+            r.GPFSEL4.get(),
+            r.GPFSEL5.get(),
+        ];
+        let lev = [r.GPLEV0.get(), r.GPLEV1.get()];
+
+        let mut pins = [GPIOPinState {
+            function: GPIOFunction::Input,
+            high: false,
+            pull: GPIOPull::Unknown,
+        }; GPIO_NUM_PINS];
+
+        for (pin, state) in pins.iter_mut().enumerate() {
+            *state = GPIOPinState {
+                function: GPIOFunction::from_fsel(fsel[pin / 10] >> ((pin % 10) * 3)),
+                high: lev[pin / 32] & (1 << (pin % 32)) != 0,
+                pull: self.pull(pin),
+            };
+        }
+
+        GPIOSnapshot { pins }
+    }
+
     /// Map PL011 UART as standard output.
     ///
     /// TX to pin 14
@@ -199,6 +347,123 @@ impl GPIOInner {
         #[cfg(feature = "bsp_rpi4")]
         self.disable_pud_14_15_bcm2711();
     }
+
+    /// Configure `pin` as an output.
+    pub fn set_output(&mut self, pin: u32) -> Result<(), &'static str> {
+        if pin >= NUM_PINS {
+            return Err("GPIO: Pin does not exist");
+        }
+
+        let shift = (pin % 10) * 3;
+        let fsel = |val: u32| (val & !(0b111 << shift)) | (FSEL_OUTPUT << shift);
+
+        let r = &self.registers;
+        match pin / 10 {
+            0 => r.GPFSEL0.set(fsel(r.GPFSEL0.get())),
+            1 => r.GPFSEL1.set(fsel(r.GPFSEL1.get())),
+            2 => r.GPFSEL2.set(fsel(r.GPFSEL2.get())),
+            3 => r.GPFSEL3.set(fsel(r.GPFSEL3.get())),
+            4 => r.GPFSEL4.set(fsel(r.GPFSEL4.get())),
+            _ => r.GPFSEL5.set(fsel(r.GPFSEL5.get())),
+        }
+
+        Ok(())
+    }
+
+    /// Drive the output `pin` high or low.
+    pub fn set_level(&mut self, pin: u32, high: bool) -> Result<(), &'static str> {
+        if pin >= NUM_PINS {
+            return Err("GPIO: Pin does not exist");
+        }
+
+        let bit = 1 << (pin % 32);
+        let r = &self.registers;
+        match (pin / 32, high) {
+            (0, true) => r.GPSET0.set(bit),
+            (0, false) => r.GPCLR0.set(bit),
+            (_, true) => r.GPSET1.set(bit),
+            (_, false) => r.GPCLR1.set(bit),
+        }
+
+        Ok(())
+    }
+}
+
+impl GPIOFunction {
+    /// Decode the lowest three bits of a function select register field.
+    fn from_fsel(val: u32) -> Self {
+        match val & 0b111 {
+            0b000 => GPIOFunction::Input,
+            0b001 => GPIOFunction::Output,
+            0b100 => GPIOFunction::Alt0,
+            0b101 => GPIOFunction::Alt1,
+            0b110 => GPIOFunction::Alt2,
+            0b111 => GPIOFunction::Alt3,
+            0b011 => GPIOFunction::Alt4,
+            _ => GPIOFunction::Alt5,
+        }
+    }
+}
+
+impl fmt::Display for GPIOFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GPIOFunction::Input => f.pad("in"),
+            GPIOFunction::Output => f.pad("out"),
+            GPIOFunction::Alt0 => f.pad("alt0"),
+            GPIOFunction::Alt1 => f.pad("alt1"),
+            GPIOFunction::Alt2 => f.pad("alt2"),
+            GPIOFunction::Alt3 => f.pad("alt3"),
+            GPIOFunction::Alt4 => f.pad("alt4"),
+            GPIOFunction::Alt5 => f.pad("alt5"),
+        }
+    }
+}
+
+impl fmt::Display for GPIOPull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GPIOPull::None => f.pad("none"),
+            GPIOPull::Up => f.pad("up"),
+            GPIOPull::Down => f.pad("down"),
+            GPIOPull::Unknown => f.pad("?"),
+        }
+    }
+}
+
+impl fmt::Display for GPIOPinState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<4}  {:<4}  {}",
+            self.function,
+            if self.high { "high" } else { "low" },
+            self.pull
+        )
+    }
+}
+
+impl GPIOSnapshot {
+    /// The pins whose state differs from `earlier`, as `(pin, earlier state, state)`.
+    pub fn diff<'a>(
+        &'a self,
+        earlier: &'a GPIOSnapshot,
+    ) -> impl Iterator<Item = (usize, GPIOPinState, GPIOPinState)> + 'a {
+        self.pins
+            .iter()
+            .zip(earlier.pins.iter())
+            .enumerate()
+            .filter(|(_, (now, before))| now != before)
+            .map(|(pin, (now, before))| (pin, *before, *now))
+    }
+
+    /// Print a table of all pins.
+    pub fn print(&self) {
+        info!("      Pin  Func  Lvl   Pull");
+        for (pin, state) in self.pins.iter().enumerate() {
+            info!("      {:>3}  {}", pin, state);
+        }
+    }
 }
 
 impl GPIO {
@@ -219,6 +484,26 @@ impl GPIO {
     pub fn map_pl011_uart(&self) {
         self.inner.lock(|inner| inner.map_pl011_uart())
     }
+
+    /// Concurrency safe version of `GPIOInner.set_output()`
+    pub fn set_output(&self, pin: u32) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.set_output(pin))
+    }
+
+    /// Concurrency safe version of `GPIOInner.set_level()`
+    pub fn set_level(&self, pin: u32, high: bool) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.set_level(pin, high))
+    }
+
+    /// Concurrency safe version of `GPIOInner.snapshot()`
+    pub fn snapshot(&self) -> GPIOSnapshot {
+        self.inner.lock(|inner| inner.snapshot())
+    }
+
+    /// Print the function, level and pull of all pins.
+    pub fn dump_state(&self) {
+        self.snapshot().print()
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -4,9 +4,16 @@
 
 //! Interrupt Controller Driver.
 
+mod local_ic;
 mod peripheral_ic;
 
-use crate::{driver, exception, memory};
+use crate::{
+    bsp::device_driver::common::{
+        ArmIRQ, IRQNumber, LocalIRQ, PeripheralIRQ, MAX_ARM_IRQ_NUMBER, MAX_LOCAL_IRQ_NUMBER,
+        MAX_PERIPHERAL_IRQ_NUMBER,
+    },
+    driver, exception, memory,
+};
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
@@ -21,20 +28,9 @@ struct PendingIRQs {
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
-pub type LocalIRQ =
-    exception::asynchronous::IRQNumber<{ InterruptController::MAX_LOCAL_IRQ_NUMBER }>;
-pub type PeripheralIRQ =
-    exception::asynchronous::IRQNumber<{ InterruptController::MAX_PERIPHERAL_IRQ_NUMBER }>;
-
-/// Used for the associated type of trait [`exception::asynchronous::interface::IRQManager`].
-#[derive(Copy, Clone)]
-pub enum IRQNumber {
-    Local(LocalIRQ),
-    Peripheral(PeripheralIRQ),
-}
-
 /// Representation of the Interrupt Controller.
 pub struct InterruptController {
+    local: local_ic::LocalIC,
     periph: peripheral_ic::PeripheralIC,
 }
 
@@ -70,9 +66,9 @@ impl Iterator for PendingIRQs {
 //--------------------------------------------------------------------------------------------------
 
 impl InterruptController {
-    const MAX_LOCAL_IRQ_NUMBER: usize = 11;
-    const MAX_PERIPHERAL_IRQ_NUMBER: usize = 63;
-    const NUM_PERIPHERAL_IRQS: usize = Self::MAX_PERIPHERAL_IRQ_NUMBER + 1;
+    const NUM_LOCAL_IRQS: usize = MAX_LOCAL_IRQ_NUMBER + 1;
+    const NUM_PERIPHERAL_IRQS: usize = MAX_PERIPHERAL_IRQ_NUMBER + 1;
+    const NUM_ARM_IRQS: usize = MAX_ARM_IRQ_NUMBER + 1;
 
     /// Create an instance.
     ///
@@ -80,13 +76,26 @@ impl InterruptController {
     ///
     /// - The user must ensure to provide correct MMIO descriptors.
     pub const unsafe fn new(
-        _local_mmio_descriptor: memory::mmu::MMIODescriptor,
+        local_mmio_descriptor: memory::mmu::MMIODescriptor,
         periph_mmio_descriptor: memory::mmu::MMIODescriptor,
     ) -> Self {
         Self {
+            local: local_ic::LocalIC::new(local_mmio_descriptor),
             periph: peripheral_ic::PeripheralIC::new(periph_mmio_descriptor),
         }
     }
+
+    /// A number that identifies the IRQ within the controller, if the controller can handle it. The
+    /// local, the peripheral and the ARM IRQs are numbered one after the other.
+    #[cfg(feature = "test_build")]
+    pub fn native_irq_number(irq: IRQNumber) -> Option<usize> {
+        match irq {
+            IRQNumber::Local(x) if local_ic::LocalIC::is_supported(x.get()) => Some(x.get()),
+            IRQNumber::Local(_) => None,
+            IRQNumber::Peripheral(x) => Some(Self::NUM_LOCAL_IRQS + x.get()),
+            IRQNumber::Arm(x) => Some(Self::NUM_LOCAL_IRQS + Self::NUM_PERIPHERAL_IRQS + x.get()),
+        }
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -98,7 +107,12 @@ impl driver::interface::DeviceDriver for InterruptController {
         "BCM Interrupt Controller"
     }
 
+    fn is_essential(&self) -> bool {
+        true
+    }
+
     unsafe fn init(&self) -> Result<(), &'static str> {
+        self.local.init()?;
         self.periph.init()
     }
 }
@@ -112,27 +126,59 @@ impl exception::asynchronous::interface::IRQManager for InterruptController {
         descriptor: exception::asynchronous::IRQDescriptor,
     ) -> Result<(), &'static str> {
         match irq {
-            IRQNumber::Local(_) => unimplemented!("Local IRQ controller not implemented."),
+            IRQNumber::Local(lirq) => self.local.register_handler(lirq, descriptor),
             IRQNumber::Peripheral(pirq) => self.periph.register_handler(pirq, descriptor),
+            IRQNumber::Arm(airq) => self.periph.register_arm_handler(airq, descriptor),
         }
     }
 
     fn enable(&self, irq: Self::IRQNumberType) {
         match irq {
-            IRQNumber::Local(_) => unimplemented!("Local IRQ controller not implemented."),
+            IRQNumber::Local(lirq) => self.local.enable(lirq),
             IRQNumber::Peripheral(pirq) => self.periph.enable(pirq),
+            IRQNumber::Arm(airq) => self.periph.enable_arm(airq),
+        }
+    }
+
+    fn disable(&self, irq: Self::IRQNumberType) {
+        match irq {
+            IRQNumber::Local(lirq) => self.local.disable(lirq),
+            IRQNumber::Peripheral(pirq) => self.periph.disable(pirq),
+            IRQNumber::Arm(airq) => self.periph.disable_arm(airq),
         }
     }
 
+    /// The peripheral IRQs are routed by the local controller's GPU routing register, which the
+    /// driver leaves at the boot core.
+    fn set_target_cores(
+        &self,
+        _irq: Self::IRQNumberType,
+        _core_mask: usize,
+    ) -> Result<(), &'static str> {
+        Err("IRQ targeting is not supported by the BCM interrupt controller")
+    }
+
+    fn local_core_init(&self) {}
+
     fn handle_pending_irqs<'irq_context>(
         &'irq_context self,
         ic: &exception::asynchronous::IRQContext<'irq_context>,
+        context: &exception::ExceptionContext,
     ) {
-        // It can only be a peripheral IRQ pending because enable() does not support local IRQs yet.
-        self.periph.handle_pending_irqs(ic)
+        self.local.handle_pending_irqs(ic, context);
+
+        if self.local.is_gpu_irq_pending() {
+            self.periph.handle_pending_irqs(ic, context)
+        }
     }
 
     fn print_handler(&self) {
+        self.local.print_handler();
         self.periph.print_handler();
     }
+
+    fn handler_health(&self, f: &mut dyn FnMut(&exception::asynchronous::IRQHandlerHealthReport)) {
+        self.local.handler_health(f);
+        self.periph.handler_health(f);
+    }
 }
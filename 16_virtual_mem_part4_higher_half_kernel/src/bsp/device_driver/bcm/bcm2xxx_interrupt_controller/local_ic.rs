@@ -0,0 +1,263 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Local Interrupt Controller Driver.
+//!
+//! The per-core interrupt controller of the BCM2836 and later. Only the routing of the core timer
+//! IRQs and of the PMU IRQ is supported. The GPU interrupt, which is the output of the peripheral interrupt
+//! controller, is routed to core 0 by default and reported as pending here as well.
+
+use super::{InterruptController, LocalIRQ, PendingIRQs};
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper,
+    cpu, driver, exception, memory, synchronization,
+    synchronization::{IRQSafeNullLock, InitStateLock},
+};
+use register::{mmio::*, register_bitfields, register_structs};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_bitfields! {
+    u32,
+
+    /// Core Timer Interrupt Control.
+    CORE_TIMER_IRQCNTL [
+        /// Bits 0..=3 enable the IRQs of CNTPSIRQ, CNTPNSIRQ, CNTHPIRQ and CNTVIRQ, in that
+        /// order.
+        IRQ_ENABLE OFFSET(0) NUMBITS(4) [],
+
+        /// Same as above, but routes to FIQ. Takes precedence over the IRQ bits.
+        FIQ_ENABLE OFFSET(4) NUMBITS(4) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RWRegisterBlock {
+        (0x00 => _reserved1),
+        (0x10 => PMU_IRQ_ROUTING_SET: WriteOnly<u32>),
+        (0x14 => PMU_IRQ_ROUTING_CLR: WriteOnly<u32>),
+        (0x18 => _reserved2),
+        (0x40 => CORE_TIMER_IRQCNTL: [ReadWrite<u32, CORE_TIMER_IRQCNTL::Register>; 4]),
+        (0x50 => @END),
+    }
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RORegisterBlock {
+        (0x00 => _reserved1),
+        (0x60 => CORE_IRQ_SOURCE: [ReadOnly<u32>; 4]),
+        (0x70 => @END),
+    }
+}
+
+/// Abstraction for the ReadWrite parts of the associated MMIO registers.
+type ReadWriteRegisters = MMIODerefWrapper<RWRegisterBlock>;
+
+/// Abstraction for the ReadOnly parts of the associated MMIO registers.
+type ReadOnlyRegisters = MMIODerefWrapper<RORegisterBlock>;
+
+type HandlerTable = [exception::asynchronous::IRQHandlerSlot; InterruptController::NUM_LOCAL_IRQS];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: exception::asynchronous::IRQHandlerSlot =
+    exception::asynchronous::IRQHandlerSlot::new();
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Representation of the local interrupt controller.
+pub struct LocalIC {
+    mmio_descriptor: memory::mmu::MMIODescriptor,
+
+    /// Access to read-write registers is guarded with a lock.
+    rw_registers: IRQSafeNullLock<ReadWriteRegisters>,
+
+    /// Register read access is unguarded.
+    ro_registers: InitStateLock<ReadOnlyRegisters>,
+
+    /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
+    handler_table: InitStateLock<HandlerTable>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl LocalIC {
+    /// The highest local IRQ number that belongs to a core timer.
+    const MAX_TIMER_IRQ_NUMBER: usize = 3;
+
+    /// The local IRQ number of the GPU interrupt.
+    const GPU_IRQ_NUMBER: usize = 8;
+
+    /// The local IRQ number of the PMU interrupt.
+    const PMU_IRQ_NUMBER: usize = 9;
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct MMIO descriptors.
+    pub const unsafe fn new(mmio_descriptor: memory::mmu::MMIODescriptor) -> Self {
+        let addr = mmio_descriptor.start_addr().into_usize();
+
+        Self {
+            mmio_descriptor,
+            rw_registers: IRQSafeNullLock::new(ReadWriteRegisters::new(addr)),
+            ro_registers: InitStateLock::new(ReadOnlyRegisters::new(addr)),
+            handler_table: InitStateLock::new([NO_HANDLER; InterruptController::NUM_LOCAL_IRQS]),
+        }
+    }
+
+    /// Return true if the IRQ's routing is supported.
+    pub fn is_supported(irq_number: usize) -> bool {
+        irq_number <= Self::MAX_TIMER_IRQ_NUMBER || irq_number == Self::PMU_IRQ_NUMBER
+    }
+
+    /// Query the list of IRQs pending on the executing core.
+    fn pending_irqs(&self) -> PendingIRQs {
+        self.ro_registers.read(|regs| {
+            let pending_mask = regs.CORE_IRQ_SOURCE[cpu::core_id()].get();
+
+            PendingIRQs::new(u64::from(pending_mask))
+        })
+    }
+
+    /// Disable an IRQ for the executing core.
+    fn disable_number(&self, irq_number: usize) {
+        self.rw_registers.lock(|regs| {
+            if irq_number == Self::PMU_IRQ_NUMBER {
+                regs.PMU_IRQ_ROUTING_CLR.set(1 << cpu::core_id());
+                return;
+            }
+
+            let reg = &regs.CORE_TIMER_IRQCNTL[cpu::core_id()];
+            let enabled = reg.read(CORE_TIMER_IRQCNTL::IRQ_ENABLE);
+
+            reg.modify(CORE_TIMER_IRQCNTL::IRQ_ENABLE.val(enabled & !(1 << irq_number)));
+        });
+    }
+
+    /// Return true if the peripheral interrupt controller signals an IRQ to the executing core.
+    pub fn is_gpu_irq_pending(&self) -> bool {
+        self.pending_irqs().any(|x| x == Self::GPU_IRQ_NUMBER)
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::{Mutex, ReadWriteEx};
+
+impl driver::interface::DeviceDriver for LocalIC {
+    fn compatible(&self) -> &'static str {
+        "BCM Local Interrupt Controller"
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let virt_addr =
+            memory::mmu::kernel_map_mmio(self.compatible(), &self.mmio_descriptor)?.into_usize();
+
+        self.rw_registers
+            .lock(|regs| *regs = ReadWriteRegisters::new(virt_addr));
+        self.ro_registers
+            .write(|regs| *regs = ReadOnlyRegisters::new(virt_addr));
+
+        Ok(())
+    }
+}
+
+impl exception::asynchronous::interface::IRQManager for LocalIC {
+    type IRQNumberType = LocalIRQ;
+
+    fn register_handler(
+        &self,
+        irq: Self::IRQNumberType,
+        descriptor: exception::asynchronous::IRQDescriptor,
+    ) -> Result<(), &'static str> {
+        if !Self::is_supported(irq.get()) {
+            return Err("Only the core timer and PMU IRQs are supported");
+        }
+
+        self.handler_table
+            .write(|table| table[irq.get()].register(descriptor))
+    }
+
+    /// Enable the IRQ for the executing core. The core timers and the PMU are banked per core.
+    fn enable(&self, irq: Self::IRQNumberType) {
+        assert!(
+            Self::is_supported(irq.get()),
+            "Only the core timer and PMU IRQs are supported"
+        );
+
+        self.rw_registers.lock(|regs| {
+            if irq.get() == Self::PMU_IRQ_NUMBER {
+                regs.PMU_IRQ_ROUTING_SET.set(1 << cpu::core_id());
+                return;
+            }
+
+            let reg = &regs.CORE_TIMER_IRQCNTL[cpu::core_id()];
+            let enabled = reg.read(CORE_TIMER_IRQCNTL::IRQ_ENABLE);
+
+            reg.modify(CORE_TIMER_IRQCNTL::IRQ_ENABLE.val(enabled | (1 << irq.get())));
+        });
+    }
+
+    /// Disable the IRQ for the executing core.
+    fn disable(&self, irq: Self::IRQNumberType) {
+        assert!(
+            Self::is_supported(irq.get()),
+            "Only the core timer and PMU IRQs are supported"
+        );
+
+        self.disable_number(irq.get());
+    }
+
+    /// Handle the pending core timer and PMU IRQs. The GPU interrupt is left to the caller.
+    fn handle_pending_irqs<'irq_context>(
+        &'irq_context self,
+        ic: &exception::asynchronous::IRQContext<'irq_context>,
+        context: &exception::ExceptionContext,
+    ) {
+        self.handler_table.read(|table| {
+            for irq_number in self.pending_irqs().filter(|x| Self::is_supported(*x)) {
+                let outcome = exception::asynchronous::handle_irq(
+                    ic,
+                    &table[irq_number],
+                    "local ",
+                    irq_number,
+                    context,
+                );
+
+                if outcome == exception::asynchronous::IRQOutcome::Mask {
+                    self.disable_number(irq_number);
+                }
+            }
+        })
+    }
+
+    fn print_handler(&self) {
+        use crate::info;
+
+        info!("      Local handler:");
+
+        self.handler_table.read(|table| {
+            for (i, slot) in table.iter().enumerate() {
+                if let Some(handler) = slot.descriptor() {
+                    info!("            {: >3}. {}", i, handler.name);
+                }
+            }
+        });
+    }
+
+    fn handler_health(&self, f: &mut dyn FnMut(&exception::asynchronous::IRQHandlerHealthReport)) {
+        self.handler_table
+            .read(|table| table.iter().filter_map(|x| x.health()).for_each(|x| f(&x)));
+    }
+}
@@ -4,7 +4,7 @@
 
 //! Peripheral Interrupt Controller Driver.
 
-use super::{InterruptController, PendingIRQs, PeripheralIRQ};
+use super::{ArmIRQ, InterruptController, PendingIRQs, PeripheralIRQ};
 use crate::{
     bsp::device_driver::common::MMIODerefWrapper,
     driver, exception, memory, synchronization,
@@ -22,14 +22,18 @@ register_structs! {
         (0x00 => _reserved1),
         (0x10 => ENABLE_1: WriteOnly<u32>),
         (0x14 => ENABLE_2: WriteOnly<u32>),
-        (0x24 => @END),
+        (0x18 => ENABLE_BASIC: WriteOnly<u32>),
+        (0x1C => DISABLE_1: WriteOnly<u32>),
+        (0x20 => DISABLE_2: WriteOnly<u32>),
+        (0x24 => DISABLE_BASIC: WriteOnly<u32>),
+        (0x28 => @END),
     }
 }
 
 register_structs! {
     #[allow(non_snake_case)]
     RORegisterBlock {
-        (0x00 => _reserved1),
+        (0x00 => PENDING_BASIC: ReadOnly<u32>),
         (0x04 => PENDING_1: ReadOnly<u32>),
         (0x08 => PENDING_2: ReadOnly<u32>),
         (0x0c => @END),
@@ -43,7 +47,16 @@ type WriteOnlyRegisters = MMIODerefWrapper<WORegisterBlock>;
 type ReadOnlyRegisters = MMIODerefWrapper<RORegisterBlock>;
 
 type HandlerTable =
-    [Option<exception::asynchronous::IRQDescriptor>; InterruptController::NUM_PERIPHERAL_IRQS];
+    [exception::asynchronous::IRQHandlerSlot; InterruptController::NUM_PERIPHERAL_IRQS];
+
+type ArmHandlerTable = [exception::asynchronous::IRQHandlerSlot; InterruptController::NUM_ARM_IRQS];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: exception::asynchronous::IRQHandlerSlot =
+    exception::asynchronous::IRQHandlerSlot::new();
+
+/// The ARM-side IRQs are in the lowest bits of the basic pending register.
+const PENDING_BASIC_ARM_MASK: u32 = (1 << InterruptController::NUM_ARM_IRQS) - 1;
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -61,6 +74,9 @@ pub struct PeripheralIC {
 
     /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
     handler_table: InitStateLock<HandlerTable>,
+
+    /// Same as above, for the ARM-side IRQs.
+    arm_handler_table: InitStateLock<ArmHandlerTable>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -80,10 +96,49 @@ impl PeripheralIC {
             mmio_descriptor,
             wo_registers: IRQSafeNullLock::new(WriteOnlyRegisters::new(addr)),
             ro_registers: InitStateLock::new(ReadOnlyRegisters::new(addr)),
-            handler_table: InitStateLock::new([None; InterruptController::NUM_PERIPHERAL_IRQS]),
+            handler_table: InitStateLock::new(
+                [NO_HANDLER; InterruptController::NUM_PERIPHERAL_IRQS],
+            ),
+            arm_handler_table: InitStateLock::new([NO_HANDLER; InterruptController::NUM_ARM_IRQS]),
         }
     }
 
+    /// Register a handler for an ARM-side IRQ.
+    pub fn register_arm_handler(
+        &self,
+        irq: ArmIRQ,
+        descriptor: exception::asynchronous::IRQDescriptor,
+    ) -> Result<(), &'static str> {
+        self.arm_handler_table
+            .write(|table| table[irq.get()].register(descriptor))
+    }
+
+    /// Enable an ARM-side IRQ.
+    pub fn enable_arm(&self, irq: ArmIRQ) {
+        self.wo_registers
+            .lock(|regs| regs.ENABLE_BASIC.set(1 << irq.get()));
+    }
+
+    /// Disable an IRQ.
+    fn disable_number(&self, irq_number: usize) {
+        self.wo_registers.lock(|regs| {
+            let disable_reg = if irq_number <= 31 {
+                &regs.DISABLE_1
+            } else {
+                &regs.DISABLE_2
+            };
+
+            // Same as for enabling, writing a 1 only affects the corresponding IRQ.
+            disable_reg.set(1 << (irq_number % 32));
+        });
+    }
+
+    /// Disable an ARM-side IRQ.
+    pub fn disable_arm(&self, irq: ArmIRQ) {
+        self.wo_registers
+            .lock(|regs| regs.DISABLE_BASIC.set(1 << irq.get()));
+    }
+
     /// Query the list of pending IRQs.
     fn pending_irqs(&self) -> PendingIRQs {
         self.ro_registers.read(|regs| {
@@ -93,6 +148,13 @@ impl PeripheralIC {
             PendingIRQs::new(pending_mask)
         })
     }
+
+    /// Query the list of pending ARM-side IRQs.
+    fn pending_arm_irqs(&self) -> PendingIRQs {
+        self.ro_registers.read(|regs| {
+            PendingIRQs::new(u64::from(regs.PENDING_BASIC.get() & PENDING_BASIC_ARM_MASK))
+        })
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -126,17 +188,8 @@ impl exception::asynchronous::interface::IRQManager for PeripheralIC {
         irq: Self::IRQNumberType,
         descriptor: exception::asynchronous::IRQDescriptor,
     ) -> Result<(), &'static str> {
-        self.handler_table.write(|table| {
-            let irq_number = irq.get();
-
-            if table[irq_number].is_some() {
-                return Err("IRQ handler already registered");
-            }
-
-            table[irq_number] = Some(descriptor);
-
-            Ok(())
-        })
+        self.handler_table
+            .write(|table| table[irq.get()].register(descriptor))
     }
 
     fn enable(&self, irq: Self::IRQNumberType) {
@@ -155,18 +208,33 @@ impl exception::asynchronous::interface::IRQManager for PeripheralIC {
         });
     }
 
+    fn disable(&self, irq: Self::IRQNumberType) {
+        self.disable_number(irq.get());
+    }
+
     fn handle_pending_irqs<'irq_context>(
         &'irq_context self,
-        _ic: &exception::asynchronous::IRQContext<'irq_context>,
+        ic: &exception::asynchronous::IRQContext<'irq_context>,
+        context: &exception::ExceptionContext,
     ) {
+        use exception::asynchronous::{handle_irq, IRQOutcome};
+
         self.handler_table.read(|table| {
             for irq_number in self.pending_irqs() {
-                match table[irq_number] {
-                    None => panic!("No handler registered for IRQ {}", irq_number),
-                    Some(descriptor) => {
-                        // Call the IRQ handler. Panics on failure.
-                        descriptor.handler.handle().expect("Error handling IRQ");
-                    }
+                let outcome = handle_irq(ic, &table[irq_number], "", irq_number, context);
+
+                if outcome == IRQOutcome::Mask {
+                    self.disable_number(irq_number);
+                }
+            }
+        });
+
+        self.arm_handler_table.read(|table| {
+            for irq_number in self.pending_arm_irqs() {
+                let outcome = handle_irq(ic, &table[irq_number], "ARM ", irq_number, context);
+
+                if outcome == IRQOutcome::Mask {
+                    self.disable_arm(ArmIRQ::new(irq_number));
                 }
             }
         })
@@ -178,11 +246,30 @@ impl exception::asynchronous::interface::IRQManager for PeripheralIC {
         info!("      Peripheral handler:");
 
         self.handler_table.read(|table| {
-            for (i, opt) in table.iter().enumerate() {
-                if let Some(handler) = opt {
+            for (i, slot) in table.iter().enumerate() {
+                if let Some(handler) = slot.descriptor() {
+                    info!("            {: >3}. {}", i, handler.name);
+                }
+            }
+        });
+
+        info!("      ARM handler:");
+
+        self.arm_handler_table.read(|table| {
+            for (i, slot) in table.iter().enumerate() {
+                if let Some(handler) = slot.descriptor() {
                     info!("            {: >3}. {}", i, handler.name);
                 }
             }
         });
     }
+
+    fn handler_health(&self, f: &mut dyn FnMut(&exception::asynchronous::IRQHandlerHealthReport)) {
+        let mut visit = |table: &[exception::asynchronous::IRQHandlerSlot]| {
+            table.iter().filter_map(|x| x.health()).for_each(|x| f(&x))
+        };
+
+        self.handler_table.read(|table| visit(&table[..]));
+        self.arm_handler_table.read(|table| visit(&table[..]));
+    }
 }
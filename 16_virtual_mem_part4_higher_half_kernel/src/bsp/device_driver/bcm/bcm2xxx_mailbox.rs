@@ -0,0 +1,599 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! VideoCore Mailbox Driver.
+//!
+//! The firmware running on the VideoCore answers requests that are passed through the mailbox. Only
+//! the property channel is supported. A request is a buffer of tags in DRAM, whose bus address is
+//! written to the mailbox. The firmware overwrites the tags' values with the response.
+//!
+//! Until interrupts are enabled, the driver polls for the response. Afterwards, requests can also
+//! be queued with `call_async()`, which returns right away. The hardware has a single request in
+//! flight, so queued requests are sent one after the other from the mailbox's IRQ handler. A
+//! blocking `property()` call waits for the request in flight first.
+
+use crate::{
+    bsp,
+    bsp::device_driver::common::MMIORegisters,
+    cpu, driver, exception, memory,
+    memory::{Address, Virtual},
+    poll_register, synchronization,
+    synchronization::IRQSafeNullLock,
+};
+use core::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+use register::{mmio::*, register_bitfields, register_structs};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Mailbox registers.
+//
+// There is no official documentation. The layout is taken from the Linux bcm2835-mailbox driver.
+// The ARM reads from mailbox 0 and writes to mailbox 1.
+register_bitfields! {
+    u32,
+
+    /// Status
+    STATUS [
+        /// The mailbox can't take another message.
+        FULL OFFSET(31) NUMBITS(1) [],
+
+        /// The mailbox holds no message.
+        EMPTY OFFSET(30) NUMBITS(1) []
+    ],
+
+    /// Configuration
+    CONFIG [
+        /// Raise an IRQ while the mailbox holds a message.
+        DATA_IRQ_EN OFFSET(0) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => READ: ReadOnly<u32>),
+        (0x04 => _reserved1),
+        (0x18 => READ_STATUS: ReadOnly<u32, STATUS::Register>),
+        (0x1C => CONFIG: ReadWrite<u32, CONFIG::Register>),
+        (0x20 => WRITE: WriteOnly<u32>),
+        (0x24 => _reserved3),
+        (0x38 => WRITE_STATUS: ReadOnly<u32, STATUS::Register>),
+        (0x3C => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIORegisters<RegisterBlock>;
+
+/// The channel for requests to the firmware's property interface.
+const CHANNEL_PROPERTY: u32 = 8;
+
+/// The VideoCore's alias of the ARM's DRAM that bypasses the VideoCore's L2 cache.
+const BUS_DRAM_ALIAS: u32 = 0xC000_0000;
+
+/// How long the firmware is given to answer.
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Size of the property buffer in words.
+const BUFFER_WORDS: usize = 36;
+
+/// Code in the buffer header that marks a request.
+const CODE_REQUEST: u32 = 0;
+
+/// Code in the buffer header that marks a successful response.
+const CODE_RESPONSE_SUCCESS: u32 = 0x8000_0000;
+
+/// Bit in a tag's length word that marks the response.
+const TAG_RESPONSE: u32 = 0x8000_0000;
+
+/// The tag that terminates the list.
+const TAG_END: u32 = 0;
+
+/// Words in the buffer that are not values: Buffer size, code, tag, value size, tag code, end tag.
+const OVERHEAD_WORDS: usize = 6;
+
+/// Number of requests that can be queued with `call_async()`, including the one in flight.
+const QUEUE_LEN: usize = 4;
+
+/// The request buffer. The lowest four bits of its address must be zero, since a message carries
+/// the channel there.
+#[repr(C, align(16))]
+#[derive(Copy, Clone)]
+struct PropertyBuffer([u32; BUFFER_WORDS]);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum SlotState {
+    Free,
+    Queued,
+    InFlight,
+    Done,
+}
+
+/// A request queued with `call_async()`.
+#[derive(Copy, Clone)]
+struct Slot {
+    buffer: PropertyBuffer,
+    state: SlotState,
+
+    /// The message that is written to the mailbox for the buffer.
+    message: u32,
+
+    /// Orders the queued requests.
+    seq: u64,
+
+    /// The ticket was dropped. The slot is freed when the request completes.
+    abandoned: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Tags of the property interface that are used in the kernel.
+#[allow(missing_docs)]
+pub mod tag {
+    pub const GET_BOARD_REVISION: u32 = 0x0001_0002;
+    pub const GET_ARM_MEMORY: u32 = 0x0001_0005;
+    pub const GET_VC_MEMORY: u32 = 0x0001_0006;
+    pub const ALLOCATE_BUFFER: u32 = 0x0004_0001;
+    pub const GET_PITCH: u32 = 0x0004_0008;
+    pub const SET_PHYSICAL_SIZE: u32 = 0x0004_8003;
+    pub const SET_VIRTUAL_SIZE: u32 = 0x0004_8004;
+    pub const SET_DEPTH: u32 = 0x0004_8005;
+    pub const SET_VIRTUAL_OFFSET: u32 = 0x0004_8009;
+    pub const GET_CLOCK_RATE: u32 = 0x0003_0002;
+    pub const GET_MAX_CLOCK_RATE: u32 = 0x0003_0004;
+    pub const GET_TEMPERATURE: u32 = 0x0003_0006;
+    pub const GET_MAX_TEMPERATURE: u32 = 0x0003_000A;
+    pub const SET_GPIO_STATE: u32 = 0x0003_8041;
+}
+
+pub struct MailboxInner {
+    registers: Registers,
+    buffer: PropertyBuffer,
+    slots: [Slot; QUEUE_LEN],
+    in_flight: Option<usize>,
+    next_seq: u64,
+}
+
+/// Representation of the VideoCore mailbox.
+pub struct Mailbox {
+    mmio_descriptor: memory::mmu::MMIODescriptor,
+    virt_mmio_start_addr: AtomicUsize,
+    inner: IRQSafeNullLock<MailboxInner>,
+    irq_number: bsp::device_driver::IRQNumber,
+    irq_mode: AtomicBool,
+}
+
+/// A request queued with `Mailbox::call_async()`, with `N` values in the response.
+///
+/// Dropping the ticket discards the response.
+pub struct MailboxTicket<const N: usize> {
+    mailbox: &'static Mailbox,
+    slot: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl PropertyBuffer {
+    /// The address of the first value. The firmware writes the values behind the compiler's back,
+    /// so they are only accessed through `memory::ops`.
+    fn values_addr(start: *const u32) -> Address<Virtual> {
+        Address::new(start.wrapping_add(5) as usize)
+    }
+
+    /// Fill in a request for a single tag with the given values.
+    fn fill<const N: usize>(&mut self, tag: u32, values: [u32; N]) -> Result<(), &'static str> {
+        if N + OVERHEAD_WORDS > BUFFER_WORDS {
+            return Err("Mailbox: Too many values");
+        }
+
+        let buf = &mut self.0;
+        let value_size = (N * 4) as u32;
+
+        buf[0] = ((N + OVERHEAD_WORDS) * 4) as u32;
+        buf[1] = CODE_REQUEST;
+        buf[2] = tag;
+        buf[3] = value_size;
+        buf[4] = 0;
+        buf[5 + N] = TAG_END;
+
+        let mut bytes = [0; BUFFER_WORDS * 4];
+        for (chunk, value) in bytes.chunks_exact_mut(4).zip(values.iter()) {
+            chunk.copy_from_slice(&value.to_ne_bytes());
+        }
+
+        unsafe {
+            memory::ops::copy_to_device(Self::values_addr(self.0.as_mut_ptr()), &bytes[..N * 4])
+        }
+    }
+
+    /// The values of the firmware's response.
+    fn response<const N: usize>(&self) -> Result<[u32; N], &'static str> {
+        let buf = &self.0;
+        if buf[1] != CODE_RESPONSE_SUCCESS {
+            return Err("Mailbox: Firmware rejected the request");
+        }
+        if buf[4] & TAG_RESPONSE == 0 {
+            return Err("Mailbox: Firmware does not know the tag");
+        }
+
+        let mut bytes = [0; BUFFER_WORDS * 4];
+        unsafe {
+            memory::ops::copy_from_device(&mut bytes[..N * 4], Self::values_addr(buf.as_ptr()))?
+        };
+
+        let mut response = [0; N];
+        for (value, chunk) in response.iter_mut().zip(bytes.chunks_exact(4)) {
+            *value = u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+
+        Ok(response)
+    }
+
+    /// The message that hands the buffer to the firmware.
+    fn message(&self) -> Result<u32, &'static str> {
+        let virt_addr = Address::new(self.0.as_ptr() as usize);
+        let size = core::mem::size_of::<PropertyBuffer>();
+
+        // The firmware takes a single physical address, so the buffer must be contiguous.
+        let mut runs = memory::mmu::virt_to_phys_region(virt_addr, size)
+            .map_err(|_| "Mailbox: Buffer is not mapped")?;
+        let phys_addr = match runs.next() {
+            Some((start, run_size)) if run_size == size => start.into_usize() as u32,
+            _ => return Err("Mailbox: Buffer is not physically contiguous"),
+        };
+
+        Ok((phys_addr | BUS_DRAM_ALIAS) | CHANNEL_PROPERTY)
+    }
+
+    /// The VideoCore does not snoop the ARM's caches.
+    fn flush(&self) {
+        unsafe {
+            cpu::cache::clean_invalidate_dcache_range(
+                self.0.as_ptr() as usize,
+                core::mem::size_of::<PropertyBuffer>(),
+            )
+        };
+    }
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            buffer: PropertyBuffer([0; BUFFER_WORDS]),
+            state: SlotState::Free,
+            message: 0,
+            seq: 0,
+            abandoned: false,
+        }
+    }
+}
+
+impl MailboxInner {
+    fn send(&mut self, message: u32) -> Result<(), &'static str> {
+        poll_register!(TIMEOUT, self.registers.WRITE_STATUS, STATUS::FULL::CLEAR)
+            .map_err(|_| "Mailbox: Timeout waiting for the firmware")?;
+        self.registers.WRITE.set(message);
+
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<u32, &'static str> {
+        poll_register!(TIMEOUT, self.registers.READ_STATUS, STATUS::EMPTY::CLEAR)
+            .map_err(|_| "Mailbox: Timeout waiting for the firmware")?;
+
+        Ok(self.registers.READ.get())
+    }
+
+    /// Complete the queued request in flight if `message` is its response. Other messages are
+    /// stale and dropped.
+    fn complete(&mut self, message: u32) {
+        let i = match self.in_flight {
+            Some(i) if self.slots[i].message == message => i,
+            _ => return,
+        };
+
+        let slot = &mut self.slots[i];
+        slot.buffer.flush();
+        slot.state = if slot.abandoned {
+            SlotState::Free
+        } else {
+            SlotState::Done
+        };
+        self.in_flight = None;
+    }
+
+    /// Send the oldest queued request, unless one is in flight already.
+    fn start_next(&mut self) -> Result<(), &'static str> {
+        if self.in_flight.is_some() {
+            return Ok(());
+        }
+
+        let next = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.state == SlotState::Queued)
+            .min_by_key(|(_, x)| x.seq)
+            .map(|(i, _)| i);
+        let i = match next {
+            None => return Ok(()),
+            Some(i) => i,
+        };
+
+        let message = self.slots[i].message;
+        self.slots[i].buffer.flush();
+        self.send(message)?;
+
+        self.slots[i].state = SlotState::InFlight;
+        self.in_flight = Some(i);
+
+        Ok(())
+    }
+
+    /// Hand the buffer to the firmware and wait for the response.
+    fn call(&mut self) -> Result<(), &'static str> {
+        let message = self.buffer.message()?;
+
+        // The hardware has a single request in flight.
+        while self.in_flight.is_some() {
+            let x = self.receive()?;
+            self.complete(x);
+        }
+
+        self.buffer.flush();
+        self.send(message)?;
+
+        // Skip stale messages on other channels.
+        loop {
+            if self.receive()? == message {
+                break;
+            }
+        }
+
+        self.buffer.flush();
+
+        // Requests that were queued in the meantime go next.
+        self.start_next()
+    }
+
+    /// Queue a request. Returns the slot.
+    fn queue<const N: usize>(&mut self, tag: u32, values: [u32; N]) -> Result<usize, &'static str> {
+        let i = self
+            .slots
+            .iter()
+            .position(|x| x.state == SlotState::Free)
+            .ok_or("Mailbox: Request queue is full")?;
+
+        let slot = &mut self.slots[i];
+        slot.buffer.fill(tag, values)?;
+        slot.message = slot.buffer.message()?;
+        slot.seq = self.next_seq;
+        slot.abandoned = false;
+        slot.state = SlotState::Queued;
+        self.next_seq += 1;
+
+        if let Err(x) = self.start_next() {
+            self.slots[i].state = SlotState::Free;
+            return Err(x);
+        }
+
+        Ok(i)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl MailboxInner {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO descriptor.
+    pub const unsafe fn new(mmio_descriptor: &memory::mmu::MMIODescriptor) -> Self {
+        Self {
+            registers: Registers::new(mmio_descriptor),
+            buffer: PropertyBuffer([0; BUFFER_WORDS]),
+            slots: [Slot::new(); QUEUE_LEN],
+            in_flight: None,
+            next_seq: 0,
+        }
+    }
+
+    /// Init code. Maps the registers.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO descriptor.
+    pub unsafe fn init(
+        &mut self,
+        name: &'static str,
+        mmio_descriptor: &memory::mmu::MMIODescriptor,
+    ) -> Result<Address<Virtual>, &'static str> {
+        self.registers.map(name, mmio_descriptor)
+    }
+
+    /// Send a single property tag with the given values, and return the values of the response.
+    pub fn property<const N: usize>(
+        &mut self,
+        tag: u32,
+        values: [u32; N],
+    ) -> Result<[u32; N], &'static str> {
+        self.buffer.fill(tag, values)?;
+        self.call()?;
+
+        self.buffer.response()
+    }
+}
+
+impl Mailbox {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct MMIO descriptors.
+    /// - The user must ensure to provide correct IRQ numbers.
+    pub const unsafe fn new(
+        mmio_descriptor: memory::mmu::MMIODescriptor,
+        irq_number: bsp::device_driver::IRQNumber,
+    ) -> Self {
+        Self {
+            mmio_descriptor,
+            virt_mmio_start_addr: AtomicUsize::new(0),
+            inner: IRQSafeNullLock::new(MailboxInner::new(&mmio_descriptor)),
+            irq_number,
+            irq_mode: AtomicBool::new(false),
+        }
+    }
+
+    /// Concurrency safe version of `MailboxInner.property()`
+    pub fn property<const N: usize>(
+        &self,
+        tag: u32,
+        values: [u32; N],
+    ) -> Result<[u32; N], &'static str> {
+        if self.virt_mmio_start_addr.load(Ordering::Relaxed) == 0 {
+            return Err("Mailbox: Driver is not initialized");
+        }
+
+        self.inner.lock(|inner| inner.property(tag, values))
+    }
+
+    /// Queue a single property tag with the given values, without waiting for the response.
+    ///
+    /// Only available after the mailbox's IRQ was enabled. Before, use `property()`.
+    pub fn call_async<const N: usize>(
+        &'static self,
+        tag: u32,
+        values: [u32; N],
+    ) -> Result<MailboxTicket<N>, &'static str> {
+        if !self.irq_mode.load(Ordering::Acquire) {
+            return Err("Mailbox: Interrupts are not enabled yet");
+        }
+
+        let slot = self.inner.lock(|inner| inner.queue(tag, values))?;
+
+        Ok(MailboxTicket {
+            mailbox: self,
+            slot,
+        })
+    }
+}
+
+impl<const N: usize> MailboxTicket<N> {
+    /// Return true if the response has arrived.
+    pub fn is_complete(&self) -> bool {
+        self.mailbox
+            .inner
+            .lock(|inner| inner.slots[self.slot].state == SlotState::Done)
+    }
+
+    /// Wait for the response and return its values.
+    ///
+    /// The core sleeps in between the checks and is woken by the mailbox's IRQ.
+    pub fn wait(self, timeout: Duration) -> Result<[u32; N], &'static str> {
+        if !cpu::spin_until(|| self.is_complete(), Some(timeout)) {
+            return Err("Mailbox: Timeout waiting for the firmware");
+        }
+
+        self.mailbox
+            .inner
+            .lock(|inner| inner.slots[self.slot].buffer.response())
+    }
+}
+
+impl<const N: usize> Drop for MailboxTicket<N> {
+    fn drop(&mut self) {
+        self.mailbox.inner.lock(|inner| {
+            let slot = &mut inner.slots[self.slot];
+
+            match slot.state {
+                SlotState::InFlight => slot.abandoned = true,
+                _ => slot.state = SlotState::Free,
+            }
+        });
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl driver::interface::DeviceDriver for Mailbox {
+    fn compatible(&self) -> &'static str {
+        "BCM VideoCore Mailbox"
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let virt_addr = self
+            .inner
+            .lock(|inner| inner.init(self.compatible(), &self.mmio_descriptor))?;
+
+        self.virt_mmio_start_addr
+            .store(virt_addr.into_usize(), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
+        use bsp::exception::asynchronous::irq_manager;
+        use exception::asynchronous::{interface::IRQManager, IRQDescriptor};
+
+        let descriptor = IRQDescriptor {
+            name: "BCM VideoCore Mailbox",
+            handler: self,
+        };
+
+        irq_manager().register_handler(self.irq_number, descriptor)?;
+        irq_manager().enable(self.irq_number);
+
+        Ok(())
+    }
+
+    unsafe fn post_irq_init(&self) -> Result<(), &'static str> {
+        self.inner
+            .lock(|inner| inner.registers.CONFIG.write(CONFIG::DATA_IRQ_EN::SET));
+        self.irq_mode.store(true, Ordering::Release);
+
+        Ok(())
+    }
+
+    fn virt_mmio_start_addr(&self) -> Option<usize> {
+        let addr = self.virt_mmio_start_addr.load(Ordering::Relaxed);
+
+        if addr == 0 {
+            return None;
+        }
+
+        Some(addr)
+    }
+
+    fn irq_numbers(&self) -> &[bsp::device_driver::IRQNumber] {
+        core::slice::from_ref(&self.irq_number)
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for Mailbox {
+    fn handle(&self, _context: &exception::ExceptionContext) -> Result<(), &'static str> {
+        self.inner.lock(|inner| {
+            while !inner.registers.READ_STATUS.matches_all(STATUS::EMPTY::SET) {
+                let message = inner.registers.READ.get();
+                inner.complete(message);
+            }
+
+            inner.start_next()
+        })
+    }
+}
@@ -10,12 +10,22 @@
 //! - <https://developer.arm.com/documentation/ddi0183/latest>
 
 use crate::{
-    bsp, bsp::device_driver::common::MMIODerefWrapper, console, cpu, driver, exception, memory,
-    synchronization, synchronization::IRQSafeNullLock,
+    bsp,
+    bsp::device_driver::common::MMIODerefWrapper,
+    config, console, cpu, driver, exception,
+    log::RateLimiter,
+    memory, monitor, poll_register,
+    shutdown::ShutdownReason,
+    synchronization,
+    synchronization::IRQSafeNullLock,
+    time,
+    units::{Baud, Hertz},
+    warn_rate_limited,
 };
 use core::{
     fmt,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
 };
 use register::{mmio::*, register_bitfields, register_structs};
 
@@ -123,6 +133,13 @@ register_bitfields! {
             Enabled = 1
         ],
 
+        /// Loopback enable. If this bit is set to 1, the transmit serial output is internally fed
+        /// back to the receive serial input.
+        LBE OFFSET(7) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
         /// UART enable:
         ///
         /// 0 = UART is disabled. If the UART is disabled in the middle of transmission or
@@ -207,27 +224,112 @@ register_structs! {
         (0x3C => _reserved3),
         (0x40 => MIS: ReadOnly<u32, MIS::Register>),
         (0x44 => ICR: WriteOnly<u32, ICR::Register>),
-        (0x48 => @END),
+        (0x48 => _reserved4),
+        (0xFE0 => PeriphID: [ReadOnly<u32>; 4]),
+        (0xFF0 => PCellID: [ReadOnly<u32>; 4]),
+        (0x1000 => @END),
     }
 }
 
 /// Abstraction for the associated MMIO registers.
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
-#[derive(PartialEq)]
-enum BlockingMode {
-    Blocking,
-    NonBlocking,
+/// Offsets in `RegisterBlock`, for the accesses that do not go through `Registers`.
+const DR_OFFSET: usize = 0x00;
+const FR_OFFSET: usize = 0x18;
+
+/// The reference clock that config.txt asks the firmware for, until the real one is known.
+const DEFAULT_REF_CLOCK: Hertz = Hertz::from_mhz(48);
+
+/// How long transmitting waits for the UART. Draining the full TX FIFO takes less than 3 ms even at
+/// 115200 baud, so running out means that the UART is wedged or switched off.
+const TX_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The part number and designer fields of the peripheral ID, which name an ARM PL011. The revision
+/// field above them differs between the UART's releases.
+const PERIPH_ID_MASK: u32 = 0x000F_FFFF;
+const PERIPH_ID_PL011: u32 = 0x0004_1011;
+
+/// The PrimeCell ID, which all of ARM's PrimeCell peripherals share.
+const PCELL_ID: u32 = 0xB105_F00D;
+
+/// Break error flag in a word read from the data register. The input was held low for longer than
+/// a character. The break is received as a NUL character.
+const DR_BE: u32 = 1 << 10;
+
+/// Overrun error flag in a word read from the data register. The receive FIFO was full, and at
+/// least one character was lost in front of this one.
+const DR_OE: u32 = 1 << 11;
+
+/// The number of timestamps in `RxRing`. None unless they are enabled, to save the memory.
+const NUM_RX_TIMESTAMPS: usize = if config::console::RX_TIMESTAMPS {
+    config::console::RX_RING_DEPTH
+} else {
+    0
+};
+
+/// Characters that the IRQ handler received, with their arrival times.
+///
+/// Only filled if `config::console::RX_TIMESTAMPS` is on. Otherwise, the IRQ handler echoes the
+/// characters and drops them.
+struct RxRing {
+    chars: [u8; config::console::RX_RING_DEPTH],
+    timestamps: [Duration; NUM_RX_TIMESTAMPS],
+
+    /// Index of the oldest character.
+    head: usize,
+    len: usize,
+}
+
+/// Counters that are kept up to date from IRQ context and read without the driver's lock.
+struct Counters {
+    tx_queue_high_water: AtomicUsize,
+    rx_queue_high_water: AtomicUsize,
+    rx_dropped: AtomicUsize,
+    tx_blocking_fallbacks: AtomicUsize,
 }
 
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// The UART's reference clock in Hz.
+///
+/// Kept outside of the instances, because the panic handler creates its own instance and must
+/// program the same divisor.
+static REF_CLOCK_HZ: AtomicU64 = AtomicU64::new(DEFAULT_REF_CLOCK.0);
+
+/// Kept outside of the instances as well, so that the emergency output can be counted.
+static COUNTERS: Counters = Counters {
+    tx_queue_high_water: AtomicUsize::new(0),
+    rx_queue_high_water: AtomicUsize::new(0),
+    rx_dropped: AtomicUsize::new(0),
+    tx_blocking_fallbacks: AtomicUsize::new(0),
+};
+
+/// Whether any instance's `init()` has programmed the UART. Until then, the emergency output brings
+/// the UART up with default settings itself.
+static CONFIGURED: AtomicBool = AtomicBool::new(false);
+
+/// Set when the emergency output reprogrammed the UART. The next regular write re-applies the
+/// driver's settings then.
+static EMERGENCY_CONFIGURED: AtomicBool = AtomicBool::new(false);
+
+static OVERRUN_WARNINGS: RateLimiter = RateLimiter::new(Duration::from_secs(1), 1);
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
 pub struct PL011UartInner {
     registers: Registers,
+
+    // The mapped registers, once the driver mapped them. Used by the TX burst path, so that its
+    // accesses are counted with the `mmio_stats` feature.
+    region: Option<memory::mmu::MmioRegion>,
     chars_written: usize,
     chars_read: usize,
+    breaks_received: usize,
 }
 
 // Export the inner struct so that BSPs can use it for the panic handler.
@@ -238,13 +340,99 @@ pub struct PL011Uart {
     mmio_descriptor: memory::mmu::MMIODescriptor,
     virt_mmio_start_addr: AtomicUsize,
     inner: IRQSafeNullLock<PL011UartInner>,
+    rx_ring: IRQSafeNullLock<RxRing>,
     irq_number: bsp::device_driver::IRQNumber,
+    echo: AtomicBool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Assemble an ID from four registers, each of which holds one byte of it.
+fn id_from_bytes(regs: &[ReadOnly<u32>; 4]) -> u32 {
+    regs.iter()
+        .enumerate()
+        .fold(0, |id, (i, x)| id | ((x.get() & 0xFF) << (8 * i)))
+}
+
+/// Calculate the integer and fractional baud rate divisors for the given reference clock.
+///
+/// From the PL011 Technical Reference Manual, the divisor is `ref_clock / (16 * baud)`. `FBRD`
+/// holds its fractional part in 64ths, rounded to the nearest. For example, with a 48 MHz clock:
+///
+/// `(48_000_000 / 16) / 921_600 = 3.2552083`, so `IBRD = 3` and
+/// `FBRD = INTEGER((0.2552083 * 64) + 0.5) = 16`.
+///
+/// The generated baud rate is then `48_000_000 / (16 * 3.25) = 923_077`, an error of 0.16%.
+fn baud_divisors(ref_clock: Hertz, baud: Baud) -> Result<(u32, u32), &'static str> {
+    const ERR: &str = "PL011: Baud rate out of range for the reference clock";
+
+    // The divisor in 64ths is `ref_clock * 64 / (16 * baud)`.
+    let baud = u64::from(baud.0);
+    if baud == 0 {
+        return Err(ERR);
+    }
+    let div_64ths = ref_clock
+        .checked_mul(4)
+        .and_then(|x| x.0.checked_add(baud / 2))
+        .ok_or(ERR)?
+        / baud;
+
+    let int = div_64ths >> 6;
+    let frac = div_64ths & 0x3F;
+
+    if int == 0 || int > 0xFFFF {
+        return Err(ERR);
+    }
+
+    Ok((int as u32, frac as u32))
 }
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+impl RxRing {
+    const fn new() -> Self {
+        Self {
+            chars: [0; config::console::RX_RING_DEPTH],
+            timestamps: [Duration::from_secs(0); NUM_RX_TIMESTAMPS],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Add a character that arrived at `timestamp`. Returns false if the ring is full.
+    fn push(&mut self, c: u8, timestamp: Duration) -> bool {
+        if self.len == self.chars.len() {
+            return false;
+        }
+
+        let i = (self.head + self.len) % self.chars.len();
+        self.chars[i] = c;
+        if let Some(x) = self.timestamps.get_mut(i) {
+            *x = timestamp;
+        }
+        self.len += 1;
+
+        true
+    }
+
+    /// Remove the oldest character, together with its arrival time if it was recorded.
+    fn pop(&mut self) -> Option<(u8, Option<Duration>)> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let i = self.head;
+        self.head = (self.head + 1) % self.chars.len();
+        self.len -= 1;
+
+        Some((self.chars[i], self.timestamps.get(i).copied()))
+    }
+}
+
 impl PL011UartInner {
     /// Create an instance.
     ///
@@ -254,28 +442,120 @@ impl PL011UartInner {
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
         Self {
             registers: Registers::new(mmio_start_addr),
+            region: None,
             chars_written: 0,
             chars_read: 0,
+            breaks_received: 0,
         }
     }
 
-    /// Set up baud rate and characteristics.
+    /// Read the peripheral ID and the PrimeCell ID.
+    fn identification(&self) -> (u32, u32) {
+        (
+            id_from_bytes(&self.registers.PeriphID),
+            id_from_bytes(&self.registers.PCellID),
+        )
+    }
+
+    /// Bring the UART up with 8N1 and the default reference clock's divisors.
     ///
-    /// This results in 8N1 and 921_600 baud.
+    /// Only touches `CR`, `LCR_H`, `IBRD` and `FBRD`, so that the driver's later `init()` finds
+    /// everything else in its reset state.
+    fn emergency_configure(registers: &Registers) {
+        // The default clock generates the configured baud rate, see `baud_divisors_work()`.
+        let (int, frac) = match baud_divisors(DEFAULT_REF_CLOCK, config::console::BAUD_RATE) {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+
+        registers.CR.set(0);
+        registers.IBRD.write(IBRD::BAUD_DIVINT.val(int));
+        registers.FBRD.write(FBRD::BAUD_DIVFRAC.val(frac));
+        registers
+            .LCR_H
+            .write(LCR_H::WLEN::EightBit + LCR_H::FEN::FifosEnabled);
+        registers
+            .CR
+            .write(CR::UARTEN::Enabled + CR::TXE::Enabled + CR::RXE::Enabled);
+
+        EMERGENCY_CONFIGURED.store(true, Ordering::Release);
+    }
+
+    /// Re-apply the driver's settings if the emergency output reprogrammed the UART.
+    fn reconfigure_after_emergency(&mut self) {
+        if !EMERGENCY_CONFIGURED.load(Ordering::Acquire) || !CONFIGURED.load(Ordering::Acquire) {
+            return;
+        }
+
+        // The settings were applied successfully before, so they will be again.
+        let _ = unsafe { self.init(None) };
+    }
+
+    /// Write a string straight to the data register of the UART at `mmio_start_addr`.
     ///
-    /// The calculation for the BRD is (we set the clock to 48 MHz in config.txt):
-    /// `(48_000_000 / 16) / 921_600 = 3.2552083`.
+    /// This is the emergency output for when the regular paths cannot be trusted anymore. There is
+    /// no locking and no formatting. If no driver instance has programmed the UART yet, it is
+    /// brought up with default settings first. Waiting for space in the TX FIFO is bounded, so a
+    /// UART that is stuck or switched off cannot hang the caller.
     ///
-    /// This means the integer part is `3` and goes into the `IBRD`.
-    /// The fractional part is `0.2552083`.
+    /// # Safety
     ///
-    /// `FBRD` calculation according to the PL011 Technical Reference Manual:
-    /// `INTEGER((0.2552083 * 64) + 0.5) = 16`.
+    /// - The user must ensure to provide a correct MMIO start address.
+    pub unsafe fn emergency_write(mmio_start_addr: usize, s: &str) {
+        const MAX_SPINS_PER_CHAR: usize = 100_000;
+
+        COUNTERS
+            .tx_blocking_fallbacks
+            .fetch_add(1, Ordering::Relaxed);
+        let registers = Registers::new(mmio_start_addr);
+
+        if !CONFIGURED.load(Ordering::Acquire) {
+            Self::emergency_configure(&registers);
+        }
+
+        for b in s.bytes() {
+            for _ in 0..MAX_SPINS_PER_CHAR {
+                if !registers.FR.matches_all(FR::TXFF::SET) {
+                    break;
+                }
+                cpu::nop();
+            }
+
+            registers.DR.set(b as u32);
+        }
+    }
+
+    /// Write a string to the UART at the physical `mmio_start_addr`, with the MMU switched off.
     ///
-    /// Therefore, the generated baud rate divider is: `3 + 16/64 = 3.25`. Which results in a
-    /// genrated baud rate of `48_000_000 / (16 * 3.25) = 923_077`.
+    /// Like `emergency_write()`, but for when the UART's virtual mapping cannot be trusted either.
     ///
-    /// Error = `((923_077 - 921_600) / 921_600) * 100 = 0.16%`.
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    /// - See `memory::mmu::write_with_mmu_disabled()`.
+    pub unsafe fn emergency_write_phys(
+        mmio_start_addr: memory::Address<memory::Physical>,
+        s: &str,
+    ) -> Result<(), &'static str> {
+        // Count before the data cache goes off, atomics need it.
+        COUNTERS
+            .tx_blocking_fallbacks
+            .fetch_add(1, Ordering::Relaxed);
+
+        let tx = memory::mmu::PhysTransmitter {
+            data_reg: mmio_start_addr + DR_OFFSET,
+            flags_reg: mmio_start_addr + FR_OFFSET,
+            busy_mask: FR::TXFF::SET.value,
+        };
+
+        memory::mmu::write_with_mmu_disabled(&tx, s.as_bytes())
+    }
+
+    /// Set up baud rate and characteristics.
+    ///
+    /// This results in 8N1 and 921_600 baud.
+    ///
+    /// The divisor is derived from the reference clock, see `baud_divisors()`.
     ///
     /// # Safety
     ///
@@ -295,6 +575,9 @@ impl PL011UartInner {
         // Hence, flush first to ensure all pending characters are transmitted.
         self.flush();
 
+        // Settings that the emergency output applies from here on are overwritten below anyway.
+        EMERGENCY_CONFIGURED.store(false, Ordering::Release);
+
         // Turn the UART off temporarily.
         self.registers.CR.set(0);
 
@@ -308,11 +591,21 @@ impl PL011UartInner {
         // contents of IBRD or FBRD, a LCR_H write must always be performed at the end.
         //
         // Set the baud rate, 8N1 and FIFO enabled.
-        self.registers.IBRD.write(IBRD::BAUD_DIVINT.val(3));
-        self.registers.FBRD.write(FBRD::BAUD_DIVFRAC.val(16));
-        self.registers
-            .LCR_H
-            .write(LCR_H::WLEN::EightBit + LCR_H::FEN::FifosEnabled);
+        let (int, frac) = baud_divisors(
+            Hertz(REF_CLOCK_HZ.load(Ordering::Relaxed)),
+            config::console::BAUD_RATE,
+        )?;
+        self.registers.IBRD.write(IBRD::BAUD_DIVINT.val(int));
+        self.registers.FBRD.write(FBRD::BAUD_DIVFRAC.val(frac));
+
+        // Without the FIFOs, each received character raises an IRQ of its own, and its timestamp
+        // is only off by the IRQ latency.
+        let fifos = if config::console::RX_TIMESTAMPS {
+            LCR_H::FEN::FifosDisabled
+        } else {
+            LCR_H::FEN::FifosEnabled
+        };
+        self.registers.LCR_H.write(LCR_H::WLEN::EightBit + fifos);
 
         // Set RX FIFO fill level at 1/8.
         self.registers.IFLS.write(IFLS::RXIFLSEL::OneEigth);
@@ -327,14 +620,17 @@ impl PL011UartInner {
             .CR
             .write(CR::UARTEN::Enabled + CR::TXE::Enabled + CR::RXE::Enabled);
 
+        CONFIGURED.store(true, Ordering::Release);
+
         Ok(())
     }
 
     /// Send a character.
     fn write_char(&mut self, c: char) {
-        // Spin while TX FIFO full is set, waiting for an empty slot.
-        while self.registers.FR.matches_all(FR::TXFF::SET) {
-            cpu::nop();
+        // Wait while TX FIFO full is set, waiting for an empty slot. A wedged UART loses the
+        // character instead of hanging the caller.
+        if poll_register!(TX_TIMEOUT, self.registers.FR, FR::TXFF::CLEAR).is_err() {
+            return;
         }
 
         // Write the character to the buffer.
@@ -343,31 +639,160 @@ impl PL011UartInner {
         self.chars_written += 1;
     }
 
-    /// Block execution until the last buffered character has been physically put on the TX wire.
-    fn flush(&self) {
-        // Spin until the busy bit is cleared.
-        while self.registers.FR.matches_all(FR::BUSY::SET) {
-            cpu::nop();
+    /// Push bytes into the TX FIFO until it is full or `bytes` ran out. Returns the number of bytes
+    /// pushed.
+    fn fill_tx_fifo(&self, bytes: &mut core::slice::Iter<u8>) -> usize {
+        let mut burst = 0;
+
+        match &self.region {
+            Some(region) => {
+                let tx_fifo_full = || {
+                    region
+                        .read32(FR_OFFSET)
+                        .map_or(true, |x| (x & FR::TXFF::SET.value) != 0)
+                };
+
+                while !tx_fifo_full() {
+                    match bytes.next() {
+                        None => break,
+                        Some(&x) => {
+                            if region.write32(DR_OFFSET, x as u32).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    burst += 1;
+                }
+            }
+            None => {
+                while !self.registers.FR.matches_all(FR::TXFF::SET) {
+                    match bytes.next() {
+                        None => break,
+                        Some(&x) => self.registers.DR.set(x as u32),
+                    }
+                    burst += 1;
+                }
+            }
         }
+
+        burst
     }
 
-    /// Retrieve a character.
-    fn read_char_converting(&mut self, blocking_mode: BlockingMode) -> Option<char> {
-        // If RX FIFO is empty,
-        if self.registers.FR.matches_all(FR::RXFE::SET) {
-            // immediately return in non-blocking mode.
-            if blocking_mode == BlockingMode::NonBlocking {
-                return None;
+    /// Send raw bytes.
+    ///
+    /// Fills the TX FIFO in bursts, checking the FIFO state only when it was full.
+    fn write_array(&mut self, data: &[u8]) {
+        let mut bytes = data.iter();
+
+        while !bytes.as_slice().is_empty() {
+            // Wait for at least one free slot. A wedged UART loses the rest of the data.
+            if poll_register!(TX_TIMEOUT, self.registers.FR, FR::TXFF::CLEAR).is_err() {
+                break;
             }
 
-            // Otherwise, wait until a char was received.
-            while self.registers.FR.matches_all(FR::RXFE::SET) {
-                cpu::nop();
+            // Then push as many bytes as the FIFO takes.
+            let burst = self.fill_tx_fifo(&mut bytes);
+
+            COUNTERS
+                .tx_queue_high_water
+                .fetch_max(burst, Ordering::Relaxed);
+        }
+
+        self.chars_written += data.len() - bytes.as_slice().len();
+    }
+
+    /// Receive raw bytes until `buf` is full or `timeout` has elapsed.
+    ///
+    /// Drains the RX FIFO in bursts, checking the time only when the FIFO ran empty.
+    fn read_array(&mut self, buf: &mut [u8], timeout: Duration) -> usize {
+        use time::interface::TimeManager;
+
+        let deadline = time::time_manager().uptime() + timeout;
+        let mut num_read = 0;
+
+        while num_read < buf.len() {
+            let remaining = deadline
+                .checked_sub(time::time_manager().uptime())
+                .unwrap_or_default();
+
+            if poll_register!(remaining, self.registers.FR, FR::RXFE::CLEAR).is_err() {
+                break;
+            }
+
+            let mut burst = 0;
+            while num_read < buf.len() && !self.registers.FR.matches_all(FR::RXFE::SET) {
+                buf[num_read] = self.read_data();
+                num_read += 1;
+                burst += 1;
+            }
+
+            COUNTERS
+                .rx_queue_high_water
+                .fetch_max(burst, Ordering::Relaxed);
+        }
+
+        self.chars_read += num_read;
+
+        num_read
+    }
+
+    /// Read one character from the data register. Count an overrun that happened before it, and
+    /// whether it is a break.
+    fn read_data(&mut self) -> u8 {
+        let data = self.registers.DR.get();
+
+        if data & DR_OE != 0 {
+            COUNTERS.rx_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if data & DR_BE != 0 {
+            self.breaks_received += 1;
+        }
+
+        data as u8
+    }
+
+    /// Block execution until the last buffered character has been physically put on the TX wire.
+    fn flush(&self) {
+        // Spin until the busy bit is cleared, or give up on a wedged UART.
+        let _ = poll_register!(TX_TIMEOUT, self.registers.FR, FR::BUSY::CLEAR);
+    }
+
+    /// Send a byte with loopback enabled, and check that it is received.
+    ///
+    /// Input that is waiting in the RX FIFO is discarded.
+    fn loopback_test(&mut self) -> Result<(), &'static str> {
+        const PATTERN: u8 = 0xA5;
+
+        self.flush();
+        self.registers.CR.modify(CR::LBE::Enabled);
+        self.registers.DR.set(PATTERN as u32);
+
+        let mut result = Err("Sent byte was not received");
+        while poll_register!(TX_TIMEOUT, self.registers.FR, FR::RXFE::CLEAR).is_ok() {
+            if self.read_data() == PATTERN {
+                result = Ok(());
+                break;
             }
         }
 
+        self.flush();
+        self.registers.CR.modify(CR::LBE::Disabled);
+
+        result
+    }
+
+    /// Retrieve a character, if the RX FIFO holds one.
+    ///
+    /// Both the console's reads and the IRQ handler go through here, so `chars_read` counts every
+    /// character exactly once.
+    fn read_char_converting(&mut self) -> Option<char> {
+        if self.registers.FR.matches_all(FR::RXFE::SET) {
+            return None;
+        }
+
         // Read one character.
-        let mut ret = self.registers.DR.get() as u8 as char;
+        let mut ret = self.read_data() as char;
 
         // Convert carrige return to newline.
         if ret == '\r' {
@@ -417,9 +842,49 @@ impl PL011Uart {
             inner: IRQSafeNullLock::new(PL011UartInner::new(
                 mmio_descriptor.start_addr().into_usize(),
             )),
+            rx_ring: IRQSafeNullLock::new(RxRing::new()),
             irq_number,
+            echo: AtomicBool::new(true),
         }
     }
+
+    /// Check the UART's transmit and receive paths with its internal loopback.
+    ///
+    /// Not every emulated PL011 implements the loopback, so a failure does not necessarily mean that
+    /// the UART is broken.
+    pub fn loopback_test(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.loopback_test())
+    }
+
+    /// Move the received characters into the ring, stamped with the time of their arrival. Called
+    /// from the IRQ handler.
+    fn keep_received(&self, inner: &mut PL011UartInner, echo: bool) {
+        use time::interface::TimeManager;
+
+        while !inner.registers.FR.matches_all(FR::RXFE::SET) {
+            let timestamp = time::time_manager().uptime();
+            let c = inner.read_data();
+            inner.chars_read += 1;
+
+            if !self.rx_ring.lock(|ring| ring.push(c, timestamp)) {
+                COUNTERS.rx_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+
+            if echo {
+                inner.write_char(c as char)
+            }
+        }
+    }
+
+    /// Reprogram the baud rate divisor for the given reference clock.
+    ///
+    /// Keeps the old clock if the baud rate can not be generated from the new one.
+    pub fn set_ref_clock(&self, ref_clock: Hertz) -> Result<(), &'static str> {
+        baud_divisors(ref_clock, config::console::BAUD_RATE)?;
+
+        REF_CLOCK_HZ.store(ref_clock.0, Ordering::Relaxed);
+        self.inner.lock(|inner| unsafe { inner.init(None) })
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -432,11 +897,19 @@ impl driver::interface::DeviceDriver for PL011Uart {
         "BCM PL011 UART"
     }
 
+    fn is_essential(&self) -> bool {
+        true
+    }
+
     unsafe fn init(&self) -> Result<(), &'static str> {
-        let virt_addr = memory::mmu::kernel_map_mmio(self.compatible(), &self.mmio_descriptor)?;
+        let region =
+            memory::mmu::kernel_map_mmio_region(self.compatible(), &self.mmio_descriptor)?.leak();
+        let virt_addr = region.start_addr();
 
-        self.inner
-            .lock(|inner| inner.init(Some(virt_addr.into_usize())))?;
+        self.inner.lock(|inner| {
+            inner.region = Some(region);
+            inner.init(Some(virt_addr.into_usize()))
+        })?;
 
         self.virt_mmio_start_addr
             .store(virt_addr.into_usize(), Ordering::Relaxed);
@@ -444,6 +917,17 @@ impl driver::interface::DeviceDriver for PL011Uart {
         Ok(())
     }
 
+    fn verify_mmio(&self) -> Result<(), &'static str> {
+        let (periph_id, pcell_id) = self.inner.lock(|inner| inner.identification());
+
+        driver::check_mmio_id(self.compatible(), pcell_id, PCELL_ID)?;
+        driver::check_mmio_id(
+            self.compatible(),
+            periph_id & PERIPH_ID_MASK,
+            PERIPH_ID_PL011,
+        )
+    }
+
     fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
         use bsp::exception::asynchronous::irq_manager;
         use exception::asynchronous::{interface::IRQManager, IRQDescriptor};
@@ -468,19 +952,43 @@ impl driver::interface::DeviceDriver for PL011Uart {
 
         Some(addr)
     }
+
+    fn irq_numbers(&self) -> &[bsp::device_driver::IRQNumber] {
+        core::slice::from_ref(&self.irq_number)
+    }
+
+    fn shutdown(&self, _reason: ShutdownReason) -> Result<(), &'static str> {
+        // Get everything that was printed so far onto the wire before the reset cuts it off.
+        self.inner.lock(|inner| inner.flush());
+
+        Ok(())
+    }
 }
 
 impl console::interface::Write for PL011Uart {
     /// Passthrough of `args` to the `core::fmt::Write` implementation, but guarded by a Mutex to
     /// serialize access.
     fn write_char(&self, c: char) {
-        self.inner.lock(|inner| inner.write_char(c));
+        self.inner.lock(|inner| {
+            inner.reconfigure_after_emergency();
+            inner.write_char(c)
+        });
     }
 
     fn write_fmt(&self, args: core::fmt::Arguments) -> fmt::Result {
         // Fully qualified syntax for the call to `core::fmt::Write::write:fmt()` to increase
         // readability.
-        self.inner.lock(|inner| fmt::Write::write_fmt(inner, args))
+        self.inner.lock(|inner| {
+            inner.reconfigure_after_emergency();
+            fmt::Write::write_fmt(inner, args)
+        })
+    }
+
+    fn write_array(&self, data: &[u8]) {
+        self.inner.lock(|inner| {
+            inner.reconfigure_after_emergency();
+            inner.write_array(data)
+        });
     }
 
     fn flush(&self) {
@@ -490,18 +998,51 @@ impl console::interface::Write for PL011Uart {
 }
 
 impl console::interface::Read for PL011Uart {
-    fn read_char(&self) -> char {
-        self.inner
-            .lock(|inner| inner.read_char_converting(BlockingMode::Blocking).unwrap())
+    fn try_read_char(&self) -> Option<char> {
+        // Characters that the IRQ handler kept came first.
+        if let Some((c, _)) = self.rx_ring.lock(|ring| ring.pop()) {
+            return Some(if c == b'\r' { '\n' } else { c as char });
+        }
+
+        self.inner.lock(|inner| inner.read_char_converting())
+    }
+
+    fn read_array(&self, buf: &mut [u8], timeout: Duration) -> usize {
+        let mut num_read = 0;
+        while num_read < buf.len() {
+            match self.rx_ring.lock(|ring| ring.pop()) {
+                None => break,
+                Some((c, _)) => buf[num_read] = c,
+            }
+            num_read += 1;
+        }
+
+        num_read
+            + self
+                .inner
+                .lock(|inner| inner.read_array(&mut buf[num_read..], timeout))
     }
 
     fn clear_rx(&self) {
         // Read from the RX FIFO until it is indicating empty.
-        while self
-            .inner
-            .lock(|inner| inner.read_char_converting(BlockingMode::NonBlocking))
-            .is_some()
-        {}
+        while self.try_read_char().is_some() {}
+    }
+
+    fn set_echo(&self, enabled: bool) {
+        self.echo.store(enabled, Ordering::Relaxed);
+    }
+
+    fn is_echo_enabled(&self) -> bool {
+        self.echo.load(Ordering::Relaxed)
+    }
+}
+
+impl console::interface::TimestampedRead for PL011Uart {
+    fn read_char_timestamped(&self) -> Option<(u8, Duration)> {
+        match self.rx_ring.lock(|ring| ring.pop()) {
+            Some((c, Some(timestamp))) => Some((c, timestamp)),
+            _ => None,
+        }
     }
 }
 
@@ -513,11 +1054,31 @@ impl console::interface::Statistics for PL011Uart {
     fn chars_read(&self) -> usize {
         self.inner.lock(|inner| inner.chars_read)
     }
+
+    fn tx_queue_high_water(&self) -> usize {
+        COUNTERS.tx_queue_high_water.load(Ordering::Relaxed)
+    }
+
+    fn rx_queue_high_water(&self) -> usize {
+        COUNTERS.rx_queue_high_water.load(Ordering::Relaxed)
+    }
+
+    fn rx_dropped(&self) -> usize {
+        COUNTERS.rx_dropped.load(Ordering::Relaxed)
+    }
+
+    fn tx_blocking_fallbacks(&self) -> usize {
+        COUNTERS.tx_blocking_fallbacks.load(Ordering::Relaxed)
+    }
 }
 
 impl exception::asynchronous::interface::IRQHandler for PL011Uart {
-    fn handle(&self) -> Result<(), &'static str> {
-        self.inner.lock(|inner| {
+    fn handle(&self, _context: &exception::ExceptionContext) -> Result<(), &'static str> {
+        let echo = self.echo.load(Ordering::Relaxed);
+        let rx_dropped = COUNTERS.rx_dropped.load(Ordering::Relaxed);
+
+        let break_received = self.inner.lock(|inner| {
+            let breaks_received = inner.breaks_received;
             let pending = inner.registers.MIS.extract();
 
             // Clear all pending IRQs.
@@ -525,13 +1086,88 @@ impl exception::asynchronous::interface::IRQHandler for PL011Uart {
 
             // Check for any kind of RX interrupt.
             if pending.matches_any(MIS::RXMIS::SET + MIS::RTMIS::SET) {
-                // Echo any received characters.
-                while let Some(c) = inner.read_char_converting(BlockingMode::NonBlocking) {
-                    inner.write_char(c)
+                if config::console::RX_TIMESTAMPS {
+                    self.keep_received(inner, echo);
+                } else {
+                    // Echo any received characters, if enabled.
+                    let mut burst = 0;
+                    while let Some(c) = inner.read_char_converting() {
+                        burst += 1;
+                        if echo {
+                            inner.write_char(c)
+                        }
+                    }
+
+                    COUNTERS
+                        .rx_queue_high_water
+                        .fetch_max(burst, Ordering::Relaxed);
                 }
             }
+
+            inner.breaks_received != breaks_received
         });
 
+        // A break is not an error. It is how the user asks for the monitor.
+        if break_received && config::console::BREAK_ENTERS_MONITOR {
+            monitor::request_entry();
+        }
+
+        // Warn outside of the lock, because the warning goes out through this UART as well.
+        if COUNTERS.rx_dropped.load(Ordering::Relaxed) != rx_dropped {
+            warn_rate_limited!(
+                OVERRUN_WARNINGS,
+                "PL011: Receive FIFO overrun or ring full, input was lost"
+            );
+        }
+
         Ok(())
     }
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check the divisors against the worked example, and that a too slow clock is rejected.
+    #[kernel_test]
+    fn baud_divisors_work() {
+        assert_eq!(
+            baud_divisors(Hertz::from_mhz(48), Baud(921_600)),
+            Ok((3, 16))
+        );
+        assert_eq!(
+            baud_divisors(Hertz::from_mhz(3), Baud(115_200)),
+            Ok((1, 40))
+        );
+        assert!(baud_divisors(Hertz::from_mhz(3), Baud(921_600)).is_err());
+        assert!(baud_divisors(Hertz(u64::MAX), Baud(115_200)).is_err());
+        assert!(baud_divisors(Hertz::from_mhz(48), Baud(0)).is_err());
+    }
+
+    /// Check that the ring returns characters in order, and rejects them when full.
+    #[kernel_test]
+    fn rx_ring_works() {
+        let mut ring = RxRing::new();
+        let depth = config::console::RX_RING_DEPTH;
+
+        for i in 0..depth {
+            assert!(ring.push(i as u8, Duration::from_micros(i as u64)));
+        }
+        assert!(!ring.push(0xFF, Duration::from_secs(1)));
+
+        for i in 0..depth {
+            let (c, timestamp) = ring.pop().unwrap();
+
+            assert_eq!(c, i as u8);
+            if config::console::RX_TIMESTAMPS {
+                assert_eq!(timestamp, Some(Duration::from_micros(i as u64)));
+            }
+        }
+        assert!(ring.pop().is_none());
+    }
+}
@@ -0,0 +1,266 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Power Management Watchdog Driver.
+//!
+//! The watchdog of the BCM's power management block is the only way to reset the board. Before the
+//! reset is triggered, the firmware is told which boot partition to start from. The special
+//! partition 63 makes the firmware halt instead of booting, which is how the board is powered
+//! "off".
+
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper, driver, memory, shutdown::ShutdownReason,
+    synchronization, synchronization::IRQSafeNullLock,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use register::{mmio::*, register_bitfields, register_structs};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Power management registers.
+//
+// There is no official documentation. The layout is taken from the Linux bcm2835_wdt driver.
+register_bitfields! {
+    u32,
+
+    /// Reset Control
+    RSTC [
+        /// Password that must accompany every write.
+        PASSWD OFFSET(24) NUMBITS(8) [
+            Password = 0x5a
+        ],
+
+        /// Reset configuration.
+        WRCFG OFFSET(4) NUMBITS(2) [
+            Clear = 0b00,
+            FullReset = 0b10
+        ]
+    ],
+
+    /// Watchdog
+    WDOG [
+        /// Password that must accompany every write.
+        PASSWD OFFSET(24) NUMBITS(8) [
+            Password = 0x5a
+        ],
+
+        /// Ticks until the watchdog fires. One tick is roughly 16 µs.
+        TIME OFFSET(0) NUMBITS(20) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => _reserved1),
+        (0x1C => RSTC: ReadWrite<u32, RSTC::Register>),
+        (0x20 => RSTS: ReadWrite<u32>),
+        (0x24 => WDOG: ReadWrite<u32, WDOG::Register>),
+        (0x28 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// Password that must accompany every write to RSTS.
+const RSTS_PASSWORD: u32 = 0x5a00_0000;
+
+/// Bits of RSTS that carry the boot partition. The partition number's bit `n` is stored in bit
+/// `2 * n`.
+const RSTS_PARTITION_MASK: u32 = 0x0000_0555;
+
+/// Watchdog ticks until the reset.
+const RESET_TICKS: u32 = 10;
+
+/// Watchdog ticks until the reset that is armed during a shutdown for rebooting, roughly one
+/// second. The reset follows much earlier unless the remaining shutdown hangs.
+const SHUTDOWN_FALLBACK_TICKS: u32 = 62_500;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Boot partitions the firmware can be asked to start from after the reset.
+#[derive(Copy, Clone)]
+pub enum BootPartition {
+    /// Boot as usual.
+    Default,
+
+    /// Do not boot at all, and halt in the firmware.
+    Halt,
+}
+
+pub struct PMWatchdogInner {
+    registers: Registers,
+}
+
+// Export the inner struct so that BSPs can use it for the panic handler.
+pub use PMWatchdogInner as PanicPMWatchdog;
+
+/// Representation of the power management watchdog.
+pub struct PMWatchdog {
+    mmio_descriptor: memory::mmu::MMIODescriptor,
+    virt_mmio_start_addr: AtomicUsize,
+    inner: IRQSafeNullLock<PMWatchdogInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl BootPartition {
+    /// The partition number in the RSTS encoding.
+    fn to_rsts_bits(self) -> u32 {
+        let partition: u32 = match self {
+            BootPartition::Default => 0,
+            BootPartition::Halt => 63,
+        };
+
+        (0..6).fold(0, |bits, n| bits | ((partition >> n) & 1) << (2 * n))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl PMWatchdogInner {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+        }
+    }
+
+    /// Init code.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    pub unsafe fn init(&mut self, new_mmio_start_addr: Option<usize>) -> Result<(), &'static str> {
+        if let Some(addr) = new_mmio_start_addr {
+            self.registers = Registers::new(addr);
+        }
+
+        Ok(())
+    }
+
+    /// Select the boot partition and let the watchdog reset the board shortly after.
+    pub fn reset(&mut self, partition: BootPartition) {
+        self.arm(partition, RESET_TICKS);
+    }
+
+    /// Select the boot partition and let the watchdog reset the board after `ticks`.
+    pub fn arm(&mut self, partition: BootPartition, ticks: u32) {
+        let rsts = (self.registers.RSTS.get() & !RSTS_PARTITION_MASK) | partition.to_rsts_bits();
+        self.registers.RSTS.set(RSTS_PASSWORD | rsts);
+
+        self.registers
+            .WDOG
+            .write(WDOG::PASSWD::Password + WDOG::TIME.val(ticks));
+        self.registers
+            .RSTC
+            .modify(RSTC::PASSWD::Password + RSTC::WRCFG::FullReset);
+    }
+
+    /// Stop a pending reset.
+    pub fn disarm(&mut self) {
+        self.registers
+            .RSTC
+            .modify(RSTC::PASSWD::Password + RSTC::WRCFG::Clear);
+    }
+}
+
+impl PMWatchdog {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct MMIO descriptors.
+    pub const unsafe fn new(mmio_descriptor: memory::mmu::MMIODescriptor) -> Self {
+        Self {
+            mmio_descriptor,
+            virt_mmio_start_addr: AtomicUsize::new(0),
+            inner: IRQSafeNullLock::new(PMWatchdogInner::new(
+                mmio_descriptor.start_addr().into_usize(),
+            )),
+        }
+    }
+
+    /// Concurrency safe version of `PMWatchdogInner.reset()`
+    pub fn reset(&self, partition: BootPartition) {
+        self.inner.lock(|inner| inner.reset(partition))
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl driver::interface::DeviceDriver for PMWatchdog {
+    fn compatible(&self) -> &'static str {
+        "BCM PM Watchdog"
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let virt_addr = memory::mmu::kernel_map_mmio(self.compatible(), &self.mmio_descriptor)?;
+
+        self.inner
+            .lock(|inner| inner.init(Some(virt_addr.into_usize())))?;
+
+        self.virt_mmio_start_addr
+            .store(virt_addr.into_usize(), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn virt_mmio_start_addr(&self) -> Option<usize> {
+        let addr = self.virt_mmio_start_addr.load(Ordering::Relaxed);
+
+        if addr == 0 {
+            return None;
+        }
+
+        Some(addr)
+    }
+
+    fn shutdown(&self, reason: ShutdownReason) -> Result<(), &'static str> {
+        // A reboot must happen even if the rest of the shutdown hangs. A halt must not turn into a
+        // reboot because of a reset that is still pending.
+        self.inner.lock(|inner| {
+            if reason.resets() {
+                inner.arm(BootPartition::Default, SHUTDOWN_FALLBACK_TICKS)
+            } else {
+                inner.disarm()
+            }
+        });
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check the spread-out encoding of the boot partition.
+    #[kernel_test]
+    fn boot_partition_encoding_works() {
+        assert_eq!(BootPartition::Default.to_rsts_bits(), 0);
+        assert_eq!(BootPartition::Halt.to_rsts_bits(), RSTS_PARTITION_MASK);
+    }
+}
@@ -0,0 +1,323 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! System Timer Driver.
+//!
+//! A free-running 64-bit counter at `config::time::SYSTEM_TIMER_TICK_RATE`, with four compare
+//! channels. Channels 0 and 2 are used by the VideoCore, so only channels 1 and 3 are offered. A
+//! channel raises its IRQ when the lower 32 bits of the counter match its compare value.
+
+use crate::{
+    bsp, bsp::device_driver::common::MMIODerefWrapper, config, driver, exception, memory,
+    synchronization, synchronization::IRQSafeNullLock, time, units::Hertz,
+};
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use register::{mmio::*, register_bitfields, register_structs};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// System timer registers.
+//
+// Descriptions taken from
+// - https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+register_bitfields! {
+    u32,
+
+    /// Control/Status
+    CS [
+        /// Channel 3 matched. Write 1 to clear.
+        M3 OFFSET(3) NUMBITS(1) [],
+
+        /// Channel 1 matched. Write 1 to clear.
+        M1 OFFSET(1) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => CS: ReadWrite<u32, CS::Register>),
+        (0x04 => CLO: ReadOnly<u32>),
+        (0x08 => CHI: ReadOnly<u32>),
+        (0x0C => _reserved1),
+        (0x10 => C1: ReadWrite<u32>),
+        (0x14 => _reserved2),
+        (0x18 => C3: ReadWrite<u32>),
+        (0x1C => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The compare channels that are free for the ARM.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Channel {
+    C1,
+    C3,
+}
+
+pub struct SystemTimerInner {
+    registers: Registers,
+    on_match: [Option<fn()>; 2],
+}
+
+/// Representation of the system timer.
+pub struct SystemTimer {
+    mmio_descriptor: memory::mmu::MMIODescriptor,
+    virt_mmio_start_addr: AtomicUsize,
+    irq_numbers: [bsp::device_driver::IRQNumber; 2],
+    inner: IRQSafeNullLock<SystemTimerInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Channel {
+    fn index(self) -> usize {
+        match self {
+            Channel::C1 => 0,
+            Channel::C3 => 1,
+        }
+    }
+}
+
+impl SystemTimerInner {
+    fn set_compare(&mut self, channel: Channel, val: u32) {
+        match channel {
+            Channel::C1 => self.registers.C1.set(val),
+            Channel::C3 => self.registers.C3.set(val),
+        }
+    }
+
+    fn clear_match(&mut self, channel: Channel) {
+        match channel {
+            Channel::C1 => self.registers.CS.write(CS::M1::SET),
+            Channel::C3 => self.registers.CS.write(CS::M3::SET),
+        }
+    }
+
+    /// Take the callbacks of the matched channels and acknowledge the matches.
+    fn take_matched(&mut self) -> [Option<fn()>; 2] {
+        let mut matched = [None; 2];
+
+        for channel in [Channel::C1, Channel::C3].iter().copied() {
+            let is_matched = match channel {
+                Channel::C1 => self.registers.CS.is_set(CS::M1),
+                Channel::C3 => self.registers.CS.is_set(CS::M3),
+            };
+
+            if is_matched {
+                self.clear_match(channel);
+                matched[channel.index()] = self.on_match[channel.index()].take();
+            }
+        }
+
+        matched
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl SystemTimerInner {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            on_match: [None; 2],
+        }
+    }
+
+    /// Init code.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    pub unsafe fn init(&mut self, new_mmio_start_addr: Option<usize>) -> Result<(), &'static str> {
+        if let Some(addr) = new_mmio_start_addr {
+            self.registers = Registers::new(addr);
+        }
+
+        Ok(())
+    }
+
+    /// The 64-bit counter.
+    pub fn counter(&self) -> u64 {
+        // The halves are read separately. Retry if the upper half changed in between.
+        loop {
+            let hi = self.registers.CHI.get();
+            let lo = self.registers.CLO.get();
+
+            if self.registers.CHI.get() == hi {
+                return (u64::from(hi) << 32) | u64::from(lo);
+            }
+        }
+    }
+
+    /// Call `on_match` in IRQ context once `timeout` has passed.
+    ///
+    /// Replaces an earlier callback on the same channel that is still pending.
+    pub fn arm(
+        &mut self,
+        channel: Channel,
+        timeout: Duration,
+        on_match: fn(),
+    ) -> Result<(), &'static str> {
+        let ticks = config::time::SYSTEM_TIMER_TICK_RATE
+            .cycles_in(timeout)
+            .unwrap_or(u64::MAX);
+        if ticks == 0 || ticks > u64::from(u32::max_value()) {
+            return Err("System timer: Timeout out of range");
+        }
+
+        self.clear_match(channel);
+        self.on_match[channel.index()] = Some(on_match);
+
+        let compare = (self.registers.CLO.get()).wrapping_add(ticks as u32);
+        self.set_compare(channel, compare);
+
+        Ok(())
+    }
+
+    /// Drop the callback of `channel`, if any.
+    pub fn disarm(&mut self, channel: Channel) {
+        self.on_match[channel.index()] = None;
+        self.clear_match(channel);
+    }
+}
+
+impl SystemTimer {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct MMIO descriptors.
+    pub const unsafe fn new(
+        mmio_descriptor: memory::mmu::MMIODescriptor,
+        irq_numbers: [bsp::device_driver::IRQNumber; 2],
+    ) -> Self {
+        Self {
+            mmio_descriptor,
+            virt_mmio_start_addr: AtomicUsize::new(0),
+            irq_numbers,
+            inner: IRQSafeNullLock::new(SystemTimerInner::new(
+                mmio_descriptor.start_addr().into_usize(),
+            )),
+        }
+    }
+
+    /// Concurrency safe version of `SystemTimerInner.arm()`
+    pub fn arm(
+        &self,
+        channel: Channel,
+        timeout: Duration,
+        on_match: fn(),
+    ) -> Result<(), &'static str> {
+        self.inner
+            .lock(|inner| inner.arm(channel, timeout, on_match))
+    }
+
+    /// Concurrency safe version of `SystemTimerInner.disarm()`
+    pub fn disarm(&self, channel: Channel) {
+        self.inner.lock(|inner| inner.disarm(channel))
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl driver::interface::DeviceDriver for SystemTimer {
+    fn compatible(&self) -> &'static str {
+        "BCM System Timer"
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let virt_addr = memory::mmu::kernel_map_mmio(self.compatible(), &self.mmio_descriptor)?;
+
+        self.inner
+            .lock(|inner| inner.init(Some(virt_addr.into_usize())))?;
+
+        self.virt_mmio_start_addr
+            .store(virt_addr.into_usize(), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
+        use bsp::exception::asynchronous::irq_manager;
+        use exception::asynchronous::{interface::IRQManager, IRQDescriptor};
+
+        let descriptor = IRQDescriptor {
+            name: "BCM System Timer",
+            handler: self,
+        };
+
+        for irq_number in self.irq_numbers.iter().copied() {
+            irq_manager().register_handler(irq_number, descriptor)?;
+            irq_manager().enable(irq_number);
+        }
+
+        Ok(())
+    }
+
+    fn irq_numbers(&self) -> &[bsp::device_driver::IRQNumber] {
+        &self.irq_numbers
+    }
+
+    fn virt_mmio_start_addr(&self) -> Option<usize> {
+        let addr = self.virt_mmio_start_addr.load(Ordering::Relaxed);
+
+        if addr == 0 {
+            return None;
+        }
+
+        Some(addr)
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for SystemTimer {
+    fn handle(&self, _context: &exception::ExceptionContext) -> Result<(), &'static str> {
+        // Call the callbacks with the lock released, so that they can re-arm.
+        let matched = self.inner.lock(|inner| inner.take_matched());
+
+        for on_match in matched.iter().flatten() {
+            on_match();
+        }
+
+        Ok(())
+    }
+}
+
+impl time::interface::TimeSource for SystemTimer {
+    fn name(&self) -> &'static str {
+        "BCM System Timer"
+    }
+
+    fn frequency(&self) -> Hertz {
+        config::time::SYSTEM_TIMER_TICK_RATE
+    }
+
+    fn counter(&self) -> u64 {
+        self.inner.lock(|inner| inner.counter())
+    }
+}
@@ -4,7 +4,11 @@
 
 //! Common device driver code.
 
-use core::{marker::PhantomData, ops};
+use crate::{
+    exception, memory,
+    memory::{Address, Virtual},
+};
+use core::{cell::Cell, fmt, marker::PhantomData, mem, ops};
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -15,6 +19,50 @@ pub struct MMIODerefWrapper<T> {
     phantom: PhantomData<fn() -> T>,
 }
 
+/// A typed register block in an MMIO range.
+///
+/// Points at the physical start address until `map()` is called, which maps the range with
+/// `kernel_map_mmio()` and checks that the register block fits into it.
+///
+/// The type is `Send`, but not `Sync`, so that a driver can only share it when it is wrapped in one
+/// of the crate's locks.
+pub struct MMIORegisters<T> {
+    start_addr: usize,
+    phantom: PhantomData<fn() -> T>,
+    not_sync: PhantomData<Cell<()>>,
+}
+
+/// The highest number of a core's private interrupt.
+pub const MAX_LOCAL_IRQ_NUMBER: usize = 11;
+
+/// The highest number of a VideoCore peripheral's interrupt.
+pub const MAX_PERIPHERAL_IRQ_NUMBER: usize = 63;
+
+/// The highest number of an ARM-side peripheral's interrupt.
+pub const MAX_ARM_IRQ_NUMBER: usize = 7;
+
+/// A core's private interrupt, numbered like the sources of the BCM's local interrupt controller.
+/// For example, the core timers are 0..=3.
+pub type LocalIRQ = exception::asynchronous::IRQNumber<{ MAX_LOCAL_IRQ_NUMBER }>;
+
+/// An interrupt of the peripherals behind the VideoCore, like the UARTs and the system timer. The
+/// numbering is the same on all BCM SoCs.
+pub type PeripheralIRQ = exception::asynchronous::IRQNumber<{ MAX_PERIPHERAL_IRQ_NUMBER }>;
+
+/// An interrupt of the ARM-side peripherals, like the mailbox.
+pub type ArmIRQ = exception::asynchronous::IRQNumber<{ MAX_ARM_IRQ_NUMBER }>;
+
+/// Used for the associated type of trait [`exception::asynchronous::interface::IRQManager`].
+///
+/// The numbering is shared by the boards, so that the BSP's IRQ map is written once. Each interrupt
+/// controller driver translates it into its native numbering.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum IRQNumber {
+    Local(LocalIRQ),
+    Peripheral(PeripheralIRQ),
+    Arm(ArmIRQ),
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -29,6 +77,51 @@ impl<T> MMIODerefWrapper<T> {
     }
 }
 
+impl<T> MMIORegisters<T> {
+    /// Create an instance at the physical start address of `mmio_descriptor`.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO descriptor for `T`.
+    pub const unsafe fn new(mmio_descriptor: &memory::mmu::MMIODescriptor) -> Self {
+        Self {
+            start_addr: mmio_descriptor.start_addr().into_usize(),
+            phantom: PhantomData,
+            not_sync: PhantomData,
+        }
+    }
+
+    /// Map `mmio_descriptor` into the kernel's address space, and point the registers at it.
+    ///
+    /// # Safety
+    ///
+    /// - See `kernel_map_mmio()`.
+    pub unsafe fn map(
+        &mut self,
+        name: &'static str,
+        mmio_descriptor: &memory::mmu::MMIODescriptor,
+    ) -> Result<Address<Virtual>, &'static str> {
+        if mem::size_of::<T>() > mmio_descriptor.size() {
+            return Err("Register block does not fit into the MMIO descriptor");
+        }
+
+        let virt_addr = memory::mmu::kernel_map_mmio(name, mmio_descriptor)?;
+        self.start_addr = virt_addr.into_usize();
+
+        Ok(virt_addr)
+    }
+}
+
+impl fmt::Display for IRQNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IRQNumber::Local(x) => write!(f, "Local {}", x),
+            IRQNumber::Peripheral(x) => write!(f, "Peripheral {}", x),
+            IRQNumber::Arm(x) => write!(f, "ARM {}", x),
+        }
+    }
+}
+
 impl<T> ops::Deref for MMIODerefWrapper<T> {
     type Target = T;
 
@@ -36,3 +129,33 @@ impl<T> ops::Deref for MMIODerefWrapper<T> {
         unsafe { &*(self.start_addr as *const _) }
     }
 }
+
+impl<T> ops::Deref for MMIORegisters<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*(self.start_addr as *const _) }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check that a register block that is larger than its MMIO descriptor is not mapped.
+    #[kernel_test]
+    fn oversized_register_block_is_rejected() {
+        let descriptor = memory::mmu::MMIODescriptor::new(Address::new(0x1000_0000), 4);
+        let mut registers = unsafe { MMIORegisters::<[u32; 2]>::new(&descriptor) };
+
+        assert_eq!(
+            unsafe { registers.map("Oversized", &descriptor) }.err(),
+            Some("Register block does not fit into the MMIO descriptor")
+        );
+    }
+}
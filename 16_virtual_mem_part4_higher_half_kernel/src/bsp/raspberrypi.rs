@@ -4,43 +4,106 @@
 
 //! Top-level BSP file for the Raspberry Pi 3 and 4.
 
+pub mod board;
+pub mod clocks;
 pub mod console;
 pub mod cpu;
 pub mod driver;
 pub mod exception;
+pub mod fb_console;
+pub mod gpio;
+pub mod led;
 pub mod memory;
+pub mod thermal;
 
 use super::device_driver;
-use crate::memory::mmu::MMIODescriptor;
+use crate::{config, memory::mmu::MMIODescriptor, time};
+use exception::asynchronous::irq_map::BoardIRQ;
 use memory::map::mmio;
 
 //--------------------------------------------------------------------------------------------------
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
-static GPIO: device_driver::GPIO =
-    unsafe { device_driver::GPIO::new(MMIODescriptor::new(mmio::GPIO_START, mmio::GPIO_SIZE)) };
+static GPIO: device_driver::GPIO = unsafe {
+    device_driver::GPIO::new(MMIODescriptor::new_peripheral(
+        mmio::GPIO_START,
+        mmio::GPIO_SIZE,
+    ))
+};
 
 static PL011_UART: device_driver::PL011Uart = unsafe {
     device_driver::PL011Uart::new(
-        MMIODescriptor::new(mmio::PL011_UART_START, mmio::PL011_UART_SIZE),
-        exception::asynchronous::irq_map::PL011_UART,
+        MMIODescriptor::new_peripheral(mmio::PL011_UART_START, mmio::PL011_UART_SIZE),
+        BoardIRQ::PL011Uart.irq_number(),
+    )
+};
+
+static MAILBOX: device_driver::Mailbox = unsafe {
+    device_driver::Mailbox::new(
+        MMIODescriptor::new_peripheral(mmio::MAILBOX_START, mmio::MAILBOX_SIZE),
+        BoardIRQ::Mailbox.irq_number(),
+    )
+};
+
+static FRAMEBUFFER: device_driver::Framebuffer = unsafe {
+    device_driver::Framebuffer::new(
+        &MAILBOX,
+        device_driver::FramebufferMode {
+            width: config::framebuffer::WIDTH,
+            height: config::framebuffer::HEIGHT,
+            double_buffered: config::framebuffer::DOUBLE_BUFFERED,
+        },
+        MMIODescriptor::new_peripheral(mmio::SMI_START, mmio::SMI_SIZE),
+        BoardIRQ::SMI.irq_number(),
+    )
+};
+
+static PM_WATCHDOG: device_driver::PMWatchdog = unsafe {
+    device_driver::PMWatchdog::new(MMIODescriptor::new_peripheral(
+        mmio::PM_WATCHDOG_START,
+        mmio::PM_WATCHDOG_SIZE,
+    ))
+};
+
+static CLOCK_MANAGER: device_driver::ClockManager = unsafe {
+    device_driver::ClockManager::new(MMIODescriptor::new_peripheral(
+        mmio::CLOCK_MANAGER_START,
+        mmio::CLOCK_MANAGER_SIZE,
+    ))
+};
+
+static SYSTEM_TIMER: device_driver::SystemTimer = unsafe {
+    device_driver::SystemTimer::new(
+        MMIODescriptor::new_peripheral(mmio::SYSTEM_TIMER_START, mmio::SYSTEM_TIMER_SIZE),
+        [
+            BoardIRQ::SystemTimer1.irq_number(),
+            BoardIRQ::SystemTimer3.irq_number(),
+        ],
     )
 };
 
+static TIMEOUT_TIMER: time::TimeoutTimer = time::TimeoutTimer::new(
+    BoardIRQ::TimeoutTimerPhys.irq_number(),
+    BoardIRQ::TimeoutTimerVirt.irq_number(),
+);
+
+static PERFORMANCE_MONITOR: crate::cpu::pmu::PerformanceMonitor =
+    crate::cpu::pmu::PerformanceMonitor::new(BoardIRQ::PMU.irq_number());
+
 #[cfg(feature = "bsp_rpi3")]
 static INTERRUPT_CONTROLLER: device_driver::InterruptController = unsafe {
     device_driver::InterruptController::new(
-        MMIODescriptor::new(mmio::LOCAL_IC_START, mmio::LOCAL_IC_SIZE),
-        MMIODescriptor::new(mmio::PERIPHERAL_IC_START, mmio::PERIPHERAL_IC_SIZE),
+        MMIODescriptor::new_peripheral(mmio::LOCAL_IC_START, mmio::LOCAL_IC_SIZE),
+        MMIODescriptor::new_peripheral(mmio::PERIPHERAL_IC_START, mmio::PERIPHERAL_IC_SIZE),
     )
 };
 
 #[cfg(feature = "bsp_rpi4")]
 static INTERRUPT_CONTROLLER: device_driver::GICv2 = unsafe {
     device_driver::GICv2::new(
-        MMIODescriptor::new(mmio::GICD_START, mmio::GICD_SIZE),
-        MMIODescriptor::new(mmio::GICC_START, mmio::GICC_SIZE),
+        MMIODescriptor::new_peripheral(mmio::GICD_START, mmio::GICD_SIZE),
+        MMIODescriptor::new_peripheral(mmio::GICC_START, mmio::GICC_SIZE),
     )
 };
 
@@ -48,7 +111,7 @@ static INTERRUPT_CONTROLLER: device_driver::GICv2 = unsafe {
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
-/// Board identification.
+/// The board family the kernel was built for. See `board::info()` for the detected board.
 pub fn board_name() -> &'static str {
     #[cfg(feature = "bsp_rpi3")]
     {
@@ -60,3 +123,18 @@ pub fn board_name() -> &'static str {
         "Raspberry Pi 4"
     }
 }
+
+/// Return a reference to the clock manager, which derives the peripheral clocks.
+pub fn clock_manager() -> &'static device_driver::ClockManager {
+    &CLOCK_MANAGER
+}
+
+/// Return a reference to the framebuffer.
+pub fn framebuffer() -> &'static device_driver::Framebuffer {
+    &FRAMEBUFFER
+}
+
+/// Return a reference to the timer used for timeouts.
+pub fn timeout_timer() -> &'static time::TimeoutTimer {
+    &TIMEOUT_TIMER
+}
@@ -0,0 +1,473 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Board detection.
+//!
+//! The board family is chosen at compile time, because it decides the MMIO base and the interrupt
+//! controller. Within a family, the boards differ in details like the wiring of the ACT LED. These
+//! are selected at runtime from the detected board.
+//!
+//! The board is identified by the revision code that the firmware reports through the mailbox. If
+//! the mailbox does not answer, the `compatible` property of the device tree's root node is used.
+
+use super::{device_driver::tag, MAILBOX};
+use crate::{
+    banner, dtb,
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+    warn,
+};
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Bit that marks a new-style revision code. Old-style codes are only used by boards older than the
+/// Raspberry Pi 2.
+const REVISION_NEW_STYLE: u32 = 1 << 23;
+
+/// Root node `compatible` strings of the boards, as used by the firmware's device trees.
+const COMPATIBLE: [(&str, Model); 8] = [
+    ("raspberrypi,3-model-b", Model::Pi3B),
+    ("raspberrypi,3-model-b-plus", Model::Pi3BPlus),
+    ("raspberrypi,3-model-a-plus", Model::Pi3APlus),
+    ("raspberrypi,3-compute-module", Model::CM3),
+    ("raspberrypi,3-compute-module-plus", Model::CM3Plus),
+    ("raspberrypi,4-model-b", Model::Pi4B),
+    ("raspberrypi,400", Model::Pi400),
+    ("raspberrypi,4-compute-module", Model::CM4),
+];
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The SoC.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Soc {
+    BCM2837,
+    BCM2711,
+}
+
+/// Board models that this kernel can run on.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Model {
+    Pi3B,
+    Pi3BPlus,
+    Pi3APlus,
+    CM3,
+    CM3Plus,
+    Pi4B,
+    Pi400,
+    CM4,
+}
+
+/// The manufacturer field of the revision code.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Manufacturer {
+    SonyUK,
+    Egoman,
+    Embest,
+    SonyJapan,
+    Stadium,
+    Unknown(u32),
+}
+
+/// Where the board information was taken from.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Source {
+    Mailbox,
+    DeviceTree,
+    BuiltIn,
+}
+
+/// How the ACT LED is wired.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ActLed {
+    /// A GPIO pin of the SoC.
+    Gpio(u32),
+
+    /// A pin of the GPIO expander, which only the firmware can drive.
+    FirmwareGpio(u32),
+
+    /// Not wired, or unknown.
+    None,
+}
+
+/// Information about the board.
+#[derive(Copy, Clone, Debug)]
+pub struct BoardInfo {
+    /// The SoC. For the built-in information, the one the kernel was built for.
+    pub soc: Soc,
+
+    /// The model, if known.
+    pub model: Option<Model>,
+
+    /// The raw revision code, if the firmware reported it.
+    pub revision: Option<u32>,
+
+    /// The PCB revision, if known.
+    pub pcb_revision: Option<u32>,
+
+    /// The memory size in MiB, if known.
+    pub memory_mib: Option<u32>,
+
+    /// The manufacturer, if known.
+    pub manufacturer: Option<Manufacturer>,
+
+    /// Where the information was taken from.
+    pub source: Source,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static BOARD_INFO: InitStateLock<BoardInfo> = InitStateLock::new(BoardInfo::built_in());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Soc {
+    /// The SoC the kernel was built for.
+    const fn compiled_for() -> Self {
+        #[cfg(feature = "bsp_rpi3")]
+        {
+            Soc::BCM2837
+        }
+
+        #[cfg(feature = "bsp_rpi4")]
+        {
+            Soc::BCM2711
+        }
+    }
+
+    /// The value of the `BSP` make variable that builds for the SoC.
+    fn bsp(self) -> &'static str {
+        match self {
+            Soc::BCM2837 => "rpi3",
+            Soc::BCM2711 => "rpi4",
+        }
+    }
+}
+
+impl Model {
+    fn soc(self) -> Soc {
+        match self {
+            Model::Pi3B | Model::Pi3BPlus | Model::Pi3APlus | Model::CM3 | Model::CM3Plus => {
+                Soc::BCM2837
+            }
+            Model::Pi4B | Model::Pi400 | Model::CM4 => Soc::BCM2711,
+        }
+    }
+
+    /// Decode the type field of a new-style revision code.
+    fn from_revision_type(t: u32) -> Option<Self> {
+        let model = match t {
+            0x08 => Model::Pi3B,
+            0x0A => Model::CM3,
+            0x0D => Model::Pi3BPlus,
+            0x0E => Model::Pi3APlus,
+            0x10 => Model::CM3Plus,
+            0x11 => Model::Pi4B,
+            0x13 => Model::Pi400,
+            0x14 => Model::CM4,
+            _ => return None,
+        };
+
+        Some(model)
+    }
+}
+
+impl Manufacturer {
+    fn from_revision_field(m: u32) -> Self {
+        match m {
+            0 => Manufacturer::SonyUK,
+            1 => Manufacturer::Egoman,
+            2 | 4 => Manufacturer::Embest,
+            3 => Manufacturer::SonyJapan,
+            5 => Manufacturer::Stadium,
+            x => Manufacturer::Unknown(x),
+        }
+    }
+}
+
+impl BoardInfo {
+    /// What is known without asking the hardware.
+    const fn built_in() -> Self {
+        Self {
+            soc: Soc::compiled_for(),
+            model: None,
+            revision: None,
+            pcb_revision: None,
+            memory_mib: None,
+            manufacturer: None,
+            source: Source::BuiltIn,
+        }
+    }
+
+    /// Decode a revision code as reported by the firmware.
+    fn from_revision(code: u32) -> Result<Self, &'static str> {
+        if code & REVISION_NEW_STYLE == 0 {
+            return Err("Old-style revision code");
+        }
+
+        let field = |shift: u32, bits: u32| (code >> shift) & ((1 << bits) - 1);
+
+        let soc = match field(12, 4) {
+            2 => Soc::BCM2837,
+            3 => Soc::BCM2711,
+            _ => return Err("Revision code names an unsupported SoC"),
+        };
+        let model = Model::from_revision_type(field(4, 8));
+
+        Ok(Self {
+            soc,
+            model,
+            revision: Some(code),
+            pcb_revision: Some(field(0, 4)),
+            memory_mib: Some(256 << field(20, 3)),
+            manufacturer: Some(Manufacturer::from_revision_field(field(16, 4))),
+            source: Source::Mailbox,
+        })
+    }
+
+    /// Identify the board from a list of `compatible` strings, separated by NUL.
+    fn from_compatible(compatible: &str) -> Option<Self> {
+        let model = compatible.split('\0').find_map(|x| {
+            COMPATIBLE
+                .iter()
+                .find(|(name, _)| *name == x)
+                .map(|(_, model)| *model)
+        })?;
+
+        Some(Self {
+            soc: model.soc(),
+            model: Some(model),
+            source: Source::DeviceTree,
+            ..Self::built_in()
+        })
+    }
+
+    /// True if the board is of the family the kernel was built for.
+    pub fn matches_build(&self) -> bool {
+        self.soc == Soc::compiled_for()
+    }
+
+    /// The wiring of the ACT LED.
+    pub fn act_led(&self) -> ActLed {
+        match self.model {
+            Some(Model::Pi3B) => ActLed::FirmwareGpio(130),
+            Some(Model::Pi3BPlus) | Some(Model::Pi3APlus) => ActLed::Gpio(29),
+            Some(Model::Pi4B) | Some(Model::Pi400) | Some(Model::CM4) => ActLed::Gpio(42),
+            Some(Model::CM3) | Some(Model::CM3Plus) | None => ActLed::None,
+        }
+    }
+
+    /// The temperature above which the firmware lowers the clock before the hard limit, if the
+    /// board has one.
+    pub fn soft_temp_limit_celsius(&self) -> Option<u32> {
+        match self.model {
+            Some(Model::Pi3BPlus) => Some(60),
+            _ => None,
+        }
+    }
+}
+
+/// Render the detected board for the banner.
+fn render(out: &mut dyn fmt::Write) -> fmt::Result {
+    write!(out, "{}", info())?;
+
+    if let Some(x) = dtb::device_tree().and_then(|x| x.stdout_uart_addr()) {
+        write!(out, "\nFirmware's console UART: {:#x}", x)?;
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for Soc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Soc::BCM2837 => "BCM2837",
+            Soc::BCM2711 => "BCM2711",
+        };
+
+        f.pad(name)
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Model::Pi3B => "Raspberry Pi 3 Model B",
+            Model::Pi3BPlus => "Raspberry Pi 3 Model B+",
+            Model::Pi3APlus => "Raspberry Pi 3 Model A+",
+            Model::CM3 => "Raspberry Pi Compute Module 3",
+            Model::CM3Plus => "Raspberry Pi Compute Module 3+",
+            Model::Pi4B => "Raspberry Pi 4 Model B",
+            Model::Pi400 => "Raspberry Pi 400",
+            Model::CM4 => "Raspberry Pi Compute Module 4",
+        };
+
+        f.pad(name)
+    }
+}
+
+impl fmt::Display for Manufacturer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Manufacturer::SonyUK => f.pad("Sony UK"),
+            Manufacturer::Egoman => f.pad("Egoman"),
+            Manufacturer::Embest => f.pad("Embest"),
+            Manufacturer::SonyJapan => f.pad("Sony Japan"),
+            Manufacturer::Stadium => f.pad("Stadium"),
+            Manufacturer::Unknown(x) => write!(f, "Unknown manufacturer {}", x),
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Source::Mailbox => "firmware's revision code",
+            Source::DeviceTree => "device tree",
+            Source::BuiltIn => "built-in default",
+        };
+
+        f.pad(name)
+    }
+}
+
+impl fmt::Display for BoardInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.model {
+            Some(x) => write!(f, "{}", x)?,
+            None => write!(f, "{}", super::board_name())?,
+        }
+        if let Some(x) = self.pcb_revision {
+            write!(f, " rev 1.{}", x)?;
+        }
+        write!(f, ", {}", self.soc)?;
+        if let Some(x) = self.memory_mib {
+            write!(f, ", {} MiB", x)?;
+        }
+        if let Some(x) = self.manufacturer {
+            write!(f, ", made by {}", x)?;
+        }
+
+        write!(f, " (from the {})", self.source)
+    }
+}
+
+/// Identify the board.
+///
+/// The firmware's revision code is preferred, since it carries the most information. Falls back to
+/// the device tree, and then to the board family the kernel was built for.
+pub fn detect() -> BoardInfo {
+    let mailbox_err = match MAILBOX
+        .property(tag::GET_BOARD_REVISION, [0])
+        .and_then(|[code]| BoardInfo::from_revision(code))
+    {
+        Ok(x) => return x,
+        Err(x) => x,
+    };
+
+    if let Some(x) = dtb::device_tree()
+        .and_then(|x| x.root().property_str("compatible"))
+        .and_then(BoardInfo::from_compatible)
+    {
+        return x;
+    }
+
+    warn!("Board detection failed: {}", mailbox_err);
+    BoardInfo::built_in()
+}
+
+/// Detect the board, warn if the kernel was built for a different one, and add the board to the
+/// banner.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init, after the `PostMMU` driver init stage.
+pub unsafe fn init() {
+    let info = detect();
+    BOARD_INFO.write(|x| *x = info);
+
+    if let Err(x) = banner::register_section(("Board", render)) {
+        warn!("{}", x);
+    }
+
+    if !info.matches_build() {
+        warn!("################################################################################");
+        warn!(
+            "Kernel was built for the {}, but runs on a {}",
+            super::board_name(),
+            info
+        );
+        warn!(
+            "MMIO addresses and the interrupt controller are wrong. Rebuild with BSP={}",
+            info.soc.bsp()
+        );
+        warn!("################################################################################");
+    }
+}
+
+/// The detected board, or the built-in default before `init()`.
+pub fn info() -> BoardInfo {
+    BOARD_INFO.read(|x| *x)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Decode revision codes of real boards.
+    #[kernel_test]
+    fn revision_decoding_works() {
+        let pi3b = BoardInfo::from_revision(0x00a0_2082).unwrap();
+        assert_eq!(pi3b.model, Some(Model::Pi3B));
+        assert_eq!(pi3b.soc, Soc::BCM2837);
+        assert_eq!(pi3b.memory_mib, Some(1024));
+        assert_eq!(pi3b.manufacturer, Some(Manufacturer::SonyUK));
+        assert_eq!(pi3b.pcb_revision, Some(2));
+        assert_eq!(pi3b.act_led(), ActLed::FirmwareGpio(130));
+
+        let pi3bplus = BoardInfo::from_revision(0x00a0_20d3).unwrap();
+        assert_eq!(pi3bplus.model, Some(Model::Pi3BPlus));
+        assert_eq!(pi3bplus.act_led(), ActLed::Gpio(29));
+        assert_eq!(pi3bplus.soft_temp_limit_celsius(), Some(60));
+
+        let pi4b = BoardInfo::from_revision(0x00c0_3112).unwrap();
+        assert_eq!(pi4b.model, Some(Model::Pi4B));
+        assert_eq!(pi4b.soc, Soc::BCM2711);
+        assert_eq!(pi4b.memory_mib, Some(4096));
+        assert_eq!(pi4b.act_led(), ActLed::Gpio(42));
+
+        assert!(BoardInfo::from_revision(0x0000_000e).is_err());
+    }
+
+    /// The first known string of the list wins.
+    #[kernel_test]
+    fn compatible_lookup_works() {
+        let info = BoardInfo::from_compatible("raspberrypi,3-model-b-plus\0brcm,bcm2837").unwrap();
+        assert_eq!(info.model, Some(Model::Pi3BPlus));
+        assert_eq!(info.source, Source::DeviceTree);
+
+        assert!(BoardInfo::from_compatible("brcm,bcm2711").is_none());
+    }
+}
@@ -0,0 +1,165 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Clock frequencies, as set up by the firmware.
+//!
+//! The firmware may lower the ARM clock when the SoC runs hot or the supply voltage drops, so the
+//! current rate is reported next to the maximum.
+
+use super::{
+    device_driver::{tag, CmClock},
+    CLOCK_MANAGER, MAILBOX,
+};
+use crate::{banner, cpu, exception, time, units::Hertz, warn};
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Iterations of the calibration loop. Takes about 10 ms at the Raspberry's clock rates.
+const CALIBRATION_ITERATIONS: u64 = 10_000_000;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The firmware's clocks that are used in the kernel.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ClockId {
+    /// The SD card controller's clock.
+    Emmc,
+
+    /// The PL011 UART's reference clock.
+    Uart,
+
+    /// The ARM cores' clock.
+    Arm,
+
+    /// The VideoCore's clock, which also drives the peripheral bus.
+    Core,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl ClockId {
+    /// The firmware's ID of the clock.
+    fn firmware_id(self) -> u32 {
+        match self {
+            ClockId::Emmc => 1,
+            ClockId::Uart => 2,
+            ClockId::Arm => 3,
+            ClockId::Core => 4,
+        }
+    }
+}
+
+/// Render the clock rates for the banner.
+fn render(out: &mut dyn fmt::Write) -> fmt::Result {
+    match (get(ClockId::Arm), get_max(ClockId::Arm)) {
+        (Ok(x), Ok(max)) => write!(
+            out,
+            "ARM {} (max {}, measured {})",
+            x,
+            max,
+            measured_cpu_freq()
+        )?,
+        (Ok(x), Err(_)) => write!(out, "ARM {} (measured {})", x, measured_cpu_freq())?,
+        (Err(e), _) => write!(out, "ARM unknown ({}), measured {}", e, measured_cpu_freq())?,
+    }
+
+    for id in [ClockId::Core, ClockId::Uart].iter().copied() {
+        match get(id) {
+            Ok(x) => write!(out, "\n{} {}", id, x)?,
+            Err(e) => write!(out, "\n{} unknown ({})", id, e)?,
+        }
+    }
+
+    let cm_clocks = [
+        CmClock::Gp0,
+        CmClock::Gp1,
+        CmClock::Gp2,
+        CmClock::Pcm,
+        CmClock::Pwm,
+    ];
+    for clock in cm_clocks.iter().copied() {
+        if let Some(x) = CLOCK_MANAGER.frequency(clock) {
+            write!(out, "\n{} {}", clock, x)?;
+        }
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for ClockId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            ClockId::Emmc => "EMMC",
+            ClockId::Uart => "UART",
+            ClockId::Arm => "ARM",
+            ClockId::Core => "Core",
+        })
+    }
+}
+
+/// The current rate of clock `id`.
+pub fn get(id: ClockId) -> Result<Hertz, &'static str> {
+    let [_, rate] = MAILBOX.property(tag::GET_CLOCK_RATE, [id.firmware_id(), 0])?;
+
+    if rate == 0 {
+        return Err("Firmware does not know the clock");
+    }
+
+    Ok(Hertz(u64::from(rate)))
+}
+
+/// The rate that the firmware may run clock `id` at.
+pub fn get_max(id: ClockId) -> Result<Hertz, &'static str> {
+    let [_, rate] = MAILBOX.property(tag::GET_MAX_CLOCK_RATE, [id.firmware_id(), 0])?;
+
+    if rate == 0 {
+        return Err("Firmware does not know the clock");
+    }
+
+    Ok(Hertz(u64::from(rate)))
+}
+
+/// Estimate the executing core's clock by timing a busy loop against the architectural counter.
+///
+/// Independent of the firmware, so it catches a wrong answer from it. Under QEMU, the result says
+/// more about the host than about the emulated board.
+pub fn measured_cpu_freq() -> Hertz {
+    let source = time::arch_time_source();
+
+    let ticks = exception::asynchronous::exec_with_irq_masked(|| {
+        let start = source.counter();
+        cpu::spin_for_iterations(CALIBRATION_ITERATIONS);
+
+        source.counter().wrapping_sub(start)
+    });
+
+    // One iteration takes one cycle.
+    source
+        .frequency()
+        .checked_mul(CALIBRATION_ITERATIONS)
+        .map_or(Hertz(u64::MAX), |x| Hertz(x.0 / ticks.max(1)))
+}
+
+/// Register the banner section with the rates of the ARM, core and UART clocks, and of the clock
+/// manager's clocks that run.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init.
+pub unsafe fn init() {
+    if let Err(x) = banner::register_section(("Clocks", render)) {
+        warn!("{}", x);
+    }
+}
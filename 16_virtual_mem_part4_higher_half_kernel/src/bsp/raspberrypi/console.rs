@@ -5,9 +5,43 @@
 //! BSP console facilities.
 
 use super::memory;
-use crate::{bsp::device_driver, console, cpu, driver};
+use crate::{bsp::device_driver, cmdline, console, driver, memory::mmu, warn};
 use core::fmt;
 
+#[cfg(not(feature = "test_build"))]
+use crate::cpu;
+
+#[cfg(feature = "test_build")]
+use crate::shutdown::{self, ShutdownReason};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Formatted output through `panic_emergency_out()`.
+struct EmergencyOut;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Whether log messages are prefixed with uptime, core id and level by default.
+///
+/// Test builds leave the prefix off, so that golden-output tests need not match timestamps.
+pub const LOG_PREFIX_ENABLED: bool = !cfg!(feature = "test_build");
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Write for EmergencyOut {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        unsafe { panic_emergency_out(s) };
+
+        Ok(())
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -58,13 +92,99 @@ pub unsafe fn panic_console_out() -> impl fmt::Write {
 
     panic_uart
         .init(maybe_uart_mmio_start_addr)
-        .unwrap_or_else(|_| cpu::qemu_exit_failure());
+        .unwrap_or_else(|_| shutdown::kernel_shutdown(ShutdownReason::Panic));
 
     panic_uart
 }
 
+/// Last resort output for when the panic handler itself panicked, and the backend of
+/// `console::emergency_write()`.
+///
+/// Writes the string to the UART without locking or formatting. The UART is only set up if its
+/// driver did not do so yet, see `PanicUart::emergency_write()`.
+///
+/// While the kernel's translation tables are being changed, the UART's virtual mapping might be
+/// broken, so the UART is written through its physical address with the MMU switched off instead.
+///
+/// # Safety
+///
+/// - Use only for printing during a nested panic or a fatal exception.
+/// - IRQs must be masked.
+pub unsafe fn panic_emergency_out(s: &str) {
+    use driver::interface::DeviceDriver;
+
+    if mmu::kernel_tables_in_flux() {
+        // There is no other way out if this fails.
+        let _ =
+            device_driver::PanicUart::emergency_write_phys(memory::map::mmio::PL011_UART_START, s);
+        return;
+    }
+
+    let uart_mmio_start_addr = super::PL011_UART
+        .virt_mmio_start_addr()
+        .unwrap_or_else(|| memory::map::mmio::PL011_UART_START.into_usize());
+
+    device_driver::PanicUart::emergency_write(uart_mmio_start_addr, s);
+}
+
+/// Formatted version of `panic_emergency_out()`.
+///
+/// # Safety
+///
+/// - See `panic_emergency_out()`.
+pub unsafe fn panic_emergency_console_out() -> impl fmt::Write {
+    EmergencyOut
+}
+
+/// Add a console to the kernel's console multiplexer. All console output goes to all consoles.
+///
+/// The first registered console also becomes the one that console input is read from. The others
+/// can be switched to by `name`.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init.
+pub unsafe fn register_console(
+    name: &'static str,
+    console: &'static (impl console::interface::All + Sync),
+) -> Result<(), &'static str> {
+    let mux = console::mux::console_mux();
+
+    mux.register_sink(console)?;
+    mux.register_source(name, console)
+}
+
+/// Check the `console=` command line argument against the consoles that this BSP provides.
+///
+/// UARTs are named like in Linux. A console that was registered under the given name becomes the
+/// primary one. Only the PL011 UART, `ttyAMA0`, is supported. Selecting the mini
+/// UART, `ttyS0`, falls back to it with a warning. Consoles that are not UARTs, like `tty1`, are
+/// ignored.
+pub fn check_cmdline_console() {
+    let name = match cmdline::get("console") {
+        None => return,
+        // Strip the options, for example `,115200`.
+        Some(x) => x.split(',').next().unwrap_or(x),
+    };
+
+    if name == "ttyS0" {
+        warn!("console=ttyS0: The mini UART is not supported. Using ttyAMA0");
+        return;
+    }
+
+    // Consoles that this BSP does not provide are ignored.
+    let _ = console::switch_primary(name);
+}
+
 /// Return a reference to the console.
 pub fn console() -> &'static impl console::interface::All {
+    console::mux::console_mux()
+}
+
+/// Return a reference to the console that records when characters arrive.
+///
+/// Only the PL011 UART does, and only if `config::console::RX_TIMESTAMPS` is on.
+pub fn timestamped_console() -> &'static impl console::interface::TimestampedRead {
     &super::PL011_UART
 }
 
@@ -83,6 +203,11 @@ pub fn qemu_bring_up_console() {
     unsafe {
         super::PL011_UART
             .init()
-            .unwrap_or_else(|_| cpu::qemu_exit_failure());
+            .unwrap_or_else(|_| shutdown::kernel_shutdown(ShutdownReason::Panic));
+
+        register_console("ttyAMA0", &super::PL011_UART)
+            .unwrap_or_else(|_| shutdown::kernel_shutdown(ShutdownReason::Panic));
     }
+
+    console::buffer::replay_into(console());
 }
@@ -4,6 +4,16 @@
 
 //! BSP Processor code.
 
+use super::memory::map;
+use crate::{
+    bsp::device_driver::{self, BootPartition},
+    cpu,
+    cpu::psci,
+    driver,
+    memory::{mmu, mmu::MMIODescriptor},
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -12,3 +22,90 @@
 #[no_mangle]
 #[link_section = ".text._start_arguments"]
 pub static BOOT_CORE_ID: u64 = 0;
+
+/// The number of processor cores on the board.
+pub const NUM_CORES: usize = 4;
+
+/// The conduit for reaching PSCI firmware, if any.
+///
+/// The Raspberry's default firmware does not implement PSCI. Issuing an SMC or HVC without a
+/// handler in place would lose the core, so PSCI is disabled by default. Set this if the kernel is
+/// booted by PSCI-capable firmware, for example, Trusted Firmware-A.
+pub const PSCI_CONDUIT: Option<psci::Conduit> = None;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// Virtual start address of the remapped spin-table. Zero until `spin_table_init()` succeeded.
+static SPIN_TABLE_VIRT_START_ADDR: AtomicUsize = AtomicUsize::new(0);
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Map the firmware's spin-table into the kernel's address space.
+///
+/// The spin-table lives in DRAM below the kernel binary. It is mapped as device memory, so that
+/// writes reach the secondary cores, which still run with caches disabled, without any cache
+/// maintenance.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init.
+pub unsafe fn spin_table_init() -> Result<(), &'static str> {
+    let descriptor = MMIODescriptor::new(map::SPIN_TABLE_START, map::SPIN_TABLE_SIZE);
+    let region = mmu::kernel_map_mmio_region("Spin-table", &descriptor)?.leak();
+
+    SPIN_TABLE_VIRT_START_ADDR.store(region.start_addr().into_usize(), Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// Return the address of the spin-table entry that releases the given secondary core.
+///
+/// Returns `None` if the spin-table has not been mapped yet or if `core_id` does not belong to a
+/// secondary core.
+pub fn spin_table_release_addr(core_id: usize) -> Option<*mut u64> {
+    let start = SPIN_TABLE_VIRT_START_ADDR.load(Ordering::Relaxed);
+
+    if (start == 0) || (core_id as u64 == BOOT_CORE_ID) || (core_id >= NUM_CORES) {
+        return None;
+    }
+
+    // The entry of core 0 is at offset 0, followed by one u64 per core.
+    Some((start + (core_id * core::mem::size_of::<u64>())) as *mut u64)
+}
+
+/// Reset the board through the power management watchdog, and boot into `partition`.
+///
+/// Works before the watchdog driver is initialized, too, by taking a chance with the physical
+/// address, as the panic console does.
+fn watchdog_reset(partition: BootPartition) -> ! {
+    use driver::interface::DeviceDriver;
+
+    match super::PM_WATCHDOG.virt_mmio_start_addr() {
+        Some(_) => super::PM_WATCHDOG.reset(partition),
+        None => unsafe {
+            let mut panic_watchdog =
+                device_driver::PanicPMWatchdog::new(map::mmio::PM_WATCHDOG_START.into_usize());
+            panic_watchdog.reset(partition)
+        },
+    }
+
+    // Wait for the watchdog to fire.
+    cpu::wait_forever()
+}
+
+/// Reset the board.
+pub fn system_reset() -> ! {
+    watchdog_reset(BootPartition::Default)
+}
+
+/// Power off the board.
+///
+/// The Raspberry cannot cut its own power. Instead, the board is reset into the firmware's halt
+/// partition, which stops it with the least possible power draw.
+pub fn system_off() -> ! {
+    watchdog_reset(BootPartition::Halt)
+}
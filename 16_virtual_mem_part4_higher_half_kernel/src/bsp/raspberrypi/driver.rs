@@ -4,58 +4,206 @@
 
 //! BSP driver support.
 
-use crate::driver;
+use super::device_driver::tag;
+use crate::{
+    cmdline, console,
+    driver::{self, interface::DeviceDriver, DeviceDriverDescriptor, DriverErrorKind, InitStage},
+    error::KernelError,
+    selftest::{self, SelfTest},
+    time, warn,
+};
 
 //--------------------------------------------------------------------------------------------------
-// Private Definitions
+// Private Code
 //--------------------------------------------------------------------------------------------------
 
-/// Device Driver Manager type.
-struct BSPDriverManager {
-    device_drivers: [&'static (dyn DeviceDriver + Sync); 3],
+/// Wrap a self-test failure of `driver`.
+fn selftest_error(driver: &dyn DeviceDriver, error: &'static str) -> KernelError {
+    KernelError::Driver {
+        name: driver.compatible(),
+        kind: DriverErrorKind::SelfTest(error),
+    }
 }
 
-//--------------------------------------------------------------------------------------------------
-// Global instances
-//--------------------------------------------------------------------------------------------------
+fn selftest_uart() -> Result<(), KernelError> {
+    super::PL011_UART
+        .loopback_test()
+        .map_err(|x| selftest_error(&super::PL011_UART, x))
+}
 
-static BSP_DRIVER_MANAGER: BSPDriverManager = BSPDriverManager {
-    device_drivers: [
-        &super::GPIO,
-        &super::PL011_UART,
-        &super::INTERRUPT_CONTROLLER,
-    ],
-};
+fn selftest_mailbox() -> Result<(), KernelError> {
+    super::MAILBOX
+        .property(tag::GET_BOARD_REVISION, [0])
+        .map(|_| ())
+        .map_err(|x| selftest_error(&super::MAILBOX, x))
+}
 
-//--------------------------------------------------------------------------------------------------
-// Public Code
-//--------------------------------------------------------------------------------------------------
+/// One of the two checks compares the time manager's source with itself, and always passes.
+fn selftest_timers() -> Result<(), KernelError> {
+    let agree =
+        time::cross_check(&super::SYSTEM_TIMER) && time::cross_check(time::arch_time_source());
+    if !agree {
+        return Err(KernelError::Time("Generic timer and system timer disagree"));
+    }
 
-/// Return a reference to the driver manager.
-pub fn driver_manager() -> &'static impl driver::interface::DriverManager {
-    &BSP_DRIVER_MANAGER
+    Ok(())
 }
 
-//------------------------------------------------------------------------------
-// OS Interface Code
-//------------------------------------------------------------------------------
-use driver::interface::DeviceDriver;
+/// Register a self-test. A failure only costs the test, so it is logged and not passed on.
+fn register_selftest(name: &'static str, func: selftest::SelfTestFn) {
+    let result = selftest::register(SelfTest {
+        name,
+        essential: false,
+        func,
+    });
 
-impl driver::interface::DriverManager for BSPDriverManager {
-    fn all_device_drivers(&self) -> &[&'static (dyn DeviceDriver + Sync)] {
-        &self.device_drivers[..]
+    if let Err(x) = result {
+        warn!("{}: {}", name, x);
     }
+}
 
-    fn early_print_device_drivers(&self) -> &[&'static (dyn DeviceDriver + Sync)] {
-        &self.device_drivers[0..=1]
-    }
+/// This must be called only after successful init of the GPIO driver.
+unsafe fn post_init_gpio() -> Result<(), &'static str> {
+    // Record the pins as the firmware left them, so that later changes can be shown.
+    super::gpio::init()?;
+
+    // Configure PL011Uart's output pins.
+    super::GPIO.map_pl011_uart();
+    Ok(())
+}
+
+/// This must be called only after successful init of the UART driver.
+unsafe fn post_init_uart() -> Result<(), &'static str> {
+    super::console::register_console("ttyAMA0", &super::PL011_UART)?;
+
+    // The console is up. Print everything that was printed before.
+    console::buffer::replay_into(super::console::console());
+
+    super::console::check_cmdline_console();
+    register_selftest("UART loopback", selftest_uart);
+    Ok(())
+}
 
-    fn non_early_print_device_drivers(&self) -> &[&'static (dyn DeviceDriver + Sync)] {
-        &self.device_drivers[2..]
+/// This must be called only after successful init of the mailbox driver.
+unsafe fn post_init_mailbox() -> Result<(), &'static str> {
+    // The UART was brought up with the clock that config.txt asks for. Use the actual one.
+    let result = super::clocks::get(super::clocks::ClockId::Uart)
+        .and_then(|x| super::PL011_UART.set_ref_clock(x));
+
+    if let Err(x) = result {
+        warn!("Keeping the UART's default reference clock: {}", x);
     }
 
-    fn post_early_print_device_driver_init(&self) {
-        // Configure PL011Uart's output pins.
-        super::GPIO.map_pl011_uart();
+    register_selftest("Mailbox ping", selftest_mailbox);
+    Ok(())
+}
+
+/// This must be called only after successful init of the framebuffer driver.
+unsafe fn post_init_framebuffer() -> Result<(), &'static str> {
+    super::fb_console::init()
+}
+
+/// This must be called only after successful init of the system timer driver.
+unsafe fn post_init_system_timer() -> Result<(), &'static str> {
+    // The architectural timer backs the time manager, unless the command line asks otherwise.
+    // Either way, the other source is used to cross-check it.
+    match cmdline::get("clocksource") {
+        Some("bcm") => {
+            time::set_time_source(&super::SYSTEM_TIMER);
+            time::cross_check(time::arch_time_source());
+        }
+        None | Some("arch") => {
+            time::cross_check(&super::SYSTEM_TIMER);
+        }
+        Some(x) => {
+            warn!("Unknown clocksource '{}'. Expected 'arch' or 'bcm'", x);
+            time::cross_check(&super::SYSTEM_TIMER);
+        }
     }
+
+    register_selftest("Generic timer vs. system timer", selftest_timers);
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Register the board's drivers with the kernel's driver manager.
+///
+/// The drivers needed for printing are registered first, and are brought up in the `Early` stage.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init, before the `Early` driver init stage.
+pub unsafe fn init() -> Result<(), &'static str> {
+    let driver_manager = driver::driver_manager();
+
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::GPIO,
+        InitStage::Early,
+        Some(post_init_gpio),
+    ))?;
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::PL011_UART,
+        InitStage::Early,
+        Some(post_init_uart),
+    ))?;
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::INTERRUPT_CONTROLLER,
+        InitStage::PostMMU,
+        None,
+    ))?;
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::SYSTEM_TIMER,
+        InitStage::PostMMU,
+        Some(post_init_system_timer),
+    ))?;
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::TIMEOUT_TIMER,
+        InitStage::PostMMU,
+        None,
+    ))?;
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::PERFORMANCE_MONITOR,
+        InitStage::PostMMU,
+        None,
+    ))?;
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::MAILBOX,
+        InitStage::PostMMU,
+        Some(post_init_mailbox),
+    ))?;
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::FRAMEBUFFER,
+        InitStage::PostMMU,
+        Some(post_init_framebuffer),
+    ))?;
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::PM_WATCHDOG,
+        InitStage::PostMMU,
+        None,
+    ))?;
+    driver_manager.register_driver(DeviceDriverDescriptor::new(
+        &super::CLOCK_MANAGER,
+        InitStage::PostMMU,
+        None,
+    ))?;
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+/// Minimal code needed to bring up the timeout timer in QEMU (for testing only).
+///
+/// # Safety
+///
+/// - Must only be called during kernel init.
+#[cfg(feature = "test_build")]
+pub unsafe fn qemu_bring_up_timeout_timer() -> Result<(), &'static str> {
+    super::INTERRUPT_CONTROLLER.init()?;
+    super::TIMEOUT_TIMER.register_and_enable_irq_handler()
 }
@@ -5,3 +5,27 @@
 //! BSP synchronous and asynchronous exception handling.
 
 pub mod asynchronous;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The size of each core's exception stack.
+///
+/// The linker script lays out the stacks, so the value is kept in `exception_stack_size.ld`, which
+/// both sides read.
+pub const STACK_SIZE: usize = get_stack_size();
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Retrieve the exception stack size from the file that is shared with the linker script. Same hack
+/// as for the kernel's virtual address space size.
+const fn get_stack_size() -> usize {
+    let __exception_stack_size;
+
+    include!("exception_stack_size.ld");
+
+    __exception_stack_size
+}
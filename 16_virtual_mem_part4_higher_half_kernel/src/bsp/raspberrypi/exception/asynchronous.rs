@@ -10,18 +10,76 @@ use crate::{bsp, exception};
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
-#[cfg(feature = "bsp_rpi3")]
+/// The board's interrupts.
+///
+/// The drivers are handed their interrupts as a `BoardIRQ`, so that the numbers are written down in
+/// one place only. The numbering is shared by the boards, so the map is the same on all of them.
+/// The interrupt controller drivers translate into their native numbering.
+#[allow(dead_code)]
 pub(in crate::bsp) mod irq_map {
-    use super::bsp::device_driver::{IRQNumber, PeripheralIRQ};
+    use super::bsp::device_driver::{ArmIRQ, IRQNumber, LocalIRQ, PeripheralIRQ};
 
-    pub const PL011_UART: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(57));
-}
+    /// The interrupts of the board's peripherals.
+    #[allow(missing_docs)]
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum BoardIRQ {
+        TimeoutTimerPhys,
+        TimeoutTimerVirt,
+        PMU,
+        Mailbox,
+        SystemTimer1,
+        SystemTimer3,
+        GPIOBank0,
+        GPIOBank1,
+        GPIOBank2,
+        GPIOAll,
+        PL011Uart,
+        EMMC,
+        SMI,
+    }
 
-#[cfg(feature = "bsp_rpi4")]
-pub(in crate::bsp) mod irq_map {
-    use super::bsp::device_driver::IRQNumber;
+    impl BoardIRQ {
+        /// All interrupts, in the order of their declaration.
+        pub const ALL: [Self; 13] = [
+            Self::TimeoutTimerPhys,
+            Self::TimeoutTimerVirt,
+            Self::PMU,
+            Self::Mailbox,
+            Self::SystemTimer1,
+            Self::SystemTimer3,
+            Self::GPIOBank0,
+            Self::GPIOBank1,
+            Self::GPIOBank2,
+            Self::GPIOAll,
+            Self::PL011Uart,
+            Self::EMMC,
+            Self::SMI,
+        ];
+
+        /// The number that the interrupt controller knows the interrupt by.
+        ///
+        /// Numbers out of range fail the build, since the drivers' statics are initialized with
+        /// the result.
+        pub const fn irq_number(self) -> IRQNumber {
+            match self {
+                Self::TimeoutTimerPhys => IRQNumber::Local(LocalIRQ::new(1)), // CNTPNSIRQ
+                Self::TimeoutTimerVirt => IRQNumber::Local(LocalIRQ::new(3)), // CNTVIRQ
+                Self::PMU => IRQNumber::Local(LocalIRQ::new(9)),
 
-    pub const PL011_UART: IRQNumber = IRQNumber::new(153);
+                Self::Mailbox => IRQNumber::Arm(ArmIRQ::new(1)),
+
+                Self::SystemTimer1 => IRQNumber::Peripheral(PeripheralIRQ::new(1)),
+                Self::SystemTimer3 => IRQNumber::Peripheral(PeripheralIRQ::new(3)),
+                Self::GPIOBank0 => IRQNumber::Peripheral(PeripheralIRQ::new(49)),
+                Self::GPIOBank1 => IRQNumber::Peripheral(PeripheralIRQ::new(50)),
+                Self::GPIOBank2 => IRQNumber::Peripheral(PeripheralIRQ::new(51)),
+                Self::GPIOAll => IRQNumber::Peripheral(PeripheralIRQ::new(52)),
+                Self::PL011Uart => IRQNumber::Peripheral(PeripheralIRQ::new(57)),
+                Self::EMMC => IRQNumber::Peripheral(PeripheralIRQ::new(62)),
+                Self::SMI => IRQNumber::Peripheral(PeripheralIRQ::new(48)),
+            }
+        }
+    }
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -34,3 +92,134 @@ pub fn irq_manager() -> &'static impl exception::asynchronous::interface::IRQMan
 > {
     &super::super::INTERRUPT_CONTROLLER
 }
+
+/// Return the interrupt controller to its reset state before another kernel takes over.
+///
+/// The BCM interrupt controller of the RPi3 is left alone, since it has no state beyond the enabled
+/// IRQs, which the next kernel disables on its own.
+///
+/// # Safety
+///
+/// - IRQs must be masked. No registered handler is called afterwards.
+pub unsafe fn reset_irq_controller() {
+    #[cfg(feature = "bsp_rpi4")]
+    super::super::INTERRUPT_CONTROLLER.reset_to_defaults();
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exception::asynchronous::{interface::IRQHandler, IRQDescriptor};
+    use test_macros::kernel_test;
+
+    struct DummyHandler;
+
+    impl IRQHandler for DummyHandler {
+        fn handle(&self, _context: &exception::ExceptionContext) -> Result<(), &'static str> {
+            Ok(())
+        }
+    }
+
+    static DUMMY_HANDLER: DummyHandler = DummyHandler;
+
+    /// The interrupt controller's native number of an IRQ.
+    fn native_irq_number(irq: bsp::device_driver::IRQNumber) -> Option<usize> {
+        #[cfg(feature = "bsp_rpi3")]
+        let number = bsp::device_driver::InterruptController::native_irq_number(irq);
+
+        #[cfg(feature = "bsp_rpi4")]
+        let number = bsp::device_driver::GICv2::native_irq_number(irq);
+
+        number
+    }
+
+    /// Each of the board's interrupts is one that the interrupt controller can handle, and no two
+    /// of them share a number.
+    #[kernel_test]
+    fn irq_map_is_consistent() {
+        use irq_map::BoardIRQ;
+
+        for (i, irq) in BoardIRQ::ALL.iter().enumerate() {
+            // Each variant is listed once, in order.
+            assert_eq!(*irq as usize, i);
+
+            let number = native_irq_number(irq.irq_number());
+            assert!(number.is_some());
+
+            for other in BoardIRQ::ALL[(i + 1)..].iter() {
+                assert_ne!(native_irq_number(other.irq_number()), number);
+            }
+        }
+    }
+
+    /// Registering the UART's IRQ is the same on all boards. The test kernels leave the UART's IRQ
+    /// unregistered.
+    #[kernel_test]
+    fn uart_irq_registration_works() {
+        use exception::asynchronous::interface::IRQManager;
+
+        let descriptor = IRQDescriptor {
+            name: "Test",
+            handler: &DUMMY_HANDLER,
+        };
+
+        // Handlers may only be registered with IRQs masked.
+        exception::asynchronous::exec_with_irq_masked(|| {
+            assert!(irq_manager()
+                .register_handler(irq_map::BoardIRQ::PL011Uart.irq_number(), descriptor)
+                .is_ok());
+            assert!(irq_manager()
+                .register_handler(irq_map::BoardIRQ::PL011Uart.irq_number(), descriptor)
+                .is_err());
+        });
+
+        // The handler was never called, so it is healthy.
+        let mut found = false;
+        irq_manager().handler_health(&mut |x| {
+            if x.name == "Test" {
+                assert_eq!(x.num_errors, 0);
+                assert!(!x.masked);
+                found = true;
+            }
+        });
+        assert!(found);
+    }
+
+    /// An IRQ that a previous kernel left pending must not fire after the reset of the interrupt
+    /// controller, even if the new kernel enables it.
+    #[cfg(feature = "bsp_rpi4")]
+    #[kernel_test]
+    fn reset_clears_stale_pending_irq() {
+        use crate::bsp::device_driver::{IRQNumber, PeripheralIRQ};
+        use exception::asynchronous::interface::IRQManager;
+
+        let gic = &super::super::super::INTERRUPT_CONTROLLER;
+
+        // Not used by any driver.
+        let stale_irq = IRQNumber::Peripheral(PeripheralIRQ::new(60));
+
+        exception::asynchronous::exec_with_irq_masked(|| {
+            let state = gic.save_state();
+
+            // What the previous kernel left behind.
+            gic.enable(stale_irq);
+            gic.set_pending(stale_irq);
+            assert!(gic.is_pending(stale_irq));
+
+            gic.reset_to_defaults();
+            assert!(!gic.is_pending(stale_irq));
+
+            // The new kernel configures the GIC and enables the IRQ.
+            gic.restore_state(&state);
+            gic.enable(stale_irq);
+            assert!(!gic.is_pending(stale_irq));
+
+            // Put the test kernel's configuration back.
+            gic.restore_state(&state);
+        });
+    }
+}
@@ -0,0 +1,310 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Text console on the framebuffer.
+//!
+//! Characters are put into a text grid first. The grid is drawn and presented once a line is
+//! complete, or on a flush, so that a line of output costs a single flip in double-buffered mode.
+//! Each buffer remembers the characters that it shows, so only the changed cells are drawn. After
+//! scrolling, that is all of them.
+//!
+//! The console is output only. ANSI escape sequences, which the monitor's line editor sends, are
+//! skipped.
+
+use crate::{
+    bsp::device_driver::FramebufferSurface,
+    config,
+    console::{self, font},
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+};
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const COLS: usize = config::framebuffer::WIDTH as usize / font::GLYPH_WIDTH;
+const ROWS: usize = config::framebuffer::HEIGHT as usize / font::GLYPH_HEIGHT;
+
+const NUM_BUFFERS: usize = if config::framebuffer::DOUBLE_BUFFERED {
+    2
+} else {
+    1
+};
+
+const FG_COLOR: u32 = 0x00AA_AAAA;
+const BG_COLOR: u32 = 0x0000_0000;
+
+/// Marks a cell whose content in a buffer is unknown. Never put into the text grid.
+const UNKNOWN: u8 = 0;
+
+type Grid = [[u8; COLS]; ROWS];
+
+/// Progress through an escape sequence.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Escape {
+    None,
+    Started,
+    Csi,
+}
+
+struct FbConsoleInner {
+    text: Grid,
+    shown: [Grid; NUM_BUFFERS],
+    row: usize,
+    col: usize,
+    escape: Escape,
+
+    /// The grid changed since the last present.
+    dirty: bool,
+
+    /// A line was completed since the last present.
+    line_done: bool,
+}
+
+/// The framebuffer console.
+struct FbConsole {
+    inner: IRQSafeNullLock<FbConsoleInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static FB_CONSOLE: FbConsole = FbConsole {
+    inner: IRQSafeNullLock::new(FbConsoleInner::new()),
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl FbConsoleInner {
+    const fn new() -> Self {
+        Self {
+            text: [[b' '; COLS]; ROWS],
+            shown: [[[UNKNOWN; COLS]; ROWS]; NUM_BUFFERS],
+            row: 0,
+            col: 0,
+            escape: Escape::None,
+            dirty: true,
+            line_done: false,
+        }
+    }
+
+    fn newline(&mut self) {
+        self.col = 0;
+        if self.row + 1 < ROWS {
+            self.row += 1;
+        } else {
+            self.text.copy_within(1..ROWS, 0);
+            self.text[ROWS - 1] = [b' '; COLS];
+        }
+
+        self.dirty = true;
+        self.line_done = true;
+    }
+
+    fn put(&mut self, c: char) {
+        match self.escape {
+            Escape::Started => {
+                self.escape = if c == '[' { Escape::Csi } else { Escape::None };
+                return;
+            }
+            Escape::Csi => {
+                if ('@'..='~').contains(&c) {
+                    self.escape = Escape::None;
+                }
+                return;
+            }
+            Escape::None => (),
+        }
+
+        match c {
+            '\x1b' => self.escape = Escape::Started,
+            '\n' => self.newline(),
+            '\r' => self.col = 0,
+            '\x08' => self.col = self.col.saturating_sub(1),
+            c if c.is_control() => (),
+            c => {
+                // Wrap only when the next character arrives, so that a full line followed by a
+                // newline does not leave an empty line.
+                if self.col == COLS {
+                    self.newline();
+                }
+
+                self.text[self.row][self.col] = if c.is_ascii() { c as u8 } else { b'?' };
+                self.col += 1;
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Draw the cells that differ from what the surface shows.
+    fn draw(&mut self, surface: &mut FramebufferSurface) -> Result<(), &'static str> {
+        let shown = &mut self.shown[surface.index() % NUM_BUFFERS];
+
+        for (row, (text, shown)) in self.text.iter().zip(shown.iter_mut()).enumerate() {
+            for (col, (c, shown)) in text.iter().zip(shown.iter_mut()).enumerate() {
+                if c == shown {
+                    continue;
+                }
+
+                surface.draw_glyph(
+                    col * font::GLYPH_WIDTH,
+                    row * font::GLYPH_HEIGHT,
+                    font::glyph(*c),
+                    FG_COLOR,
+                    BG_COLOR,
+                )?;
+                *shown = *c;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return whether to present, and reset the flags if so.
+    fn take_present(&mut self, force: bool) -> bool {
+        let present = self.line_done || (force && self.dirty);
+        if present {
+            self.line_done = false;
+            self.dirty = false;
+        }
+
+        present
+    }
+}
+
+impl fmt::Write for FbConsoleInner {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.put(c);
+        }
+
+        Ok(())
+    }
+}
+
+impl FbConsole {
+    /// Draw the grid into the framebuffer's back buffer, and show it.
+    ///
+    /// Errors are dropped, since printing them would come back here.
+    fn present(&self) {
+        let fb = super::framebuffer();
+
+        let _ = fb.draw(|surface| self.inner.lock(|inner| inner.draw(surface)));
+        let _ = fb.present();
+    }
+
+    /// Present if a line was completed, or if anything changed and `force` is set.
+    fn present_if_needed(&self, force: bool) {
+        if self.inner.lock(|inner| inner.take_present(force)) {
+            self.present();
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Clear the screen and add the framebuffer console to the console multiplexer.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init, after the framebuffer driver's init.
+pub unsafe fn init() -> Result<(), &'static str> {
+    super::framebuffer()
+        .info()
+        .ok_or("Framebuffer is not initialized")?;
+
+    // The grid is all blanks, so presenting once per buffer clears the screen.
+    for _ in 0..NUM_BUFFERS {
+        FB_CONSOLE.present();
+    }
+
+    console::mux::console_mux().register_sink(&FB_CONSOLE)
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl console::interface::Write for FbConsole {
+    fn write_char(&self, c: char) {
+        self.inner.lock(|inner| inner.put(c));
+        self.present_if_needed(false);
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        self.inner
+            .lock(|inner| fmt::Write::write_fmt(inner, args))?;
+        self.present_if_needed(false);
+
+        Ok(())
+    }
+
+    fn flush(&self) {
+        self.present_if_needed(true);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    fn put_str(inner: &mut FbConsoleInner, s: &str) {
+        fmt::Write::write_str(inner, s).unwrap();
+    }
+
+    /// Presenting waits for a completed line, unless forced. Escape sequences take no cells.
+    #[kernel_test]
+    fn lines_are_batched() {
+        let mut inner = FbConsoleInner::new();
+        assert!(inner.take_present(true));
+
+        put_str(&mut inner, "ab\x1b[2Kc");
+        assert_eq!(&inner.text[0][..4], b"abc ");
+        assert!(!inner.take_present(false));
+
+        put_str(&mut inner, "\r\x08d\n");
+        assert_eq!(&inner.text[0][..4], b"dbc ");
+        assert_eq!((inner.row, inner.col), (1, 0));
+        assert!(inner.take_present(false));
+        assert!(!inner.take_present(true));
+    }
+
+    /// A full line wraps when the next character arrives, and the last line scrolls the grid up.
+    #[kernel_test]
+    fn wraps_and_scrolls() {
+        let mut inner = FbConsoleInner::new();
+
+        for _ in 0..COLS {
+            inner.put('x');
+        }
+        assert_eq!((inner.row, inner.col), (0, COLS));
+        inner.put('\n');
+        assert_eq!((inner.row, inner.col), (1, 0));
+
+        for _ in 0..COLS {
+            inner.put('y');
+        }
+        inner.put('z');
+        assert_eq!((inner.row, inner.col), (2, 1));
+
+        for _ in 2..ROWS {
+            inner.put('\n');
+        }
+        assert_eq!(inner.row, ROWS - 1);
+        assert_eq!(inner.text[0], [b'y'; COLS]);
+        assert_eq!(inner.text[1][0], b'z');
+        assert_eq!(inner.text[ROWS - 1], [b' '; COLS]);
+    }
+}
@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! GPIO pin state debugging.
+//!
+//! A snapshot of all pins is taken before the kernel's drivers touch them. The `gpio` monitor
+//! command compares the current pin state against it, to show what a driver init changed.
+
+use super::{device_driver::GPIOSnapshot, GPIO};
+use crate::{
+    error::KernelError,
+    info, monitor,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static SNAPSHOT: IRQSafeNullLock<Option<GPIOSnapshot>> = IRQSafeNullLock::new(None);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Print the pins that changed since the last snapshot.
+fn print_diff() -> Result<(), &'static str> {
+    let earlier = match SNAPSHOT.lock(|x| *x) {
+        Some(x) => x,
+        None => return Err("No snapshot taken"),
+    };
+    let now = GPIO.snapshot();
+
+    let mut changed = false;
+    for (pin, before, after) in now.diff(&earlier) {
+        info!("      {:>3}  {}  ->  {}", pin, before, after);
+        changed = true;
+    }
+
+    if !changed {
+        info!("No pin changed since the snapshot");
+    }
+
+    Ok(())
+}
+
+/// Monitor command: `gpio [snap|diff]`.
+fn cmd_gpio(args: &[&str]) -> Result<(), KernelError> {
+    match args {
+        [] => GPIO.dump_state(),
+        ["snap"] => snapshot(),
+        ["diff"] => print_diff()?,
+        _ => return Err("Usage: gpio [snap|diff]".into()),
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Remember the current state of all pins.
+pub fn snapshot() {
+    let now = GPIO.snapshot();
+
+    SNAPSHOT.lock(|x| *x = Some(now));
+}
+
+/// Take the boot snapshot and register the `gpio` monitor command.
+///
+/// This must be called only after successful init of the GPIO driver, before the pins are
+/// configured.
+pub fn init() -> Result<(), &'static str> {
+    snapshot();
+
+    monitor::register_command(("gpio", cmd_gpio))
+}
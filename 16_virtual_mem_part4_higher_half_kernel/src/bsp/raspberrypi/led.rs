@@ -0,0 +1,28 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! ACT LED.
+//!
+//! The LED's wiring differs between boards of the same family, so it is looked up in the detected
+//! board information.
+
+use super::{board, board::ActLed, device_driver::tag, GPIO, MAILBOX};
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Switch the ACT LED on or off.
+pub fn set_act(on: bool) -> Result<(), &'static str> {
+    match board::info().act_led() {
+        ActLed::Gpio(pin) => {
+            GPIO.set_output(pin)?;
+            GPIO.set_level(pin, on)
+        }
+        ActLed::FirmwareGpio(pin) => MAILBOX
+            .property(tag::SET_GPIO_STATE, [pin, on as u32])
+            .map(|_| ()),
+        ActLed::None => Err("Board has no known ACT LED"),
+    }
+}
@@ -21,6 +21,7 @@
 //! |                                             | rw_start == rx_end
 //! | .data                                       |
 //! | .bss                                        |
+//! | .persistent                                 |
 //! |                                             | rw_end_inclusive
 //! +---------------------------------------------+
 //! |                                             | rw_end
@@ -33,11 +34,33 @@
 //! |                                             |                                | direction
 //! |                                             | boot_core_stack_end_inclusive  |
 //! +---------------------------------------------+
+//! |                                             | secondary_core_stacks_start
+//! | Unmapped Core 1 Stack Guard Page            |
+//! | Core 1 Stack                                |
+//! |                                             |
+//! | (Core 2 and Core 3 guard page + stack)      |
+//! |                                             | secondary_core_stacks_end_exclusive
+//! +---------------------------------------------+
+//! |                                             | exception_stacks_start
+//! | Unmapped Core 0 Exception Stack Guard Page  |
+//! | Core 0 Exception Stack                      |
+//! |                                             |
+//! | (Core 1 to Core 3 guard page + stack)       |
+//! |                                             | exception_stacks_end_exclusive
+//! +---------------------------------------------+
 
+mod layout;
 pub mod mmu;
 
-use crate::memory::{Address, Physical, Virtual};
-use core::{cell::UnsafeCell, ops::RangeInclusive};
+use super::{device_driver::tag, MAILBOX};
+use crate::{
+    banner, common, console, cpu, dtb, info,
+    memory::{mmu::PageSliceDescriptor, Address, Physical, Virtual},
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+    units::ByteSize,
+    warn,
+};
+use core::{cell::UnsafeCell, fmt, ops::RangeInclusive, ptr, slice};
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
@@ -45,12 +68,17 @@ use core::{cell::UnsafeCell, ops::RangeInclusive};
 
 // Symbols from the linker script.
 extern "Rust" {
+    static __kernel_virt_start_addr: UnsafeCell<()>;
+    static __rpi_load_addr: UnsafeCell<()>;
+
     static __rx_start: UnsafeCell<()>;
     static __rx_end_exclusive: UnsafeCell<()>;
 
     static __rw_start: UnsafeCell<()>;
     static __bss_start: UnsafeCell<u64>;
     static __bss_end_inclusive: UnsafeCell<u64>;
+    static __persistent_start: UnsafeCell<()>;
+    static __persistent_end_exclusive: UnsafeCell<()>;
     static __rw_end_exclusive: UnsafeCell<()>;
 
     static __boot_core_stack_start: UnsafeCell<()>;
@@ -58,12 +86,41 @@ extern "Rust" {
 
     static __boot_core_stack_guard_page_start: UnsafeCell<()>;
     static __boot_core_stack_guard_page_end_exclusive: UnsafeCell<()>;
+
+    static __secondary_core_stacks_start: UnsafeCell<()>;
+    static __secondary_core_stack_guard_page_size: UnsafeCell<()>;
+    static __secondary_core_stack_size: UnsafeCell<()>;
+    static __secondary_core_stack_slot_size: UnsafeCell<()>;
+
+    static __exception_stacks_start: UnsafeCell<()>;
+    static __exception_stack_guard_page_size: UnsafeCell<()>;
+    static __exception_stack_slot_size: UnsafeCell<()>;
+
+    static __exception_vector_start: UnsafeCell<()>;
+}
+
+/// The value of `IMAGE_CHECKSUM` if the image was not patched.
+const IMAGE_CHECKSUM_DUMMY: u32 = 0xCCCC_EEEE;
+
+#[derive(Copy, Clone)]
+struct DramEnd {
+    addr: Address<Physical>,
+    source: DramSizeSource,
 }
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// Where the size of the DRAM was taken from.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DramSizeSource {
+    Mailbox,
+    DeviceTree,
+    BuiltIn,
+}
+
 /// The board's physical memory map.
 #[rustfmt::skip]
 pub(super) mod map {
@@ -74,19 +131,40 @@ pub(super) mod map {
     pub mod mmio {
         use super::*;
 
+        /// The window that holds the peripherals.
+        pub const WINDOW: PageSliceDescriptor<Physical> =
+            crate::page_slice!(Physical, 0x3F00_0000, 0x0101_0000);
+
+        pub const START:               Address<Physical> = WINDOW.start_addr();
+
+        pub const SYSTEM_TIMER_START:  Address<Physical> = Address::new(0x3F00_3000);
+        pub const SYSTEM_TIMER_SIZE:   usize             =              0x1C;
+
         pub const PERIPHERAL_IC_START: Address<Physical> = Address::new(0x3F00_B200);
         pub const PERIPHERAL_IC_SIZE:  usize             =              0x24;
 
+        pub const MAILBOX_START:       Address<Physical> = Address::new(0x3F00_B880);
+        pub const MAILBOX_SIZE:        usize             =              0x3C;
+
+        pub const PM_WATCHDOG_START:   Address<Physical> = Address::new(0x3F10_0000);
+        pub const PM_WATCHDOG_SIZE:    usize             =              0x28;
+
+        pub const CLOCK_MANAGER_START: Address<Physical> = Address::new(0x3F10_1000);
+        pub const CLOCK_MANAGER_SIZE:  usize             =              0xA8;
+
         pub const GPIO_START:          Address<Physical> = Address::new(0x3F20_0000);
         pub const GPIO_SIZE:           usize             =              0xA0;
 
         pub const PL011_UART_START:    Address<Physical> = Address::new(0x3F20_1000);
-        pub const PL011_UART_SIZE:     usize             =              0x48;
+        pub const PL011_UART_SIZE:     usize             =              0x1000;
+
+        pub const SMI_START:           Address<Physical> = Address::new(0x3F60_0000);
+        pub const SMI_SIZE:            usize             =              0x4;
 
         pub const LOCAL_IC_START:      Address<Physical> = Address::new(0x4000_0000);
         pub const LOCAL_IC_SIZE:       usize             =              0x100;
 
-        pub const END:                 Address<Physical> = Address::new(0x4001_0000);
+        pub const END:                 Address<Physical> = WINDOW.end_addr();
     }
 
     /// Physical devices.
@@ -94,11 +172,32 @@ pub(super) mod map {
     pub mod mmio {
         use super::*;
 
+        /// The window that holds the peripherals.
+        pub const WINDOW: PageSliceDescriptor<Physical> =
+            crate::page_slice!(Physical, 0xFE00_0000, 0x0185_0000);
+
+        pub const START:             Address<Physical> = WINDOW.start_addr();
+
+        pub const SYSTEM_TIMER_START: Address<Physical> = Address::new(0xFE00_3000);
+        pub const SYSTEM_TIMER_SIZE:  usize             =              0x1C;
+
+        pub const MAILBOX_START:     Address<Physical> = Address::new(0xFE00_B880);
+        pub const MAILBOX_SIZE:      usize             =              0x3C;
+
+        pub const PM_WATCHDOG_START: Address<Physical> = Address::new(0xFE10_0000);
+        pub const PM_WATCHDOG_SIZE:  usize             =              0x28;
+
+        pub const CLOCK_MANAGER_START: Address<Physical> = Address::new(0xFE10_1000);
+        pub const CLOCK_MANAGER_SIZE:  usize             =              0xA8;
+
         pub const GPIO_START:       Address<Physical> = Address::new(0xFE20_0000);
-        pub const GPIO_SIZE:        usize             =              0xA0;
+        pub const GPIO_SIZE:        usize             =              0xF4;
 
         pub const PL011_UART_START: Address<Physical> = Address::new(0xFE20_1000);
-        pub const PL011_UART_SIZE:  usize             =              0x48;
+        pub const PL011_UART_SIZE:  usize             =              0x1000;
+
+        pub const SMI_START:        Address<Physical> = Address::new(0xFE60_0000);
+        pub const SMI_SIZE:         usize             =              0x4;
 
         pub const GICD_START:       Address<Physical> = Address::new(0xFF84_1000);
         pub const GICD_SIZE:        usize             =              0x824;
@@ -106,12 +205,46 @@ pub(super) mod map {
         pub const GICC_START:       Address<Physical> = Address::new(0xFF84_2000);
         pub const GICC_SIZE:        usize             =              0x14;
 
-        pub const END:              Address<Physical> = Address::new(0xFF85_0000);
+        pub const END:              Address<Physical> = WINDOW.end_addr();
     }
 
+    /// The firmware's spin-table, where the secondary cores wait for their release.
+    pub const SPIN_TABLE_START: Address<Physical> = Address::new(0xD8);
+    pub const SPIN_TABLE_SIZE:  usize             =              0x20;
+
+    /// End of the DRAM that belongs to the ARM cores with the firmware's default GPU memory split.
+    /// The memory above is owned by the VideoCore.
+    ///
+    /// Only used if the firmware does not pass a device tree. See `dram_end()`.
+    pub const DRAM_END: Address<Physical> = Address::new(0x3B40_0000);
+
+    /// Start of the peripherals in the VideoCore's bus address space, which is the address space
+    /// of the device tree's `/soc` node.
+    pub const BUS_MMIO_START: u64 = 0x7E00_0000;
+
     pub const END: Address<Physical> = mmio::END;
 }
 
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static DRAM_END: InitStateLock<DramEnd> = InitStateLock::new(DramEnd {
+    addr: map::DRAM_END,
+    source: DramSizeSource::BuiltIn,
+});
+
+/// Start and size of the memory that the firmware reserved for the VideoCore.
+static GPU_MEMORY: InitStateLock<Option<(Address<Physical>, usize)>> = InitStateLock::new(None);
+
+/// The CRC-32 of the code and RO data, from `__rx_start` to `__rx_end_exclusive`.
+///
+/// This will be patched to the correct value by the "translation table tool" after linking. This
+/// given value here is just a dummy.
+#[link_section = ".image_checksum"]
+#[no_mangle]
+static IMAGE_CHECKSUM: u32 = IMAGE_CHECKSUM_DUMMY;
+
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
@@ -181,16 +314,301 @@ fn boot_core_stack_guard_page_size() -> usize {
     }
 }
 
+/// Start address of the given secondary core's stack guard page.
+///
+/// # Safety
+///
+/// - Values are provided by the linker script and must be trusted as-is.
+#[inline(always)]
+fn virt_secondary_core_stack_guard_page_start(core_id: usize) -> Address<Virtual> {
+    assert!((core_id > 0) && (core_id < super::cpu::NUM_CORES));
+
+    let slot_start = unsafe {
+        (__secondary_core_stacks_start.get() as usize)
+            + ((core_id - 1) * (__secondary_core_stack_slot_size.get() as usize))
+    };
+
+    Address::new(slot_start)
+}
+
+/// Size of a secondary core's stack guard page.
+#[inline(always)]
+fn secondary_core_stack_guard_page_size() -> usize {
+    unsafe { __secondary_core_stack_guard_page_size.get() as usize }
+}
+
+/// Start address of the given secondary core's stack.
+#[inline(always)]
+fn virt_secondary_core_stack_start(core_id: usize) -> Address<Virtual> {
+    virt_secondary_core_stack_guard_page_start(core_id) + secondary_core_stack_guard_page_size()
+}
+
+/// Size of a secondary core's stack.
+#[inline(always)]
+fn secondary_core_stack_size() -> usize {
+    unsafe { __secondary_core_stack_size.get() as usize }
+}
+
+/// Start address of the given core's exception stack guard page.
+///
+/// # Safety
+///
+/// - Values are provided by the linker script and must be trusted as-is.
+#[inline(always)]
+fn virt_exception_stack_guard_page_start(core_id: usize) -> Address<Virtual> {
+    assert!(core_id < super::cpu::NUM_CORES);
+
+    let slot_start = unsafe {
+        (__exception_stacks_start.get() as usize)
+            + (core_id * (__exception_stack_slot_size.get() as usize))
+    };
+
+    Address::new(slot_start)
+}
+
+/// Size of an exception stack guard page.
+#[inline(always)]
+fn exception_stack_guard_page_size() -> usize {
+    unsafe { __exception_stack_guard_page_size.get() as usize }
+}
+
+/// Start address of the given core's exception stack.
+#[inline(always)]
+fn virt_exception_stack_start(core_id: usize) -> Address<Virtual> {
+    virt_exception_stack_guard_page_start(core_id) + exception_stack_guard_page_size()
+}
+
+/// Size of an exception stack.
+#[inline(always)]
+fn exception_stack_size() -> usize {
+    super::exception::STACK_SIZE
+}
+
 /// Exclusive end address of the physical address space.
 #[inline(always)]
 fn phys_addr_space_end() -> Address<Physical> {
     map::END
 }
 
+/// Turn the size of the DRAM at address zero into its page-aligned end, if it is plausible.
+fn checked_dram_end(size: usize) -> Option<Address<Physical>> {
+    let end = Address::new(size).align_down(mmu::KernelGranule::SIZE);
+    if (end <= mmu::phys_unused_dram_start()) || (end > map::mmio::START) {
+        return None;
+    }
+
+    Some(end)
+}
+
+/// Return the end of the ARM's DRAM and the VideoCore's share, as reported by the firmware.
+fn mailbox_memory_split() -> Result<(Address<Physical>, (Address<Physical>, usize)), &'static str> {
+    let [arm_base, arm_size] = MAILBOX.property(tag::GET_ARM_MEMORY, [0, 0])?;
+    if arm_base != 0 {
+        return Err("Firmware reports no ARM memory at address zero");
+    }
+    let end = checked_dram_end(arm_size as usize)
+        .ok_or("Firmware reports an implausible ARM memory size")?;
+
+    let [gpu_base, gpu_size] = MAILBOX.property(tag::GET_VC_MEMORY, [0, 0])?;
+
+    Ok((end, (Address::new(gpu_base as usize), gpu_size as usize)))
+}
+
+/// Return the end of the DRAM at address zero, as reported by the device tree.
+fn dtb_dram_end(tree: &dtb::DeviceTree) -> Result<Address<Physical>, &'static str> {
+    let region = tree
+        .memory_regions()
+        .ok_or("Device tree has no memory node")?
+        .find(|x| x.addr == 0)
+        .ok_or("Device tree reports no DRAM at address zero")?;
+
+    checked_dram_end(region.size as usize).ok_or("Device tree reports an implausible DRAM size")
+}
+
+/// Render the DRAM split for the banner.
+fn render(out: &mut dyn fmt::Write) -> fmt::Result {
+    match gpu_memory() {
+        Some((gpu_start, gpu_size)) => write!(
+            out,
+            "ARM {}, GPU {} at {}, split taken from the {}",
+            ByteSize(dram_end().into_usize()),
+            ByteSize(gpu_size),
+            gpu_start,
+            dram_size_source()
+        ),
+        None => write!(
+            out,
+            "{}, size taken from the {}",
+            ByteSize(dram_end().into_usize()),
+            dram_size_source()
+        ),
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+impl fmt::Display for DramSizeSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DramSizeSource::Mailbox => f.pad("mailbox"),
+            DramSizeSource::DeviceTree => f.pad("device tree"),
+            DramSizeSource::BuiltIn => f.pad("built-in default"),
+        }
+    }
+}
+
+/// Check the invariants of the linker script's layout, and panic naming the first violated one.
+///
+/// Runs first thing in `kernel_init()`, before anything relies on the layout.
+pub fn validate_layout() {
+    let layout = unsafe {
+        layout::KernelLayout {
+            virt_start_addr: __kernel_virt_start_addr.get() as usize,
+            load_addr: __rpi_load_addr.get() as usize,
+            rx_start: __rx_start.get() as usize,
+            rx_end_exclusive: __rx_end_exclusive.get() as usize,
+            rw_start: __rw_start.get() as usize,
+            bss_start: __bss_start.get() as usize,
+            bss_end_inclusive: __bss_end_inclusive.get() as usize,
+            rw_end_exclusive: __rw_end_exclusive.get() as usize,
+            boot_core_stack_guard_page_start: __boot_core_stack_guard_page_start.get() as usize,
+            boot_core_stack_start: __boot_core_stack_start.get() as usize,
+            boot_core_stack_end_exclusive: __boot_core_stack_end_exclusive.get() as usize,
+            exception_vector_start: __exception_vector_start.get() as usize,
+        }
+    };
+
+    if let Err(x) = layout.check() {
+        // The exception vectors are not installed yet, so a fault in the panic handler would go
+        // unreported.
+        console::emergency_write("Kernel layout invariant violated: ");
+        console::emergency_write(x);
+        console::emergency_write("\n");
+
+        panic!("Kernel layout invariant violated: {}", x);
+    }
+}
+
+/// Check the code and RO data against the checksum that was recorded at build time, and print the
+/// result.
+///
+/// Catches images that were corrupted on the SD card or during a chainloader transfer. The checksum
+/// is taken after the tables' base address has been patched into the code, so this must run before
+/// anything else writes to the range.
+pub fn check_image_integrity() {
+    let (start, size) = unsafe {
+        let start = __rx_start.get() as usize;
+
+        (start, __rx_end_exclusive.get() as usize - start)
+    };
+
+    // The firmware or the chainloader wrote the image, possibly with the data cache off. Read what
+    // is in memory, not what a stale cache line holds.
+    let image = unsafe {
+        cpu::cache::clean_invalidate_dcache_range(start, size);
+
+        slice::from_raw_parts(start as *const u8, size)
+    };
+
+    // The value is patched after linking, so the compiler must not fold the dummy.
+    let expected = unsafe { ptr::read_volatile(&IMAGE_CHECKSUM) };
+    if expected == IMAGE_CHECKSUM_DUMMY {
+        warn!("Image checksum: Not recorded, the image was not patched");
+        return;
+    }
+
+    let actual = common::crc32_update(0, image);
+    if actual == expected {
+        info!("Image checksum: PASS ({:#010x})", actual);
+    } else {
+        warn!(
+            "Image checksum: MISMATCH! Expected {:#010x}, actual {:#010x}. The image is corrupted",
+            expected, actual
+        );
+    }
+}
+
+/// Find out how much of the DRAM belongs to the ARM cores.
+///
+/// The firmware splits the DRAM between the ARM cores and the VideoCore according to config.txt.
+/// The split is asked for over the mailbox first, then taken from the device tree. The built-in
+/// default stays in effect if neither is usable. Either way, the split is added to the banner.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init, after `dtb::init()` and after the mailbox is up.
+pub unsafe fn init() -> Result<(), &'static str> {
+    if let Err(x) = banner::register_section(("DRAM", render)) {
+        warn!("{}", x);
+    }
+
+    match mailbox_memory_split() {
+        Ok((addr, gpu)) => {
+            DRAM_END.write(|x| {
+                *x = DramEnd {
+                    addr,
+                    source: DramSizeSource::Mailbox,
+                }
+            });
+            GPU_MEMORY.write(|x| *x = Some(gpu));
+        }
+        Err(x) => info!("Mailbox does not report the memory split: {}", x),
+    }
+
+    let tree = match dtb::device_tree() {
+        None if dram_size_source() == DramSizeSource::Mailbox => return Ok(()),
+        None => return Err("No device tree"),
+        Some(x) => x,
+    };
+
+    // The MMIO addresses are built in. Point out if the firmware sees the peripherals elsewhere.
+    match tree.translate_soc_addr(map::BUS_MMIO_START) {
+        Some(x) if x == map::mmio::START.into_usize() as u64 => (),
+        Some(x) => warn!(
+            "Device tree puts the peripherals at {:#x}, expected {}",
+            x,
+            map::mmio::START
+        ),
+        None => info!("Device tree does not describe the peripherals"),
+    }
+
+    if dram_size_source() == DramSizeSource::Mailbox {
+        return Ok(());
+    }
+
+    let addr = dtb_dram_end(&tree)?;
+    DRAM_END.write(|x| {
+        *x = DramEnd {
+            addr,
+            source: DramSizeSource::DeviceTree,
+        }
+    });
+
+    Ok(())
+}
+
+/// The physical window that holds the peripherals.
+pub const fn phys_mmio_window() -> PageSliceDescriptor<Physical> {
+    map::mmio::WINDOW
+}
+
+/// Exclusive end address of the DRAM that belongs to the ARM cores.
+pub fn dram_end() -> Address<Physical> {
+    DRAM_END.read(|x| x.addr)
+}
+
+/// Where the value of `dram_end()` was taken from.
+pub fn dram_size_source() -> DramSizeSource {
+    DRAM_END.read(|x| x.source)
+}
+
+/// Start and size of the VideoCore's memory. Only known if the mailbox reported the split.
+pub fn gpu_memory() -> Option<(Address<Physical>, usize)> {
+    GPU_MEMORY.read(|x| *x)
+}
+
 /// Return the inclusive range spanning the .bss section.
 ///
 /// # Safety
@@ -206,3 +624,17 @@ pub fn bss_range_inclusive() -> RangeInclusive<*mut u64> {
 
     range
 }
+
+/// Start address and size of the memory that is not zeroed at boot.
+///
+/// Its content survives a reset, but not a power cycle.
+pub fn virt_persistent_region() -> (Address<Virtual>, usize) {
+    unsafe {
+        let start = __persistent_start.get() as usize;
+
+        (
+            Address::new(start),
+            (__persistent_end_exclusive.get() as usize) - start,
+        )
+    }
+}
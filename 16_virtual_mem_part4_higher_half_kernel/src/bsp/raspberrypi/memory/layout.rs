@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Invariants of the kernel's memory layout.
+//!
+//! The layout is defined by the linker script, and the MMU code as well as the translation table
+//! tool rely on properties that the script only establishes implicitly. Breaking one of them in the
+//! script goes unnoticed until something faults much later, so they are checked at boot.
+//!
+//! The attributes of the kernel binary's sections are defined here too, since the translation table
+//! tool maps the sections with them.
+
+use crate::{
+    bsp::memory::mmu::KernelGranule,
+    memory::mmu::{AccessPermissions, AttributeFields, MemAttributes},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The attributes of the code and RO data.
+pub const CODE_ATTRIBUTES: AttributeFields = AttributeFields {
+    mem_attributes: MemAttributes::CacheableDRAM,
+    acc_perms: AccessPermissions::ReadOnly,
+    execute_never: false,
+};
+
+/// The attributes of the RW data and the stacks.
+pub const DATA_ATTRIBUTES: AttributeFields = AttributeFields {
+    mem_attributes: MemAttributes::CacheableDRAM,
+    acc_perms: AccessPermissions::ReadWrite,
+    execute_never: true,
+};
+
+/// The addresses that the linker script exports.
+#[allow(missing_docs)]
+#[derive(Copy, Clone)]
+pub struct KernelLayout {
+    pub virt_start_addr: usize,
+    pub load_addr: usize,
+    pub rx_start: usize,
+    pub rx_end_exclusive: usize,
+    pub rw_start: usize,
+    pub bss_start: usize,
+    pub bss_end_inclusive: usize,
+    pub rw_end_exclusive: usize,
+    pub boot_core_stack_guard_page_start: usize,
+    pub boot_core_stack_start: usize,
+    pub boot_core_stack_end_exclusive: usize,
+    pub exception_vector_start: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+#[inline(always)]
+fn is_page_aligned(addr: usize) -> bool {
+    addr & KernelGranule::MASK == 0
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl KernelLayout {
+    /// Check all invariants. The error names the first one that does not hold.
+    pub fn check(&self) -> Result<(), &'static str> {
+        if self.rx_start != self.virt_start_addr + self.load_addr {
+            return Err(".text does not start at the load address");
+        }
+
+        let boundaries = [
+            self.rx_start,
+            self.rx_end_exclusive,
+            self.rw_start,
+            self.rw_end_exclusive,
+            self.boot_core_stack_guard_page_start,
+            self.boot_core_stack_start,
+            self.boot_core_stack_end_exclusive,
+        ];
+        if !boundaries.iter().all(|x| is_page_aligned(*x)) {
+            return Err("Section boundary not aligned to KernelGranule::SIZE");
+        }
+
+        if self.rw_start < self.rx_end_exclusive {
+            return Err("RW range overlaps the RX range");
+        }
+
+        if (self.bss_start < self.rw_start) || (self.bss_end_inclusive >= self.rw_end_exclusive) {
+            return Err(".bss is outside of the RW range");
+        }
+
+        if (self.boot_core_stack_guard_page_start < self.rw_end_exclusive)
+            || (self.boot_core_stack_start <= self.bss_end_inclusive)
+        {
+            return Err("Boot core stack overlaps .bss");
+        }
+
+        if self.boot_core_stack_end_exclusive <= self.boot_core_stack_start {
+            return Err("Boot core stack is empty");
+        }
+
+        if (self.exception_vector_start & 0x7FF) != 0 {
+            return Err("Exception vector table not aligned to 2 KiB");
+        }
+
+        if (self.exception_vector_start < self.rx_start)
+            || (self.exception_vector_start >= self.rx_end_exclusive)
+        {
+            return Err("Exception vector table is outside of the RX range");
+        }
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(all(test, not(target_os = "none")))]
+mod host_tests {
+    use super::*;
+
+    const VIRT_START: usize = 0xFFFF_FFFF_C000_0000;
+
+    /// The layout that the linker script produces, give or take the section sizes.
+    fn good() -> KernelLayout {
+        KernelLayout {
+            virt_start_addr: VIRT_START,
+            load_addr: 0x8_0000,
+            rx_start: VIRT_START + 0x8_0000,
+            rx_end_exclusive: VIRT_START + 0xA_0000,
+            rw_start: VIRT_START + 0xA_0000,
+            bss_start: VIRT_START + 0xA_1000,
+            bss_end_inclusive: VIRT_START + 0xA_2FF8,
+            rw_end_exclusive: VIRT_START + 0xB_0000,
+            boot_core_stack_guard_page_start: VIRT_START + 0xB_0000,
+            boot_core_stack_start: VIRT_START + 0xC_0000,
+            boot_core_stack_end_exclusive: VIRT_START + 0x14_0000,
+            exception_vector_start: VIRT_START + 0x8_1800,
+        }
+    }
+
+    fn violation(modify: impl FnOnce(&mut KernelLayout)) -> &'static str {
+        let mut layout = good();
+        modify(&mut layout);
+
+        layout.check().unwrap_err()
+    }
+
+    #[test]
+    fn linker_script_layout_passes() {
+        assert_eq!(good().check(), Ok(()));
+    }
+
+    #[test]
+    fn text_must_start_at_load_address() {
+        assert_eq!(
+            violation(|x| x.load_addr = 0x9_0000),
+            ".text does not start at the load address"
+        );
+    }
+
+    #[test]
+    fn sections_must_be_page_aligned() {
+        assert_eq!(
+            violation(|x| x.rw_end_exclusive += 0x1000),
+            "Section boundary not aligned to KernelGranule::SIZE"
+        );
+    }
+
+    #[test]
+    fn stack_must_not_overlap_bss() {
+        assert_eq!(
+            violation(|x| {
+                x.bss_end_inclusive = VIRT_START + 0xC_0008;
+                x.rw_end_exclusive = VIRT_START + 0xD_0000;
+            }),
+            "Boot core stack overlaps .bss"
+        );
+    }
+
+    #[test]
+    fn vector_table_must_be_2kib_aligned() {
+        assert_eq!(
+            violation(|x| x.exception_vector_start += 0x400),
+            "Exception vector table not aligned to 2 KiB"
+        );
+    }
+}
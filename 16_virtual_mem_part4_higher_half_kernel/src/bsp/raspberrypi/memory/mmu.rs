@@ -4,17 +4,19 @@
 
 //! BSP Memory Management Unit.
 
+use super::layout::{CODE_ATTRIBUTES, DATA_ATTRIBUTES};
 use crate::{
-    common,
+    common, dtb,
     memory::{
         mmu as generic_mmu,
         mmu::{
-            AccessPermissions, AddressSpace, AssociatedTranslationTable, AttributeFields,
-            MemAttributes, Page, PageSliceDescriptor, TranslationGranule,
+            AddressSpace, AssociatedTranslationTable, Page, PageSliceDescriptor,
+            TranslationDescriptor, TranslationGranule,
         },
-        Physical, Virtual,
+        Address, Physical, Virtual,
     },
     synchronization::InitStateLock,
+    warn,
 };
 use core::convert::TryInto;
 
@@ -25,6 +27,14 @@ use core::convert::TryInto;
 type KernelTranslationTable =
     <KernelVirtAddrSpace as AssociatedTranslationTable>::TableStartFromTop;
 
+/// Code and RO data, RW data, the boot core's stack, one stack per secondary core and one exception
+/// stack per core.
+const NUM_LAYOUT_SECTIONS: usize =
+    3 + (super::super::cpu::NUM_CORES - 1) + super::super::cpu::NUM_CORES;
+
+/// Index of the first exception stack in the layout.
+const FIRST_EXCEPTION_STACK_SECTION: usize = 3 + (super::super::cpu::NUM_CORES - 1);
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -36,6 +46,16 @@ pub type KernelGranule = TranslationGranule<{ 64 * 1024 }>;
 /// The kernel's virtual address space defined by this BSP.
 pub type KernelVirtAddrSpace = AddressSpace<{ get_virt_addr_space_size() }>;
 
+/// The number of pages of the DMA pool, see `memory::dma_pool`. The Raspberry Pi 4 has more DRAM to
+/// spare.
+#[cfg(feature = "bsp_rpi3")]
+pub const DMA_POOL_NUM_PAGES: usize = 4;
+
+/// The number of pages of the DMA pool, see `memory::dma_pool`. The Raspberry Pi 4 has more DRAM to
+/// spare.
+#[cfg(feature = "bsp_rpi4")]
+pub const DMA_POOL_NUM_PAGES: usize = 16;
+
 //--------------------------------------------------------------------------------------------------
 // Global instances
 //--------------------------------------------------------------------------------------------------
@@ -58,6 +78,14 @@ static KERNEL_TABLES: InitStateLock<KernelTranslationTable> =
 #[no_mangle]
 static PHYS_KERNEL_TABLES_BASE_ADDR: u64 = 0xCCCCAAAAFFFFEEEE;
 
+/// A second instance of the kernel's table type, for the tests of the translation table code.
+///
+/// It is all-zero, so it is placed in `.bss`. It is never installed into a TTBR, so tests can
+/// break it without breaking the kernel.
+#[cfg(test)]
+static TEST_TABLES: crate::synchronization::IRQSafeNullLock<KernelTranslationTable> =
+    crate::synchronization::IRQSafeNullLock::new(KernelTranslationTable::new_for_runtime());
+
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
@@ -73,33 +101,30 @@ const fn get_virt_addr_space_size() -> usize {
     __kernel_virt_addr_space_size
 }
 
-/// Helper function for calculating the number of pages the given parameter spans.
-const fn size_to_num_pages(size: usize) -> usize {
-    assert!(size > 0);
-    assert!(size % KernelGranule::SIZE == 0);
-
-    size >> KernelGranule::SHIFT
-}
-
 /// The Read+Execute (RX) pages of the kernel binary.
 fn virt_rx_page_desc() -> PageSliceDescriptor<Virtual> {
-    let num_pages = size_to_num_pages(super::rx_size());
-
-    PageSliceDescriptor::from_addr(super::virt_rx_start(), num_pages)
+    PageSliceDescriptor::from_addr_and_size(super::virt_rx_start(), super::rx_size())
 }
 
 /// The Read+Write (RW) pages of the kernel binary.
 fn virt_rw_page_desc() -> PageSliceDescriptor<Virtual> {
-    let num_pages = size_to_num_pages(super::rw_size());
-
-    PageSliceDescriptor::from_addr(super::virt_rw_start(), num_pages)
+    PageSliceDescriptor::from_addr_and_size(super::virt_rw_start(), super::rw_size())
 }
 
 /// The boot core's stack.
 fn virt_boot_core_stack_page_desc() -> PageSliceDescriptor<Virtual> {
-    let num_pages = size_to_num_pages(super::boot_core_stack_size());
+    PageSliceDescriptor::from_addr_and_size(
+        super::virt_boot_core_stack_start(),
+        super::boot_core_stack_size(),
+    )
+}
 
-    PageSliceDescriptor::from_addr(super::virt_boot_core_stack_start(), num_pages)
+/// The given secondary core's stack.
+fn virt_secondary_core_stack_page_desc(core_id: usize) -> PageSliceDescriptor<Virtual> {
+    PageSliceDescriptor::from_addr_and_size(
+        super::virt_secondary_core_stack_start(core_id),
+        super::secondary_core_stack_size(),
+    )
 }
 
 // There is no reason to expect the following conversions to fail, since they were generated offline
@@ -120,6 +145,62 @@ fn phys_boot_core_stack_page_desc() -> PageSliceDescriptor<Physical> {
     virt_boot_core_stack_page_desc().try_into().unwrap()
 }
 
+/// The given secondary core's stack.
+fn phys_secondary_core_stack_page_desc(core_id: usize) -> PageSliceDescriptor<Physical> {
+    virt_secondary_core_stack_page_desc(core_id)
+        .try_into()
+        .unwrap()
+}
+
+/// The given core's exception stack.
+fn phys_exception_stack_page_desc(core_id: usize) -> PageSliceDescriptor<Physical> {
+    virt_exception_stack_page_desc(core_id).try_into().unwrap()
+}
+
+/// The section of the kernel's virtual memory layout with the given index.
+fn layout_section(index: usize) -> TranslationDescriptor {
+    match index {
+        0 => TranslationDescriptor {
+            name: "Kernel code and RO data",
+            virt_pages: virt_rx_page_desc(),
+            phys_pages: phys_rx_page_desc(),
+            attribute_fields: CODE_ATTRIBUTES,
+        },
+        1 => TranslationDescriptor {
+            name: "Kernel data and bss",
+            virt_pages: virt_rw_page_desc(),
+            phys_pages: phys_rw_page_desc(),
+            attribute_fields: DATA_ATTRIBUTES,
+        },
+        2 => TranslationDescriptor {
+            name: "Kernel boot-core stack",
+            virt_pages: virt_boot_core_stack_page_desc(),
+            phys_pages: phys_boot_core_stack_page_desc(),
+            attribute_fields: DATA_ATTRIBUTES,
+        },
+        _ if index < FIRST_EXCEPTION_STACK_SECTION => {
+            let core_id = index - 2;
+
+            TranslationDescriptor {
+                name: "Kernel secondary-core stack",
+                virt_pages: virt_secondary_core_stack_page_desc(core_id),
+                phys_pages: phys_secondary_core_stack_page_desc(core_id),
+                attribute_fields: DATA_ATTRIBUTES,
+            }
+        }
+        _ => {
+            let core_id = index - FIRST_EXCEPTION_STACK_SECTION;
+
+            TranslationDescriptor {
+                name: "Kernel exception stack",
+                virt_pages: virt_exception_stack_page_desc(core_id),
+                phys_pages: phys_exception_stack_page_desc(core_id),
+                attribute_fields: DATA_ATTRIBUTES,
+            }
+        }
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -129,11 +210,124 @@ pub fn kernel_translation_tables() -> &'static InitStateLock<KernelTranslationTa
     &KERNEL_TABLES
 }
 
+/// The pages that hold the kernel's translation tables.
+pub fn virt_kernel_tables_page_desc() -> PageSliceDescriptor<Virtual> {
+    PageSliceDescriptor::from_addr_and_size(
+        Address::new(&KERNEL_TABLES as *const _ as usize),
+        core::mem::size_of::<KernelTranslationTable>(),
+    )
+}
+
+/// Return a reference to the test instance of the kernel's translation table type.
+#[cfg(test)]
+pub fn test_translation_tables(
+) -> &'static crate::synchronization::IRQSafeNullLock<KernelTranslationTable> {
+    &TEST_TABLES
+}
+
 /// The boot core's stack guard page.
 pub fn virt_boot_core_stack_guard_page_desc() -> PageSliceDescriptor<Virtual> {
-    let num_pages = size_to_num_pages(super::boot_core_stack_guard_page_size());
+    PageSliceDescriptor::from_addr_and_size(
+        super::virt_boot_core_stack_guard_page_start(),
+        super::boot_core_stack_guard_page_size(),
+    )
+}
 
-    PageSliceDescriptor::from_addr(super::virt_boot_core_stack_guard_page_start(), num_pages)
+/// The given secondary core's stack guard page.
+pub fn virt_secondary_core_stack_guard_page_desc(core_id: usize) -> PageSliceDescriptor<Virtual> {
+    PageSliceDescriptor::from_addr_and_size(
+        super::virt_secondary_core_stack_guard_page_start(core_id),
+        super::secondary_core_stack_guard_page_size(),
+    )
+}
+
+/// The stack that the given core runs kernel code on. The boot core's, or a secondary core's.
+pub fn virt_kernel_stack_page_desc(core_id: usize) -> PageSliceDescriptor<Virtual> {
+    if core_id == super::super::cpu::BOOT_CORE_ID as usize {
+        virt_boot_core_stack_page_desc()
+    } else {
+        virt_secondary_core_stack_page_desc(core_id)
+    }
+}
+
+/// The given core's exception stack.
+pub fn virt_exception_stack_page_desc(core_id: usize) -> PageSliceDescriptor<Virtual> {
+    PageSliceDescriptor::from_addr_and_size(
+        super::virt_exception_stack_start(core_id),
+        super::exception_stack_size(),
+    )
+}
+
+/// The given core's exception stack guard page.
+pub fn virt_exception_stack_guard_page_desc(core_id: usize) -> PageSliceDescriptor<Virtual> {
+    PageSliceDescriptor::from_addr_and_size(
+        super::virt_exception_stack_guard_page_start(core_id),
+        super::exception_stack_guard_page_size(),
+    )
+}
+
+/// The start of the physical DRAM that is not used by the kernel binary or its stacks.
+pub(super) fn phys_unused_dram_start() -> Address<Physical> {
+    phys_exception_stack_page_desc(super::super::cpu::NUM_CORES - 1).end_addr()
+}
+
+/// The physical DRAM that is not used by the kernel binary or its stacks.
+///
+/// Starts right after the last core's exception stack and ends where the VideoCore's memory starts.
+/// The memory below the kernel binary is excluded, since it holds the firmware's data, for example,
+/// the spin-table. If the firmware put the device tree into this range, only the part in front of
+/// or behind it is returned, whichever is larger.
+fn phys_free_dram_page_desc() -> PageSliceDescriptor<Physical> {
+    let mut start = phys_unused_dram_start();
+    let mut end = super::dram_end();
+
+    if let Some(dtb) = dtb::phys_page_desc() {
+        if (dtb.start_addr() < end) && (dtb.end_addr() > start) {
+            let front = dtb
+                .start_addr()
+                .into_usize()
+                .saturating_sub(start.into_usize());
+            let back = end.into_usize().saturating_sub(dtb.end_addr().into_usize());
+
+            if front >= back {
+                end = dtb.start_addr();
+            } else {
+                start = dtb.end_addr();
+            }
+        }
+    }
+
+    let num_pages = (end.into_usize().saturating_sub(start.into_usize())) >> KernelGranule::SHIFT;
+
+    PageSliceDescriptor::from_addr(start, num_pages)
+}
+
+/// The physical pages of the DMA pool, at the start of the DRAM that is not used by the kernel
+/// binary or its stacks. None if the DRAM is too small for it.
+pub fn phys_dma_pool_page_desc() -> Option<PageSliceDescriptor<Physical>> {
+    let free = phys_free_dram_page_desc();
+    if free.num_pages() < DMA_POOL_NUM_PAGES {
+        return None;
+    }
+
+    Some(PageSliceDescriptor::from_addr(
+        free.start_addr(),
+        DMA_POOL_NUM_PAGES,
+    ))
+}
+
+/// The physical DRAM that is not used by the kernel binary, its stacks or the DMA pool.
+///
+/// Loaders, benchmarks and tests take their memory from here.
+pub fn phys_unused_dram_page_desc() -> PageSliceDescriptor<Physical> {
+    let free = phys_free_dram_page_desc();
+
+    match phys_dma_pool_page_desc() {
+        None => free,
+        Some(pool) => {
+            PageSliceDescriptor::from_addr(pool.end_addr(), free.num_pages() - pool.num_pages())
+        }
+    }
 }
 
 /// Pointer to the last page of the physical address space.
@@ -144,6 +338,38 @@ pub fn phys_addr_space_end_page() -> *const Page<Physical> {
     ) as *const Page<_>
 }
 
+/// The kernel's precomputed virtual memory layout, ordered by virtual address.
+///
+/// The stack guard pages are not part of it, since they are not mapped.
+pub fn virt_mem_layout() -> impl Iterator<Item = TranslationDescriptor> {
+    (0..NUM_LAYOUT_SECTIONS).map(layout_section)
+}
+
+/// The section of the kernel's virtual memory layout that contains `virt_addr`, if any.
+pub fn virt_addr_properties(virt_addr: Address<Virtual>) -> Option<TranslationDescriptor> {
+    virt_mem_layout().find(|x| x.virt_pages.contains(virt_addr))
+}
+
+/// Check the precomputed entries of the kernel binary against the kernel's descriptor encoding.
+///
+/// The `translation table tool` encodes them with the same code, so a mismatch means that the image
+/// was patched by something else, or corrupted afterwards.
+pub fn kernel_verify_precomputed() -> Result<(), &'static str> {
+    for section in virt_mem_layout() {
+        generic_mmu::kernel_verify_pages_at(
+            &section.virt_pages,
+            &section.phys_pages,
+            &section.attribute_fields,
+        )
+        .map_err(|x| {
+            warn!("{}: {}", section.name, x);
+            "Entries differ from the kernel's encoding"
+        })?;
+    }
+
+    Ok(())
+}
+
 /// Add mapping records for the kernel binary.
 ///
 /// The actual translation table entries for the kernel binary are generated using the offline
@@ -152,36 +378,12 @@ pub fn phys_addr_space_end_page() -> *const Page<Physical> {
 ///
 /// It must be ensured that these entries are in sync with the offline tool.
 pub fn kernel_add_mapping_records_for_precomputed() {
-    generic_mmu::kernel_add_mapping_record(
-        "Kernel code and RO data",
-        &virt_rx_page_desc(),
-        &phys_rx_page_desc(),
-        &AttributeFields {
-            mem_attributes: MemAttributes::CacheableDRAM,
-            acc_perms: AccessPermissions::ReadOnly,
-            execute_never: false,
-        },
-    );
-
-    generic_mmu::kernel_add_mapping_record(
-        "Kernel data and bss",
-        &virt_rw_page_desc(),
-        &phys_rw_page_desc(),
-        &AttributeFields {
-            mem_attributes: MemAttributes::CacheableDRAM,
-            acc_perms: AccessPermissions::ReadWrite,
-            execute_never: true,
-        },
-    );
-
-    generic_mmu::kernel_add_mapping_record(
-        "Kernel boot-core stack",
-        &virt_boot_core_stack_page_desc(),
-        &phys_boot_core_stack_page_desc(),
-        &AttributeFields {
-            mem_attributes: MemAttributes::CacheableDRAM,
-            acc_perms: AccessPermissions::ReadWrite,
-            execute_never: true,
-        },
-    );
+    for section in virt_mem_layout() {
+        generic_mmu::kernel_add_mapping_record(
+            section.name,
+            &section.virt_pages,
+            &section.phys_pages,
+            &section.attribute_fields,
+        );
+    }
 }
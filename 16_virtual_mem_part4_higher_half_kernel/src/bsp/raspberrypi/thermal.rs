@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! SoC temperature, as measured by the firmware.
+
+use super::{board, device_driver::tag, MAILBOX};
+use crate::info;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The firmware's ID of the SoC's sensor.
+const SENSOR_ID: u32 = 0;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// The current temperature in millidegrees Celsius.
+pub fn temperature() -> Result<u32, &'static str> {
+    let [_, temp] = MAILBOX.property(tag::GET_TEMPERATURE, [SENSOR_ID, 0])?;
+
+    Ok(temp)
+}
+
+/// The temperature in millidegrees Celsius at which the firmware throttles the clock.
+pub fn max_temperature() -> Result<u32, &'static str> {
+    let [_, temp] = MAILBOX.property(tag::GET_MAX_TEMPERATURE, [SENSOR_ID, 0])?;
+
+    Ok(temp)
+}
+
+/// Print the temperature and the limits that apply to the detected board.
+pub fn report() {
+    match temperature() {
+        Err(x) => info!("SoC temperature: Unknown ({})", x),
+        Ok(x) => info!("SoC temperature: {}.{} °C", x / 1000, (x % 1000) / 100),
+    }
+
+    if let Ok(x) = max_temperature() {
+        info!(
+            "      Firmware throttles at {}.{} °C",
+            x / 1000,
+            (x % 1000) / 100
+        );
+    }
+
+    let board = board::info();
+    if let (Some(model), Some(x)) = (board.model, board.soft_temp_limit_celsius()) {
+        info!("      {} lowers its clock above {} °C", model, x);
+    }
+}
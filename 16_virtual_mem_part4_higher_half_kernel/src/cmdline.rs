@@ -0,0 +1,208 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Kernel command line.
+//!
+//! Taken from the `bootargs` property of the device tree's `/chosen` node, which the Raspberry's
+//! firmware fills from `cmdline.txt`. The command line is split into whitespace separated words
+//! once during kernel init. A word is either a `key=value` pair or a flag without a value.
+//!
+//! Keys that the kernel does not consume are kept, so that they can be inspected with the monitor's
+//! `cmdline` command.
+
+use crate::{
+    dtb, info,
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Maximum number of words that are stored.
+const MAX_ENTRIES: usize = 32;
+
+/// Keys that are consumed somewhere in the kernel.
+const KNOWN_KEYS: [&str; 5] = ["clocksource", "console", "coredump", "loglevel", "nosmp"];
+
+struct CommandLine {
+    entries: [Option<Entry>; MAX_ENTRIES],
+    num_dropped: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A word of the command line.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    /// The part in front of the first `=`, or the whole word.
+    pub key: &'static str,
+
+    /// The part after the first `=`. `None` for flags.
+    pub value: Option<&'static str>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static COMMAND_LINE: InitStateLock<CommandLine> = InitStateLock::new(CommandLine::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl CommandLine {
+    const fn new() -> Self {
+        Self {
+            entries: [None; MAX_ENTRIES],
+            num_dropped: 0,
+        }
+    }
+
+    fn parse(s: &'static str) -> Self {
+        let mut cmdline = Self::new();
+
+        for word in s.split_whitespace() {
+            let entry = match word.find('=') {
+                None => Entry {
+                    key: word,
+                    value: None,
+                },
+                Some(i) => Entry {
+                    key: &word[..i],
+                    value: Some(&word[i + 1..]),
+                },
+            };
+
+            match cmdline.entries.iter_mut().find(|x| x.is_none()) {
+                None => cmdline.num_dropped += 1,
+                Some(x) => *x = Some(entry),
+            }
+        }
+
+        cmdline
+    }
+
+    fn entries(&self) -> impl DoubleEndedIterator<Item = &Entry> {
+        self.entries.iter().flatten()
+    }
+
+    fn get(&self, key: &str) -> Option<&'static str> {
+        self.entries()
+            .rev()
+            .filter(|x| x.key == key)
+            .find_map(|x| x.value)
+    }
+
+    fn flag(&self, key: &str) -> bool {
+        self.entries().any(|x| x.key == key && x.value.is_none())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Parse the command line from the device tree, if there is one.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init, after `dtb::init()`.
+pub unsafe fn init() -> Result<(), &'static str> {
+    let bootargs = match dtb::device_tree()
+        .and_then(|x| x.find_node("/chosen"))
+        .and_then(|x| x.property_str("bootargs"))
+    {
+        None => return Ok(()),
+        Some(x) => x,
+    };
+
+    let cmdline = CommandLine::parse(bootargs);
+    let num_dropped = cmdline.num_dropped;
+    COMMAND_LINE.write(|x| *x = cmdline);
+
+    if num_dropped > 0 {
+        return Err("Command line has too many words. Ignored the excess");
+    }
+
+    Ok(())
+}
+
+/// Return the value of `key`. If the key is given multiple times, the last one wins.
+pub fn get(key: &str) -> Option<&'static str> {
+    COMMAND_LINE.read(|x| x.get(key))
+}
+
+/// Return true if `key` is given as a flag, that is, without a value.
+pub fn flag(key: &str) -> bool {
+    COMMAND_LINE.read(|x| x.flag(key))
+}
+
+/// Return true if the kernel consumes `key`.
+pub fn is_known(key: &str) -> bool {
+    KNOWN_KEYS.contains(&key)
+}
+
+/// Human-readable print of the command line. Unknown keys are marked.
+pub fn print() {
+    COMMAND_LINE.read(|cmdline| {
+        for entry in cmdline.entries() {
+            let marker = if is_known(entry.key) {
+                ""
+            } else {
+                " (unknown)"
+            };
+
+            match entry.value {
+                None => info!("      {}{}", entry.key, marker),
+                Some(value) => info!("      {}={}{}", entry.key, value, marker),
+            }
+        }
+
+        if cmdline.num_dropped > 0 {
+            info!("      ... and {} more words", cmdline.num_dropped);
+        }
+    })
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check splitting into keys, values and flags.
+    #[kernel_test]
+    fn parse_works() {
+        let cmdline =
+            CommandLine::parse("console=ttyAMA0,115200  nosmp foo=bar=baz loglevel=7 loglevel=8");
+
+        assert_eq!(cmdline.get("console"), Some("ttyAMA0,115200"));
+        assert_eq!(cmdline.get("foo"), Some("bar=baz"));
+        assert_eq!(cmdline.get("loglevel"), Some("8"));
+        assert_eq!(cmdline.get("nosmp"), None);
+        assert!(cmdline.flag("nosmp"));
+        assert!(!cmdline.flag("console"));
+        assert_eq!(cmdline.num_dropped, 0);
+    }
+
+    /// Words that don't fit are counted.
+    #[kernel_test]
+    fn excess_words_are_dropped() {
+        let cmdline = CommandLine::parse(
+            "a b c d e f g h i j k l m n o p q r s t u v w x y z aa bb cc dd ee ff gg hh",
+        );
+
+        assert_eq!(cmdline.entries().count(), MAX_ENTRIES);
+        assert_eq!(cmdline.num_dropped, 2);
+        assert!(cmdline.flag("ff"));
+        assert!(!cmdline.flag("gg"));
+    }
+}
@@ -19,3 +19,50 @@ pub const fn align_down(value: usize, alignment: usize) -> usize {
 
     value & !(alignment - 1)
 }
+
+/// Align up.
+#[inline(always)]
+pub const fn align_up(value: usize, alignment: usize) -> usize {
+    assert!(alignment.is_power_of_two());
+
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+/// Update a CRC-32 checksum with `data`.
+///
+/// Uses the IEEE 802.3 polynomial, like zlib. Start with a `crc` of zero.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+
+    for &byte in data {
+        crc ^= byte as u32;
+
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(all(test, target_os = "none"))]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check the CRC-32 against the standard check value, also when fed in pieces.
+    #[kernel_test]
+    fn crc32_check_value() {
+        assert_eq!(crc32_update(0, b"123456789"), 0xCBF4_3926);
+        assert_eq!(
+            crc32_update(crc32_update(0, b"1234"), b"56789"),
+            0xCBF4_3926
+        );
+        assert_eq!(crc32_update(0, &[]), 0);
+    }
+}
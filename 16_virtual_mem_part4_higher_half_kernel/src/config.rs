@@ -0,0 +1,197 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Kernel configuration.
+//!
+//! The kernel's tunables, grouped per subsystem. This is the one place to edit for retuning the
+//! kernel; the subsystems read their values from here. Facts about the board are taken from the
+//! BSP. Values that can be changed at boot say so, together with the command line argument.
+
+use crate::{info, units::ByteSize};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Console configuration.
+pub mod console {
+    use crate::units::Baud;
+
+    /// The UART console's baud rate.
+    pub const BAUD_RATE: Baud = Baud(921_600);
+
+    /// Whether a break on the UART console asks the monitor to take over, see
+    /// `monitor::request_entry()`. Switch it off where a stray break, for example from unplugging
+    /// the serial adapter, must not stop the kernel.
+    pub const BREAK_ENTERS_MONITOR: bool = true;
+
+    /// Whether the UART's IRQ handler keeps received characters together with their arrival time,
+    /// see `console::interface::TimestampedRead`. Off by default, since the UART's FIFOs are
+    /// switched off for precise times, and the timestamps more than double the memory of the ring.
+    pub const RX_TIMESTAMPS: bool = false;
+
+    /// The number of received characters that the UART's IRQ handler keeps if `RX_TIMESTAMPS` is
+    /// on. Further ones are dropped until the ring is read.
+    pub const RX_RING_DEPTH: usize = 64;
+
+    /// Whether the console mux panics if its output lock is held for too long. On in test builds,
+    /// so that tests catch a stuck sink. Otherwise, the output goes ahead without the lock, so that
+    /// a stuck sink does not stall the other cores indefinitely.
+    pub const PANIC_ON_OUTPUT_LOCK_TIMEOUT: bool = cfg!(feature = "test_build");
+}
+
+/// Processor configuration.
+pub mod cpu {
+    use crate::bsp;
+
+    /// The number of processor cores. Taken from the BSP.
+    pub const NUM_CORES: usize = bsp::cpu::NUM_CORES;
+
+    /// Whether EL0 may read the performance monitors' counters directly. Off, so that user tasks
+    /// cannot use them as a timing side channel.
+    pub const PMU_EL0_ACCESS: bool = false;
+}
+
+/// Exception handling configuration.
+pub mod exception {
+    use crate::{bsp, exception::asynchronous::IRQTargetPolicy};
+
+    /// The size of the stack that each core switches to when taking an exception. Must be a
+    /// multiple of 64 KiB. Since the linker script needs it too, it is set in the BSP's
+    /// `exception_stack_size.ld`.
+    pub const STACK_SIZE: usize = bsp::exception::STACK_SIZE;
+
+    /// The number of times in a row that an IRQ handler may fail before its IRQ is masked.
+    pub const IRQ_HANDLER_MAX_CONSECUTIVE_ERRORS: usize = 8;
+
+    /// Which cores the drivers' shared IRQs are routed to once the secondary cores run. With
+    /// anything but the boot core, the secondary cores take IRQs, so the handlers must be safe to
+    /// run on any core. Needs an interrupt controller that can route IRQs, which is the GICv2.
+    pub const IRQ_TARGET_POLICY: IRQTargetPolicy = IRQTargetPolicy::BootCore;
+}
+
+/// Framebuffer configuration.
+pub mod framebuffer {
+    /// The display's width in pixels. Must be a multiple of the console font's glyph width, 8.
+    pub const WIDTH: u32 = 640;
+
+    /// The display's height in pixels. Must be a multiple of the console font's glyph height, 8.
+    pub const HEIGHT: u32 = 480;
+
+    /// Whether the framebuffer console draws off-screen and flips once per line, so that scrolling
+    /// does not tear. Doubles the memory that the firmware takes for the framebuffer, so
+    /// low-memory setups may want to turn it off.
+    pub const DOUBLE_BUFFERED: bool = true;
+}
+
+/// Logging configuration.
+pub mod log {
+    use crate::log::Level;
+
+    /// The global log level at boot. Can be changed with `loglevel=`.
+    pub const DEFAULT_LEVEL: Level = Level::Info;
+}
+
+// Memory management configuration. In its own file, since the translation table tool and the host
+// tests include it.
+pub mod memory;
+
+/// Timekeeping configuration.
+pub mod time {
+    use crate::units::Hertz;
+
+    /// The tick rate of the BCM system timer, at which its counter and compare channels run. The
+    /// firmware sets the timer up with 1 MHz.
+    pub const SYSTEM_TIMER_TICK_RATE: Hertz = Hertz::from_mhz(1);
+
+    /// The generic timer's event stream fires on every transition of this bit of the counter.
+    ///
+    /// Bounds the time that a core sleeps in `wait_for_event()` without being woken. Bit 9 means
+    /// roughly every 16 µs at the usual counter frequencies of 54 MHz to 62.5 MHz. Must be 15 at
+    /// most.
+    pub const EVENT_STREAM_COUNTER_BIT: u64 = 9;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Print the configuration. Values that can be changed at boot are printed as they are in effect.
+pub fn print() {
+    info!("Configuration:");
+    info!("      Console:                {}", console::BAUD_RATE);
+    if console::RX_TIMESTAMPS {
+        info!(
+            "      RX timestamps:          {} characters",
+            console::RX_RING_DEPTH
+        );
+    }
+    if console::BREAK_ENTERS_MONITOR {
+        info!("      Break:                  Enters the monitor");
+    }
+    if console::PANIC_ON_OUTPUT_LOCK_TIMEOUT {
+        info!("      Output lock:            Panics on timeout");
+    }
+    info!("      Cores:                  {}", cpu::NUM_CORES);
+    info!(
+        "      PMU access from EL0:    {}",
+        if cpu::PMU_EL0_ACCESS { "Yes" } else { "No" }
+    );
+    info!(
+        "      Exception stack:        {} per core",
+        ByteSize(exception::STACK_SIZE)
+    );
+    info!(
+        "      IRQ handler errors:     {} in a row before masking",
+        exception::IRQ_HANDLER_MAX_CONSECUTIVE_ERRORS
+    );
+    info!(
+        "      IRQ targets:            {}",
+        exception::IRQ_TARGET_POLICY
+    );
+    info!(
+        "      Framebuffer:            {}x{}, {}",
+        framebuffer::WIDTH,
+        framebuffer::HEIGHT,
+        if framebuffer::DOUBLE_BUFFERED {
+            "double-buffered"
+        } else {
+            "single-buffered"
+        }
+    );
+    info!(
+        "      Log level:              {} (default {})",
+        crate::log::global_level(),
+        log::DEFAULT_LEVEL
+    );
+    info!(
+        "      Heap:                   {}",
+        ByteSize(memory::HEAP_SIZE)
+    );
+    info!(
+        "      Mapping record:         {} entries, {} users each",
+        memory::MAPPING_RECORD_ENTRIES,
+        memory::MAPPING_RECORD_USERS_PER_ENTRY
+    );
+    info!(
+        "      MMIO region:            {}",
+        ByteSize(memory::MMIO_REGION_SIZE)
+    );
+    info!(
+        "      Kernel tables:          {}",
+        if memory::READ_ONLY_KERNEL_TABLES {
+            "Read-only"
+        } else {
+            "Writable"
+        }
+    );
+    info!(
+        "      System timer:           {}",
+        time::SYSTEM_TIMER_TICK_RATE
+    );
+    info!(
+        "      Event stream:           counter bit {}",
+        time::EVENT_STREAM_COUNTER_BIT
+    );
+}
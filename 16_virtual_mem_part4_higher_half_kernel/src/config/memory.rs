@@ -0,0 +1,22 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Memory management configuration.
+
+/// The size of the kernel heap, see `memory::heap`.
+pub const HEAP_SIZE: usize = 256 * 1024;
+
+/// The number of mappings that the kernel's mapping record can hold.
+pub const MAPPING_RECORD_ENTRIES: usize = 24;
+
+/// The number of users that can share one MMIO mapping.
+pub const MAPPING_RECORD_USERS_PER_ENTRY: usize = 5;
+
+/// The size of the virtual address range at the end of the kernel's address space that MMIO
+/// mappings are taken from.
+pub const MMIO_REGION_SIZE: usize = 256 * 1024 * 1024;
+
+/// Map the kernel's translation tables read-only during kernel init. Changes to the tables map
+/// the table page that they write to writable for the duration of the change.
+pub const READ_ONLY_KERNEL_TABLES: bool = true;
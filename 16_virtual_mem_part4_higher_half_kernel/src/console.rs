@@ -4,13 +4,22 @@
 
 //! System console.
 
+use crate::{bsp, driver, exception};
+
+pub mod buffer;
+pub mod font;
+mod hexdump;
+pub mod mux;
+
+pub use hexdump::{_hexdump_slice, hexdump};
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
 /// Console interfaces.
 pub mod interface {
-    use core::fmt;
+    use core::{fmt, time::Duration};
 
     /// Console write functions.
     pub trait Write {
@@ -20,19 +29,127 @@ pub mod interface {
         /// Write a Rust format string.
         fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result;
 
+        /// Write raw bytes, without any conversion.
+        ///
+        /// The default implementation writes one character per byte. Drivers should override it
+        /// with a more efficient burst transfer.
+        fn write_array(&self, data: &[u8]) {
+            for &x in data {
+                self.write_char(x as char);
+            }
+        }
+
         /// Block until the last buffered character has been physically put on the TX wire.
         fn flush(&self);
+
+        /// Return true if the console cannot take more output right now.
+        ///
+        /// The console multiplexer skips full consoles instead of waiting for them.
+        fn is_full(&self) -> bool {
+            false
+        }
     }
 
     /// Console read functions.
-    pub trait Read {
-        /// Read a single character.
+    ///
+    /// Reading builds on [`Write`] so that input can be echoed back to the same console.
+    pub trait Read: Write {
+        /// Read a single character if one is available, without waiting.
+        fn try_read_char(&self) -> Option<char> {
+            None
+        }
+
+        /// Read a single character, waiting in a low-power state until one arrives.
+        ///
+        /// Builds on `try_read_char()`, so a driver needs to implement the non-blocking variant
+        /// only.
         fn read_char(&self) -> char {
-            ' '
+            let mut c = None;
+            crate::cpu::spin_until(
+                || {
+                    c = self.try_read_char();
+                    c.is_some()
+                },
+                None,
+            );
+
+            c.unwrap()
         }
 
+        /// Read raw bytes, without any conversion, into `buf`.
+        ///
+        /// Returns when `buf` is full or `timeout` has elapsed, whichever comes first. Returns the
+        /// number of bytes read.
+        fn read_array(&self, buf: &mut [u8], timeout: Duration) -> usize;
+
         /// Clear RX buffers, if any.
         fn clear_rx(&self);
+
+        /// Enable or disable echoing of input read through `read_line()`.
+        fn set_echo(&self, _enabled: bool) {}
+
+        /// Return true if `read_line()` echoes input.
+        fn is_echo_enabled(&self) -> bool {
+            true
+        }
+
+        /// Read a line of input into `buf` and return the number of bytes stored.
+        ///
+        /// - Printable ASCII characters are stored and, if enabled, echoed.
+        /// - Backspace and delete erase the last stored character.
+        /// - CR and LF terminate the line and are stored as a single `\n`.
+        ///
+        /// The last byte of `buf` is reserved for the line terminator, so excess input is
+        /// discarded until the line ends.
+        fn read_line(&self, buf: &mut [u8]) -> usize {
+            if buf.is_empty() {
+                return 0;
+            }
+
+            let echo = self.is_echo_enabled();
+            let max_chars = buf.len() - 1;
+            let mut len = 0;
+
+            loop {
+                match self.read_char() {
+                    '\r' | '\n' => {
+                        if echo {
+                            self.write_char('\n');
+                        }
+
+                        buf[len] = b'\n';
+                        return len + 1;
+                    }
+                    '\x08' | '\x7f' => {
+                        if len > 0 {
+                            len -= 1;
+
+                            if echo {
+                                let _ = self.write_fmt(format_args!("\x08 \x08"));
+                            }
+                        }
+                    }
+                    c if (c.is_ascii_graphic() || c == ' ') && len < max_chars => {
+                        buf[len] = c as u8;
+                        len += 1;
+
+                        if echo {
+                            self.write_char(c);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    /// Console read functions that tell when a character arrived.
+    pub trait TimestampedRead {
+        /// Retrieve a received byte without any conversion, together with the uptime at which it
+        /// arrived. Returns `None` if no byte is waiting or arrival times are not recorded.
+        fn read_char_timestamped(&self) -> Option<(u8, Duration)> {
+            None
+        }
     }
 
     /// Console statistics.
@@ -46,8 +163,108 @@ pub mod interface {
         fn chars_read(&self) -> usize {
             0
         }
+
+        /// Return the most characters that were queued for sending at once.
+        fn tx_queue_high_water(&self) -> usize {
+            0
+        }
+
+        /// Return the most characters that were waiting to be received at once.
+        fn rx_queue_high_water(&self) -> usize {
+            0
+        }
+
+        /// Return the number of times that received characters were lost.
+        fn rx_dropped(&self) -> usize {
+            0
+        }
+
+        /// Return the number of writes that went around the driver, for example, from the panic
+        /// path.
+        fn tx_blocking_fallbacks(&self) -> usize {
+            0
+        }
+    }
+
+    /// A full-fledged console.
+    pub trait All: Write + Read + Statistics {}
+
+    impl<T: Write + Read + Statistics> All for T {}
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Enable or disable the IRQs of the device driver that `console` is, if it is one.
+fn set_driver_irqs_enabled(console: mux::ConsoleSource, enabled: bool) {
+    use exception::asynchronous::interface::IRQManager;
+
+    let irq_manager = bsp::exception::asynchronous::irq_manager();
+    let console_addr = console as *const _ as *const ();
+
+    driver::driver_manager().for_each_driver(|x| {
+        if x as *const _ as *const () != console_addr {
+            return;
+        }
+
+        for irq_number in x.irq_numbers() {
+            if enabled {
+                irq_manager.enable(*irq_number);
+            } else {
+                irq_manager.disable(*irq_number);
+            }
+        }
+    });
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Make the console called `name` the primary one, which input is read from.
+///
+/// Messages that are printed during the switch are held in the early boot buffer, and replayed once
+/// the new console is active. The old console is flushed, stops echoing input, and its driver's
+/// IRQs are disabled in the interrupt controller, while the new console's are enabled. The IRQ
+/// handlers stay registered, because the handler tables are frozen after kernel init.
+///
+/// The old driver's MMIO mapping is not released with `memory::mmu::kernel_unmap_mmio()`, because
+/// the old console stays an output sink of the multiplexer, and the panic path writes to the UART's
+/// registers directly.
+pub fn switch_primary(name: &str) -> Result<(), &'static str> {
+    use interface::{Read, Write};
+
+    let mux = mux::console_mux();
+    if mux.source_name() == Some(name) {
+        return Ok(());
     }
 
-    /// Trait alias for a full-fledged console.
-    pub trait All = Write + Read + Statistics;
+    buffer::hold();
+
+    let result = mux.switch_source(name).map(|(old, new)| {
+        set_driver_irqs_enabled(old, false);
+        set_driver_irqs_enabled(new, true);
+        old.flush();
+        new.set_echo(old.is_echo_enabled());
+        old.set_echo(false);
+        new.clear_rx();
+    });
+
+    // Replay in any case, so that printing does not stay in the buffer.
+    buffer::replay_into(mux);
+    mux.flush();
+
+    result
+}
+
+/// Write `s` synchronously, bypassing the console multiplexer and all locks.
+///
+/// For the earliest exception handlers and for failures during early init, when the console might
+/// not be registered yet or its lock might be held. A UART that no driver has set up yet is brought
+/// up with default settings. The driver re-applies its own settings on its next write.
+pub fn emergency_write(s: &str) {
+    exception::asynchronous::exec_with_irq_masked(|| unsafe {
+        bsp::console::panic_emergency_out(s)
+    });
 }
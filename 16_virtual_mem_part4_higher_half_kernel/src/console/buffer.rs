@@ -0,0 +1,159 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Early boot console buffer.
+//!
+//! Until the console driver is up, everything that is printed is stored here. The buffer is
+//! replayed once into the console as soon as it becomes available. From then on, printing goes
+//! straight to the console.
+//!
+//! The buffer is used the same way while the console is switched at runtime.
+//!
+//! If the buffer runs full, the oldest messages are dropped first. Their number is reported when
+//! the buffer is replayed.
+
+use crate::{
+    console,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const BUFFER_SIZE: usize = 4096;
+
+struct EarlyBuffer {
+    data: [u8; BUFFER_SIZE],
+    len: usize,
+    num_dropped: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static EARLY_BUFFER: IRQSafeNullLock<EarlyBuffer> = IRQSafeNullLock::new(EarlyBuffer::new());
+
+/// Set once the buffer has been replayed. Printing bypasses the buffer from then on.
+static REPLAYED: AtomicBool = AtomicBool::new(false);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl EarlyBuffer {
+    const fn new() -> Self {
+        Self {
+            data: [0; BUFFER_SIZE],
+            len: 0,
+            num_dropped: 0,
+        }
+    }
+
+    /// Remove the oldest message, which is everything up to and including the first newline.
+    fn drop_oldest_message(&mut self) {
+        let end = self.data[..self.len]
+            .iter()
+            .position(|&x| x == b'\n')
+            .map_or(self.len, |x| x + 1);
+
+        self.data.copy_within(end..self.len, 0);
+        self.len -= end;
+        self.num_dropped += 1;
+    }
+
+    /// The buffered text. Only whole characters are ever stored, so it is always valid UTF-8.
+    fn as_str(&self) -> &str {
+        unsafe { core::str::from_utf8_unchecked(&self.data[..self.len]) }
+    }
+}
+
+impl fmt::Write for EarlyBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Make room by dropping old messages. A single string that is larger than the whole buffer
+        // is cut at the last character that still fits.
+        let mut num_bytes = s.len();
+        while num_bytes > BUFFER_SIZE - self.len {
+            if self.len == 0 {
+                num_bytes = BUFFER_SIZE;
+                while !s.is_char_boundary(num_bytes) {
+                    num_bytes -= 1;
+                }
+                break;
+            }
+
+            self.drop_oldest_message();
+        }
+
+        self.data[self.len..(self.len + num_bytes)].copy_from_slice(&s.as_bytes()[..num_bytes]);
+        self.len += num_bytes;
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Store `args` if the buffer has not been replayed yet.
+///
+/// Returns false if printing should go to the console instead.
+pub fn capture(args: fmt::Arguments) -> bool {
+    if REPLAYED.load(Ordering::Acquire) {
+        return false;
+    }
+
+    EARLY_BUFFER.lock(|buffer| {
+        use fmt::Write;
+
+        // A replay may have drained the buffer since the check above.
+        if REPLAYED.load(Ordering::Acquire) {
+            return false;
+        }
+
+        buffer.write_fmt(args).is_ok()
+    })
+}
+
+/// Store printing in the buffer again, until the next replay.
+///
+/// Used while the console is switched, so that no message is lost on the way.
+pub fn hold() {
+    REPLAYED.store(false, Ordering::Release);
+}
+
+/// Hand the buffered messages to `out`, once, and let all later printing bypass the buffer.
+///
+/// Does nothing if the buffer has been replayed already.
+pub fn replay_with(mut out: impl FnMut(fmt::Arguments)) {
+    EARLY_BUFFER.lock(|buffer| {
+        if REPLAYED.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        if buffer.num_dropped > 0 {
+            out(format_args!(
+                "[{} early messages dropped]\n",
+                buffer.num_dropped
+            ));
+        }
+        out(format_args!("{}", buffer.as_str()));
+
+        buffer.len = 0;
+    })
+}
+
+/// Replay the buffered messages into `console`, which must be ready for printing.
+pub fn replay_into(console: &dyn console::interface::Write) {
+    replay_with(|args| {
+        // Nothing that could be done about an error here.
+        let _ = console.write_fmt(args);
+    })
+}
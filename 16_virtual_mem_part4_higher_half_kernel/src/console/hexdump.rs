@@ -0,0 +1,199 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Hex dumps.
+//!
+//! The output uses the canonical layout of 16 bytes per line, with offset, hex and ASCII columns.
+//! Runs of identical lines are collapsed into a single `*`.
+//!
+//! ```text
+//! 00000000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 00  |Hello, world!...|
+//! 00000010  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|
+//! *
+//! 00000040
+//! ```
+
+use crate::{
+    memory::{self, mmu, Address, Virtual},
+    println,
+};
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const BYTES_PER_LINE: usize = 16;
+
+/// A single line of a hex dump.
+struct Line<'a> {
+    offset: usize,
+    offset_width: usize,
+    bytes: &'a [u8],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:0width$x}  ", self.offset, width = self.offset_width)?;
+
+        for i in 0..BYTES_PER_LINE {
+            if i == BYTES_PER_LINE / 2 {
+                write!(f, " ")?;
+            }
+
+            match self.bytes.get(i) {
+                Some(x) => write!(f, "{:02x} ", x)?,
+                None => write!(f, "   ")?,
+            }
+        }
+
+        write!(f, " |")?;
+        for &x in self.bytes {
+            let c = if x.is_ascii_graphic() || x == b' ' {
+                x as char
+            } else {
+                '.'
+            };
+            write!(f, "{}", c)?;
+        }
+        write!(f, "|")
+    }
+}
+
+/// Print `len` bytes, labeling the first byte with `base`.
+///
+/// The bytes are fetched a line at a time by `read`, which is given the offset of the line.
+fn dump(
+    len: usize,
+    base: usize,
+    offset_width: usize,
+    mut read: impl FnMut(usize, &mut [u8]) -> Result<(), &'static str>,
+) -> Result<(), &'static str> {
+    let mut line = [0; BYTES_PER_LINE];
+    let mut previous: Option<[u8; BYTES_PER_LINE]> = None;
+    let mut collapsing = false;
+
+    for offset in (0..len).step_by(BYTES_PER_LINE) {
+        let bytes = &mut line[..(len - offset).min(BYTES_PER_LINE)];
+        read(offset, bytes)?;
+
+        // The last line is always printed, so that the end of the data is visible. All others are
+        // full lines.
+        let is_last = offset + bytes.len() == len;
+        if previous.map_or(false, |x| x[..] == *bytes) && !is_last {
+            if !collapsing {
+                println!("*");
+                collapsing = true;
+            }
+            continue;
+        }
+
+        println!(
+            "{}",
+            Line {
+                offset: base + offset,
+                offset_width,
+                bytes
+            }
+        );
+        previous = Some(line);
+        collapsing = false;
+    }
+
+    println!("{:0width$x}", base + len, width = offset_width);
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Print a hex dump of `len` bytes of memory starting at `addr`.
+///
+/// The range is checked to be mapped before it is read, so that a bad address results in an error
+/// instead of a data abort. Reads use accesses that suit the memory's attributes, so that MMIO can
+/// be dumped, too.
+pub fn hexdump(addr: Address<Virtual>, len: usize) -> Result<(), &'static str> {
+    if !mmu::is_virt_range_readable(addr, len) {
+        return Err("Address range is not mapped");
+    }
+
+    dump(len, addr.into_usize(), 16, |offset, bytes| unsafe {
+        memory::ops::copy_from_device(bytes, addr + offset)
+    })
+}
+
+/// Print a hex dump of `data`, with offsets counting from zero.
+///
+/// Prefer the `hexdump!` macro.
+#[doc(hidden)]
+pub fn _hexdump_slice(data: &[u8]) {
+    let _ = dump(data.len(), 0, 8, |offset, bytes| {
+        bytes.copy_from_slice(&data[offset..(offset + bytes.len())]);
+
+        Ok(())
+    });
+}
+
+/// Prints a hex dump of anything that can be viewed as `&[u8]`.
+#[macro_export]
+macro_rules! hexdump {
+    ($data:expr) => {
+        $crate::console::_hexdump_slice(::core::convert::AsRef::<[u8]>::as_ref(&$data))
+    };
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Writes formatted output into a fixed buffer.
+    struct Buffer {
+        data: [u8; 128],
+        len: usize,
+    }
+
+    impl fmt::Write for Buffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            self.data
+                .get_mut(self.len..end)
+                .ok_or(fmt::Error)?
+                .copy_from_slice(s.as_bytes());
+            self.len = end;
+
+            Ok(())
+        }
+    }
+
+    /// Check the layout of a partial line.
+    #[kernel_test]
+    fn line_layout_works() {
+        use fmt::Write;
+
+        let mut buffer = Buffer {
+            data: [0; 128],
+            len: 0,
+        };
+        let line = Line {
+            offset: 0x10,
+            offset_width: 8,
+            bytes: b"Hi!\n",
+        };
+        write!(buffer, "{}", line).unwrap();
+
+        let expected = "00000010  48 69 21 0a                                       |Hi!.|";
+        assert_eq!(&buffer.data[..buffer.len], expected.as_bytes());
+    }
+}
@@ -0,0 +1,472 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Console multiplexer.
+//!
+//! Output is fanned out to all registered sinks, for example, a UART, a framebuffer console and an
+//! in-memory log. Input is taken from a single designated source.
+//!
+//! The input console can be switched at runtime among the consoles that were registered as sources
+//! during kernel init.
+//!
+//! The panic handler does not go through the multiplexer. It talks to the lowest-latency sink, the
+//! raw UART, directly.
+//!
+//! Each write takes the output lock, which is shared between the cores. A line that is printed with
+//! a single `write_fmt()` therefore comes out in one piece, even if other cores print at the same
+//! time. Output that spans multiple lines should be rendered with `print::Block` first.
+
+use super::interface;
+use crate::{
+    config, cpu, exception,
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+    time,
+    time::interface::TimeManager,
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Maximum number of sinks.
+const MAX_SINKS: usize = 4;
+
+/// How long a core waits for the output lock. Far longer than the sinks take for a screenful of
+/// output, so a core that holds the lock longer is stuck in a sink.
+const MAX_HOLD_TIME: Duration = Duration::from_millis(250);
+
+/// Value of `ConsoleMux::output_owner` while no core holds the output lock.
+const UNLOCKED: usize = usize::MAX;
+
+struct ConsoleMuxInner {
+    sinks: [Option<ConsoleSink>; MAX_SINKS],
+    num_sinks: usize,
+    sources: [Option<(&'static str, ConsoleSource)>; MAX_SINKS],
+    num_sources: usize,
+}
+
+/// Per-sink counters. Kept outside of the `InitStateLock`, because they change at runtime.
+struct SinkCounters {
+    num_errors: AtomicUsize,
+    num_overflows: AtomicUsize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// An output console.
+pub type ConsoleSink = &'static (dyn interface::Write + Sync);
+
+/// An input console.
+pub type ConsoleSource = &'static (dyn interface::All + Sync);
+
+/// Error and overflow counts of a sink.
+#[derive(Copy, Clone, Debug)]
+pub struct SinkStatistics {
+    /// Number of writes that returned an error.
+    pub num_errors: usize,
+
+    /// Number of writes that were dropped because the sink was full.
+    pub num_overflows: usize,
+}
+
+/// Fans out console output to multiple sinks.
+pub struct ConsoleMux {
+    inner: InitStateLock<ConsoleMuxInner>,
+    counters: [SinkCounters; MAX_SINKS],
+    active_source: AtomicUsize,
+
+    /// The id of the core that holds the output lock, or `UNLOCKED`.
+    output_owner: AtomicUsize,
+    num_lock_timeouts: AtomicUsize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static CONSOLE_MUX: ConsoleMux = ConsoleMux::new();
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl ConsoleMuxInner {
+    const fn new() -> Self {
+        Self {
+            sinks: [None; MAX_SINKS],
+            num_sinks: 0,
+            sources: [None; MAX_SINKS],
+            num_sources: 0,
+        }
+    }
+}
+
+impl SinkCounters {
+    // Only used as an array initializer.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW: Self = Self {
+        num_errors: AtomicUsize::new(0),
+        num_overflows: AtomicUsize::new(0),
+    };
+}
+
+impl ConsoleMux {
+    const fn new() -> Self {
+        Self {
+            inner: InitStateLock::new(ConsoleMuxInner::new()),
+            counters: [SinkCounters::NEW; MAX_SINKS],
+            active_source: AtomicUsize::new(0),
+            output_owner: AtomicUsize::new(UNLOCKED),
+            num_lock_timeouts: AtomicUsize::new(0),
+        }
+    }
+
+    /// Spin until the output lock is free, and take it. Returns false if it was not freed within
+    /// `MAX_HOLD_TIME`, or panics then, depending on
+    /// `config::console::PANIC_ON_OUTPUT_LOCK_TIMEOUT`.
+    fn acquire_output_lock(&self, core_id: usize) -> bool {
+        let start = time::time_manager().uptime();
+
+        loop {
+            if self
+                .output_owner
+                .compare_exchange_weak(UNLOCKED, core_id, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return true;
+            }
+
+            if time::time_manager().uptime() - start > MAX_HOLD_TIME {
+                self.num_lock_timeouts.fetch_add(1, Ordering::Relaxed);
+
+                if config::console::PANIC_ON_OUTPUT_LOCK_TIMEOUT {
+                    panic!(
+                        "Console output lock held by core {} for longer than {:?}",
+                        self.output_owner.load(Ordering::Relaxed),
+                        MAX_HOLD_TIME
+                    );
+                }
+
+                return false;
+            }
+
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Run `f` with the output lock held.
+    ///
+    /// IRQs are masked meanwhile, so that an IRQ handler that prints can not wait for the lock that
+    /// its own core holds. Output that the holding core produces in between, for example from the
+    /// handler of an exception that a sink raised, goes out right away.
+    fn with_output_lock<R>(&self, f: impl FnOnce() -> R) -> R {
+        let core_id = cpu::core_id();
+        if self.output_owner.load(Ordering::Relaxed) == core_id {
+            return f();
+        }
+
+        exception::asynchronous::exec_with_irq_masked(|| {
+            let is_locked = self.acquire_output_lock(core_id);
+            let result = f();
+
+            if is_locked {
+                self.output_owner.store(UNLOCKED, Ordering::Release);
+            }
+
+            result
+        })
+    }
+
+    /// The console that input is currently read from.
+    fn source(&self) -> Option<ConsoleSource> {
+        let active = self.active_source.load(Ordering::Acquire);
+
+        self.inner
+            .read(|inner| inner.sources[active].map(|(_, source)| source))
+    }
+
+    /// Call `f` for each sink that is not full, and count overflows and errors.
+    ///
+    /// Fails only if `f` failed for every sink that it was called for. A single broken sink is
+    /// counted in its statistics, but does not fail the output that reached the others.
+    fn for_each_sink(&self, mut f: impl FnMut(ConsoleSink) -> fmt::Result) -> fmt::Result {
+        self.inner.read(|inner| {
+            let mut num_written = 0;
+            let mut num_failed = 0;
+
+            for (sink, counters) in inner.sinks.iter().flatten().zip(self.counters.iter()) {
+                if sink.is_full() {
+                    counters.num_overflows.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+
+                num_written += 1;
+                if f(*sink).is_err() {
+                    counters.num_errors.fetch_add(1, Ordering::Relaxed);
+                    num_failed += 1;
+                }
+            }
+
+            if (num_written != 0) && (num_failed == num_written) {
+                return Err(fmt::Error);
+            }
+
+            Ok(())
+        })
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Return a reference to the global console multiplexer.
+pub fn console_mux() -> &'static ConsoleMux {
+    &CONSOLE_MUX
+}
+
+impl ConsoleMux {
+    /// Add a sink. All output is written to all sinks, in the order of their registration.
+    ///
+    /// Registration is only possible during kernel init.
+    pub fn register_sink(&self, sink: ConsoleSink) -> Result<(), &'static str> {
+        self.inner.write(|inner| {
+            let slot = inner
+                .sinks
+                .get_mut(inner.num_sinks)
+                .ok_or("Console sinks exhausted")?;
+
+            *slot = Some(sink);
+            inner.num_sinks += 1;
+
+            Ok(())
+        })
+    }
+
+    /// Add a console that input can be read from. The first one becomes the active source. The
+    /// statistics of the active source are reported as the multiplexer's statistics.
+    ///
+    /// Registration is only possible during kernel init.
+    pub fn register_source(
+        &self,
+        name: &'static str,
+        source: ConsoleSource,
+    ) -> Result<(), &'static str> {
+        self.inner.write(|inner| {
+            let slot = inner
+                .sources
+                .get_mut(inner.num_sources)
+                .ok_or("Console sources exhausted")?;
+
+            *slot = Some((name, source));
+            inner.num_sources += 1;
+
+            Ok(())
+        })
+    }
+
+    /// Return the name of the active source.
+    pub fn source_name(&self) -> Option<&'static str> {
+        let active = self.active_source.load(Ordering::Acquire);
+
+        self.inner
+            .read(|inner| inner.sources[active].map(|(name, _)| name))
+    }
+
+    /// Read input from the source called `name` from now on.
+    ///
+    /// Returns the previous and the new source.
+    pub fn switch_source(
+        &self,
+        name: &str,
+    ) -> Result<(ConsoleSource, ConsoleSource), &'static str> {
+        let old = self.source().ok_or("No console source registered")?;
+
+        let (index, new) = self.inner.read(|inner| {
+            inner
+                .sources
+                .iter()
+                .enumerate()
+                .find_map(|(i, x)| match x {
+                    Some((x, source)) if *x == name => Some((i, *source)),
+                    _ => None,
+                })
+                .ok_or("No console source with this name")
+        })?;
+
+        self.active_source.store(index, Ordering::Release);
+
+        Ok((old, new))
+    }
+
+    /// Return the number of registered sinks.
+    pub fn num_sinks(&self) -> usize {
+        self.inner.read(|inner| inner.num_sinks)
+    }
+
+    /// Return how often a core gave up waiting for the output lock.
+    pub fn num_lock_timeouts(&self) -> usize {
+        self.num_lock_timeouts.load(Ordering::Relaxed)
+    }
+
+    /// Return the error and overflow counts of the sink with the given registration index.
+    pub fn sink_statistics(&self, index: usize) -> Option<SinkStatistics> {
+        if index >= self.num_sinks() {
+            return None;
+        }
+
+        let counters = &self.counters[index];
+
+        Some(SinkStatistics {
+            num_errors: counters.num_errors.load(Ordering::Relaxed),
+            num_overflows: counters.num_overflows.load(Ordering::Relaxed),
+        })
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl interface::Write for ConsoleMux {
+    fn write_char(&self, c: char) {
+        self.with_output_lock(|| {
+            let _ = self.for_each_sink(|sink| {
+                sink.write_char(c);
+                Ok(())
+            });
+        })
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        self.with_output_lock(|| self.for_each_sink(|sink| sink.write_fmt(args)))
+    }
+
+    fn write_array(&self, data: &[u8]) {
+        self.with_output_lock(|| {
+            let _ = self.for_each_sink(|sink| {
+                sink.write_array(data);
+                Ok(())
+            });
+        })
+    }
+
+    fn flush(&self) {
+        self.with_output_lock(|| {
+            self.inner
+                .read(|inner| inner.sinks.iter().flatten().for_each(|sink| sink.flush()))
+        })
+    }
+}
+
+impl interface::Read for ConsoleMux {
+    fn try_read_char(&self) -> Option<char> {
+        self.source().and_then(|source| source.try_read_char())
+    }
+
+    fn read_char(&self) -> char {
+        match self.source() {
+            Some(source) => source.read_char(),
+            None => ' ',
+        }
+    }
+
+    fn read_array(&self, buf: &mut [u8], timeout: Duration) -> usize {
+        match self.source() {
+            Some(source) => source.read_array(buf, timeout),
+            None => 0,
+        }
+    }
+
+    fn clear_rx(&self) {
+        if let Some(source) = self.source() {
+            source.clear_rx()
+        }
+    }
+
+    fn set_echo(&self, enabled: bool) {
+        if let Some(source) = self.source() {
+            source.set_echo(enabled)
+        }
+    }
+
+    fn is_echo_enabled(&self) -> bool {
+        self.source().map_or(false, |x| x.is_echo_enabled())
+    }
+}
+
+impl interface::Statistics for ConsoleMux {
+    fn chars_written(&self) -> usize {
+        self.source().map_or(0, |x| x.chars_written())
+    }
+
+    fn chars_read(&self) -> usize {
+        self.source().map_or(0, |x| x.chars_read())
+    }
+
+    fn tx_queue_high_water(&self) -> usize {
+        self.source().map_or(0, |x| x.tx_queue_high_water())
+    }
+
+    fn rx_queue_high_water(&self) -> usize {
+        self.source().map_or(0, |x| x.rx_queue_high_water())
+    }
+
+    fn rx_dropped(&self) -> usize {
+        self.source().map_or(0, |x| x.rx_dropped())
+    }
+
+    fn tx_blocking_fallbacks(&self) -> usize {
+        self.source().map_or(0, |x| x.tx_blocking_fallbacks())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check that switching to an unknown console keeps the active source.
+    #[kernel_test]
+    fn switch_to_unknown_source_fails() {
+        let mux = console_mux();
+        let name = mux.source_name();
+
+        assert!(name.is_some());
+        assert_eq!(
+            mux.switch_source("tty1").err(),
+            Some("No console source with this name")
+        );
+        assert_eq!(mux.source_name(), name);
+    }
+
+    /// Check that the core that holds the output lock can print in between, and that the lock is
+    /// free again afterwards.
+    #[kernel_test]
+    fn output_lock_is_reentrant() {
+        use interface::Write;
+
+        let mux = console_mux();
+        let timeouts = mux.num_lock_timeouts();
+
+        mux.with_output_lock(|| {
+            assert_eq!(mux.output_owner.load(Ordering::Relaxed), cpu::core_id());
+            mux.write_fmt(format_args!("")).unwrap();
+        });
+
+        assert_eq!(mux.output_owner.load(Ordering::Relaxed), UNLOCKED);
+        assert_eq!(mux.num_lock_timeouts(), timeouts);
+    }
+}
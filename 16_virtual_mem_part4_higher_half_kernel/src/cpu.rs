@@ -10,12 +10,69 @@ mod arch_cpu;
 
 mod boot;
 
+pub mod barrier;
+pub mod cache;
+pub mod features;
+pub mod idle;
+pub mod pmu;
+pub mod psci;
 pub mod smp;
 
+use crate::{bsp, exception, time, time::interface::TimeManager};
+use core::time::Duration;
+
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
-pub use arch_cpu::{nop, wait_forever};
+pub use arch_cpu::{
+    cache_core_id, core_id, enable_event_stream, nop, return_addresses, send_event,
+    spin_for_iterations, stack_pointer, wait_for_event, wait_for_interrupt, wait_forever,
+};
 
 #[cfg(feature = "test_build")]
-pub use arch_cpu::{qemu_exit_failure, qemu_exit_success};
+pub use arch_cpu::qemu_exit;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Return true if the executing core is the boot core.
+#[inline(always)]
+pub fn is_boot_core() -> bool {
+    core_id() == bsp::cpu::BOOT_CORE_ID as usize
+}
+
+/// Wait in a low-power state until `condition` is true or `timeout` has elapsed.
+///
+/// The condition is checked before each call to `wait_for_event()`, so wake-ups that do not
+/// change the condition, for example spurious events or events meant for other waiters, only
+/// cost another check. A condition that becomes true without an accompanying event is picked up
+/// at the next tick of the timer's event stream at the latest.
+///
+/// The time spent waiting is accounted as idle time, see `idle`, unless the caller runs in IRQ
+/// context.
+///
+/// Returns true if the condition was met, and false on timeout. Waits indefinitely if `timeout` is
+/// `None`.
+pub fn spin_until(mut condition: impl FnMut() -> bool, timeout: Option<Duration>) -> bool {
+    let deadline = timeout.map(|x| time::time_manager().uptime() + x);
+
+    loop {
+        if condition() {
+            return true;
+        }
+
+        if let Some(x) = deadline {
+            if time::time_manager().uptime() >= x {
+                return false;
+            }
+        }
+
+        // Waiting in IRQ context is not idle time, since the core is busy handling the IRQ.
+        if exception::asynchronous::in_irq_context() {
+            wait_for_event();
+        } else {
+            idle::wait();
+        }
+    }
+}
@@ -0,0 +1,22 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Memory and instruction barriers.
+//!
+//! Besides the plain barriers, this module provides the sequences that the architecture requires
+//! after specific kinds of updates. Code that makes such an update calls the matching `complete_*`
+//! helper instead of open-coding the barriers, so each sequence is written down exactly once.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "../_arch/aarch64/cpu/barrier.rs"]
+mod arch_barrier;
+
+//--------------------------------------------------------------------------------------------------
+// Architectural Public Reexports
+//--------------------------------------------------------------------------------------------------
+pub use arch_barrier::{
+    complete_cache_maintenance, complete_local_invalidation, complete_system_register_write,
+    complete_table_update, complete_tlb_invalidation, dmb_ish, dsb_ish, dsb_ishst, dsb_nsh, dsb_sy,
+    isb,
+};
@@ -0,0 +1,50 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Cache control.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "../_arch/aarch64/cpu/cache.rs"]
+mod arch_cache;
+
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Architectural Public Reexports
+//--------------------------------------------------------------------------------------------------
+pub use arch_cache::{
+    clean_invalidate_dcache_range, disable_dcache, disable_icache, enable_dcache, enable_icache,
+    invalidate_all_icache, status,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// State of the MMU and the caches of the executing core.
+#[allow(missing_docs)]
+#[derive(Copy, Clone)]
+pub struct CacheStatus {
+    pub mmu: bool,
+    pub dcache: bool,
+    pub icache: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for CacheStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let to_str = |x| if x { "On" } else { "Off" };
+
+        write!(
+            f,
+            "MMU: {}, D-cache: {}, I-cache: {}",
+            to_str(self.mmu),
+            to_str(self.dcache),
+            to_str(self.icache)
+        )
+    }
+}
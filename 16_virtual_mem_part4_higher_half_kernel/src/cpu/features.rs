@@ -0,0 +1,37 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! CPU feature and implementation reporting.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "../_arch/aarch64/cpu/features.rs"]
+mod arch_features;
+
+use crate::info;
+
+//--------------------------------------------------------------------------------------------------
+// Architectural Public Reexports
+//--------------------------------------------------------------------------------------------------
+pub use arch_features::{has_feature, implementation, pa_range_bits, Feature, Implementation};
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Print the executing core's implementation and supported features.
+pub fn report() {
+    info!("CPU: {}", implementation());
+
+    match pa_range_bits() {
+        Some(x) => info!("      Physical address range: {} bit", x),
+        None => info!("      Physical address range: Unknown"),
+    }
+
+    info!("      Features:");
+    for feature in Feature::ALL.iter() {
+        let supported = if has_feature(*feature) { "Yes" } else { "No" };
+
+        info!("            {:<16} {}", feature, supported);
+    }
+}
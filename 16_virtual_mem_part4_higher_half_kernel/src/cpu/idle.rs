@@ -0,0 +1,411 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Idle accounting.
+//!
+//! Each core timestamps the entry and exit of its low-power waits in `wait()`, and accumulates the
+//! time in between in its own counters. There is no periodic tick, so utilization is reported
+//! between two snapshots of the counters, which the caller takes at the start and the end of the
+//! interval of interest.
+//!
+//! Only the owning core writes its counters, with IRQs masked. Other contexts read them under a
+//! sequence count, so that a read never mixes the state before and after an update.
+//!
+//! Cores without anything left to do sleep in `wait_for_interrupt()`. Each wake-up from it is
+//! attributed to the IRQ that was taken right after, which is found by comparing the core's IRQ
+//! statistics before and after the wait.
+
+use crate::{
+    config, cpu, exception, monitor,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    time,
+    time::interface::TimeManager,
+};
+use core::{
+    fmt, iter,
+    sync::atomic::{fence, AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Value of `IdleCounters::since_ns` while the core is not idle.
+const NOT_IDLE: u64 = u64::MAX;
+
+/// Number of distinct IRQs that the wake-up histogram tells apart. Further ones are counted
+/// together.
+const MAX_WAKEUP_SOURCES: usize = 8;
+
+/// Idle time of one core.
+///
+/// Only loads and stores are used, no read-modify-write operations, so the counters also work
+/// while the data cache is disabled.
+struct IdleCounters {
+    /// Odd while the owning core updates the other fields.
+    seq: AtomicU32,
+
+    /// Time spent idle in completed idle periods, in ns.
+    total_ns: AtomicU64,
+
+    /// Uptime in ns at which the current idle period started, or `NOT_IDLE`.
+    since_ns: AtomicU64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The idle time of all cores at one point in time.
+#[derive(Copy, Clone)]
+pub struct IdleSnapshot {
+    uptime: Duration,
+    idle: [Duration; config::cpu::NUM_CORES],
+}
+
+/// How a core's time was split between being busy and being idle, in percent.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Utilization {
+    pub busy_percent: u8,
+    pub idle_percent: u8,
+}
+
+/// Wake-ups from `wait_for_interrupt()`, counted per IRQ.
+#[derive(Copy, Clone)]
+pub struct WakeupHistogram {
+    irqs: [Option<(&'static str, usize)>; MAX_WAKEUP_SOURCES],
+    other_irqs: usize,
+    no_irq: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+#[allow(clippy::declare_interior_mutable_const)]
+const INIT: IdleCounters = IdleCounters::new();
+
+/// Idle counters, indexed by core id.
+static COUNTERS: [IdleCounters; config::cpu::NUM_CORES] = [INIT; config::cpu::NUM_CORES];
+
+/// Shared by all cores. Only the boot core takes IRQs, so it is the only one that wakes up.
+static WAKEUP_HISTOGRAM: IRQSafeNullLock<WakeupHistogram> =
+    IRQSafeNullLock::new(WakeupHistogram::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+#[inline(always)]
+fn uptime_ns() -> u64 {
+    time::time_manager().uptime().as_nanos() as u64
+}
+
+impl IdleCounters {
+    const fn new() -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+            total_ns: AtomicU64::new(0),
+            since_ns: AtomicU64::new(NOT_IDLE),
+        }
+    }
+
+    /// Change the counters in `f`. Must only be called by the owning core, with IRQs masked.
+    fn update(&self, f: impl FnOnce(&Self)) {
+        let seq = self.seq.load(Ordering::Relaxed);
+
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        f(self);
+
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    fn enter(&self, now_ns: u64) {
+        // A nested wait, for example from an IRQ handler, continues the current idle period.
+        if self.since_ns.load(Ordering::Relaxed) != NOT_IDLE {
+            return;
+        }
+
+        self.update(|x| x.since_ns.store(now_ns, Ordering::Relaxed));
+    }
+
+    fn exit(&self, now_ns: u64) {
+        let since_ns = self.since_ns.load(Ordering::Relaxed);
+        if since_ns == NOT_IDLE {
+            return;
+        }
+
+        self.update(|x| {
+            let total_ns = x.total_ns.load(Ordering::Relaxed);
+
+            x.total_ns.store(
+                total_ns + now_ns.saturating_sub(since_ns),
+                Ordering::Relaxed,
+            );
+            x.since_ns.store(NOT_IDLE, Ordering::Relaxed);
+        });
+    }
+
+    /// The idle time up to `now_ns`, including a running idle period.
+    fn read(&self, now_ns: u64) -> u64 {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            let total_ns = self.total_ns.load(Ordering::Relaxed);
+            let since_ns = self.since_ns.load(Ordering::Relaxed);
+            fence(Ordering::Acquire);
+
+            if (seq & 1 == 0) && (self.seq.load(Ordering::Relaxed) == seq) {
+                if since_ns == NOT_IDLE {
+                    return total_ns;
+                }
+
+                return total_ns + now_ns.saturating_sub(since_ns);
+            }
+
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl WakeupHistogram {
+    const fn new() -> Self {
+        Self {
+            irqs: [None; MAX_WAKEUP_SOURCES],
+            other_irqs: 0,
+            no_irq: 0,
+        }
+    }
+
+    /// Count a wake-up by the IRQ called `source`, or one without an IRQ.
+    fn record(&mut self, source: Option<&'static str>) {
+        let name = match source {
+            None => {
+                self.no_irq += 1;
+                return;
+            }
+            Some(x) => x,
+        };
+
+        for entry in self.irqs.iter_mut() {
+            match entry {
+                Some((x, count)) if *x == name => {
+                    *count += 1;
+                    return;
+                }
+                Some(_) => (),
+                None => {
+                    *entry = Some((name, 1));
+                    return;
+                }
+            }
+        }
+
+        self.other_irqs += 1;
+    }
+}
+
+impl Utilization {
+    fn new(idle: Duration, elapsed: Duration) -> Self {
+        let elapsed_ns = elapsed.as_nanos().max(1);
+        let idle_percent = (idle.as_nanos() * 100 / elapsed_ns).min(100) as u8;
+
+        Self {
+            busy_percent: 100 - idle_percent,
+            idle_percent,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Wait for an event like `cpu::wait_for_event()`, and account the time as idle time.
+pub fn wait() {
+    let counters = &COUNTERS[cpu::core_id()];
+
+    exception::asynchronous::exec_with_irq_masked(|| counters.enter(uptime_ns()));
+    cpu::wait_for_event();
+    exception::asynchronous::exec_with_irq_masked(|| counters.exit(uptime_ns()));
+}
+
+/// Sleep until an interrupt is pending, unless `work_pending` returns true. The time asleep is
+/// accounted as idle time.
+///
+/// `work_pending` is checked with IRQs masked, and the core wakes up on pending IRQs even while
+/// they are masked. So an IRQ that makes work pending right after the check cannot be lost. It is
+/// taken after the wait, when the IRQ mask is restored, and recorded as the source of the wake-up.
+/// If the caller has IRQs masked, they stay pending, and the wake-up is recorded without a source.
+pub fn wait_for_interrupt(work_pending: impl FnOnce() -> bool) {
+    use exception::asynchronous::{last_irq_name, num_irqs_taken};
+
+    let core_id = cpu::core_id();
+    let counters = &COUNTERS[core_id];
+    let num_irqs_before = num_irqs_taken(core_id);
+
+    unsafe {
+        let saved = exception::asynchronous::local_irq_mask_save();
+
+        if work_pending() {
+            exception::asynchronous::local_irq_restore(saved);
+            return;
+        }
+
+        counters.enter(uptime_ns());
+        cpu::wait_for_interrupt();
+        counters.exit(uptime_ns());
+
+        exception::asynchronous::local_irq_restore(saved);
+    }
+
+    let source = if num_irqs_taken(core_id) != num_irqs_before {
+        last_irq_name(core_id)
+    } else {
+        None
+    };
+
+    WAKEUP_HISTOGRAM.lock(|x| x.record(source));
+}
+
+/// Idle on the executing core for good, for example because there is no work for it.
+///
+/// Enters the monitor if a break asks the core to, see `monitor::request_entry()`.
+pub fn idle_loop() -> ! {
+    loop {
+        wait_for_interrupt(monitor::entry_requested);
+        monitor::safe_point();
+    }
+}
+
+/// A copy of the wake-up histogram.
+pub fn wakeup_histogram() -> WakeupHistogram {
+    WAKEUP_HISTOGRAM.lock(|x| *x)
+}
+
+impl WakeupHistogram {
+    /// The number of wake-ups per IRQ name, followed by the IRQs that did not fit into the
+    /// histogram and the wake-ups without an IRQ.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, usize)> + '_ {
+        let extra = iter::once(("Other IRQs", self.other_irqs))
+            .chain(iter::once(("No IRQ", self.no_irq)))
+            .filter(|(_, count)| *count > 0);
+
+        self.irqs.iter().flatten().copied().chain(extra)
+    }
+}
+
+/// Take a snapshot of the idle time of all cores.
+pub fn snapshot() -> IdleSnapshot {
+    let now_ns = uptime_ns();
+    let mut idle = [Duration::from_secs(0); config::cpu::NUM_CORES];
+
+    for (x, counters) in idle.iter_mut().zip(COUNTERS.iter()) {
+        *x = Duration::from_nanos(counters.read(now_ns));
+    }
+
+    IdleSnapshot {
+        uptime: Duration::from_nanos(now_ns),
+        idle,
+    }
+}
+
+/// The utilization of each core since `earlier` was taken.
+pub fn utilization(earlier: &IdleSnapshot) -> [Utilization; config::cpu::NUM_CORES] {
+    snapshot().utilization_since(earlier)
+}
+
+impl IdleSnapshot {
+    /// The utilization of each core between `earlier` and this snapshot.
+    pub fn utilization_since(&self, earlier: &Self) -> [Utilization; config::cpu::NUM_CORES] {
+        let elapsed = self.uptime.saturating_sub(earlier.uptime);
+        let mut ret = [Utilization::new(Duration::from_secs(0), elapsed); config::cpu::NUM_CORES];
+
+        for (core_id, x) in ret.iter_mut().enumerate() {
+            let idle = self.idle[core_id].saturating_sub(earlier.idle[core_id]);
+
+            *x = Utilization::new(idle, elapsed);
+        }
+
+        ret
+    }
+}
+
+impl fmt::Display for Utilization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "busy {:>3}% idle {:>3}%",
+            self.busy_percent, self.idle_percent
+        )
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check the bookkeeping of idle periods, including nested and unmatched calls.
+    #[kernel_test]
+    fn idle_periods_are_accumulated() {
+        let c = IdleCounters::new();
+
+        c.exit(50);
+        assert_eq!(c.read(100), 0);
+
+        c.enter(100);
+        c.enter(150);
+        assert_eq!(c.read(180), 80);
+        c.exit(200);
+        c.exit(300);
+        assert_eq!(c.read(1000), 100);
+    }
+
+    /// Check that wake-ups are counted per IRQ, and that the histogram overflows gracefully.
+    #[kernel_test]
+    fn wakeups_are_counted_per_irq() {
+        let mut h = WakeupHistogram::new();
+
+        h.record(Some("Timer"));
+        h.record(Some("Timer"));
+        h.record(None);
+        for _ in 0..MAX_WAKEUP_SOURCES {
+            h.record(Some("UART"));
+        }
+        for name in ["a", "b", "c", "d", "e", "f", "g"].iter() {
+            h.record(Some(*name));
+        }
+
+        let mut iter = h.iter();
+        assert_eq!(iter.next(), Some(("Timer", 2)));
+        assert_eq!(iter.next(), Some(("UART", MAX_WAKEUP_SOURCES)));
+        assert_eq!(
+            h.iter().find(|(x, _)| *x == "Other IRQs"),
+            Some(("Other IRQs", 1))
+        );
+        assert_eq!(h.iter().last(), Some(("No IRQ", 1)));
+    }
+
+    /// Check the percentages, which are clamped to 100.
+    #[kernel_test]
+    fn utilization_is_computed() {
+        let u = Utilization::new(Duration::from_millis(250), Duration::from_secs(1));
+        assert_eq!(u.busy_percent, 75);
+        assert_eq!(u.idle_percent, 25);
+
+        let u = Utilization::new(Duration::from_secs(2), Duration::from_secs(1));
+        assert_eq!(u.idle_percent, 100);
+
+        let u = Utilization::new(Duration::from_secs(0), Duration::from_secs(0));
+        assert_eq!(u.busy_percent, 100);
+    }
+}
@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Performance monitors.
+//!
+//! Each core has a cycle counter, which runs at the core's clock, and a few event counters. The
+//! counters are banked per core, so they must be configured and read on the core whose execution
+//! is measured.
+//!
+//! ```
+//! cpu::pmu::configure_counter(0, cpu::pmu::Event::L1DRefill)?;
+//! let start = cpu::pmu::read_cycles();
+//!
+//! do_work();
+//!
+//! let cycles = cpu::pmu::read_cycles() - start;
+//! let refills = cpu::pmu::read_counter(0)?;
+//! ```
+
+#[cfg(target_arch = "aarch64")]
+#[path = "../_arch/aarch64/cpu/pmu.rs"]
+mod arch_pmu;
+
+//--------------------------------------------------------------------------------------------------
+// Architectural Public Reexports
+//--------------------------------------------------------------------------------------------------
+pub use arch_pmu::{
+    configure_counter, init, num_counters, read_counter, read_cycles, Event, PerformanceMonitor,
+    MAX_COUNTERS,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check that the cycle counter runs.
+    #[kernel_test]
+    fn cycle_counter_advances() {
+        let start = read_cycles();
+        crate::cpu::spin_for_iterations(1000);
+
+        assert!(read_cycles() > start);
+    }
+
+    /// Check that an event counter counts the instructions of a busy loop.
+    #[kernel_test]
+    fn event_counter_counts_instructions() {
+        assert!(num_counters() > 0);
+        assert!(configure_counter(0, Event::InstructionsRetired).is_ok());
+
+        crate::cpu::spin_for_iterations(1000);
+        assert!(read_counter(0).unwrap() >= 1000);
+
+        assert!(configure_counter(MAX_COUNTERS, Event::InstructionsRetired).is_err());
+        assert!(read_counter(MAX_COUNTERS).is_err());
+    }
+}
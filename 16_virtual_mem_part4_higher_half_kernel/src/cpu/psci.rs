@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Power State Coordination Interface (PSCI).
+//!
+//! PSCI is the standard firmware interface for powering cores on and off, and for resetting or
+//! shutting down the system. Whether it is available, and which conduit must be used to reach it,
+//! is decided by the BSP.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "../_arch/aarch64/cpu/psci.rs"]
+mod arch_psci;
+
+use crate::{
+    bsp,
+    memory::{Address, Physical},
+};
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Architectural Public Reexports
+//--------------------------------------------------------------------------------------------------
+pub use arch_psci::Conduit;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// PSCI function IDs.
+mod function_id {
+    pub const VERSION: u32 = 0x8400_0000;
+    pub const CPU_ON_64: u32 = 0xC400_0003;
+    pub const SYSTEM_OFF: u32 = 0x8400_0008;
+    pub const SYSTEM_RESET: u32 = 0x8400_0009;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// PSCI error variants.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PSCIError {
+    NotSupported,
+    InvalidParameters,
+    Denied,
+    AlreadyOn,
+    OnPending,
+    InternalFailure,
+    NotPresent,
+    Disabled,
+    InvalidAddress,
+    Unknown(i64),
+}
+
+/// A PSCI version number.
+#[allow(missing_docs)]
+#[derive(Copy, Clone)]
+pub struct Version {
+    pub major: u16,
+    pub minor: u16,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Map a PSCI return value to a result.
+fn result_from(ret: i64) -> Result<i64, PSCIError> {
+    let err = match ret {
+        x if x >= 0 => return Ok(x),
+        -1 => PSCIError::NotSupported,
+        -2 => PSCIError::InvalidParameters,
+        -3 => PSCIError::Denied,
+        -4 => PSCIError::AlreadyOn,
+        -5 => PSCIError::OnPending,
+        -6 => PSCIError::InternalFailure,
+        -7 => PSCIError::NotPresent,
+        -8 => PSCIError::Disabled,
+        -9 => PSCIError::InvalidAddress,
+        x => PSCIError::Unknown(x),
+    };
+
+    Err(err)
+}
+
+/// Issue a PSCI call through the BSP-selected conduit.
+fn call(function_id: u32, arg0: u64, arg1: u64, arg2: u64) -> Result<i64, PSCIError> {
+    let conduit = bsp::cpu::PSCI_CONDUIT.ok_or(PSCIError::NotSupported)?;
+
+    // Safety: The BSP only selects a conduit if PSCI firmware is known to be present.
+    let ret = unsafe { arch_psci::call(conduit, function_id, arg0, arg1, arg2) };
+
+    result_from(ret)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for PSCIError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PSCIError::NotSupported => write!(f, "PSCI: Not supported"),
+            PSCIError::InvalidParameters => write!(f, "PSCI: Invalid parameters"),
+            PSCIError::Denied => write!(f, "PSCI: Denied"),
+            PSCIError::AlreadyOn => write!(f, "PSCI: Already on"),
+            PSCIError::OnPending => write!(f, "PSCI: On pending"),
+            PSCIError::InternalFailure => write!(f, "PSCI: Internal failure"),
+            PSCIError::NotPresent => write!(f, "PSCI: Not present"),
+            PSCIError::Disabled => write!(f, "PSCI: Disabled"),
+            PSCIError::InvalidAddress => write!(f, "PSCI: Invalid address"),
+            PSCIError::Unknown(x) => write!(f, "PSCI: Unknown error {}", x),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Query the PSCI version implemented by the firmware.
+///
+/// Succeeds only if PSCI is available, so it doubles as a presence check.
+pub fn version() -> Result<Version, PSCIError> {
+    let ret = call(function_id::VERSION, 0, 0, 0)?;
+
+    Ok(Version {
+        major: (ret >> 16) as u16,
+        minor: ret as u16,
+    })
+}
+
+/// Power on the core identified by `target_mpidr`.
+///
+/// The core starts execution at the physical address `entry` with the MMU disabled, and `context`
+/// in its first general purpose register.
+///
+/// # Safety
+///
+/// - `entry` must point to code that can run with the MMU disabled.
+pub unsafe fn cpu_on(
+    target_mpidr: u64,
+    entry: Address<Physical>,
+    context: u64,
+) -> Result<(), PSCIError> {
+    call(
+        function_id::CPU_ON_64,
+        target_mpidr,
+        entry.into_usize() as u64,
+        context,
+    )?;
+
+    Ok(())
+}
+
+/// Reset the system.
+///
+/// Only returns on failure.
+pub fn system_reset() -> PSCIError {
+    match call(function_id::SYSTEM_RESET, 0, 0, 0) {
+        Ok(_) => PSCIError::InternalFailure,
+        Err(x) => x,
+    }
+}
+
+/// Power off the system.
+///
+/// Only returns on failure.
+pub fn system_off() -> PSCIError {
+    match call(function_id::SYSTEM_OFF, 0, 0, 0) {
+        Ok(_) => PSCIError::InternalFailure,
+        Err(x) => x,
+    }
+}
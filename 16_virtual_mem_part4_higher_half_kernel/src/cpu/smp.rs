@@ -8,7 +8,71 @@
 #[path = "../_arch/aarch64/cpu/smp.rs"]
 mod arch_smp;
 
+use crate::{bsp, config, cpu, cpu::psci, state};
+use core::time::Duration;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
 //--------------------------------------------------------------------------------------------------
-// Architectural Public Reexports
+
+/// The secondary core entry of `libkernel`.
+///
+/// It is linked weakly, so that binaries which do not care about secondary cores, for example the
+/// integration tests, do not need to provide it. Secondary cores are simply parked in this case.
+#[linkage = "weak"]
+#[no_mangle]
+fn kernel_secondary_main(_core_id: usize) -> ! {
+    cpu::idle::idle_loop()
+}
+
+/// Power on the secondary cores using PSCI `CPU_ON`.
+///
+/// Entry happens at the same place as with the spin-table method. PSCI implementations start
+/// non-secure cores in EL2 if it is implemented, which is what `_start_secondary` expects.
+fn release_secondary_cores_psci() -> Result<(), &'static str> {
+    let phys_entry_addr = arch_smp::phys_secondary_entry_addr()?;
+
+    for core_id in 0..config::cpu::NUM_CORES {
+        if core_id == bsp::cpu::BOOT_CORE_ID as usize {
+            continue;
+        }
+
+        // Safety: `_start_secondary` is written to run with the MMU disabled.
+        if let Err(x) = unsafe { psci::cpu_on(core_id as u64, phys_entry_addr, 0) } {
+            if x != psci::PSCIError::AlreadyOn {
+                return Err("PSCI CPU_ON failed");
+            }
+        }
+    }
+
+    Ok(())
+}
+
 //--------------------------------------------------------------------------------------------------
-pub use arch_smp::core_id;
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Boot the secondary cores.
+///
+/// Transitions the kernel into the multi-core state and releases the secondary cores. PSCI is
+/// preferred if the firmware implements it, with the spin-table as fallback. Afterwards, waits a
+/// limited amount of time for the cores to check in with the state manager.
+pub fn boot_secondary_cores() -> Result<(), &'static str> {
+    const CHECK_IN_TIMEOUT: Duration = Duration::from_millis(100);
+
+    state::state_manager().transition(state::State::SingleCoreMain, state::State::MultiCoreMain);
+
+    if psci::version().is_ok() {
+        release_secondary_cores_psci()?;
+    } else {
+        arch_smp::release_secondary_cores_spin_table()?;
+    }
+
+    let all_checked_in = || state::state_manager().num_cores_checked_in() >= config::cpu::NUM_CORES;
+
+    if !cpu::spin_until(all_checked_in, Some(CHECK_IN_TIMEOUT)) {
+        return Err("Timeout while waiting for secondary cores to check in");
+    }
+
+    Ok(())
+}
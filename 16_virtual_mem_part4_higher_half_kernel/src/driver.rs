@@ -4,17 +4,164 @@
 
 //! Driver support.
 
+use crate::{
+    bsp,
+    bsp::device_driver::IRQNumber,
+    config, error,
+    error::KernelError,
+    exception,
+    fmt::table::{Column, Table},
+    info,
+    memory::{Address, Virtual},
+    shutdown::ShutdownReason,
+    state,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    warn,
+};
+use core::fmt;
+
+#[cfg(feature = "mmio_stats")]
+use crate::memory::mmu::mmio_access::{self, MmioAccessCounts};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Capacity of the driver registration table.
+const NUM_DRIVERS: usize = 16;
+
+/// Maximum number of driver init failures recorded in the report.
+const MAX_INIT_ERRORS: usize = 8;
+
+/// The driver registration table.
+type DriverTable = [Option<DeviceDriverDescriptor>; NUM_DRIVERS];
+
+struct DriverManagerInner {
+    descriptors: DriverTable,
+    num_descriptors: usize,
+    init_report: DriverInitReport,
+}
+
+/// Display helper for a driver's optional MMIO start address.
+struct MMIOStartAddr(Option<usize>);
+
+/// Display helper for a driver's IRQ numbers.
+struct IRQNumbers<'a>(&'a [IRQNumber]);
+
+/// Display helper for an access count of a driver that might not be counted.
+#[cfg(feature = "mmio_stats")]
+struct AccessCount(Option<u64>);
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// Driver initialization stages, in the order in which the kernel runs them.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum InitStage {
+    /// Runs `init()` of the drivers needed for printing. Printing is not available yet.
+    Early,
+
+    /// Runs `init()` of the remaining drivers and registers the IRQ handlers of all drivers that
+    /// came up.
+    PostMMU,
+
+    /// Runs `post_irq_init()` of all drivers, after IRQs have been unmasked on the boot core.
+    PostIRQ,
+}
+
+/// Runtime status of a driver.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DriverStatus {
+    Uninitialized,
+    Ok,
+    Failed,
+}
+
+/// The step of a driver's bring-up that failed, with the error returned by the driver.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DriverErrorKind {
+    /// `init()` or the post-init callback.
+    Init(&'static str),
+
+    /// `verify_mmio()`.
+    VerifyMmio(&'static str),
+
+    /// `register_and_enable_irq_handler()`.
+    IrqHandler(&'static str),
+
+    /// `post_irq_init()`.
+    PostIrqInit(&'static str),
+
+    /// A self-test that the driver registered.
+    SelfTest(&'static str),
+}
+
+/// A driver init failure.
+#[derive(Copy, Clone)]
+pub struct DriverInitError {
+    /// The failing driver's compatible string.
+    pub driver: &'static str,
+
+    /// The stage in which the driver failed.
+    pub stage: InitStage,
+
+    /// What failed.
+    pub kind: DriverErrorKind,
+}
+
+/// Record of all driver init failures.
+#[derive(Copy, Clone)]
+pub struct DriverInitReport {
+    errors: [Option<DriverInitError>; MAX_INIT_ERRORS],
+    num_errors: usize,
+    completed_stage: Option<InitStage>,
+}
+
+/// The counted MMIO accesses of the registered drivers, see `DriverManager::mmio_stats()`.
+///
+/// Drivers are counted by their compatible string. Drivers that did not map an `MmioRegion` under
+/// it have no counts.
+#[cfg(feature = "mmio_stats")]
+#[derive(Copy, Clone)]
+pub struct DriverMmioStats {
+    entries: [Option<(&'static str, Option<MmioAccessCounts>)>; NUM_DRIVERS],
+}
+
+/// Type to be used as an optional callback after a driver's `init()` has run.
+pub type DeviceDriverPostInitCallback = unsafe fn() -> Result<(), &'static str>;
+
+/// A descriptor for device drivers.
+#[derive(Copy, Clone)]
+pub struct DeviceDriverDescriptor {
+    device_driver: &'static (dyn interface::DeviceDriver + Sync),
+    init_stage: InitStage,
+    post_init_callback: Option<DeviceDriverPostInitCallback>,
+}
+
+/// Provides device driver management functions.
+pub struct DriverManager {
+    inner: IRQSafeNullLock<DriverManagerInner>,
+}
+
 /// Driver interfaces.
 pub mod interface {
+    use crate::{bsp::device_driver::IRQNumber, shutdown::ShutdownReason};
+
     /// Device Driver functions.
     pub trait DeviceDriver {
         /// Return a compatibility string for identifying the driver.
         fn compatible(&self) -> &'static str;
 
+        /// Return true if the kernel cannot run without the driver.
+        ///
+        /// Failures of essential drivers halt the boot. Failures of all other drivers only leave
+        /// the system in a degraded state.
+        fn is_essential(&self) -> bool {
+            false
+        }
+
         /// Called by the kernel to bring up the device.
         ///
         /// # Safety
@@ -24,6 +171,19 @@ pub mod interface {
             Ok(())
         }
 
+        /// Called by the kernel after IRQs have been unmasked on the boot core.
+        ///
+        /// Intended for bring-up steps that depend on interrupts being delivered. Since IRQs are
+        /// unmasked, MMIO remapping and IRQ handler registration are not possible anymore and must
+        /// be done in `init()`.
+        ///
+        /// # Safety
+        ///
+        /// - During init, drivers might do stuff with system-wide impact.
+        unsafe fn post_irq_init(&self) -> Result<(), &'static str> {
+            Ok(())
+        }
+
         /// Called by the kernel to register and enable the device's IRQ handlers, if any.
         ///
         /// Rust's type system will prevent a call to this function unless the calling instance
@@ -39,24 +199,601 @@ pub mod interface {
         fn virt_mmio_start_addr(&self) -> Option<usize> {
             None
         }
+
+        /// Return the IRQ numbers the driver registers handlers for.
+        fn irq_numbers(&self) -> &[IRQNumber] {
+            &[]
+        }
+
+        /// Called by the kernel after the `PostMMU` stage's inits, to check that the device at the
+        /// mapped MMIO address is the expected one.
+        ///
+        /// Drivers read an identification register here, if the device has one. A mismatch usually
+        /// means that the kernel was built for another board, which otherwise shows up as a hang.
+        fn verify_mmio(&self) -> Result<(), &'static str> {
+            Ok(())
+        }
+
+        /// Called by the kernel during an orderly shutdown, before the system is reset or powered
+        /// off.
+        ///
+        /// Drivers finish outstanding work here, for example, by draining their buffers. The
+        /// device must stay usable for printing, since the kernel keeps printing afterwards.
+        fn shutdown(&self, _reason: ShutdownReason) -> Result<(), &'static str> {
+            Ok(())
+        }
     }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
 
-    /// Device driver management functions.
+static DRIVER_MANAGER: DriverManager = DriverManager::new();
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl DriverManagerInner {
+    /// Create an instance.
+    const fn new() -> Self {
+        Self {
+            descriptors: [None; NUM_DRIVERS],
+            num_descriptors: 0,
+            init_report: DriverInitReport::new(),
+        }
+    }
+}
+
+impl DriverInitReport {
+    /// Create an instance.
+    const fn new() -> Self {
+        Self {
+            errors: [None; MAX_INIT_ERRORS],
+            num_errors: 0,
+            completed_stage: None,
+        }
+    }
+
+    /// Add a failure. Failures beyond the capacity are only counted.
+    fn add(&mut self, error: DriverInitError) {
+        if let Some(slot) = self.errors.get_mut(self.num_errors) {
+            *slot = Some(error);
+        }
+
+        self.num_errors += 1;
+    }
+}
+
+impl fmt::Display for MMIOStartAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(x) => write!(f, "{}", Address::<Virtual>::new(x)),
+            None => write!(f, "-"),
+        }
+    }
+}
+
+impl fmt::Display for IRQNumbers<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "-");
+        }
+
+        for (i, irq_number) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", irq_number)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "mmio_stats")]
+impl fmt::Display for AccessCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(x) => write!(f, "{}", x),
+            None => write!(f, "-"),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Return a reference to the global DriverManager.
+pub fn driver_manager() -> &'static DriverManager {
+    &DRIVER_MANAGER
+}
+
+/// Compare the value of a device's identification register with the expected one.
+///
+/// For use in `DeviceDriver::verify_mmio()`. Logs the values on a mismatch, since the returned error
+/// cannot carry them.
+pub fn check_mmio_id(name: &str, read: u32, expected: u32) -> Result<(), &'static str> {
+    if read == expected {
+        return Ok(());
+    }
+
+    error!(
+        "Device at {} did not identify as expected (read {:#010x}, expected {:#010x})",
+        name, read, expected
+    );
+
+    Err("Device did not identify as expected")
+}
+
+impl DeviceDriverDescriptor {
+    /// Create an instance.
     ///
-    /// The `BSP` is supposed to supply one global instance.
-    pub trait DriverManager {
-        /// Return a slice of references to all `BSP`-instantiated drivers.
-        fn all_device_drivers(&self) -> &[&'static (dyn DeviceDriver + Sync)];
+    /// `init_stage` selects the stage in which the driver's `init()` runs. It must be either
+    /// `Early`, for the drivers needed for printing, or `PostMMU`.
+    pub fn new(
+        device_driver: &'static (dyn interface::DeviceDriver + Sync),
+        init_stage: InitStage,
+        post_init_callback: Option<DeviceDriverPostInitCallback>,
+    ) -> Self {
+        assert!(init_stage != InitStage::PostIRQ);
 
-        /// Return only those drivers needed for the BSP's early printing functionality.
-        ///
-        /// For example, the default UART.
-        fn early_print_device_drivers(&self) -> &[&'static (dyn DeviceDriver + Sync)];
+        Self {
+            device_driver,
+            init_stage,
+            post_init_callback,
+        }
+    }
+
+    /// Return the described driver.
+    pub fn device_driver(&self) -> &'static (dyn interface::DeviceDriver + Sync) {
+        self.device_driver
+    }
+}
+
+impl fmt::Display for DriverStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DriverStatus::Uninitialized => "Uninitialized",
+            DriverStatus::Ok => "Ok",
+            DriverStatus::Failed => "Failed",
+        };
+
+        f.pad(s)
+    }
+}
 
-        /// Return all drivers minus early-print drivers.
-        fn non_early_print_device_drivers(&self) -> &[&'static (dyn DeviceDriver + Sync)];
+impl fmt::Display for InitStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitStage::Early => write!(f, "Early"),
+            InitStage::PostMMU => write!(f, "PostMMU"),
+            InitStage::PostIRQ => write!(f, "PostIRQ"),
+        }
+    }
+}
+
+impl fmt::Display for DriverErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriverErrorKind::Init(x) => write!(f, "init: {}", x),
+            DriverErrorKind::VerifyMmio(x) => write!(f, "MMIO check: {}", x),
+            DriverErrorKind::IrqHandler(x) => write!(f, "IRQ handler: {}", x),
+            DriverErrorKind::PostIrqInit(x) => write!(f, "post-IRQ init: {}", x),
+            DriverErrorKind::SelfTest(x) => write!(f, "self-test: {}", x),
+        }
+    }
+}
+
+impl DriverInitReport {
+    /// Return an iterator over the recorded failures.
+    pub fn errors(&self) -> impl Iterator<Item = &DriverInitError> {
+        self.errors.iter().filter_map(|x| x.as_ref())
+    }
+
+    /// Return the last initialization stage that ran to completion, if any.
+    pub fn completed_stage(&self) -> Option<InitStage> {
+        self.completed_stage
+    }
+
+    /// Return the total number of failures, including those that did not fit into the report.
+    pub fn num_errors(&self) -> usize {
+        self.num_errors
+    }
+
+    /// Return the first recorded failure of the driver with the given compatible string.
+    pub fn error_of(&self, compatible: &str) -> Option<&DriverInitError> {
+        self.errors().find(|x| x.driver == compatible)
+    }
+
+    /// Return true if the driver with the given compatible string failed.
+    pub fn has_failed(&self, compatible: &str) -> bool {
+        self.error_of(compatible).is_some()
+    }
+}
+
+#[cfg(feature = "mmio_stats")]
+impl DriverMmioStats {
+    /// The drivers with their counts, in registration order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, Option<MmioAccessCounts>)> + '_ {
+        self.entries.iter().flatten().copied()
+    }
+
+    /// The accesses that were counted since `earlier`.
+    ///
+    /// Drivers that had no counts in `earlier` keep their totals.
+    pub fn since(&self, earlier: &Self) -> Self {
+        let mut result = *self;
+
+        for (name, counts) in result.entries.iter_mut().flatten() {
+            let earlier_counts = earlier
+                .iter()
+                .find(|(x, _)| *x == *name)
+                .and_then(|(_, x)| x);
+
+            if let (Some(now), Some(e)) = (counts.as_mut(), earlier_counts) {
+                *now = now.since(&e);
+            }
+        }
+
+        result
+    }
+
+    /// Print a table of the drivers with their reads and writes.
+    pub fn print(&self) {
+        let mut table = Table::new([
+            Column::left("Driver"),
+            Column::right("Reads"),
+            Column::right("Writes"),
+        ]);
+
+        for (name, counts) in self.iter() {
+            table.row(|r| {
+                r.cell(name)
+                    .cell(AccessCount(counts.map(|x| x.reads)))
+                    .cell(AccessCount(counts.map(|x| x.writes)));
+            });
+        }
+        table.print();
+    }
+}
+
+impl DriverManager {
+    /// Create an instance.
+    const fn new() -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(DriverManagerInner::new()),
+        }
+    }
+
+    /// Return a copy of the registration table.
+    ///
+    /// Drivers are called on the copy, so that they can register further drivers from their init
+    /// functions.
+    fn descriptors(&self) -> DriverTable {
+        self.inner.lock(|inner| inner.descriptors)
+    }
+
+    /// Record and report a driver init failure. Halts the kernel if the driver is essential.
+    ///
+    /// Returns the failure as a kernel error.
+    fn init_failed(
+        &self,
+        descriptor: &DeviceDriverDescriptor,
+        stage: InitStage,
+        kind: DriverErrorKind,
+    ) -> KernelError {
+        let driver = descriptor.device_driver;
+        let error = DriverInitError {
+            driver: driver.compatible(),
+            stage,
+            kind,
+        };
+
+        self.inner.lock(|inner| inner.init_report.add(error));
+
+        // Printing is not available in the early stage yet.
+        if stage == InitStage::Early {
+            return error.into();
+        }
+
+        if driver.is_essential() {
+            crate::error::fatal("Essential driver failed", error.into());
+        }
+
+        warn!(
+            "Driver failed in stage {}: {}",
+            stage,
+            KernelError::from(error)
+        );
+
+        error.into()
+    }
+
+    /// Return true if the driver did not fail so far.
+    fn is_healthy(&self, descriptor: &DeviceDriverDescriptor) -> bool {
+        !self.inner.lock(|inner| {
+            inner
+                .init_report
+                .has_failed(descriptor.device_driver.compatible())
+        })
+    }
+
+    /// Register a device driver with the kernel.
+    ///
+    /// Registration is possible until the kernel leaves the init phase. Drivers that are registered
+    /// after a stage has run are only picked up by the later stages.
+    pub fn register_driver(&self, descriptor: DeviceDriverDescriptor) -> Result<(), &'static str> {
+        if !state::state_manager().is_init() {
+            return Err("Driver registration is closed after kernel init");
+        }
+
+        self.inner.lock(|inner| {
+            let slot = inner
+                .descriptors
+                .get_mut(inner.num_descriptors)
+                .ok_or("Driver registration table is full")?;
+
+            *slot = Some(descriptor);
+            inner.num_descriptors += 1;
+
+            Ok(())
+        })
+    }
 
-        /// Initialization code that runs after the early print driver init.
-        fn post_early_print_device_driver_init(&self);
+    /// Return the number of registered drivers.
+    pub fn num_drivers(&self) -> usize {
+        self.inner.lock(|inner| inner.num_descriptors)
+    }
+
+    /// Run the given initialization stage.
+    ///
+    /// Errors are recorded in the driver init report and do not keep the remaining drivers from
+    /// being initialized, unless the failing driver is essential. The first error of the stage is
+    /// returned. The exception is the `Early` stage, which cannot print yet. It stops at the first
+    /// error.
+    ///
+    /// Drivers that failed in an earlier stage are skipped in the later ones.
+    ///
+    /// # Safety
+    ///
+    /// - Must only be called during kernel init, with the stages in order.
+    pub unsafe fn init_stage(&self, stage: InitStage) -> Result<(), KernelError> {
+        let mut first_error = None;
+
+        match stage {
+            InitStage::Early | InitStage::PostMMU => {
+                for descriptor in self
+                    .descriptors()
+                    .iter()
+                    .flatten()
+                    .filter(|x| x.init_stage == stage)
+                {
+                    let result = descriptor.device_driver.init().and_then(|_| {
+                        match descriptor.post_init_callback {
+                            Some(callback) => callback(),
+                            None => Ok(()),
+                        }
+                    });
+
+                    if let Err(x) = result {
+                        let error = self.init_failed(descriptor, stage, DriverErrorKind::Init(x));
+
+                        if stage == InitStage::Early {
+                            return Err(error);
+                        }
+                        first_error = first_error.or(Some(error));
+                    }
+                }
+
+                // Now that all MMIO is mapped and printing works, let the drivers check that their
+                // devices are where they expect them.
+                if stage == InitStage::PostMMU {
+                    for descriptor in self
+                        .descriptors()
+                        .iter()
+                        .flatten()
+                        .filter(|x| self.is_healthy(x))
+                    {
+                        if let Err(x) = descriptor.device_driver.verify_mmio() {
+                            let error =
+                                self.init_failed(descriptor, stage, DriverErrorKind::VerifyMmio(x));
+                            first_error = first_error.or(Some(error));
+                        }
+                    }
+                }
+
+                // Let drivers register and enable their handlers with the interrupt controller,
+                // which is only available after the PostMMU stage's inits.
+                if stage == InitStage::PostMMU {
+                    for descriptor in self
+                        .descriptors()
+                        .iter()
+                        .flatten()
+                        .filter(|x| self.is_healthy(x))
+                    {
+                        if let Err(x) = descriptor.device_driver.register_and_enable_irq_handler() {
+                            let error =
+                                self.init_failed(descriptor, stage, DriverErrorKind::IrqHandler(x));
+                            first_error = first_error.or(Some(error));
+                        }
+                    }
+                }
+            }
+            InitStage::PostIRQ => {
+                for descriptor in self
+                    .descriptors()
+                    .iter()
+                    .flatten()
+                    .filter(|x| self.is_healthy(x))
+                {
+                    if let Err(x) = descriptor.device_driver.post_irq_init() {
+                        let error =
+                            self.init_failed(descriptor, stage, DriverErrorKind::PostIrqInit(x));
+                        first_error = first_error.or(Some(error));
+                    }
+                }
+            }
+        }
+
+        self.inner
+            .lock(|inner| inner.init_report.completed_stage = Some(stage));
+
+        match first_error {
+            None => Ok(()),
+            Some(x) => Err(x),
+        }
+    }
+
+    /// Return the report of all driver init failures so far.
+    pub fn init_report(&self) -> DriverInitReport {
+        self.inner.lock(|inner| inner.init_report)
+    }
+
+    /// Return the runtime status of the given driver.
+    pub fn driver_status(&self, descriptor: &DeviceDriverDescriptor) -> DriverStatus {
+        let report = self.init_report();
+
+        if report.has_failed(descriptor.device_driver.compatible()) {
+            return DriverStatus::Failed;
+        }
+
+        match report.completed_stage() {
+            Some(x) if x >= descriptor.init_stage => DriverStatus::Ok,
+            _ => DriverStatus::Uninitialized,
+        }
+    }
+
+    /// Return the number of registered drivers that have the given status.
+    pub fn num_drivers_with_status(&self, status: DriverStatus) -> usize {
+        self.descriptors()
+            .iter()
+            .flatten()
+            .filter(|x| self.driver_status(x) == status)
+            .count()
+    }
+
+    /// Call the given closure for each registered driver.
+    pub fn for_each_driver(
+        &self,
+        mut f: impl FnMut(&'static (dyn interface::DeviceDriver + Sync)),
+    ) {
+        for descriptor in self.descriptors().iter().flatten() {
+            f(descriptor.device_driver)
+        }
+    }
+
+    /// Return the counted MMIO accesses of all registered drivers.
+    #[cfg(feature = "mmio_stats")]
+    pub fn mmio_stats(&self) -> DriverMmioStats {
+        let mut stats = DriverMmioStats {
+            entries: [None; NUM_DRIVERS],
+        };
+
+        for (slot, descriptor) in stats.entries.iter_mut().zip(self.descriptors().iter()) {
+            *slot = descriptor.map(|x| {
+                let name = x.device_driver.compatible();
+
+                (name, mmio_access::access_counts(name))
+            });
+        }
+
+        stats
+    }
+
+    /// Run the `shutdown()` hooks of all drivers that came up, in reverse init order.
+    ///
+    /// Failures do not keep the remaining drivers from being shut down. The first error is
+    /// returned.
+    pub fn shutdown_drivers(&self, reason: ShutdownReason) -> Result<(), &'static str> {
+        let descriptors = self.descriptors();
+        let mut first_error = None;
+
+        for stage in [InitStage::PostMMU, InitStage::Early].iter() {
+            for descriptor in descriptors
+                .iter()
+                .rev()
+                .flatten()
+                .filter(|x| x.init_stage == *stage)
+                .filter(|x| self.driver_status(x) == DriverStatus::Ok)
+            {
+                if let Err(x) = descriptor.device_driver.shutdown(reason) {
+                    warn!(
+                        "{}: Shutdown failed: {}",
+                        descriptor.device_driver.compatible(),
+                        x
+                    );
+                    first_error = first_error.or(Some(x));
+                }
+            }
+        }
+
+        match first_error {
+            None => Ok(()),
+            Some(x) => Err(x),
+        }
+    }
+
+    /// Route the drivers' IRQs to the cores that `config::exception::IRQ_TARGET_POLICY` selects.
+    ///
+    /// Local IRQs belong to their core and are left alone. Stops at the first IRQ that can not be
+    /// routed.
+    pub fn apply_irq_target_policy(&self) -> Result<(), &'static str> {
+        use exception::asynchronous::{interface::IRQManager, IRQTargetPolicy};
+
+        let policy = config::exception::IRQ_TARGET_POLICY;
+        if policy == IRQTargetPolicy::BootCore {
+            return Ok(());
+        }
+
+        let mut n = 0;
+        for descriptor in self.descriptors().iter().flatten() {
+            for irq_number in descriptor.device_driver.irq_numbers() {
+                if let IRQNumber::Local(_) = irq_number {
+                    continue;
+                }
+
+                let core_mask = exception::asynchronous::target_cores_by_policy(policy, n);
+                bsp::exception::asynchronous::irq_manager()
+                    .set_target_cores(*irq_number, core_mask)?;
+                n += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Print a table of all drivers with their status, MMIO start address and IRQ numbers.
+    pub fn print_status(&self) {
+        let report = self.init_report();
+        let mut table = Table::new([
+            Column::right("#"),
+            Column::left("Driver"),
+            Column::left("Status"),
+            Column::left("MMIO start"),
+            Column::left("IRQs"),
+        ]);
+
+        for (i, descriptor) in self.descriptors().iter().flatten().enumerate() {
+            let driver = descriptor.device_driver;
+
+            table.row(|r| {
+                r.cell(format_args!("{}.", i + 1))
+                    .cell(driver.compatible())
+                    .cell(self.driver_status(descriptor))
+                    .cell(MMIOStartAddr(driver.virt_mmio_start_addr()))
+                    .cell(IRQNumbers(driver.irq_numbers()));
+            });
+        }
+        table.print();
+
+        // The errors are too long for a column, so they are listed below the table.
+        for (i, descriptor) in self.descriptors().iter().flatten().enumerate() {
+            if let Some(x) = report.error_of(descriptor.device_driver.compatible()) {
+                info!("      {:>2}. Error in stage {}: {}", i + 1, x.stage, x.kind);
+            }
+        }
     }
 }
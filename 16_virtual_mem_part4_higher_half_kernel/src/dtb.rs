@@ -0,0 +1,154 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Device tree.
+//!
+//! The firmware passes the physical address of a flattened device tree (FDT) in register `x0`. The
+//! blob is mapped read-only during kernel init and can be queried afterwards.
+
+mod fdt;
+
+use crate::{
+    bsp,
+    memory::{
+        mmu,
+        mmu::{AccessPermissions, AttributeFields, MemAttributes, PageSliceDescriptor},
+        Address, Physical, Virtual,
+    },
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+};
+
+pub use fdt::*;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The largest blob that is accepted. The Raspberry's are around 30 KiB.
+const MAX_SIZE: usize = 256 * 1024;
+
+const ATTR: AttributeFields = AttributeFields {
+    mem_attributes: MemAttributes::CacheableDRAM,
+    acc_perms: AccessPermissions::ReadOnly,
+    execute_never: true,
+};
+
+#[derive(Copy, Clone)]
+struct MappedTree {
+    phys_pages: PageSliceDescriptor<Physical>,
+    tree: DeviceTree<'static>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static PHYS_ADDR: InitStateLock<Option<Address<Physical>>> = InitStateLock::new(None);
+
+static MAPPED_TREE: InitStateLock<Option<MappedTree>> = InitStateLock::new(None);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The pages spanning the `size` bytes starting at `phys_addr`.
+fn phys_pages_covering(phys_addr: Address<Physical>, size: usize) -> PageSliceDescriptor<Physical> {
+    use bsp::memory::mmu::KernelGranule;
+
+    let start = phys_addr.align_down(KernelGranule::SIZE);
+    let end = (phys_addr + size + KernelGranule::MASK).align_down(KernelGranule::SIZE);
+
+    PageSliceDescriptor::from_addr(
+        start,
+        (end.into_usize() - start.into_usize()) >> KernelGranule::SHIFT,
+    )
+}
+
+/// Map `phys_pages` at the start of `window` and return the blob as a slice of `size` bytes.
+///
+/// # Safety
+///
+/// - The window must be unused.
+unsafe fn map_blob(
+    window: &PageSliceDescriptor<Virtual>,
+    phys_addr: Address<Physical>,
+    size: usize,
+) -> Result<(PageSliceDescriptor<Virtual>, &'static [u8]), &'static str> {
+    use bsp::memory::mmu::KernelGranule;
+
+    let phys_pages = phys_pages_covering(phys_addr, size);
+    let virt_pages = PageSliceDescriptor::from_addr(window.start_addr(), phys_pages.num_pages());
+    mmu::kernel_map_window(&virt_pages, &phys_pages, &ATTR)?;
+
+    let virt_addr = window.start_addr() + (phys_addr.into_usize() & KernelGranule::MASK);
+    let blob = core::slice::from_raw_parts(virt_addr.into_usize() as *const u8, size);
+
+    Ok((virt_pages, blob))
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Remember the address that the firmware passed. Zero means that there is no device tree.
+///
+/// # Safety
+///
+/// - Must only be called by `runtime_init()`, after the `bss` section was zeroed.
+pub(crate) unsafe fn set_phys_addr(phys_addr: usize) {
+    if phys_addr != 0 {
+        PHYS_ADDR.write(|x| *x = Some(Address::new(phys_addr)));
+    }
+}
+
+/// Map and validate the device tree, if the firmware passed one.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init.
+pub unsafe fn init() -> Result<(), &'static str> {
+    let phys_addr = match PHYS_ADDR.read(|x| *x) {
+        None => return Ok(()),
+        Some(x) => x,
+    };
+
+    let max_pages = phys_pages_covering(phys_addr, MAX_SIZE).num_pages();
+    let window = mmu::kernel_alloc_window(max_pages)?;
+
+    // Map the header first, to learn the size of the blob.
+    let (virt_pages, header) = map_blob(&window, phys_addr, HEADER_SIZE)?;
+    let total_size = DeviceTree::total_size(header);
+    mmu::kernel_unmap_window(&virt_pages)?;
+
+    let total_size = total_size?;
+    if total_size > MAX_SIZE {
+        return Err("Device tree is too large");
+    }
+
+    let (virt_pages, blob) = map_blob(&window, phys_addr, total_size)?;
+    let tree = match DeviceTree::new(blob) {
+        Err(x) => {
+            mmu::kernel_unmap_window(&virt_pages)?;
+            return Err(x);
+        }
+        Ok(x) => x,
+    };
+
+    let phys_pages = phys_pages_covering(phys_addr, total_size);
+    mmu::kernel_add_mapping_record("Device tree", &virt_pages, &phys_pages, &ATTR);
+
+    MAPPED_TREE.write(|x| *x = Some(MappedTree { phys_pages, tree }));
+
+    Ok(())
+}
+
+/// Return the device tree, if the firmware passed a valid one.
+pub fn device_tree() -> Option<DeviceTree<'static>> {
+    MAPPED_TREE.read(|x| x.map(|x| x.tree))
+}
+
+/// Return the physical pages that hold the device tree, if it is mapped.
+pub fn phys_page_desc() -> Option<PageSliceDescriptor<Physical>> {
+    MAPPED_TREE.read(|x| x.map(|x| x.phys_pages))
+}
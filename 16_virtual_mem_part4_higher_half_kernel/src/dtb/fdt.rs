@@ -0,0 +1,726 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Flattened device tree parser.
+//!
+//! Read-only and allocation free. Supports just enough of the format to look up nodes by path and
+//! read their properties. Pure code, so that it can be tested on the host as well.
+//!
+//! # Resources
+//!
+//! - https://github.com/devicetree-org/devicetree-specification/releases/tag/v0.3
+
+use core::{convert::TryInto, str};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const MAGIC: u32 = 0xd00d_feed;
+
+/// The first version whose header contains the size of the structure block.
+const MIN_VERSION: u32 = 17;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// Offsets of the header fields.
+mod field {
+    pub const MAGIC: usize = 0x00;
+    pub const TOTAL_SIZE: usize = 0x04;
+    pub const OFF_DT_STRUCT: usize = 0x08;
+    pub const OFF_DT_STRINGS: usize = 0x0C;
+    pub const VERSION: usize = 0x14;
+    pub const LAST_COMP_VERSION: usize = 0x18;
+    pub const SIZE_DT_STRINGS: usize = 0x20;
+    pub const SIZE_DT_STRUCT: usize = 0x24;
+}
+
+const TRUNCATED: &str = "Device tree structure block is truncated";
+
+#[derive(Copy, Clone)]
+enum Token<'a> {
+    BeginNode(&'a str),
+    EndNode,
+    Prop(&'a str, &'a [u8]),
+    End,
+}
+
+/// Iterator over the tokens of the structure block, starting at a given offset.
+struct Tokens<'a> {
+    tree: DeviceTree<'a>,
+    offset: usize,
+    done: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Size of the header in bytes.
+pub const HEADER_SIZE: usize = 0x28;
+
+/// A validated device tree blob.
+#[derive(Copy, Clone)]
+pub struct DeviceTree<'a> {
+    structure: &'a [u8],
+    strings: &'a [u8],
+}
+
+/// A node of the device tree.
+#[derive(Copy, Clone)]
+pub struct Node<'a> {
+    tree: DeviceTree<'a>,
+    name: &'a str,
+
+    /// Offset of the first token after the node's `FDT_BEGIN_NODE`.
+    offset: usize,
+}
+
+/// An entry of a `reg` property.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RegEntry {
+    pub addr: u64,
+    pub size: u64,
+}
+
+/// Iterator over the entries of a `reg` property.
+#[derive(Clone)]
+pub struct RegIter<'a> {
+    data: &'a [u8],
+    addr_cells: usize,
+    size_cells: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Read the big-endian u32 at `offset`.
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let b = bytes.get(offset..offset.checked_add(4)?)?;
+
+    Some(u32::from_be_bytes(b.try_into().ok()?))
+}
+
+/// Round up to the next multiple of four.
+const fn align4(value: usize) -> usize {
+    (value + 3) & !3
+}
+
+/// Read a number that is `cells` cells wide and return it together with the remaining bytes.
+fn read_cells(data: &[u8], cells: usize) -> Option<(u64, &[u8])> {
+    if cells > 2 {
+        return None;
+    }
+
+    let len = cells * 4;
+    let bytes = data.get(..len)?;
+    let value = bytes.chunks_exact(4).fold(0, |acc, x| {
+        (acc << 32) | u64::from(u32::from_be_bytes(x.try_into().unwrap()))
+    });
+
+    Some((value, &data[len..]))
+}
+
+/// Check if the path component `component` names the node `name`.
+///
+/// The unit address may be omitted, so `memory` names `memory@0`.
+fn name_matches(name: &str, component: &str) -> bool {
+    if name == component {
+        return true;
+    }
+
+    !component.contains('@')
+        && name.starts_with(component)
+        && name.as_bytes().get(component.len()) == Some(&b'@')
+}
+
+impl<'a> DeviceTree<'a> {
+    /// Decode the token at `offset`. Return it together with the offset of the next token.
+    fn token_at(&self, mut offset: usize) -> Result<(Token<'a>, usize), &'static str> {
+        loop {
+            let token = be32(self.structure, offset).ok_or(TRUNCATED)?;
+            offset += 4;
+
+            match token {
+                FDT_BEGIN_NODE => {
+                    let rest = self.structure.get(offset..).ok_or(TRUNCATED)?;
+                    let len = rest.iter().position(|x| *x == 0).ok_or(TRUNCATED)?;
+                    let name =
+                        str::from_utf8(&rest[..len]).map_err(|_| "Node name is not UTF-8")?;
+
+                    return Ok((Token::BeginNode(name), align4(offset + len + 1)));
+                }
+                FDT_END_NODE => return Ok((Token::EndNode, offset)),
+                FDT_PROP => {
+                    let len = be32(self.structure, offset).ok_or(TRUNCATED)? as usize;
+                    let name_offset = be32(self.structure, offset + 4).ok_or(TRUNCATED)? as usize;
+                    let value_start = offset + 8;
+                    let value = self
+                        .structure
+                        .get(value_start..value_start + len)
+                        .ok_or(TRUNCATED)?;
+
+                    return Ok((
+                        Token::Prop(self.string_at(name_offset)?, value),
+                        align4(value_start + len),
+                    ));
+                }
+                FDT_NOP => continue,
+                FDT_END => return Ok((Token::End, offset)),
+                _ => return Err("Unknown token in device tree structure block"),
+            }
+        }
+    }
+
+    /// Return the string at `offset` in the strings block.
+    fn string_at(&self, offset: usize) -> Result<&'a str, &'static str> {
+        let rest = self
+            .strings
+            .get(offset..)
+            .ok_or("Property name outside of strings block")?;
+        let len = rest
+            .iter()
+            .position(|x| *x == 0)
+            .ok_or("Unterminated property name")?;
+
+        str::from_utf8(&rest[..len]).map_err(|_| "Property name is not UTF-8")
+    }
+
+    fn tokens(&self, offset: usize) -> Tokens<'a> {
+        Tokens {
+            tree: *self,
+            offset,
+            done: false,
+        }
+    }
+
+    /// Walk the whole structure block once, so that lookups can treat errors as "not found".
+    fn validate_structure(&self) -> Result<(), &'static str> {
+        let mut offset = 0;
+        let mut depth = 0_usize;
+
+        loop {
+            let (token, next) = self.token_at(offset)?;
+            offset = next;
+
+            match token {
+                Token::BeginNode(_) => depth += 1,
+                Token::EndNode => {
+                    depth = depth
+                        .checked_sub(1)
+                        .ok_or("Unbalanced nodes in device tree")?;
+                }
+                Token::Prop(..) if depth == 0 => return Err("Property outside of root node"),
+                Token::Prop(..) => (),
+                Token::End if depth == 0 => return Ok(()),
+                Token::End => return Err("Unbalanced nodes in device tree"),
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.tree.token_at(self.offset) {
+            Ok((Token::End, _)) | Err(_) => {
+                self.done = true;
+                None
+            }
+            Ok((token, next)) => {
+                self.offset = next;
+                Some(token)
+            }
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<'a> DeviceTree<'a> {
+    /// Validate a header and return the size of the whole blob.
+    ///
+    /// `header` needs to span only the first `HEADER_SIZE` bytes of the blob.
+    pub fn total_size(header: &[u8]) -> Result<usize, &'static str> {
+        if header.len() < HEADER_SIZE {
+            return Err("Device tree header is truncated");
+        }
+
+        if be32(header, field::MAGIC) != Some(MAGIC) {
+            return Err("Bad device tree magic");
+        }
+
+        let version = be32(header, field::VERSION).unwrap();
+        let last_comp_version = be32(header, field::LAST_COMP_VERSION).unwrap();
+        if version < MIN_VERSION || last_comp_version > MIN_VERSION {
+            return Err("Unsupported device tree version");
+        }
+
+        let total_size = be32(header, field::TOTAL_SIZE).unwrap() as usize;
+        if total_size < HEADER_SIZE {
+            return Err("Bad device tree size");
+        }
+
+        Ok(total_size)
+    }
+
+    /// Create an instance from a blob, checking it thoroughly.
+    pub fn new(blob: &'a [u8]) -> Result<Self, &'static str> {
+        let total_size = Self::total_size(blob)?;
+        let blob = blob
+            .get(..total_size)
+            .ok_or("Device tree blob is truncated")?;
+
+        let block = |offset_field, size_field| {
+            let offset = be32(blob, offset_field).unwrap() as usize;
+            let size = be32(blob, size_field).unwrap() as usize;
+
+            blob.get(offset..offset.checked_add(size)?)
+        };
+
+        let tree = Self {
+            structure: block(field::OFF_DT_STRUCT, field::SIZE_DT_STRUCT)
+                .ok_or("Device tree structure block out of bounds")?,
+            strings: block(field::OFF_DT_STRINGS, field::SIZE_DT_STRINGS)
+                .ok_or("Device tree strings block out of bounds")?,
+        };
+
+        tree.validate_structure()?;
+
+        match tree.token_at(0)? {
+            (Token::BeginNode(""), _) => Ok(tree),
+            _ => Err("Device tree does not start with the root node"),
+        }
+    }
+
+    /// The root node.
+    pub fn root(&self) -> Node<'a> {
+        // Checked by `new()`.
+        let (_, offset) = self.token_at(0).unwrap();
+
+        Node {
+            tree: *self,
+            name: "",
+            offset,
+        }
+    }
+
+    /// Look up a node by its absolute path, for example `/soc/serial@7e201000`.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        let mut components = path.split('/').filter(|x| !x.is_empty()).peekable();
+        if components.peek().is_none() {
+            return Some(self.root());
+        }
+
+        // The root node is at depth one, and `matched` counts the matched nodes below it.
+        let mut tokens = self.tokens(0);
+        let mut depth = 0;
+        let mut matched = 0;
+
+        loop {
+            match tokens.next()? {
+                Token::BeginNode(name) => {
+                    depth += 1;
+
+                    if depth == matched + 2 && name_matches(name, components.peek()?) {
+                        components.next();
+                        matched += 1;
+
+                        if components.peek().is_none() {
+                            return Some(Node {
+                                tree: *self,
+                                name,
+                                offset: tokens.offset,
+                            });
+                        }
+                    }
+                }
+                Token::EndNode => {
+                    // The deepest matched node ends without a match among its children.
+                    if depth == matched + 1 {
+                        return None;
+                    }
+
+                    depth -= 1;
+                }
+                Token::Prop(..) | Token::End => (),
+            }
+        }
+    }
+
+    /// The entries of the `reg` property of the `/memory` node.
+    pub fn memory_regions(&self) -> Option<RegIter<'a>> {
+        self.find_node("/memory")?.reg(&self.root())
+    }
+
+    /// Translate an address of the `/soc` node's address space to a physical address, using the
+    /// node's `ranges` property.
+    pub fn translate_soc_addr(&self, addr: u64) -> Option<u64> {
+        let soc = self.find_node("/soc")?;
+        let mut ranges = soc.property("ranges")?;
+
+        // An empty property means that the address spaces are identical.
+        if ranges.is_empty() {
+            return Some(addr);
+        }
+
+        let child_cells = soc.address_cells();
+        let parent_cells = self.root().address_cells();
+        let size_cells = soc.size_cells();
+        if child_cells == 0 || size_cells == 0 {
+            return None;
+        }
+
+        while !ranges.is_empty() {
+            let (child, rest) = read_cells(ranges, child_cells)?;
+            let (parent, rest) = read_cells(rest, parent_cells)?;
+            let (size, rest) = read_cells(rest, size_cells)?;
+            ranges = rest;
+
+            if addr >= child && addr - child < size {
+                return Some(parent + (addr - child));
+            }
+        }
+
+        None
+    }
+
+    /// The physical address of the UART that the `/chosen` node's `stdout-path` points to.
+    pub fn stdout_uart_addr(&self) -> Option<u64> {
+        let chosen = self.find_node("/chosen")?;
+        let path = chosen
+            .property_str("stdout-path")
+            .or_else(|| chosen.property_str("linux,stdout-path"))?;
+
+        // Strip the options, for example `:115200n8`.
+        let path = path.split(':').next()?;
+
+        // Resolve an alias, for example `serial0`.
+        let path = if path.starts_with('/') {
+            path
+        } else {
+            self.find_node("/aliases")?.property_str(path)?
+        };
+
+        let parent_path = &path[..path.rfind('/')?];
+        let parent = self.find_node(parent_path)?;
+        let addr = self.find_node(path)?.reg(&parent)?.next()?.addr;
+
+        if parent_path == "/soc" {
+            self.translate_soc_addr(addr)
+        } else {
+            Some(addr)
+        }
+    }
+}
+
+impl<'a> Node<'a> {
+    /// The node's name, including the unit address.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Return the raw value of a property.
+    pub fn property(&self, name: &str) -> Option<&'a [u8]> {
+        // Properties precede the child nodes.
+        for token in self.tree.tokens(self.offset) {
+            match token {
+                Token::Prop(x, value) if x == name => return Some(value),
+                Token::Prop(..) => (),
+                _ => return None,
+            }
+        }
+
+        None
+    }
+
+    /// Return the value of a property that holds a single u32.
+    pub fn property_u32(&self, name: &str) -> Option<u32> {
+        let value = self.property(name)?;
+        if value.len() != 4 {
+            return None;
+        }
+
+        be32(value, 0)
+    }
+
+    /// Return the value of a property that holds a string.
+    pub fn property_str(&self, name: &str) -> Option<&'a str> {
+        let value = self.property(name)?;
+        let value = value.strip_suffix(b"\0").unwrap_or(value);
+
+        str::from_utf8(value).ok()
+    }
+
+    /// Cells that children use for addresses.
+    pub fn address_cells(&self) -> usize {
+        self.property_u32("#address-cells").unwrap_or(2) as usize
+    }
+
+    /// Cells that children use for sizes.
+    pub fn size_cells(&self) -> usize {
+        self.property_u32("#size-cells").unwrap_or(1) as usize
+    }
+
+    /// The entries of the node's `reg` property, decoded with the cell sizes of `parent`.
+    pub fn reg(&self, parent: &Node<'a>) -> Option<RegIter<'a>> {
+        Some(RegIter {
+            data: self.property("reg")?,
+            addr_cells: parent.address_cells(),
+            size_cells: parent.size_cells(),
+        })
+    }
+}
+
+impl Iterator for RegIter<'_> {
+    type Item = RegEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() || (self.addr_cells + self.size_cells) == 0 {
+            return None;
+        }
+
+        let (addr, rest) = read_cells(self.data, self.addr_cells)?;
+        let (size, rest) = read_cells(rest, self.size_cells)?;
+        self.data = rest;
+
+        Some(RegEntry { addr, size })
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(all(test, not(target_os = "none")))]
+mod host_tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Builder {
+        structure: Vec<u8>,
+        strings: Vec<u8>,
+    }
+
+    impl Builder {
+        fn token(&mut self, token: u32) -> &mut Self {
+            self.structure.extend_from_slice(&token.to_be_bytes());
+            self
+        }
+
+        fn pad(&mut self) {
+            self.structure.resize(align4(self.structure.len()), 0);
+        }
+
+        fn begin(&mut self, name: &str) -> &mut Self {
+            self.token(FDT_BEGIN_NODE);
+            self.structure.extend_from_slice(name.as_bytes());
+            self.structure.push(0);
+            self.pad();
+            self
+        }
+
+        fn end(&mut self) -> &mut Self {
+            self.token(FDT_END_NODE)
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) -> &mut Self {
+            let name_offset = self.strings.len() as u32;
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+
+            self.token(FDT_PROP)
+                .token(value.len() as u32)
+                .token(name_offset);
+            self.structure.extend_from_slice(value);
+            self.pad();
+            self
+        }
+
+        fn prop_cells(&mut self, name: &str, cells: &[u32]) -> &mut Self {
+            let value: Vec<u8> = cells.iter().flat_map(|x| x.to_be_bytes()).collect();
+            self.prop(name, &value)
+        }
+
+        fn prop_str(&mut self, name: &str, value: &str) -> &mut Self {
+            let mut value = value.as_bytes().to_vec();
+            value.push(0);
+            self.prop(name, &value)
+        }
+
+        fn finish(&mut self) -> Vec<u8> {
+            self.token(FDT_END);
+
+            let off_struct = HEADER_SIZE;
+            let off_strings = off_struct + self.structure.len();
+            let total_size = off_strings + self.strings.len();
+            let header = [
+                MAGIC,
+                total_size as u32,
+                off_struct as u32,
+                off_strings as u32,
+                HEADER_SIZE as u32, // Empty memory reservation map, shared with the structure.
+                17,
+                16,
+                0,
+                self.strings.len() as u32,
+                self.structure.len() as u32,
+            ];
+
+            let mut blob: Vec<u8> = header.iter().flat_map(|x| x.to_be_bytes()).collect();
+            blob.extend_from_slice(&self.structure);
+            blob.extend_from_slice(&self.strings);
+            blob
+        }
+    }
+
+    /// A device tree that resembles the one of the Raspberry Pi 3.
+    fn rpi3_like() -> Vec<u8> {
+        Builder::default()
+            .begin("")
+            .prop_cells("#address-cells", &[1])
+            .prop_cells("#size-cells", &[1])
+            .begin("chosen")
+            .prop_str("stdout-path", "serial0:115200n8")
+            .end()
+            .begin("aliases")
+            .prop_str("serial0", "/soc/serial@7e201000")
+            .end()
+            .begin("memory@0")
+            .prop_str("device_type", "memory")
+            .prop_cells("reg", &[0, 0x3b40_0000])
+            .end()
+            .begin("soc")
+            .prop_cells("#address-cells", &[1])
+            .prop_cells("#size-cells", &[1])
+            .prop_cells(
+                "ranges",
+                &[
+                    0x7e00_0000,
+                    0x3f00_0000,
+                    0x0100_0000,
+                    0x4000_0000,
+                    0x4000_0000,
+                    0x1000,
+                ],
+            )
+            .begin("serial@7e215040")
+            .prop_cells("reg", &[0x7e21_5040, 0x40])
+            .end()
+            .begin("serial@7e201000")
+            .prop_cells("reg", &[0x7e20_1000, 0x200])
+            .end()
+            .end()
+            .end()
+            .finish()
+    }
+
+    /// Broken headers are rejected.
+    #[test]
+    fn header_validation() {
+        let blob = rpi3_like();
+        assert_eq!(DeviceTree::total_size(&blob), Ok(blob.len()));
+        assert!(DeviceTree::total_size(&blob[..HEADER_SIZE - 1]).is_err());
+        assert!(DeviceTree::new(&blob[..blob.len() - 1]).is_err());
+
+        let mut bad_magic = blob.clone();
+        bad_magic[0] = 0;
+        assert!(DeviceTree::total_size(&bad_magic).is_err());
+
+        let mut old_version = blob;
+        old_version[field::VERSION + 3] = 16;
+        assert!(DeviceTree::total_size(&old_version).is_err());
+    }
+
+    /// A structure block with unbalanced nodes is rejected.
+    #[test]
+    fn unbalanced_nodes() {
+        let blob = Builder::default().begin("").begin("soc").end().finish();
+        assert!(DeviceTree::new(&blob).is_err());
+    }
+
+    /// Nodes are found by their path, with or without the unit address.
+    #[test]
+    fn find_node() {
+        let blob = rpi3_like();
+        let tree = DeviceTree::new(&blob).unwrap();
+
+        assert_eq!(tree.find_node("/").unwrap().name(), "");
+        assert_eq!(tree.find_node("/memory").unwrap().name(), "memory@0");
+        assert_eq!(tree.find_node("/memory@0").unwrap().name(), "memory@0");
+        assert_eq!(
+            tree.find_node("/soc/serial@7e201000").unwrap().name(),
+            "serial@7e201000"
+        );
+
+        assert!(tree.find_node("/mem").is_none());
+        assert!(tree.find_node("/memory@1").is_none());
+        assert!(tree.find_node("/serial@7e201000").is_none());
+        assert!(tree.find_node("/soc/serial@7e201000/foo").is_none());
+    }
+
+    /// Property values are decoded.
+    #[test]
+    fn properties() {
+        let blob = rpi3_like();
+        let tree = DeviceTree::new(&blob).unwrap();
+        let memory = tree.find_node("/memory").unwrap();
+
+        assert_eq!(memory.property_str("device_type"), Some("memory"));
+        assert_eq!(tree.root().property_u32("#size-cells"), Some(1));
+        assert!(memory.property("#size-cells").is_none());
+
+        // Properties of child nodes don't belong to the parent.
+        assert!(tree.find_node("/soc").unwrap().property("reg").is_none());
+    }
+
+    /// The DRAM is read from the `/memory` node.
+    #[test]
+    fn memory_regions() {
+        let blob = rpi3_like();
+        let tree = DeviceTree::new(&blob).unwrap();
+        let regions: Vec<RegEntry> = tree.memory_regions().unwrap().collect();
+
+        assert_eq!(
+            regions,
+            [RegEntry {
+                addr: 0,
+                size: 0x3b40_0000
+            }]
+        );
+    }
+
+    /// Bus addresses are translated with the `ranges` of `/soc`.
+    #[test]
+    fn soc_translation() {
+        let blob = rpi3_like();
+        let tree = DeviceTree::new(&blob).unwrap();
+
+        assert_eq!(tree.translate_soc_addr(0x7e20_1000), Some(0x3f20_1000));
+        assert_eq!(tree.translate_soc_addr(0x4000_0040), Some(0x4000_0040));
+        assert_eq!(tree.translate_soc_addr(0x7f00_0000), None);
+    }
+
+    /// The stdout UART is found through the alias.
+    #[test]
+    fn stdout_uart() {
+        let blob = rpi3_like();
+        let tree = DeviceTree::new(&blob).unwrap();
+
+        assert_eq!(tree.stdout_uart_addr(), Some(0x3f20_1000));
+    }
+}
@@ -0,0 +1,238 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Kernel error type.
+//!
+//! Subsystems keep their own error types. `KernelError` wraps them with the subsystem they came
+//! from, so that an error that is passed up through several layers still tells where it started.
+//! It prints as a chain from the outermost to the innermost cause:
+//!
+//! ```text
+//! driver BCM PL011 UART: MMIO check: Device did not identify as expected
+//! ```
+//!
+//! Errors that end the kernel are handed to `fatal()`, which keeps the error for the panic handler
+//! to print.
+
+use crate::{
+    cpu::psci::PSCIError,
+    driver::{DriverErrorKind, DriverInitError},
+    loader::LoadError,
+    memory::mmu::{MMIOMapError, MMUEnableError, TranslationError},
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+};
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Errors of the MMU subsystem.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MmuError {
+    Enable(MMUEnableError),
+    Translation(TranslationError),
+    MmioMap(MMIOMapError),
+}
+
+/// An error, together with the subsystem that reported it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KernelError {
+    /// The MMU or the kernel's translation tables.
+    Mmu(MmuError),
+
+    /// A device driver.
+    Driver {
+        /// The driver's compatible string.
+        name: &'static str,
+
+        /// What failed.
+        kind: DriverErrorKind,
+    },
+
+    /// The interrupt controller or an IRQ handler.
+    Irq(&'static str),
+
+    /// A timer or the time manager.
+    Time(&'static str),
+
+    /// The image loader.
+    Loader(LoadError),
+
+    /// The firmware's power state coordination interface.
+    Psci(PSCIError),
+
+    /// Any other error, without a subsystem.
+    Other(&'static str),
+}
+
+/// Ending the kernel on errors.
+pub trait ResultExt<T> {
+    /// Return the value, or end the kernel with the error via `fatal()`.
+    fn or_fatal(self, context: &'static str) -> T;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// The error that is passed to the panic handler by `fatal()`.
+static FATAL_ERROR: IRQSafeNullLock<Option<KernelError>> = IRQSafeNullLock::new(None);
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for MmuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MmuError::Enable(x) => write!(f, "enable: {}", x),
+            MmuError::Translation(x) => write!(f, "translation: {}", x),
+            MmuError::MmioMap(x) => write!(f, "MMIO mapping: {}", x),
+        }
+    }
+}
+
+impl fmt::Display for KernelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KernelError::Mmu(x) => write!(f, "MMU: {}", x),
+            KernelError::Driver { name, kind } => write!(f, "driver {}: {}", name, kind),
+            KernelError::Irq(x) => write!(f, "IRQ: {}", x),
+            KernelError::Time(x) => write!(f, "time: {}", x),
+            KernelError::Loader(x) => write!(f, "loader: {}", x),
+            KernelError::Psci(x) => write!(f, "PSCI: {}", x),
+            KernelError::Other(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+impl From<MMUEnableError> for KernelError {
+    fn from(x: MMUEnableError) -> Self {
+        KernelError::Mmu(MmuError::Enable(x))
+    }
+}
+
+impl From<TranslationError> for KernelError {
+    fn from(x: TranslationError) -> Self {
+        KernelError::Mmu(MmuError::Translation(x))
+    }
+}
+
+impl From<MMIOMapError> for KernelError {
+    fn from(x: MMIOMapError) -> Self {
+        KernelError::Mmu(MmuError::MmioMap(x))
+    }
+}
+
+impl From<DriverInitError> for KernelError {
+    fn from(x: DriverInitError) -> Self {
+        KernelError::Driver {
+            name: x.driver,
+            kind: x.kind,
+        }
+    }
+}
+
+impl From<LoadError> for KernelError {
+    fn from(x: LoadError) -> Self {
+        KernelError::Loader(x)
+    }
+}
+
+impl From<PSCIError> for KernelError {
+    fn from(x: PSCIError) -> Self {
+        KernelError::Psci(x)
+    }
+}
+
+impl From<&'static str> for KernelError {
+    fn from(x: &'static str) -> Self {
+        KernelError::Other(x)
+    }
+}
+
+impl<T, E: Into<KernelError>> ResultExt<T> for Result<T, E> {
+    fn or_fatal(self, context: &'static str) -> T {
+        match self {
+            Ok(x) => x,
+            Err(x) => fatal(context, x.into()),
+        }
+    }
+}
+
+/// End the kernel because of `error`.
+///
+/// Panics with `context` as the message. The panic handler prints the error's chain below it.
+pub fn fatal(context: &'static str, error: KernelError) -> ! {
+    FATAL_ERROR.lock(|x| *x = Some(error));
+
+    panic!("{}", context)
+}
+
+/// Take the error that was passed to `fatal()`, if any. For the panic handler.
+pub fn take_fatal() -> Option<KernelError> {
+    FATAL_ERROR.lock(|x| x.take())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::InitStage;
+    use test_macros::kernel_test;
+
+    /// The chain names the subsystem first and ends with the innermost error.
+    #[kernel_test]
+    fn error_chain_is_printed() {
+        use core::fmt::Write;
+
+        struct Buf {
+            data: [u8; 96],
+            len: usize,
+        }
+
+        impl Write for Buf {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                let end = self.len + s.len();
+                self.data
+                    .get_mut(self.len..end)
+                    .ok_or(fmt::Error)?
+                    .copy_from_slice(s.as_bytes());
+                self.len = end;
+
+                Ok(())
+            }
+        }
+
+        fn check(error: KernelError, expected: &str) {
+            let mut buf = Buf {
+                data: [0; 96],
+                len: 0,
+            };
+            write!(buf, "{}", error).unwrap();
+
+            assert_eq!(&buf.data[..buf.len], expected.as_bytes());
+        }
+
+        check(
+            MMIOMapError::RegionExhausted.into(),
+            "MMU: MMIO mapping: Not enough MMIO space left",
+        );
+        check(
+            DriverInitError {
+                driver: "Test",
+                stage: InitStage::PostMMU,
+                kind: DriverErrorKind::VerifyMmio("Bad ID"),
+            }
+            .into(),
+            "driver Test: MMIO check: Bad ID",
+        );
+        check("Plain".into(), "Plain");
+    }
+}
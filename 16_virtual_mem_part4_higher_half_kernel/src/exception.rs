@@ -9,11 +9,21 @@
 mod arch_exception;
 
 pub mod asynchronous;
+pub mod core_dump;
+pub mod fault_injection;
+
+use crate::{
+    memory::{Address, Virtual},
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+};
 
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
-pub use arch_exception::{current_privilege_level, handling_init};
+pub use arch_exception::{
+    core_dump, current_privilege_level, handling_init, last_context_snapshot, null_exception,
+    take_context_snapshot, ExceptionContext, Snapshot,
+};
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -29,6 +39,93 @@ pub enum PrivilegeLevel {
     Unknown,
 }
 
+/// The cause of a synchronous exception, as far as the kernel tells them apart.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FaultClass {
+    /// The address is not mapped.
+    Translation,
+
+    /// The mapping's access flag is not set.
+    AccessFlag,
+
+    /// The access is not allowed by the mapping's permissions.
+    Permission,
+
+    /// The access is misaligned for the memory type.
+    Alignment,
+
+    /// A breakpoint instruction was executed.
+    Breakpoint,
+
+    /// A supervisor call instruction was executed.
+    SupervisorCall,
+
+    /// Anything else.
+    Other,
+}
+
+/// The condition flags of an interrupted context.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ConditionFlags {
+    pub negative: bool,
+    pub zero: bool,
+    pub carry: bool,
+    pub overflow: bool,
+}
+
+/// Decoded information about a synchronous exception.
+#[derive(Copy, Clone)]
+pub struct SyncExceptionInfo {
+    /// The cause.
+    pub class: FaultClass,
+
+    /// The faulting address. Only available for aborts.
+    pub fault_addr: Option<Address<Virtual>>,
+
+    /// The address of the instruction that caused the exception. For a supervisor call, the address
+    /// of the instruction after it.
+    pub pc: Address<Virtual>,
+
+    /// The raw, architecture-specific syndrome.
+    pub syndrome: u64,
+}
+
+/// A function that is offered synchronous exceptions before the default handler.
+///
+/// If it returns true, the exception is considered handled, and execution resumes after the
+/// instruction that caused it.
+pub type SyncExceptionHook = fn(&SyncExceptionInfo) -> bool;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static SYNC_EXCEPTION_HOOK: IRQSafeNullLock<Option<SyncExceptionHook>> = IRQSafeNullLock::new(None);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Offer a synchronous exception to the hook. Returns true if the hook handled it.
+fn call_sync_exception_hook(info: &SyncExceptionInfo) -> bool {
+    match SYNC_EXCEPTION_HOOK.lock(|x| *x) {
+        None => false,
+        Some(hook) => hook(info),
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Install or remove the hook for synchronous exceptions.
+///
+/// Used by fault injection, see `fault_injection`.
+pub fn set_sync_exception_hook(hook: Option<SyncExceptionHook>) {
+    SYNC_EXCEPTION_HOOK.lock(|x| *x = hook);
+}
+
 //--------------------------------------------------------------------------------------------------
 // Testing
 //--------------------------------------------------------------------------------------------------
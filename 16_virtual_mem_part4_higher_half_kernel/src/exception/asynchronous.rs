@@ -8,7 +8,20 @@
 #[path = "../_arch/aarch64/exception/asynchronous.rs"]
 mod arch_asynchronous;
 
-use core::{fmt, marker::PhantomData};
+use crate::{
+    bsp, config, cpu,
+    exception::ExceptionContext,
+    log::RateLimiter,
+    state,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    trace, trace_event, warn, warn_rate_limited,
+};
+use core::{
+    fmt,
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
 
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
@@ -18,6 +31,19 @@ pub use arch_asynchronous::{
     print_state,
 };
 
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// How the calls of a handler went.
+struct IRQHandlerHealth {
+    num_handled: [AtomicUsize; config::cpu::NUM_CORES],
+    num_errors: AtomicUsize,
+    num_consecutive_errors: AtomicUsize,
+    last_error: IRQSafeNullLock<Option<&'static str>>,
+    masked: AtomicBool,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -32,6 +58,57 @@ pub struct IRQDescriptor {
     pub handler: &'static (dyn interface::IRQHandler + Sync),
 }
 
+/// A snapshot of a handler's `IRQHandlerHealth`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct IRQHandlerHealthReport {
+    /// The name of the IRQ.
+    pub name: &'static str,
+
+    /// The number of calls since boot, indexed by the id of the core that made them.
+    pub num_handled: [usize; config::cpu::NUM_CORES],
+
+    /// The number of failed calls since boot.
+    pub num_errors: usize,
+
+    /// The number of failed calls since the last successful one.
+    pub num_consecutive_errors: usize,
+
+    /// The error of the most recent failed call.
+    pub last_error: Option<&'static str>,
+
+    /// Whether the IRQ was masked because of the failures.
+    pub masked: bool,
+}
+
+/// An entry of an IRQ manager's handler table.
+pub struct IRQHandlerSlot {
+    descriptor: Option<IRQDescriptor>,
+    health: IRQHandlerHealth,
+}
+
+/// The outcome of `handle_irq()`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IRQOutcome {
+    /// The handler was called.
+    Handled,
+
+    /// There is no handler.
+    Unhandled,
+
+    /// The handler failed too many times in a row. The IRQ manager must mask the IRQ.
+    Mask,
+}
+
+/// Which cores the shared interrupts are routed to, see `config::exception::IRQ_TARGET_POLICY`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IRQTargetPolicy {
+    /// All to the boot core.
+    BootCore,
+
+    /// Each to one core, taking turns among the cores that checked in.
+    RoundRobin,
+}
+
 /// IRQContext token.
 ///
 /// An instance of this type indicates that the local core is currently executing in IRQ
@@ -50,7 +127,9 @@ pub mod interface {
     /// Implemented by types that handle IRQs.
     pub trait IRQHandler {
         /// Called when the corresponding interrupt is asserted.
-        fn handle(&self) -> Result<(), &'static str>;
+        ///
+        /// `context` is the context of the code that was interrupted.
+        fn handle(&self, context: &crate::exception::ExceptionContext) -> Result<(), &'static str>;
     }
 
     /// IRQ management functions.
@@ -71,32 +150,194 @@ pub mod interface {
         /// Enable an interrupt in the controller.
         fn enable(&self, irq_number: Self::IRQNumberType);
 
+        /// Disable an interrupt in the controller. Its handler stays registered.
+        fn disable(&self, irq_number: Self::IRQNumberType);
+
+        /// Route a shared interrupt to the cores in `core_mask`, which has one bit per core id.
+        ///
+        /// The mask must only name cores that checked in, see `check_target_cores()`. Private
+        /// interrupts always go to their own core and can not be routed.
+        fn set_target_cores(
+            &self,
+            irq_number: Self::IRQNumberType,
+            core_mask: usize,
+        ) -> Result<(), &'static str>;
+
+        /// Prepare the executing core's side of the controller to receive interrupts.
+        ///
+        /// The boot core is prepared by the controller's driver init. Secondary cores call this
+        /// before they unmask IRQs.
+        fn local_core_init(&self);
+
         /// Handle pending interrupts.
         ///
         /// This function is called directly from the CPU's IRQ exception vector. On AArch64,
         /// this means that the respective CPU core has disabled exception handling.
         /// This function can therefore not be preempted and runs start to finish.
         ///
-        /// Takes an IRQContext token to ensure it can only be called from IRQ context.
+        /// Takes an IRQContext token to ensure it can only be called from IRQ context. `context`
+        /// is passed on to the handlers.
         #[allow(clippy::trivially_copy_pass_by_ref)]
         fn handle_pending_irqs<'irq_context>(
             &'irq_context self,
             ic: &super::IRQContext<'irq_context>,
+            context: &crate::exception::ExceptionContext,
         );
 
         /// Print list of registered handlers.
         fn print_handler(&self);
+
+        /// Call `f` with the health of each registered handler.
+        fn handler_health(&self, f: &mut dyn FnMut(&super::IRQHandlerHealthReport));
     }
 }
 
 /// A wrapper type for IRQ numbers with integrated range sanity check.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Eq, PartialEq)]
 pub struct IRQNumber<const MAX_INCLUSIVE: usize>(usize);
 
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of IRQs taken, indexed by core id.
+static NUM_IRQS_TAKEN: [AtomicUsize; config::cpu::NUM_CORES] = [ZERO; config::cpu::NUM_CORES];
+
+/// Number of IRQs that are being handled, indexed by core id.
+static IRQ_NESTING_DEPTH: [AtomicUsize; config::cpu::NUM_CORES] = [ZERO; config::cpu::NUM_CORES];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_IRQ: IRQSafeNullLock<Option<&'static str>> = IRQSafeNullLock::new(None);
+
+/// Name of the most recently handled IRQ, indexed by core id.
+static LAST_IRQ_NAME: [IRQSafeNullLock<Option<&'static str>>; config::cpu::NUM_CORES] =
+    [NO_IRQ; config::cpu::NUM_CORES];
+
+static UNHANDLED_IRQ_WARNINGS: RateLimiter = RateLimiter::new(Duration::from_secs(1), 5);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl IRQHandlerHealth {
+    const fn new() -> Self {
+        Self {
+            num_handled: [ZERO; config::cpu::NUM_CORES],
+            num_errors: AtomicUsize::new(0),
+            num_consecutive_errors: AtomicUsize::new(0),
+            last_error: IRQSafeNullLock::new(None),
+            masked: AtomicBool::new(false),
+        }
+    }
+
+    /// Account for a call of the handler of the IRQ `name`. Returns true if the IRQ must be masked.
+    fn record(&self, name: &'static str, result: Result<(), &'static str>) -> bool {
+        let error = match result {
+            Ok(()) => {
+                self.num_consecutive_errors.store(0, Ordering::Relaxed);
+                return false;
+            }
+            Err(x) => x,
+        };
+
+        self.num_errors.fetch_add(1, Ordering::Relaxed);
+        self.last_error.lock(|x| *x = Some(error));
+        let consecutive = self.num_consecutive_errors.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if consecutive <= config::exception::IRQ_HANDLER_MAX_CONSECUTIVE_ERRORS
+            || self.masked.swap(true, Ordering::Relaxed)
+        {
+            return false;
+        }
+
+        warn!(
+            "IRQ handler {} failed {} times in a row, last with: {}",
+            name, consecutive, error
+        );
+        warn!(
+            "Masking IRQ {}. The driver will not get interrupts anymore",
+            name
+        );
+
+        true
+    }
+
+    fn report(&self, name: &'static str) -> IRQHandlerHealthReport {
+        let mut num_handled = [0; config::cpu::NUM_CORES];
+        for (x, y) in num_handled.iter_mut().zip(self.num_handled.iter()) {
+            *x = y.load(Ordering::Relaxed);
+        }
+
+        IRQHandlerHealthReport {
+            name,
+            num_handled,
+            num_errors: self.num_errors.load(Ordering::Relaxed),
+            num_consecutive_errors: self.num_consecutive_errors.load(Ordering::Relaxed),
+            last_error: self.last_error.lock(|x| *x),
+            masked: self.masked.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Report a pending IRQ that has no handler.
+///
+/// Such an IRQ is likely to fire again right away, so the warnings are rate limited.
+fn warn_unhandled_irq(kind: &str, irq_number: usize) {
+    warn_rate_limited!(
+        UNHANDLED_IRQ_WARNINGS,
+        "No handler registered for {}IRQ {}",
+        kind,
+        irq_number
+    );
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+impl IRQHandlerSlot {
+    /// Create an empty slot.
+    pub const fn new() -> Self {
+        Self {
+            descriptor: None,
+            health: IRQHandlerHealth::new(),
+        }
+    }
+
+    /// Register a handler in the slot.
+    pub fn register(&mut self, descriptor: IRQDescriptor) -> Result<(), &'static str> {
+        if self.descriptor.is_some() {
+            return Err("IRQ handler already registered");
+        }
+
+        self.descriptor = Some(descriptor);
+
+        Ok(())
+    }
+
+    /// The registered handler, if any.
+    pub fn descriptor(&self) -> Option<&IRQDescriptor> {
+        self.descriptor.as_ref()
+    }
+
+    /// The health of the registered handler, if any.
+    pub fn health(&self) -> Option<IRQHandlerHealthReport> {
+        self.descriptor.map(|x| self.health.report(x.name))
+    }
+}
+
+impl fmt::Display for IRQTargetPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            IRQTargetPolicy::BootCore => "Boot core",
+            IRQTargetPolicy::RoundRobin => "Round-robin",
+        })
+    }
+}
+
 impl<'irq_context> IRQContext<'irq_context> {
     /// Creates an IRQContext token.
     ///
@@ -134,6 +375,124 @@ impl<const MAX_INCLUSIVE: usize> fmt::Display for IRQNumber<{ MAX_INCLUSIVE }> {
     }
 }
 
+/// Account for an IRQ taken on the executing core.
+///
+/// Called from the CPU's IRQ exception vector, hence the IRQContext token.
+pub fn account_irq(_ic: &IRQContext) {
+    NUM_IRQS_TAKEN[cpu::core_id()].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Check a mask for `IRQManager::set_target_cores()`.
+///
+/// It must name at least one core, and only cores that checked in with the state manager.
+/// Interrupts routed to other cores would go unnoticed.
+pub fn check_target_cores(core_mask: usize) -> Result<(), &'static str> {
+    if core_mask == 0 {
+        return Err("No target core");
+    }
+
+    if (core_mask & !state::state_manager().cores_checked_in_mask()) != 0 {
+        return Err("Target core is not running");
+    }
+
+    Ok(())
+}
+
+/// The target of the `n`th shared interrupt under `policy`, as a mask for `set_target_cores()`.
+pub fn target_cores_by_policy(policy: IRQTargetPolicy, n: usize) -> usize {
+    let boot_core_mask = 1 << bsp::cpu::BOOT_CORE_ID;
+
+    match policy {
+        IRQTargetPolicy::BootCore => boot_core_mask,
+        IRQTargetPolicy::RoundRobin => {
+            let running = state::state_manager().cores_checked_in_mask();
+            let num_running = running.count_ones() as usize;
+            if num_running == 0 {
+                return boot_core_mask;
+            }
+
+            // The (n % num_running)th set bit of the mask.
+            let mut remaining = running;
+            for _ in 0..(n % num_running) {
+                remaining &= remaining - 1;
+            }
+
+            remaining & remaining.wrapping_neg()
+        }
+    }
+}
+
+/// Return the number of IRQs taken on the core with id `core_id` since boot.
+pub fn num_irqs_taken(core_id: usize) -> usize {
+    NUM_IRQS_TAKEN
+        .get(core_id)
+        .map_or(0, |x| x.load(Ordering::Relaxed))
+}
+
+/// Mark the executing core as handling an IRQ until the matching `irq_context_exit()`.
+///
+/// Called from the CPU's IRQ exception vector, hence the IRQContext token.
+pub fn irq_context_enter(_ic: &IRQContext) {
+    let depth = IRQ_NESTING_DEPTH[cpu::core_id()].fetch_add(1, Ordering::Relaxed) + 1;
+    trace_event!(trace::id::IRQ_ENTRY, depth);
+}
+
+/// Counterpart to `irq_context_enter()`.
+pub fn irq_context_exit(_ic: &IRQContext) {
+    let depth = IRQ_NESTING_DEPTH[cpu::core_id()].fetch_sub(1, Ordering::Relaxed) - 1;
+    trace_event!(trace::id::IRQ_EXIT, depth);
+}
+
+/// The number of IRQs that the executing core is handling. More than one if an IRQ handler was
+/// interrupted by another IRQ.
+pub fn irq_nesting_depth() -> usize {
+    IRQ_NESTING_DEPTH[cpu::core_id()].load(Ordering::Relaxed)
+}
+
+/// Whether the executing core is handling an IRQ.
+///
+/// Code that runs in IRQ context must not wait for long, since it delays all other IRQs of the
+/// core, and must not wait for anything that only the interrupted code can provide.
+pub fn in_irq_context() -> bool {
+    irq_nesting_depth() > 0
+}
+
+/// Call the handler in `slot`, and remember the IRQ as the executing core's most recent one.
+///
+/// A failure of the handler is recorded in the slot. `kind` and `irq_number` are only used for
+/// the warning about an IRQ without a handler.
+pub fn handle_irq(
+    _ic: &IRQContext,
+    slot: &IRQHandlerSlot,
+    kind: &str,
+    irq_number: usize,
+    context: &ExceptionContext,
+) -> IRQOutcome {
+    let descriptor = match slot.descriptor {
+        None => {
+            warn_unhandled_irq(kind, irq_number);
+            return IRQOutcome::Unhandled;
+        }
+        Some(x) => x,
+    };
+
+    let core_id = cpu::core_id();
+    LAST_IRQ_NAME[core_id].lock(|x| *x = Some(descriptor.name));
+    slot.health.num_handled[core_id].fetch_add(1, Ordering::Relaxed);
+
+    let result = descriptor.handler.handle(context);
+    if slot.health.record(descriptor.name, result) {
+        return IRQOutcome::Mask;
+    }
+
+    IRQOutcome::Handled
+}
+
+/// Return the name of the IRQ that was handled last on the core with id `core_id`.
+pub fn last_irq_name(core_id: usize) -> Option<&'static str> {
+    LAST_IRQ_NAME.get(core_id).and_then(|x| x.lock(|x| *x))
+}
+
 /// Executes the provided closure while IRQs are masked on the executing core.
 ///
 /// While the function temporarily changes the HW state of the executing core, it restores it to the
@@ -150,3 +509,114 @@ pub fn exec_with_irq_masked<T>(f: impl FnOnce() -> T) -> T {
 
     ret
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsp;
+    use core::sync::atomic::AtomicBool;
+    use test_macros::kernel_test;
+
+    static CALLBACK_DEPTH: AtomicUsize = AtomicUsize::new(0);
+    static CALLBACK_IN_IRQ_CONTEXT: AtomicBool = AtomicBool::new(false);
+
+    fn record_irq_context() {
+        CALLBACK_IN_IRQ_CONTEXT.store(in_irq_context(), Ordering::Relaxed);
+        CALLBACK_DEPTH.store(irq_nesting_depth(), Ordering::Release);
+    }
+
+    /// A handler is masked once it failed more than the configured number of times in a row, and
+    /// only then.
+    #[kernel_test]
+    fn failing_handler_is_masked() {
+        let health = IRQHandlerHealth::new();
+        let max = config::exception::IRQ_HANDLER_MAX_CONSECUTIVE_ERRORS;
+
+        for _ in 0..max {
+            assert!(!health.record("Test", Err("Failed")));
+        }
+        assert!(!health.record("Test", Ok(())));
+        assert_eq!(health.report("Test").num_consecutive_errors, 0);
+
+        for _ in 0..max {
+            assert!(!health.record("Test", Err("Failed")));
+        }
+        assert!(health.record("Test", Err("Failed again")));
+        assert!(!health.record("Test", Err("Failed again")));
+
+        assert_eq!(
+            health.report("Test"),
+            IRQHandlerHealthReport {
+                name: "Test",
+                num_handled: [0; config::cpu::NUM_CORES],
+                num_errors: 2 * max + 2,
+                num_consecutive_errors: max + 2,
+                last_error: Some("Failed again"),
+                masked: true,
+            }
+        );
+    }
+
+    /// Targets name exactly one running core per IRQ, and cores that do not run are rejected.
+    #[kernel_test]
+    fn irq_targets_are_checked() {
+        let running = state::state_manager().cores_checked_in_mask();
+        let boot_core_mask = 1 << bsp::cpu::BOOT_CORE_ID;
+
+        assert_eq!(check_target_cores(0), Err("No target core"));
+        assert!(check_target_cores(!running).is_err());
+
+        for n in 0..(2 * config::cpu::NUM_CORES) {
+            assert_eq!(
+                target_cores_by_policy(IRQTargetPolicy::BootCore, n),
+                boot_core_mask
+            );
+
+            let mask = target_cores_by_policy(IRQTargetPolicy::RoundRobin, n);
+            assert_eq!(mask.count_ones(), 1);
+            assert!(mask & (running | boot_core_mask) != 0);
+        }
+    }
+
+    /// Check that the test itself does not run in IRQ context.
+    #[kernel_test]
+    fn not_in_irq_context_in_kernel_code() {
+        assert!(!in_irq_context());
+        assert_eq!(irq_nesting_depth(), 0);
+    }
+
+    /// Check that a timer callback, which is called by the timer's IRQ handler, runs in IRQ
+    /// context.
+    #[kernel_test]
+    fn in_irq_context_in_timer_callback() {
+        CALLBACK_DEPTH.store(0, Ordering::Relaxed);
+
+        // Replaces the test runner's timeout, which is disarmed after the test anyway.
+        bsp::timeout_timer()
+            .arm(Duration::from_millis(1), record_irq_context)
+            .unwrap();
+
+        // Unit tests run with IRQs masked.
+        let called = unsafe {
+            let saved = local_irq_mask_save();
+            local_irq_unmask();
+
+            let called = cpu::spin_until(
+                || CALLBACK_DEPTH.load(Ordering::Acquire) != 0,
+                Some(Duration::from_secs(1)),
+            );
+
+            local_irq_restore(saved);
+            called
+        };
+
+        assert!(called);
+        assert!(CALLBACK_IN_IRQ_CONTEXT.load(Ordering::Relaxed));
+        assert_eq!(CALLBACK_DEPTH.load(Ordering::Relaxed), 1);
+        assert!(!in_irq_context());
+    }
+}
@@ -0,0 +1,247 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Core dump over the console.
+//!
+//! The dump goes out through the emergency console, so it works from an exception handler and
+//! with a wedged console driver. It needs no allocation. The format is meant for
+//! `core_dump_tool/main.rb` on the host:
+//!
+//! ```text
+//! === CORE DUMP BEGIN v1 core=<id> ===
+//! @<section> addr=<hex> len=<dec> crc=<hex>
+//! <hex bytes, 32 per line>
+//! ...
+//! === CORE DUMP END ===
+//! ```
+//!
+//! The CRC is the IEEE CRC-32 of a section's bytes. Sections, all little endian:
+//!
+//! - `REGS`: The registers, one u64 each, x0 to x29, lr, ELR_EL1,
+//!   SPSR_EL1, SP_EL0, the interrupted stack pointer, ESR_EL1 and FAR_EL1.
+//! - `STACK`: The interrupted stack from the stack pointer upwards. `addr` is the stack pointer.
+//! - `MAPS`: One record per kernel mapping: virtual start, physical start, size and attributes,
+//!   one u64 each. Attribute bit 0 is device memory, bit 1 writable, bit 2 execute-never.
+//! - `IRQS`: The IRQs taken per core, then the executing core's IRQ nesting depth, one u64 each.
+
+use crate::{
+    bsp, common, config,
+    exception::asynchronous,
+    memory::{
+        mmu::{self, AccessPermissions, MemAttributes, TranslationDescriptor},
+        Address,
+    },
+};
+use core::fmt::{self, Write};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Format version, bumped on incompatible changes.
+const VERSION: usize = 1;
+
+/// Bytes of the interrupted stack that are dumped at most.
+const MAX_STACK_BYTES: usize = 4096;
+
+/// Bytes per line of hex.
+const BYTES_PER_LINE: usize = 32;
+
+/// Size of a record in the `MAPS` section.
+const MAP_RECORD_SIZE: usize = 4 * 8;
+
+/// Writes the framed sections.
+struct Dumper<W: Write> {
+    out: W,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl<W: Write> Dumper<W> {
+    /// Write a section whose bytes are produced in pieces by `for_each_piece`. The pieces are
+    /// visited twice, once for the CRC and once for the output, so that nothing is buffered.
+    fn section(
+        &mut self,
+        name: &str,
+        addr: usize,
+        for_each_piece: impl Fn(&mut dyn FnMut(&[u8])),
+    ) -> fmt::Result {
+        let mut len = 0;
+        let mut crc = 0;
+        for_each_piece(&mut |piece: &[u8]| {
+            len += piece.len();
+            crc = common::crc32_update(crc, piece);
+        });
+
+        writeln!(
+            self.out,
+            "@{} addr={:#x} len={} crc={:08x}",
+            name, addr, len, crc
+        )?;
+
+        let mut column = 0;
+        let mut result = Ok(());
+        for_each_piece(&mut |piece: &[u8]| {
+            for b in piece {
+                if result.is_err() {
+                    return;
+                }
+
+                result = write!(self.out, "{:02x}", b);
+                column += 1;
+                if column == BYTES_PER_LINE {
+                    column = 0;
+                    result = result.and_then(|_| writeln!(self.out));
+                }
+            }
+        });
+        result?;
+
+        if column != 0 {
+            writeln!(self.out)?;
+        }
+
+        Ok(())
+    }
+
+    fn words(&mut self, name: &str, words: &[u64]) -> fmt::Result {
+        self.section(name, 0, |f| {
+            for w in words {
+                f(&w.to_le_bytes())
+            }
+        })
+    }
+}
+
+/// The `MAPS` record of a mapping.
+fn map_record(x: &TranslationDescriptor) -> [u8; MAP_RECORD_SIZE] {
+    let mut attr = 0u64;
+    if x.attribute_fields.mem_attributes == MemAttributes::Device {
+        attr |= 1 << 0;
+    }
+    if let AccessPermissions::ReadWrite = x.attribute_fields.acc_perms {
+        attr |= 1 << 1;
+    }
+    if x.attribute_fields.execute_never {
+        attr |= 1 << 2;
+    }
+
+    let mut record = [0; MAP_RECORD_SIZE];
+    let fields = [
+        x.virt_pages.start_addr().into_usize() as u64,
+        x.phys_pages.start_addr().into_usize() as u64,
+        x.virt_pages.size() as u64,
+        attr,
+    ];
+    for (chunk, field) in record.chunks_exact_mut(8).zip(fields.iter()) {
+        chunk.copy_from_slice(&field.to_le_bytes());
+    }
+
+    record
+}
+
+fn write_dump(out: impl Write, registers: &[u64], stack_pointer: usize) -> fmt::Result {
+    let mut dumper = Dumper { out };
+
+    writeln!(
+        dumper.out,
+        "\n=== CORE DUMP BEGIN v{} core={} ===",
+        VERSION,
+        crate::cpu::core_id()
+    )?;
+
+    dumper.words("REGS", registers)?;
+
+    // Only the stack's mapped part is dumped, and no more than the bound.
+    if let Some(x) = mmu::kernel_find_mapping(Address::new(stack_pointer)) {
+        let end = x.virt_pages.end_addr().into_usize();
+        let len = (end - stack_pointer).min(MAX_STACK_BYTES);
+        let stack = unsafe { core::slice::from_raw_parts(stack_pointer as *const u8, len) };
+
+        dumper.section("STACK", stack_pointer, |f| f(stack))?;
+    }
+
+    dumper.section("MAPS", 0, |f| {
+        mmu::kernel_for_each_mapping(|x| f(&map_record(x)))
+    })?;
+
+    let mut irqs = [0u64; config::cpu::NUM_CORES + 1];
+    for (core_id, x) in irqs.iter_mut().take(config::cpu::NUM_CORES).enumerate() {
+        *x = asynchronous::num_irqs_taken(core_id) as u64;
+    }
+    irqs[config::cpu::NUM_CORES] = asynchronous::irq_nesting_depth() as u64;
+    dumper.words("IRQS", &irqs)?;
+
+    writeln!(dumper.out, "=== CORE DUMP END ===")
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Emit a core dump of the executing core through the emergency console.
+///
+/// `registers` are the values of the interrupted context, and `stack_pointer` is its stack pointer.
+pub fn write(registers: &[u64], stack_pointer: usize) {
+    let out = unsafe { bsp::console::panic_emergency_console_out() };
+
+    // There is nowhere to report a failing console to.
+    let _ = write_dump(out, registers, stack_pointer);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Collects the output in a fixed buffer.
+    struct Buffer {
+        data: [u8; 128],
+        len: usize,
+    }
+
+    impl Write for Buffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            if end > self.data.len() {
+                return Err(fmt::Error);
+            }
+
+            self.data[self.len..end].copy_from_slice(s.as_bytes());
+            self.len = end;
+
+            Ok(())
+        }
+    }
+
+    /// Check the framing of a section that is produced in two pieces.
+    #[kernel_test]
+    fn sections_are_framed() {
+        let mut dumper = Dumper {
+            out: Buffer {
+                data: [0; 128],
+                len: 0,
+            },
+        };
+
+        dumper
+            .section("TEST", 0x10, |f| {
+                f(b"1234");
+                f(b"56789");
+            })
+            .unwrap();
+
+        let out = core::str::from_utf8(&dumper.out.data[..dumper.out.len]).unwrap();
+        assert_eq!(
+            out,
+            "@TEST addr=0x10 len=9 crc=cbf43926\n313233343536373839\n"
+        );
+    }
+}
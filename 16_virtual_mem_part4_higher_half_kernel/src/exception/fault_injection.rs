@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Fault injection.
+//!
+//! Each helper causes one specific synchronous exception and returns what the exception handler
+//! decoded, so that tests can check the fault class and address directly instead of matching on
+//! the panic output. While a helper runs, a hook in the synchronous exception handler captures the
+//! exception and resumes execution after the faulting instruction.
+//!
+//! Besides the tests, the monitor's `fault` command uses the helpers to demonstrate the exception
+//! path on real hardware.
+//!
+//! ```
+//! let info = exception::fault_injection::trigger_permission_fault().unwrap();
+//! assert_eq!(info.class, exception::FaultClass::Permission);
+//! ```
+
+#[cfg(target_arch = "aarch64")]
+#[path = "../_arch/aarch64/exception/fault_injection.rs"]
+mod arch_fault_injection;
+
+use crate::{
+    bsp, exception,
+    exception::SyncExceptionInfo,
+    memory::{
+        mmu,
+        mmu::{AccessPermissions, AttributeFields, MemAttributes, PageSliceDescriptor},
+        Address, Virtual,
+    },
+    synchronization::{interface::Mutex, interface::ReadWriteEx, IRQSafeNullLock, InitStateLock},
+};
+use core::ptr;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Lives in `.rodata`, which is mapped read-only.
+static RODATA_TARGET: u64 = 0;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// The exception captured by the hook.
+static CAPTURED: IRQSafeNullLock<Option<SyncExceptionInfo>> = IRQSafeNullLock::new(None);
+
+/// A page mapped with device memory attributes.
+static DEVICE_PAGE: InitStateLock<Option<Address<Virtual>>> = InitStateLock::new(None);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Capture the first exception, and leave any further ones to the default handler.
+fn capture_hook(info: &SyncExceptionInfo) -> bool {
+    CAPTURED.lock(|x| {
+        if x.is_some() {
+            return false;
+        }
+
+        *x = Some(*info);
+        true
+    })
+}
+
+/// Recurse until the stack runs into its guard page.
+///
+/// Each level keeps a frame's worth of data alive across the call, so that neither the frame nor
+/// the call can be optimized away.
+#[inline(never)]
+fn recurse(depth: usize) -> usize {
+    let frame = [depth; 64];
+
+    let x = unsafe { ptr::read_volatile(&frame[depth % frame.len()]) };
+
+    x + recurse(depth + 1)
+}
+
+/// Run `f` with the hook installed, and return the captured exception.
+fn capture(f: impl FnOnce()) -> Result<SyncExceptionInfo, &'static str> {
+    CAPTURED.lock(|x| *x = None);
+    exception::set_sync_exception_hook(Some(capture_hook));
+
+    f();
+
+    exception::set_sync_exception_hook(None);
+    CAPTURED
+        .lock(|x| x.take())
+        .ok_or("No synchronous exception was taken")
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Map the page used by `trigger_alignment_fault_on_device()`.
+///
+/// Only tests call this. Other users can hand any device memory to `trigger_alignment_fault_at()`.
+///
+/// The last page of the unused DRAM is mapped a second time, with device memory attributes.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init.
+/// - The last page of the unused DRAM must not be used otherwise.
+pub unsafe fn init() -> Result<(), &'static str> {
+    let attr = AttributeFields {
+        mem_attributes: MemAttributes::Device,
+        acc_perms: AccessPermissions::ReadWrite,
+        execute_never: true,
+    };
+
+    let phys_unused = bsp::memory::mmu::phys_unused_dram_page_desc();
+    let phys_page = PageSliceDescriptor::from_addr(
+        phys_unused.end_addr() - bsp::memory::mmu::KernelGranule::SIZE,
+        1,
+    );
+    let virt_page = mmu::kernel_alloc_window(1)?;
+    mmu::kernel_map_window(&virt_page, &phys_page, &attr)?;
+
+    DEVICE_PAGE.write(|x| *x = Some(virt_page.start_addr()));
+
+    Ok(())
+}
+
+/// Read from the boot core's stack guard page, which is never mapped.
+pub fn trigger_translation_fault() -> Result<SyncExceptionInfo, &'static str> {
+    let addr = bsp::memory::mmu::virt_boot_core_stack_guard_page_desc().start_addr();
+
+    capture(|| unsafe { arch_fault_injection::load(addr) })
+}
+
+/// Write to a static in the read-only mapped `.rodata`.
+pub fn trigger_permission_fault() -> Result<SyncExceptionInfo, &'static str> {
+    let addr = Address::new(&RODATA_TARGET as *const _ as usize);
+
+    capture(|| unsafe { arch_fault_injection::store(addr) })
+}
+
+/// Do a misaligned read from device memory. Needs `init()`.
+pub fn trigger_alignment_fault_on_device() -> Result<SyncExceptionInfo, &'static str> {
+    let addr = DEVICE_PAGE
+        .read(|x| *x)
+        .ok_or("Fault injection not initialized")?;
+
+    trigger_alignment_fault_at(addr)
+}
+
+/// Do a misaligned read from the device memory at `device_addr`.
+///
+/// The alignment is checked before the access is made, so the device does not see the read.
+pub fn trigger_alignment_fault_at(
+    device_addr: Address<Virtual>,
+) -> Result<SyncExceptionInfo, &'static str> {
+    let attr = mmu::kernel_find_mapping(device_addr)
+        .ok_or("Address is not in a recorded mapping")?
+        .attribute_fields;
+    if attr.mem_attributes != MemAttributes::Device {
+        return Err("Address is not device memory");
+    }
+
+    capture(|| unsafe { arch_fault_injection::load(device_addr.align_down(4) + 1) })
+}
+
+/// Execute a breakpoint instruction.
+pub fn trigger_brk() -> Result<SyncExceptionInfo, &'static str> {
+    capture(arch_fault_injection::brk)
+}
+
+/// Execute a supervisor call.
+pub fn trigger_svc() -> Result<SyncExceptionInfo, &'static str> {
+    capture(arch_fault_injection::svc)
+}
+
+/// Overflow the executing core's stack into its guard page.
+///
+/// The hook is not installed, so the exception reaches the default handler, which panics.
+pub fn trigger_stack_overflow() -> ! {
+    recurse(0);
+
+    unreachable!("The stack did not overflow")
+}
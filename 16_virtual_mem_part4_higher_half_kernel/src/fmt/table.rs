@@ -0,0 +1,411 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Tables with aligned columns.
+//!
+//! The cells are rendered into a fixed buffer as the rows are added, and the width of each column
+//! is updated on the way. Printing then only pads the buffered cells, so that each cell is formatted
+//! exactly once. Cells that do not fit into the buffer are cut short, rows beyond the capacity are
+//! dropped, and the table says so in its last line.
+//!
+//! ```
+//! let mut table = Table::new([Column::left("Handler"), Column::right("Errors")]);
+//!
+//! table.row(|r| {
+//!     r.cell("BCM PL011 UART").cell(0);
+//! });
+//! table.print();
+//! ```
+
+use crate::{
+    log::{self, Level},
+    print::Block,
+};
+use core::{
+    fmt::{self, Write},
+    iter,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The size of the buffer that holds the text of all cells.
+const BUFFER_SIZE: usize = 4096;
+
+/// The number of cells that a table can hold.
+const MAX_CELLS: usize = 256;
+
+const COLUMN_SEPARATOR: &str = "  ";
+
+/// A cell's text in the buffer.
+#[derive(Copy, Clone)]
+struct Cell {
+    start: usize,
+    len: usize,
+}
+
+/// Appends to the buffer, and cuts the text short at a character boundary when it is full.
+struct CellWriter<'a> {
+    buffer: &'a mut [u8; BUFFER_SIZE],
+    len: &'a mut usize,
+    is_truncated: bool,
+}
+
+enum LineKind {
+    Header,
+    Separator,
+    Row(usize),
+    Truncated,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The alignment of a column's cells.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// A column's header and alignment.
+#[derive(Copy, Clone)]
+pub struct Column {
+    header: &'static str,
+    align: Align,
+}
+
+/// A table with `N` columns.
+pub struct Table<const N: usize> {
+    columns: [Column; N],
+    widths: [usize; N],
+    buffer: [u8; BUFFER_SIZE],
+    buffer_len: usize,
+    cells: [Cell; MAX_CELLS],
+    num_cells: usize,
+    is_truncated: bool,
+}
+
+/// The row that is being added. See `Table::row()`.
+pub struct Row<'a, const N: usize> {
+    table: &'a mut Table<N>,
+    column: usize,
+}
+
+/// A line of the rendered table, without a newline.
+pub struct Line<'a, const N: usize> {
+    table: &'a Table<N>,
+    kind: LineKind,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Write for CellWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let space = BUFFER_SIZE - *self.len;
+
+        let mut n = s.len().min(space);
+        while !s.is_char_boundary(n) {
+            n -= 1;
+        }
+
+        self.buffer[*self.len..(*self.len + n)].copy_from_slice(&s.as_bytes()[..n]);
+        *self.len += n;
+
+        if n < s.len() {
+            self.is_truncated = true;
+            return Err(fmt::Error);
+        }
+
+        Ok(())
+    }
+}
+
+impl<const N: usize> Table<N> {
+    /// The number of complete rows.
+    fn num_rows(&self) -> usize {
+        self.num_cells / N
+    }
+
+    /// The text of the cell in `row` and `column`.
+    fn cell_text(&self, row: usize, column: usize) -> &str {
+        let cell = self.cells[row * N + column];
+
+        // Cells are only cut at character boundaries.
+        core::str::from_utf8(&self.buffer[cell.start..(cell.start + cell.len)]).unwrap_or("?")
+    }
+
+    /// Write one line, with each column padded to its width. The last column is not padded on the
+    /// right, so that lines do not end in spaces.
+    fn write_line<'a>(
+        &'a self,
+        f: &mut fmt::Formatter,
+        mut text: impl FnMut(usize) -> &'a str,
+    ) -> fmt::Result {
+        for (i, (column, width)) in self.columns.iter().zip(self.widths.iter()).enumerate() {
+            let text = text(i);
+            let padding = width.saturating_sub(text.chars().count());
+            let is_last = i == N - 1;
+
+            if i > 0 {
+                f.write_str(COLUMN_SEPARATOR)?;
+            }
+
+            match column.align {
+                Align::Left => {
+                    f.write_str(text)?;
+                    if !is_last {
+                        write!(f, "{:1$}", "", padding)?;
+                    }
+                }
+                Align::Right => {
+                    write!(f, "{:1$}", "", padding)?;
+                    f.write_str(text)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Column {
+    /// A left-aligned column.
+    pub const fn left(header: &'static str) -> Self {
+        Self {
+            header,
+            align: Align::Left,
+        }
+    }
+
+    /// A right-aligned column.
+    pub const fn right(header: &'static str) -> Self {
+        Self {
+            header,
+            align: Align::Right,
+        }
+    }
+}
+
+impl<const N: usize> Table<N> {
+    /// Create an empty table.
+    pub fn new(columns: [Column; N]) -> Self {
+        let mut widths = [0; N];
+        for (width, column) in widths.iter_mut().zip(columns.iter()) {
+            *width = column.header.chars().count();
+        }
+
+        Self {
+            columns,
+            widths,
+            buffer: [0; BUFFER_SIZE],
+            buffer_len: 0,
+            cells: [Cell { start: 0, len: 0 }; MAX_CELLS],
+            num_cells: 0,
+            is_truncated: false,
+        }
+    }
+
+    /// Add a row. `f` adds the cells from left to right. Cells that `f` does not add are empty, and
+    /// cells beyond the last column are ignored.
+    pub fn row(&mut self, f: impl FnOnce(&mut Row<'_, N>)) {
+        if self.num_cells + N > MAX_CELLS {
+            self.is_truncated = true;
+            return;
+        }
+
+        let mut row = Row {
+            table: self,
+            column: 0,
+        };
+        f(&mut row);
+
+        while row.column < N {
+            row.cell("");
+        }
+    }
+
+    /// The lines of the table: the headers, a separator, the rows, and a note if the table was
+    /// truncated.
+    pub fn lines(&self) -> impl Iterator<Item = Line<'_, N>> {
+        let rows = (0..self.num_rows()).map(LineKind::Row);
+        let truncated = if self.is_truncated {
+            Some(LineKind::Truncated)
+        } else {
+            None
+        };
+
+        iter::once(LineKind::Header)
+            .chain(iter::once(LineKind::Separator))
+            .chain(rows)
+            .chain(truncated)
+            .map(move |kind| Line { table: self, kind })
+    }
+
+    /// Print the table as indented info messages. The lines are printed together, so that output
+    /// of other cores can not end up in between.
+    pub fn print(&self) {
+        if !log::is_enabled(Level::Info, module_path!()) {
+            return;
+        }
+
+        let mut block = Block::with_level(Level::Info);
+        for line in self.lines() {
+            block.line(format_args!("      {}", line));
+        }
+        block.print();
+    }
+}
+
+impl<const N: usize> Row<'_, N> {
+    /// Add a cell.
+    pub fn cell(&mut self, value: impl fmt::Display) -> &mut Self {
+        if self.column >= N {
+            return self;
+        }
+
+        let table = &mut *self.table;
+        let start = table.buffer_len;
+        let mut writer = CellWriter {
+            buffer: &mut table.buffer,
+            len: &mut table.buffer_len,
+            is_truncated: false,
+        };
+
+        // An error is only returned if the buffer is full, which is recorded in the writer.
+        let _ = write!(writer, "{}", value);
+        if writer.is_truncated {
+            table.is_truncated = true;
+        }
+
+        let cell = Cell {
+            start,
+            len: table.buffer_len - start,
+        };
+        table.cells[table.num_cells] = cell;
+        table.num_cells += 1;
+
+        let width = core::str::from_utf8(&table.buffer[start..table.buffer_len])
+            .map_or(cell.len, |x| x.chars().count());
+        table.widths[self.column] = table.widths[self.column].max(width);
+
+        self.column += 1;
+        self
+    }
+}
+
+impl<const N: usize> fmt::Display for Line<'_, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let table = self.table;
+
+        match self.kind {
+            LineKind::Header => table.write_line(f, |i| table.columns[i].header),
+            LineKind::Separator => {
+                for (i, width) in table.widths.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(COLUMN_SEPARATOR)?;
+                    }
+                    for _ in 0..*width {
+                        f.write_char('-')?;
+                    }
+                }
+
+                Ok(())
+            }
+            LineKind::Row(row) => table.write_line(f, |i| table.cell_text(row, i)),
+            LineKind::Truncated => f.write_str("(table truncated)"),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Writes formatted output into a fixed buffer.
+    struct Buffer {
+        data: [u8; 256],
+        len: usize,
+    }
+
+    impl fmt::Write for Buffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            self.data
+                .get_mut(self.len..end)
+                .ok_or(fmt::Error)?
+                .copy_from_slice(s.as_bytes());
+            self.len = end;
+
+            Ok(())
+        }
+    }
+
+    /// Check the rendering of a table with left and right aligned columns.
+    #[kernel_test]
+    fn table_rendering_works() {
+        let mut table = Table::new([
+            Column::right("#"),
+            Column::left("Name"),
+            Column::right("Count"),
+            Column::left("Note"),
+        ]);
+        table.row(|r| {
+            r.cell(1).cell("UART").cell(12345).cell("ok");
+        });
+        table.row(|r| {
+            r.cell(2).cell("Interrupt controller").cell(0);
+        });
+
+        let mut buffer = Buffer {
+            data: [0; 256],
+            len: 0,
+        };
+        for line in table.lines() {
+            writeln!(buffer, "{}", line).unwrap();
+        }
+
+        let expected = "\
+#  Name                  Count  Note
+-  --------------------  -----  ----
+1  UART                  12345  ok
+2  Interrupt controller      0
+";
+        assert_eq!(
+            core::str::from_utf8(&buffer.data[..buffer.len]),
+            Ok(expected)
+        );
+    }
+
+    /// Check that rows beyond the capacity are dropped and reported.
+    #[kernel_test]
+    fn table_truncation_works() {
+        let mut table = Table::new([Column::left("A"), Column::left("B")]);
+        for _ in 0..(MAX_CELLS / 2 + 1) {
+            table.row(|r| {
+                r.cell("x").cell("y");
+            });
+        }
+
+        assert_eq!(table.num_rows(), MAX_CELLS / 2);
+        assert!(matches!(
+            table.lines().last().map(|x| x.kind),
+            Some(LineKind::Truncated)
+        ));
+    }
+}
@@ -47,7 +47,7 @@
 //!
 //! Just like processor architecture code, the `BSP` code's module structure tries to mirror the
 //! `kernel`'s subsystem modules, but there is no reexporting this time. That means whatever is
-//! provided must be called starting from the `bsp` namespace, e.g. `bsp::driver::driver_manager()`.
+//! provided must be called starting from the `bsp` namespace, e.g. `bsp::driver::init()`.
 //!
 //! ## Kernel interfaces
 //!
@@ -105,11 +105,16 @@
 //! 1. The kernel's entry point is the function `cpu::boot::arch_boot::_start()`.
 //!     - It is implemented in `src/_arch/__arch_name__/cpu/boot.s`.
 //! 2. Once finished with architectural setup, the arch code calls [`runtime_init::runtime_init()`].
+//! 3. Secondary cores are released later by [`cpu::smp::boot_secondary_cores()`]. They enter at
+//!    `cpu::boot::arch_boot::_start_secondary()` and continue in
+//!    `runtime_init::secondary_runtime_init()`.
 //!
 //! [`runtime_init::runtime_init()`]: runtime_init/fn.runtime_init.html
+//! [`cpu::smp::boot_secondary_cores()`]: cpu/smp/fn.boot_secondary_cores.html
 
 #![allow(clippy::upper_case_acronyms)]
 #![allow(incomplete_features)]
+#![feature(alloc_error_handler)]
 #![feature(asm)]
 #![feature(const_evaluatable_checked)]
 #![feature(const_fn)]
@@ -129,20 +134,39 @@
 #![reexport_test_harness_main = "test_main"]
 #![test_runner(crate::test_runner)]
 
+extern crate alloc;
+
 mod panic_wait;
 mod runtime_init;
 mod synchronization;
 
+pub mod banner;
+pub mod bench;
 pub mod bsp;
+pub mod cmdline;
 pub mod common;
+pub mod config;
 pub mod console;
 pub mod cpu;
 pub mod driver;
+pub mod dtb;
+pub mod error;
 pub mod exception;
+pub mod fmt;
+pub mod loader;
+pub mod log;
 pub mod memory;
+pub mod monitor;
+pub mod panic_record;
 pub mod print;
+pub mod selftest;
+pub mod shutdown;
 pub mod state;
+#[cfg(feature = "test_build")]
+pub mod test_infra;
 pub mod time;
+pub mod trace;
+pub mod units;
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
@@ -161,18 +185,63 @@ pub fn version() -> &'static str {
 // Testing
 //--------------------------------------------------------------------------------------------------
 
+/// How long a single test may run before it is failed.
+///
+/// Shorter than the time `tests/runner.rb` waits for output, so that a hanging test is reported as
+/// such. The timeout is an IRQ, so it only fires in tests that unmask IRQs. Tests that hang with
+/// IRQs masked are only caught by the runner script.
+const TEST_TIMEOUT: core::time::Duration = core::time::Duration::from_secs(3);
+
+/// Called in IRQ context if a test exceeds `TEST_TIMEOUT`.
+fn test_timed_out() {
+    println!("[timeout]");
+    println!("      Test did not finish within {:?}", TEST_TIMEOUT);
+
+    shutdown::kernel_shutdown(shutdown::ShutdownReason::TestTimeout)
+}
+
 /// The default runner for unit tests.
+///
+/// A `should_panic` test ends the kernel from the panic handler, so it is run after all other tests.
+/// There can be only one per test binary, which `#[kernel_test]` checks at compile time.
 pub fn test_runner(tests: &[&test_types::UnitTest]) {
+    use time::interface::TimeManager;
+
+    let ordered = tests
+        .iter()
+        .filter(|x| x.should_panic.is_none())
+        .chain(tests.iter().filter(|x| x.should_panic.is_some()));
+
     println!("Running {} tests", tests.len());
     println!("-------------------------------------------------------------------\n");
-    for (i, test) in tests.iter().enumerate() {
+    for (i, test) in ordered.enumerate() {
         print!("{:>3}. {:.<58}", i + 1, test.name);
 
+        // The timeout only fires if the board's timeout timer IRQ was brought up.
+        if let Err(x) = bsp::timeout_timer().arm(TEST_TIMEOUT, test_timed_out) {
+            println!("[failed]");
+            error::fatal("Arming the test timeout", error::KernelError::Time(x));
+        }
+        panic_wait::set_expected_panic(test.should_panic);
+
         // Run the actual test.
+        let start = time::time_manager().uptime();
         (test.test_func)();
+        let elapsed = time::time_manager().uptime() - start;
+
+        bsp::timeout_timer().disarm();
+        panic_wait::set_expected_panic(None);
+
+        if let Some(x) = test.should_panic {
+            println!("[failed]");
+            panic!(
+                "Test did not panic. Expected a message containing \"{}\"",
+                x
+            );
+        }
 
         // Failed tests call panic!(). Execution reaches here only if the test has passed.
-        println!("[ok]")
+        println!("[ok] {:>9} us", elapsed.as_micros())
     }
 }
 
@@ -181,9 +250,18 @@ pub fn test_runner(tests: &[&test_types::UnitTest]) {
 #[no_mangle]
 unsafe fn kernel_init() -> ! {
     exception::handling_init();
+    if let Err(x) = memory::mmu::kernel_write_protect_tables() {
+        warn!("Translation tables stay writable: {}", x);
+    }
     bsp::console::qemu_bring_up_console();
 
+    // Enable the per-test timeout. Without it, only the runner script catches hanging tests. IRQs
+    // stay masked, tests that need them unmask them.
+    if let Err(x) = bsp::driver::qemu_bring_up_timeout_timer() {
+        warn!("Test timeout not available: {}", x);
+    }
+
     test_main();
 
-    cpu::qemu_exit_success()
+    shutdown::kernel_shutdown(shutdown::ShutdownReason::Success)
 }
@@ -0,0 +1,311 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! UART chainloader.
+//!
+//! Receives a binary image over the console and boots it, like the chainloader of tutorial 06, but
+//! from within the running kernel. The protocol is the one of `utils/minipush.rb`, extended by a
+//! checksum:
+//!
+//! 1. The kernel requests the image by sending three `0x03` bytes.
+//! 2. The host sends the image's size as a 4-byte little-endian number.
+//! 3. The kernel answers `OK` if the image fits into the destination, and `NO` otherwise.
+//! 4. The host sends the image.
+//! 5. The host sends the image's CRC-32 as a 4-byte little-endian number.
+//! 6. The kernel answers `OK` if the checksum matches, and `ER` otherwise.
+//!
+//! Images are received into the staging area, a range of otherwise unused DRAM that is mapped
+//! during kernel init. Since the kernel tables can't be changed after init, this is the only memory
+//! that the loader can write to at runtime.
+//!
+//! Booting an image turns off the MMU and the caches, and enters the image at its first byte in
+//! EL1. Images that insist on being entered in EL2, like the tutorial kernels, can not be booted.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "_arch/aarch64/loader.rs"]
+mod arch_loader;
+
+use crate::{
+    bsp, common, console, exception,
+    memory::{
+        mmu,
+        mmu::{AccessPermissions, AttributeFields, MemAttributes, PageSliceDescriptor},
+        Address, Physical, Virtual,
+    },
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+    warn,
+};
+use core::{fmt, time::Duration};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Size of the staging area in pages.
+const STAGING_NUM_PAGES: usize = 256;
+
+/// How long to wait for the host to answer the image request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for each chunk of the transfer.
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(1);
+
+const CHUNK_SIZE: usize = 512;
+
+const REQUEST_TOKEN: [u8; 3] = [3, 3, 3];
+
+#[derive(Copy, Clone)]
+struct StagingArea {
+    virt_pages: PageSliceDescriptor<Virtual>,
+    phys_pages: PageSliceDescriptor<Physical>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Loader error variants.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LoadError {
+    /// `init()` was not called or failed.
+    NotInitialized,
+
+    /// The destination is outside of the staging area.
+    InvalidDestination,
+
+    /// The image does not fit between the destination and the end of the staging area.
+    TooLarge,
+
+    /// The host stopped sending.
+    Timeout,
+
+    /// The received image does not match the host's checksum.
+    ChecksumMismatch,
+}
+
+/// An image that was received completely and intact.
+#[derive(Copy, Clone)]
+pub struct LoadedImage {
+    phys_start: Address<Physical>,
+    size: usize,
+    checksum: u32,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static STAGING_AREA: InitStateLock<Option<StagingArea>> = InitStateLock::new(None);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Run the protocol once. IRQs must be masked, so that the UART's RX handler does not consume the
+/// image.
+fn load_once(staging: StagingArea, dest: Address<Physical>) -> Result<LoadedImage, LoadError> {
+    use console::interface::{Read, Write};
+
+    let offset = dest.into_usize() - staging.phys_pages.start_addr().into_usize();
+    let capacity = staging.phys_pages.size() - offset;
+    let console = bsp::console::console();
+
+    // Discard any spurious received characters before starting with the protocol.
+    console.flush();
+    console.clear_rx();
+    console.write_array(&REQUEST_TOKEN);
+
+    let mut size = [0u8; 4];
+    if console.read_array(&mut size, REQUEST_TIMEOUT) != size.len() {
+        return Err(LoadError::Timeout);
+    }
+
+    let size = u32::from_le_bytes(size) as usize;
+    if size > capacity {
+        console.write_array(b"NO");
+        return Err(LoadError::TooLarge);
+    }
+    console.write_array(b"OK");
+
+    let image = unsafe {
+        core::slice::from_raw_parts_mut(
+            (staging.virt_pages.start_addr().into_usize() + offset) as *mut u8,
+            size,
+        )
+    };
+
+    for chunk in image.chunks_mut(CHUNK_SIZE) {
+        if console.read_array(chunk, TRANSFER_TIMEOUT) != chunk.len() {
+            return Err(LoadError::Timeout);
+        }
+    }
+
+    let mut expected = [0u8; 4];
+    if console.read_array(&mut expected, TRANSFER_TIMEOUT) != expected.len() {
+        return Err(LoadError::Timeout);
+    }
+
+    let checksum = common::crc32_update(0, image);
+    if checksum != u32::from_le_bytes(expected) {
+        console.write_array(b"ER");
+        return Err(LoadError::ChecksumMismatch);
+    }
+    console.write_array(b"OK");
+
+    Ok(LoadedImage {
+        phys_start: dest,
+        size,
+        checksum,
+    })
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl LoadError {
+    /// Return true if the load can be attempted again.
+    pub fn is_retryable(self) -> bool {
+        matches!(self, LoadError::Timeout | LoadError::ChecksumMismatch)
+    }
+
+    /// Return a description of the error.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LoadError::NotInitialized => "Loader not initialized",
+            LoadError::InvalidDestination => "Destination outside of the staging area",
+            LoadError::TooLarge => "Image too large",
+            LoadError::Timeout => "Timeout",
+            LoadError::ChecksumMismatch => "Checksum mismatch",
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl LoadedImage {
+    /// The physical start address of the image.
+    pub fn start_addr(&self) -> Address<Physical> {
+        self.phys_start
+    }
+
+    /// The size of the image in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The CRC-32 of the image.
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    /// Reset the interrupt controller, turn off the MMU and the caches, and jump to the image's first
+    /// byte.
+    ///
+    /// # Safety
+    ///
+    /// - The image must be position independent or linked to its physical start address.
+    /// - The image must be prepared to be entered in EL1.
+    /// - Secondary cores keep running the kernel. The image must not rely on them.
+    pub unsafe fn boot(self) -> ! {
+        // The image must not inherit IRQs that are enabled, pending or active.
+        exception::asynchronous::local_irq_mask();
+        bsp::exception::asynchronous::reset_irq_controller();
+
+        arch_loader::jump_to_image(self.phys_start)
+    }
+}
+
+/// Map the staging area.
+///
+/// The staging area is put at the start of the DRAM that the kernel does not use.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init.
+/// - The staging area is assumed to be unused otherwise.
+pub unsafe fn init() -> Result<(), &'static str> {
+    let attr = AttributeFields {
+        mem_attributes: MemAttributes::CacheableDRAM,
+        acc_perms: AccessPermissions::ReadWrite,
+        execute_never: true,
+    };
+
+    let phys_unused = bsp::memory::mmu::phys_unused_dram_page_desc();
+    if phys_unused.num_pages() < STAGING_NUM_PAGES {
+        return Err("Not enough unused DRAM for the loader's staging area");
+    }
+
+    let phys_pages = PageSliceDescriptor::from_addr(phys_unused.start_addr(), STAGING_NUM_PAGES);
+    let virt_pages = mmu::kernel_alloc_window(STAGING_NUM_PAGES)?;
+    mmu::kernel_map_window(&virt_pages, &phys_pages, &attr)?;
+    mmu::kernel_add_mapping_record("Loader staging area", &virt_pages, &phys_pages, &attr);
+
+    STAGING_AREA.write(|x| {
+        *x = Some(StagingArea {
+            virt_pages,
+            phys_pages,
+        })
+    });
+
+    Ok(())
+}
+
+/// Return the physical pages of the staging area, if it is mapped.
+pub fn staging_area() -> Option<PageSliceDescriptor<Physical>> {
+    STAGING_AREA.read(|x| x.map(|x| x.phys_pages))
+}
+
+/// Receive an image over the console and place it at `dest`, which must be in the staging area.
+pub fn load(dest: Address<Physical>) -> Result<LoadedImage, LoadError> {
+    let staging = STAGING_AREA.read(|x| *x).ok_or(LoadError::NotInitialized)?;
+
+    if !staging.phys_pages.contains(dest) {
+        return Err(LoadError::InvalidDestination);
+    }
+
+    exception::asynchronous::exec_with_irq_masked(|| load_once(staging, dest))
+}
+
+/// Like `load()`, but retry timeouts and checksum failures up to `max_attempts` times in total.
+pub fn load_retrying(
+    dest: Address<Physical>,
+    max_attempts: usize,
+) -> Result<LoadedImage, LoadError> {
+    let mut attempt = 1;
+
+    loop {
+        match load(dest) {
+            Err(x) if x.is_retryable() && attempt < max_attempts => {
+                warn!("Loader: {}. Retrying ({}/{})", x, attempt, max_attempts);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Only transient errors may be retried.
+    #[kernel_test]
+    fn retryable_errors() {
+        assert!(LoadError::Timeout.is_retryable());
+        assert!(LoadError::ChecksumMismatch.is_retryable());
+        assert!(!LoadError::TooLarge.is_retryable());
+        assert!(!LoadError::InvalidDestination.is_retryable());
+        assert!(!LoadError::NotInitialized.is_retryable());
+    }
+}
@@ -0,0 +1,395 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Leveled logging.
+//!
+//! Each log message has a level. It is printed if its level is enabled for the module that emits
+//! it. By default, this is decided by the runtime global level. Chatty or especially interesting
+//! modules can get a static override in `MODULE_LEVELS`.
+//!
+//! The global level can be preset with `loglevel=` on the kernel command line.
+//!
+//! Messages that can recur at a high rate, for example, from interrupt handlers, should go through
+//! a [`RateLimiter`] with `warn_rate_limited!`.
+
+use crate::{
+    cmdline, config,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    time,
+    time::interface::TimeManager,
+};
+use core::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Static per-module level overrides. An entry applies to all modules whose path starts with the
+/// given prefix. If multiple entries apply, the longest prefix wins.
+///
+/// For example, `("libkernel::memory", Level::Debug)` would enable debug output for the whole
+/// memory subsystem.
+const MODULE_LEVELS: &[(&str, Level)] = &[];
+
+/// The most verbose level of all overrides.
+const MAX_MODULE_LEVEL: Level = max_level(MODULE_LEVELS);
+
+struct RateLimiterInner {
+    window_start: Option<Duration>,
+    count: u32,
+    suppressed: u32,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Log levels, from the most to the least severe.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+/// Lets at most `max_count` messages through per time window, and counts the others.
+pub struct RateLimiter {
+    window: Duration,
+    max_count: u32,
+    inner: IRQSafeNullLock<RateLimiterInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static GLOBAL_LEVEL: AtomicU8 = AtomicU8::new(config::log::DEFAULT_LEVEL as u8);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+const fn max_level(table: &[(&str, Level)]) -> Level {
+    let mut max = Level::Error;
+    let mut i = 0;
+
+    while i < table.len() {
+        if table[i].1 as u8 > max as u8 {
+            max = table[i].1;
+        }
+        i += 1;
+    }
+
+    max
+}
+
+/// Return the level of the longest prefix in `table` that matches `module_path`, if any.
+fn module_level(table: &[(&str, Level)], module_path: &str) -> Option<Level> {
+    table
+        .iter()
+        .filter(|(prefix, _)| module_path.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, level)| *level)
+}
+
+/// Parse the value of `loglevel=`.
+///
+/// Takes the level names, and the numeric console log levels of Linux, where `loglevel=N` prints
+/// all messages that are more severe than `N`.
+fn parse_cmdline_level(s: &str) -> Result<Level, &'static str> {
+    if let Ok(x) = s.parse() {
+        return Ok(x);
+    }
+
+    match s.parse::<u8>() {
+        Ok(0..=4) => Ok(Level::Error),
+        Ok(5) => Ok(Level::Warn),
+        Ok(6) | Ok(7) => Ok(Level::Info),
+        Ok(_) => Ok(Level::Debug),
+        Err(_) => Err("Invalid loglevel"),
+    }
+}
+
+impl Level {
+    fn from_u8(x: u8) -> Self {
+        match x {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+}
+
+impl RateLimiterInner {
+    const fn new() -> Self {
+        Self {
+            window_start: None,
+            count: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// See `RateLimiter::check()`.
+    fn check(&mut self, now: Duration, window: Duration, max_count: u32) -> Option<u32> {
+        let is_window_over = match self.window_start {
+            None => true,
+            Some(x) => now >= x + window,
+        };
+
+        if is_window_over {
+            self.window_start = Some(now);
+            self.count = 0;
+        }
+
+        if self.count >= max_count {
+            self.suppressed += 1;
+            return None;
+        }
+        self.count += 1;
+
+        let suppressed = self.suppressed;
+        self.suppressed = 0;
+
+        Some(suppressed)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl RateLimiter {
+    /// Create an instance.
+    pub const fn new(window: Duration, max_count: u32) -> Self {
+        Self {
+            window,
+            max_count,
+            inner: IRQSafeNullLock::new(RateLimiterInner::new()),
+        }
+    }
+
+    /// Decide whether a message may be printed now.
+    ///
+    /// Returns `None` if the message must be dropped. Otherwise, returns how many messages were
+    /// dropped since the last one that was let through.
+    pub fn check(&self) -> Option<u32> {
+        let now = time::time_manager().uptime();
+
+        self.inner
+            .lock(|inner| inner.check(now, self.window, self.max_count))
+    }
+}
+
+impl Level {
+    /// The one-letter tag that marks the level in log output.
+    pub const fn tag(self) -> char {
+        match self {
+            Level::Error => 'E',
+            Level::Warn => 'W',
+            Level::Info => 'I',
+            Level::Debug => 'D',
+            Level::Trace => 'T',
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+
+        f.pad(s)
+    }
+}
+
+impl FromStr for Level {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err("Unknown log level"),
+        }
+    }
+}
+
+/// Return the runtime global level.
+pub fn global_level() -> Level {
+    Level::from_u8(GLOBAL_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Set the runtime global level. Modules with a static override are not affected.
+pub fn set_global_level(level: Level) {
+    GLOBAL_LEVEL.store(level as u8, Ordering::Relaxed)
+}
+
+/// Set the runtime global level from the kernel command line, if it has a `loglevel=`.
+pub fn init_from_cmdline() -> Result<(), &'static str> {
+    if let Some(x) = cmdline::get("loglevel") {
+        set_global_level(parse_cmdline_level(x)?);
+    }
+
+    Ok(())
+}
+
+/// Return true if messages of `level` from the module at `module_path` are printed.
+///
+/// The common case of a message that is too verbose for both the global level and all overrides
+/// is decided by a single atomic load.
+#[inline(always)]
+pub fn is_enabled(level: Level, module_path: &str) -> bool {
+    let global_level = GLOBAL_LEVEL.load(Ordering::Relaxed);
+
+    if level as u8 > global_level && level > MAX_MODULE_LEVEL {
+        return false;
+    }
+
+    if MODULE_LEVELS.is_empty() {
+        return true;
+    }
+
+    match module_level(MODULE_LEVELS, module_path) {
+        Some(x) => level <= x,
+        None => level as u8 <= global_level,
+    }
+}
+
+/// Prints a message of the given level, with a newline.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => ({
+        let level: $crate::log::Level = $level;
+
+        if $crate::log::is_enabled(level, module_path!()) {
+            $crate::print::_print_with_prefix(level, format_args!($($arg)*));
+        }
+    })
+}
+
+/// Prints an error, with a newline.
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Error, $($arg)*));
+}
+
+/// Prints a warning, with a newline.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Warn, $($arg)*));
+}
+
+/// Prints a warning, with a newline, unless the given `RateLimiter` holds it back.
+///
+/// The first warning after others were held back tells how many.
+#[macro_export]
+macro_rules! warn_rate_limited {
+    ($limiter:expr, $($arg:tt)*) => ({
+        let limiter: &$crate::log::RateLimiter = &$limiter;
+
+        match limiter.check() {
+            None => (),
+            Some(0) => $crate::warn!($($arg)*),
+            Some(x) => $crate::warn!(
+                "{} (suppressed {} similar messages)",
+                format_args!($($arg)*),
+                x
+            ),
+        }
+    })
+}
+
+/// Prints an info, with a newline.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Info, $($arg)*));
+}
+
+/// Prints a debug message, with a newline.
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Debug, $($arg)*));
+}
+
+/// Prints a trace message, with a newline.
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Trace, $($arg)*));
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check that the longest matching module prefix wins.
+    #[kernel_test]
+    fn module_level_lookup_works() {
+        const TABLE: &[(&str, Level)] = &[
+            ("libkernel::memory", Level::Debug),
+            ("libkernel::memory::mmu", Level::Error),
+        ];
+
+        assert_eq!(module_level(TABLE, "libkernel::driver"), None);
+        assert_eq!(module_level(TABLE, "libkernel::memory"), Some(Level::Debug));
+        assert_eq!(
+            module_level(TABLE, "libkernel::memory::mmu::mapping_record"),
+            Some(Level::Error)
+        );
+        assert_eq!(max_level(TABLE), Level::Debug);
+    }
+
+    /// Check names and Linux numbers for `loglevel=`.
+    #[kernel_test]
+    fn cmdline_level_parsing_works() {
+        assert_eq!(parse_cmdline_level("debug"), Ok(Level::Debug));
+        assert_eq!(parse_cmdline_level("4"), Ok(Level::Error));
+        assert_eq!(parse_cmdline_level("5"), Ok(Level::Warn));
+        assert_eq!(parse_cmdline_level("7"), Ok(Level::Info));
+        assert_eq!(parse_cmdline_level("8"), Ok(Level::Debug));
+        assert!(parse_cmdline_level("loud").is_err());
+    }
+
+    /// Check that messages beyond the limit are held back and counted, using a mocked clock.
+    #[kernel_test]
+    fn rate_limiter_counts_suppressed_messages() {
+        let window = Duration::from_secs(1);
+        let mut inner = RateLimiterInner::new();
+        let at = Duration::from_millis;
+
+        assert_eq!(inner.check(at(0), window, 2), Some(0));
+        assert_eq!(inner.check(at(100), window, 2), Some(0));
+        assert_eq!(inner.check(at(200), window, 2), None);
+        assert_eq!(inner.check(at(999), window, 2), None);
+
+        // A new window reports what was held back, once.
+        assert_eq!(inner.check(at(1000), window, 2), Some(2));
+        assert_eq!(inner.check(at(1001), window, 2), Some(0));
+        assert_eq!(inner.check(at(1002), window, 2), None);
+
+        assert_eq!(inner.check(at(5000), window, 2), Some(1));
+    }
+}
@@ -11,7 +11,10 @@
 #![no_main]
 #![no_std]
 
-use libkernel::{bsp, cpu, driver, exception, info, memory, state, time, warn};
+use libkernel::{
+    banner, bench, bsp, cmdline, config, cpu, driver, dtb, exception, info, loader, log, memory,
+    monitor, panic_record, state, time, warn,
+};
 
 /// Early init code.
 ///
@@ -23,47 +26,111 @@ use libkernel::{bsp, cpu, driver, exception, info, memory, state, time, warn};
 /// - Printing will not work until the respective driver's MMIO is remapped.
 #[no_mangle]
 unsafe fn kernel_init() -> ! {
-    use driver::interface::DriverManager;
-
+    bsp::memory::validate_layout();
     exception::handling_init();
 
+    // Before anything writes to the code or RO data.
+    bsp::memory::check_image_integrity();
+
+    // Nothing but the MMU code may write the translation tables from here on.
+    if let Err(x) = memory::mmu::kernel_write_protect_tables() {
+        warn!("Translation tables stay writable: {}", x);
+    }
+
+    // Before anything takes the time.
+    time::select_counter();
+
+    // The precomputed entries were generated offline, so check them with the kernel's own code.
+    if let Err(x) = bsp::memory::mmu::kernel_verify_precomputed() {
+        warn!("Precomputed translation tables: {}", x);
+    }
+
     // Add the mapping records for the precomputed entries first, so that they appear on the top of
     // the list.
     bsp::memory::mmu::kernel_add_mapping_records_for_precomputed();
 
-    // Bring up the drivers needed for printing first.
-    for i in bsp::driver::driver_manager()
-        .early_print_device_drivers()
-        .iter()
-    {
-        // Any encountered errors cannot be printed yet, obviously, so just safely park the CPU.
-        i.init().unwrap_or_else(|_| cpu::wait_forever());
+    // The command line in the device tree configures logging and the console, so it is parsed
+    // before the drivers. Messages are kept in the early console buffer until the console is up.
+    if let Err(x) = dtb::init() {
+        warn!("Error mapping the device tree: {}", x);
+    }
+    if let Err(x) = cmdline::init() {
+        warn!("{}", x);
+    }
+    if let Err(x) = log::init_from_cmdline() {
+        warn!("{}", x);
     }
-    bsp::driver::driver_manager().post_early_print_device_driver_init();
+
+    // Register the board's drivers, then bring up the ones needed for printing first.
+    //
+    // Any encountered errors cannot be printed yet, obviously, so just safely park the CPU.
+    bsp::driver::init().unwrap_or_else(|_| cpu::wait_forever());
+    driver::driver_manager()
+        .init_stage(driver::InitStage::Early)
+        .unwrap_or_else(|_| cpu::wait_forever());
     // Printing available from here on.
 
-    // Now bring up the remaining drivers.
-    for i in bsp::driver::driver_manager()
-        .non_early_print_device_drivers()
-        .iter()
-    {
-        if let Err(x) = i.init() {
-            panic!("Error loading driver: {}: {}", i.compatible(), x);
-        }
+    // Tell why the previous boot ended, if it panicked.
+    panic_record::report_and_clear();
+
+    // Now bring up the remaining drivers and let all drivers register and enable their handlers
+    // with the interrupt controller.
+    if let Err(x) = driver::driver_manager().init_stage(driver::InitStage::PostMMU) {
+        warn!("Not all drivers came up. First failure: {}", x);
+    }
+
+    // Identify the board, which needs the mailbox.
+    bsp::board::init();
+    bsp::clocks::init();
+
+    // Take the size of the DRAM from the mailbox or the device tree. This must happen before
+    // anything claims unused DRAM.
+    if let Err(x) = bsp::memory::init() {
+        warn!("Using the built-in DRAM size: {}", x);
+    }
+
+    // Opt-in test of the unused DRAM, for ruling out hardware problems.
+    #[cfg(feature = "dram_selftest")]
+    if let Err(x) = memory::selftest::quick_dram_test() {
+        warn!("{}", x);
+    }
+
+    // Map the staging area for images received by the chainloader.
+    if let Err(x) = loader::init() {
+        warn!("Error initializing the loader: {}", x);
+    }
+
+    // Map the window for the memcpy benchmark.
+    if let Err(x) = bench::init() {
+        warn!("Error initializing the benchmarks: {}", x);
+    }
+
+    // Map the pool of DMA bounce buffers.
+    if let Err(x) = memory::dma_pool::init() {
+        warn!("Error initializing the DMA pool: {}", x);
     }
 
-    // Let device drivers register and enable their handlers with the interrupt controller.
-    for i in bsp::driver::driver_manager().all_device_drivers() {
-        if let Err(msg) = i.register_and_enable_irq_handler() {
-            warn!("Error registering IRQ handler: {}", msg);
-        }
+    // Map the spin-table, so that the secondary cores can be released later.
+    if let Err(x) = bsp::cpu::spin_table_init() {
+        warn!("Error mapping the spin-table: {}", x);
     }
 
     // Unmask interrupts on the boot CPU core.
     exception::asynchronous::local_irq_unmask();
 
+    // Finish the bring-up of drivers that depend on interrupts.
+    if let Err(x) = driver::driver_manager().init_stage(driver::InitStage::PostIRQ) {
+        warn!(
+            "Not all drivers finished their bring-up. First failure: {}",
+            x
+        );
+    }
+
+    // The boot core is up and running.
+    state::state_manager().core_checked_in(cpu::core_id());
+
     // Announce conclusion of the kernel_init() phase.
-    state::state_manager().transition_to_single_core_main();
+    state::state_manager().transition(state::State::Init, state::State::SingleCoreMain);
 
     // Transition from unsafe to safe.
     kernel_main()
@@ -71,38 +138,70 @@ unsafe fn kernel_init() -> ! {
 
 /// The main function running after the early init.
 fn kernel_main() -> ! {
-    use driver::interface::DriverManager;
     use exception::asynchronous::interface::IRQManager;
 
-    info!("{}", libkernel::version());
-    info!("Booting on: {}", bsp::board_name());
+    if cmdline::flag("nosmp") {
+        info!("Not booting secondary cores: nosmp");
+    } else if let Err(x) = cpu::smp::boot_secondary_cores() {
+        warn!("Error booting secondary cores: {}", x);
+    } else if let Err(x) = driver::driver_manager().apply_irq_target_policy() {
+        warn!("Error routing IRQs: {}", x);
+    }
+
+    // Opt-in check of the hardware, before anything relies on it. Only essential failures stop the
+    // boot.
+    #[cfg(feature = "selftest")]
+    if let Err(x) = libkernel::selftest::run_all() {
+        libkernel::error::fatal("Essential self-test failed", x);
+    }
+
+    banner::print();
+    cpu::features::report();
+    config::print();
 
     info!("MMU online:");
     memory::mmu::kernel_print_mappings();
 
-    let (_, privilege_level) = exception::current_privilege_level();
-    info!("Current privilege level: {}", privilege_level);
+    bsp::thermal::report();
 
     info!("Exception handling state:");
     exception::asynchronous::print_state();
 
-    info!(
-        "Architectural timer resolution: {} ns",
-        time::time_manager().resolution().as_nanos()
-    );
-
     info!("Drivers loaded:");
-    for (i, driver) in bsp::driver::driver_manager()
-        .all_device_drivers()
-        .iter()
-        .enumerate()
-    {
-        info!("      {}. {}", i + 1, driver.compatible());
-    }
+    driver::driver_manager().print_status();
 
     info!("Registered IRQ handlers:");
     bsp::exception::asynchronous::irq_manager().print_handler();
 
-    info!("Echoing input now");
-    cpu::wait_forever();
+    // Signal the end of the boot.
+    if let Err(x) = bsp::led::set_act(true) {
+        info!("ACT LED: {}", x);
+    }
+
+    info!("Entering the monitor. Type 'help' for a list of commands");
+    monitor::run()
+}
+
+/// The main function of the secondary cores.
+///
+/// Entered after the MMU has been enabled on the respective core. The secondary cores check in with
+/// the state manager and then idle, since there is no work to distribute yet. Unless all IRQs go to
+/// the boot core, they take the IRQs that are routed to them.
+#[no_mangle]
+unsafe fn kernel_secondary_main(core_id: usize) -> ! {
+    use exception::asynchronous::{interface::IRQManager, IRQTargetPolicy};
+
+    exception::handling_init();
+
+    if config::exception::IRQ_TARGET_POLICY != IRQTargetPolicy::BootCore {
+        bsp::exception::asynchronous::irq_manager().local_core_init();
+        exception::asynchronous::local_irq_unmask();
+    }
+
+    state::state_manager().core_checked_in(core_id);
+
+    // Wake up the boot core, which is waiting for all cores to check in.
+    cpu::send_event();
+
+    cpu::idle::idle_loop()
 }
@@ -0,0 +1,212 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2018-2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Address types.
+
+use super::mmu;
+use crate::{bsp, common};
+use core::{
+    convert::TryFrom,
+    fmt,
+    marker::PhantomData,
+    ops::{AddAssign, SubAssign},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Metadata trait for marking the type of an address.
+pub trait AddressType: Copy + Clone + Ord + Eq {
+    /// Short name of the address space, for debug output.
+    const TAG: &'static str;
+}
+
+/// Zero-sized type to mark a physical address.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+pub enum Physical {}
+
+/// Zero-sized type to mark a virtual address.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+pub enum Virtual {}
+
+/// Generic address type.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+pub struct Address<ATYPE: AddressType> {
+    value: usize,
+    _address_type: PhantomData<fn() -> ATYPE>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl AddressType for Physical {
+    const TAG: &'static str = "PA";
+}
+
+impl AddressType for Virtual {
+    const TAG: &'static str = "VA";
+}
+
+impl<ATYPE: AddressType> Address<ATYPE> {
+    /// Create an instance.
+    pub const fn new(value: usize) -> Self {
+        Self {
+            value,
+            _address_type: PhantomData,
+        }
+    }
+
+    /// Align down.
+    pub const fn align_down(self, alignment: usize) -> Self {
+        let aligned = common::align_down(self.value, alignment);
+
+        Self {
+            value: aligned,
+            _address_type: PhantomData,
+        }
+    }
+
+    /// Align up. None if the aligned address does not fit into the address space.
+    pub fn align_up(self, alignment: usize) -> Option<Self> {
+        let aligned = self.value.checked_add(alignment - 1)?;
+
+        Some(Self::new(aligned).align_down(alignment))
+    }
+
+    /// Checks if the address is aligned to the kernel's translation granule.
+    pub const fn is_granule_aligned(self) -> bool {
+        common::is_aligned(self.value, bsp::memory::mmu::KernelGranule::SIZE)
+    }
+
+    /// Add an offset. None if the result does not fit into the address space.
+    pub fn checked_add(self, offset: usize) -> Option<Self> {
+        self.value.checked_add(offset).map(Self::new)
+    }
+
+    /// The distance in bytes from `base` up to this address.
+    ///
+    /// # Panics
+    ///
+    /// - If `base` is above this address.
+    pub fn offset_from(self, base: Self) -> usize {
+        self.value
+            .checked_sub(base.value)
+            .expect("Address is below the base")
+    }
+
+    /// Converts `Address` into an usize.
+    pub const fn into_usize(self) -> usize {
+        self.value
+    }
+}
+
+impl TryFrom<Address<Virtual>> for Address<Physical> {
+    type Error = mmu::TranslationError;
+
+    fn try_from(virt: Address<Virtual>) -> Result<Self, Self::Error> {
+        mmu::try_virt_to_phys(virt)
+    }
+}
+
+impl<ATYPE: AddressType> core::ops::Add<usize> for Address<ATYPE> {
+    type Output = Self;
+
+    fn add(self, other: usize) -> Self {
+        Self {
+            value: self.value + other,
+            _address_type: PhantomData,
+        }
+    }
+}
+
+impl<ATYPE: AddressType> AddAssign for Address<ATYPE> {
+    fn add_assign(&mut self, other: Self) {
+        *self = Self {
+            value: self.value + other.into_usize(),
+            _address_type: PhantomData,
+        };
+    }
+}
+
+impl<ATYPE: AddressType> core::ops::Sub<usize> for Address<ATYPE> {
+    type Output = Self;
+
+    fn sub(self, other: usize) -> Self {
+        Self {
+            value: self.value - other,
+            _address_type: PhantomData,
+        }
+    }
+}
+
+impl<ATYPE: AddressType> SubAssign for Address<ATYPE> {
+    fn sub_assign(&mut self, other: Self) {
+        *self = Self {
+            value: self.value - other.into_usize(),
+            _address_type: PhantomData,
+        };
+    }
+}
+
+/// Prints all 64 bits in groups of four hex digits, so that physical and virtual addresses line up
+/// in tables.
+impl<ATYPE: AddressType> fmt::Display for Address<ATYPE> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let q4: u16 = ((self.value >> 48) & 0xffff) as u16;
+        let q3: u16 = ((self.value >> 32) & 0xffff) as u16;
+        let q2: u16 = ((self.value >> 16) & 0xffff) as u16;
+        let q1: u16 = (self.value & 0xffff) as u16;
+
+        write!(f, "0x")?;
+        write!(f, "{:04x}_", q4)?;
+        write!(f, "{:04x}_", q3)?;
+        write!(f, "{:04x}_", q2)?;
+        write!(f, "{:04x}", q1)
+    }
+}
+
+/// Like `Display`, prefixed with the address space.
+impl<ATYPE: AddressType> fmt::Debug for Address<ATYPE> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", ATYPE::TAG, self)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(all(test, target_os = "none"))]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check the arithmetic at the top of the address space, where it overflows.
+    #[kernel_test]
+    fn arithmetic_near_the_top_works() {
+        let granule = bsp::memory::mmu::KernelGranule::SIZE;
+        let top = Address::<Virtual>::new(usize::MAX);
+        let last_page = top.align_down(granule);
+
+        assert_eq!(top.checked_add(1), None);
+        assert_eq!(last_page.checked_add(granule - 1), Some(top));
+        assert_eq!(last_page.checked_add(granule), None);
+
+        assert_eq!(last_page.align_up(granule), Some(last_page));
+        assert_eq!((last_page + 1).align_up(granule), None);
+        assert_eq!(
+            Address::<Virtual>::new(1).align_up(granule),
+            Some(Address::new(granule))
+        );
+
+        assert!(last_page.is_granule_aligned());
+        assert!(!top.is_granule_aligned());
+
+        assert_eq!(top.offset_from(last_page), granule - 1);
+        assert_eq!(top.offset_from(Address::new(0)), usize::MAX);
+        assert!(last_page < top);
+    }
+}
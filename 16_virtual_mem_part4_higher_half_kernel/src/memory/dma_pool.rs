@@ -0,0 +1,487 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Bounce buffers for DMA.
+//!
+//! DMA engines access physical memory and bypass the CPU caches. A buffer that a device reads or
+//! writes must therefore be physically contiguous, and must not be cached, or the device and the
+//! CPU see different data. Buffers that the kernel hands to a driver are often neither, so their
+//! data is copied through a buffer from this pool instead. That is called bouncing.
+//!
+//! The pool is a BSP-sized range of physically contiguous DRAM that is mapped as Device memory at
+//! init. It is handed out in blocks of `BLOCK_SIZE` bytes. A buffer takes the first run of free
+//! blocks that is large enough.
+//!
+//! Drivers that program a DMA engine use a `DmaMapping`. It passes the caller's buffer on if the
+//! device can use it as is, and bounces it otherwise.
+
+use crate::{
+    bsp::{
+        self,
+        memory::mmu::{KernelGranule, DMA_POOL_NUM_PAGES},
+    },
+    common, info,
+    memory::{
+        mmu::{self, AccessPermissions, AttributeFields, MemAttributes, PageSliceDescriptor},
+        ops, Address, Physical, Virtual,
+    },
+    synchronization::{
+        interface::{Mutex, ReadWriteEx},
+        IRQSafeNullLock, InitStateLock,
+    },
+    units::ByteSize,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The unit of allocation. Smaller than a page, so that small transfers do not take a whole one.
+const BLOCK_SIZE: usize = 4096;
+
+const POOL_SIZE: usize = DMA_POOL_NUM_PAGES * KernelGranule::SIZE;
+const NUM_BLOCKS: usize = POOL_SIZE / BLOCK_SIZE;
+
+/// The mapped pool.
+#[derive(Copy, Clone)]
+struct Pool {
+    virt_pages: PageSliceDescriptor<Virtual>,
+    phys_pages: PageSliceDescriptor<Physical>,
+}
+
+/// Which blocks are in use.
+struct BlockMap {
+    used: [bool; NUM_BLOCKS],
+    num_used: usize,
+    peak_used: usize,
+}
+
+struct DmaPoolInner {
+    blocks: BlockMap,
+    num_direct: usize,
+    num_bounced: usize,
+    bytes_bounced: usize,
+    alloc_failures: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A physically contiguous, non-cacheable buffer from the pool.
+///
+/// The blocks are returned to the pool on drop.
+pub struct DmaBuffer {
+    virt_addr: Address<Virtual>,
+    phys_addr: Address<Physical>,
+    size: usize,
+    first_block: usize,
+    num_blocks: usize,
+}
+
+/// The buffer of a DMA transfer, together with the direction of the transfer.
+pub enum DmaTarget<'a> {
+    /// The device reads the buffer.
+    ToDevice(&'a [u8]),
+
+    /// The device writes the buffer.
+    FromDevice(&'a mut [u8]),
+}
+
+/// A buffer prepared for a DMA transfer.
+///
+/// `phys_addr()` is the address to program into the device. If the buffer was bounced, the data
+/// is copied in when the mapping is created, and copied out by `finish()`.
+pub struct DmaMapping<'a> {
+    target: DmaTarget<'a>,
+    phys_addr: Address<Physical>,
+    bounce: Option<DmaBuffer>,
+}
+
+/// A snapshot of the pool's statistics.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DmaPoolStats {
+    /// The size of the pool. Zero if it is not initialized.
+    pub size: usize,
+
+    /// The size of a block.
+    pub block_size: usize,
+
+    /// The number of blocks that are allocated now.
+    pub blocks_in_use: usize,
+
+    /// The most blocks that were allocated at any time.
+    pub peak_blocks_in_use: usize,
+
+    /// The number of transfers that used the caller's buffer directly.
+    pub num_direct: usize,
+
+    /// The number of transfers that were bounced.
+    pub num_bounced: usize,
+
+    /// The bytes that were copied in or out of bounce buffers.
+    pub bytes_bounced: usize,
+
+    /// The number of allocations that failed.
+    pub alloc_failures: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static POOL: InitStateLock<Option<Pool>> = InitStateLock::new(None);
+
+static DMA_POOL: IRQSafeNullLock<DmaPoolInner> = IRQSafeNullLock::new(DmaPoolInner::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl BlockMap {
+    const fn new() -> Self {
+        Self {
+            used: [false; NUM_BLOCKS],
+            num_used: 0,
+            peak_used: 0,
+        }
+    }
+
+    /// Take the first run of `n` free blocks, and return the index of its first block.
+    fn alloc(&mut self, n: usize) -> Option<usize> {
+        if (n == 0) || (n > NUM_BLOCKS) {
+            return None;
+        }
+
+        let mut run = 0;
+        let mut found = None;
+        for (i, used) in self.used.iter().enumerate() {
+            if *used {
+                run = 0;
+                continue;
+            }
+
+            run += 1;
+            if run == n {
+                found = Some(i + 1 - n);
+                break;
+            }
+        }
+
+        let first = found?;
+        self.used[first..(first + n)].fill(true);
+        self.num_used += n;
+        self.peak_used = self.peak_used.max(self.num_used);
+
+        Some(first)
+    }
+
+    fn free(&mut self, first: usize, n: usize) {
+        for used in self.used[first..(first + n)].iter_mut() {
+            assert!(*used, "DMA pool block freed twice");
+            *used = false;
+        }
+
+        self.num_used -= n;
+    }
+}
+
+impl DmaPoolInner {
+    const fn new() -> Self {
+        Self {
+            blocks: BlockMap::new(),
+            num_direct: 0,
+            num_bounced: 0,
+            bytes_bounced: 0,
+            alloc_failures: 0,
+        }
+    }
+}
+
+/// The physical address at which the device can use `len` bytes from `virt` directly, or None if
+/// they must be bounced.
+///
+/// They can be used directly if they are physically contiguous and not cacheable.
+fn direct_phys_addr(
+    virt: Address<Virtual>,
+    len: usize,
+) -> Result<Option<Address<Physical>>, &'static str> {
+    let mut runs = mmu::virt_to_phys_region(virt, len).map_err(|_| "DMA buffer is not mapped")?;
+    let (phys, _) = runs.next().ok_or("DMA buffer is empty")?;
+    if runs.next().is_some() {
+        return Ok(None);
+    }
+
+    // Memory without a mapping record is kernel memory, which is cacheable.
+    let non_cacheable = match mmu::kernel_find_mapping(virt) {
+        None => false,
+        Some(mapping) => {
+            (mapping.attribute_fields.mem_attributes == MemAttributes::Device)
+                && (virt.into_usize() + len <= mapping.virt_pages.end_addr().into_usize())
+        }
+    };
+
+    Ok(if non_cacheable { Some(phys) } else { None })
+}
+
+/// The number of blocks that hold `len` bytes.
+const fn blocks_for(len: usize) -> usize {
+    common::align_up(len, BLOCK_SIZE) / BLOCK_SIZE
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Map the pool that the BSP set aside.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init.
+/// - The pool's DRAM is assumed to be unused otherwise.
+pub unsafe fn init() -> Result<(), &'static str> {
+    let attr = AttributeFields {
+        mem_attributes: MemAttributes::Device,
+        acc_perms: AccessPermissions::ReadWrite,
+        execute_never: true,
+    };
+
+    let phys_pages =
+        bsp::memory::mmu::phys_dma_pool_page_desc().ok_or("Not enough DRAM for the DMA pool")?;
+    let virt_pages = mmu::kernel_alloc_window(phys_pages.num_pages())?;
+    mmu::kernel_map_window(&virt_pages, &phys_pages, &attr)?;
+    mmu::kernel_add_mapping_record("DMA pool", &virt_pages, &phys_pages, &attr);
+
+    POOL.write(|x| {
+        *x = Some(Pool {
+            virt_pages,
+            phys_pages,
+        })
+    });
+
+    Ok(())
+}
+
+/// Allocate a bounce buffer of at least `len` bytes.
+///
+/// Its contents are undefined.
+pub fn alloc(len: usize) -> Result<DmaBuffer, &'static str> {
+    if len == 0 {
+        return Err("DMA buffer is empty");
+    }
+
+    let pool = match POOL.read(|x| *x) {
+        Some(x) => x,
+        None => {
+            DMA_POOL.lock(|inner| inner.alloc_failures += 1);
+            return Err("DMA pool not initialized");
+        }
+    };
+
+    let num_blocks = blocks_for(len);
+    let first_block = DMA_POOL
+        .lock(|inner| {
+            let first = inner.blocks.alloc(num_blocks);
+            if first.is_none() {
+                inner.alloc_failures += 1;
+            }
+
+            first
+        })
+        .ok_or("DMA pool exhausted")?;
+
+    let offset = first_block * BLOCK_SIZE;
+    Ok(DmaBuffer {
+        virt_addr: pool.virt_pages.start_addr() + offset,
+        phys_addr: pool.phys_pages.start_addr() + offset,
+        size: num_blocks * BLOCK_SIZE,
+        first_block,
+        num_blocks,
+    })
+}
+
+impl DmaBuffer {
+    /// The address at which the CPU accesses the buffer.
+    pub fn virt_addr(&self) -> Address<Virtual> {
+        self.virt_addr
+    }
+
+    /// The address at which a device accesses the buffer.
+    pub fn phys_addr(&self) -> Address<Physical> {
+        self.phys_addr
+    }
+
+    /// The size of the buffer, which is `alloc()`'s length rounded up to whole blocks.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Copy `src` to the start of the buffer.
+    pub fn copy_in(&mut self, src: &[u8]) -> Result<(), &'static str> {
+        if src.len() > self.size {
+            return Err("Source is larger than the DMA buffer");
+        }
+
+        unsafe { ops::copy_to_device(self.virt_addr, src) }
+    }
+
+    /// Fill `dst` from the start of the buffer.
+    pub fn copy_out(&self, dst: &mut [u8]) -> Result<(), &'static str> {
+        if dst.len() > self.size {
+            return Err("Destination is larger than the DMA buffer");
+        }
+
+        unsafe { ops::copy_from_device(dst, self.virt_addr) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        DMA_POOL.lock(|inner| inner.blocks.free(self.first_block, self.num_blocks));
+    }
+}
+
+impl<'a> DmaTarget<'a> {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Self::ToDevice(x) => x,
+            Self::FromDevice(x) => x,
+        }
+    }
+}
+
+impl<'a> DmaMapping<'a> {
+    /// Prepare `target` for a transfer.
+    ///
+    /// The buffer is bounced if `virt_to_phys_region()` shows that it is physically fragmented, or
+    /// if it is cacheable. For transfers to the device, the data is copied into the bounce buffer
+    /// right away.
+    pub fn new(target: DmaTarget<'a>) -> Result<Self, &'static str> {
+        let slice = target.as_slice();
+        let virt = Address::new(slice.as_ptr() as usize);
+
+        if let Some(phys_addr) = direct_phys_addr(virt, slice.len())? {
+            DMA_POOL.lock(|inner| inner.num_direct += 1);
+
+            return Ok(Self {
+                target,
+                phys_addr,
+                bounce: None,
+            });
+        }
+
+        let mut bounce = alloc(slice.len())?;
+        if let DmaTarget::ToDevice(src) = &target {
+            bounce.copy_in(src)?;
+            DMA_POOL.lock(|inner| inner.bytes_bounced += src.len());
+        }
+        DMA_POOL.lock(|inner| inner.num_bounced += 1);
+
+        Ok(Self {
+            target,
+            phys_addr: bounce.phys_addr(),
+            bounce: Some(bounce),
+        })
+    }
+
+    /// The address to program into the device.
+    pub fn phys_addr(&self) -> Address<Physical> {
+        self.phys_addr
+    }
+
+    /// Return true if the transfer goes through a bounce buffer.
+    pub fn is_bounced(&self) -> bool {
+        self.bounce.is_some()
+    }
+
+    /// End the transfer. For transfers from the device, copies the data out of the bounce buffer.
+    ///
+    /// Must only be called once the device is done with the buffer.
+    pub fn finish(self) -> Result<(), &'static str> {
+        if let (DmaTarget::FromDevice(dst), Some(bounce)) = (self.target, &self.bounce) {
+            bounce.copy_out(dst)?;
+            DMA_POOL.lock(|inner| inner.bytes_bounced += dst.len());
+        }
+
+        Ok(())
+    }
+}
+
+/// A snapshot of the pool's statistics.
+pub fn stats() -> DmaPoolStats {
+    let size = POOL.read(|x| x.map_or(0, |pool| pool.phys_pages.size()));
+
+    DMA_POOL.lock(|inner| DmaPoolStats {
+        size,
+        block_size: BLOCK_SIZE,
+        blocks_in_use: inner.blocks.num_used,
+        peak_blocks_in_use: inner.blocks.peak_used,
+        num_direct: inner.num_direct,
+        num_bounced: inner.num_bounced,
+        bytes_bounced: inner.bytes_bounced,
+        alloc_failures: inner.alloc_failures,
+    })
+}
+
+/// Print the pool's statistics.
+pub fn print_stats() {
+    let stats = stats();
+
+    if stats.size == 0 {
+        info!("DMA pool: Not initialized");
+        return;
+    }
+
+    let transfers = stats.num_direct + stats.num_bounced;
+    let bounced_percent = if transfers == 0 {
+        0
+    } else {
+        (stats.num_bounced * 100) / transfers
+    };
+
+    info!(
+        "DMA pool: {} of {} in use (peak {})",
+        ByteSize(stats.blocks_in_use * stats.block_size),
+        ByteSize(stats.size),
+        ByteSize(stats.peak_blocks_in_use * stats.block_size)
+    );
+    info!(
+        "          {} transfers, {} bounced ({} %), {} copied",
+        transfers,
+        stats.num_bounced,
+        bounced_percent,
+        ByteSize(stats.bytes_bounced)
+    );
+    info!("          {} failed allocations", stats.alloc_failures);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Allocations take the first run of free blocks that fits, and freed blocks can be reused.
+    #[kernel_test]
+    fn blocks_are_first_fit() {
+        let mut blocks = BlockMap::new();
+
+        assert_eq!(blocks.alloc(2), Some(0));
+        assert_eq!(blocks.alloc(1), Some(2));
+        assert_eq!(blocks.alloc(3), Some(3));
+
+        blocks.free(0, 2);
+        assert_eq!(blocks.alloc(3), Some(6));
+        assert_eq!(blocks.alloc(2), Some(0));
+        assert_eq!((blocks.num_used, blocks.peak_used), (11, 11));
+
+        blocks.free(3, 3);
+        assert_eq!(blocks.num_used, 8);
+        assert_eq!(blocks.alloc(0), None);
+        assert_eq!(blocks.alloc(NUM_BLOCKS), None);
+    }
+}
@@ -0,0 +1,544 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! The kernel heap.
+//!
+//! A first-fit allocator over a fixed region in `.bss`. The free blocks form a list that is sorted
+//! by address, so that a freed block can be merged with its free neighbours. A free block holds its
+//! size and the address of the next free block in its first bytes. Allocated blocks carry no
+//! header, since `dealloc()` is given the layout again.
+//!
+//! Sizes and alignments are rounded up to `MIN_BLOCK`. This keeps every block large enough for the
+//! free list entry, and makes every leftover of a split large enough to become a free block.
+//!
+//! With the `heap_callsites` feature, allocations are also counted per call site. A call site is
+//! told apart by the innermost return addresses at the time of the allocation, which are printed
+//! raw with the statistics, to be looked up in the kernel ELF, for example with `addr2line`. Frees
+//! are not attributed, since allocated blocks carry no header, so the counts only ever grow.
+
+#[cfg(feature = "heap_callsites")]
+use crate::cpu;
+use crate::{
+    common, config, error, info,
+    log::Level,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    units::ByteSize,
+};
+use core::alloc::{GlobalAlloc, Layout};
+#[cfg(feature = "heap_callsites")]
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The granularity of block sizes and alignments. Fits a `FreeBlock`.
+const MIN_BLOCK: usize = 16;
+
+/// The number of return addresses that make up a call site. The innermost ones are usually inside
+/// the `alloc` crate.
+#[cfg(feature = "heap_callsites")]
+const CALLSITE_DEPTH: usize = 5;
+
+/// The number of call sites that are told apart. Allocations from further ones are counted
+/// together.
+#[cfg(feature = "heap_callsites")]
+const MAX_CALLSITES: usize = 32;
+
+#[repr(C, align(16))]
+struct HeapArea([u8; config::memory::HEAP_SIZE]);
+
+/// The head of a free block.
+#[repr(C)]
+struct FreeBlock {
+    size: usize,
+
+    /// The address of the next free block, or zero.
+    next: usize,
+}
+
+/// The allocations from one call site.
+#[cfg(feature = "heap_callsites")]
+#[derive(Copy, Clone)]
+struct CallSite {
+    return_addrs: [usize; CALLSITE_DEPTH],
+    num_allocs: usize,
+    bytes: usize,
+}
+
+/// Prints return addresses as a space separated list.
+#[cfg(feature = "heap_callsites")]
+struct ReturnAddrs<'a>(&'a [usize]);
+
+struct HeapInner {
+    initialized: bool,
+
+    /// The address of the first free block, or zero.
+    head: usize,
+
+    current: usize,
+    peak: usize,
+    num_allocs: usize,
+    live_allocs: usize,
+    failures: usize,
+
+    #[cfg(feature = "heap_callsites")]
+    callsites: [Option<CallSite>; MAX_CALLSITES],
+
+    /// The allocations and bytes from call sites that did not fit into `callsites`.
+    #[cfg(feature = "heap_callsites")]
+    untracked: (usize, usize),
+}
+
+/// The kernel heap.
+struct KernelHeap {
+    inner: IRQSafeNullLock<HeapInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A snapshot of the heap's statistics.
+///
+/// Byte counts are taken after rounding to the heap's block granularity.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct HeapStats {
+    /// The size of the heap.
+    pub size: usize,
+
+    /// The bytes that are allocated now.
+    pub current: usize,
+
+    /// The most bytes that were allocated at any time.
+    pub peak: usize,
+
+    /// The number of allocations since boot.
+    pub num_allocs: usize,
+
+    /// The number of allocations that were not freed yet.
+    pub live_allocs: usize,
+
+    /// The number of allocations that failed.
+    pub failures: usize,
+
+    /// The size of the largest free block, which bounds the largest allocation that can succeed.
+    pub largest_free: usize,
+
+    /// The number of free blocks.
+    pub free_blocks: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static mut HEAP_AREA: HeapArea = HeapArea([0; config::memory::HEAP_SIZE]);
+
+#[global_allocator]
+static KERNEL_HEAP: KernelHeap = KernelHeap {
+    inner: IRQSafeNullLock::new(HeapInner::new()),
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The free block at `addr`.
+///
+/// # Safety
+///
+/// - `addr` must be a `MIN_BLOCK` aligned address inside the heap area that is not allocated.
+unsafe fn block(addr: usize) -> &'static mut FreeBlock {
+    &mut *(addr as *mut FreeBlock)
+}
+
+/// The size and alignment of the block that serves `layout`, or None if it can never fit.
+fn block_layout(layout: Layout) -> Option<(usize, usize)> {
+    if (layout.size() > config::memory::HEAP_SIZE) || (layout.align() > config::memory::HEAP_SIZE) {
+        return None;
+    }
+
+    Some((
+        common::align_up(layout.size().max(1), MIN_BLOCK),
+        layout.align().max(MIN_BLOCK),
+    ))
+}
+
+impl HeapInner {
+    const fn new() -> Self {
+        Self {
+            initialized: false,
+            head: 0,
+            current: 0,
+            peak: 0,
+            num_allocs: 0,
+            live_allocs: 0,
+            failures: 0,
+
+            #[cfg(feature = "heap_callsites")]
+            callsites: [None; MAX_CALLSITES],
+
+            #[cfg(feature = "heap_callsites")]
+            untracked: (0, 0),
+        }
+    }
+
+    /// Make the whole area a single free block on first use.
+    fn init(&mut self) {
+        if self.initialized {
+            return;
+        }
+
+        let start = unsafe { core::ptr::addr_of_mut!(HEAP_AREA) } as usize;
+        *unsafe { block(start) } = FreeBlock {
+            size: config::memory::HEAP_SIZE,
+            next: 0,
+        };
+
+        self.head = start;
+        self.initialized = true;
+    }
+
+    /// Point the free block at `prev`, or the list head if `prev` is zero, to `next`.
+    fn link(&mut self, prev: usize, next: usize) {
+        if prev == 0 {
+            self.head = next;
+        } else {
+            unsafe { block(prev) }.next = next;
+        }
+    }
+
+    /// Returns the address of the allocation, or zero if no free block fits.
+    fn alloc(&mut self, layout: Layout) -> usize {
+        self.init();
+
+        let (size, align) = match block_layout(layout) {
+            Some(x) => x,
+            None => {
+                self.failures += 1;
+                return 0;
+            }
+        };
+
+        let mut prev = 0;
+        let mut addr = self.head;
+        while addr != 0 {
+            let free = unsafe { block(addr) };
+            let start = common::align_up(addr, align);
+            let front = start - addr;
+
+            if front + size > free.size {
+                prev = addr;
+                addr = free.next;
+                continue;
+            }
+
+            // Both leftovers are multiples of MIN_BLOCK, so each is either empty or a block.
+            let back = free.size - front - size;
+            let mut next = free.next;
+            if back != 0 {
+                *unsafe { block(start + size) } = FreeBlock { size: back, next };
+                next = start + size;
+            }
+
+            if front != 0 {
+                *free = FreeBlock { size: front, next };
+            } else {
+                self.link(prev, next);
+            }
+
+            self.current += size;
+            self.peak = self.peak.max(self.current);
+            self.num_allocs += 1;
+            self.live_allocs += 1;
+
+            return start;
+        }
+
+        self.failures += 1;
+        0
+    }
+
+    fn dealloc(&mut self, addr: usize, layout: Layout) {
+        let (size, _) = block_layout(layout).unwrap();
+
+        let mut prev = 0;
+        let mut next = self.head;
+        while (next != 0) && (next < addr) {
+            prev = next;
+            next = unsafe { block(next) }.next;
+        }
+
+        let mut merged_size = size;
+        if (next != 0) && (addr + size == next) {
+            let free = unsafe { block(next) };
+            merged_size += free.size;
+            next = free.next;
+        }
+
+        if (prev != 0) && (prev + unsafe { block(prev) }.size == addr) {
+            let free = unsafe { block(prev) };
+            free.size += merged_size;
+            free.next = next;
+        } else {
+            *unsafe { block(addr) } = FreeBlock {
+                size: merged_size,
+                next,
+            };
+            self.link(prev, addr);
+        }
+
+        self.current -= size;
+        self.live_allocs -= 1;
+    }
+
+    /// Count an allocation of `layout` for the call site with the given return addresses.
+    #[cfg(feature = "heap_callsites")]
+    fn count_callsite(&mut self, return_addrs: [usize; CALLSITE_DEPTH], layout: Layout) {
+        let (size, _) = block_layout(layout).unwrap();
+
+        let slot = self
+            .callsites
+            .iter_mut()
+            .find(|x| x.map_or(true, |x| x.return_addrs == return_addrs));
+        let slot = match slot {
+            Some(x) => x,
+            None => {
+                self.untracked.0 += 1;
+                self.untracked.1 += size;
+                return;
+            }
+        };
+
+        let site = slot.get_or_insert(CallSite {
+            return_addrs,
+            num_allocs: 0,
+            bytes: 0,
+        });
+        site.num_allocs += 1;
+        site.bytes += size;
+    }
+
+    fn stats(&mut self) -> HeapStats {
+        self.init();
+
+        let mut largest_free = 0;
+        let mut free_blocks = 0;
+        let mut addr = self.head;
+        while addr != 0 {
+            let free = unsafe { block(addr) };
+            largest_free = largest_free.max(free.size);
+            free_blocks += 1;
+            addr = free.next;
+        }
+
+        HeapStats {
+            size: config::memory::HEAP_SIZE,
+            current: self.current,
+            peak: self.peak,
+            num_allocs: self.num_allocs,
+            live_allocs: self.live_allocs,
+            failures: self.failures,
+            largest_free,
+            free_blocks,
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        #[cfg(feature = "heap_callsites")]
+        let return_addrs = {
+            let mut x = [0; CALLSITE_DEPTH];
+            cpu::return_addresses(&mut x);
+            x
+        };
+
+        self.inner.lock(|inner| {
+            let addr = inner.alloc(layout);
+
+            #[cfg(feature = "heap_callsites")]
+            {
+                if addr != 0 {
+                    inner.count_callsite(return_addrs, layout);
+                }
+            }
+
+            addr
+        }) as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.lock(|inner| inner.dealloc(ptr as usize, layout))
+    }
+}
+
+#[cfg(feature = "heap_callsites")]
+impl fmt::Display for ReturnAddrs<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, addr) in self.0.iter().enumerate() {
+            if i != 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:#018x}", addr)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn log_stats(level: Level) {
+    let stats = stats();
+
+    crate::log!(
+        level,
+        "Heap: {} of {} in use (peak {})",
+        ByteSize(stats.current),
+        ByteSize(stats.size),
+        ByteSize(stats.peak)
+    );
+    crate::log!(
+        level,
+        "      {} allocations, {} live, {} failed",
+        stats.num_allocs,
+        stats.live_allocs,
+        stats.failures
+    );
+    crate::log!(
+        level,
+        "      Largest free block {} of {} free blocks ({} % fragmented)",
+        ByteSize(stats.largest_free),
+        stats.free_blocks,
+        stats.fragmentation()
+    );
+
+    #[cfg(feature = "heap_callsites")]
+    log_callsites(level);
+}
+
+/// Print the allocations per call site. Copied out first, so that the heap is not locked while
+/// printing.
+#[cfg(feature = "heap_callsites")]
+fn log_callsites(level: Level) {
+    let (callsites, untracked) = KERNEL_HEAP
+        .inner
+        .lock(|inner| (inner.callsites, inner.untracked));
+
+    crate::log!(
+        level,
+        "      Allocations per call site, innermost return address first:"
+    );
+    for site in callsites.iter().flatten() {
+        crate::log!(
+            level,
+            "      {}: {} allocations, {}",
+            ReturnAddrs(&site.return_addrs),
+            site.num_allocs,
+            ByteSize(site.bytes)
+        );
+    }
+
+    if untracked.0 != 0 {
+        crate::log!(
+            level,
+            "      Further call sites: {} allocations, {}",
+            untracked.0,
+            ByteSize(untracked.1)
+        );
+    }
+}
+
+/// Called by the `alloc` crate when an allocation fails.
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    error!(
+        "Heap allocation of {} bytes with alignment {} failed",
+        layout.size(),
+        layout.align()
+    );
+    log_stats(Level::Error);
+
+    panic!("Out of heap memory")
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl HeapStats {
+    /// The percentage of free bytes that lie outside of the largest free block.
+    ///
+    /// Zero means that all free memory can be handed out in one piece.
+    pub fn fragmentation(&self) -> usize {
+        let free = self.size - self.current;
+        if free == 0 {
+            return 0;
+        }
+
+        100 - (self.largest_free * 100) / free
+    }
+}
+
+/// A snapshot of the heap's statistics.
+pub fn stats() -> HeapStats {
+    KERNEL_HEAP.inner.lock(|inner| inner.stats())
+}
+
+/// Print the heap's statistics.
+pub fn print_stats() {
+    log_stats(Level::Info);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Freed blocks merge with their neighbours, whatever the order of the frees.
+    #[kernel_test]
+    fn freed_blocks_coalesce() {
+        let baseline = stats();
+        let small = Layout::from_size_align(24, 8).unwrap();
+        let aligned = Layout::from_size_align(64, 256).unwrap();
+
+        let (a, b, c) = unsafe {
+            (
+                KERNEL_HEAP.alloc(small),
+                KERNEL_HEAP.alloc(aligned),
+                KERNEL_HEAP.alloc(small),
+            )
+        };
+        assert!(!a.is_null() && !b.is_null() && !c.is_null());
+        assert!(common::is_aligned(b as usize, 256));
+        assert_eq!(stats().live_allocs, baseline.live_allocs + 3);
+        assert_eq!(stats().current, baseline.current + 32 + 64 + 32);
+
+        unsafe {
+            KERNEL_HEAP.dealloc(b, aligned);
+            KERNEL_HEAP.dealloc(a, small);
+            KERNEL_HEAP.dealloc(c, small);
+        }
+
+        let after = stats();
+        assert_eq!(after.current, baseline.current);
+        assert_eq!(after.free_blocks, baseline.free_blocks);
+        assert_eq!(after.largest_free, baseline.largest_free);
+    }
+
+    /// A request that is larger than the heap fails without touching the heap.
+    #[kernel_test]
+    fn oversized_request_fails() {
+        let baseline = stats();
+        let layout = Layout::from_size_align(config::memory::HEAP_SIZE + 1, 8).unwrap();
+
+        assert!(unsafe { KERNEL_HEAP.alloc(layout) }.is_null());
+        assert_eq!(stats().failures, baseline.failures + 1);
+        assert_eq!(stats().current, baseline.current);
+    }
+}
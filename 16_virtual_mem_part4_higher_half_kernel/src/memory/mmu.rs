@@ -9,25 +9,42 @@
 mod arch_mmu;
 
 mod mapping_record;
+mod mmio;
+#[cfg(feature = "mmio_stats")]
+pub mod mmio_access;
+#[cfg(feature = "mmu_trace")]
+pub mod trace;
 mod translation_table;
 mod types;
 
 use crate::{
-    bsp,
+    bsp, config,
     memory::{Address, Physical, Virtual},
     synchronization, warn,
 };
-use core::fmt;
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
+pub use mmio::{MappedMmio, MmioRegion};
 pub use types::*;
 
+//--------------------------------------------------------------------------------------------------
+// Architectural Public Reexports
+//--------------------------------------------------------------------------------------------------
+pub use arch_mmu::{
+    activate, configure_translation_control, disable_identity_mapping, enable_identity_mapping,
+    saved_translation_control, TranslationControl,
+};
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
 /// MMU enable errors variants.
 #[allow(missing_docs)]
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum MMUEnableError {
     AlreadyEnabled,
     Other(&'static str),
@@ -35,12 +52,46 @@ pub enum MMUEnableError {
 
 /// Translation error variants.
 #[allow(missing_docs)]
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum TranslationError {
     MMUDisabled,
     Aborted,
 }
 
+/// MMIO remap error variants.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MMIOMapError {
+    RegionExhausted,
+    Other(&'static str),
+}
+
+/// Usage of the kernel's MMIO region, in pages.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MMIOStats {
+    pub num_pages_used: usize,
+    pub num_pages_total: usize,
+}
+
+/// Iterator over the physically contiguous runs of a virtual range.
+///
+/// Returned by `virt_to_phys_region()`.
+pub struct PhysRegionIter {
+    virt: Address<Virtual>,
+    remaining: usize,
+}
+
+/// The registers of a transmitter, for example a UART, that is written with the MMU switched off.
+///
+/// Each byte is written to `data_reg` once `flags_reg & busy_mask` reads as zero.
+#[allow(missing_docs)]
+pub struct PhysTransmitter {
+    pub data_reg: Address<Physical>,
+    pub flags_reg: Address<Physical>,
+    pub busy_mask: u32,
+}
+
 /// Memory Management interfaces.
 pub mod interface {
     use super::*;
@@ -49,6 +100,9 @@ pub mod interface {
     pub trait MMU {
         /// Turns on the MMU for the first time and enables data and instruction caching.
         ///
+        /// Meant for the boot core. The translation regime's configuration is kept for the
+        /// secondary cores, which only apply it.
+        ///
         /// # Safety
         ///
         /// - Changes the HW's global state.
@@ -67,12 +121,12 @@ pub mod interface {
             &self,
             virt: Address<Virtual>,
         ) -> Result<Address<Physical>, TranslationError>;
+
+        /// Returns true if there exists a valid mapping for the input VA that permits writes.
+        fn is_virt_writable(&self, virt: Address<Virtual>) -> bool;
     }
 }
 
-/// Describes the characteristics of a translation granule.
-pub struct TranslationGranule<const GRANULE_SIZE: usize>;
-
 /// Describes properties of an address space.
 pub struct AddressSpace<const AS_SIZE: usize>;
 
@@ -89,6 +143,13 @@ pub trait AssociatedTranslationTable {
     type TableStartFromBottom;
 }
 
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// Set while the kernel's translation tables are being changed.
+static KERNEL_TABLES_IN_FLUX: AtomicBool = AtomicBool::new(false);
+
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
@@ -96,6 +157,16 @@ use interface::MMU;
 use synchronization::interface::ReadWriteEx;
 use translation_table::interface::TranslationTable;
 
+/// Change the kernel's translation tables in `f`, and have `kernel_tables_in_flux()` report it
+/// meanwhile.
+fn kernel_tables_update<T>(f: impl FnOnce() -> T) -> T {
+    KERNEL_TABLES_IN_FLUX.store(true, Ordering::Release);
+    let ret = f();
+    KERNEL_TABLES_IN_FLUX.store(false, Ordering::Release);
+
+    ret
+}
+
 /// Map pages in the kernel's translation tables.
 ///
 /// No input checks done, input is passed through to the architectural implementation.
@@ -110,14 +181,54 @@ unsafe fn kernel_map_pages_at_unchecked(
     phys_pages: &PageSliceDescriptor<Physical>,
     attr: &AttributeFields,
 ) -> Result<(), &'static str> {
-    bsp::memory::mmu::kernel_translation_tables()
-        .write(|tables| tables.map_pages_at(virt_pages, phys_pages, attr))?;
-
+    let result = bsp::memory::mmu::kernel_translation_tables()
+        .write(|tables| kernel_tables_update(|| tables.map_pages_at(virt_pages, phys_pages, attr)));
+
+    #[cfg(feature = "mmu_trace")]
+    trace::record(
+        trace::TraceOp::Map,
+        virt_pages,
+        Some(phys_pages),
+        Some(attr),
+        result,
+    );
+
+    result?;
     kernel_add_mapping_record(name, virt_pages, phys_pages, attr);
 
     Ok(())
 }
 
+/// Return true if `page_ok` holds for each page touched by the `size` bytes starting at `start`.
+fn is_virt_range_accessible(
+    start: Address<Virtual>,
+    size: usize,
+    page_ok: impl Fn(Address<Virtual>) -> bool,
+) -> bool {
+    use bsp::memory::mmu::KernelGranule;
+
+    if size == 0 {
+        return true;
+    }
+
+    let end_inclusive = match start.into_usize().checked_add(size - 1) {
+        None => return false,
+        Some(x) => x,
+    };
+
+    let mut page = start.align_down(KernelGranule::SIZE).into_usize();
+    loop {
+        if !page_ok(Address::new(page)) {
+            return false;
+        }
+
+        page = match page.checked_add(KernelGranule::SIZE) {
+            Some(x) if x <= end_inclusive => x,
+            _ => return true,
+        };
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -131,20 +242,36 @@ impl fmt::Display for MMUEnableError {
     }
 }
 
-impl<const GRANULE_SIZE: usize> TranslationGranule<GRANULE_SIZE> {
-    /// The granule's size.
-    pub const SIZE: usize = Self::size_checked();
-
-    /// The granule's mask.
-    pub const MASK: usize = Self::SIZE - 1;
+impl fmt::Display for TranslationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranslationError::MMUDisabled => write!(f, "MMU is disabled"),
+            TranslationError::Aborted => write!(f, "Address is not mapped"),
+        }
+    }
+}
 
-    /// The granule's shift, aka log2(size).
-    pub const SHIFT: usize = Self::SIZE.trailing_zeros() as usize;
+impl fmt::Display for MMIOMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MMIOMapError::RegionExhausted => write!(f, "Not enough MMIO space left"),
+            MMIOMapError::Other(x) => write!(f, "{}", x),
+        }
+    }
+}
 
-    const fn size_checked() -> usize {
-        assert!(GRANULE_SIZE.is_power_of_two());
+impl From<&'static str> for MMIOMapError {
+    fn from(x: &'static str) -> Self {
+        MMIOMapError::Other(x)
+    }
+}
 
-        GRANULE_SIZE
+impl From<MMIOMapError> for &'static str {
+    fn from(x: MMIOMapError) -> Self {
+        match x {
+            MMIOMapError::RegionExhausted => "Not enough MMIO space left",
+            MMIOMapError::Other(x) => x,
+        }
     }
 }
 
@@ -177,6 +304,16 @@ pub fn kernel_add_mapping_record(
     }
 }
 
+/// Check that the kernel translation tables map the given pages as `kernel_map_pages_at()` would.
+pub fn kernel_verify_pages_at(
+    virt_pages: &PageSliceDescriptor<Virtual>,
+    phys_pages: &PageSliceDescriptor<Physical>,
+    attr: &AttributeFields,
+) -> Result<(), &'static str> {
+    bsp::memory::mmu::kernel_translation_tables()
+        .read(|tables| tables.verify_pages_at(virt_pages, phys_pages, attr))
+}
+
 /// Raw mapping of virtual to physical pages in the kernel translation tables.
 ///
 /// Prevents mapping into the MMIO range of the tables.
@@ -197,14 +334,59 @@ pub unsafe fn kernel_map_pages_at(
         return Err("Attempt to manually map into MMIO region");
     }
 
+    // Memory above the ARM's share of the DRAM belongs to the VideoCore.
+    if (attr.mem_attributes != MemAttributes::Device)
+        && (phys_pages.end_addr() > bsp::memory::dram_end())
+    {
+        return Err("Attempt to map DRAM that belongs to the VideoCore");
+    }
+
     kernel_map_pages_at_unchecked(name, virt_pages, phys_pages, attr)?;
 
     Ok(())
 }
 
+/// Map the physical pages behind an already mapped kernel object a second time, at
+/// `new_virt_start`.
+///
+/// The physical pages are found by translating `existing_virt`, so the caller does not need to know
+/// them. Fails if `new_virt_start` is not page aligned, if any page of `existing_virt` is not
+/// mapped, or if the pages are not one physically contiguous run. Returns the new virtual pages.
+///
+/// # Safety
+///
+/// - See `kernel_map_pages_at()`. The new mapping is an alias of the existing one by design.
+pub unsafe fn kernel_remap_object_at(
+    name: &'static str,
+    existing_virt: &PageSliceDescriptor<Virtual>,
+    new_virt_start: Address<Virtual>,
+    attr: &AttributeFields,
+) -> Result<PageSliceDescriptor<Virtual>, &'static str> {
+    use bsp::memory::mmu::KernelGranule;
+
+    if new_virt_start.align_down(KernelGranule::SIZE) != new_virt_start {
+        return Err("Remap target is not page aligned");
+    }
+
+    let mut runs = virt_to_phys_region(existing_virt.start_addr(), existing_virt.size())
+        .map_err(|_| "Object to remap is not mapped")?;
+    let phys_start = match (runs.next(), runs.next()) {
+        (Some((start, _)), None) => start,
+        _ => return Err("Object to remap is not physically contiguous"),
+    };
+
+    let virt_pages = PageSliceDescriptor::from_addr(new_virt_start, existing_virt.num_pages());
+    let phys_pages = PageSliceDescriptor::from_addr(phys_start, existing_virt.num_pages());
+    kernel_map_pages_at(name, &virt_pages, &phys_pages, attr)?;
+
+    Ok(virt_pages)
+}
+
 /// MMIO remapping in the kernel translation tables.
 ///
-/// Typically used by device drivers.
+/// Typically used by device drivers. Mapping the same pages again returns the existing virtual
+/// address and adds `name` as a user. Pages that merely overlap an existing mapping get a mapping of
+/// their own.
 ///
 /// # Safety
 ///
@@ -212,10 +394,11 @@ pub unsafe fn kernel_map_pages_at(
 pub unsafe fn kernel_map_mmio(
     name: &'static str,
     mmio_descriptor: &MMIODescriptor,
-) -> Result<Address<Virtual>, &'static str> {
+) -> Result<Address<Virtual>, MMIOMapError> {
     let phys_pages: PageSliceDescriptor<Physical> = (*mmio_descriptor).into();
-    let offset_into_start_page =
-        mmio_descriptor.start_addr().into_usize() & bsp::memory::mmu::KernelGranule::MASK;
+    let offset_into_start_page = mmio_descriptor
+        .start_addr()
+        .offset_from(phys_pages.start_addr());
 
     // Check if an identical page slice has been mapped for another driver. If so, reuse it.
     let virt_addr = if let Some(addr) =
@@ -225,8 +408,14 @@ pub unsafe fn kernel_map_mmio(
     // Otherwise, allocate a new virtual page slice and map it.
     } else {
         let virt_pages: PageSliceDescriptor<Virtual> =
-            bsp::memory::mmu::kernel_translation_tables()
-                .write(|tables| tables.next_mmio_virt_page_slice(phys_pages.num_pages()))?;
+            bsp::memory::mmu::kernel_translation_tables().write(|tables| {
+                let (used, total) = tables.mmio_stats();
+                if phys_pages.num_pages() > (total - used) {
+                    return Err(MMIOMapError::RegionExhausted);
+                }
+
+                Ok(tables.next_mmio_virt_page_slice(phys_pages.num_pages())?)
+            })?;
 
         kernel_map_pages_at_unchecked(
             name,
@@ -242,7 +431,129 @@ pub unsafe fn kernel_map_mmio(
         virt_pages.start_addr()
     };
 
-    Ok(virt_addr + offset_into_start_page)
+    virt_addr
+        .checked_add(offset_into_start_page)
+        .ok_or(MMIOMapError::Other(
+            "MMIO mapping ends above the address space",
+        ))
+}
+
+/// Like `kernel_map_mmio()`, but returns a guard that knows the mapping's size and drops `name`'s
+/// use of it when it goes out of scope.
+///
+/// # Safety
+///
+/// - See `kernel_map_mmio()`.
+pub unsafe fn kernel_map_mmio_region(
+    name: &'static str,
+    mmio_descriptor: &MMIODescriptor,
+) -> Result<MappedMmio, MMIOMapError> {
+    MappedMmio::new(name, mmio_descriptor)
+}
+
+/// Drop `name`'s use of an MMIO mapping obtained from `kernel_map_mmio()`.
+///
+/// The pages are unmapped once the last user is gone. Their virtual space is reclaimed if no newer
+/// MMIO slice was handed out since, so mappings should be dropped in reverse order.
+///
+/// # Safety
+///
+/// - The caller must ensure that the mapping is not referenced anymore by `name`.
+pub unsafe fn kernel_unmap_mmio(
+    name: &'static str,
+    mmio_descriptor: &MMIODescriptor,
+) -> Result<(), &'static str> {
+    let virt_start_addr = match mapping_record::kernel_remove_mmio_user(mmio_descriptor, name)? {
+        None => return Ok(()),
+        Some(x) => x,
+    };
+
+    let phys_pages: PageSliceDescriptor<Physical> = (*mmio_descriptor).into();
+    let virt_pages = PageSliceDescriptor::from_addr(virt_start_addr, phys_pages.num_pages());
+
+    let result = bsp::memory::mmu::kernel_translation_tables().write(|tables| {
+        kernel_tables_update(|| tables.unmap_pages_at(&virt_pages))?;
+        tables.free_mmio_virt_page_slice(&virt_pages);
+
+        Ok(())
+    });
+
+    #[cfg(feature = "mmu_trace")]
+    trace::record(trace::TraceOp::Unmap, &virt_pages, None, None, result);
+
+    result
+}
+
+/// Usage of the kernel's MMIO region.
+pub fn kernel_mmio_stats() -> MMIOStats {
+    let (num_pages_used, num_pages_total) =
+        bsp::memory::mmu::kernel_translation_tables().read(|tables| tables.mmio_stats());
+
+    MMIOStats {
+        num_pages_used,
+        num_pages_total,
+    }
+}
+
+/// Obtain a virtual page slice in the MMIO region without mapping it.
+///
+/// The slice serves as a window that can be pointed at different physical pages over time using
+/// `kernel_map_window()` and `kernel_unmap_window()`. Window mappings are not recorded.
+pub fn kernel_alloc_window(num_pages: usize) -> Result<PageSliceDescriptor<Virtual>, &'static str> {
+    bsp::memory::mmu::kernel_translation_tables()
+        .write(|tables| tables.next_mmio_virt_page_slice(num_pages))
+}
+
+/// Point a window obtained from `kernel_alloc_window()` at the given physical pages.
+///
+/// # Safety
+///
+/// - See `kernel_map_pages_at_unchecked()`.
+pub unsafe fn kernel_map_window(
+    virt_pages: &PageSliceDescriptor<Virtual>,
+    phys_pages: &PageSliceDescriptor<Physical>,
+    attr: &AttributeFields,
+) -> Result<(), &'static str> {
+    let result = bsp::memory::mmu::kernel_translation_tables().write(|tables| {
+        if !tables.is_virt_page_slice_mmio(virt_pages) {
+            return Err("Window is not in the MMIO region");
+        }
+
+        kernel_tables_update(|| tables.map_pages_at(virt_pages, phys_pages, attr))
+    });
+
+    #[cfg(feature = "mmu_trace")]
+    trace::record(
+        trace::TraceOp::MapWindow,
+        virt_pages,
+        Some(phys_pages),
+        Some(attr),
+        result,
+    );
+
+    result
+}
+
+/// Unmap a window that was mapped with `kernel_map_window()`.
+///
+/// # Safety
+///
+/// - The caller must ensure that the window is not referenced anymore.
+pub unsafe fn kernel_unmap_window(
+    virt_pages: &PageSliceDescriptor<Virtual>,
+) -> Result<(), &'static str> {
+    let result = bsp::memory::mmu::kernel_translation_tables().write(|tables| {
+        if !tables.is_virt_page_slice_mmio(virt_pages) {
+            return Err("Window is not in the MMIO region");
+        }
+
+        kernel_tables_update(|| tables.unmap_pages_at(virt_pages))
+    });
+
+    #[cfg(feature = "mmu_trace")]
+    trace::record(trace::TraceOp::UnmapWindow, virt_pages, None, None, result);
+
+    result
 }
 
 /// Try to translate a virtual address to a physical address.
@@ -252,6 +563,53 @@ pub fn try_virt_to_phys(virt: Address<Virtual>) -> Result<Address<Physical>, Tra
     arch_mmu::mmu().try_virt_to_phys(virt)
 }
 
+/// Translate the virtual range of `len` bytes starting at `virt` into physically contiguous runs.
+///
+/// Each page is translated once, instead of each address, so this is the way to build
+/// scatter-gather lists for DMA. The runs are yielded as `(start, size)`, in the order of the
+/// virtual range, and adjacent runs are merged as far as possible. Fails if any of the pages is not
+/// mapped.
+pub fn virt_to_phys_region(
+    virt: Address<Virtual>,
+    len: usize,
+) -> Result<PhysRegionIter, TranslationError> {
+    use bsp::memory::mmu::KernelGranule;
+
+    if len > 0 {
+        let mut page = virt.align_down(KernelGranule::SIZE);
+        let end_inclusive = virt + (len - 1);
+
+        while page <= end_inclusive {
+            try_virt_to_phys(page)?;
+
+            match page.into_usize().checked_add(KernelGranule::SIZE) {
+                None => break,
+                Some(x) => page = Address::new(x),
+            }
+        }
+    }
+
+    Ok(PhysRegionIter {
+        virt,
+        remaining: len,
+    })
+}
+
+/// Return true if all of the `size` bytes starting at `start` are mapped and readable.
+///
+/// Used to check addresses that come from outside of the kernel's control, for example, from the
+/// console, before touching them.
+pub fn is_virt_range_readable(start: Address<Virtual>, size: usize) -> bool {
+    is_virt_range_accessible(start, size, |page| try_virt_to_phys(page).is_ok())
+}
+
+/// Return true if all of the `size` bytes starting at `start` are mapped and writable.
+///
+/// The write counterpart of [`is_virt_range_readable()`].
+pub fn is_virt_range_writable(start: Address<Virtual>, size: usize) -> bool {
+    is_virt_range_accessible(start, size, |page| arch_mmu::mmu().is_virt_writable(page))
+}
+
 /// Enable the MMU and data + instruction caching.
 ///
 /// # Safety
@@ -264,7 +622,202 @@ pub unsafe fn enable_mmu_and_caching(
     arch_mmu::mmu().enable_mmu_and_caching(phys_tables_base_addr)
 }
 
+/// Map the pages that hold the kernel's translation tables read-only, if enabled by
+/// `config::memory::READ_ONLY_KERNEL_TABLES`.
+///
+/// Only the pages that hold the descriptors of the tables themselves stay writable. The functions
+/// that change the tables map the page that they write to writable for the duration of the write.
+///
+/// # Safety
+///
+/// - Must be called once, during kernel init, after the MMU was enabled.
+pub unsafe fn kernel_write_protect_tables() -> Result<(), &'static str> {
+    if !config::memory::READ_ONLY_KERNEL_TABLES {
+        return Ok(());
+    }
+
+    let own_pages = bsp::memory::mmu::virt_kernel_tables_page_desc();
+
+    bsp::memory::mmu::kernel_translation_tables()
+        .write(|tables| kernel_tables_update(|| tables.write_protect(&own_pages)))
+}
+
+/// Returns true while the kernel's translation tables are being changed.
+///
+/// A fault in this window may have hit a mapping that is only half updated, so fatal error paths
+/// check this before relying on virtual MMIO mappings.
+pub fn kernel_tables_in_flux() -> bool {
+    KERNEL_TABLES_IN_FLUX.load(Ordering::Acquire)
+}
+
+/// Write `bytes` to `tx` with the MMU switched off, for when the MMIO mappings cannot be trusted.
+///
+/// The bytes are translated while the MMU is still on, and written one physically contiguous run
+/// at a time.
+///
+/// # Safety
+///
+/// - IRQs must be masked.
+/// - See `enable_identity_mapping()`.
+pub unsafe fn write_with_mmu_disabled(
+    tx: &PhysTransmitter,
+    bytes: &[u8],
+) -> Result<(), &'static str> {
+    let runs = virt_to_phys_region(Address::new(bytes.as_ptr() as usize), bytes.len())
+        .map_err(|_| "Bytes are not mapped")?;
+
+    for (start, size) in runs {
+        arch_mmu::write_with_mmu_disabled(tx, start, size)?;
+    }
+
+    Ok(())
+}
+
+/// The recorded kernel mapping that contains `virt_addr`, if any.
+///
+/// Named after the mapping's first user.
+pub fn kernel_find_mapping(virt_addr: Address<Virtual>) -> Option<TranslationDescriptor> {
+    mapping_record::kernel_find(virt_addr)
+}
+
+/// Call `f` with each recorded kernel mapping.
+pub fn kernel_for_each_mapping(f: impl FnMut(&TranslationDescriptor)) {
+    mapping_record::kernel_for_each(f)
+}
+
 /// Human-readable print of all recorded kernel mappings.
 pub fn kernel_print_mappings() {
     mapping_record::kernel_print()
 }
+
+impl Iterator for PhysRegionIter {
+    type Item = (Address<Physical>, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use bsp::memory::mmu::KernelGranule;
+
+        if self.remaining == 0 {
+            return None;
+        }
+
+        // The pages were checked when the iterator was created.
+        let start = try_virt_to_phys(self.virt).ok()?;
+
+        // Extend the run page by page, for as long as the physical pages are adjacent.
+        let to_page_end = KernelGranule::SIZE - (self.virt.into_usize() & KernelGranule::MASK);
+        let mut size = to_page_end.min(self.remaining);
+        while size < self.remaining {
+            match try_virt_to_phys(self.virt + size) {
+                Ok(x) if x == start + size => (),
+                _ => break,
+            }
+
+            size += KernelGranule::SIZE.min(self.remaining - size);
+        }
+
+        self.virt = self.virt + size;
+        self.remaining -= size;
+
+        Some((start, size))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// The boot core's stack is one physically contiguous run, and its guard page is not mapped.
+    #[kernel_test]
+    fn virt_to_phys_region_merges_runs() {
+        let guard_page = bsp::memory::mmu::virt_boot_core_stack_guard_page_desc();
+        let stack_start = guard_page.end_addr();
+        let len = 3 * bsp::memory::mmu::KernelGranule::SIZE + 0x10;
+
+        let mut runs = virt_to_phys_region(stack_start + 0x100, len).unwrap();
+        let (start, size) = runs.next().unwrap();
+        assert!(start == try_virt_to_phys(stack_start + 0x100).unwrap());
+        assert_eq!(size, len);
+        assert!(runs.next().is_none());
+
+        assert!(virt_to_phys_region(guard_page.start_addr(), 0x10).is_err());
+        assert!(virt_to_phys_region(stack_start - 0x10, 0x20).is_err());
+        assert!(virt_to_phys_region(guard_page.start_addr(), 0)
+            .unwrap()
+            .next()
+            .is_none());
+    }
+
+    /// The kernel is entered at its virtual address, so nothing of it is left identity mapped
+    /// after boot.
+    #[kernel_test]
+    fn kernel_is_not_identity_mapped() {
+        let code = Address::<Virtual>::new(kernel_is_not_identity_mapped as *const () as usize);
+        let phys_code = try_virt_to_phys(code).unwrap();
+
+        assert!(try_virt_to_phys(Address::new(phys_code.into_usize())).is_err());
+    }
+
+    /// With read-only tables, mapping and unmapping still works, and leaves the tables read-only.
+    #[kernel_test]
+    fn read_only_tables_can_be_changed() {
+        use bsp::memory::mmu::KernelGranule;
+
+        if !config::memory::READ_ONLY_KERNEL_TABLES {
+            return;
+        }
+
+        // The last page holds the lvl2 descriptors and the MMIO allocator's state.
+        let tables = bsp::memory::mmu::virt_kernel_tables_page_desc();
+        let last_page = tables.end_addr() - KernelGranule::SIZE;
+        assert!(is_virt_range_readable(last_page, KernelGranule::SIZE));
+        assert!(!is_virt_range_writable(last_page, KernelGranule::SIZE));
+
+        let guard_page = bsp::memory::mmu::virt_boot_core_stack_guard_page_desc();
+        let phys =
+            PageSliceDescriptor::from_addr(try_virt_to_phys(guard_page.end_addr()).unwrap(), 1);
+        let attr = AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadOnly,
+            execute_never: true,
+        };
+
+        let window = kernel_alloc_window(1).unwrap();
+        unsafe {
+            assert!(kernel_map_window(&window, &phys, &attr).is_ok());
+            assert!(try_virt_to_phys(window.start_addr()) == Ok(phys.start_addr()));
+            assert!(kernel_unmap_window(&window).is_ok());
+        }
+        assert!(try_virt_to_phys(window.start_addr()).is_err());
+
+        assert!(!is_virt_range_writable(last_page, KernelGranule::SIZE));
+    }
+
+    /// Remapping checks the target's alignment and that the object is mapped, before touching the
+    /// tables.
+    #[kernel_test]
+    fn remap_object_rejects_bad_input() {
+        let guard_page = bsp::memory::mmu::virt_boot_core_stack_guard_page_desc();
+        let stack_page = PageSliceDescriptor::from_addr(guard_page.end_addr(), 1);
+        let attr = AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadOnly,
+            execute_never: true,
+        };
+
+        let unaligned = guard_page.start_addr() + 0x10;
+        assert_eq!(
+            unsafe { kernel_remap_object_at("Test", &stack_page, unaligned, &attr) }.unwrap_err(),
+            "Remap target is not page aligned"
+        );
+        assert_eq!(
+            unsafe { kernel_remap_object_at("Test", &guard_page, guard_page.start_addr(), &attr) }
+                .unwrap_err(),
+            "Object to remap is not mapped"
+        );
+    }
+}
@@ -6,9 +6,16 @@
 
 use super::{
     AccessPermissions, Address, AttributeFields, MMIODescriptor, MemAttributes,
-    PageSliceDescriptor, Physical, Virtual,
+    PageSliceDescriptor, Physical, TranslationDescriptor, Virtual,
+};
+use crate::{
+    config,
+    fmt::table::{Column, Table},
+    synchronization,
+    synchronization::InitStateLock,
+    units::ByteSize,
+    warn,
 };
-use crate::{info, synchronization, synchronization::InitStateLock, warn};
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
@@ -18,14 +25,14 @@ use crate::{info, synchronization, synchronization::InitStateLock, warn};
 #[allow(missing_docs)]
 #[derive(Copy, Clone)]
 struct MappingRecordEntry {
-    pub users: [Option<&'static str>; 5],
+    pub users: [Option<&'static str>; config::memory::MAPPING_RECORD_USERS_PER_ENTRY],
     pub phys_pages: PageSliceDescriptor<Physical>,
     pub virt_start_addr: Address<Virtual>,
     pub attribute_fields: AttributeFields,
 }
 
 struct MappingRecord {
-    inner: [Option<MappingRecordEntry>; 12],
+    inner: [Option<MappingRecordEntry>; config::memory::MAPPING_RECORD_ENTRIES],
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -46,8 +53,11 @@ impl MappingRecordEntry {
         phys_pages: &PageSliceDescriptor<Physical>,
         attr: &AttributeFields,
     ) -> Self {
+        let mut users = [None; config::memory::MAPPING_RECORD_USERS_PER_ENTRY];
+        users[0] = Some(name);
+
         Self {
-            users: [Some(name), None, None, None, None],
+            users,
             phys_pages: *phys_pages,
             virt_start_addr: virt_pages.start_addr(),
             attribute_fields: *attr,
@@ -67,11 +77,44 @@ impl MappingRecordEntry {
         *x = Some(user);
         Ok(())
     }
+
+    /// Remove `user`, keeping the remaining users packed at the front.
+    pub fn remove_user(&mut self, user: &'static str) -> Result<(), &'static str> {
+        let i = self
+            .users
+            .iter()
+            .position(|x| *x == Some(user))
+            .ok_or("Mapping has no such user")?;
+
+        self.users[i..].rotate_left(1);
+        *self.users.last_mut().unwrap() = None;
+
+        Ok(())
+    }
+
+    pub fn has_users(&self) -> bool {
+        self.users[0].is_some()
+    }
+
+    /// The entry as a descriptor, named after its first user.
+    pub fn translation_descriptor(&self) -> TranslationDescriptor {
+        TranslationDescriptor {
+            name: self.users[0].unwrap_or("Unknown"),
+            virt_pages: PageSliceDescriptor::from_addr(
+                self.virt_start_addr,
+                self.phys_pages.num_pages(),
+            ),
+            phys_pages: self.phys_pages,
+            attribute_fields: self.attribute_fields,
+        }
+    }
 }
 
 impl MappingRecord {
     pub const fn new() -> Self {
-        Self { inner: [None; 12] }
+        Self {
+            inner: [None; config::memory::MAPPING_RECORD_ENTRIES],
+        }
     }
 
     fn find_next_free(&mut self) -> Result<&mut Option<MappingRecordEntry>, &'static str> {
@@ -107,31 +150,58 @@ impl MappingRecord {
         Ok(())
     }
 
-    pub fn print(&self) {
-        const KIB_RSHIFT: u32 = 10; // log2(1024).
-        const MIB_RSHIFT: u32 = 20; // log2(1024 * 1024).
+    /// Remove `user` from the device mapping of `phys_pages`.
+    ///
+    /// If it was the last user, the entry is dropped and its virtual start address returned.
+    pub fn remove_mmio_user(
+        &mut self,
+        phys_pages: &PageSliceDescriptor<Physical>,
+        user: &'static str,
+    ) -> Result<Option<Address<Virtual>>, &'static str> {
+        let slot = self
+            .inner
+            .iter_mut()
+            .find(|x| match x {
+                Some(x) => {
+                    x.attribute_fields.mem_attributes == MemAttributes::Device
+                        && x.phys_pages == *phys_pages
+                }
+                None => false,
+            })
+            .ok_or("No MMIO mapping for these pages")?;
+
+        let entry = slot.as_mut().unwrap();
+        entry.remove_user(user)?;
+        if entry.has_users() {
+            return Ok(None);
+        }
+
+        let virt_start_addr = entry.virt_start_addr;
+        *slot = None;
+
+        Ok(Some(virt_start_addr))
+    }
+
+    /// The mapping that contains `virt_addr`, if any.
+    pub fn find(&self, virt_addr: Address<Virtual>) -> Option<TranslationDescriptor> {
+        self.inner
+            .iter()
+            .flatten()
+            .map(|x| x.translation_descriptor())
+            .find(|x| x.virt_pages.contains(virt_addr))
+    }
 
-        info!("      -------------------------------------------------------------------------------------------------------------------------------------------");
-        info!(
-            "      {:^44}     {:^30}   {:^7}   {:^9}   {:^35}",
-            "Virtual", "Physical", "Size", "Attr", "Entity"
-        );
-        info!("      -------------------------------------------------------------------------------------------------------------------------------------------");
+    pub fn print(&self) {
+        let mut table = Table::new([
+            Column::left("Virtual"),
+            Column::left("Physical"),
+            Column::right("Size"),
+            Column::left("Attr"),
+            Column::left("Entity"),
+        ]);
 
         for i in self.inner.iter().flatten() {
-            let virt_start = i.virt_start_addr;
-            let virt_end_inclusive = virt_start + i.phys_pages.size() - 1;
-            let phys_start = i.phys_pages.start_addr();
-            let phys_end_inclusive = i.phys_pages.end_addr_inclusive();
-            let size = i.phys_pages.size();
-
-            let (size, unit) = if (size >> MIB_RSHIFT) > 0 {
-                (size >> MIB_RSHIFT, "MiB")
-            } else if (size >> KIB_RSHIFT) > 0 {
-                (size >> KIB_RSHIFT, "KiB")
-            } else {
-                (size, "Byte")
-            };
+            let virt_pages = i.translation_descriptor().virt_pages;
 
             let attr = match i.attribute_fields.mem_attributes {
                 MemAttributes::CacheableDRAM => "C",
@@ -149,32 +219,30 @@ impl MappingRecord {
                 "X"
             };
 
-            info!(
-                "      {}..{} --> {}..{} | \
-                        {: >3} {} | {: <3} {} {: <2} | {}",
-                virt_start,
-                virt_end_inclusive,
-                phys_start,
-                phys_end_inclusive,
-                size,
-                unit,
-                attr,
-                acc_p,
-                xn,
-                i.users[0].unwrap()
-            );
-
-            for k in i.users[1..].iter() {
-                if let Some(additional_user) = *k {
-                    info!(
-                        "                                                                                                            | {}",
-                        additional_user
-                    );
-                }
+            table.row(|r| {
+                r.cell(format_args!(
+                    "{}..{}",
+                    virt_pages.start_addr(),
+                    virt_pages.end_addr_inclusive()
+                ))
+                .cell(format_args!(
+                    "{}..{}",
+                    i.phys_pages.start_addr(),
+                    i.phys_pages.end_addr_inclusive()
+                ))
+                .cell(ByteSize(i.phys_pages.size()))
+                .cell(format_args!("{:<3} {} {}", attr, acc_p, xn))
+                .cell(i.users[0].unwrap());
+            });
+
+            for additional_user in i.users[1..].iter().flatten() {
+                table.row(|r| {
+                    r.cell("").cell("").cell("").cell("").cell(additional_user);
+                });
             }
         }
 
-        info!("      -------------------------------------------------------------------------------------------------------------------------------------------");
+        table.print();
     }
 }
 
@@ -210,7 +278,135 @@ pub fn kernel_find_and_insert_mmio_duplicate(
     })
 }
 
+/// Remove a user from an MMIO mapping.
+///
+/// Returns the mapping's virtual start address if it has no users left.
+pub fn kernel_remove_mmio_user(
+    mmio_descriptor: &MMIODescriptor,
+    user: &'static str,
+) -> Result<Option<Address<Virtual>>, &'static str> {
+    let phys_pages: PageSliceDescriptor<Physical> = (*mmio_descriptor).into();
+
+    KERNEL_MAPPING_RECORD.write(|mr| mr.remove_mmio_user(&phys_pages, user))
+}
+
+/// The recorded kernel mapping that contains `virt_addr`, if any.
+pub fn kernel_find(virt_addr: Address<Virtual>) -> Option<TranslationDescriptor> {
+    KERNEL_MAPPING_RECORD.read(|mr| mr.find(virt_addr))
+}
+
+/// Call `f` with each recorded kernel mapping.
+pub fn kernel_for_each(mut f: impl FnMut(&TranslationDescriptor)) {
+    KERNEL_MAPPING_RECORD.read(|mr| {
+        for x in mr.inner.iter().flatten() {
+            f(&x.translation_descriptor())
+        }
+    });
+}
+
 /// Human-readable print of all recorded kernel mappings.
 pub fn kernel_print() {
     KERNEL_MAPPING_RECORD.read(|mr| mr.print());
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(all(test, not(target_os = "none")))]
+mod host_tests {
+    use super::*;
+
+    const DEVICE: AttributeFields = AttributeFields {
+        mem_attributes: MemAttributes::Device,
+        acc_perms: AccessPermissions::ReadWrite,
+        execute_never: true,
+    };
+
+    const DRAM: AttributeFields = AttributeFields {
+        mem_attributes: MemAttributes::CacheableDRAM,
+        acc_perms: AccessPermissions::ReadWrite,
+        execute_never: true,
+    };
+
+    fn pages(addr: usize) -> (PageSliceDescriptor<Virtual>, PageSliceDescriptor<Physical>) {
+        (
+            PageSliceDescriptor::from_addr(Address::new(addr + 0x1_0000_0000), 1),
+            PageSliceDescriptor::from_addr(Address::new(addr), 1),
+        )
+    }
+
+    /// Only device mappings are shared between users.
+    #[test]
+    fn duplicates_are_found_for_device_memory_only() {
+        let mut mr = MappingRecord::new();
+
+        let (virt, phys) = pages(0x3F20_0000);
+        mr.add("GPIO", &virt, &phys, &DEVICE).unwrap();
+        assert!(mr.find_duplicate(&phys).unwrap().virt_start_addr == virt.start_addr());
+
+        let (virt, phys) = pages(0x8_0000);
+        mr.add("Kernel", &virt, &phys, &DRAM).unwrap();
+        assert!(mr.find_duplicate(&phys).is_none());
+    }
+
+    /// An entry has room for the configured number of users.
+    #[test]
+    fn users_are_exhausted() {
+        let (virt, phys) = pages(0x3F20_0000);
+        let mut entry = MappingRecordEntry::new("User", &virt, &phys, &DEVICE);
+
+        for _ in 1..config::memory::MAPPING_RECORD_USERS_PER_ENTRY {
+            entry.add_user("User").unwrap();
+        }
+        assert!(entry.add_user("User").is_err());
+    }
+
+    /// The entry goes away with its last user.
+    #[test]
+    fn mmio_users_are_removed() {
+        let mut mr = MappingRecord::new();
+
+        let (virt, phys) = pages(0x3F20_0000);
+        mr.add("GPIO", &virt, &phys, &DEVICE).unwrap();
+        mr.find_duplicate(&phys).unwrap().add_user("UART").unwrap();
+
+        assert!(mr.remove_mmio_user(&phys, "Timer").is_err());
+        assert!(mr.remove_mmio_user(&phys, "GPIO").unwrap().is_none());
+        assert!(mr.find_duplicate(&phys).unwrap().users[0] == Some("UART"));
+        assert!(mr.remove_mmio_user(&phys, "UART").unwrap().unwrap() == virt.start_addr());
+        assert!(mr.find_duplicate(&phys).is_none());
+    }
+
+    /// Addresses are looked up by virtual page, and the entry is named after its first user.
+    #[test]
+    fn mappings_are_found_by_virtual_address() {
+        let mut mr = MappingRecord::new();
+
+        let (virt, phys) = pages(0x3F20_0000);
+        mr.add("GPIO", &virt, &phys, &DEVICE).unwrap();
+
+        let found = mr.find(virt.start_addr() + 0x94).unwrap();
+        assert_eq!(found.name, "GPIO");
+        assert!(found.virt_pages == virt);
+        assert!(mr
+            .find(Address::new(phys.start_addr().into_usize()))
+            .is_none());
+        assert!(mr.find(virt.end_addr()).is_none());
+    }
+
+    /// The record has room for the configured number of entries.
+    #[test]
+    fn record_is_exhausted() {
+        let mut mr = MappingRecord::new();
+        let num_entries = config::memory::MAPPING_RECORD_ENTRIES;
+
+        for i in 0..num_entries {
+            let (virt, phys) = pages(i * 0x1_0000);
+            mr.add("Entry", &virt, &phys, &DRAM).unwrap();
+        }
+
+        let (virt, phys) = pages(num_entries * 0x1_0000);
+        assert!(mr.add("Entry", &virt, &phys, &DRAM).is_err());
+    }
+}
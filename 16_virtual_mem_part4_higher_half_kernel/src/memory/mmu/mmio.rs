@@ -0,0 +1,188 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! MMIO mappings that know their size and unmap themselves.
+
+use super::{MMIODescriptor, MMIOMapError};
+use crate::{
+    memory::{Address, Virtual},
+    warn,
+};
+use core::{mem, ops, ptr};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A mapped MMIO range, with volatile accessors that are checked against its bounds.
+///
+/// Not `Clone`, so that a region can not be copied out of a [`MappedMmio`] and outlive the mapping.
+pub struct MmioRegion {
+    start_addr: Address<Virtual>,
+    size: usize,
+
+    // The counters of the name that the region was mapped for.
+    #[cfg(feature = "mmio_stats")]
+    counters: Option<&'static super::mmio_access::MmioCounters>,
+}
+
+/// An MMIO mapping that is unmapped when the value is dropped.
+///
+/// Returned by `kernel_map_mmio_region()`. Dropping goes through `kernel_unmap_mmio()`, so it
+/// removes this user from the mapping record and unmaps the pages once the last user is gone. Like
+/// `kernel_unmap_mmio()`, it may only happen during kernel init. Drivers that keep their registers
+/// for good call `leak()`.
+pub struct MappedMmio {
+    region: MmioRegion,
+
+    // The key of the user in the mapping record.
+    name: &'static str,
+    mmio_descriptor: MMIODescriptor,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl MmioRegion {
+    /// A pointer to a `T` at `offset`, if it lies within the range and is naturally aligned.
+    fn ptr<T>(&self, offset: usize) -> Result<*mut T, &'static str> {
+        let end = offset
+            .checked_add(mem::size_of::<T>())
+            .ok_or("MMIO access out of range")?;
+        if end > self.size {
+            return Err("MMIO access out of range");
+        }
+
+        let addr = self.start_addr.into_usize() + offset;
+        if addr % mem::align_of::<T>() != 0 {
+            return Err("Unaligned MMIO access");
+        }
+
+        Ok(addr as *mut T)
+    }
+
+    /// Count a read. Compiled out without the `mmio_stats` feature.
+    #[inline(always)]
+    fn count_read(&self) {
+        #[cfg(feature = "mmio_stats")]
+        if let Some(x) = self.counters {
+            x.count_read()
+        }
+    }
+
+    /// Count a write. Compiled out without the `mmio_stats` feature.
+    #[inline(always)]
+    fn count_write(&self) {
+        #[cfg(feature = "mmio_stats")]
+        if let Some(x) = self.counters {
+            x.count_write()
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl MmioRegion {
+    /// The virtual start address.
+    pub fn start_addr(&self) -> Address<Virtual> {
+        self.start_addr
+    }
+
+    /// The size in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Read the 32 bit register at `offset`.
+    pub fn read32(&self, offset: usize) -> Result<u32, &'static str> {
+        let p = self.ptr::<u32>(offset)?;
+        self.count_read();
+
+        Ok(unsafe { ptr::read_volatile(p) })
+    }
+
+    /// Write the 32 bit register at `offset`.
+    pub fn write32(&self, offset: usize, value: u32) -> Result<(), &'static str> {
+        let p = self.ptr::<u32>(offset)?;
+        self.count_write();
+        unsafe { ptr::write_volatile(p, value) };
+
+        Ok(())
+    }
+
+    /// Read the 64 bit register at `offset`.
+    pub fn read64(&self, offset: usize) -> Result<u64, &'static str> {
+        let p = self.ptr::<u64>(offset)?;
+        self.count_read();
+
+        Ok(unsafe { ptr::read_volatile(p) })
+    }
+
+    /// Write the 64 bit register at `offset`.
+    pub fn write64(&self, offset: usize, value: u64) -> Result<(), &'static str> {
+        let p = self.ptr::<u64>(offset)?;
+        self.count_write();
+        unsafe { ptr::write_volatile(p, value) };
+
+        Ok(())
+    }
+}
+
+impl MappedMmio {
+    /// Map `mmio_descriptor` for `name`.
+    ///
+    /// # Safety
+    ///
+    /// - See `kernel_map_mmio()`.
+    pub(super) unsafe fn new(
+        name: &'static str,
+        mmio_descriptor: &MMIODescriptor,
+    ) -> Result<Self, MMIOMapError> {
+        let start_addr = super::kernel_map_mmio(name, mmio_descriptor)?;
+
+        Ok(Self {
+            region: MmioRegion {
+                start_addr,
+                size: mmio_descriptor.size(),
+                #[cfg(feature = "mmio_stats")]
+                counters: super::mmio_access::counters_for(name),
+            },
+            name,
+            mmio_descriptor: *mmio_descriptor,
+        })
+    }
+
+    /// Keep the mapping for the rest of the kernel's life.
+    pub fn leak(self) -> MmioRegion {
+        let region = MmioRegion {
+            start_addr: self.region.start_addr,
+            size: self.region.size,
+            #[cfg(feature = "mmio_stats")]
+            counters: self.region.counters,
+        };
+        mem::forget(self);
+
+        region
+    }
+}
+
+impl ops::Deref for MappedMmio {
+    type Target = MmioRegion;
+
+    fn deref(&self) -> &Self::Target {
+        &self.region
+    }
+}
+
+impl Drop for MappedMmio {
+    fn drop(&mut self) {
+        // The region is only handed out by reference, so nothing refers to the mapping anymore.
+        if let Err(x) = unsafe { super::kernel_unmap_mmio(self.name, &self.mmio_descriptor) } {
+            warn!("{}: Unmapping MMIO failed: {}", self.name, x);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Counters of the register accesses through [`MmioRegion`](super::MmioRegion)s.
+//!
+//! The counters are kept per name, which is the name that a region was mapped for. All regions of
+//! a name share its counters. Registers that a driver reaches other than through a region are not
+//! counted.
+
+use crate::synchronization::{interface::Mutex, IRQSafeNullLock};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Number of names that can be counted. Regions of further names are not counted.
+const NUM_ENTRIES: usize = 16;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The counters of one name.
+pub struct MmioCounters {
+    reads: AtomicU64,
+    writes: AtomicU64,
+}
+
+/// The counted accesses of one name.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct MmioAccessCounts {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+const NO_COUNTERS: MmioCounters = MmioCounters::new();
+
+/// The counters, at the same index as their name in `NAMES`.
+static COUNTERS: [MmioCounters; NUM_ENTRIES] = [NO_COUNTERS; NUM_ENTRIES];
+
+/// Only locked when a region is mapped or the counts are read, not on register accesses.
+static NAMES: IRQSafeNullLock<[Option<&'static str>; NUM_ENTRIES]> =
+    IRQSafeNullLock::new([None; NUM_ENTRIES]);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl MmioCounters {
+    const fn new() -> Self {
+        Self {
+            reads: AtomicU64::new(0),
+            writes: AtomicU64::new(0),
+        }
+    }
+
+    fn counts(&self) -> MmioAccessCounts {
+        MmioAccessCounts {
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl MmioCounters {
+    /// Count a read.
+    #[inline(always)]
+    pub fn count_read(&self) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a write.
+    #[inline(always)]
+    pub fn count_write(&self) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl MmioAccessCounts {
+    /// The accesses that were counted since `earlier`.
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            reads: self.reads.wrapping_sub(earlier.reads),
+            writes: self.writes.wrapping_sub(earlier.writes),
+        }
+    }
+}
+
+/// The counters of `name`, which get a slot the first time they are asked for.
+///
+/// Returns `None` if all slots are taken by other names.
+pub fn counters_for(name: &'static str) -> Option<&'static MmioCounters> {
+    NAMES.lock(|names| {
+        let index = match names.iter().position(|x| *x == Some(name)) {
+            Some(i) => i,
+            None => {
+                let i = names.iter().position(|x| x.is_none())?;
+                names[i] = Some(name);
+                i
+            }
+        };
+
+        Some(&COUNTERS[index])
+    })
+}
+
+/// The counted accesses of `name`, or `None` if no region was mapped for it.
+pub fn access_counts(name: &str) -> Option<MmioAccessCounts> {
+    NAMES
+        .lock(|names| names.iter().position(|x| *x == Some(name)))
+        .map(|i| COUNTERS[i].counts())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// A name keeps its counters, and the counts only cover its own accesses.
+    #[kernel_test]
+    fn counters_are_kept_per_name() {
+        let a = counters_for("mmio_access test a").unwrap();
+        let b = counters_for("mmio_access test b").unwrap();
+        assert!(core::ptr::eq(
+            a,
+            counters_for("mmio_access test a").unwrap()
+        ));
+        assert!(!core::ptr::eq(a, b));
+
+        a.count_read();
+        a.count_read();
+        a.count_write();
+
+        assert_eq!(
+            access_counts("mmio_access test a"),
+            Some(MmioAccessCounts {
+                reads: 2,
+                writes: 1
+            })
+        );
+        assert_eq!(
+            access_counts("mmio_access test b"),
+            Some(MmioAccessCounts::default())
+        );
+        assert_eq!(access_counts("mmio_access test c"), None);
+    }
+}
@@ -0,0 +1,242 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Trace of the changes to the kernel's translation tables.
+//!
+//! The mapping record only shows the final state. The trace keeps the most recent operations in
+//! the order they happened, including the failed ones, which helps when a sequence of maps and
+//! unmaps goes wrong.
+//!
+//! Entries are added after the tables' lock was released, so that tracing does not change what
+//! runs inside the critical section.
+
+use super::{AccessPermissions, AttributeFields, MemAttributes, PageSliceDescriptor};
+use crate::{
+    info,
+    memory::{Physical, Virtual},
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    time,
+    time::interface::TimeManager,
+};
+use core::{fmt, time::Duration};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Number of operations that are kept. The oldest are overwritten first.
+const NUM_ENTRIES: usize = 64;
+
+struct TraceBuffer {
+    entries: [Option<TraceEntry>; NUM_ENTRIES],
+    next: usize,
+    num_dropped: usize,
+}
+
+/// Formats the physical pages and attributes of an entry, if it has them.
+struct Target<'a>(&'a TraceEntry);
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The kinds of operations that change the kernel's translation tables.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TraceOp {
+    Map,
+    Unmap,
+    MapWindow,
+    UnmapWindow,
+}
+
+/// A traced operation.
+#[allow(missing_docs)]
+#[derive(Copy, Clone)]
+pub struct TraceEntry {
+    pub uptime: Duration,
+    pub op: TraceOp,
+    pub virt_pages: PageSliceDescriptor<Virtual>,
+    pub phys_pages: Option<PageSliceDescriptor<Physical>>,
+    pub attr: Option<AttributeFields>,
+    pub result: Result<(), &'static str>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static TRACE: IRQSafeNullLock<TraceBuffer> = IRQSafeNullLock::new(TraceBuffer::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl TraceBuffer {
+    const fn new() -> Self {
+        Self {
+            entries: [None; NUM_ENTRIES],
+            next: 0,
+            num_dropped: 0,
+        }
+    }
+
+    fn push(&mut self, entry: TraceEntry) {
+        if self.entries[self.next].is_some() {
+            self.num_dropped += 1;
+        }
+
+        self.entries[self.next] = Some(entry);
+        self.next = (self.next + 1) % NUM_ENTRIES;
+    }
+
+    /// The entries from the oldest to the newest.
+    fn iter(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.entries[self.next..]
+            .iter()
+            .chain(self.entries[..self.next].iter())
+            .flatten()
+    }
+}
+
+impl fmt::Display for Target<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (phys_pages, attr) = match (self.0.phys_pages, self.0.attr) {
+            (Some(p), Some(a)) => (p, a),
+            _ => return Ok(()),
+        };
+
+        let mem_attr = match attr.mem_attributes {
+            MemAttributes::CacheableDRAM => "C",
+            MemAttributes::Device => "Dev",
+        };
+
+        let acc_p = match attr.acc_perms {
+            AccessPermissions::ReadOnly => "RO",
+            AccessPermissions::ReadWrite => "RW",
+        };
+
+        let xn = if attr.execute_never { "XN" } else { "X" };
+
+        write!(
+            f,
+            " --> {}..{} | {:<3} {} {:<2}",
+            phys_pages.start_addr(),
+            phys_pages.end_addr_inclusive(),
+            mem_attr,
+            acc_p,
+            xn
+        )
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for TraceOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            TraceOp::Map => "map",
+            TraceOp::Unmap => "unmap",
+            TraceOp::MapWindow => "map-win",
+            TraceOp::UnmapWindow => "unmap-win",
+        })
+    }
+}
+
+/// Add an operation to the trace.
+///
+/// Must not be called with the kernel's translation tables locked.
+pub fn record(
+    op: TraceOp,
+    virt_pages: &PageSliceDescriptor<Virtual>,
+    phys_pages: Option<&PageSliceDescriptor<Physical>>,
+    attr: Option<&AttributeFields>,
+    result: Result<(), &'static str>,
+) {
+    let entry = TraceEntry {
+        uptime: time::time_manager().uptime(),
+        op,
+        virt_pages: *virt_pages,
+        phys_pages: phys_pages.copied(),
+        attr: attr.copied(),
+        result,
+    };
+
+    TRACE.lock(|trace| trace.push(entry));
+}
+
+/// Call `f` with each traced operation, from the oldest to the newest.
+pub fn for_each(mut f: impl FnMut(&TraceEntry)) {
+    TRACE.lock(|trace| {
+        for x in trace.iter() {
+            f(x)
+        }
+    });
+}
+
+/// Print the traced operations, from the oldest to the newest.
+pub fn dump_trace() {
+    let num_dropped = TRACE.lock(|trace| trace.num_dropped);
+    if num_dropped > 0 {
+        info!("      ({} older operations dropped)", num_dropped);
+    }
+
+    for_each(|x| {
+        info!(
+            "      [{:>5}.{:06}] {:<9} {}..{}{} | {}",
+            x.uptime.as_secs(),
+            x.uptime.subsec_micros(),
+            x.op,
+            x.virt_pages.start_addr(),
+            x.virt_pages.end_addr_inclusive(),
+            Target(x),
+            match x.result {
+                Ok(()) => "ok",
+                Err(e) => e,
+            }
+        )
+    });
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::mmu;
+    use test_macros::kernel_test;
+
+    /// Check that window operations show up in the trace, in order.
+    #[kernel_test]
+    fn window_operations_are_traced() {
+        let window = mmu::kernel_alloc_window(1).unwrap();
+        let phys = PageSliceDescriptor::from_addr(crate::memory::Address::new(0), 1);
+        let attr = AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadOnly,
+            execute_never: true,
+        };
+
+        unsafe {
+            mmu::kernel_map_window(&window, &phys, &attr).unwrap();
+            mmu::kernel_unmap_window(&window).unwrap();
+        }
+
+        let mut last_two = [None, None];
+        for_each(|x| {
+            if x.virt_pages == window {
+                last_two = [last_two[1], Some(x.op)];
+            }
+        });
+
+        assert_eq!(
+            last_two,
+            [Some(TraceOp::MapWindow), Some(TraceOp::UnmapWindow)]
+        );
+    }
+}
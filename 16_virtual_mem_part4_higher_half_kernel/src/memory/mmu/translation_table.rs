@@ -53,6 +53,27 @@ pub mod interface {
             attr: &AttributeFields,
         ) -> Result<(), &'static str>;
 
+        /// Check that the given virtual pages are mapped to the given physical pages exactly as
+        /// `map_pages_at()` would have mapped them.
+        ///
+        /// Meant for entries that were not written by `map_pages_at()`, like precomputed ones.
+        fn verify_pages_at(
+            &self,
+            virt_pages: &PageSliceDescriptor<Virtual>,
+            phys_pages: &PageSliceDescriptor<Physical>,
+            attr: &AttributeFields,
+        ) -> Result<(), &'static str>;
+
+        /// Unmap the given virtual pages and invalidate their TLB entries.
+        ///
+        /// # Safety
+        ///
+        /// - The caller must ensure that the pages are not referenced anymore.
+        unsafe fn unmap_pages_at(
+            &mut self,
+            virt_pages: &PageSliceDescriptor<Virtual>,
+        ) -> Result<(), &'static str>;
+
         /// Obtain a free virtual page slice in the MMIO region.
         ///
         /// The "MMIO region" is a distinct region of the implementor's choice, which allows
@@ -65,6 +86,29 @@ pub mod interface {
             num_pages: usize,
         ) -> Result<PageSliceDescriptor<Virtual>, &'static str>;
 
+        /// Give a slice obtained from `next_mmio_virt_page_slice()` back to the MMIO region.
+        ///
+        /// Implementors may hand out slices bottom-up and only reclaim the most recently obtained
+        /// one. In that case, slices must be freed in reverse order to make room again.
+        fn free_mmio_virt_page_slice(&mut self, virt_pages: &PageSliceDescriptor<Virtual>);
+
+        /// The number of used and the number of total pages in the MMIO region.
+        fn mmio_stats(&self) -> (usize, usize);
+
+        /// Map `own_pages`, the pages that hold the table, read-only in the table.
+        ///
+        /// Afterwards, the functions that change the table map the page that they write to
+        /// writable for the duration of the write. The pages that hold the descriptors of
+        /// `own_pages` stay writable, since that is where the permissions are changed.
+        ///
+        /// # Safety
+        ///
+        /// - The table must be the one that translates the executing code.
+        unsafe fn write_protect(
+            &mut self,
+            own_pages: &PageSliceDescriptor<Virtual>,
+        ) -> Result<(), &'static str>;
+
         /// Check if a virtual page splice is in the "MMIO region".
         fn is_virt_page_slice_mmio(&self, virt_pages: &PageSliceDescriptor<Virtual>) -> bool;
     }
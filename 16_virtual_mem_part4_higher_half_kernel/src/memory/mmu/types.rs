@@ -17,6 +17,30 @@ use core::{
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// Create a `PageSliceDescriptor` from a start address and a size in bytes at compile time.
+///
+/// Meant for the BSP's static layout tables. If the start or the size are not aligned to the
+/// `KernelGranule`, the build fails.
+///
+/// ```
+/// const WINDOW: PageSliceDescriptor<Physical> = page_slice!(Physical, 0xFE00_0000, 0x0185_0000);
+/// ```
+#[macro_export]
+macro_rules! page_slice {
+    ($atype:ty, $start:expr, $size:expr) => {{
+        const DESC: $crate::memory::mmu::PageSliceDescriptor<$atype> =
+            $crate::memory::mmu::PageSliceDescriptor::from_addr_and_size(
+                $crate::memory::Address::new($start),
+                $size,
+            );
+
+        DESC
+    }};
+}
+
+/// Describes the characteristics of a translation granule.
+pub struct TranslationGranule<const GRANULE_SIZE: usize>;
+
 /// Generic page type.
 #[repr(C)]
 pub struct Page<ATYPE: AddressType> {
@@ -63,10 +87,41 @@ pub struct MMIODescriptor {
     size: usize,
 }
 
+/// A named section of the kernel's virtual memory layout, and its translation.
+#[allow(missing_docs)]
+#[derive(Copy, Clone)]
+pub struct TranslationDescriptor {
+    pub name: &'static str,
+    pub virt_pages: PageSliceDescriptor<Virtual>,
+    pub phys_pages: PageSliceDescriptor<Physical>,
+    pub attribute_fields: AttributeFields,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+//------------------------------------------------------------------------------
+// TranslationGranule
+//------------------------------------------------------------------------------
+
+impl<const GRANULE_SIZE: usize> TranslationGranule<GRANULE_SIZE> {
+    /// The granule's size.
+    pub const SIZE: usize = Self::size_checked();
+
+    /// The granule's mask.
+    pub const MASK: usize = Self::SIZE - 1;
+
+    /// The granule's shift, aka log2(size).
+    pub const SHIFT: usize = Self::SIZE.trailing_zeros() as usize;
+
+    const fn size_checked() -> usize {
+        assert!(GRANULE_SIZE.is_power_of_two());
+
+        GRANULE_SIZE
+    }
+}
+
 //------------------------------------------------------------------------------
 // Page
 //------------------------------------------------------------------------------
@@ -83,15 +138,70 @@ impl<ATYPE: AddressType> Page<ATYPE> {
 //------------------------------------------------------------------------------
 
 impl<ATYPE: AddressType> PageSliceDescriptor<ATYPE> {
+    /// Check that the slice starts on a page boundary, is not empty and does not wrap around the
+    /// address space.
+    const fn check(start: Address<ATYPE>, num_pages: usize) -> Result<(), &'static str> {
+        if !common::is_aligned(start.into_usize(), bsp::memory::mmu::KernelGranule::SIZE) {
+            return Err("Page slice start is not page aligned");
+        }
+
+        if num_pages == 0 {
+            return Err("Page slice is empty");
+        }
+
+        let size = match num_pages.checked_mul(bsp::memory::mmu::KernelGranule::SIZE) {
+            Some(x) => x,
+            None => return Err("Page slice wraps around the address space"),
+        };
+        if start.into_usize().checked_add(size - 1).is_none() {
+            return Err("Page slice wraps around the address space");
+        }
+
+        Ok(())
+    }
+
+    /// Convert a size in bytes into a number of pages. The size must be page aligned.
+    const fn size_to_num_pages(size: usize) -> Result<usize, &'static str> {
+        if !common::is_aligned(size, bsp::memory::mmu::KernelGranule::SIZE) {
+            return Err("Page slice size is not page aligned");
+        }
+
+        Ok(size >> bsp::memory::mmu::KernelGranule::SHIFT)
+    }
+
     /// Create an instance.
     pub const fn from_addr(start: Address<ATYPE>, num_pages: usize) -> Self {
-        assert!(common::is_aligned(
-            start.into_usize(),
-            bsp::memory::mmu::KernelGranule::SIZE
-        ));
-        assert!(num_pages > 0);
+        match Self::check(start, num_pages) {
+            Ok(()) => Self { start, num_pages },
+            Err(_) => panic!("Invalid page slice"),
+        }
+    }
 
-        Self { start, num_pages }
+    /// Create an instance from a start address and a size in bytes, and panic if they are not page
+    /// aligned.
+    ///
+    /// Meant for compile-time constants, where the panic becomes a build error. See `page_slice!`.
+    pub const fn from_addr_and_size(start: Address<ATYPE>, size: usize) -> Self {
+        match Self::try_from_addr_and_size(start, size) {
+            Ok(x) => x,
+            Err(_) => panic!("Page slice is empty, wraps or is not page aligned"),
+        }
+    }
+
+    /// Create an instance from a start address and a size in bytes.
+    pub const fn try_from_addr_and_size(
+        start: Address<ATYPE>,
+        size: usize,
+    ) -> Result<Self, &'static str> {
+        let num_pages = match Self::size_to_num_pages(size) {
+            Ok(x) => x,
+            Err(x) => return Err(x),
+        };
+
+        match Self::check(start, num_pages) {
+            Ok(()) => Ok(Self { start, num_pages }),
+            Err(x) => Err(x),
+        }
     }
 
     /// Return a pointer to the first page of the described slice.
@@ -115,8 +225,8 @@ impl<ATYPE: AddressType> PageSliceDescriptor<ATYPE> {
     }
 
     /// Return the exclusive end address.
-    pub fn end_addr(&self) -> Address<ATYPE> {
-        self.start + self.size()
+    pub const fn end_addr(&self) -> Address<ATYPE> {
+        Address::new(self.start.into_usize() + self.size())
     }
 
     /// Return the inclusive end address.
@@ -158,7 +268,7 @@ impl From<MMIODescriptor> for PageSliceDescriptor<Physical> {
             .start_addr
             .align_down(bsp::memory::mmu::KernelGranule::SIZE);
 
-        let len = ((desc.end_addr_inclusive().into_usize() - start_page_addr.into_usize())
+        let len = (desc.end_addr_inclusive().offset_from(start_page_addr)
             >> bsp::memory::mmu::KernelGranule::SHIFT)
             + 1;
 
@@ -174,11 +284,65 @@ impl From<MMIODescriptor> for PageSliceDescriptor<Physical> {
 //------------------------------------------------------------------------------
 
 impl MMIODescriptor {
+    /// Check that the range is not empty and does not wrap around the address space.
+    const fn check_range(start_addr: Address<Physical>, size: usize) -> Result<(), &'static str> {
+        if size == 0 {
+            return Err("MMIO descriptor has size zero");
+        }
+
+        if start_addr.into_usize().checked_add(size - 1).is_none() {
+            return Err("MMIO descriptor wraps around the address space");
+        }
+
+        Ok(())
+    }
+
+    /// Check that the range is a valid descriptor of the board's peripherals.
+    const fn check_peripheral(
+        start_addr: Address<Physical>,
+        size: usize,
+    ) -> Result<(), &'static str> {
+        if let Err(x) = Self::check_range(start_addr, size) {
+            return Err(x);
+        }
+
+        let window = bsp::memory::phys_mmio_window();
+        if (start_addr.into_usize() < window.start_addr().into_usize())
+            || (start_addr.into_usize() + (size - 1) >= window.end_addr().into_usize())
+        {
+            return Err("MMIO descriptor is outside of the peripheral window");
+        }
+
+        Ok(())
+    }
+
     /// Create an instance.
+    ///
+    /// Not restricted to the peripheral window, so that device memory that lives in DRAM, for
+    /// example, the firmware's spin-table, can be described as well.
     pub const fn new(start_addr: Address<Physical>, size: usize) -> Self {
-        assert!(size > 0);
+        match Self::check_range(start_addr, size) {
+            Ok(()) => Self { start_addr, size },
+            Err(_) => panic!("Invalid MMIO descriptor"),
+        }
+    }
 
-        Self { start_addr, size }
+    /// Create an instance that describes a peripheral, and panic if it does not.
+    ///
+    /// Meant for compile-time constants, where the panic becomes a build error.
+    pub const fn new_peripheral(start_addr: Address<Physical>, size: usize) -> Self {
+        match Self::check_peripheral(start_addr, size) {
+            Ok(()) => Self { start_addr, size },
+            Err(_) => panic!("MMIO descriptor is empty, wraps or is outside of the peripherals"),
+        }
+    }
+
+    /// Create an instance that describes a peripheral.
+    pub const fn try_new(start_addr: Address<Physical>, size: usize) -> Result<Self, &'static str> {
+        match Self::check_peripheral(start_addr, size) {
+            Ok(()) => Ok(Self { start_addr, size }),
+            Err(x) => Err(x),
+        }
     }
 
     /// Return the start address.
@@ -195,13 +359,32 @@ impl MMIODescriptor {
     pub const fn size(&self) -> usize {
         self.size
     }
+
+    /// Check if an address is contained within this descriptor.
+    pub fn contains(&self, addr: Address<Physical>) -> bool {
+        (addr >= self.start_addr) && (addr <= self.end_addr_inclusive())
+    }
+
+    /// Split into the `offset` bytes at the start and the rest.
+    ///
+    /// Useful for devices that share a page, but are driven separately.
+    pub fn split(&self, offset: usize) -> Result<(Self, Self), &'static str> {
+        if (offset == 0) || (offset >= self.size) {
+            return Err("Split offset is outside of the MMIO descriptor");
+        }
+
+        Ok((
+            Self::new(self.start_addr, offset),
+            Self::new(self.start_addr + offset, self.size - offset),
+        ))
+    }
 }
 
 //--------------------------------------------------------------------------------------------------
 // Testing
 //--------------------------------------------------------------------------------------------------
 
-#[cfg(test)]
+#[cfg(all(test, target_os = "none"))]
 mod tests {
     use super::*;
     use test_macros::kernel_test;
@@ -215,3 +398,112 @@ mod tests {
         );
     }
 }
+
+#[cfg(all(test, not(target_os = "none")))]
+mod host_tests {
+    use super::*;
+
+    type Granule = bsp::memory::mmu::KernelGranule;
+
+    /// Check the granule constants.
+    #[test]
+    fn granule_constants() {
+        assert_eq!(Granule::SIZE, 64 * 1024);
+        assert_eq!(Granule::MASK, 0xFFFF);
+        assert_eq!(Granule::SHIFT, 16);
+    }
+
+    /// Check the arithmetic of page slice descriptors.
+    #[test]
+    fn page_slice_descriptor_arithmetic() {
+        let start = Address::<Virtual>::new(0x10_0000);
+        let desc = PageSliceDescriptor::from_addr(start, 3);
+
+        assert_eq!(desc.num_pages(), 3);
+        assert_eq!(desc.size(), 3 * Granule::SIZE);
+        assert!(desc.start_addr() == start);
+        assert!(desc.end_addr() == start + 3 * Granule::SIZE);
+        assert!(desc.end_addr_inclusive() == start + (3 * Granule::SIZE - 1));
+
+        assert!(desc.contains(start));
+        assert!(desc.contains(desc.end_addr_inclusive()));
+        assert!(!desc.contains(desc.end_addr()));
+        assert!(!desc.contains(Address::new(0x10_0000 - 1)));
+    }
+
+    /// Check that page slices are only created from page aligned, non-empty ranges.
+    #[test]
+    fn page_slice_descriptor_validation() {
+        let start = Address::<Virtual>::new(0x10_0000);
+
+        let desc = PageSliceDescriptor::try_from_addr_and_size(start, 2 * Granule::SIZE).unwrap();
+        assert_eq!(desc.num_pages(), 2);
+        assert!(desc == PageSliceDescriptor::from_addr(start, 2));
+
+        const DESC: PageSliceDescriptor<Virtual> = page_slice!(Virtual, 0x20_0000, 0x1_0000);
+        assert!(DESC.start_addr() == Address::new(0x20_0000));
+        assert_eq!(DESC.num_pages(), 1);
+
+        assert!(PageSliceDescriptor::try_from_addr_and_size(start, 0).is_err());
+        assert!(PageSliceDescriptor::try_from_addr_and_size(start, Granule::SIZE + 1).is_err());
+        assert!(PageSliceDescriptor::try_from_addr_and_size(start + 1, Granule::SIZE).is_err());
+        assert!(PageSliceDescriptor::<Virtual>::try_from_addr_and_size(
+            Address::new(usize::MAX - Granule::MASK),
+            2 * Granule::SIZE
+        )
+        .is_err());
+    }
+
+    /// Check that MMIO descriptors are rounded out to whole pages.
+    #[test]
+    fn mmio_descriptor_to_page_slice() {
+        // Fits into a single page.
+        let desc: PageSliceDescriptor<Physical> =
+            MMIODescriptor::new(Address::new(0x3F20_1000), 0x48).into();
+        assert!(desc.start_addr() == Address::new(0x3F20_0000));
+        assert_eq!(desc.num_pages(), 1);
+
+        // Crosses a page boundary.
+        let desc: PageSliceDescriptor<Physical> =
+            MMIODescriptor::new(Address::new(0x3F20_FFF0), 0x20).into();
+        assert!(desc.start_addr() == Address::new(0x3F20_0000));
+        assert_eq!(desc.num_pages(), 2);
+
+        // Exactly one page.
+        let desc: PageSliceDescriptor<Physical> =
+            MMIODescriptor::new(Address::new(0x3F20_0000), Granule::SIZE).into();
+        assert_eq!(desc.num_pages(), 1);
+    }
+
+    /// Check that only non-empty descriptors within the peripheral window are accepted.
+    #[test]
+    fn mmio_descriptor_validation() {
+        let window = bsp::memory::phys_mmio_window();
+        let (window_start, window_end) = (window.start_addr(), window.end_addr());
+
+        assert!(MMIODescriptor::try_new(window_start, 0x48).is_ok());
+        assert!(MMIODescriptor::try_new(window_start, 0).is_err());
+        assert!(MMIODescriptor::try_new(Address::new(usize::MAX), 2).is_err());
+        assert!(MMIODescriptor::try_new(window_end - 0x10, 0x20).is_err());
+        assert!(MMIODescriptor::try_new(window_start - 0x10, 0x20).is_err());
+    }
+
+    /// Check the helpers for deriving sub-descriptors.
+    #[test]
+    fn mmio_descriptor_split() {
+        let desc = MMIODescriptor::new(Address::new(0x3F20_0000), 0x1048);
+
+        assert!(desc.end_addr_inclusive() == Address::new(0x3F20_1047));
+        assert!(desc.contains(Address::new(0x3F20_1000)));
+        assert!(!desc.contains(Address::new(0x3F20_1048)));
+
+        let (gpio, uart) = desc.split(0x1000).unwrap();
+        assert!(gpio.start_addr() == Address::new(0x3F20_0000));
+        assert_eq!(gpio.size(), 0x1000);
+        assert!(uart.start_addr() == Address::new(0x3F20_1000));
+        assert_eq!(uart.size(), 0x48);
+
+        assert!(desc.split(0).is_err());
+        assert!(desc.split(0x1048).is_err());
+    }
+}
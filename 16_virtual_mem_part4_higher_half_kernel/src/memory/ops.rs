@@ -0,0 +1,337 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Copying to and from memory with the access sizes that its attributes allow.
+//!
+//! The compiler's `memcpy` and `memset` may use unaligned or wide accesses. That is fine for
+//! cacheable DRAM, but unaligned accesses to Device memory fault, and many peripherals only support
+//! 32 bit accesses. The functions here look up the attributes of the memory on the far side and
+//! then use:
+//!
+//! - Device memory: Byte accesses up to the first 4-byte boundary, then 32 bit accesses, then byte
+//!   accesses for the rest.
+//! - Cacheable DRAM: The same with 16-byte accesses.
+//!
+//! All accesses to the far side are volatile. The near side is always a slice in normal memory.
+
+use crate::memory::{
+    mmu::{self, MemAttributes},
+    Address, Virtual,
+};
+use core::{mem, ptr};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A unit of access.
+trait Word: Copy {
+    /// The word with all of its 32 bit parts set to `value`.
+    fn splat(value: u32) -> Self;
+}
+
+/// Which side of a copy the far memory is on.
+#[derive(Copy, Clone, PartialEq)]
+enum Far {
+    Destination,
+    Source,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Word for u32 {
+    fn splat(value: u32) -> Self {
+        value
+    }
+}
+
+impl Word for u128 {
+    fn splat(value: u32) -> Self {
+        let value = Self::from(value);
+
+        value | (value << 32) | (value << 64) | (value << 96)
+    }
+}
+
+/// Return true if `len` bytes from `addr` are Device memory, and false if they are cacheable DRAM.
+fn is_device(addr: Address<Virtual>, len: usize) -> Result<bool, &'static str> {
+    let mapping = mmu::kernel_find_mapping(addr).ok_or("Address is not mapped")?;
+
+    let end = addr
+        .into_usize()
+        .checked_add(len)
+        .ok_or("Address range overflows")?;
+    if end > mapping.virt_pages.end_addr().into_usize() {
+        return Err("Address range crosses mappings");
+    }
+
+    Ok(mapping.attribute_fields.mem_attributes == MemAttributes::Device)
+}
+
+/// Number of bytes in front of the first `W`-aligned address, capped at `len`.
+fn head_len<W: Word>(addr: usize, len: usize) -> usize {
+    let size = mem::size_of::<W>();
+
+    ((size - (addr % size)) % size).min(len)
+}
+
+/// Copy `len` bytes with accesses that are naturally aligned on the far side.
+///
+/// # Safety
+///
+/// - Both ranges must be valid and must not overlap.
+unsafe fn copy<W: Word>(dst: *mut u8, src: *const u8, len: usize, far: Far) {
+    let size = mem::size_of::<W>();
+    let far_addr = match far {
+        Far::Destination => dst as usize,
+        Far::Source => src as usize,
+    };
+
+    let head = head_len::<W>(far_addr, len);
+    let num_words = (len - head) / size;
+
+    for i in 0..head {
+        ptr::write_volatile(dst.add(i), ptr::read_volatile(src.add(i)));
+    }
+
+    for i in 0..num_words {
+        let offset = head + i * size;
+        let (d, s) = (dst.add(offset) as *mut W, src.add(offset) as *const W);
+
+        match far {
+            Far::Destination => ptr::write_volatile(d, ptr::read_unaligned(s)),
+            Far::Source => ptr::write_unaligned(d, ptr::read_volatile(s)),
+        }
+    }
+
+    for i in (head + num_words * size)..len {
+        ptr::write_volatile(dst.add(i), ptr::read_volatile(src.add(i)));
+    }
+}
+
+/// Set `len` bytes to `value` with naturally aligned accesses.
+///
+/// # Safety
+///
+/// - The range must be valid.
+unsafe fn set<W: Word>(dst: *mut u8, value: u8, len: usize) {
+    let size = mem::size_of::<W>();
+    let head = head_len::<W>(dst as usize, len);
+    let num_words = (len - head) / size;
+
+    for i in 0..head {
+        ptr::write_volatile(dst.add(i), value);
+    }
+
+    let word = W::splat(u32::from_ne_bytes([value; 4]));
+    for i in 0..num_words {
+        ptr::write_volatile(dst.add(head + i * size) as *mut W, word);
+    }
+
+    for i in (head + num_words * size)..len {
+        ptr::write_volatile(dst.add(i), value);
+    }
+}
+
+/// Set `count` 32 bit words to `value` with naturally aligned accesses.
+///
+/// # Safety
+///
+/// - The range must be valid, and `dst` must be 4-byte aligned.
+unsafe fn set_u32<W: Word>(dst: *mut u32, value: u32, count: usize) {
+    let ratio = mem::size_of::<W>() / 4;
+    let head = head_len::<W>(dst as usize, count * 4) / 4;
+    let num_words = (count - head) / ratio;
+
+    for i in 0..head {
+        ptr::write_volatile(dst.add(i), value);
+    }
+
+    let word = W::splat(value);
+    for i in 0..num_words {
+        ptr::write_volatile(dst.add(head + i * ratio) as *mut W, word);
+    }
+
+    for i in (head + num_words * ratio)..count {
+        ptr::write_volatile(dst.add(i), value);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Copy `src` to the memory at `dst`.
+///
+/// # Safety
+///
+/// - The destination must be writable, and writing it must have no unwanted side effects.
+pub unsafe fn copy_to_device(dst: Address<Virtual>, src: &[u8]) -> Result<(), &'static str> {
+    let dst_ptr = dst.into_usize() as *mut u8;
+
+    if is_device(dst, src.len())? {
+        copy::<u32>(dst_ptr, src.as_ptr(), src.len(), Far::Destination);
+    } else {
+        copy::<u128>(dst_ptr, src.as_ptr(), src.len(), Far::Destination);
+    }
+
+    Ok(())
+}
+
+/// Fill `dst` from the memory at `src`.
+///
+/// # Safety
+///
+/// - Reading the source must have no unwanted side effects.
+pub unsafe fn copy_from_device(dst: &mut [u8], src: Address<Virtual>) -> Result<(), &'static str> {
+    let src_ptr = src.into_usize() as *const u8;
+
+    if is_device(src, dst.len())? {
+        copy::<u32>(dst.as_mut_ptr(), src_ptr, dst.len(), Far::Source);
+    } else {
+        copy::<u128>(dst.as_mut_ptr(), src_ptr, dst.len(), Far::Source);
+    }
+
+    Ok(())
+}
+
+/// Set `len` bytes of memory at `dst` to `value`.
+///
+/// # Safety
+///
+/// - The destination must be writable, and writing it must have no unwanted side effects.
+pub unsafe fn set_device(dst: Address<Virtual>, value: u8, len: usize) -> Result<(), &'static str> {
+    let dst_ptr = dst.into_usize() as *mut u8;
+
+    if is_device(dst, len)? {
+        set::<u32>(dst_ptr, value, len);
+    } else {
+        set::<u128>(dst_ptr, value, len);
+    }
+
+    Ok(())
+}
+
+/// Set `count` 32 bit words of memory at `dst` to `value`.
+///
+/// # Safety
+///
+/// - The destination must be writable, and writing it must have no unwanted side effects.
+pub unsafe fn set_device_u32(
+    dst: Address<Virtual>,
+    value: u32,
+    count: usize,
+) -> Result<(), &'static str> {
+    if dst.into_usize() % 4 != 0 {
+        return Err("Address is not 4-byte aligned");
+    }
+
+    let dst_ptr = dst.into_usize() as *mut u32;
+    let len = count.checked_mul(4).ok_or("Address range overflows")?;
+
+    if is_device(dst, len)? {
+        set_u32::<u32>(dst_ptr, value, count);
+    } else {
+        set_u32::<u128>(dst_ptr, value, count);
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    const BUF_LEN: usize = 64;
+
+    #[repr(align(16))]
+    struct Buffer([u8; BUF_LEN]);
+
+    fn pattern() -> Buffer {
+        let mut buf = Buffer([0; BUF_LEN]);
+        for (i, x) in buf.0.iter_mut().enumerate() {
+            *x = i as u8 + 1;
+        }
+
+        buf
+    }
+
+    /// Copy and set at all alignments of both sides and all lengths, for both word sizes, and
+    /// check that exactly the requested bytes change.
+    fn check_all<W: Word>() {
+        let src = pattern();
+
+        for far in [Far::Destination, Far::Source].iter().copied() {
+            for dst_offset in 0..16 {
+                for src_offset in 0..16 {
+                    for len in 0..(BUF_LEN - 16) {
+                        let mut dst = Buffer([0; BUF_LEN]);
+                        unsafe {
+                            copy::<W>(
+                                dst.0.as_mut_ptr().add(dst_offset),
+                                src.0.as_ptr().add(src_offset),
+                                len,
+                                far,
+                            )
+                        };
+
+                        let copied = &dst.0[dst_offset..(dst_offset + len)];
+                        assert_eq!(copied, &src.0[src_offset..(src_offset + len)]);
+                        assert!(dst.0[..dst_offset].iter().all(|x| *x == 0));
+                        assert!(dst.0[(dst_offset + len)..].iter().all(|x| *x == 0));
+                    }
+                }
+            }
+        }
+
+        for dst_offset in 0..16 {
+            for len in 0..(BUF_LEN - 16) {
+                let mut dst = Buffer([0; BUF_LEN]);
+                unsafe { set::<W>(dst.0.as_mut_ptr().add(dst_offset), 0xA5, len) };
+
+                assert!(dst.0[dst_offset..(dst_offset + len)]
+                    .iter()
+                    .all(|x| *x == 0xA5));
+                assert!(dst.0[..dst_offset].iter().all(|x| *x == 0));
+                assert!(dst.0[(dst_offset + len)..].iter().all(|x| *x == 0));
+            }
+        }
+
+        for dst_offset in (0..16).step_by(4) {
+            for count in 0..((BUF_LEN - 16) / 4) {
+                let mut dst = Buffer([0; BUF_LEN]);
+                let value = u32::from_ne_bytes([0xA1, 0xB2, 0xC3, 0xD4]);
+                unsafe {
+                    set_u32::<W>(dst.0.as_mut_ptr().add(dst_offset) as *mut u32, value, count)
+                };
+
+                let len = count * 4;
+                assert!(dst.0[dst_offset..(dst_offset + len)]
+                    .chunks_exact(4)
+                    .all(|x| x == value.to_ne_bytes()));
+                assert!(dst.0[..dst_offset].iter().all(|x| *x == 0));
+                assert!(dst.0[(dst_offset + len)..].iter().all(|x| *x == 0));
+            }
+        }
+    }
+
+    /// Check the Device memory accesses with unaligned heads and tails.
+    #[kernel_test]
+    fn device_copies_are_correct() {
+        check_all::<u32>();
+    }
+
+    /// Check the cacheable DRAM accesses with unaligned heads and tails.
+    #[kernel_test]
+    fn dram_copies_are_correct() {
+        check_all::<u128>();
+    }
+}
@@ -0,0 +1,176 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Memory self-tests.
+//!
+//! Marginal power supplies can cause bit errors in DRAM, which then show up as impossible kernel
+//! bugs. The tests in this module help ruling out such hardware problems.
+
+use crate::{
+    bsp, info,
+    memory::{
+        mmu,
+        mmu::{AccessPermissions, AttributeFields, MemAttributes, PageSliceDescriptor},
+        Address, Physical, Virtual,
+    },
+    warn,
+};
+use core::mem;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Number of pages that are tested at once.
+const CHUNK_NUM_PAGES: usize = 256;
+
+/// Maximum number of failing addresses that are reported individually.
+const MAX_REPORTED_ERRORS: usize = 16;
+
+/// A test pattern, yielding the value for a word given the word's physical address and index.
+struct Pattern {
+    name: &'static str,
+    value_for: fn(usize, usize) -> u64,
+}
+
+const PATTERNS: [Pattern; 2] = [
+    Pattern {
+        name: "address-in-address",
+        value_for: address_in_address,
+    },
+    Pattern {
+        name: "walking ones",
+        value_for: walking_ones,
+    },
+];
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Each word holds its own physical address, which catches address line faults.
+fn address_in_address(phys_addr: usize, _index: usize) -> u64 {
+    phys_addr as u64
+}
+
+/// Each word holds a single set bit, which moves by one position from word to word.
+fn walking_ones(_phys_addr: usize, index: usize) -> u64 {
+    1 << (index % 64)
+}
+
+/// Write the pattern to the chunk mapped at `virt_start`, read it back and report mismatches.
+///
+/// Returns the number of failing words.
+///
+/// # Safety
+///
+/// - The chunk must be mapped and not be in use by anyone else.
+unsafe fn test_chunk(
+    pattern: &Pattern,
+    virt_start: Address<Virtual>,
+    phys_start: Address<Physical>,
+    size: usize,
+    num_reported: &mut usize,
+) -> usize {
+    let words = virt_start.into_usize() as *mut u64;
+    let num_words = size / mem::size_of::<u64>();
+    let phys_addr_of = |i| phys_start.into_usize() + (i * mem::size_of::<u64>());
+
+    for i in 0..num_words {
+        core::ptr::write_volatile(words.add(i), (pattern.value_for)(phys_addr_of(i), i));
+    }
+
+    let mut num_errors = 0;
+    for i in 0..num_words {
+        let expected = (pattern.value_for)(phys_addr_of(i), i);
+        let actual = core::ptr::read_volatile(words.add(i));
+
+        if actual != expected {
+            num_errors += 1;
+
+            if *num_reported < MAX_REPORTED_ERRORS {
+                *num_reported += 1;
+                warn!(
+                    "DRAM error at {}: Expected {:#018x}, read {:#018x} ({})",
+                    Address::<Physical>::new(phys_addr_of(i)),
+                    expected,
+                    actual,
+                    pattern.name
+                );
+            }
+        }
+    }
+
+    num_errors
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Test the DRAM that is not used by the kernel.
+///
+/// The memory is mapped chunk-wise through a window in the kernel's MMIO region. It is mapped as
+/// device memory, so that the accesses go to DRAM instead of being served by the caches. Each
+/// chunk is tested with an address-in-address and a walking ones pattern.
+///
+/// The previous contents of the tested memory are destroyed.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init, before the secondary cores are released.
+pub unsafe fn quick_dram_test() -> Result<(), &'static str> {
+    let attr = AttributeFields {
+        mem_attributes: MemAttributes::Device,
+        acc_perms: AccessPermissions::ReadWrite,
+        execute_never: true,
+    };
+
+    let phys_unused = bsp::memory::mmu::phys_unused_dram_page_desc();
+    let window = mmu::kernel_alloc_window(CHUNK_NUM_PAGES)?;
+
+    info!(
+        "DRAM self-test: Testing {} MiB from {} to {}",
+        phys_unused.size() / (1024 * 1024),
+        phys_unused.start_addr(),
+        phys_unused.end_addr_inclusive()
+    );
+
+    let mut num_errors = 0;
+    let mut num_reported = 0;
+    let mut pages_left = phys_unused.num_pages();
+    let mut phys_start = phys_unused.start_addr();
+
+    while pages_left > 0 {
+        let num_pages = core::cmp::min(pages_left, CHUNK_NUM_PAGES);
+        let phys_chunk = PageSliceDescriptor::from_addr(phys_start, num_pages);
+        let virt_chunk = PageSliceDescriptor::from_addr(window.start_addr(), num_pages);
+
+        mmu::kernel_map_window(&virt_chunk, &phys_chunk, &attr)?;
+
+        for pattern in PATTERNS.iter() {
+            num_errors += test_chunk(
+                pattern,
+                virt_chunk.start_addr(),
+                phys_start,
+                phys_chunk.size(),
+                &mut num_reported,
+            );
+        }
+
+        mmu::kernel_unmap_window(&virt_chunk)?;
+
+        pages_left -= num_pages;
+        phys_start = phys_start + phys_chunk.size();
+    }
+
+    if num_errors > 0 {
+        warn!("DRAM self-test: {} failing words", num_errors);
+        return Err("DRAM self-test failed");
+    }
+
+    info!("DRAM self-test: Passed");
+
+    Ok(())
+}
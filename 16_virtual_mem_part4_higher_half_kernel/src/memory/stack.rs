@@ -0,0 +1,220 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Stack usage instrumentation.
+//!
+//! Each core has a kernel stack, which kernel code runs on, and an exception stack, which the core
+//! switches to when taking an exception. Both are filled with a pattern early on. The part that
+//! was overwritten since then is the stack's high-water mark, which tells how close the stack came
+//! to overflowing into its guard page.
+//!
+//! The kernel stack is already in use when it is filled, so only the part below the live stack
+//! pointer is filled, and a scan of the stack that the scanning code runs on stops at the stack
+//! pointer as well.
+
+use crate::{
+    bsp, config, cpu, info,
+    memory::{mmu::PageSliceDescriptor, Virtual},
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The value that unused stack words hold.
+const FILL_PATTERN: u64 = 0x5354_4143_4B5F_4649;
+
+/// Bytes below the live stack pointer that are not filled.
+///
+/// Leaves room for the frames of the functions that the filling loop might call in unoptimized
+/// builds.
+const LIVE_STACK_MARGIN: usize = 1024;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A stack of a core.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Stack {
+    /// The stack that the core with the given id runs kernel code on.
+    Kernel(usize),
+
+    /// The stack that the core with the given id handles exceptions on.
+    Exception(usize),
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+#[allow(clippy::declare_interior_mutable_const)]
+const UNFILLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the kernel stack was filled with the pattern, indexed by core id.
+static KERNEL_STACK_FILLED: [AtomicBool; config::cpu::NUM_CORES] =
+    [UNFILLED; config::cpu::NUM_CORES];
+
+/// Whether the exception stack was filled with the pattern, indexed by core id.
+static EXCEPTION_STACK_FILLED: [AtomicBool; config::cpu::NUM_CORES] =
+    [UNFILLED; config::cpu::NUM_CORES];
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Stack {
+    /// All stacks of all cores.
+    fn all() -> impl Iterator<Item = Stack> {
+        (0..config::cpu::NUM_CORES)
+            .map(Stack::Kernel)
+            .chain((0..config::cpu::NUM_CORES).map(Stack::Exception))
+    }
+
+    fn pages(self) -> PageSliceDescriptor<Virtual> {
+        match self {
+            Stack::Kernel(core_id) => bsp::memory::mmu::virt_kernel_stack_page_desc(core_id),
+            Stack::Exception(core_id) => bsp::memory::mmu::virt_exception_stack_page_desc(core_id),
+        }
+    }
+
+    fn filled(self) -> &'static AtomicBool {
+        match self {
+            Stack::Kernel(core_id) => &KERNEL_STACK_FILLED[core_id],
+            Stack::Exception(core_id) => &EXCEPTION_STACK_FILLED[core_id],
+        }
+    }
+
+    /// The stack's words, from the bottom to the exclusive top. If the executing code runs on the
+    /// stack, the top is the stack pointer.
+    fn unused_words(self) -> (*mut u64, *mut u64) {
+        let pages = self.pages();
+        let start = pages.start_addr().into_usize();
+        let mut end = pages.end_addr().into_usize();
+
+        let sp = cpu::stack_pointer();
+        if (start..end).contains(&sp) {
+            end = sp;
+        }
+
+        (start as *mut u64, end as *mut u64)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for Stack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stack::Kernel(core_id) => write!(f, "Core {} kernel stack", core_id),
+            Stack::Exception(core_id) => write!(f, "Core {} exception stack", core_id),
+        }
+    }
+}
+
+/// Fill the unused part of the given stack with the pattern.
+///
+/// # Safety
+///
+/// - The stack must not be in use by another core, or by code of the executing core that was
+///   interrupted. Only the part below the live stack pointer of the executing code is spared.
+pub unsafe fn fill(which: Stack) {
+    let (mut ptr, mut end) = which.unused_words();
+
+    if (end as usize) != which.pages().end_addr().into_usize() {
+        end = (end as usize).saturating_sub(LIVE_STACK_MARGIN) as *mut u64;
+    }
+
+    while ptr < end {
+        core::ptr::write_volatile(ptr, FILL_PATTERN);
+        ptr = ptr.offset(1);
+    }
+
+    which.filled().store(true, Ordering::Release);
+}
+
+/// The number of bytes of the given stack that were used at most, and its size.
+///
+/// None if the stack has not been filled with the pattern yet.
+pub fn stack_usage(which: Stack) -> Option<(usize, usize)> {
+    if !which.filled().load(Ordering::Acquire) {
+        return None;
+    }
+
+    let size = which.pages().size();
+    let (mut ptr, end) = which.unused_words();
+
+    // The stack grows downwards, so the untouched words are at the bottom.
+    while (ptr < end) && (unsafe { core::ptr::read_volatile(ptr) } == FILL_PATTERN) {
+        ptr = unsafe { ptr.offset(1) };
+    }
+
+    let unused = (ptr as usize) - which.pages().start_addr().into_usize();
+
+    Some((size - unused, size))
+}
+
+/// The exclusive top of the stack that the executing code runs on.
+///
+/// None if the stack pointer is not on any of the kernel's stacks.
+pub fn current_stack_top() -> Option<usize> {
+    let sp = cpu::stack_pointer();
+
+    Stack::all()
+        .map(|which| which.pages())
+        .find(|pages| {
+            (pages.start_addr().into_usize()..pages.end_addr().into_usize()).contains(&sp)
+        })
+        .map(|pages| pages.end_addr().into_usize())
+}
+
+/// Print the usage of all stacks that were filled with the pattern.
+pub fn print_usage() {
+    for which in Stack::all() {
+        if let Some((used, total)) = stack_usage(which) {
+            info!(
+                "{}: {} of {} bytes ({} %)",
+                which,
+                used,
+                total,
+                (used * 100) / total
+            );
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exception;
+    use test_macros::kernel_test;
+
+    /// Check that taking an exception shows up in the executing core's exception stack usage.
+    #[kernel_test]
+    fn exception_raises_high_water_mark() {
+        exception::null_exception();
+
+        let (used, total) = stack_usage(Stack::Exception(cpu::core_id())).unwrap();
+        assert!((used > 0) && (used <= total));
+    }
+
+    /// Check that the usage of the stack that the test runs on covers at least the live part.
+    #[kernel_test]
+    fn live_stack_counts_as_used() {
+        let which = Stack::Kernel(cpu::core_id());
+        let (used, _) = stack_usage(which).unwrap();
+
+        assert!(used >= which.pages().end_addr().into_usize() - cpu::stack_pointer());
+    }
+}
@@ -0,0 +1,939 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Interactive kernel monitor.
+//!
+//! A minimal shell on the console for poking at the running kernel. Commands are looked up by name
+//! in the built-in table first, then in the table of commands registered by other subsystems.
+//!
+//! Input lines can be edited with the cursor keys, and previous lines are recalled with up and down.
+//!
+//! A break on the console asks the core that takes the UART's IRQ to enter the monitor as well, see
+//! `request_entry()`. It does so at its next call to `safe_point()`, and returns to what it was
+//! doing on `continue`. Only one core reads monitor input at a time. A break that arrives while
+//! another core does is dropped.
+//!
+//! ```text
+//! mon> md 0xffffffffc0000000 32
+//! ```
+
+mod line_editor;
+
+use crate::{
+    banner, bench, bsp, cmdline, config, console, cpu, driver,
+    error::KernelError,
+    exception::{self, fault_injection, SyncExceptionInfo},
+    fmt::table::{Column, Table},
+    loader,
+    memory::{
+        self,
+        mmu::{self, AccessPermissions, MemAttributes},
+        Address, Virtual,
+    },
+    print, println, selftest, shutdown,
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+    time,
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+use line_editor::{Event, LineEditor};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const PROMPT: &str = "mon> ";
+
+/// The prompt of a monitor that was entered on a break.
+const BREAK_PROMPT: &str = "brk> ";
+
+/// Value of `PROMPT_OWNER` while no core reads monitor input.
+const NO_CORE: usize = usize::MAX;
+
+/// Maximum length of an input line, including the line terminator.
+const MAX_LINE_LEN: usize = 128;
+
+/// Maximum number of whitespace-separated words in an input line, including the command name.
+const MAX_WORDS: usize = 8;
+
+/// Maximum number of commands that can be registered in addition to the built-in ones.
+const MAX_REGISTERED_COMMANDS: usize = 16;
+
+/// The width of `mr` and `mw` accesses if none is given, in bytes.
+const DEFAULT_ACCESS_WIDTH: usize = 4;
+
+/// How often the `load` command tries to receive an image.
+const LOAD_MAX_ATTEMPTS: usize = 3;
+
+const BUILTIN_COMMANDS: [Command; 27] = [
+    ("help", cmd_help),
+    ("mappings", cmd_mappings),
+    ("layout", cmd_layout),
+    ("drivers", cmd_drivers),
+    ("irqs", cmd_irqs),
+    ("md", cmd_md),
+    ("mr", cmd_mr),
+    ("mw", cmd_mw),
+    ("uptime", cmd_uptime),
+    ("reboot", cmd_reboot),
+    ("halt", cmd_halt),
+    ("continue", cmd_continue),
+    ("load", cmd_load),
+    ("cmdline", cmd_cmdline),
+    ("clocks", cmd_clocks),
+    ("bench", cmd_bench),
+    ("console", cmd_console),
+    ("top", cmd_top),
+    ("stacks", cmd_stacks),
+    ("heap", cmd_heap),
+    ("dma", cmd_dma),
+    ("mmutrace", cmd_mmutrace),
+    ("trace", cmd_trace),
+    ("mmiostats", cmd_mmiostats),
+    ("selftest", cmd_selftest),
+    ("coredump", cmd_coredump),
+    ("fault", cmd_fault),
+];
+
+/// The kind of access that `checked_access()` checks.
+#[derive(Copy, Clone)]
+enum Access {
+    Read,
+    Write { force: bool },
+}
+
+/// Display helper for per-core counts, separated by slashes in the order of the core ids.
+struct PerCore<'a>(&'a [usize]);
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A command handler. Receives the arguments following the command name.
+pub type CommandFn = fn(&[&str]) -> Result<(), KernelError>;
+
+/// A command, made of its name and its handler.
+pub type Command = (&'static str, CommandFn);
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static REGISTERED_COMMANDS: InitStateLock<[Option<Command>; MAX_REGISTERED_COMMANDS]> =
+    InitStateLock::new([None; MAX_REGISTERED_COMMANDS]);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether a break asked the core to enter the monitor, indexed by core id.
+static ENTRY_REQUESTED: [AtomicBool; config::cpu::NUM_CORES] =
+    [NOT_REQUESTED; config::cpu::NUM_CORES];
+
+/// The core that reads monitor input, or `NO_CORE`.
+static PROMPT_OWNER: AtomicUsize = AtomicUsize::new(NO_CORE);
+
+/// Set while a monitor that was entered on a break runs. Cleared by `continue`.
+static IN_BREAK: AtomicBool = AtomicBool::new(false);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Parse a number, which is interpreted as hexadecimal if prefixed with `0x`.
+fn parse_usize(s: &str) -> Result<usize, &'static str> {
+    let (digits, radix) = match s.strip_prefix("0x") {
+        Some(x) => (x, 16),
+        None => (s, 10),
+    };
+
+    usize::from_str_radix(digits, radix).map_err(|_| "Invalid number")
+}
+
+impl fmt::Display for PerCore<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, x) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "/")?;
+            }
+            write!(f, "{}", x)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Check an access of `width` bytes at `addr` from the `mr` and `mw` commands, and print the name of
+/// the mapping that is accessed.
+///
+/// The address must be inside a recorded mapping. Device memory only takes 32 bit accesses, which
+/// is the width of the peripherals' registers, and executable memory is only written with `force`.
+fn checked_access(addr: usize, width: usize, access: Access) -> Result<(), &'static str> {
+    if !matches!(width, 1 | 2 | 4 | 8) {
+        return Err("Width must be 1, 2, 4 or 8 bytes");
+    }
+
+    if addr % width != 0 {
+        return Err("Address is not aligned to the access width");
+    }
+
+    let start = Address::<Virtual>::new(addr);
+    let mapping = mmu::kernel_find_mapping(start).ok_or("Address is not in a recorded mapping")?;
+
+    if !mapping.virt_pages.contains(start + (width - 1)) {
+        return Err("Access crosses the end of the mapping");
+    }
+
+    let attr = mapping.attribute_fields;
+    if (attr.mem_attributes == MemAttributes::Device) && (width != 4) {
+        return Err("Device memory only takes 32 bit accesses");
+    }
+
+    if let Access::Write { force } = access {
+        if !attr.execute_never && !force {
+            return Err("Address is mapped executable. Use --force to write anyway");
+        }
+
+        if !mmu::is_virt_range_writable(start, width) {
+            return Err("Address is not mapped writable");
+        }
+    }
+
+    println!("      {}", mapping.name);
+
+    Ok(())
+}
+
+/// Read `width` bytes at `addr`. The width must be 1, 2, 4 or 8.
+unsafe fn read_volatile_width(addr: usize, width: usize) -> u64 {
+    match width {
+        1 => core::ptr::read_volatile(addr as *const u8) as u64,
+        2 => core::ptr::read_volatile(addr as *const u16) as u64,
+        4 => core::ptr::read_volatile(addr as *const u32) as u64,
+        _ => core::ptr::read_volatile(addr as *const u64),
+    }
+}
+
+/// Write the low `width` bytes of `val` to `addr`. The width must be 1, 2, 4 or 8.
+unsafe fn write_volatile_width(addr: usize, width: usize, val: u64) {
+    match width {
+        1 => core::ptr::write_volatile(addr as *mut u8, val as u8),
+        2 => core::ptr::write_volatile(addr as *mut u16, val as u16),
+        4 => core::ptr::write_volatile(addr as *mut u32, val as u32),
+        _ => core::ptr::write_volatile(addr as *mut u64, val),
+    }
+}
+
+/// Print a table on the console. Unlike `Table::print()`, the output does not depend on the log
+/// level.
+fn print_table<const N: usize>(table: &Table<N>) {
+    let mut block = print::Block::new();
+    for line in table.lines() {
+        block.line(format_args!("      {}", line));
+    }
+    block.print();
+}
+
+/// Print `prompt`, and wait until a line was entered or abandoned. Returns true if it was entered.
+/// The line is available through `editor.line()` then.
+fn read_line(prompt: &str, editor: &mut LineEditor) -> bool {
+    let console = bsp::console::console();
+
+    print!("{}", prompt);
+
+    // Keep the UART's RX interrupt from consuming the input while the line is being read.
+    let mut event = None;
+    exception::asynchronous::exec_with_irq_masked(|| {
+        cpu::spin_until(
+            || {
+                event = editor.poll(console);
+                event.is_some()
+            },
+            None,
+        )
+    });
+
+    event == Some(Event::Entered)
+}
+
+/// Make the executing core the one that reads monitor input. Returns false if another core does.
+fn claim_prompt() -> bool {
+    PROMPT_OWNER
+        .compare_exchange(
+            NO_CORE,
+            cpu::core_id(),
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        )
+        .is_ok()
+}
+
+fn release_prompt() {
+    PROMPT_OWNER.store(NO_CORE, Ordering::Release);
+}
+
+/// Run the prompt loop of a monitor that was entered on a break, until `continue`.
+fn run_break() {
+    if !claim_prompt() {
+        return;
+    }
+
+    println!();
+    println!(
+        "Break: Core {} entered the monitor. Type 'continue' to resume",
+        cpu::core_id()
+    );
+
+    let mut editor = LineEditor::new();
+    IN_BREAK.store(true, Ordering::Relaxed);
+    while IN_BREAK.load(Ordering::Relaxed) {
+        if !read_line(BREAK_PROMPT, &mut editor) {
+            continue;
+        }
+
+        if let Err(x) = execute(editor.line()) {
+            println!("Error: {}", x);
+        }
+    }
+
+    release_prompt();
+}
+
+/// Ask `question`, and return true if it was answered with `yes`.
+fn confirm(question: &str) -> bool {
+    let mut editor = LineEditor::new();
+
+    println!("{}", question);
+    read_line("Type 'yes' to continue: ", &mut editor) && (editor.line() == "yes")
+}
+
+/// The start of the first recorded mapping of device memory.
+fn first_device_mapping() -> Option<Address<Virtual>> {
+    let mut addr = None;
+
+    mmu::kernel_for_each_mapping(|x| {
+        if addr.is_none() && (x.attribute_fields.mem_attributes == MemAttributes::Device) {
+            addr = Some(x.virt_pages.start_addr());
+        }
+    });
+
+    addr
+}
+
+/// Print what the exception handler decoded about an injected fault.
+fn print_fault(info: &SyncExceptionInfo) {
+    println!("      Class:         {:?}", info.class);
+    println!("      PC:            {}", info.pc);
+
+    if let Some(addr) = info.fault_addr {
+        let name = mmu::kernel_find_mapping(addr).map_or("Not in a recorded mapping", |x| x.name);
+
+        println!("      Fault address: {} ({})", addr, name);
+    }
+
+    println!("      Syndrome:      {:#010x}", info.syndrome);
+}
+
+fn find_command(name: &str) -> Option<CommandFn> {
+    if let Some((_, f)) = BUILTIN_COMMANDS.iter().find(|(x, _)| *x == name) {
+        return Some(*f);
+    }
+
+    REGISTERED_COMMANDS.read(|commands| {
+        commands
+            .iter()
+            .flatten()
+            .find(|(x, _)| *x == name)
+            .map(|(_, f)| *f)
+    })
+}
+
+/// Split `line` into words and run the command named by the first one.
+fn execute(line: &str) -> Result<(), KernelError> {
+    let mut words = [""; MAX_WORDS];
+    let mut num_words = 0;
+
+    for word in line.split_whitespace() {
+        if num_words == MAX_WORDS {
+            return Err("Too many arguments".into());
+        }
+
+        words[num_words] = word;
+        num_words += 1;
+    }
+
+    if num_words == 0 {
+        return Ok(());
+    }
+
+    match find_command(words[0]) {
+        None => Err("Unknown command. Try 'help'".into()),
+        Some(f) => f(&words[1..num_words]),
+    }
+}
+
+fn cmd_help(_args: &[&str]) -> Result<(), KernelError> {
+    println!("Available commands:");
+
+    for (name, _) in BUILTIN_COMMANDS.iter() {
+        println!("      {}", name);
+    }
+
+    REGISTERED_COMMANDS.read(|commands| {
+        for (name, _) in commands.iter().flatten() {
+            println!("      {}", name);
+        }
+    });
+
+    Ok(())
+}
+
+fn cmd_mappings(_args: &[&str]) -> Result<(), KernelError> {
+    mmu::kernel_print_mappings();
+
+    Ok(())
+}
+
+fn cmd_layout(_args: &[&str]) -> Result<(), KernelError> {
+    for section in bsp::memory::mmu::virt_mem_layout() {
+        let attr = section.attribute_fields;
+
+        println!(
+            "      {}..{} --> {}..{} | {} {} | {}",
+            section.virt_pages.start_addr(),
+            section.virt_pages.end_addr_inclusive(),
+            section.phys_pages.start_addr(),
+            section.phys_pages.end_addr_inclusive(),
+            match attr.acc_perms {
+                AccessPermissions::ReadOnly => "RO",
+                AccessPermissions::ReadWrite => "RW",
+            },
+            if attr.execute_never { "XN" } else { "X " },
+            section.name
+        );
+    }
+
+    Ok(())
+}
+
+fn cmd_drivers(_args: &[&str]) -> Result<(), KernelError> {
+    driver::driver_manager().print_status();
+
+    Ok(())
+}
+
+fn cmd_irqs(_args: &[&str]) -> Result<(), KernelError> {
+    use exception::asynchronous::interface::IRQManager;
+
+    let mut table = Table::new([Column::right("Core"), Column::right("IRQs taken")]);
+    for core_id in 0..config::cpu::NUM_CORES {
+        table.row(|r| {
+            r.cell(core_id)
+                .cell(exception::asynchronous::num_irqs_taken(core_id));
+        });
+    }
+    print_table(&table);
+    println!();
+
+    bsp::exception::asynchronous::irq_manager().print_handler();
+    println!();
+
+    let mut table = Table::new([
+        Column::left("Handler"),
+        Column::right("Handled per core"),
+        Column::right("Errors"),
+        Column::right("In a row"),
+        Column::left("Masked"),
+        Column::left("Last error"),
+    ]);
+    bsp::exception::asynchronous::irq_manager().handler_health(&mut |x| {
+        table.row(|r| {
+            r.cell(x.name)
+                .cell(PerCore(&x.num_handled))
+                .cell(x.num_errors)
+                .cell(x.num_consecutive_errors)
+                .cell(if x.masked { "yes" } else { "no" })
+                .cell(x.last_error.unwrap_or("-"));
+        })
+    });
+    print_table(&table);
+
+    Ok(())
+}
+
+fn cmd_md(args: &[&str]) -> Result<(), KernelError> {
+    if args.len() != 2 {
+        return Err("Usage: md <addr> <len>".into());
+    }
+
+    let addr = parse_usize(args[0])?;
+    let len = parse_usize(args[1])?;
+
+    console::hexdump(Address::<Virtual>::new(addr), len)
+}
+
+fn cmd_mr(args: &[&str]) -> Result<(), KernelError> {
+    let (addr, width) = match args {
+        [addr] => (parse_usize(addr)?, DEFAULT_ACCESS_WIDTH),
+        [addr, width] => (parse_usize(addr)?, parse_usize(width)?),
+        _ => return Err("Usage: mr <addr> [width]".into()),
+    };
+
+    checked_access(addr, width, Access::Read)?;
+
+    let val = unsafe { read_volatile_width(addr, width) };
+    println!("      {:#018x}: {:#x}", addr, val);
+
+    Ok(())
+}
+
+fn cmd_mw(args: &[&str]) -> Result<(), KernelError> {
+    let (force, args) = match args {
+        ["--force", rest @ ..] => (true, rest),
+        _ => (false, args),
+    };
+
+    let (addr, val, width) = match args {
+        [addr, val] => (parse_usize(addr)?, parse_usize(val)?, DEFAULT_ACCESS_WIDTH),
+        [addr, val, width] => (parse_usize(addr)?, parse_usize(val)?, parse_usize(width)?),
+        _ => return Err("Usage: mw [--force] <addr> <val> [width]".into()),
+    };
+
+    if (width < 8) && (val >> (width * 8) != 0) {
+        return Err("Value does not fit into the access width".into());
+    }
+
+    checked_access(addr, width, Access::Write { force })?;
+
+    let read_back = unsafe {
+        write_volatile_width(addr, width, val as u64);
+        read_volatile_width(addr, width)
+    };
+
+    // Common for registers that clear bits on write, or that have read-only bits.
+    if read_back != val as u64 {
+        println!(
+            "      Warning: Read back {:#x} after writing {:#x}",
+            read_back, val
+        );
+    }
+
+    Ok(())
+}
+
+fn cmd_uptime(_args: &[&str]) -> Result<(), KernelError> {
+    use time::interface::TimeManager;
+
+    let uptime = time::time_manager().uptime();
+    let secs = uptime.as_secs();
+
+    println!(
+        "Up {}h {:02}m {:02}.{:03}s",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60,
+        (uptime - Duration::from_secs(secs)).as_millis()
+    );
+
+    Ok(())
+}
+
+fn cmd_reboot(_args: &[&str]) -> Result<(), KernelError> {
+    println!("Rebooting...");
+
+    shutdown::kernel_shutdown(shutdown::ShutdownReason::Reboot)
+}
+
+fn cmd_halt(_args: &[&str]) -> Result<(), KernelError> {
+    println!("Halting...");
+
+    shutdown::kernel_shutdown(shutdown::ShutdownReason::Halt)
+}
+
+fn cmd_continue(_args: &[&str]) -> Result<(), KernelError> {
+    if !IN_BREAK.swap(false, Ordering::Relaxed) {
+        return Err("The monitor was not entered on a break".into());
+    }
+
+    println!("Resuming...");
+
+    Ok(())
+}
+
+fn cmd_load(args: &[&str]) -> Result<(), KernelError> {
+    use console::interface::Write;
+
+    let dest = match args {
+        [] => loader::staging_area()
+            .ok_or_else(|| loader::LoadError::NotInitialized.as_str())?
+            .start_addr(),
+        [x] => Address::new(parse_usize(x)?),
+        _ => return Err("Usage: load [phys_addr]".into()),
+    };
+
+    println!("Waiting for an image. Push it with 'minipush.rb --checksum'");
+    let image = loader::load_retrying(dest, LOAD_MAX_ATTEMPTS)?;
+
+    println!(
+        "Loaded {} KiB to {} (CRC-32 {:#010x}). Booting it now",
+        image.size() / 1024,
+        image.start_addr(),
+        image.checksum()
+    );
+    bsp::console::console().flush();
+
+    unsafe { image.boot() }
+}
+
+fn cmd_cmdline(_args: &[&str]) -> Result<(), KernelError> {
+    cmdline::print();
+
+    Ok(())
+}
+
+fn cmd_clocks(_args: &[&str]) -> Result<(), KernelError> {
+    banner::print_section("Clocks")
+}
+
+fn cmd_console(args: &[&str]) -> Result<(), KernelError> {
+    use console::interface::Statistics;
+
+    match args {
+        [] => (),
+        ["use", name] => return console::switch_primary(name),
+        _ => return Err("Usage: console [use <name>]".into()),
+    }
+
+    // The statistics of the multiplexer are the ones of the input console.
+    let mux = console::mux::console_mux();
+    println!(
+        "Primary console:       {}",
+        mux.source_name().unwrap_or("None")
+    );
+    println!("Chars written:         {}", mux.chars_written());
+    println!("Chars read:            {}", mux.chars_read());
+    println!("TX queue high-water:   {}", mux.tx_queue_high_water());
+    println!("RX queue high-water:   {}", mux.rx_queue_high_water());
+    println!("RX dropped:            {}", mux.rx_dropped());
+    println!("TX blocking fallbacks: {}", mux.tx_blocking_fallbacks());
+    println!("Output lock timeouts:  {}", mux.num_lock_timeouts());
+
+    for i in 0..mux.num_sinks() {
+        if let Some(x) = mux.sink_statistics(i) {
+            println!(
+                "Sink {}: {} errors, {} overflows",
+                i, x.num_errors, x.num_overflows
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_bench(args: &[&str]) -> Result<(), KernelError> {
+    if args.is_empty() {
+        bench::run_all();
+        return Ok(());
+    }
+
+    for name in args {
+        bench::run(name)?;
+    }
+
+    Ok(())
+}
+
+fn cmd_top(args: &[&str]) -> Result<(), KernelError> {
+    let interval = match args {
+        [] => Duration::from_secs(1),
+        [x] => Duration::from_secs(parse_usize(x)? as u64),
+        _ => return Err("Usage: top [seconds]".into()),
+    };
+
+    let earlier = cpu::idle::snapshot();
+
+    // Idle in the meantime, so that the monitor itself does not show up as busy.
+    cpu::spin_until(|| false, Some(interval));
+
+    for (core_id, x) in cpu::idle::utilization(&earlier).iter().enumerate() {
+        println!("      Core {}: {}", core_id, x);
+    }
+
+    println!();
+    println!("      Wake-ups from idle since boot:");
+    for (name, count) in cpu::idle::wakeup_histogram().iter() {
+        println!("      {:>10}  {}", count, name);
+    }
+
+    Ok(())
+}
+
+fn cmd_stacks(_args: &[&str]) -> Result<(), KernelError> {
+    memory::stack::print_usage();
+
+    Ok(())
+}
+
+fn cmd_heap(_args: &[&str]) -> Result<(), KernelError> {
+    memory::heap::print_stats();
+
+    Ok(())
+}
+
+fn cmd_dma(_args: &[&str]) -> Result<(), KernelError> {
+    memory::dma_pool::print_stats();
+
+    Ok(())
+}
+
+fn cmd_mmutrace(_args: &[&str]) -> Result<(), KernelError> {
+    #[cfg(feature = "mmu_trace")]
+    {
+        memory::mmu::trace::dump_trace();
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "mmu_trace"))]
+    {
+        Err("Kernel built without the mmu_trace feature".into())
+    }
+}
+
+fn cmd_trace(_args: &[&str]) -> Result<(), KernelError> {
+    #[cfg(feature = "event_trace")]
+    {
+        crate::trace::dump();
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "event_trace"))]
+    {
+        Err("Kernel built without the event_trace feature".into())
+    }
+}
+
+fn cmd_mmiostats(args: &[&str]) -> Result<(), KernelError> {
+    #[cfg(feature = "mmio_stats")]
+    {
+        let interval = match args {
+            [] => None,
+            [x] => Some(Duration::from_secs(parse_usize(x)? as u64)),
+            _ => return Err("Usage: mmiostats [seconds]".into()),
+        };
+
+        let stats = match interval {
+            None => driver::driver_manager().mmio_stats(),
+            Some(x) => {
+                let earlier = driver::driver_manager().mmio_stats();
+                cpu::spin_until(|| false, Some(x));
+
+                println!("      Accesses in the last {} s:", x.as_secs());
+                driver::driver_manager().mmio_stats().since(&earlier)
+            }
+        };
+        stats.print();
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "mmio_stats"))]
+    {
+        let _ = args;
+
+        Err("Kernel built without the mmio_stats feature".into())
+    }
+}
+
+fn cmd_selftest(_args: &[&str]) -> Result<(), KernelError> {
+    selftest::run_all()
+}
+
+fn cmd_coredump(_args: &[&str]) -> Result<(), KernelError> {
+    exception::core_dump();
+
+    Ok(())
+}
+
+fn cmd_fault(args: &[&str]) -> Result<(), KernelError> {
+    let info = match args {
+        ["translation"] => fault_injection::trigger_translation_fault(),
+        ["permission"] => fault_injection::trigger_permission_fault(),
+        ["alignment"] => {
+            let addr = first_device_mapping().ok_or("No device memory is mapped")?;
+
+            fault_injection::trigger_alignment_fault_at(addr)
+        }
+        ["brk"] => fault_injection::trigger_brk(),
+        ["svc"] => fault_injection::trigger_svc(),
+        ["stack-overflow"] => {
+            if !confirm("The stack overflow is not recovered from. The kernel panics.") {
+                return Ok(());
+            }
+
+            fault_injection::trigger_stack_overflow()
+        }
+        _ => {
+            return Err(
+                "Usage: fault <translation|permission|alignment|brk|svc|stack-overflow>".into(),
+            )
+        }
+    }?;
+
+    println!("Recovered from the exception:");
+    print_fault(&info);
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Register an additional command.
+///
+/// Must be called during kernel init. Built-in commands take precedence over registered ones with
+/// the same name.
+pub fn register_command(command: Command) -> Result<(), &'static str> {
+    REGISTERED_COMMANDS.write(|commands| {
+        if commands.iter().flatten().any(|(x, _)| *x == command.0) {
+            return Err("Command already registered");
+        }
+
+        match commands.iter_mut().find(|x| x.is_none()) {
+            None => Err("Command table full"),
+            Some(x) => {
+                *x = Some(command);
+                Ok(())
+            }
+        }
+    })
+}
+
+/// Ask the executing core to enter the monitor at its next `safe_point()`.
+///
+/// Called by the console's IRQ handler on a break, if `config::console::BREAK_ENTERS_MONITOR` is
+/// on.
+pub fn request_entry() {
+    ENTRY_REQUESTED[cpu::core_id()].store(true, Ordering::Relaxed);
+}
+
+/// Whether the executing core was asked to enter the monitor.
+pub fn entry_requested() -> bool {
+    ENTRY_REQUESTED[cpu::core_id()].load(Ordering::Relaxed)
+}
+
+/// Enter the monitor if the executing core was asked to, and return once it is continued.
+///
+/// Call from places where the executing core may stop for an indefinite time: outside of IRQ
+/// context, and without holding any locks. Does nothing in IRQ context.
+pub fn safe_point() {
+    if exception::asynchronous::in_irq_context() {
+        return;
+    }
+
+    if ENTRY_REQUESTED[cpu::core_id()].swap(false, Ordering::Relaxed) {
+        run_break();
+    }
+}
+
+/// Run the monitor's prompt loop.
+pub fn run() -> ! {
+    use console::interface::Read;
+
+    let mut editor = LineEditor::new();
+
+    bsp::console::console().clear_rx();
+    loop {
+        // Wait for a monitor that was entered on a break on another core to be continued.
+        cpu::spin_until(claim_prompt, None);
+        let entered = read_line(PROMPT, &mut editor);
+        release_prompt();
+
+        if !entered {
+            continue;
+        }
+
+        if let Err(x) = execute(editor.line()) {
+            println!("Error: {}", x);
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check number parsing in both bases.
+    #[kernel_test]
+    fn parse_usize_works() {
+        assert_eq!(parse_usize("42"), Ok(42));
+        assert_eq!(parse_usize("0x2a"), Ok(42));
+        assert!(parse_usize("0x").is_err());
+        assert!(parse_usize("forty-two").is_err());
+    }
+
+    /// Check that lines are dispatched by the first word and unknown commands are reported.
+    #[kernel_test]
+    fn execute_dispatches() {
+        assert_eq!(execute("   "), Ok(()));
+        assert!(execute("does_not_exist").is_err());
+        assert_eq!(execute("md 0x0"), Err("Usage: md <addr> <len>".into()));
+    }
+
+    /// Check that a break request is kept per core, and that `continue` needs a break.
+    #[kernel_test]
+    fn break_request_is_per_core() {
+        assert!(!entry_requested());
+        request_entry();
+        assert!(entry_requested());
+        ENTRY_REQUESTED[cpu::core_id()].store(false, Ordering::Relaxed);
+
+        assert!(execute("continue").is_err());
+    }
+
+    /// Check that the recoverable faults are survived, and reported with the right class.
+    #[kernel_test]
+    fn fault_command_recovers() {
+        for kind in ["translation", "permission", "brk", "svc"].iter() {
+            assert_eq!(cmd_fault(&[*kind]), Ok(()));
+        }
+        assert!(cmd_fault(&["does_not_exist"]).is_err());
+    }
+
+    /// Check the safety rails of `mr` and `mw`.
+    #[kernel_test]
+    fn memory_access_is_checked() {
+        static mut TARGET: u64 = 0;
+        let target = unsafe { &TARGET as *const _ as usize };
+
+        assert_eq!(
+            checked_access(0, 4, Access::Read),
+            Err("Address is not in a recorded mapping")
+        );
+        assert_eq!(
+            checked_access(target + 1, 4, Access::Read),
+            Err("Address is not aligned to the access width")
+        );
+        assert_eq!(checked_access(target, 8, Access::Read), Ok(()));
+
+        let code = cmd_mr as *const () as usize & !0b111;
+        assert_eq!(
+            checked_access(code, 8, Access::Write { force: false }),
+            Err("Address is mapped executable. Use --force to write anyway")
+        );
+        assert_eq!(
+            checked_access(code, 8, Access::Write { force: true }),
+            Err("Address is not mapped writable")
+        );
+    }
+}
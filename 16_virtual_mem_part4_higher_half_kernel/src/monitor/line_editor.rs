@@ -0,0 +1,306 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Line editing for the monitor.
+//!
+//! On top of what `read_line()` of the console does, the editor keeps a history of the entered
+//! lines and understands the cursor keys:
+//!
+//! - Up and down walk through the history.
+//! - Left and right move the cursor. Characters are inserted at the cursor.
+//! - Ctrl-C abandons the line, and a bare ESC erases it.
+//!
+//! Input is taken with `try_read_char()`, one call to `poll()` at a time, so the caller decides how
+//! to wait between characters.
+
+use super::MAX_LINE_LEN;
+use crate::{console, time, time::interface::TimeManager};
+use core::{str, time::Duration};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Number of entered lines that are kept.
+const HISTORY_LEN: usize = 8;
+
+/// How long to wait for the rest of an escape sequence before taking an ESC as a key of its own.
+const ESC_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Maximum number of characters in a line. The console's `read_line()` counts the line
+/// terminator, too, so this keeps both interfaces at the same limit.
+const MAX_CHARS: usize = MAX_LINE_LEN - 1;
+
+/// Progress through an escape sequence. The durations are the uptime at which the ESC arrived.
+#[derive(Copy, Clone)]
+enum Escape {
+    None,
+    Esc(Duration),
+    Csi(Duration),
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The outcome of a line.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// The line was entered. It is available through `line()` until the next `poll()`.
+    Entered,
+
+    /// The line was abandoned with Ctrl-C.
+    Abandoned,
+}
+
+/// A line being edited, and the history of previous lines.
+pub struct LineEditor {
+    buf: [u8; MAX_CHARS],
+    len: usize,
+    cursor: usize,
+    finished: bool,
+
+    history: [[u8; MAX_CHARS]; HISTORY_LEN],
+    history_lens: [usize; HISTORY_LEN],
+    num_entered: usize,
+
+    /// How many lines back in the history the current line was taken from. Zero if it was not.
+    browsing: usize,
+
+    escape: Escape,
+    echo: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl LineEditor {
+    /// The entry `back` lines before the newest one, starting at one.
+    fn history_entry(&self, back: usize) -> &[u8] {
+        let i = (self.num_entered - back) % HISTORY_LEN;
+
+        &self.history[i][..self.history_lens[i]]
+    }
+
+    fn push_history(&mut self) {
+        let line = &self.buf[..self.len];
+        if line.is_empty() || ((self.num_entered > 0) && (self.history_entry(1) == line)) {
+            return;
+        }
+
+        let i = self.num_entered % HISTORY_LEN;
+        self.history[i][..self.len].copy_from_slice(line);
+        self.history_lens[i] = self.len;
+        self.num_entered += 1;
+    }
+
+    fn print(&self, console: &impl console::interface::Read, args: core::fmt::Arguments) {
+        if self.echo {
+            let _ = console.write_fmt(args);
+        }
+    }
+
+    /// Move the terminal's cursor `n` columns left.
+    fn cursor_left(&self, console: &impl console::interface::Read, n: usize) {
+        if n > 0 {
+            self.print(console, format_args!("\x1b[{}D", n));
+        }
+    }
+
+    /// Print the line from the cursor to its end, erasing `extra` columns behind it, and put the
+    /// terminal's cursor back.
+    fn redraw_tail(&self, console: &impl console::interface::Read, extra: usize) {
+        let tail = str::from_utf8(&self.buf[self.cursor..self.len]).unwrap_or("");
+        self.print(console, format_args!("{}{:2$}", tail, "", extra));
+        self.cursor_left(console, tail.len() + extra);
+    }
+
+    /// Replace the line, for example with a history entry.
+    fn replace_line(&mut self, console: &impl console::interface::Read, back: usize) {
+        self.cursor_left(console, self.cursor);
+        self.print(console, format_args!("\x1b[K"));
+
+        self.len = if back == 0 {
+            0
+        } else {
+            let i = (self.num_entered - back) % HISTORY_LEN;
+            let len = self.history_lens[i];
+            self.buf[..len].copy_from_slice(&self.history[i][..len]);
+
+            len
+        };
+        self.cursor = self.len;
+        self.browsing = back;
+        self.print(console, format_args!("{}", self.line()));
+    }
+
+    fn move_cursor_to(&mut self, console: &impl console::interface::Read, pos: usize) {
+        if pos < self.cursor {
+            self.cursor_left(console, self.cursor - pos);
+        } else if pos > self.cursor {
+            self.print(console, format_args!("\x1b[{}C", pos - self.cursor));
+        }
+
+        self.cursor = pos;
+    }
+
+    fn insert(&mut self, console: &impl console::interface::Read, c: u8) {
+        if self.len == MAX_CHARS {
+            return;
+        }
+
+        self.buf.copy_within(self.cursor..self.len, self.cursor + 1);
+        self.buf[self.cursor] = c;
+        self.len += 1;
+
+        self.print(console, format_args!("{}", c as char));
+        self.cursor += 1;
+        self.redraw_tail(console, 0);
+    }
+
+    fn erase_before_cursor(&mut self, console: &impl console::interface::Read) {
+        if self.cursor == 0 {
+            return;
+        }
+
+        self.buf.copy_within(self.cursor..self.len, self.cursor - 1);
+        self.len -= 1;
+
+        self.cursor_left(console, 1);
+        self.cursor -= 1;
+        self.redraw_tail(console, 1);
+    }
+
+    /// Handle the final character of a CSI sequence.
+    fn handle_csi(&mut self, console: &impl console::interface::Read, c: char) {
+        match c {
+            'A' if self.browsing < self.num_entered.min(HISTORY_LEN) => {
+                self.replace_line(console, self.browsing + 1)
+            }
+            'B' if self.browsing > 0 => self.replace_line(console, self.browsing - 1),
+            'C' if self.cursor < self.len => self.move_cursor_to(console, self.cursor + 1),
+            'D' if self.cursor > 0 => self.move_cursor_to(console, self.cursor - 1),
+            _ => (),
+        }
+    }
+
+    /// Handle a character outside of an escape sequence.
+    fn handle_char(
+        &mut self,
+        console: &impl console::interface::Read,
+        c: char,
+        now: Duration,
+    ) -> Option<Event> {
+        match c {
+            '\r' | '\n' => {
+                self.print(console, format_args!("\n"));
+                self.push_history();
+                self.finished = true;
+
+                return Some(Event::Entered);
+            }
+            '\x03' => {
+                self.print(console, format_args!("^C\n"));
+                self.len = 0;
+                self.finished = true;
+
+                return Some(Event::Abandoned);
+            }
+            '\x1b' => self.escape = Escape::Esc(now),
+            '\x08' | '\x7f' => self.erase_before_cursor(console),
+            c if c.is_ascii_graphic() || c == ' ' => self.insert(console, c as u8),
+            _ => (),
+        }
+
+        None
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl LineEditor {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_CHARS],
+            len: 0,
+            cursor: 0,
+            finished: false,
+            history: [[0; MAX_CHARS]; HISTORY_LEN],
+            history_lens: [0; HISTORY_LEN],
+            num_entered: 0,
+            browsing: 0,
+            escape: Escape::None,
+            echo: true,
+        }
+    }
+
+    /// Process the characters that are available on `console`, without waiting.
+    ///
+    /// Returns an event when a line was finished. A new line is started with the next call.
+    pub fn poll(&mut self, console: &impl console::interface::Read) -> Option<Event> {
+        if self.finished {
+            self.len = 0;
+            self.cursor = 0;
+            self.browsing = 0;
+            self.finished = false;
+        }
+
+        self.echo = console.is_echo_enabled();
+        let now = time::time_manager().uptime();
+
+        while let Some(c) = console.try_read_char() {
+            match self.escape {
+                Escape::None => {
+                    if let Some(x) = self.handle_char(console, c, now) {
+                        return Some(x);
+                    }
+                }
+                Escape::Esc(start) => {
+                    if c == '[' {
+                        self.escape = Escape::Csi(start);
+                        continue;
+                    }
+
+                    // A bare ESC, followed by an unrelated key.
+                    self.escape = Escape::None;
+                    self.replace_line(console, 0);
+                    if let Some(x) = self.handle_char(console, c, now) {
+                        return Some(x);
+                    }
+                }
+                Escape::Csi(_) => {
+                    // Parameters, for example of "ESC [ 3 ~", are skipped.
+                    if c.is_ascii_digit() || c == ';' {
+                        continue;
+                    }
+
+                    self.escape = Escape::None;
+                    self.handle_csi(console, c);
+                }
+            }
+        }
+
+        // Nothing followed the ESC in time, so it was a key of its own. Incomplete sequences are
+        // dropped.
+        match self.escape {
+            Escape::Esc(start) if now - start >= ESC_TIMEOUT => {
+                self.escape = Escape::None;
+                self.replace_line(console, 0);
+            }
+            Escape::Csi(start) if now - start >= ESC_TIMEOUT => self.escape = Escape::None,
+            _ => (),
+        }
+
+        None
+    }
+
+    /// The line that was entered last.
+    pub fn line(&self) -> &str {
+        str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
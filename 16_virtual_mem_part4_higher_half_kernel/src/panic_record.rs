@@ -0,0 +1,282 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Panic information that survives a reset.
+//!
+//! The panic handler writes a compact record into memory that is not zeroed at boot. The next boot
+//! reports and clears it, so that the reason for a watchdog reset is not lost together with the
+//! console output.
+//!
+//! The power management block has no scratch register to spare, since the spare bits of the reset
+//! status register carry the boot partition. The record is kept in DRAM instead, which keeps its
+//! content across a reset, though not across a power cycle. A magic number and a CRC reject
+//! whatever the DRAM holds after a cold boot, and records of a kernel with a different layout.
+
+use crate::{
+    bsp, common, config, cpu, exception,
+    memory::{Address, Virtual},
+    warn,
+};
+use core::{
+    fmt, mem,
+    panic::PanicInfo,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Marks a record as written by this kernel.
+const MAGIC: u32 = 0x5041_4E43;
+
+/// Bytes of the panic message that are kept.
+const MESSAGE_CAPACITY: usize = 160;
+
+/// The record as stored in memory.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PanicRecord {
+    magic: u32,
+
+    /// CRC of the record, computed with this field set to zero.
+    crc: u32,
+
+    uptime_us: u64,
+
+    /// The address of the instruction that caused a fatal exception, or zero.
+    pc: u64,
+
+    core_id: u32,
+
+    /// Length of the full message. Only `MESSAGE_CAPACITY` bytes of it are kept.
+    message_len: u32,
+
+    /// CRC of the full message, which tells apart messages that only differ after the cut.
+    message_crc: u32,
+
+    _reserved: u32,
+
+    num_irqs_taken: [u64; config::cpu::NUM_CORES],
+    message: [u8; MESSAGE_CAPACITY],
+}
+
+/// Appends formatted output to a record's message, keeping track of the full length and CRC.
+struct MessageWriter<'a> {
+    record: &'a mut PanicRecord,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_PC: AtomicU64 = AtomicU64::new(0);
+
+/// The address of the instruction that caused a fatal exception, indexed by core id.
+static FAULTING_PC: [AtomicU64; config::cpu::NUM_CORES] = [NO_PC; config::cpu::NUM_CORES];
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl PanicRecord {
+    const fn empty() -> Self {
+        Self {
+            magic: 0,
+            crc: 0,
+            uptime_us: 0,
+            pc: 0,
+            core_id: 0,
+            message_len: 0,
+            message_crc: 0,
+            _reserved: 0,
+            num_irqs_taken: [0; config::cpu::NUM_CORES],
+            message: [0; MESSAGE_CAPACITY],
+        }
+    }
+
+    fn compute_crc(&self) -> u32 {
+        let mut copy = *self;
+        copy.crc = 0;
+
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                &copy as *const _ as *const u8,
+                mem::size_of::<PanicRecord>(),
+            )
+        };
+
+        common::crc32_update(0, bytes)
+    }
+
+    fn seal(&mut self) {
+        self.magic = MAGIC;
+        self.crc = self.compute_crc();
+    }
+
+    fn is_valid(&self) -> bool {
+        (self.magic == MAGIC) && (self.crc == self.compute_crc())
+    }
+
+    /// The kept part of the message, cut at the last complete character.
+    fn message(&self) -> &str {
+        let len = (self.message_len as usize).min(MESSAGE_CAPACITY);
+
+        match core::str::from_utf8(&self.message[..len]) {
+            Ok(x) => x,
+            Err(e) => unsafe { core::str::from_utf8_unchecked(&self.message[..e.valid_up_to()]) },
+        }
+    }
+
+    fn is_truncated(&self) -> bool {
+        self.message_len as usize > MESSAGE_CAPACITY
+    }
+}
+
+impl fmt::Write for MessageWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let record = &mut *self.record;
+        let start = record.message_len as usize;
+
+        if start < MESSAGE_CAPACITY {
+            let n = s.len().min(MESSAGE_CAPACITY - start);
+            record.message[start..(start + n)].copy_from_slice(&s.as_bytes()[..n]);
+        }
+
+        record.message_len = record.message_len.saturating_add(s.len() as u32);
+        record.message_crc = common::crc32_update(record.message_crc, s.as_bytes());
+
+        Ok(())
+    }
+}
+
+/// The record in the persistent memory.
+fn record_ptr() -> *mut PanicRecord {
+    let (start, size) = bsp::memory::virt_persistent_region();
+    assert!(mem::size_of::<PanicRecord>() <= size);
+
+    start.into_usize() as *mut PanicRecord
+}
+
+/// Make sure that the record reaches the DRAM. A reset drops dirty cache lines.
+fn flush(ptr: *mut PanicRecord) {
+    unsafe {
+        cpu::cache::clean_invalidate_dcache_range(ptr as usize, mem::size_of::<PanicRecord>())
+    };
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Remember the address of the instruction that caused a fatal exception on the executing core.
+///
+/// Called by the exception handler right before it panics.
+pub fn set_faulting_pc(pc: Address<Virtual>) {
+    FAULTING_PC[cpu::core_id()].store(pc.into_usize() as u64, Ordering::Relaxed);
+}
+
+/// Write the record of a panic. Only called from the panic handler.
+pub fn write(info: &PanicInfo, uptime: Duration) {
+    use fmt::Write;
+
+    let core_id = cpu::core_id();
+    let mut record = PanicRecord::empty();
+
+    record.uptime_us = uptime.as_micros() as u64;
+    record.pc = FAULTING_PC[core_id].load(Ordering::Relaxed);
+    record.core_id = core_id as u32;
+    for (i, x) in record.num_irqs_taken.iter_mut().enumerate() {
+        *x = exception::asynchronous::num_irqs_taken(i) as u64;
+    }
+
+    let mut writer = MessageWriter {
+        record: &mut record,
+    };
+    if let Some(args) = info.message() {
+        let _ = writer.write_fmt(*args);
+    }
+    if let Some(location) = info.location() {
+        let _ = write!(writer, " at {}:{}", location.file(), location.line());
+    }
+
+    record.seal();
+
+    let ptr = record_ptr();
+    unsafe { core::ptr::write_volatile(ptr, record) };
+    flush(ptr);
+}
+
+/// Print the record that the previous boot left behind, if any, and clear it.
+pub fn report_and_clear() {
+    let ptr = record_ptr();
+    let record = unsafe { core::ptr::read_volatile(ptr) };
+
+    if !record.is_valid() {
+        return;
+    }
+
+    warn!(
+        "Previous boot panicked on core {} at {}.{:06} s: {}{}",
+        record.core_id,
+        record.uptime_us / 1_000_000,
+        record.uptime_us % 1_000_000,
+        record.message(),
+        if record.is_truncated() { " [...]" } else { "" }
+    );
+    if record.pc != 0 {
+        warn!("      Faulting instruction: {:#018x}", record.pc);
+    }
+    warn!(
+        "      Message CRC: {:#010x}, IRQs taken per core: {:?}",
+        record.message_crc, record.num_irqs_taken
+    );
+
+    unsafe { core::ptr::write_volatile(ptr, PanicRecord::empty()) };
+    flush(ptr);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fmt::Write;
+    use test_macros::kernel_test;
+
+    /// Check that a sealed record is accepted, and that any change invalidates it.
+    #[kernel_test]
+    fn records_are_checked() {
+        let mut record = PanicRecord::empty();
+        assert!(!record.is_valid());
+
+        record.uptime_us = 42;
+        record.seal();
+        assert!(record.is_valid());
+
+        record.message[0] ^= 1;
+        assert!(!record.is_valid());
+    }
+
+    /// Check that long messages are cut, but counted in full.
+    #[kernel_test]
+    fn long_messages_are_truncated() {
+        let mut record = PanicRecord::empty();
+        let mut writer = MessageWriter {
+            record: &mut record,
+        };
+
+        for _ in 0..MESSAGE_CAPACITY {
+            write!(writer, "ab").unwrap();
+        }
+
+        assert_eq!(record.message_len as usize, 2 * MESSAGE_CAPACITY);
+        assert_eq!(record.message().len(), MESSAGE_CAPACITY);
+        assert!(record.is_truncated());
+    }
+}
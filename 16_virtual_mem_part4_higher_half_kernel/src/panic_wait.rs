@@ -2,18 +2,160 @@
 //
 // Copyright (c) 2018-2021 Andre Richter <andre.o.richter@gmail.com>
 
-//! A panic handler that infinitely waits.
+//! A panic handler that shuts down the kernel.
 
-use crate::{bsp, cpu, exception};
-use core::{fmt, panic::PanicInfo};
+use crate::{
+    bsp, console, cpu, error, exception, memory, panic_record,
+    shutdown::{self, ShutdownReason},
+    state,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    time,
+    time::interface::TimeManager,
+};
+use core::{
+    fmt,
+    panic::PanicInfo,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Checks whether formatted output starts with a given prefix, without buffering the output.
+///
+/// Formatting is aborted as soon as the outcome is known.
+struct PrefixMatcher {
+    prefix: &'static [u8],
+    num_matched: usize,
+}
+
+/// Checks whether formatted output contains a given substring, without buffering the output.
+///
+/// Formatting is aborted as soon as the substring was found.
+struct SubstringMatcher {
+    needle: &'static [u8],
+    num_matched: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// Bitmask of the cores that are executing the panic handler. Bit `n` corresponds to core id `n`.
+static PANICKING_CORES: AtomicU8 = AtomicU8::new(0);
+
+/// If set, a panic is expected by the test runner, with a message containing the substring.
+static EXPECTED_PANIC: IRQSafeNullLock<Option<&'static str>> = IRQSafeNullLock::new(None);
 
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
 
+/// Set the executing core's panicking flag. Returns true if it was already set.
+fn enter_panic_handler() -> bool {
+    let core_mask = 1 << cpu::core_id();
+
+    PANICKING_CORES.fetch_or(core_mask, Ordering::AcqRel) & core_mask != 0
+}
+
+impl PrefixMatcher {
+    const fn new(prefix: &'static str) -> Self {
+        Self {
+            prefix: prefix.as_bytes(),
+            num_matched: 0,
+        }
+    }
+
+    fn is_match(&self) -> bool {
+        self.num_matched == self.prefix.len()
+    }
+}
+
+impl fmt::Write for PrefixMatcher {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for b in s.bytes() {
+            if self.is_match() || self.prefix[self.num_matched] != b {
+                return Err(fmt::Error);
+            }
+
+            self.num_matched += 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl SubstringMatcher {
+    const fn new(needle: &'static str) -> Self {
+        Self {
+            needle: needle.as_bytes(),
+            num_matched: 0,
+        }
+    }
+
+    fn is_match(&self) -> bool {
+        self.num_matched == self.needle.len()
+    }
+
+    /// The length of the longest prefix of the needle that ends the output after `b` was appended.
+    fn advance(&self, b: u8) -> usize {
+        let matched = &self.needle[..self.num_matched];
+
+        (0..=self.num_matched)
+            .rev()
+            .find(|&k| self.needle[k] == b && matched.ends_with(&self.needle[..k]))
+            .map_or(0, |k| k + 1)
+    }
+}
+
+impl fmt::Write for SubstringMatcher {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for b in s.bytes() {
+            if self.is_match() {
+                return Err(fmt::Error);
+            }
+
+            self.num_matched = self.advance(b);
+        }
+
+        Ok(())
+    }
+}
+
+/// Return true if the panic message contains `needle`.
+fn message_contains(info: &PanicInfo, needle: &'static str) -> bool {
+    let mut matcher = SubstringMatcher::new(needle);
+
+    if let Some(args) = info.message() {
+        let _ = fmt::write(&mut matcher, *args);
+    }
+
+    matcher.is_match()
+}
+
+/// Return true if the panic was caused by a failed `assert!()` and friends.
+fn is_assertion_failure(info: &PanicInfo) -> bool {
+    let args = match info.message() {
+        Some(x) => x,
+        None => return false,
+    };
+
+    let mut matcher = PrefixMatcher::new("assertion failed");
+    let _ = fmt::write(&mut matcher, *args);
+
+    matcher.is_match()
+}
+
 fn _panic_print(args: fmt::Arguments) {
     use fmt::Write;
 
+    // The console's mapping might be broken while the translation tables are being changed.
+    if memory::mmu::kernel_tables_in_flux() {
+        let _ = unsafe { bsp::console::panic_emergency_console_out().write_fmt(args) };
+        return;
+    }
+
     unsafe { bsp::console::panic_console_out().write_fmt(args).unwrap() };
 }
 
@@ -22,16 +164,8 @@ fn _panic_print(args: fmt::Arguments) {
 /// It is linked weakly, so that the integration tests can overload its standard behavior.
 #[linkage = "weak"]
 #[no_mangle]
-fn _panic_exit() -> ! {
-    #[cfg(not(test_build))]
-    {
-        cpu::wait_forever()
-    }
-
-    #[cfg(test_build)]
-    {
-        cpu::qemu_exit_failure()
-    }
+fn _panic_exit(reason: ShutdownReason) -> ! {
+    shutdown::kernel_shutdown(reason)
 }
 
 /// Prints with a newline - only use from the panic handler.
@@ -44,15 +178,122 @@ macro_rules! panic_println {
     })
 }
 
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Let the next panic end the kernel successfully if its message contains `substring`, and with an
+/// assertion failure otherwise. `None` restores the default behavior.
+///
+/// Used by the test runner for `should_panic` tests.
+pub fn set_expected_panic(substring: Option<&'static str>) {
+    EXPECTED_PANIC.lock(|x| *x = substring);
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    unsafe { exception::asynchronous::local_irq_mask() };
+    // A panic while handling a panic on the same core. Anything beyond the bare minimum might fail
+    // again, so skip formatting and the regular console, and stop. The shutdown path is skipped as
+    // well, since its PSCI, watchdog and driver code could panic once more.
+    if enter_panic_handler() {
+        unsafe { exception::asynchronous::local_irq_mask() };
+        console::emergency_write("\n*** Nested kernel panic. Halting core. ***\n");
+
+        #[cfg(feature = "test_build")]
+        {
+            cpu::qemu_exit(ShutdownReason::NestedPanic.exit_code())
+        }
+
+        #[cfg(not(feature = "test_build"))]
+        {
+            cpu::wait_forever()
+        }
+    }
+
+    // Entering the panicking state masks IRQs on the executing core.
+    state::state_manager().transition_to_panicking();
+
+    let uptime = time::time_manager().uptime();
+    let (_, privilege_level) = exception::current_privilege_level();
+
+    // Messages that were printed before the console came up would be lost otherwise.
+    console::buffer::replay_with(_panic_print);
+
+    // Initializing the panic console flushes the characters that are still queued in the UART.
+    panic_println!(
+        "\n[  {:>3}.{:06}] Kernel panic on core {} ({}):",
+        uptime.as_secs(),
+        uptime.subsec_micros(),
+        cpu::core_id(),
+        privilege_level
+    );
 
     if let Some(args) = info.message() {
-        panic_println!("\nKernel panic: {}", args);
-    } else {
-        panic_println!("\nKernel panic!");
+        panic_println!("{}", args);
     }
 
-    _panic_exit()
+    if let Some(location) = info.location() {
+        panic_println!("    at {}:{}", location.file(), location.line());
+    }
+
+    // The error that led to the panic, if it was raised with `error::fatal()`.
+    if let Some(x) = error::take_fatal() {
+        panic_println!("    caused by: {}", x);
+    }
+
+    if let Some(expected) = EXPECTED_PANIC.lock(|x| x.take()) {
+        if message_contains(info, expected) {
+            panic_println!("[ok] Panicked as expected");
+            _panic_exit(ShutdownReason::Success)
+        }
+
+        panic_println!(
+            "[failed] Expected a panic message containing \"{}\"",
+            expected
+        );
+        _panic_exit(ShutdownReason::AssertionFailure)
+    }
+
+    // Keep the panic's details for the next boot, in case the console output is lost.
+    panic_record::write(info, uptime);
+
+    if is_assertion_failure(info) {
+        _panic_exit(ShutdownReason::AssertionFailure)
+    }
+
+    _panic_exit(ShutdownReason::Panic)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    fn contains(haystack: &str, needle: &'static str) -> bool {
+        let mut matcher = SubstringMatcher::new(needle);
+        let _ = fmt::write(&mut matcher, format_args!("{}", haystack));
+
+        matcher.is_match()
+    }
+
+    /// Check substring matching, including restarts after partial matches.
+    #[kernel_test]
+    fn substring_matcher_works() {
+        assert!(contains("index out of bounds", "out of"));
+        assert!(contains("aaab", "aab"));
+        assert!(contains("abababc", "ababc"));
+        assert!(contains("anything", ""));
+        assert!(!contains("aaba", "aab a"));
+        assert!(!contains("out o", "out of"));
+    }
+
+    /// Check that an expected panic ends the test binary successfully.
+    #[kernel_test(should_panic = "expected panic")]
+    fn expected_panic_is_accepted() {
+        panic!("This is an expected panic");
+    }
 }
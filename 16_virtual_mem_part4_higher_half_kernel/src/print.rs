@@ -3,21 +3,287 @@
 // Copyright (c) 2018-2021 Andre Richter <andre.o.richter@gmail.com>
 
 //! Printing.
+//!
+//! Each print goes out in a single write to the console, which the other cores can not interleave
+//! with. Multi-line output, like tables, is rendered into a [`Block`] first, so that it comes out
+//! in one piece as well.
+//!
+//! The blocks' buffers are too large for the stacks, so they are taken from a small pool per core.
+//! A block that finds no free buffer, for example in a deeply nested exception, prints each line
+//! right away instead.
 
-use crate::{bsp, console};
-use core::fmt;
+use crate::{bsp, config, console, cpu, log, time, time::interface::TimeManager};
+use core::{
+    cell::UnsafeCell,
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The size of a `Block`'s buffer.
+const BLOCK_SIZE: usize = 4096;
+
+/// The number of buffers per core, which is the number of blocks that can be in use on a core at
+/// the same time. Two cover a block in an IRQ handler that interrupted another one.
+const BUFFERS_PER_CORE: usize = 2;
+
+/// A `Block`'s buffer.
+type Buffer = [u8; BLOCK_SIZE];
+
+/// The pool of `Block` buffers. A core only takes buffers from its own row.
+struct BufferPool {
+    buffers: [[UnsafeCell<Buffer>; BUFFERS_PER_CORE]; config::cpu::NUM_CORES],
+    in_use: [[AtomicBool; BUFFERS_PER_CORE]; config::cpu::NUM_CORES],
+}
+
+/// The prefix of log messages: the uptime, the executing core's id and the level tag.
+struct Prefix(log::Level);
+
+/// Appends to a `Block`'s buffer. Fails without appending anything if a string does not fit.
+struct BlockWriter<'a> {
+    buffer: &'a mut [u8],
+    len: &'a mut usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Lines that are rendered into a buffer, and printed together.
+///
+/// The buffer is written out early only if it runs full. A line that is longer than the whole
+/// buffer is cut short.
+pub struct Block {
+    level: Option<log::Level>,
+
+    /// The core and index of the buffer from the pool, and the buffer. None if none was free.
+    buffer: Option<(usize, usize, &'static mut Buffer)>,
+
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static PREFIX_ENABLED: AtomicBool = AtomicBool::new(bsp::console::LOG_PREFIX_ENABLED);
+
+static BUFFER_POOL: BufferPool = BufferPool::new();
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// A core takes and returns its own buffers only, so that a buffer is never used twice.
+unsafe impl Sync for BufferPool {}
+
+impl BufferPool {
+    const fn new() -> Self {
+        const BUFFER: UnsafeCell<Buffer> = UnsafeCell::new([0; BLOCK_SIZE]);
+        const CORE_BUFFERS: [UnsafeCell<Buffer>; BUFFERS_PER_CORE] = [BUFFER; BUFFERS_PER_CORE];
+        const FREE: AtomicBool = AtomicBool::new(false);
+        const CORE_IN_USE: [AtomicBool; BUFFERS_PER_CORE] = [FREE; BUFFERS_PER_CORE];
+
+        Self {
+            buffers: [CORE_BUFFERS; config::cpu::NUM_CORES],
+            in_use: [CORE_IN_USE; config::cpu::NUM_CORES],
+        }
+    }
+
+    /// Take a free buffer of the executing core. Returns the core, the index and the buffer.
+    fn take(&self) -> Option<(usize, usize, &'static mut Buffer)> {
+        let core_id = cpu::core_id();
+
+        let index = self.in_use[core_id].iter().position(|x| {
+            x.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        })?;
+
+        // The flag was just set, so no other block uses the buffer until it is returned.
+        let buffer = unsafe { &mut *self.buffers[core_id][index].get() };
+
+        Some((core_id, index, buffer))
+    }
+
+    fn put(&self, core_id: usize, index: usize) {
+        self.in_use[core_id][index].store(false, Ordering::Release);
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let timestamp = time::time_manager().uptime();
+
+        write!(
+            f,
+            "[{} {:>3}.{:06} C{}] ",
+            self.0.tag(),
+            timestamp.as_secs(),
+            timestamp.subsec_micros(),
+            cpu::core_id()
+        )
+    }
+}
+
+impl fmt::Write for BlockWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = *self.len + s.len();
+        if end > self.buffer.len() {
+            return Err(fmt::Error);
+        }
+
+        self.buffer[*self.len..end].copy_from_slice(s.as_bytes());
+        *self.len = end;
+
+        Ok(())
+    }
+}
+
+impl Block {
+    /// Append a line. Fails if it does not fit, leaving the part that did.
+    fn try_line(&mut self, args: fmt::Arguments) -> fmt::Result {
+        let buffer = match &mut self.buffer {
+            Some((_, _, x)) => x,
+            None => return Err(fmt::Error),
+        };
+
+        // One byte is kept free for the newline.
+        if self.len >= BLOCK_SIZE {
+            return Err(fmt::Error);
+        }
+
+        let mut writer = BlockWriter {
+            buffer: &mut buffer[..BLOCK_SIZE - 1],
+            len: &mut self.len,
+        };
+
+        let mut result = Ok(());
+        if let Some(level) = self.level {
+            result = write!(writer, "{}", Prefix(level));
+        }
+        if result.is_ok() {
+            result = writer.write_fmt(args);
+        }
+
+        buffer[self.len] = b'\n';
+        self.len += 1;
+
+        result
+    }
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        if let Some((core_id, index, _)) = self.buffer {
+            BUFFER_POOL.put(core_id, index);
+        }
+    }
+}
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+/// Turn the prefix of log messages on or off.
+///
+/// The default is chosen by the BSP, which turns the prefix off in test builds, because its
+/// timestamps change from run to run. Tests that check the prefix itself turn it back on.
+pub fn set_prefix_enabled(enabled: bool) {
+    PREFIX_ENABLED.store(enabled, Ordering::Relaxed)
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use console::interface::Write;
 
+    // Before the console is up, printing goes to the early boot buffer.
+    if console::buffer::capture(args) {
+        return;
+    }
+
     bsp::console::console().write_fmt(args).unwrap();
 }
 
+/// Prints a log message with a newline, prefixed by the uptime, the executing core's id and the
+/// level tag.
+///
+/// Prefix and message are printed in a single `write_fmt()`, so that messages from different
+/// contexts do not interleave mid-prefix.
+#[doc(hidden)]
+pub fn _print_with_prefix(level: log::Level, args: fmt::Arguments) {
+    if !PREFIX_ENABLED.load(Ordering::Relaxed) {
+        _print(format_args_nl!("{}", args));
+        return;
+    }
+
+    _print(format_args_nl!("{}{}", Prefix(level), args));
+}
+
+impl Block {
+    /// Create a block of lines without a prefix, like the ones of `println!`.
+    pub fn new() -> Self {
+        Self {
+            level: None,
+            buffer: BUFFER_POOL.take(),
+            len: 0,
+        }
+    }
+
+    /// Create a block of log messages of `level`.
+    ///
+    /// Whether the level is enabled is up to the caller to check, see `log::is_enabled()`.
+    pub fn with_level(level: log::Level) -> Self {
+        let level = if PREFIX_ENABLED.load(Ordering::Relaxed) {
+            Some(level)
+        } else {
+            None
+        };
+
+        let mut block = Self::new();
+        block.level = level;
+
+        block
+    }
+
+    /// Append a line.
+    pub fn line(&mut self, args: fmt::Arguments) {
+        if self.buffer.is_none() {
+            match self.level {
+                Some(level) => _print(format_args_nl!("{}{}", Prefix(level), args)),
+                None => _print(format_args_nl!("{}", args)),
+            }
+            return;
+        }
+
+        let start = self.len;
+        if self.try_line(args).is_ok() {
+            return;
+        }
+
+        // Write out the lines before, and try again with the whole buffer.
+        self.len = start;
+        self.print();
+        let _ = self.try_line(args);
+    }
+
+    /// Print the lines, and empty the block.
+    pub fn print(&mut self) {
+        let buffer = match &self.buffer {
+            Some((_, _, x)) if self.len != 0 => x,
+            _ => return,
+        };
+
+        // The buffer holds whole strings only, so it is valid UTF-8.
+        if let Ok(s) = core::str::from_utf8(&buffer[..self.len]) {
+            _print(format_args!("{}", s));
+        }
+        self.len = 0;
+    }
+}
+
 /// Prints without a newline.
 ///
 /// Carbon copy from <https://doc.rust-lang.org/src/std/macros.rs.html>
@@ -37,70 +303,51 @@ macro_rules! println {
     })
 }
 
-/// Prints an info, with a newline.
-#[macro_export]
-macro_rules! info {
-    ($string:expr) => ({
-        #[allow(unused_imports)]
-        use crate::time::interface::TimeManager;
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
 
-        let timestamp = $crate::time::time_manager().uptime();
-        let timestamp_subsec_us = timestamp.subsec_micros();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
 
-        $crate::print::_print(format_args_nl!(
-            concat!("[  {:>3}.{:03}{:03}] ", $string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000
-        ));
-    });
-    ($format_string:expr, $($arg:tt)*) => ({
-        #[allow(unused_imports)]
-        use crate::time::interface::TimeManager;
-
-        let timestamp = $crate::time::time_manager().uptime();
-        let timestamp_subsec_us = timestamp.subsec_micros();
-
-        $crate::print::_print(format_args_nl!(
-            concat!("[  {:>3}.{:03}{:03}] ", $format_string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000,
-            $($arg)*
-        ));
-    })
-}
+    fn buffer(block: &Block) -> &Buffer {
+        block.buffer.as_ref().map(|(_, _, x)| &**x).unwrap()
+    }
 
-/// Prints a warning, with a newline.
-#[macro_export]
-macro_rules! warn {
-    ($string:expr) => ({
-        #[allow(unused_imports)]
-        use crate::time::interface::TimeManager;
+    fn contents(block: &Block) -> &str {
+        core::str::from_utf8(&buffer(block)[..block.len]).unwrap()
+    }
 
-        let timestamp = $crate::time::time_manager().uptime();
-        let timestamp_subsec_us = timestamp.subsec_micros();
+    /// Lines are collected, and a line that is longer than the buffer is cut short.
+    #[kernel_test]
+    fn block_collects_and_cuts_lines() {
+        let mut block = Block::new();
 
-        $crate::print::_print(format_args_nl!(
-            concat!("[W {:>3}.{:03}{:03}] ", $string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000
-        ));
-    });
-    ($format_string:expr, $($arg:tt)*) => ({
-        #[allow(unused_imports)]
-        use crate::time::interface::TimeManager;
-
-        let timestamp = $crate::time::time_manager().uptime();
-        let timestamp_subsec_us = timestamp.subsec_micros();
-
-        $crate::print::_print(format_args_nl!(
-            concat!("[W {:>3}.{:03}{:03}] ", $format_string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000,
-            $($arg)*
-        ));
-    })
+        block.line(format_args!("a{}", 1));
+        block.line(format_args!(""));
+        assert_eq!(contents(&block), "a1\n\n");
+
+        block.len = 0;
+        block.line(format_args!("{:1$}", "", 2 * BLOCK_SIZE));
+        assert_eq!(block.len, BLOCK_SIZE);
+        assert_eq!(buffer(&block)[BLOCK_SIZE - 1], b'\n');
+        assert!(block.try_line(format_args!("")).is_err());
+    }
+
+    /// Buffers go back to the pool on drop, and blocks go on without one once the pool is empty.
+    #[kernel_test]
+    fn buffers_are_pooled() {
+        let blocks: [Block; BUFFERS_PER_CORE] = [Block::new(), Block::new()];
+        assert!(blocks.iter().all(|x| x.buffer.is_some()));
+
+        let mut unbuffered = Block::new();
+        assert!(unbuffered.buffer.is_none());
+        unbuffered.line(format_args!("Printed right away"));
+        assert_eq!(unbuffered.len, 0);
+
+        drop(blocks);
+        assert!(Block::new().buffer.is_some());
+    }
 }
@@ -4,7 +4,7 @@
 
 //! Rust runtime initialization code.
 
-use crate::{bsp, memory};
+use crate::{bsp, cpu, dtb, memory};
 
 //--------------------------------------------------------------------------------------------------
 // Private Code
@@ -24,18 +24,44 @@ unsafe fn zero_bss() {
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
-/// Equivalent to `crt0` or `c0` code in C/C++ world. Clears the `bss` section, then jumps to kernel
-/// init code.
+/// Equivalent to `crt0` or `c0` code in C/C++ world. Clears the `bss` section and fills the unused
+/// part of the stack for the usage instrumentation, then jumps to kernel init code.
+///
+/// `phys_dtb_addr` is the address of the device tree that the firmware passed, or zero.
 ///
 /// # Safety
 ///
 /// - Only a single core must be active and running this function.
 #[no_mangle]
-pub unsafe fn runtime_init() -> ! {
+pub unsafe extern "C" fn runtime_init(phys_dtb_addr: usize) -> ! {
     extern "Rust" {
         fn kernel_init() -> !;
     }
 
     zero_bss();
+    memory::stack::fill(memory::stack::Stack::Kernel(cpu::core_id()));
+    dtb::set_phys_addr(phys_dtb_addr);
+
     kernel_init()
 }
+
+/// Rust entry of the secondary cores, which are released by `cpu::smp::boot_secondary_cores()`.
+///
+/// The `bss` section has already been cleared by the boot core, so this function only fills the
+/// core's stack for the usage instrumentation and then jumps to the kernel's secondary core entry.
+///
+/// # Safety
+///
+/// - Must only be called once per secondary core, after the boot core has finished
+///   `runtime_init()`.
+#[no_mangle]
+pub unsafe fn secondary_runtime_init() -> ! {
+    extern "Rust" {
+        fn kernel_secondary_main(core_id: usize) -> !;
+    }
+
+    let core_id = cpu::core_id();
+    memory::stack::fill(memory::stack::Stack::Kernel(core_id));
+
+    kernel_secondary_main(core_id)
+}
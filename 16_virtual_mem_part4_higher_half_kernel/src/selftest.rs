@@ -0,0 +1,144 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Boot-time self-tests.
+//!
+//! A short sequence of checks that the hardware and the kernel's view of it are sane. The kernel
+//! comes with a few checks of its own, and drivers add theirs with `register()` during kernel init.
+//!
+//! With the `selftest` feature, the tests run once the drivers are up, before the boot banner. A
+//! failing test is reported and the boot continues, unless the test is essential. The `selftest`
+//! monitor command runs them again on demand.
+
+use crate::{
+    bsp,
+    error::KernelError,
+    info,
+    memory::{mmu, Address, Virtual},
+    monitor,
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Maximum number of tests that can be registered in addition to the built-in ones.
+const MAX_REGISTERED_TESTS: usize = 16;
+
+const BUILTIN_TESTS: [SelfTest; 1] = [SelfTest {
+    name: "Translation of known addresses",
+    essential: true,
+    func: test_translation,
+}];
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A test function. Returns the reason on failure.
+pub type SelfTestFn = fn() -> Result<(), KernelError>;
+
+/// A self-test.
+#[derive(Copy, Clone)]
+pub struct SelfTest {
+    /// The name that is printed with the result.
+    pub name: &'static str,
+
+    /// Whether a failure must stop the boot.
+    pub essential: bool,
+
+    /// The test function.
+    pub func: SelfTestFn,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static REGISTERED_TESTS: InitStateLock<[Option<SelfTest>; MAX_REGISTERED_TESTS]> =
+    InitStateLock::new([None; MAX_REGISTERED_TESTS]);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The kernel's code must translate, and the boot core's stack guard page must not.
+fn test_translation() -> Result<(), KernelError> {
+    let code = Address::<Virtual>::new(test_translation as *const () as usize);
+    mmu::try_virt_to_phys(code)?;
+
+    let guard_page = bsp::memory::mmu::virt_boot_core_stack_guard_page_desc();
+    if mmu::try_virt_to_phys(guard_page.start_addr()).is_ok() {
+        return Err(KernelError::Other("Stack guard page is mapped"));
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Register an additional test.
+///
+/// Must be called during kernel init. Tests run in the order they were registered, after the
+/// built-in ones.
+pub fn register(test: SelfTest) -> Result<(), &'static str> {
+    REGISTERED_TESTS.write(|tests| match tests.iter_mut().find(|x| x.is_none()) {
+        None => Err("Self-test table full"),
+        Some(x) => {
+            *x = Some(test);
+            Ok(())
+        }
+    })
+}
+
+/// Run all tests and print a line per test, followed by the verdict.
+///
+/// Returns the error of the first essential test that failed.
+pub fn run_all() -> Result<(), KernelError> {
+    let mut num_run = 0;
+    let mut num_failed = 0;
+    let mut essential_error = None;
+
+    info!("Self-test:");
+
+    let mut run = |test: &SelfTest| {
+        monitor::safe_point();
+        num_run += 1;
+
+        match (test.func)() {
+            Ok(()) => info!("      [PASS] {}", test.name),
+            Err(x) => {
+                num_failed += 1;
+                info!("      [FAIL] {}: {}", test.name, x);
+
+                if test.essential && essential_error.is_none() {
+                    essential_error = Some(x);
+                }
+            }
+        }
+    };
+
+    BUILTIN_TESTS.iter().for_each(&mut run);
+    REGISTERED_TESTS.read(|tests| tests.iter().flatten().for_each(&mut run));
+
+    let verdict = match (num_failed, essential_error) {
+        (0, _) => "PASS",
+        (_, None) => "PASS with failures",
+        (_, Some(_)) => "FAIL",
+    };
+    info!(
+        "      {}: {} of {} tests passed",
+        verdict,
+        num_run - num_failed,
+        num_run
+    );
+
+    match essential_error {
+        None => Ok(()),
+        Some(x) => Err(x),
+    }
+}
@@ -0,0 +1,130 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Kernel shutdown.
+
+use crate::{bsp, console, driver, exception, info, state, time, warn};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The reason for shutting down the kernel.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ShutdownReason {
+    /// A clean shutdown, for example, after all tests passed.
+    Success,
+
+    /// A kernel panic.
+    Panic,
+
+    /// A failed assertion, or another failed check of a test.
+    AssertionFailure,
+
+    /// A panic while handling a panic.
+    NestedPanic,
+
+    /// A requested reset, for example, from the monitor.
+    Reboot,
+
+    /// A requested power off, for example, from the monitor.
+    Halt,
+
+    /// A test did not finish in time.
+    TestTimeout,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl ShutdownReason {
+    /// The exit code reported to the host when running in QEMU.
+    ///
+    /// Keep in sync with the codes in `tests/runner.rb`.
+    pub const fn exit_code(self) -> u32 {
+        match self {
+            ShutdownReason::Success => 0,
+            ShutdownReason::Panic => 1,
+            ShutdownReason::AssertionFailure => 2,
+            ShutdownReason::NestedPanic => 3,
+            ShutdownReason::Reboot => 0,
+            ShutdownReason::Halt => 0,
+            ShutdownReason::TestTimeout => 4,
+        }
+    }
+
+    /// Return true if the system is reset, and false if it is powered off.
+    pub const fn resets(self) -> bool {
+        !matches!(self, ShutdownReason::Success | ShutdownReason::Halt)
+    }
+}
+
+/// Shut down the kernel.
+///
+/// Unless the kernel panicked, the shutdown is ordered: The state manager enters `ShuttingDown`,
+/// which closes driver registration. Then, the drivers' `shutdown()` hooks run in reverse init
+/// order, and the final statistics are printed.
+///
+/// After a panic, and when called in IRQ context, the drivers and the regular console are left
+/// alone, since they might be in the state that caused the panic, or in use by the interrupted
+/// code. Only the emergency console is used then.
+///
+/// In test builds, this exits QEMU with the reason's exit code. On real hardware, a successful
+/// shutdown or a halt powers off the system, and all other reasons reset it. PSCI is used if
+/// available. Otherwise, the BSP takes over.
+pub fn kernel_shutdown(reason: ShutdownReason) -> ! {
+    let state_manager = state::state_manager();
+    let emergency = matches!(reason, ShutdownReason::Panic | ShutdownReason::NestedPanic)
+        || state_manager.is_panicking()
+        || exception::asynchronous::in_irq_context();
+
+    // Do not disturb a panic, which is the final state already.
+    let current = state_manager.current();
+    let entered = current.can_transition_to(state::State::ShuttingDown);
+    if entered {
+        state_manager.transition(current, state::State::ShuttingDown);
+    }
+    let orderly = entered && !emergency;
+
+    if orderly {
+        if let Err(x) = driver::driver_manager().shutdown_drivers(reason) {
+            warn!("Not all drivers shut down cleanly. First failure: {}", x);
+        }
+
+        info!(
+            "Shutting down ({:?}) after {:?} of uptime",
+            reason,
+            time::time_manager().uptime()
+        );
+    }
+
+    if orderly {
+        crate::memory::stack::print_usage();
+
+        // The UART's hook flushed it already, but the statistics were printed afterwards.
+        use console::interface::Write;
+        bsp::console::console().flush();
+    } else if emergency && (reason != ShutdownReason::Panic) {
+        // The panic handler printed the details of a panic already.
+        console::emergency_write("Shutting down without driver shutdown hooks\n");
+    }
+
+    #[cfg(feature = "test_build")]
+    {
+        crate::cpu::qemu_exit(reason.exit_code())
+    }
+
+    #[cfg(not(feature = "test_build"))]
+    {
+        // The PSCI calls only return if PSCI is not available or failed.
+        if reason.resets() {
+            let _ = crate::cpu::psci::system_reset();
+            crate::bsp::cpu::system_reset()
+        } else {
+            let _ = crate::cpu::psci::system_off();
+            crate::bsp::cpu::system_off()
+        }
+    }
+}
@@ -4,15 +4,31 @@
 
 //! State information about the kernel itself.
 
-use core::sync::atomic::{AtomicU8, Ordering};
+use crate::{
+    exception,
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// Maximum number of entry callbacks per state.
+const MAX_ENTRY_CALLBACKS: usize = 4;
+
+type EntryCallbackTable = [[Option<StateEntryCallback>; MAX_ENTRY_CALLBACKS]; State::NUM_STATES];
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
 /// Different stages in the kernel execution.
-#[derive(Copy, Clone, Eq, PartialEq)]
-enum State {
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum State {
     /// The kernel starts booting in this state.
     Init,
 
@@ -23,14 +39,29 @@ enum State {
     /// The kernel transitions to this state when it boots the secondary cores, aka switches
     /// exectution mode to symmetric multiprocessing (SMP).
     MultiCoreMain,
+
+    /// The kernel transitions to this state when any core panics. It can be entered from all other
+    /// states and is never left.
+    Panicking,
+
+    /// The kernel transitions to this state when it powers off or resets the system. It can be
+    /// entered from all other states, except during a panic.
+    ShuttingDown,
 }
 
-//--------------------------------------------------------------------------------------------------
-// Public Definitions
-//--------------------------------------------------------------------------------------------------
+/// Type of the callbacks that run when the kernel enters a state.
+pub type StateEntryCallback = fn();
 
 /// Maintains the kernel state and state transitions.
-pub struct StateManager(AtomicU8);
+pub struct StateManager {
+    state: AtomicU8,
+
+    /// Bitmask of the cores that have checked in. Bit `n` corresponds to core id `n`.
+    cores_checked_in: AtomicU8,
+
+    /// Callbacks that run on entering a state, indexed by the state.
+    entry_callbacks: InitStateLock<EntryCallbackTable>,
+}
 
 //--------------------------------------------------------------------------------------------------
 // Global instances
@@ -38,6 +69,45 @@ pub struct StateManager(AtomicU8);
 
 static STATE_MANAGER: StateManager = StateManager::new();
 
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl State {
+    const NUM_STATES: usize = 5;
+
+    const fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    fn from_u8(x: u8) -> Self {
+        match x {
+            0 => State::Init,
+            1 => State::SingleCoreMain,
+            2 => State::MultiCoreMain,
+            3 => State::Panicking,
+            4 => State::ShuttingDown,
+            _ => panic!("Invalid KERNEL_STATE"),
+        }
+    }
+}
+
+impl StateManager {
+    /// Run everything that is tied to entering the given state.
+    fn on_entry(&self, state: State) {
+        // Stop the kernel from being interrupted while it prints the panic message.
+        if state == State::Panicking {
+            unsafe { exception::asynchronous::local_irq_mask() };
+        }
+
+        self.entry_callbacks.read(|callbacks| {
+            for callback in callbacks[state as usize].iter().flatten() {
+                callback()
+            }
+        });
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -47,46 +117,167 @@ pub fn state_manager() -> &'static StateManager {
     &STATE_MANAGER
 }
 
-impl StateManager {
-    const INIT: u8 = 0;
-    const SINGLE_CORE_MAIN: u8 = 1;
-    const MULTI_CORE_MAIN: u8 = 2;
+impl State {
+    /// Return true if the kernel may go from `self` to `to`.
+    pub fn can_transition_to(&self, to: State) -> bool {
+        matches!(
+            (self, to),
+            (State::Init, State::SingleCoreMain)
+                | (State::SingleCoreMain, State::MultiCoreMain)
+                | (State::Init, State::Panicking)
+                | (State::SingleCoreMain, State::Panicking)
+                | (State::MultiCoreMain, State::Panicking)
+                | (State::ShuttingDown, State::Panicking)
+                | (State::Init, State::ShuttingDown)
+                | (State::SingleCoreMain, State::ShuttingDown)
+                | (State::MultiCoreMain, State::ShuttingDown)
+        )
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            State::Init => "Init",
+            State::SingleCoreMain => "SingleCoreMain",
+            State::MultiCoreMain => "MultiCoreMain",
+            State::Panicking => "Panicking",
+            State::ShuttingDown => "ShuttingDown",
+        };
+
+        f.pad(s)
+    }
+}
 
+impl StateManager {
     /// Create a new instance.
     pub const fn new() -> Self {
-        Self(AtomicU8::new(Self::INIT))
+        Self {
+            state: AtomicU8::new(State::Init.to_u8()),
+            cores_checked_in: AtomicU8::new(0),
+            entry_callbacks: InitStateLock::new([[None; MAX_ENTRY_CALLBACKS]; State::NUM_STATES]),
+        }
     }
 
     /// Return the current state.
-    fn state(&self) -> State {
-        let state = self.0.load(Ordering::Acquire);
-
-        match state {
-            Self::INIT => State::Init,
-            Self::SINGLE_CORE_MAIN => State::SingleCoreMain,
-            Self::MULTI_CORE_MAIN => State::MultiCoreMain,
-            _ => panic!("Invalid KERNEL_STATE"),
-        }
+    pub fn current(&self) -> State {
+        State::from_u8(self.state.load(Ordering::Acquire))
     }
 
     /// Return if the kernel is init state.
     pub fn is_init(&self) -> bool {
-        self.state() == State::Init
-    }
-
-    /// Transition from Init to SingleCoreMain.
-    pub fn transition_to_single_core_main(&self) {
-        if self
-            .0
-            .compare_exchange(
-                Self::INIT,
-                Self::SINGLE_CORE_MAIN,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            )
-            .is_err()
-        {
-            panic!("transition_to_single_core_main() called while state != Init");
+        self.current() == State::Init
+    }
+
+    /// Return if the kernel is in panicking state.
+    pub fn is_panicking(&self) -> bool {
+        self.current() == State::Panicking
+    }
+
+    /// Transition from `from` to `to` and run the entry callbacks of `to`.
+    ///
+    /// Panics if the transition is illegal, or if the kernel is not in state `from`.
+    pub fn transition(&self, from: State, to: State) {
+        if !from.can_transition_to(to) {
+            panic!("Illegal kernel state transition: {} -> {}", from, to);
         }
+
+        if let Err(x) = self.state.compare_exchange(
+            from.to_u8(),
+            to.to_u8(),
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        ) {
+            panic!(
+                "Kernel state transition {} -> {} requested while in {}",
+                from,
+                to,
+                State::from_u8(x)
+            );
+        }
+
+        self.on_entry(to);
+    }
+
+    /// Transition to `Panicking` from whichever state the kernel is in.
+    ///
+    /// This is the panic handler's entry point. Since a panic can hit in any state, and must not
+    /// cause another panic, there is no `from` state to validate against. The entry callbacks only
+    /// run for the first panic.
+    ///
+    /// Returns the state the kernel was in before.
+    pub fn transition_to_panicking(&self) -> State {
+        let previous = State::from_u8(self.state.swap(State::Panicking.to_u8(), Ordering::AcqRel));
+
+        if previous != State::Panicking {
+            self.on_entry(State::Panicking);
+        }
+
+        previous
+    }
+
+    /// Register a callback that runs whenever the kernel enters `state`.
+    ///
+    /// Callbacks run on the core that does the transition, in the order of their registration.
+    /// Callbacks for `Panicking` must not panic themselves, and should not take any locks.
+    ///
+    /// Registration is only possible during kernel init.
+    pub fn register_entry_callback(
+        &self,
+        state: State,
+        callback: StateEntryCallback,
+    ) -> Result<(), &'static str> {
+        self.entry_callbacks.write(|callbacks| {
+            let slot = callbacks[state as usize]
+                .iter_mut()
+                .find(|x| x.is_none())
+                .ok_or("No free entry callback slot for this state")?;
+
+            *slot = Some(callback);
+
+            Ok(())
+        })
+    }
+
+    /// Record that the core with the given id is up and running.
+    pub fn core_checked_in(&self, core_id: usize) {
+        assert!(core_id < 8, "Core id out of range");
+
+        self.cores_checked_in
+            .fetch_or(1 << core_id, Ordering::AcqRel);
+    }
+
+    /// Return the number of cores that have checked in.
+    pub fn num_cores_checked_in(&self) -> usize {
+        self.cores_checked_in.load(Ordering::Acquire).count_ones() as usize
+    }
+
+    /// Return the cores that have checked in, with one bit per core id.
+    pub fn cores_checked_in_mask(&self) -> usize {
+        self.cores_checked_in.load(Ordering::Acquire) as usize
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check the transition rules of the kernel lifecycle.
+    #[kernel_test]
+    fn state_transitions_are_validated() {
+        assert!(State::Init.can_transition_to(State::SingleCoreMain));
+        assert!(State::SingleCoreMain.can_transition_to(State::MultiCoreMain));
+        assert!(State::MultiCoreMain.can_transition_to(State::ShuttingDown));
+        assert!(State::ShuttingDown.can_transition_to(State::Panicking));
+
+        assert!(!State::Init.can_transition_to(State::MultiCoreMain));
+        assert!(!State::MultiCoreMain.can_transition_to(State::SingleCoreMain));
+        assert!(!State::Panicking.can_transition_to(State::ShuttingDown));
+        assert!(!State::Panicking.can_transition_to(State::Init));
     }
 }
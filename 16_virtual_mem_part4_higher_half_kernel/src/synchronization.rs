@@ -127,6 +127,10 @@ impl<T> interface::ReadWriteEx for InitStateLock<T> {
             !exception::asynchronous::is_local_irq_masked(),
             "InitStateLock::write called with IRQs unmasked"
         );
+        assert!(
+            !exception::asynchronous::in_irq_context(),
+            "InitStateLock::write called from IRQ context"
+        );
 
         let data = unsafe { &mut *self.data.get() };
 
@@ -0,0 +1,9 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Infrastructure for unit and integration tests.
+
+pub mod replay;
+
+pub use crate::exception::fault_injection as fault;
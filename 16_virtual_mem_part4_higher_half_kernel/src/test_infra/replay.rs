@@ -0,0 +1,218 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Scripted console input.
+//!
+//! The replay console is a console source whose input is a script, so that tests can exercise the
+//! input path, for example the monitor, without a harness that types. It has no output of its own.
+//! It is registered as a source only, so output still goes to the sinks of the console
+//! multiplexer, and the test harness captures it from the UART as usual.
+//!
+//! ```
+//! static SCRIPT: [Step; 3] = [
+//!     Step::Type("help\r"),
+//!     Step::Pause(Duration::from_millis(100)),
+//!     Step::Type("uptime\r"),
+//! ];
+//!
+//! unsafe { test_infra::replay::start(&SCRIPT).unwrap() };
+//! monitor::run();
+//! ```
+
+use crate::{
+    console, cpu,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    time,
+    time::interface::TimeManager,
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The name that the replay console is registered under.
+const NAME: &str = "replay";
+
+struct ReplayConsoleInner {
+    script: &'static [Step],
+    step: usize,
+
+    /// Position in the text of a `Step::Type`.
+    offset: usize,
+
+    /// End of the current `Step::Pause`, once it started.
+    pause_end: Option<Duration>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A step of a script.
+#[derive(Copy, Clone, Debug)]
+pub enum Step {
+    /// Characters that are available right away, as if they were typed very fast.
+    Type(&'static str),
+
+    /// No input for the given time. The time starts when the input before it was read.
+    Pause(Duration),
+}
+
+/// A console source that reads from a script.
+pub struct ReplayConsole {
+    inner: IRQSafeNullLock<ReplayConsoleInner>,
+    echo: AtomicBool,
+    chars_read: AtomicUsize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static REPLAY_CONSOLE: ReplayConsole = ReplayConsole::new();
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl ReplayConsoleInner {
+    const fn new(script: &'static [Step]) -> Self {
+        Self {
+            script,
+            step: 0,
+            offset: 0,
+            pause_end: None,
+        }
+    }
+
+    /// Return the next character, if the script has one at time `now`.
+    fn next_char(&mut self, now: Duration) -> Option<char> {
+        loop {
+            match self.script.get(self.step)? {
+                Step::Type(text) => {
+                    if let Some(&c) = text.as_bytes().get(self.offset) {
+                        self.offset += 1;
+                        return Some(c as char);
+                    }
+
+                    self.step += 1;
+                    self.offset = 0;
+                }
+                Step::Pause(duration) => {
+                    let end = *self.pause_end.get_or_insert(now + *duration);
+                    if now < end {
+                        return None;
+                    }
+
+                    self.step += 1;
+                    self.pause_end = None;
+                }
+            }
+        }
+    }
+}
+
+impl ReplayConsole {
+    const fn new() -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(ReplayConsoleInner::new(&[])),
+            echo: AtomicBool::new(true),
+            chars_read: AtomicUsize::new(0),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Register the replay console as a console source with `script` as its input, and make it the
+/// primary console.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init.
+pub unsafe fn start(script: &'static [Step]) -> Result<(), &'static str> {
+    REPLAY_CONSOLE
+        .inner
+        .lock(|inner| *inner = ReplayConsoleInner::new(script));
+
+    console::mux::console_mux().register_source(NAME, &REPLAY_CONSOLE)?;
+    console::switch_primary(NAME)
+}
+
+/// Return true if all of the script was read.
+pub fn is_finished() -> bool {
+    REPLAY_CONSOLE
+        .inner
+        .lock(|inner| inner.step >= inner.script.len())
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+/// Output is left to the sinks of the console multiplexer.
+impl console::interface::Write for ReplayConsole {
+    fn write_char(&self, _c: char) {}
+
+    fn write_fmt(&self, _args: fmt::Arguments) -> fmt::Result {
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+impl console::interface::Read for ReplayConsole {
+    fn try_read_char(&self) -> Option<char> {
+        let now = time::time_manager().uptime();
+        let c = self.inner.lock(|inner| inner.next_char(now))?;
+        self.chars_read.fetch_add(1, Ordering::Relaxed);
+
+        Some(c)
+    }
+
+    fn read_array(&self, buf: &mut [u8], timeout: Duration) -> usize {
+        let mut num_read = 0;
+
+        cpu::spin_until(
+            || {
+                while let Some(x) = buf.get_mut(num_read) {
+                    match self.try_read_char() {
+                        None => break,
+                        Some(c) => *x = c as u8,
+                    }
+                    num_read += 1;
+                }
+
+                num_read == buf.len()
+            },
+            Some(timeout),
+        );
+
+        num_read
+    }
+
+    /// The script is not input that arrived early, so it is kept.
+    fn clear_rx(&self) {}
+
+    fn set_echo(&self, enabled: bool) {
+        self.echo.store(enabled, Ordering::Relaxed);
+    }
+
+    fn is_echo_enabled(&self) -> bool {
+        self.echo.load(Ordering::Relaxed)
+    }
+}
+
+impl console::interface::Statistics for ReplayConsole {
+    fn chars_read(&self) -> usize {
+        self.chars_read.load(Ordering::Relaxed)
+    }
+}
@@ -3,22 +3,54 @@
 // Copyright (c) 2020-2021 Andre Richter <andre.o.richter@gmail.com>
 
 //! Timer primitives.
+//!
+//! The time manager derives time from a free-running counter, the time source. The architectural
+//! timer is the default. The BSP may select another source during kernel init.
 
 #[cfg(target_arch = "aarch64")]
 #[path = "_arch/aarch64/time.rs"]
 mod arch_time;
 
+use crate::{
+    cpu, exception,
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+    units::Hertz,
+    warn,
+};
+use core::{fmt, time::Duration};
+
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
-pub use arch_time::time_manager;
+pub use arch_time::{
+    active_counter, arch_time_source, event_stream_period, select_counter, Counter, TimeoutTimer,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The interval that is measured by `cross_check()`.
+const CROSS_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Divergence between two sources that `cross_check()` tolerates, in percent.
+const CROSS_CHECK_MAX_DIVERGENCE_PERCENT: u128 = 2;
+
+struct SourcedTimeManager {
+    source: InitStateLock<&'static (dyn interface::TimeSource + Sync)>,
+}
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// A condition did not become true in time.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TimeoutError;
+
 /// Timekeeping interfaces.
 pub mod interface {
+    use crate::units::Hertz;
     use core::time::Duration;
 
     /// Time management functions.
@@ -31,7 +63,265 @@ pub mod interface {
         /// This includes time consumed by firmware and bootloaders.
         fn uptime(&self) -> Duration;
 
-        /// Spin for a given duration.
-        fn spin_for(&self, duration: Duration);
+        /// Spin until the uptime has reached `deadline`.
+        ///
+        /// Waits in a low-power state, woken by the timer's event stream, while at least one period
+        /// of the stream is left, and spins on the counter for the rest. The time asleep is
+        /// accounted as idle time.
+        ///
+        /// Must not be called from IRQ context.
+        fn spin_until(&self, deadline: Duration);
+
+        /// Spin for a given duration. See `spin_until()`.
+        ///
+        /// Must not be called from IRQ context.
+        fn spin_for(&self, duration: Duration) {
+            self.spin_until(self.uptime() + duration)
+        }
+    }
+
+    /// A free-running counter that time is derived from.
+    pub trait TimeSource {
+        /// A short name of the source.
+        fn name(&self) -> &'static str;
+
+        /// The counter's frequency.
+        fn frequency(&self) -> Hertz;
+
+        /// The current value of the counter. Counts up from power-on and does not wrap in practice.
+        fn counter(&self) -> u64;
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static TIME_MANAGER: SourcedTimeManager = SourcedTimeManager {
+    source: InitStateLock::new(&arch_time::GenericTimer),
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Convert `ticks` of a counter running at `frequency` into a duration.
+fn ticks_to_duration(ticks: u64, frequency: Hertz) -> Duration {
+    frequency
+        .duration_of(ticks)
+        .expect("Time source with zero frequency")
+}
+
+/// Convert a duration into ticks of a counter running at `frequency`. Saturates, since a counter
+/// does not wrap in practice.
+fn duration_to_ticks(duration: Duration, frequency: Hertz) -> u64 {
+    frequency.cycles_in(duration).unwrap_or(u64::MAX)
+}
+
+impl SourcedTimeManager {
+    fn source(&self) -> &'static (dyn interface::TimeSource + Sync) {
+        self.source.read(|x| *x)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("Timeout")
+    }
+}
+
+impl From<TimeoutError> for &'static str {
+    fn from(_: TimeoutError) -> Self {
+        "Timeout"
+    }
+}
+
+/// Poll `f` until it returns true, or fail once `timeout` has elapsed.
+///
+/// Waits in a low-power state between polls, see `cpu::spin_until()`. Unlike `spin_for()`, it may
+/// be called from IRQ context.
+pub fn poll_until(timeout: Duration, f: impl FnMut() -> bool) -> Result<(), TimeoutError> {
+    if cpu::spin_until(f, Some(timeout)) {
+        Ok(())
+    } else {
+        Err(TimeoutError)
+    }
+}
+
+/// Poll a register until it matches the given field values, or fail once the timeout has elapsed.
+///
+/// `poll_register!(timeout, registers.FR, FR::TXFF::CLEAR)` expands to a `time::poll_until()` on
+/// the register's `matches_all()`.
+#[macro_export]
+macro_rules! poll_register {
+    ($timeout:expr, $register:expr, $fields:expr) => {
+        $crate::time::poll_until($timeout, || $register.matches_all($fields))
+    };
+}
+
+/// Return a reference to the time manager.
+pub fn time_manager() -> &'static impl interface::TimeManager {
+    &TIME_MANAGER
+}
+
+/// Back the time manager by `source` from now on.
+///
+/// # Safety
+///
+/// - Must only be called during kernel init.
+/// - The source must count from power-on as well, or the uptime jumps.
+pub unsafe fn set_time_source(source: &'static (dyn interface::TimeSource + Sync)) {
+    TIME_MANAGER.source.write(|x| *x = source);
+}
+
+/// The name of the source that backs the time manager.
+pub fn time_source_name() -> &'static str {
+    TIME_MANAGER.source().name()
+}
+
+/// The frequency of the source that backs the time manager.
+pub fn time_source_frequency() -> Hertz {
+    TIME_MANAGER.source().frequency()
+}
+
+/// Measure an interval with the time manager, and warn if `other` disagrees.
+///
+/// Useful to catch a firmware that reports a wrong frequency for one of the sources. Returns false
+/// if the sources disagree.
+pub fn cross_check(other: &(dyn interface::TimeSource + Sync)) -> bool {
+    use interface::TimeManager;
+
+    let source = TIME_MANAGER.source();
+
+    let start = other.counter();
+    TIME_MANAGER.spin_for(CROSS_CHECK_INTERVAL);
+    let end = other.counter();
+
+    let measured = ticks_to_duration(end.wrapping_sub(start), other.frequency());
+    let expected = CROSS_CHECK_INTERVAL.as_nanos();
+    let divergence = if measured.as_nanos() > expected {
+        measured.as_nanos() - expected
+    } else {
+        expected - measured.as_nanos()
+    };
+
+    if divergence * 100 > expected * CROSS_CHECK_MAX_DIVERGENCE_PERCENT {
+        warn!(
+            "Time sources disagree: {} ms on the {} are {} ms on the {}",
+            CROSS_CHECK_INTERVAL.as_millis(),
+            source.name(),
+            measured.as_millis(),
+            other.name()
+        );
+
+        return false;
+    }
+
+    true
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl interface::TimeManager for SourcedTimeManager {
+    fn resolution(&self) -> Duration {
+        ticks_to_duration(1, self.source().frequency())
+    }
+
+    fn uptime(&self) -> Duration {
+        let source = self.source();
+
+        ticks_to_duration(source.counter(), source.frequency())
+    }
+
+    fn spin_until(&self, deadline: Duration) {
+        assert!(
+            !exception::asynchronous::in_irq_context(),
+            "spin_until() called from IRQ context"
+        );
+
+        let source = self.source();
+        let target = duration_to_ticks(deadline, source.frequency());
+
+        // A wait for the next event can take up to a full period of the event stream. Sleeping
+        // closer to the deadline than that would overshoot it.
+        let sleep_margin = duration_to_ticks(event_stream_period(), source.frequency());
+
+        loop {
+            let now = source.counter();
+            if now >= target {
+                return;
+            }
+
+            if target - now > sleep_margin {
+                cpu::idle::wait();
+            }
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check the conversions at the frequencies of the Raspberry's time sources.
+    #[kernel_test]
+    fn tick_conversion_works() {
+        assert_eq!(
+            ticks_to_duration(19_200_000, Hertz(19_200_000)),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            duration_to_ticks(Duration::from_millis(5), Hertz(1_000_000)),
+            5_000
+        );
+        assert_eq!(
+            ticks_to_duration(1, Hertz(1_000_000)),
+            Duration::from_micros(1)
+        );
+        assert_eq!(
+            duration_to_ticks(Duration::from_secs(u64::MAX), Hertz(1_000_000)),
+            u64::MAX
+        );
+    }
+
+    /// Spin for `duration`, and return how long it took.
+    fn measure_spin_for(duration: Duration) -> Duration {
+        use interface::TimeManager;
+
+        let start = time_manager().uptime();
+        time_manager().spin_for(duration);
+
+        time_manager().uptime() - start
+    }
+
+    /// Check waits below the event stream's period, which spin on the counter.
+    #[kernel_test]
+    fn spin_for_short_is_accurate() {
+        let duration = event_stream_period() / 4;
+        let elapsed = measure_spin_for(duration);
+
+        assert!(elapsed >= duration);
+        assert!(elapsed < duration + event_stream_period());
+    }
+
+    /// Check waits of many event stream periods, which sleep until close to the deadline.
+    #[kernel_test]
+    fn spin_for_long_is_accurate() {
+        let duration = Duration::from_millis(10);
+        let elapsed = measure_spin_for(duration);
+
+        assert!(elapsed >= duration);
+        assert!(elapsed < duration + Duration::from_millis(2));
     }
 }
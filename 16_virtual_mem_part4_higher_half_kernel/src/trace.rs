@@ -0,0 +1,75 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Kernel event trace.
+//!
+//! Hot paths record events with `trace_event!(id, arg)`. Each core writes to a ring buffer of its
+//! own, so recording takes no lock and costs a counter read and a few stores. The `trace` monitor
+//! command merges the rings by timestamp.
+//!
+//! Without the `event_trace` feature, `trace_event!` expands to nothing.
+
+#[cfg(feature = "event_trace")]
+mod ring;
+
+#[cfg(feature = "event_trace")]
+pub use ring::{dump, record};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Event ids.
+///
+/// New ids also need an entry in `NAMES`.
+pub mod id {
+    /// An IRQ was taken. The argument is the nesting depth after entering.
+    pub const IRQ_ENTRY: u32 = 1;
+
+    /// An IRQ handler returned. The argument is the nesting depth after leaving.
+    pub const IRQ_EXIT: u32 = 2;
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Names of the event ids, for printing.
+#[cfg(feature = "event_trace")]
+const NAMES: [(u32, &str); 2] = [(id::IRQ_ENTRY, "irq-entry"), (id::IRQ_EXIT, "irq-exit")];
+
+/// The name of an event id.
+#[cfg(feature = "event_trace")]
+fn name(event_id: u32) -> &'static str {
+    NAMES
+        .iter()
+        .find(|(x, _)| *x == event_id)
+        .map_or("unknown", |(_, name)| *name)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Record an event in the executing core's trace buffer.
+#[cfg(feature = "event_trace")]
+#[macro_export]
+macro_rules! trace_event {
+    ($id:expr, $arg:expr) => {
+        $crate::trace::record($id, $arg as u64)
+    };
+}
+
+/// Record an event in the executing core's trace buffer.
+#[cfg(not(feature = "event_trace"))]
+#[macro_export]
+macro_rules! trace_event {
+    ($id:expr, $arg:expr) => {{
+        // Type-check the id, but evaluate nothing.
+        if false {
+            let _: u32 = $id;
+            let _ = $arg;
+        }
+    }};
+}
@@ -0,0 +1,133 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Per-core ring buffers of trace events.
+//!
+//! Only the owning core writes to a ring, and IRQs on that core may interrupt a write and add
+//! events of their own. Slots are claimed with an atomic increment, so an interrupted write is
+//! never overwritten by the interrupting one. A dump that races with a write may show a torn
+//! event, which is acceptable for a debugging aid.
+
+use crate::{config, cpu, info, time};
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Number of events that are kept per core. The oldest are overwritten first.
+const NUM_EVENTS: usize = 256;
+
+struct Event {
+    id: AtomicU32,
+    timestamp: AtomicU64,
+    arg: AtomicU64,
+}
+
+struct Ring {
+    /// Number of events ever recorded. The next one goes to `head % NUM_EVENTS`.
+    head: AtomicUsize,
+    events: [Event; NUM_EVENTS],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_EVENT: Event = Event {
+    id: AtomicU32::new(0),
+    timestamp: AtomicU64::new(0),
+    arg: AtomicU64::new(0),
+};
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_RING: Ring = Ring {
+    head: AtomicUsize::new(0),
+    events: [EMPTY_EVENT; NUM_EVENTS],
+};
+
+/// The rings, indexed by core id.
+static RINGS: [Ring; config::cpu::NUM_CORES] = [EMPTY_RING; config::cpu::NUM_CORES];
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Ring {
+    fn event(&self, n: usize) -> &Event {
+        &self.events[n % NUM_EVENTS]
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Add an event to the executing core's ring. Use `trace_event!` instead of calling this directly.
+pub fn record(id: u32, arg: u64) {
+    let ring = &RINGS[cpu::core_id()];
+    let event = ring.event(ring.head.fetch_add(1, Ordering::Relaxed));
+
+    event
+        .timestamp
+        .store(time::arch_time_source().counter(), Ordering::Relaxed);
+    event.arg.store(arg, Ordering::Relaxed);
+    event.id.store(id, Ordering::Release);
+}
+
+/// Print the events of all cores, from the oldest to the newest.
+pub fn dump() {
+    let frequency = time::arch_time_source().frequency();
+
+    // Per core, the number of the next event to print and the number of events recorded.
+    let mut cursors = [(0, 0); config::cpu::NUM_CORES];
+    for (core, (ring, cursor)) in RINGS.iter().zip(cursors.iter_mut()).enumerate() {
+        let head = ring.head.load(Ordering::Acquire);
+        let num_dropped = head.saturating_sub(NUM_EVENTS);
+        if num_dropped > 0 {
+            info!(
+                "      (core {}: {} older events dropped)",
+                core, num_dropped
+            );
+        }
+
+        *cursor = (num_dropped, head);
+    }
+
+    loop {
+        // The core whose next event is the oldest.
+        let next = RINGS
+            .iter()
+            .zip(cursors.iter())
+            .enumerate()
+            .filter(|(_, (_, (next, head)))| next < head)
+            .min_by_key(|(_, (ring, (next, _)))| {
+                ring.event(*next).timestamp.load(Ordering::Relaxed)
+            })
+            .map(|(core, _)| core);
+
+        let core = match next {
+            None => break,
+            Some(x) => x,
+        };
+
+        let event = RINGS[core].event(cursors[core].0);
+        cursors[core].0 += 1;
+
+        let id = event.id.load(Ordering::Acquire);
+        let time = frequency
+            .duration_of(event.timestamp.load(Ordering::Relaxed))
+            .unwrap_or_default();
+
+        info!(
+            "      [{:>5}.{:06}] core {} {:<10} {:#x}",
+            time.as_secs(),
+            time.subsec_micros(),
+            core,
+            super::name(id),
+            event.arg.load(Ordering::Relaxed)
+        );
+    }
+}
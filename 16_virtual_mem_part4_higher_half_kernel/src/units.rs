@@ -0,0 +1,196 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Units of measurement.
+//!
+//! Frequencies, baud rates and sizes are all plain integers in the hardware's eyes, which makes it
+//! easy to pass one for the other. The newtypes here keep them apart and print them in a unit that
+//! suits their magnitude. Arithmetic that can overflow is checked.
+
+use core::{convert::TryFrom, fmt, time::Duration};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const NS_PER_S: u128 = 1_000_000_000;
+
+const KIB: usize = 1 << 10;
+const MIB: usize = 1 << 20;
+const GIB: usize = 1 << 30;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A frequency in Hz.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Hertz(pub u64);
+
+/// A baud rate of a serial line.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Baud(pub u32);
+
+/// A size in bytes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct ByteSize(pub usize);
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Hertz {
+    /// Create an instance from kHz.
+    pub const fn from_khz(khz: u64) -> Self {
+        Self(khz * 1_000)
+    }
+
+    /// Create an instance from MHz.
+    pub const fn from_mhz(mhz: u64) -> Self {
+        Self(mhz * 1_000_000)
+    }
+
+    /// Multiply the frequency. None on overflow.
+    pub fn checked_mul(self, rhs: u64) -> Option<Self> {
+        self.0.checked_mul(rhs).map(Self)
+    }
+
+    /// The number of cycles in `duration`, rounded down. None on overflow.
+    pub fn cycles_in(self, duration: Duration) -> Option<u64> {
+        let cycles = duration.as_nanos().checked_mul(u128::from(self.0))? / NS_PER_S;
+
+        u64::try_from(cycles).ok()
+    }
+
+    /// The duration of `cycles`, rounded down to the nanosecond. None for a zero frequency.
+    pub fn duration_of(self, cycles: u64) -> Option<Duration> {
+        if self.0 == 0 {
+            return None;
+        }
+
+        let secs = cycles / self.0;
+        let nanos = u128::from(cycles % self.0) * NS_PER_S / u128::from(self.0);
+
+        Some(Duration::new(secs, nanos as u32))
+    }
+}
+
+impl fmt::Display for Hertz {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (divisor, unit) = if self.0 >= 1_000_000 {
+            (1_000_000, "MHz")
+        } else if self.0 >= 1_000 {
+            (1_000, "kHz")
+        } else {
+            return write!(f, "{} Hz", self.0);
+        };
+
+        // Three decimals at most, and none if they are all zero.
+        let whole = self.0 / divisor;
+        let thousandths = (self.0 % divisor) / (divisor / 1_000);
+
+        if thousandths == 0 {
+            write!(f, "{} {}", whole, unit)
+        } else {
+            write!(f, "{}.{:03} {}", whole, thousandths, unit)
+        }
+    }
+}
+
+impl fmt::Display for Baud {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} baud", self.0)
+    }
+}
+
+impl ByteSize {
+    /// Create an instance from KiB.
+    pub const fn from_kib(kib: usize) -> Self {
+        Self(kib * KIB)
+    }
+
+    /// Create an instance from MiB.
+    pub const fn from_mib(mib: usize) -> Self {
+        Self(mib * MIB)
+    }
+
+    /// Add two sizes. None on overflow.
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.0.checked_add(rhs.0).map(Self)
+    }
+
+    /// Subtract a size. None if `rhs` is larger.
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map(Self)
+    }
+
+    /// Multiply the size, for example by a number of pages. None on overflow.
+    pub fn checked_mul(self, rhs: usize) -> Option<Self> {
+        self.0.checked_mul(rhs).map(Self)
+    }
+
+    /// The size in the largest unit that represents it exactly, and the unit's name.
+    pub fn scaled(self) -> (usize, &'static str) {
+        [(GIB, "GiB"), (MIB, "MiB"), (KIB, "KiB")]
+            .iter()
+            .find(|(unit, _)| (self.0 != 0) && (self.0 % unit == 0))
+            .map_or((self.0, "Byte"), |(unit, name)| (self.0 / unit, *name))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (size, unit) = self.scaled();
+
+        write!(f, "{} {}", size, unit)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check the conversions between cycles and durations, including the overflowing ones.
+    #[kernel_test]
+    fn cycle_conversion_works() {
+        let hz = Hertz::from_khz(19_200);
+
+        assert_eq!(hz.cycles_in(Duration::from_millis(5)), Some(96_000));
+        assert_eq!(hz.duration_of(19_200_000), Some(Duration::from_secs(1)));
+        assert_eq!(
+            Hertz::from_mhz(1).duration_of(1),
+            Some(Duration::from_micros(1))
+        );
+        assert_eq!(Hertz(0).duration_of(1), None);
+
+        // Seconds times Hz beyond u64.
+        assert_eq!(hz.cycles_in(Duration::from_secs(u64::MAX)), None);
+        assert_eq!(Hertz(u64::MAX).checked_mul(2), None);
+        assert_eq!(
+            Hertz(u64::MAX).duration_of(u64::MAX),
+            Some(Duration::from_secs(1))
+        );
+    }
+
+    /// Check the size arithmetic and the choice of units.
+    #[kernel_test]
+    fn byte_size_works() {
+        assert_eq!(
+            ByteSize::from_kib(64).checked_mul(16),
+            Some(ByteSize::from_mib(1))
+        );
+        assert_eq!(ByteSize(usize::MAX).checked_add(ByteSize(1)), None);
+        assert_eq!(ByteSize(1).checked_sub(ByteSize(2)), None);
+
+        assert_eq!(ByteSize::from_mib(1024).scaled(), (1, "GiB"));
+        assert_eq!(ByteSize::from_kib(1536).scaled(), (1536, "KiB"));
+        assert_eq!(ByteSize(100).scaled(), (100, "Byte"));
+        assert_eq!(ByteSize(0).scaled(), (0, "Byte"));
+    }
+}
@@ -5,12 +5,48 @@
 use proc_macro::TokenStream;
 use proc_macro2::Span;
 use quote::quote;
-use syn::{parse_macro_input, Ident, ItemFn};
+use syn::{parse_macro_input, AttributeArgs, Ident, ItemFn, Lit, Meta, NestedMeta};
+
+/// Parse the attribute's arguments.
+///
+/// Supported are `should_panic`, which accepts any panic, and `should_panic = "substring"`, which
+/// only accepts panics whose message contains the substring.
+fn parse_should_panic(args: AttributeArgs) -> syn::Result<Option<String>> {
+    let mut should_panic = None;
+
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("should_panic") => {
+                should_panic = Some(String::new());
+            }
+            NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("should_panic") => {
+                match nv.lit {
+                    Lit::Str(ref s) => should_panic = Some(s.value()),
+                    ref x => return Err(syn::Error::new_spanned(x, "expected a string literal")),
+                }
+            }
+            x => {
+                return Err(syn::Error::new_spanned(
+                    x,
+                    "unsupported kernel_test argument",
+                ))
+            }
+        }
+    }
+
+    Ok(should_panic)
+}
 
 #[proc_macro_attribute]
-pub fn kernel_test(_attr: TokenStream, input: TokenStream) -> TokenStream {
+pub fn kernel_test(attr: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as AttributeArgs);
     let f = parse_macro_input!(input as ItemFn);
 
+    let should_panic = match parse_should_panic(args) {
+        Ok(x) => x,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
     let test_name = &format!("{}", f.sig.ident.to_string());
     let test_ident = Ident::new(
         &format!("{}_TEST_CONTAINER", f.sig.ident.to_string().to_uppercase()),
@@ -18,11 +54,39 @@ pub fn kernel_test(_attr: TokenStream, input: TokenStream) -> TokenStream {
     );
     let test_code_block = f.block;
 
+    // A should_panic test ends the test binary, so there can be only one per binary. Each one
+    // defines the same symbol, so that a second one fails to compile with "symbol
+    // `__kernel_test_should_panic` is already defined".
+    let (should_panic, marker) = match should_panic {
+        Some(x) => {
+            let marker_ident = Ident::new(
+                &format!(
+                    "{}_SHOULD_PANIC_MARKER",
+                    f.sig.ident.to_string().to_uppercase()
+                ),
+                Span::call_site(),
+            );
+
+            (
+                quote!(Some(#x)),
+                quote!(
+                    #[used]
+                    #[export_name = "__kernel_test_should_panic"]
+                    static #marker_ident: u8 = 0;
+                ),
+            )
+        }
+        None => (quote!(None), quote!()),
+    };
+
     quote!(
+        #marker
+
         #[test_case]
         const #test_ident: test_types::UnitTest = test_types::UnitTest {
             name: #test_name,
             test_func: || #test_code_block,
+            should_panic: #should_panic,
         };
     )
     .into()
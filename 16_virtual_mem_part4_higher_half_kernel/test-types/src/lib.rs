@@ -13,4 +13,7 @@ pub struct UnitTest {
 
     /// Function pointer to the test.
     pub test_func: fn(),
+
+    /// If set, the test passes only if it panics with a message containing the given substring.
+    pub should_panic: Option<&'static str>,
 }
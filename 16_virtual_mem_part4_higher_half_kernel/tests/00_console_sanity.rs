@@ -8,7 +8,7 @@
 #![no_main]
 #![no_std]
 
-use libkernel::{bsp, console, cpu, exception, print};
+use libkernel::{bsp, console, exception, print, shutdown};
 
 #[no_mangle]
 unsafe fn kernel_init() -> ! {
@@ -34,9 +34,9 @@ unsafe fn kernel_init() -> ! {
     // cpu::wait_forever();
 
     // For some reason, in this test, rustc or the linker produces an empty binary when
-    // wait_forever() is used. Calling qemu_exit_success() fixes this behavior. So for the time
+    // wait_forever() is used. Calling kernel_shutdown() fixes this behavior. So for the time
     // being, the following lines are just a workaround to fix this compiler/linker weirdness.
     use libkernel::time::interface::TimeManager;
     libkernel::time::time_manager().spin_for(core::time::Duration::from_secs(3600));
-    cpu::qemu_exit_success()
+    shutdown::kernel_shutdown(shutdown::ShutdownReason::Success)
 }
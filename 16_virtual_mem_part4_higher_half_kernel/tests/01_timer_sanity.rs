@@ -11,7 +11,12 @@
 #![test_runner(libkernel::test_runner)]
 
 use core::time::Duration;
-use libkernel::{bsp, cpu, exception, time, time::interface::TimeManager};
+use libkernel::{
+    bsp, exception,
+    shutdown::{self, ShutdownReason},
+    time,
+    time::interface::TimeManager,
+};
 use test_macros::kernel_test;
 
 #[no_mangle]
@@ -19,11 +24,11 @@ unsafe fn kernel_init() -> ! {
     exception::handling_init();
     bsp::console::qemu_bring_up_console();
 
-    // Depending on CPU arch, some timer bring-up code could go here. Not needed for the RPi.
+    time::select_counter();
 
     test_main();
 
-    cpu::qemu_exit_success()
+    shutdown::kernel_shutdown(ShutdownReason::Success)
 }
 
 /// Simple check that the timer is running.
@@ -47,3 +52,10 @@ fn spin_accuracy_check_1_second() {
 
     assert_eq!((t2 - t1).as_secs(), 1)
 }
+
+/// The kernel clears the virtual offset itself before it drops to EL1, so the counters agree and
+/// the physical one stays selected.
+#[kernel_test]
+fn physical_counter_is_selected() {
+    assert_eq!(time::active_counter(), time::Counter::Physical)
+}
@@ -17,7 +17,10 @@
 /// or indirectly.
 mod panic_exit_success;
 
-use libkernel::{bsp, cpu, exception, println};
+use libkernel::{
+    bsp, exception, println,
+    shutdown::{self, ShutdownReason},
+};
 
 #[no_mangle]
 unsafe fn kernel_init() -> ! {
@@ -32,5 +35,5 @@ unsafe fn kernel_init() -> ! {
     core::ptr::read_volatile(big_addr as *mut u64);
 
     // If execution reaches here, the memory access above did not cause a page fault exception.
-    cpu::qemu_exit_failure()
+    shutdown::kernel_shutdown(ShutdownReason::AssertionFailure)
 }
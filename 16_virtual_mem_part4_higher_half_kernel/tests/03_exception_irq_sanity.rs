@@ -10,7 +10,10 @@
 #![reexport_test_harness_main = "test_main"]
 #![test_runner(libkernel::test_runner)]
 
-use libkernel::{bsp, cpu, exception};
+use libkernel::{
+    bsp, exception,
+    shutdown::{self, ShutdownReason},
+};
 use test_macros::kernel_test;
 
 #[no_mangle]
@@ -22,7 +25,7 @@ unsafe fn kernel_init() -> ! {
 
     test_main();
 
-    cpu::qemu_exit_success()
+    shutdown::kernel_shutdown(ShutdownReason::Success)
 }
 
 /// Check that IRQ masking works.
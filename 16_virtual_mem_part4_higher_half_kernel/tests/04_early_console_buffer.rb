@@ -0,0 +1,22 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+##--------------------------------------------------------------------------------------------------
+## Test registration
+##--------------------------------------------------------------------------------------------------
+def subtest_collection
+    [
+        # Verify that output from before console bring-up is replayed.
+        GoldenOutputTest.new('Early output replayed') do
+            consume_until('Printed before console bring-up')
+        end,
+
+        # Verify that output after console bring-up follows the replayed output.
+        GoldenOutputTest.new('Later output follows') do
+            line('Printed after console bring-up')
+        end
+    ]
+end
@@ -0,0 +1,29 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Early console buffer test - output before console bring-up must not be lost.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+use libkernel::{bsp, exception, info, println, shutdown};
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+
+    // The console is not up yet, so this goes to the early buffer.
+    info!("Printed before console bring-up");
+
+    bsp::console::qemu_bring_up_console();
+
+    println!("Printed after console bring-up");
+
+    // The QEMU process running this test will be closed by the I/O test harness. See
+    // `00_console_sanity.rs` for why this does not use `wait_forever()`.
+    use libkernel::time::interface::TimeManager;
+    libkernel::time::time_manager().spin_for(core::time::Duration::from_secs(3600));
+    shutdown::kernel_shutdown(shutdown::ShutdownReason::Success)
+}
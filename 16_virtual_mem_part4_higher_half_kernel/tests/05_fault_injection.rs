@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Synchronous exceptions must be decoded correctly.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+use libkernel::{
+    bsp, exception,
+    exception::FaultClass,
+    shutdown::{self, ShutdownReason},
+    test_infra::fault,
+};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    bsp::console::qemu_bring_up_console();
+
+    fault::init().unwrap_or_else(|_| shutdown::kernel_shutdown(ShutdownReason::Panic));
+
+    test_main();
+
+    shutdown::kernel_shutdown(ShutdownReason::Success)
+}
+
+/// Check that an access to an unmapped address is a translation fault at that address.
+#[kernel_test]
+fn translation_fault_is_decoded() {
+    let info = fault::trigger_translation_fault().unwrap();
+    let guard_page = bsp::memory::mmu::virt_boot_core_stack_guard_page_desc();
+
+    assert_eq!(info.class, FaultClass::Translation);
+    assert!(info
+        .fault_addr
+        .map_or(false, |x| x == guard_page.start_addr()));
+}
+
+/// Check that a write to read-only memory is a permission fault.
+#[kernel_test]
+fn permission_fault_is_decoded() {
+    let info = fault::trigger_permission_fault().unwrap();
+
+    assert_eq!(info.class, FaultClass::Permission);
+    assert!(info.fault_addr.is_some());
+}
+
+/// Check that a misaligned device memory access is an alignment fault.
+#[kernel_test]
+fn alignment_fault_is_decoded() {
+    let info = fault::trigger_alignment_fault_on_device().unwrap();
+
+    assert_eq!(info.class, FaultClass::Alignment);
+    assert!(info.fault_addr.map_or(false, |x| x.into_usize() % 4 == 1));
+}
+
+/// Check that a breakpoint is reported without a fault address, and execution continues.
+#[kernel_test]
+fn brk_is_decoded() {
+    let info = fault::trigger_brk().unwrap();
+
+    assert_eq!(info.class, FaultClass::Breakpoint);
+    assert!(info.fault_addr.is_none());
+}
@@ -0,0 +1,48 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! The benchmarks must run to completion and report a rate.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+use libkernel::{
+    bench, bsp, exception, println,
+    shutdown::{self, ShutdownReason},
+};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    bsp::console::qemu_bring_up_console();
+
+    bench::init().unwrap_or_else(|_| shutdown::kernel_shutdown(ShutdownReason::Panic));
+
+    test_main();
+
+    shutdown::kernel_shutdown(ShutdownReason::Success)
+}
+
+/// Run each benchmark, and print the result for comparison with earlier runs.
+#[kernel_test]
+fn benchmarks_report_a_rate() {
+    let benchmarks = [
+        bench::memcpy_bandwidth,
+        bench::uart_throughput,
+        bench::lock_latency,
+        bench::exception_round_trip,
+        bench::translation_overhead,
+    ];
+
+    for benchmark in benchmarks.iter() {
+        let result = benchmark().unwrap();
+        println!("{}", result);
+
+        assert!(result.value > 0);
+    }
+}
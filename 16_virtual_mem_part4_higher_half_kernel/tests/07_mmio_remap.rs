@@ -0,0 +1,130 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! The MMIO region must share identical mappings, run out gracefully and be reclaimable.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+use libkernel::{
+    bsp, exception,
+    memory::{
+        mmu::{self, MMIODescriptor, MMIOMapError},
+        Address,
+    },
+    shutdown::{self, ShutdownReason},
+};
+use test_macros::kernel_test;
+
+/// Size of the descriptors that are used to fill up the MMIO region.
+const CHUNK_SIZE: usize = 64 * 1024 * 1024;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    bsp::console::qemu_bring_up_console();
+
+    test_main();
+
+    shutdown::kernel_shutdown(ShutdownReason::Success)
+}
+
+/// Map synthetic descriptors until the region is full, then unmap all of them again.
+///
+/// The descriptors are never accessed, so they may point anywhere in the physical address space.
+#[kernel_test]
+fn mmio_region_is_exhausted_and_reclaimed() {
+    let granule = bsp::memory::mmu::KernelGranule::SIZE;
+    let initial = mmu::kernel_mmio_stats();
+
+    // Identical descriptors share one mapping.
+    let single = MMIODescriptor::new(Address::new(0x3C00_0000), 0x1000);
+    let a = unsafe { mmu::kernel_map_mmio("A", &single) }.unwrap();
+    let b = unsafe { mmu::kernel_map_mmio("B", &single) }.unwrap();
+    assert_eq!(a.into_usize(), b.into_usize());
+    assert_eq!(
+        mmu::kernel_mmio_stats().num_pages_used,
+        initial.num_pages_used + 1
+    );
+
+    // An overlapping, but not identical descriptor gets a mapping of its own.
+    let overlapping = MMIODescriptor::new(Address::new(0x3C00_0800), granule);
+    let c = unsafe { mmu::kernel_map_mmio("C", &overlapping) }.unwrap();
+    assert!(c.into_usize() != a.into_usize());
+    assert_eq!(c.into_usize() & (granule - 1), 0x800);
+    assert_eq!(
+        mmu::kernel_mmio_stats().num_pages_used,
+        initial.num_pages_used + 3
+    );
+
+    // Fill the region with distinct descriptors until the allocator refuses.
+    let mut chunks: [Option<MMIODescriptor>; 8] = [None; 8];
+    let mut exhausted = false;
+    for (i, chunk) in chunks.iter_mut().enumerate() {
+        let descriptor = MMIODescriptor::new(Address::new(i * CHUNK_SIZE), CHUNK_SIZE);
+
+        match unsafe { mmu::kernel_map_mmio("Chunk", &descriptor) } {
+            Ok(_) => *chunk = Some(descriptor),
+            Err(MMIOMapError::RegionExhausted) => {
+                exhausted = true;
+                break;
+            }
+            Err(MMIOMapError::Other(x)) => panic!("{}", x),
+        }
+    }
+    assert!(exhausted);
+
+    let stats = mmu::kernel_mmio_stats();
+    assert!(stats.num_pages_total - stats.num_pages_used < CHUNK_SIZE / granule);
+
+    // Unmap everything in reverse order.
+    for descriptor in chunks.iter().rev().flatten() {
+        unsafe { mmu::kernel_unmap_mmio("Chunk", descriptor) }.unwrap();
+    }
+    unsafe {
+        mmu::kernel_unmap_mmio("C", &overlapping).unwrap();
+
+        // Dropping a user that does not exist must fail.
+        assert!(mmu::kernel_unmap_mmio("C", &single).is_err());
+
+        mmu::kernel_unmap_mmio("A", &single).unwrap();
+        assert!(mmu::try_virt_to_phys(b).is_ok());
+        mmu::kernel_unmap_mmio("B", &single).unwrap();
+        assert!(mmu::try_virt_to_phys(b).is_err());
+    }
+
+    assert_eq!(mmu::kernel_mmio_stats(), initial);
+}
+
+/// A mapping that is obtained as a guard knows its size, and is gone once the guard is dropped.
+#[kernel_test]
+fn mapped_mmio_is_unmapped_on_drop() {
+    let initial = mmu::kernel_mmio_stats();
+    let descriptor = MMIODescriptor::new(Address::new(0x3C00_0000), 0x1000);
+
+    let virt_addr = {
+        let mapping = unsafe { mmu::kernel_map_mmio_region("Guard", &descriptor) }.unwrap();
+        assert_eq!(mapping.size(), 0x1000);
+        assert!(mmu::try_virt_to_phys(mapping.start_addr()).is_ok());
+
+        // Accesses are checked against the size before they reach the bus.
+        assert!(mapping.read32(0x1000).is_err());
+        assert!(mapping.read32(0x2).is_err());
+
+        mapping.start_addr()
+    };
+    assert!(mmu::try_virt_to_phys(virt_addr).is_err());
+    assert_eq!(mmu::kernel_mmio_stats(), initial);
+
+    // A leaked mapping stays.
+    let region = unsafe { mmu::kernel_map_mmio_region("Leaked", &descriptor) }
+        .unwrap()
+        .leak();
+    assert!(mmu::try_virt_to_phys(region.start_addr()).is_ok());
+    unsafe { mmu::kernel_unmap_mmio("Leaked", &descriptor) }.unwrap();
+    assert_eq!(mmu::kernel_mmio_stats(), initial);
+}
@@ -0,0 +1,32 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+##--------------------------------------------------------------------------------------------------
+## Test registration
+##--------------------------------------------------------------------------------------------------
+def subtest_collection
+    [
+        # The first command of the script lists the built-in commands.
+        GoldenOutputTest.new('help') do
+            consume_until('mon> help')
+            line('Available commands:')
+            line('      help')
+            line('      mappings')
+        end,
+
+        # The MMIO mapping of the UART, which prints all of this, is in the record.
+        GoldenOutputTest.new('mappings') do
+            consume_until('mon> mappings')
+            consume_until(/.*  BCM PL011 UART/)
+        end,
+
+        # An unknown command is reported, and the prompt comes back.
+        GoldenOutputTest.new('Invalid command') do
+            consume_until('mon> frobnicate')
+            line("Error: Unknown command. Try 'help'")
+        end
+    ]
+end
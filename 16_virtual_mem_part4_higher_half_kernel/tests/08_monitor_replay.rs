@@ -0,0 +1,36 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Monitor test - commands typed by a script must be executed.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+use core::time::Duration;
+use libkernel::{
+    bsp, exception, monitor,
+    shutdown::{self, ShutdownReason},
+    test_infra::replay::{self, Step},
+};
+
+/// The pauses let the output of a command drain before the next one is typed.
+static SCRIPT: [Step; 5] = [
+    Step::Type("help\r"),
+    Step::Pause(Duration::from_millis(100)),
+    Step::Type("mappings\r"),
+    Step::Pause(Duration::from_millis(100)),
+    Step::Type("frobnicate\r"),
+];
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    bsp::console::qemu_bring_up_console();
+
+    replay::start(&SCRIPT).unwrap_or_else(|_| shutdown::kernel_shutdown(ShutdownReason::Panic));
+
+    // The QEMU process running this test will be closed by the I/O test harness.
+    monitor::run()
+}
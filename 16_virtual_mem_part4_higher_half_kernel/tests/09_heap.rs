@@ -0,0 +1,61 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! The heap statistics must follow allocations and return to their baseline once they are freed.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use libkernel::{
+    bsp, exception,
+    memory::heap,
+    shutdown::{self, ShutdownReason},
+};
+use test_macros::kernel_test;
+
+/// Number of elements of the large vector. Needs several reallocations to get there.
+const NUM_ELEMENTS: usize = 16 * 1024;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    bsp::console::qemu_bring_up_console();
+
+    test_main();
+
+    shutdown::kernel_shutdown(ShutdownReason::Success)
+}
+
+/// Grow a large vector, then drop it.
+#[kernel_test]
+fn stats_return_to_baseline() {
+    let baseline = heap::stats();
+
+    let mut v = Vec::new();
+    for i in 0..NUM_ELEMENTS {
+        v.push(i as u32);
+    }
+
+    let grown = heap::stats();
+    assert!(grown.current >= baseline.current + NUM_ELEMENTS * 4);
+    assert!(grown.peak >= grown.current);
+    assert!(grown.num_allocs > baseline.num_allocs);
+    assert_eq!(grown.live_allocs, baseline.live_allocs + 1);
+    assert_eq!(v.last(), Some(&(NUM_ELEMENTS as u32 - 1)));
+
+    drop(v);
+
+    let dropped = heap::stats();
+    assert_eq!(dropped.current, baseline.current);
+    assert_eq!(dropped.live_allocs, baseline.live_allocs);
+    assert_eq!(dropped.largest_free, baseline.largest_free);
+    assert_eq!(dropped.free_blocks, baseline.free_blocks);
+    assert_eq!(dropped.failures, baseline.failures);
+}
@@ -0,0 +1,104 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Cacheable buffers must be bounced through the DMA pool, buffers from the pool must not, and the
+//! pool's statistics must follow both.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+use libkernel::{
+    bsp, exception,
+    memory::dma_pool::{self, DmaMapping, DmaTarget},
+    shutdown::{self, ShutdownReason},
+};
+use test_macros::kernel_test;
+
+/// Length of the test buffers. Not a multiple of the block size, to exercise the rounding.
+const BUF_LEN: usize = 5000;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    bsp::console::qemu_bring_up_console();
+
+    dma_pool::init().unwrap_or_else(|_| shutdown::kernel_shutdown(ShutdownReason::Panic));
+
+    test_main();
+
+    shutdown::kernel_shutdown(ShutdownReason::Success)
+}
+
+fn pattern() -> [u8; BUF_LEN] {
+    let mut buf = [0; BUF_LEN];
+    for (i, x) in buf.iter_mut().enumerate() {
+        *x = (i * 7) as u8;
+    }
+
+    buf
+}
+
+/// Data survives a round trip through a bounce buffer, and the blocks return to the pool on drop.
+#[kernel_test]
+fn buffer_round_trip() {
+    let baseline = dma_pool::stats();
+    let src = pattern();
+    let mut dst = [0; BUF_LEN];
+
+    let mut buf = dma_pool::alloc(BUF_LEN).unwrap();
+    assert!(buf.size() >= BUF_LEN);
+    assert!(dma_pool::stats().blocks_in_use > baseline.blocks_in_use);
+
+    buf.copy_in(&src).unwrap();
+    buf.copy_out(&mut dst).unwrap();
+    assert!(src == dst);
+
+    drop(buf);
+    assert_eq!(dma_pool::stats().blocks_in_use, baseline.blocks_in_use);
+}
+
+/// A buffer on the stack is cacheable and gets bounced, a buffer from the pool is used directly.
+#[kernel_test]
+fn cacheable_buffers_are_bounced() {
+    let baseline = dma_pool::stats();
+    let pool = bsp::memory::mmu::phys_dma_pool_page_desc().unwrap();
+    let src = pattern();
+
+    let mapping = DmaMapping::new(DmaTarget::ToDevice(&src)).unwrap();
+    assert!(mapping.is_bounced());
+    assert!(mapping.phys_addr() >= pool.start_addr());
+    assert!(mapping.phys_addr() < pool.end_addr());
+    mapping.finish().unwrap();
+
+    let buf = dma_pool::alloc(BUF_LEN).unwrap();
+    let direct = unsafe {
+        core::slice::from_raw_parts_mut(buf.virt_addr().into_usize() as *mut u8, BUF_LEN)
+    };
+    let mapping = DmaMapping::new(DmaTarget::FromDevice(direct)).unwrap();
+    assert!(!mapping.is_bounced());
+    assert_eq!(mapping.phys_addr(), buf.phys_addr());
+    mapping.finish().unwrap();
+    drop(buf);
+
+    let stats = dma_pool::stats();
+    assert_eq!(stats.num_bounced, baseline.num_bounced + 1);
+    assert_eq!(stats.num_direct, baseline.num_direct + 1);
+    assert_eq!(stats.bytes_bounced, baseline.bytes_bounced + BUF_LEN);
+    assert_eq!(stats.blocks_in_use, baseline.blocks_in_use);
+}
+
+/// Asking for more than the pool holds fails and is counted.
+#[kernel_test]
+fn oversized_request_fails() {
+    let baseline = dma_pool::stats();
+
+    assert!(dma_pool::alloc(baseline.size + 1).is_err());
+    assert_eq!(
+        dma_pool::stats().alloc_failures,
+        baseline.alloc_failures + 1
+    );
+}
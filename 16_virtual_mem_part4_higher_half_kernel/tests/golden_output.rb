@@ -0,0 +1,81 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+require 'expect'
+
+# A console subtest that checks the kernel's output line by line.
+#
+# The expected output is given as a list of steps:
+#
+# - `line(pattern)`: The next line must match `pattern`.
+# - `consume_until(pattern)`: Skip lines until one matches `pattern`. Useful for skipping over
+#   variable-length output, like the mapping dump.
+#
+# Patterns are anchored to the whole line, without the line terminator. Strings match literally,
+# Regexps as regular expressions. Each step has its own timeout.
+#
+# Log messages have no prefix in test builds, so that simple tests need no regular expressions.
+class GoldenOutputTest
+    DEFAULT_TIMEOUT_SECS = 3
+
+    attr_reader :name
+
+    def initialize(name, &block)
+        @name = name
+        @steps = []
+
+        instance_eval(&block)
+    end
+
+    def line(pattern, timeout: DEFAULT_TIMEOUT_SECS)
+        @steps << [:line, anchor(pattern), timeout]
+    end
+
+    def consume_until(pattern, timeout: DEFAULT_TIMEOUT_SECS)
+        @steps << [:consume_until, anchor(pattern), timeout]
+    end
+
+    def run(qemu_out, _qemu_in)
+        @steps.each do |kind, regex, timeout|
+            case kind
+            when :line
+                actual = next_line(qemu_out, timeout)
+                next if regex.match?(actual)
+
+                raise("Expected /#{regex.source}/, got #{actual.inspect}")
+            when :consume_until
+                deadline = now + timeout
+
+                loop do
+                    break if regex.match?(next_line(qemu_out, deadline - now))
+                end
+            end
+        end
+    end
+
+    private
+
+    def anchor(pattern)
+        source, options = if pattern.is_a?(Regexp)
+                              [pattern.source, pattern.options]
+                          else
+                              [Regexp.escape(pattern), 0]
+                          end
+
+        Regexp.new("\\A(?:#{source})\\z", options)
+    end
+
+    def now
+        Process.clock_gettime(Process::CLOCK_MONOTONIC)
+    end
+
+    def next_line(qemu_out, timeout)
+        result = qemu_out.expect(/\n/, timeout) if timeout.positive?
+        raise('Timed out waiting for output') if result.nil?
+
+        result[0].chomp
+    end
+end
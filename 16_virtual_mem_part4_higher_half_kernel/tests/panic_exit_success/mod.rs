@@ -4,6 +4,6 @@
 
 /// Overwrites libkernel's `panic_wait::_panic_exit()` with the QEMU-exit version.
 #[no_mangle]
-fn _panic_exit() -> ! {
-    libkernel::cpu::qemu_exit_success()
+fn _panic_exit(_reason: libkernel::shutdown::ShutdownReason) -> ! {
+    libkernel::shutdown::kernel_shutdown(libkernel::shutdown::ShutdownReason::Success)
 }
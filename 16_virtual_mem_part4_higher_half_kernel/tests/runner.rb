@@ -7,6 +7,7 @@
 
 require 'English'
 require 'pty'
+require_relative 'golden_output'
 
 # Test base class.
 class Test
@@ -97,6 +98,14 @@ end
 class RawTest < Test
     MAX_WAIT_SECS = 5
 
+    # Exit codes of the kernel's `shutdown::ShutdownReason`.
+    EXIT_CODE_ERRORS = {
+        1 => 'Kernel panic',
+        2 => 'Assertion failed',
+        3 => 'Nested kernel panic',
+        4 => 'Test timed out'
+    }.freeze
+
     def initialize(binary, qemu_cmd, test_name)
         super()
 
@@ -106,6 +115,12 @@ class RawTest < Test
         @output = []
     end
 
+    def exit_code_error(exit_code)
+        return false if exit_code.zero?
+
+        EXIT_CODE_ERRORS.fetch(exit_code, "Exit code #{exit_code}")
+    end
+
     def exec
         error = 'Timed out waiting for test'
         io = IO.popen(@qemu_cmd)
@@ -115,7 +130,7 @@ class RawTest < Test
                 @output << io.read_nonblock(1024)
             rescue EOFError
                 io.close
-                error = $CHILD_STATUS.to_i != 0
+                error = exit_code_error($CHILD_STATUS.exitstatus)
                 break
             end
         end
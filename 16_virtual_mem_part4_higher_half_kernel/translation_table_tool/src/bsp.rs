@@ -0,0 +1,27 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Stand-in for the BSP, and the tool's knowledge about the boards.
+
+mod raspberrypi;
+
+pub use raspberrypi::RaspberryPi;
+
+pub mod memory {
+    use crate::memory::{mmu::PageSliceDescriptor, Physical};
+
+    #[path = "../../../../src/bsp/raspberrypi/memory/layout.rs"]
+    pub mod layout;
+
+    /// Same as the Raspberry Pi 3's. Only needed by the MMIO descriptor checks, which the tool
+    /// does not use.
+    pub const fn phys_mmio_window() -> PageSliceDescriptor<Physical> {
+        crate::page_slice!(Physical, 0x3F00_0000, 0x0101_0000)
+    }
+
+    pub mod mmu {
+        /// Same as the Raspberry Pi's.
+        pub type KernelGranule = crate::memory::mmu::TranslationGranule<{ 64 * 1024 }>;
+    }
+}
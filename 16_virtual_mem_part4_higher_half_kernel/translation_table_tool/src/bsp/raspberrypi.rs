@@ -0,0 +1,299 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Raspberry Pi 3 + 4.
+
+use crate::{
+    bsp::memory::layout::{CODE_ATTRIBUTES, DATA_ATTRIBUTES},
+    elf::Elf,
+    memory::{
+        mmu::{AttributeFields, Page, PageSliceDescriptor, TranslationDescriptor},
+        Address, Physical, Virtual,
+    },
+};
+use std::fs;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const MEMORY_SRC: &str = "src/bsp/raspberrypi/memory.rs";
+
+/// The virtual addresses of the symbols that the tool needs.
+struct VirtAddresses {
+    boot_core_stack_start: usize,
+    boot_core_stack_end_exclusive: usize,
+    secondary_core_stacks_start: usize,
+    exception_stacks_start: usize,
+    exception_stacks_end_exclusive: usize,
+    rx_start: usize,
+    rx_end_exclusive: usize,
+    rw_start: usize,
+    rw_end_exclusive: usize,
+    table_struct_start_addr: usize,
+    phys_tables_base_addr: usize,
+    image_checksum: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The kernel's layout on a Raspberry Pi, taken from the kernel ELF.
+pub struct RaspberryPi {
+    kernel_virt_addr_space_size: usize,
+    kernel_virt_start_addr: usize,
+    secondary_core_stack_guard_page_size: usize,
+    secondary_core_stack_size: usize,
+    secondary_core_stack_slot_size: usize,
+    exception_stack_guard_page_size: usize,
+    exception_stack_size: usize,
+    exception_stack_slot_size: usize,
+    virt_addresses: VirtAddresses,
+    phys_addr_space_end: usize,
+    text_section_offset_in_elf: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn parse_hex(s: &str) -> Result<usize, String> {
+    let digits = s.trim().trim_start_matches("0x").replace('_', "");
+
+    usize::from_str_radix(&digits, 16).map_err(|_| format!("Not a hex number: {}", s))
+}
+
+/// The exclusive end of the physical address space, which is the end of the MMIO window that the
+/// BSP defines for the given board.
+fn parse_phys_addr_space_end(bsp_type: &str) -> Result<usize, String> {
+    let src = fs::read_to_string(MEMORY_SRC).map_err(|x| format!("{}: {}", MEMORY_SRC, x))?;
+    let windows: Vec<&str> = src
+        .lines()
+        .filter(|x| x.contains("page_slice!(Physical,"))
+        .collect();
+
+    let window = match bsp_type {
+        "rpi3" => windows.get(0),
+        "rpi4" => windows.get(1),
+        _ => None,
+    }
+    .ok_or_else(|| format!("No MMIO window for BSP {}", bsp_type))?;
+
+    // crate::page_slice!(Physical, START, SIZE);
+    let args: Vec<&str> = window
+        .split(|x| x == '(' || x == ')')
+        .nth(1)
+        .unwrap_or("")
+        .split(',')
+        .collect();
+    if args.len() != 3 {
+        return Err(format!("Unexpected MMIO window: {}", window.trim()));
+    }
+
+    Ok(parse_hex(args[1])? + parse_hex(args[2])?)
+}
+
+impl RaspberryPi {
+    fn virt_to_phys(&self, virt: usize) -> usize {
+        virt - self.kernel_virt_start_addr
+    }
+
+    fn offset_in_kernel_elf(&self, virt: usize) -> usize {
+        (virt - self.virt_addresses.rx_start) + self.text_section_offset_in_elf
+    }
+
+    fn section(
+        &self,
+        name: &'static str,
+        virt_start: usize,
+        size: usize,
+        attr: AttributeFields,
+    ) -> Result<TranslationDescriptor, String> {
+        let virt_pages =
+            PageSliceDescriptor::try_from_addr_and_size(Address::new(virt_start), size)
+                .map_err(|x| format!("{}: {}", name, x))?;
+        let phys_pages = PageSliceDescriptor::try_from_addr_and_size(
+            Address::new(self.virt_to_phys(virt_start)),
+            size,
+        )
+        .map_err(|x| format!("{}: {}", name, x))?;
+
+        Ok(TranslationDescriptor {
+            name,
+            virt_pages,
+            phys_pages,
+            attribute_fields: attr,
+        })
+    }
+
+    fn secondary_core_stack_start(&self, core_id: usize) -> usize {
+        self.virt_addresses.secondary_core_stacks_start
+            + ((core_id - 1) * self.secondary_core_stack_slot_size)
+            + self.secondary_core_stack_guard_page_size
+    }
+
+    /// The linker script reserves one exception stack per core.
+    fn num_cores(&self) -> usize {
+        (self.virt_addresses.exception_stacks_end_exclusive
+            - self.virt_addresses.exception_stacks_start)
+            / self.exception_stack_slot_size
+    }
+
+    fn exception_stack_start(&self, core_id: usize) -> usize {
+        self.virt_addresses.exception_stacks_start
+            + (core_id * self.exception_stack_slot_size)
+            + self.exception_stack_guard_page_size
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl RaspberryPi {
+    /// Create an instance for the given BSP from the symbols of the kernel ELF.
+    pub fn new(elf: &Elf, bsp_type: &str) -> Result<Self, String> {
+        let virt_addresses = VirtAddresses {
+            boot_core_stack_start: elf.symbol_named("__boot_core_stack_start")?,
+            boot_core_stack_end_exclusive: elf.symbol_named("__boot_core_stack_end_exclusive")?,
+            secondary_core_stacks_start: elf.symbol_named("__secondary_core_stacks_start")?,
+            exception_stacks_start: elf.symbol_named("__exception_stacks_start")?,
+            exception_stacks_end_exclusive: elf.symbol_named("__exception_stacks_end_exclusive")?,
+            rx_start: elf.symbol_named("__rx_start")?,
+            rx_end_exclusive: elf.symbol_named("__rx_end_exclusive")?,
+            rw_start: elf.symbol_named("__rw_start")?,
+            rw_end_exclusive: elf.symbol_named("__rw_end_exclusive")?,
+            table_struct_start_addr: elf.symbol("KERNEL_TABLES", |x| {
+                x.contains("bsp::") && x.ends_with("::memory::mmu::KERNEL_TABLES")
+            })?,
+            phys_tables_base_addr: elf.symbol_named("PHYS_KERNEL_TABLES_BASE_ADDR")?,
+            image_checksum: elf.symbol_named("IMAGE_CHECKSUM")?,
+        };
+
+        Ok(Self {
+            kernel_virt_addr_space_size: elf.symbol_named("__kernel_virt_addr_space_size")?,
+            kernel_virt_start_addr: elf.symbol_named("__kernel_virt_start_addr")?,
+            secondary_core_stack_guard_page_size: elf
+                .symbol_named("__secondary_core_stack_guard_page_size")?,
+            secondary_core_stack_size: elf.symbol_named("__secondary_core_stack_size")?,
+            secondary_core_stack_slot_size: elf.symbol_named("__secondary_core_stack_slot_size")?,
+            exception_stack_guard_page_size: elf
+                .symbol_named("__exception_stack_guard_page_size")?,
+            exception_stack_size: elf.symbol_named("__exception_stack_size")?,
+            exception_stack_slot_size: elf.symbol_named("__exception_stack_slot_size")?,
+            virt_addresses,
+            phys_addr_space_end: parse_phys_addr_space_end(bsp_type)?,
+            text_section_offset_in_elf: elf.section_offset(".text")?,
+        })
+    }
+
+    /// Size of the kernel's virtual address space.
+    pub fn kernel_virt_addr_space_size(&self) -> usize {
+        self.kernel_virt_addr_space_size
+    }
+
+    /// Start of the kernel's virtual address space.
+    pub fn kernel_virt_start_addr(&self) -> Address<Virtual> {
+        Address::new(self.kernel_virt_start_addr)
+    }
+
+    /// Exclusive end of the kernel binary's RW data.
+    pub fn rw_end_exclusive(&self) -> Address<Virtual> {
+        Address::new(self.virt_addresses.rw_end_exclusive)
+    }
+
+    /// Where the kernel's `FixedSizeTranslationTable` is loaded.
+    pub fn phys_table_struct_start_addr(&self) -> Address<Physical> {
+        Address::new(self.virt_to_phys(self.virt_addresses.table_struct_start_addr))
+    }
+
+    /// The file offset of the table struct in the kernel ELF.
+    pub fn table_struct_offset_in_kernel_elf(&self) -> usize {
+        self.offset_in_kernel_elf(self.virt_addresses.table_struct_start_addr)
+    }
+
+    /// Where `PHYS_KERNEL_TABLES_BASE_ADDR` is loaded.
+    pub fn phys_tables_base_addr(&self) -> Address<Physical> {
+        Address::new(self.virt_to_phys(self.virt_addresses.phys_tables_base_addr))
+    }
+
+    /// The file offset of `PHYS_KERNEL_TABLES_BASE_ADDR` in the kernel ELF.
+    pub fn phys_tables_base_addr_offset_in_kernel_elf(&self) -> usize {
+        self.offset_in_kernel_elf(self.virt_addresses.phys_tables_base_addr)
+    }
+
+    /// Size of the code and RO data.
+    pub fn rx_size(&self) -> usize {
+        self.virt_addresses.rx_end_exclusive - self.virt_addresses.rx_start
+    }
+
+    /// The file offset of the code and RO data in the kernel ELF.
+    pub fn rx_offset_in_kernel_elf(&self) -> usize {
+        self.text_section_offset_in_elf
+    }
+
+    /// Where `IMAGE_CHECKSUM` is loaded.
+    pub fn phys_image_checksum_addr(&self) -> Address<Physical> {
+        Address::new(self.virt_to_phys(self.virt_addresses.image_checksum))
+    }
+
+    /// The file offset of `IMAGE_CHECKSUM` in the kernel ELF.
+    pub fn image_checksum_offset_in_kernel_elf(&self) -> usize {
+        self.offset_in_kernel_elf(self.virt_addresses.image_checksum)
+    }
+
+    /// Same as the BSP's `phys_addr_space_end_page()`.
+    pub fn phys_addr_space_end_page(&self) -> *const Page<Physical> {
+        Address::<Physical>::new(self.phys_addr_space_end)
+            .align_down(crate::bsp::memory::mmu::KernelGranule::SIZE)
+            .into_usize() as *const Page<_>
+    }
+
+    /// The kernel's precomputed virtual memory layout, like the BSP's `virt_mem_layout()`.
+    pub fn virt_mem_layout(&self) -> Result<Vec<TranslationDescriptor>, String> {
+        let v = &self.virt_addresses;
+
+        let mut layout = vec![
+            self.section(
+                "Kernel code and RO data",
+                v.rx_start,
+                v.rx_end_exclusive - v.rx_start,
+                CODE_ATTRIBUTES,
+            )?,
+            self.section(
+                "Kernel data and bss",
+                v.rw_start,
+                v.rw_end_exclusive - v.rw_start,
+                DATA_ATTRIBUTES,
+            )?,
+            self.section(
+                "Kernel boot-core stack",
+                v.boot_core_stack_start,
+                v.boot_core_stack_end_exclusive - v.boot_core_stack_start,
+                DATA_ATTRIBUTES,
+            )?,
+        ];
+
+        for core_id in 1..self.num_cores() {
+            layout.push(self.section(
+                "Kernel secondary-core stack",
+                self.secondary_core_stack_start(core_id),
+                self.secondary_core_stack_size,
+                DATA_ATTRIBUTES,
+            )?);
+        }
+
+        for core_id in 0..self.num_cores() {
+            layout.push(self.section(
+                "Kernel exception stack",
+                self.exception_stack_start(core_id),
+                self.exception_stack_size,
+                DATA_ATTRIBUTES,
+            )?);
+        }
+
+        Ok(layout)
+    }
+}
@@ -0,0 +1,206 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Just enough of an ELF64 little-endian reader to find the kernel's symbols and sections.
+
+use std::convert::TryInto;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const SHT_SYMTAB: u32 = 2;
+const SYMBOL_SIZE: usize = 24;
+
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: u32,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A parsed kernel ELF.
+pub struct Elf {
+    sections: Vec<(String, SectionHeader)>,
+    symbols: Vec<(String, u64)>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, String> {
+    data.get(offset..offset + 2)
+        .map(|x| u16::from_le_bytes(x.try_into().unwrap()))
+        .ok_or_else(|| format!("ELF truncated at {:#x}", offset))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    data.get(offset..offset + 4)
+        .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+        .ok_or_else(|| format!("ELF truncated at {:#x}", offset))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, String> {
+    data.get(offset..offset + 8)
+        .map(|x| u64::from_le_bytes(x.try_into().unwrap()))
+        .ok_or_else(|| format!("ELF truncated at {:#x}", offset))
+}
+
+/// The zero-terminated string at `offset` in the string table `table`.
+fn read_str(table: &[u8], offset: u32) -> String {
+    let start = &table[(offset as usize).min(table.len())..];
+    let end = start.iter().position(|x| *x == 0).unwrap_or(start.len());
+
+    String::from_utf8_lossy(&start[..end]).into_owned()
+}
+
+/// Turn a legacy mangled Rust symbol into its path, without the hash. Other names are returned as
+/// they are.
+///
+/// `_ZN9libkernel3bsp4KERNEL17h0123456789abcdefE` becomes `libkernel::bsp::KERNEL`.
+fn demangle(name: &str) -> String {
+    let mut rest = match name.strip_prefix("_ZN") {
+        Some(x) => x,
+        None => return name.to_string(),
+    };
+    let mut segments = Vec::new();
+
+    while !rest.starts_with('E') {
+        let num_digits = rest.chars().take_while(|x| x.is_ascii_digit()).count();
+        let len = match rest[..num_digits].parse::<usize>() {
+            Ok(x) if num_digits + x <= rest.len() => x,
+            _ => return name.to_string(),
+        };
+
+        segments.push(&rest[num_digits..num_digits + len]);
+        rest = &rest[num_digits + len..];
+    }
+
+    if let Some(last) = segments.last() {
+        if last.len() == 17 && last.starts_with('h') {
+            segments.pop();
+        }
+    }
+
+    segments.join("::")
+}
+
+impl SectionHeader {
+    fn parse(data: &[u8], offset: usize) -> Result<Self, String> {
+        Ok(Self {
+            name: read_u32(data, offset)?,
+            kind: read_u32(data, offset + 4)?,
+            offset: read_u64(data, offset + 24)? as usize,
+            size: read_u64(data, offset + 32)? as usize,
+            link: read_u32(data, offset + 40)?,
+        })
+    }
+
+    fn data<'a>(&self, data: &'a [u8]) -> Result<&'a [u8], String> {
+        data.get(self.offset..self.offset + self.size)
+            .ok_or_else(|| format!("ELF section at {:#x} is out of bounds", self.offset))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Elf {
+    /// Parse the section headers and the symbol table.
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if data.get(0..6) != Some(&[0x7F, b'E', b'L', b'F', 2, 1]) {
+            return Err("Not a 64 bit little-endian ELF".to_string());
+        }
+
+        let sh_offset = read_u64(data, 0x28)? as usize;
+        let sh_entry_size = read_u16(data, 0x3A)? as usize;
+        let sh_num = read_u16(data, 0x3C)? as usize;
+        let sh_str_index = read_u16(data, 0x3E)? as usize;
+
+        let headers = (0..sh_num)
+            .map(|i| SectionHeader::parse(data, sh_offset + i * sh_entry_size))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let sh_str_table = headers
+            .get(sh_str_index)
+            .ok_or("Section name table is missing")?
+            .data(data)?;
+
+        let mut symbols = Vec::new();
+        for header in headers.iter().filter(|x| x.kind == SHT_SYMTAB) {
+            let table = header.data(data)?;
+            let str_table = headers
+                .get(header.link as usize)
+                .ok_or("Symbol name table is missing")?
+                .data(data)?;
+
+            for entry in table.chunks_exact(SYMBOL_SIZE) {
+                let name = read_str(str_table, read_u32(entry, 0)?);
+                let value = read_u64(entry, 8)?;
+
+                symbols.push((demangle(&name), value));
+            }
+        }
+
+        let sections = headers
+            .into_iter()
+            .map(|x| (read_str(sh_str_table, x.name), x))
+            .collect();
+
+        Ok(Self { sections, symbols })
+    }
+
+    /// The value of the first symbol for which `matches` returns true. Mangled names are passed as
+    /// paths, see `demangle()`.
+    pub fn symbol(&self, what: &str, matches: impl Fn(&str) -> bool) -> Result<usize, String> {
+        self.symbols
+            .iter()
+            .find(|(name, _)| matches(name))
+            .map(|(_, value)| *value as usize)
+            .ok_or_else(|| format!("Symbol not found: {}", what))
+    }
+
+    /// The value of the symbol with the given name.
+    pub fn symbol_named(&self, name: &str) -> Result<usize, String> {
+        self.symbol(name, |x| x == name)
+    }
+
+    /// The offset in the file of the section with the given name.
+    pub fn section_offset(&self, name: &str) -> Result<usize, String> {
+        self.sections
+            .iter()
+            .find(|(x, _)| x == name)
+            .map(|(_, header)| header.offset)
+            .ok_or_else(|| format!("Section not found: {}", name))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mangled names lose the hash, others stay as they are.
+    #[test]
+    fn demangle_works() {
+        assert_eq!(
+            demangle(
+                "_ZN9libkernel3bsp11raspberrypi6memory3mmu13KERNEL_TABLES17h0123456789abcdefE"
+            ),
+            "libkernel::bsp::raspberrypi::memory::mmu::KERNEL_TABLES"
+        );
+        assert_eq!(demangle("__rx_start"), "__rx_start");
+        assert_eq!(demangle("_ZN99tooshortE"), "_ZN99tooshortE");
+    }
+}
@@ -0,0 +1,209 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! Precompute the kernel's translation tables and patch them into the kernel ELF.
+//!
+//! The descriptors are encoded by the kernel's own descriptor module. Like the other kernel code
+//! that the tool needs, it is included by path, in a module tree that mirrors the kernel's, so that
+//! its `crate::` paths resolve. The few items that it needs from the rest of the kernel are
+//! replaced by stand-ins. The kernel checks the precomputed tables with the same module at boot.
+//!
+//! Usage: `translation_table_tool TARGET BSP KERNEL_ELF`
+
+#![allow(dead_code)]
+#![feature(const_fn)]
+#![feature(const_fn_fn_ptr_basics)]
+#![feature(const_panic)]
+
+mod bsp;
+#[path = "../../src/common.rs"]
+mod common;
+mod config;
+mod elf;
+mod memory;
+
+use bsp::RaspberryPi;
+use elf::Elf;
+use memory::mmu::translation_table::TranslationTable;
+use std::{env, fs, process, time::Instant};
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Print a step, right-aligned and in bold green.
+fn print_step(step: &str) {
+    print!("\x1b[1;32m{:>12}\x1b[0m", step);
+}
+
+fn patch(kernel_elf: &mut [u8], offset: usize, data: &[u8]) -> Result<(), String> {
+    kernel_elf
+        .get_mut(offset..offset + data.len())
+        .ok_or_else(|| format!("Patch at offset {:#x} is out of bounds", offset))?
+        .copy_from_slice(data);
+
+    Ok(())
+}
+
+fn kernel_map_binary(bsp: &RaspberryPi, tables: &mut TranslationTable) -> Result<(), String> {
+    let layout = bsp.virt_mem_layout()?;
+    let name_width = layout.iter().map(|x| x.name.len()).max().unwrap_or(0);
+    let divider = format!("{:13}{}", "", "-".repeat(name_width + 34));
+
+    println!("{}", divider);
+    println!(
+        "{:13}{:^name_width$}   {:^21}   {:^7}",
+        "",
+        "Section",
+        "Start Virt Addr",
+        "Size",
+        name_width = name_width
+    );
+    println!("{}", divider);
+
+    for section in layout.iter() {
+        print_step("Generating");
+        println!(
+            " {:name_width$} | {} | {:>3} KiB",
+            section.name,
+            section.virt_pages.start_addr(),
+            section.virt_pages.size() / 1024,
+            name_width = name_width
+        );
+
+        tables
+            .map_pages_at(
+                &section.virt_pages,
+                &section.phys_pages,
+                &section.attribute_fields,
+            )
+            .map_err(|x| format!("{}: {}", section.name, x))?;
+    }
+
+    println!("{}", divider);
+
+    Ok(())
+}
+
+fn kernel_patch_tables(
+    bsp: &RaspberryPi,
+    tables: &TranslationTable,
+    kernel_elf: &mut [u8],
+) -> Result<(), String> {
+    print_step("Patching");
+    println!(
+        " Kernel table struct at physical {}",
+        bsp.phys_table_struct_start_addr()
+    );
+
+    patch(
+        kernel_elf,
+        bsp.table_struct_offset_in_kernel_elf(),
+        &tables.to_binary(),
+    )
+}
+
+fn kernel_patch_base_addr(
+    bsp: &RaspberryPi,
+    tables: &TranslationTable,
+    kernel_elf: &mut [u8],
+) -> Result<(), String> {
+    print_step("Patching");
+    println!(
+        " Value of kernel table physical base address ({}) at physical {}",
+        tables.phys_tables_base_addr(),
+        bsp.phys_tables_base_addr()
+    );
+
+    patch(
+        kernel_elf,
+        bsp.phys_tables_base_addr_offset_in_kernel_elf(),
+        &(tables.phys_tables_base_addr().into_usize() as u64).to_le_bytes(),
+    )
+}
+
+/// Must come last, because the other patches change the code.
+fn kernel_patch_image_checksum(bsp: &RaspberryPi, kernel_elf: &mut [u8]) -> Result<(), String> {
+    let start = bsp.rx_offset_in_kernel_elf();
+    let image = kernel_elf
+        .get(start..start + bsp.rx_size())
+        .ok_or("Code and RO data are out of bounds")?;
+    let checksum = common::crc32_update(0, image);
+
+    print_step("Patching");
+    println!(
+        " Value of image checksum ({:#010x}) at physical {}",
+        checksum,
+        bsp.phys_image_checksum_addr()
+    );
+
+    patch(
+        kernel_elf,
+        bsp.image_checksum_offset_in_kernel_elf(),
+        &checksum.to_le_bytes(),
+    )
+}
+
+fn run(target: &str, bsp_type: &str, kernel_elf_path: &str) -> Result<(), String> {
+    if !target.starts_with("aarch64") {
+        return Err(format!("Unsupported target: {}", target));
+    }
+
+    let mut kernel_elf =
+        fs::read(kernel_elf_path).map_err(|x| format!("{}: {}", kernel_elf_path, x))?;
+
+    let bsp = match bsp_type {
+        "rpi3" | "rpi4" => RaspberryPi::new(&Elf::parse(&kernel_elf)?, bsp_type)?,
+        _ => return Err(format!("Unsupported BSP: {}", bsp_type)),
+    };
+
+    let mut tables = TranslationTable::new(
+        bsp.kernel_virt_start_addr(),
+        bsp.kernel_virt_addr_space_size(),
+        bsp.phys_table_struct_start_addr(),
+        bsp.phys_addr_space_end_page(),
+    )?;
+
+    // The kernel binary must not clash with the end of the virtual address space, which is
+    // reserved for runtime-remapping of MMIO.
+    if bsp.rw_end_exclusive() >= tables.mmio_start_addr() {
+        println!("__rw_end_exclusive: {}", bsp.rw_end_exclusive());
+        println!("MMIO start:         {}", tables.mmio_start_addr());
+
+        return Err("Kernel virtual addresses clash with the MMIO region".to_string());
+    }
+
+    kernel_map_binary(&bsp, &mut tables)?;
+
+    kernel_patch_tables(&bsp, &tables, &mut kernel_elf)?;
+    kernel_patch_base_addr(&bsp, &tables, &mut kernel_elf)?;
+    kernel_patch_image_checksum(&bsp, &mut kernel_elf)?;
+
+    fs::write(kernel_elf_path, kernel_elf).map_err(|x| format!("{}: {}", kernel_elf_path, x))
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 4 {
+        eprintln!("Usage: {} TARGET BSP KERNEL_ELF", args[0]);
+        process::exit(1);
+    }
+
+    println!();
+    println!("\x1b[36mPrecomputing kernel translation tables and patching kernel ELF\x1b[0m");
+
+    let start = Instant::now();
+
+    if let Err(x) = run(&args[1], &args[2], &args[3]) {
+        eprintln!("Error: {}", x);
+        process::exit(1);
+    }
+
+    print_step("Finished");
+    println!(" in {:.2}s", start.elapsed().as_secs_f32());
+}
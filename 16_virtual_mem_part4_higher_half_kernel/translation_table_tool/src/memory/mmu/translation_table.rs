@@ -0,0 +1,263 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2021 Andre Richter <andre.o.richter@gmail.com>
+
+//! The kernel's translation tables, laid out like the kernel's `FixedSizeTranslationTable`.
+//!
+//! Stands in for `crate::memory::mmu::translation_table::arch_translation_table`, which is the
+//! parent of the descriptor module in the kernel. The descriptors are encoded by that module.
+
+#[path = "../../../../src/_arch/aarch64/memory/mmu/translation_table/descriptor.rs"]
+mod descriptor;
+
+use crate::{
+    config,
+    memory::{
+        mmu::{
+            arch_mmu::{Granule512MiB, Granule64KiB},
+            AttributeFields, Page, PageSliceDescriptor,
+        },
+        Address, Physical, Virtual,
+    },
+};
+use descriptor::{PageDescriptor, TableDescriptor};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The lvl3 and lvl2 tables of a kernel address space that starts from the top.
+///
+/// Only the descriptors are generated. The remaining fields of the kernel's struct keep the values
+/// that the kernel was compiled with.
+pub struct TranslationTable {
+    /// Page descriptors, covering 64 KiB windows per entry.
+    lvl3: Vec<[PageDescriptor; 8192]>,
+
+    /// Table descriptors, covering 512 MiB windows.
+    lvl2: Vec<TableDescriptor>,
+
+    virt_start_addr: Address<Virtual>,
+    phys_lvl2_start_addr: Address<Physical>,
+    phys_addr_space_end_page: *const Page<Physical>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl TranslationTable {
+    /// Helper to calculate the lvl2 and lvl3 indices from an address.
+    fn lvl2_lvl3_index_from(&self, addr: Address<Virtual>) -> Result<(usize, usize), &'static str> {
+        let addr = addr
+            .into_usize()
+            .checked_sub(self.virt_start_addr.into_usize())
+            .ok_or("Virtual page is out of bounds of translation table")?;
+
+        let lvl2_index = addr >> Granule512MiB::SHIFT;
+        let lvl3_index = (addr & Granule512MiB::MASK) >> Granule64KiB::SHIFT;
+
+        if lvl2_index >= self.lvl2.len() {
+            return Err("Virtual page is out of bounds of translation table");
+        }
+
+        Ok((lvl2_index, lvl3_index))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl TranslationTable {
+    /// Create an instance for the address space of `virt_addr_space_size` bytes that starts at
+    /// `virt_start_addr`, for a table struct that is loaded at `phys_start_addr`.
+    ///
+    /// The lvl2 entries are populated right away.
+    pub fn new(
+        virt_start_addr: Address<Virtual>,
+        virt_addr_space_size: usize,
+        phys_start_addr: Address<Physical>,
+        phys_addr_space_end_page: *const Page<Physical>,
+    ) -> Result<Self, &'static str> {
+        if (virt_addr_space_size == 0) || (virt_addr_space_size & Granule512MiB::MASK) != 0 {
+            return Err("Address space size is not a multiple of 512 MiB");
+        }
+
+        if !phys_start_addr.is_granule_aligned() {
+            return Err("Table struct is not page aligned");
+        }
+
+        let num_tables = virt_addr_space_size >> Granule512MiB::SHIFT;
+        let lvl3_size = core::mem::size_of::<[PageDescriptor; 8192]>();
+
+        let lvl2 = (0..num_tables)
+            .map(|i| TableDescriptor::from_next_lvl_table_addr(phys_start_addr + i * lvl3_size))
+            .collect();
+
+        Ok(Self {
+            lvl3: vec![[PageDescriptor::new_zeroed(); 8192]; num_tables],
+            lvl2,
+            virt_start_addr,
+            phys_lvl2_start_addr: phys_start_addr + num_tables * lvl3_size,
+            phys_addr_space_end_page,
+        })
+    }
+
+    /// The start address of the range at the end of the address space that the kernel reserves for
+    /// MMIO mappings.
+    pub fn mmio_start_addr(&self) -> Address<Virtual> {
+        self.virt_start_addr
+            + ((self.lvl2.len() << Granule512MiB::SHIFT) - config::memory::MMIO_REGION_SIZE)
+    }
+
+    /// Map the given virtual pages to the given physical pages. Same checks as the kernel's.
+    pub fn map_pages_at(
+        &mut self,
+        virt_pages: &PageSliceDescriptor<Virtual>,
+        phys_pages: &PageSliceDescriptor<Physical>,
+        attr: &AttributeFields,
+    ) -> Result<(), &'static str> {
+        // No work to do for empty slices.
+        if virt_pages.num_pages() == 0 {
+            return Ok(());
+        }
+
+        if virt_pages.num_pages() != phys_pages.num_pages() {
+            return Err("Tried to map page slices with unequal sizes");
+        }
+
+        let phys_last_page = phys_pages.end_addr() - Granule64KiB::SIZE;
+        if phys_last_page.into_usize() as *const Page<Physical> >= self.phys_addr_space_end_page {
+            return Err("Tried to map outside of physical address space");
+        }
+
+        for i in 0..virt_pages.num_pages() {
+            let virt_page = virt_pages.start_addr() + i * Granule64KiB::SIZE;
+            let phys_page = phys_pages.start_addr() + i * Granule64KiB::SIZE;
+
+            let (lvl2_index, lvl3_index) = self.lvl2_lvl3_index_from(virt_page)?;
+            let page_descriptor = &mut self.lvl3[lvl2_index][lvl3_index];
+            if page_descriptor.is_valid() {
+                return Err("Virtual page is already mapped");
+            }
+
+            *page_descriptor = PageDescriptor::from_output_addr(
+                phys_page.into_usize() as *const Page<Physical>,
+                attr,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The descriptors as they are in the kernel's struct: All lvl3 tables, then the lvl2 table.
+    pub fn to_binary(&self) -> Vec<u8> {
+        let lvl3 = self.lvl3.iter().flatten().map(|x| x.into_u64());
+        let lvl2 = self.lvl2.iter().map(|x| x.into_u64());
+
+        lvl3.chain(lvl2)
+            .flat_map(|x| x.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    /// The physical address of the lvl2 table, which is what the kernel loads into the TTBR.
+    pub fn phys_tables_base_addr(&self) -> Address<Physical> {
+        self.phys_lvl2_start_addr
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::mmu::{AccessPermissions, MemAttributes};
+
+    const ATTR: AttributeFields = AttributeFields {
+        mem_attributes: MemAttributes::CacheableDRAM,
+        acc_perms: AccessPermissions::ReadWrite,
+        execute_never: true,
+    };
+
+    fn table() -> TranslationTable {
+        TranslationTable::new(
+            Address::new(0xFFFF_FFFF_C000_0000),
+            1024 * 1024 * 1024,
+            Address::new(0x9_0000),
+            0x3F00_0000 as *const Page<Physical>,
+        )
+        .unwrap()
+    }
+
+    fn raw(binary: &[u8], index: usize) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&binary[index * 8..(index + 1) * 8]);
+
+        u64::from_le_bytes(bytes)
+    }
+
+    /// The lvl2 table follows the lvl3 tables, and points to them.
+    #[test]
+    fn layout_matches_the_kernel_struct() {
+        let t = table();
+        let binary = t.to_binary();
+
+        assert_eq!(binary.len(), (2 * 8192 + 2) * 8);
+        assert_eq!(
+            t.phys_tables_base_addr().into_usize(),
+            0x9_0000 + 2 * 8192 * 8
+        );
+
+        for i in 0..2 {
+            let lvl2 = raw(&binary, 2 * 8192 + i);
+
+            assert_eq!(lvl2 & 0b11, 0b11);
+            assert_eq!(
+                lvl2 & 0x0000_FFFF_FFFF_0000,
+                (0x9_0000 + i * 8192 * 8) as u64
+            );
+        }
+    }
+
+    /// Mapped pages end up at the index that the kernel uses, encoded by the kernel's code.
+    #[test]
+    fn map_pages_at_works() {
+        let mut t = table();
+        let virt = PageSliceDescriptor::from_addr(Address::new(0xFFFF_FFFF_E008_0000), 2);
+        let phys = PageSliceDescriptor::from_addr(Address::new(0x8_0000), 2);
+
+        assert!(t.map_pages_at(&virt, &phys, &ATTR).is_ok());
+        assert_eq!(
+            t.map_pages_at(&virt, &phys, &ATTR),
+            Err("Virtual page is already mapped")
+        );
+
+        let binary = t.to_binary();
+        let first = 8192 + (0x8_0000 >> Granule64KiB::SHIFT);
+        for i in 0..2 {
+            let expected = PageDescriptor::from_output_addr(
+                (0x8_0000 + i * Granule64KiB::SIZE) as *const Page<Physical>,
+                &ATTR,
+            );
+
+            assert_eq!(raw(&binary, first + i), expected.into_u64());
+        }
+    }
+
+    /// Mappings outside of the address spaces are rejected.
+    #[test]
+    fn map_pages_at_checks_bounds() {
+        let mut t = table();
+        let phys = PageSliceDescriptor::from_addr(Address::new(0x8_0000), 1);
+
+        let below = PageSliceDescriptor::from_addr(Address::new(0x8_0000), 1);
+        assert!(t.map_pages_at(&below, &phys, &ATTR).is_err());
+
+        let virt = PageSliceDescriptor::from_addr(Address::new(0xFFFF_FFFF_C000_0000), 1);
+        let beyond = PageSliceDescriptor::from_addr(Address::new(0x3F00_0000), 1);
+        assert!(t.map_pages_at(&virt, &beyond, &ATTR).is_err());
+    }
+}
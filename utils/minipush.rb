@@ -9,16 +9,19 @@ require_relative 'miniterm'
 require 'ruby-progressbar'
 require_relative 'minipush/progressbar_patch'
 require 'timeout'
+require 'zlib'
 
 class ProtocolError < StandardError; end
+class ChecksumError < StandardError; end
 
 # The main class
 class MiniPush < MiniTerm
-    def initialize(serial_name, binary_image_path)
+    def initialize(serial_name, binary_image_path, checksum: false)
         super(serial_name)
 
         @name_short = 'MP' # override
         @binary_image_path = binary_image_path
+        @checksum = checksum
         @binary_size = nil
         @binary_image = nil
     end
@@ -79,6 +82,14 @@ class MiniPush < MiniTerm
         end
     end
 
+    # The kernel's built-in loader expects the image's CRC-32 and answers whether it matches.
+    def send_checksum
+        return unless @checksum
+
+        @target_serial.print([Zlib.crc32(@binary_image)].pack('L<'))
+        raise ChecksumError if @target_serial.read(2) != 'OK'
+    end
+
     # override
     def handle_reconnect(_error)
         connetion_reset
@@ -95,10 +106,17 @@ class MiniPush < MiniTerm
     # override
     def run
         open_serial
-        wait_for_binary_request
-        load_binary
-        send_size
-        send_binary
+        begin
+            wait_for_binary_request
+            load_binary
+            send_size
+            send_binary
+            send_checksum
+        rescue ChecksumError
+            puts
+            puts "[#{@name_short}] ⚡ #{'Checksum mismatch. Retrying'.light_red}"
+            retry
+        end
         terminal
     rescue ConnectionError, EOFError, Errno::EIO, ProtocolError, Timeout::Error => e
         handle_reconnect(e)
@@ -126,5 +144,7 @@ if __FILE__ == $PROGRAM_NAME
         exit
     end
 
-    MiniPush.new(ARGV[0], ARGV[1]).run
+    checksum = !ARGV.delete('--checksum').nil?
+
+    MiniPush.new(ARGV[0], ARGV[1], checksum: checksum).run
 end